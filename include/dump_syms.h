@@ -0,0 +1,43 @@
+/* Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms. */
+
+/* The C API of the dump_syms library (built with the feature dump_syms_c). */
+
+#ifndef DUMP_SYMS_H
+#define DUMP_SYMS_H
+
+#include <stdbool.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The options of a dump (as the ones of the command line): NULL strings are unset. */
+typedef struct DumpSymsOptions {
+    const char* arch;
+    bool inlines;
+    bool cfi;
+    const char* mapping_file;
+    const char* symbol_server;
+    bool allow_export_only;
+} DumpSymsOptions;
+
+/* Fill the options with the default values (no inlines, with the CFI). */
+void dump_syms_default_options(DumpSymsOptions* options);
+
+/* Dump the symbols of the file at path in the file at out_path (stdout when it's NULL):
+ * options can be NULL for the default ones. Return 0 on success and -1 on failure (a panic of
+ * the library is a failure). */
+int dump_syms_dump_file(const char* path, const DumpSymsOptions* options, const char* out_path);
+
+/* The error message of the last failure of the thread (NULL when the last call succeeded):
+ * it's valid until the next call. */
+const char* dump_syms_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DUMP_SYMS_H */
@@ -3,12 +3,16 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use log::info;
 use std::path::PathBuf;
 
+use crate::breakpad::sym::SymInfo;
 use crate::common::{self, FileType};
+use crate::dotnet::ppdb::PortablePdbInfo;
 use crate::linux::elf::ElfInfo;
 use crate::mac::macho::MachoInfo;
 use crate::utils;
+use crate::wasm::module::WasmInfo;
 use crate::windows::pdb::PDBInfo;
 
 use super::dumper::{self, Config};
@@ -23,11 +27,43 @@ impl Action<'_> {
         if filenames.len() == 1 {
             // no need to spawn a thread for one file
             self.single_file(filenames[0])
+        } else if let Some((config, (pe, pdb))) = self.get_pe_and_pdb(filenames) {
+            // dump_syms xul.dll xul.pdb: the two files make one module
+            dumper::pe_and_pdb(config, pe, pdb)
+        } else if let Some((config, (binary, debug))) = self.get_elf_and_debug(filenames) {
+            // dump_syms libxul.so libxul.so.dbg: the ids and the symbols of the binary with the
+            // debug info of the debug file
+            info!("Dump {} with the debug file {}", binary, debug);
+            dumper::several_files::<ElfInfo>(config, &[binary, debug])
         } else {
             self.several_files(filenames)
         }
     }
 
+    fn get_pe_and_pdb<'a>(
+        &self,
+        filenames: &[&'a str],
+    ) -> Option<(&Config<'_>, (&'a str, &'a str))> {
+        match self {
+            Self::Dump(config) if config.file_type == FileType::Unknown => {
+                dumper::get_pe_and_pdb(filenames).map(|files| (config, files))
+            }
+            _ => None,
+        }
+    }
+
+    fn get_elf_and_debug<'a>(
+        &self,
+        filenames: &[&'a str],
+    ) -> Option<(&Config<'_>, (&'a str, &'a str))> {
+        match self {
+            Self::Dump(config) if config.file_type == FileType::Unknown => {
+                dumper::get_elf_and_debug(filenames).map(|files| (config, files))
+            }
+            _ => None,
+        }
+    }
+
     fn single_file(&self, filename: &str) -> common::Result<()> {
         match self {
             Self::Dump(config) => dumper::single_file(&config, filename),
@@ -47,6 +83,12 @@ impl Action<'_> {
                 FileType::Elf => dumper::several_files::<ElfInfo>(&config, filenames),
                 FileType::Macho => dumper::several_files::<MachoInfo>(&config, filenames),
                 FileType::Pdb => dumper::several_files::<PDBInfo>(&config, filenames),
+                FileType::Wasm => dumper::several_files::<WasmInfo>(&config, filenames),
+                FileType::PortablePdb => {
+                    dumper::several_files::<PortablePdbInfo>(&config, filenames)
+                }
+                FileType::Breakpad => dumper::several_files::<SymInfo>(&config, filenames),
+                FileType::Unknown => dumper::batch(&config, filenames),
                 _ => Ok(()),
             },
             Self::ListArch => {
@@ -68,33 +110,48 @@ mod tests {
 
     use regex::Regex;
     use std::fs::{copy, read};
+    use std::sync::Arc;
     use tempfile::Builder;
 
     use super::*;
 
-    #[test]
-    fn test_missing_pe() {
-        let tmp_dir = Builder::new().prefix("no_pe").tempdir().unwrap();
-        let basic64 = PathBuf::from("./test_data/windows/basic64.pdb");
-        let tmp_file = tmp_dir.path().join("basic64.pdb");
-        let tmp_out = tmp_dir.path().join("output.sym");
-
-        copy(basic64, &tmp_file).unwrap();
-
-        let action = Action::Dump(Config {
-            output: tmp_out.to_str().unwrap(),
+    /// The config of a dump with the default settings (the tests change the fields they need)
+    fn test_config(output: &str, file_type: FileType) -> Config {
+        Config {
+            output,
             symbol_server: None,
             store: None,
             debug_id: None,
             code_id: None,
             arch: common::get_compile_time_arch(),
-            file_type: FileType::Pdb,
+            file_type,
             num_jobs: 1,
             mapping_var: None,
             mapping_src: None,
             mapping_dest: None,
             mapping_file: None,
-        });
+            vcs_info: None,
+            user_agent: None,
+            headers: None,
+            allow_export_only: false,
+            all_archs: false,
+            module_name: None,
+            merge: false,
+            supplier: None,
+            options: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn test_missing_pe() {
+        let tmp_dir = Builder::new().prefix("no_pe").tempdir().unwrap();
+        let basic64 = PathBuf::from("./test_data/windows/basic64.pdb");
+        let tmp_file = tmp_dir.path().join("basic64.pdb");
+        let tmp_out = tmp_dir.path().join("output.sym");
+
+        copy(basic64, &tmp_file).unwrap();
+
+        let action = Action::Dump(test_config(tmp_out.to_str().unwrap(), FileType::Pdb));
 
         action.action(&[tmp_file.to_str().unwrap()]).unwrap();
 
@@ -105,6 +162,66 @@ mod tests {
         assert!(!data.contains("STACK CFI"));
     }
 
+    #[test]
+    fn test_pdb_store() {
+        let tmp_dir = Builder::new().prefix("pdb_store").tempdir().unwrap();
+        let basic64 = PathBuf::from("./test_data/windows/basic64.pdb");
+        let tmp_store = tmp_dir.path().join("symbols");
+
+        let action = Action::Dump(Config {
+            store: Some(tmp_store.to_str().unwrap()),
+            ..test_config("-", FileType::Pdb)
+        });
+
+        action.action(&[basic64.to_str().unwrap()]).unwrap();
+
+        // The layout of a Breakpad symbol store: <debug_file>/<DEBUG_ID>/<debug_file base>.sym
+        let dir = tmp_store.join("basic64.pdb");
+        let id_dir = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let data = read(id_dir.path().join("basic64.sym")).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        let module: Vec<_> = data.lines().next().unwrap().split(' ').collect();
+
+        assert_eq!(module[0], "MODULE");
+        assert_eq!(id_dir.file_name().to_str().unwrap(), module[3]);
+        assert_eq!(module[4], "basic64.pdb");
+    }
+
+    #[test]
+    fn test_batch() {
+        let tmp_dir = Builder::new().prefix("batch").tempdir().unwrap();
+        let basic64 = PathBuf::from("./test_data/windows/basic64.pdb");
+        let full = PathBuf::from("./test_data/linux/basic.full");
+        let tmp_store = tmp_dir.path().join("symbols");
+
+        let config = |output| Config {
+            store: Some(tmp_store.to_str().unwrap()),
+            num_jobs: 2,
+            ..test_config(output, FileType::Unknown)
+        };
+        let files = [basic64.to_str().unwrap(), full.to_str().unwrap()];
+
+        // The files can't be written in the same output
+        let action = Action::Dump(config("out.sym"));
+        assert!(action.action(&files).is_err());
+
+        let action = Action::Dump(config("-"));
+        action.action(&files).unwrap();
+
+        for (dir, name) in &[("basic64.pdb", "basic64.sym"), ("basic.full", "basic.sym")] {
+            let dir = tmp_store.join(dir);
+            let id_dir = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+            let data = read(id_dir.path().join(name)).unwrap();
+            assert!(data.starts_with(b"MODULE "));
+        }
+
+        // A file which can't be dumped doesn't stop the other ones
+        let action = Action::Dump(config("-"));
+        assert!(action
+            .action(&["./test_data/linux/basic.cpp", files[1]])
+            .is_err());
+    }
+
     #[test]
     fn test_missing_pe_but_in_dir() {
         let tmp_dir = Builder::new().prefix("no_pe").tempdir().unwrap();
@@ -117,20 +234,7 @@ mod tests {
         copy(basic64_pdb, &tmp_pdb).unwrap();
         copy(basic64_dll, &tmp_dll).unwrap();
 
-        let action = Action::Dump(Config {
-            output: tmp_out.to_str().unwrap(),
-            symbol_server: None,
-            store: None,
-            debug_id: None,
-            code_id: None,
-            arch: common::get_compile_time_arch(),
-            file_type: FileType::Pdb,
-            num_jobs: 1,
-            mapping_var: None,
-            mapping_src: None,
-            mapping_dest: None,
-            mapping_file: None,
-        });
+        let action = Action::Dump(test_config(tmp_out.to_str().unwrap(), FileType::Pdb));
 
         action.action(&[tmp_pdb.to_str().unwrap()]).unwrap();
 
@@ -141,26 +245,66 @@ mod tests {
         assert!(data.contains("STACK CFI"));
     }
 
+    #[test]
+    fn test_pe_and_pdb() {
+        let tmp_dir = Builder::new().prefix("pe_and_pdb").tempdir().unwrap();
+        // The PE isn't next to the PDB so it can't be found
+        std::fs::create_dir_all(tmp_dir.path().join("pdb")).unwrap();
+        let tmp_pdb = tmp_dir.path().join("pdb/basic64.pdb");
+        let tmp_dll = tmp_dir.path().join("basic64.dll");
+        let tmp_out = tmp_dir.path().join("output.sym");
+
+        copy("./test_data/windows/basic64.pdb", &tmp_pdb).unwrap();
+        copy("./test_data/windows/basic64.dll", &tmp_dll).unwrap();
+
+        let config = test_config(tmp_out.to_str().unwrap(), FileType::Unknown);
+        let action = Action::Dump(config.clone());
+        action
+            .action(&[tmp_dll.to_str().unwrap(), tmp_pdb.to_str().unwrap()])
+            .unwrap();
+
+        let data = read(&tmp_out).unwrap();
+        let data = String::from_utf8(data).unwrap();
+
+        assert!(data.starts_with("MODULE windows x86_64 "));
+        assert!(data.contains("INFO CODE_ID "));
+        assert!(data.contains(" basic64.dll\n"));
+
+        // Not the PDB of this PE
+        copy("./test_data/windows/basic32.dll", &tmp_dll).unwrap();
+        let action = Action::Dump(config);
+        assert!(action
+            .action(&[tmp_pdb.to_str().unwrap(), tmp_dll.to_str().unwrap()])
+            .is_err());
+    }
+
+    #[test]
+    fn test_forced_type() {
+        let tmp_dir = Builder::new().prefix("forced_type").tempdir().unwrap();
+        let full = PathBuf::from("./test_data/linux/basic.full");
+        let tmp_out = tmp_dir.path().join("output.sym");
+
+        let config = |file_type| test_config(tmp_out.to_str().unwrap(), file_type);
+
+        // The ELF file isn't parsed as a PDB one
+        let action = Action::Dump(config(FileType::Pdb));
+        assert!(action.action(&[full.to_str().unwrap()]).is_err());
+
+        let action = Action::Dump(config(FileType::Elf));
+        action.action(&[full.to_str().unwrap()]).unwrap();
+
+        let data = read(&tmp_out).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        assert!(data.starts_with("MODULE Linux "));
+    }
+
     #[test]
     fn test_elf_full() {
         let tmp_dir = Builder::new().prefix("full").tempdir().unwrap();
         let full = PathBuf::from("./test_data/linux/basic.full");
         let tmp_out = tmp_dir.path().join("output.sym");
 
-        let action = Action::Dump(Config {
-            output: tmp_out.to_str().unwrap(),
-            symbol_server: None,
-            store: None,
-            debug_id: None,
-            code_id: None,
-            arch: common::get_compile_time_arch(),
-            file_type: FileType::Elf,
-            num_jobs: 1,
-            mapping_var: None,
-            mapping_src: None,
-            mapping_dest: None,
-            mapping_file: None,
-        });
+        let action = Action::Dump(test_config(tmp_out.to_str().unwrap(), FileType::Elf));
 
         action.action(&[full.to_str().unwrap()]).unwrap();
 
@@ -182,18 +326,8 @@ mod tests {
         let tmp_out = tmp_dir.path().join("output.sym");
 
         let action = Action::Dump(Config {
-            output: tmp_out.to_str().unwrap(),
-            symbol_server: None,
-            store: None,
-            debug_id: None,
-            code_id: None,
-            arch: common::get_compile_time_arch(),
-            file_type: FileType::Elf,
             num_jobs: 2,
-            mapping_var: None,
-            mapping_src: None,
-            mapping_dest: None,
-            mapping_file: None,
+            ..test_config(tmp_out.to_str().unwrap(), FileType::Elf)
         });
 
         action
@@ -223,18 +357,8 @@ mod tests {
         let tmp_out = tmp_dir.path().join("output.sym");
 
         let action = Action::Dump(Config {
-            output: tmp_out.to_str().unwrap(),
-            symbol_server: None,
-            store: None,
-            debug_id: None,
-            code_id: None,
-            arch: common::get_compile_time_arch(),
-            file_type: FileType::Elf,
             num_jobs: 2,
-            mapping_var: None,
-            mapping_src: None,
-            mapping_dest: None,
-            mapping_file: None,
+            ..test_config(tmp_out.to_str().unwrap(), FileType::Elf)
         });
 
         action
@@ -255,4 +379,162 @@ mod tests {
 
         assert_eq!(basic, new);
     }
+
+    #[test]
+    fn test_elf_dbg_without_build_id() {
+        let tmp_dir = Builder::new().prefix("no_build_id").tempdir().unwrap();
+        let stripped = PathBuf::from("./test_data/linux/basic_nobuildid.stripped");
+        let dbg = PathBuf::from("./test_data/linux/basic_nobuildid.dbg");
+        let tmp_out = tmp_dir.path().join("output.sym");
+
+        let action = Action::Dump(Config {
+            num_jobs: 2,
+            ..test_config(tmp_out.to_str().unwrap(), FileType::Elf)
+        });
+
+        action
+            .action(&[dbg.to_str().unwrap(), stripped.to_str().unwrap()])
+            .unwrap();
+
+        let data = read(tmp_out).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        let module = data.lines().next().unwrap();
+
+        // The id of the stripped binary is used
+        assert!(module.starts_with("MODULE Linux x86_64 AEF0B8EC643235674369B2F52003CFA00 "));
+    }
+
+    #[test]
+    fn test_elf_binary_and_debug_file() {
+        let tmp_dir = Builder::new().prefix("binary_and_debug").tempdir().unwrap();
+        let stripped = PathBuf::from("./test_data/linux/basic.stripped");
+        let dbg = PathBuf::from("./test_data/linux/basic.dbg");
+        let files = [dbg.to_str().unwrap(), stripped.to_str().unwrap()];
+
+        // Without --type the pair is found as with --type elf
+        let outputs: Vec<_> = [FileType::Unknown, FileType::Elf]
+            .iter()
+            .map(|file_type| {
+                let tmp_out = tmp_dir.path().join("output.sym");
+                let action = Action::Dump(Config {
+                    num_jobs: 2,
+                    ..test_config(tmp_out.to_str().unwrap(), file_type.clone())
+                });
+                action.action(&files).unwrap();
+                String::from_utf8(read(tmp_out).unwrap()).unwrap()
+            })
+            .collect();
+
+        assert_eq!(outputs[0], outputs[1]);
+        assert!(outputs[0]
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with(" basic.stripped"));
+        assert!(outputs[0].contains("\nFUNC "));
+        assert_eq!(
+            dumper::get_elf_and_debug(&files),
+            Some((files[1], files[0]))
+        );
+        assert!(dumper::get_elf_and_debug(&[files[1], files[1]]).is_none());
+    }
+
+    #[test]
+    fn test_elf_split_dwarf() {
+        let tmp_dir = Builder::new().prefix("split_dwarf").tempdir().unwrap();
+        // The dwo file is next to the binary
+        let full = PathBuf::from("./test_data/linux/basic_split.full");
+        let tmp_out = tmp_dir.path().join("output.sym");
+
+        let action = Action::Dump(test_config(tmp_out.to_str().unwrap(), FileType::Elf));
+
+        action.action(&[full.to_str().unwrap()]).unwrap();
+
+        let data = read(tmp_out).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        let funcs: Vec<_> = data
+            .lines()
+            .filter(|l| l.starts_with("FUNC "))
+            .map(|l| l.splitn(5, ' ').last().unwrap())
+            .collect();
+
+        assert_eq!(
+            funcs,
+            vec![
+                "inline_1(int)",
+                "inline_2(int)",
+                "inline_3(int)",
+                "inline_4(int)",
+                "foo(int)",
+                "main"
+            ]
+        );
+        assert!(data.contains("\nFUNC 12c1 1c 0 main\n12c1 f 35 0\n12d0 b 36 0\n12db 2 37 0\n"));
+    }
+
+    #[test]
+    fn test_elf_debuglink() {
+        let tmp_dir = Builder::new().prefix("debuglink").tempdir().unwrap();
+        // The debug file is next to the binary
+        let stripped = PathBuf::from("./test_data/linux/basic_debuglink.stripped");
+        let tmp_out = tmp_dir.path().join("output.sym");
+
+        let action = Action::Dump(test_config(tmp_out.to_str().unwrap(), FileType::Elf));
+
+        action.action(&[stripped.to_str().unwrap()]).unwrap();
+
+        let data = read(tmp_out).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        let funcs: Vec<_> = data
+            .lines()
+            .filter(|l| l.starts_with("FUNC "))
+            .map(|l| l.splitn(5, ' ').last().unwrap())
+            .collect();
+
+        assert_eq!(
+            funcs,
+            vec![
+                "inline_1(int)",
+                "inline_2(int)",
+                "inline_3(int)",
+                "inline_4(int)",
+                "foo(int)",
+                "main"
+            ]
+        );
+        assert!(data.starts_with(
+            "MODULE Linux x86_64 8304623AAAFD3E60BEE5868FA833FACB0 basic_debuglink.stripped\n"
+        ));
+    }
+
+    #[test]
+    fn test_elf_soname() {
+        let lib = PathBuf::from("./test_data/linux/libbasic.so.1.2.3");
+
+        // The CLI override is preferred to the soname which is preferred to the file name
+        for (module_name, expected, sym) in &[
+            (None, "libbasic.so.1", "libbasic.so.1.sym"),
+            (Some("libfoo.so"), "libfoo.so", "libfoo.sym"),
+        ] {
+            let tmp_dir = Builder::new().prefix("soname").tempdir().unwrap();
+            let tmp_store = tmp_dir.path().join("store");
+
+            let action = Action::Dump(Config {
+                store: Some(tmp_store.to_str().unwrap()),
+                module_name: *module_name,
+                ..test_config("-", FileType::Elf)
+            });
+
+            action.action(&[lib.to_str().unwrap()]).unwrap();
+
+            let dir = tmp_store.join(expected);
+            let id_dir = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+            let data = String::from_utf8(read(id_dir.path().join(sym)).unwrap()).unwrap();
+            let module = data.lines().next().unwrap();
+
+            assert!(module.starts_with("MODULE Linux x86_64 "));
+            assert!(module.ends_with(&format!(" {}", expected)));
+            assert!(data.contains(&format!("<unknown in {}>", expected)));
+        }
+    }
 }
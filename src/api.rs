@@ -0,0 +1,472 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The library API: a Dumper writes the Breakpad symbols of some data or of a file in any writer.
+// A file must have one module (an archive is merged and the arch of a fat Mach-O binary must be
+// selected). The output files, the stores and the other settings of the command line aren't used.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::cancel::{self, CancellationToken};
+use crate::common::{self, Dumpable, FileType};
+use crate::dumper::{self, Config, Emitter};
+use crate::json::{Document, DocumentBuilder};
+use crate::mapping::PathMappings;
+use crate::options::Options;
+use crate::output::{RecordFilter, Records};
+use crate::supplier::SymbolSupplier;
+use crate::utils;
+use crate::visitor::{NoCfi, Visitor};
+
+/// Dump the Breakpad symbols of a file (ELF, Mach-O, PDB, PE, wasm, Breakpad, ...)
+///
+/// ```no_run
+/// # fn main() -> dump_syms::Result<()> {
+/// let mut sym = Vec::new();
+/// dump_syms::Dumper::from_path("libxul.so")
+///     .with_cfi(false)
+///     .with_mapping(&["/builds/worker/(.*)"], &["https://hg.mozilla.org/{1}"])
+///     .dump(&mut sym)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Dumper<'a> {
+    // None when the file must be read
+    data: Option<&'a [u8]>,
+    path: PathBuf,
+    arch: String,
+    inlines: bool,
+    cfi: bool,
+    mapping_src: Vec<String>,
+    mapping_dest: Vec<String>,
+    mapping_file: Option<String>,
+    symbol_server: Option<String>,
+    allow_export_only: bool,
+    supplier: Option<Arc<dyn SymbolSupplier>>,
+    token: Option<CancellationToken>,
+}
+
+impl<'a> Dumper<'a> {
+    fn new(data: Option<&'a [u8]>, path: PathBuf) -> Self {
+        Self {
+            data,
+            path,
+            arch: common::get_compile_time_arch().to_string(),
+            inlines: false,
+            cfi: true,
+            mapping_src: Vec::new(),
+            mapping_dest: Vec::new(),
+            mapping_file: None,
+            symbol_server: None,
+            allow_export_only: false,
+            supplier: None,
+            token: None,
+        }
+    }
+
+    /// Dump some data: the file name (by default "unknown") is set with with_name
+    pub fn from_bytes(data: &'a [u8]) -> Self {
+        Self::new(Some(data), PathBuf::from("unknown"))
+    }
+
+    /// Dump the file at the given path (the files next to it, e.g. a PDB, are used too)
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        Self::new(None, path.as_ref().to_path_buf())
+    }
+
+    /// The file name of the data (used in the MODULE record when the file hasn't a better one)
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.path = PathBuf::from(name);
+        self
+    }
+
+    /// The arch to dump in a fat Mach-O binary (by default the one of this binary)
+    pub fn with_arch(mut self, arch: &str) -> Self {
+        self.arch = arch.to_string();
+        self
+    }
+
+    /// Emit the INLINE and INLINE_ORIGIN records
+    pub fn with_inlines(mut self, inlines: bool) -> Self {
+        self.inlines = inlines;
+        self
+    }
+
+    /// Emit the STACK records (true by default)
+    pub fn with_cfi(mut self, cfi: bool) -> Self {
+        self.cfi = cfi;
+        self
+    }
+
+    /// Map the source files matching the regexes to the destinations
+    /// (as --mapping-src and --mapping-dest)
+    pub fn with_mapping(mut self, sources: &[&str], destinations: &[&str]) -> Self {
+        self.mapping_src = sources.iter().map(|s| s.to_string()).collect();
+        self.mapping_dest = destinations.iter().map(|d| d.to_string()).collect();
+        self
+    }
+
+    /// Map the source files with a JSON file (as --mapping-file)
+    pub fn with_mapping_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.mapping_file = Some(path.as_ref().to_string_lossy().to_string());
+        self
+    }
+
+    /// The symbol servers where a PDB is fetched (by default the ones in the config files)
+    pub fn with_symbol_server(mut self, symbol_server: &str) -> Self {
+        self.symbol_server = Some(symbol_server.to_string());
+        self
+    }
+
+    /// Fetch the debug files which aren't next to the file (e.g. a PDB) with the supplier
+    /// instead of the symbol servers
+    pub fn with_symbol_supplier<S: SymbolSupplier + 'static>(mut self, supplier: S) -> Self {
+        self.supplier = Some(Arc::new(supplier));
+        self
+    }
+
+    /// Abort the dump (with a Cancelled error) when the token is cancelled or its deadline passed
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Dump the exported functions of a PE when its PDB can't be found
+    pub fn with_allow_export_only(mut self, allow_export_only: bool) -> Self {
+        self.allow_export_only = allow_export_only;
+        self
+    }
+
+    /// Write the symbols
+    pub fn dump<W: Write>(&self, writer: W) -> common::Result<()> {
+        let mut emitter = WriterEmitter {
+            writer: Some(writer),
+            records: if self.cfi {
+                Records::All
+            } else {
+                Records::NoCfi
+            },
+        };
+        self.run(&mut emitter)
+    }
+
+    /// Get the symbols as a data structure (serialized as with --format json)
+    pub fn symbols(&self) -> common::Result<Document> {
+        let mut builder = DocumentBuilder::default();
+        self.visit(&mut builder)?;
+        Ok(builder.finish())
+    }
+
+    /// Call the visitor on each record of the symbols (as they would be written by dump)
+    pub fn visit<V: Visitor>(&self, visitor: &mut V) -> common::Result<()> {
+        let mut emitter = VisitorEmitter {
+            visitor: Some(visitor),
+            cfi: self.cfi,
+        };
+        self.run(&mut emitter)
+    }
+
+    fn run<E: Emitter>(&self, emitter: &mut E) -> common::Result<()> {
+        let _guard = cancel::set_current(self.token.clone());
+        let mapped;
+        let buf = match self.data {
+            Some(data) => data,
+            None => {
+                mapped = utils::try_map_file(&self.path)?;
+                &mapped
+            }
+        };
+        let filename = utils::get_filename(&self.path);
+        let mapping = PathMappings::new(
+            &None,
+            &as_strs(&self.mapping_src),
+            &as_strs(&self.mapping_dest),
+            &self.mapping_file.as_deref(),
+            None,
+        )?
+        .map(Arc::new);
+        let options = Options {
+            inlines: self.inlines,
+            ..Default::default()
+        };
+
+        let config = Config {
+            output: "",
+            symbol_server: self.symbol_server.as_deref(),
+            store: None,
+            debug_id: None,
+            code_id: None,
+            arch: &self.arch,
+            file_type: FileType::Unknown,
+            num_jobs: 1,
+            mapping_var: None,
+            mapping_src: None,
+            mapping_dest: None,
+            mapping_file: None,
+            vcs_info: None,
+            user_agent: None,
+            headers: None,
+            allow_export_only: self.allow_export_only,
+            all_archs: false,
+            module_name: None,
+            merge: true,
+            supplier: self.supplier.clone(),
+            options: Arc::new(options),
+        };
+        dumper::dump_buf(&config, buf, &self.path, &filename, mapping, emitter)
+    }
+}
+
+fn as_strs(v: &[String]) -> Option<Vec<&str>> {
+    Some(v.iter().map(|s| s.as_str()).collect::<Vec<_>>()).filter(|v| !v.is_empty())
+}
+
+/// Write the module in the writer
+struct WriterEmitter<W: Write> {
+    // None once the module has been written
+    writer: Option<W>,
+    records: Records,
+}
+
+impl<W: Write> Emitter for WriterEmitter<W> {
+    fn emit<D: Dumpable>(
+        &mut self,
+        part: Option<&str>,
+        dumpable: D,
+        _mapping: Option<&Arc<PathMappings>>,
+    ) -> common::Result<()> {
+        let writer = match (part, self.writer.take()) {
+            (None, Some(writer)) => writer,
+            _ => return Err("The file has several modules".into()),
+        };
+        let mut filter = RecordFilter::new(writer, self.records);
+        dumpable.dump(&mut filter)?;
+        filter.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+/// Visit the records of the module
+struct VisitorEmitter<'a, V: Visitor> {
+    // None once the module has been visited
+    visitor: Option<&'a mut V>,
+    cfi: bool,
+}
+
+impl<V: Visitor> Emitter for VisitorEmitter<'_, V> {
+    fn emit<D: Dumpable>(
+        &mut self,
+        part: Option<&str>,
+        dumpable: D,
+        _mapping: Option<&Arc<PathMappings>>,
+    ) -> common::Result<()> {
+        let visitor = match (part, self.visitor.take()) {
+            (None, Some(visitor)) => visitor,
+            _ => return Err("The file has several modules".into()),
+        };
+        if self.cfi {
+            dumpable.visit(visitor)
+        } else {
+            dumpable.visit(&mut NoCfi(visitor))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::read;
+
+    use super::*;
+    use crate::cancel::Cancelled;
+    use crate::json::JsonWriter;
+    use crate::output::Converter;
+    use crate::visitor::{
+        CfiInitRecord, FileRecord, FuncRecord, InlineRecord, LineRecord, PublicRecord,
+    };
+
+    fn dump(dumper: Dumper) -> String {
+        let mut sym = Vec::new();
+        dumper.dump(&mut sym).unwrap();
+        String::from_utf8(sym).unwrap()
+    }
+
+    #[test]
+    fn test_dumper() {
+        let data = read("./test_data/linux/basic.full").unwrap();
+        let sym = dump(Dumper::from_bytes(&data).with_name("basic.full"));
+        assert!(
+            sym.starts_with("MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n")
+        );
+        assert!(sym.contains("\nFUNC "));
+
+        let all = dump(Dumper::from_path("./test_data/linux/basic.full.sym"));
+        let no_cfi = dump(Dumper::from_path("./test_data/linux/basic.full.sym").with_cfi(false));
+        assert!(all.contains("\nSTACK ") && !no_cfi.contains("\nSTACK "));
+        assert_eq!(
+            no_cfi.lines().collect::<Vec<_>>(),
+            all.lines()
+                .filter(|l| !l.starts_with("STACK "))
+                .collect::<Vec<_>>()
+        );
+
+        assert!(Dumper::from_bytes(b"foo").dump(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_visit() {
+        #[derive(Default)]
+        struct Counter {
+            files: usize,
+            funcs: usize,
+            lines: usize,
+            inlines: usize,
+            publics: usize,
+            cfi: usize,
+        }
+
+        impl Visitor for Counter {
+            fn file(&mut self, _file: &FileRecord) {
+                self.files += 1;
+            }
+
+            fn func(&mut self, _func: &FuncRecord) {
+                self.funcs += 1;
+            }
+
+            fn line(&mut self, _line: &LineRecord) {
+                self.lines += 1;
+            }
+
+            fn inline(&mut self, _inline: &InlineRecord) {
+                self.inlines += 1;
+            }
+
+            fn public(&mut self, _public: &PublicRecord) {
+                self.publics += 1;
+            }
+
+            fn cfi_init(&mut self, _init: &CfiInitRecord) {
+                self.cfi += 1;
+            }
+        }
+
+        // The records are visited from the collected symbols (or from the records of a .sym)
+        for path in &[
+            "./test_data/linux/basic.full",
+            "./test_data/windows/basic64.pdb",
+            "./test_data/linux/basic.full.sym",
+        ] {
+            let mut counter = Counter::default();
+            Dumper::from_path(path)
+                .with_inlines(true)
+                .visit(&mut counter)
+                .unwrap();
+            let all = dump(Dumper::from_path(path).with_inlines(true));
+            let count = |prefix| all.lines().filter(|l| l.starts_with(prefix)).count();
+            assert!(counter.funcs != 0 && counter.lines != 0);
+            assert_eq!(counter.files, count("FILE "));
+            assert_eq!(counter.funcs, count("FUNC "));
+            assert_eq!(counter.inlines, count("INLINE "));
+            assert_eq!(counter.publics, count("PUBLIC "));
+            assert_eq!(counter.cfi, count("STACK CFI INIT "));
+            // The line records are the only ones without an upper case keyword
+            assert_eq!(
+                counter.lines,
+                all.lines()
+                    .filter(|l| !l.starts_with(|c: char| c.is_ascii_uppercase()))
+                    .count()
+            );
+        }
+
+        let mut counter = Counter::default();
+        Dumper::from_path("./test_data/linux/basic.full.sym")
+            .with_cfi(false)
+            .visit(&mut counter)
+            .unwrap();
+        assert!(counter.funcs != 0 && counter.cfi == 0);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let symbols = Dumper::from_path("./test_data/linux/basic.full.sym")
+            .symbols()
+            .unwrap();
+        let all = dump(Dumper::from_path("./test_data/linux/basic.full.sym"));
+        assert_eq!(symbols.module.name, "basic.full");
+        assert_eq!(symbols.functions.len(), all.matches("\nFUNC ").count());
+        assert!(symbols.functions.iter().any(|f| !f.lines.is_empty()));
+        assert_eq!(
+            symbols.stack_cfi.len(),
+            all.matches("\nSTACK CFI INIT ").count()
+        );
+
+        let json = serde_json::to_string(&symbols).unwrap();
+        let symbols: Document = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&symbols).unwrap(), json);
+
+        // The document built from the collected symbols is the one of the written records
+        for path in &[
+            "./test_data/linux/basic.full",
+            "./test_data/windows/basic64.pdb",
+        ] {
+            let dumper = Dumper::from_path(path).with_inlines(true);
+            let mut writer = JsonWriter::default();
+            dumper.dump(&mut writer).unwrap();
+            let mut from_text = Vec::new();
+            writer.write_document(&mut from_text).unwrap();
+            let symbols = dumper.symbols().unwrap();
+            assert!(!symbols.info.is_empty() && !symbols.functions.is_empty());
+            assert_eq!(
+                serde_json::to_value(&symbols).unwrap(),
+                serde_json::from_slice::<serde_json::Value>(&from_text).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_supplier() {
+        // The PDB isn't next to the data
+        let data = read("./test_data/windows/basic64.dll").unwrap();
+        let dumper = || {
+            Dumper::from_bytes(&data)
+                .with_name("basic64.dll")
+                .with_symbol_server("")
+        };
+        assert!(dumper().dump(Vec::new()).is_err());
+
+        let sym = dump(dumper().with_symbol_supplier(|name: &str, _id: &str| {
+            read(PathBuf::from("./test_data/windows").join(name)).ok()
+        }));
+        assert!(sym.starts_with("MODULE windows x86_64 "));
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let data = read("./test_data/linux/basic.full").unwrap();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut sym = Vec::new();
+        let e = Dumper::from_bytes(&data)
+            .with_cancellation(token)
+            .dump(&mut sym)
+            .unwrap_err();
+        let e = e.downcast_ref::<Cancelled>().unwrap();
+        assert!(!e.is_deadline_exceeded() && sym.is_empty());
+
+        let token = CancellationToken::new().with_timeout(std::time::Duration::from_secs(0));
+        let e = Dumper::from_bytes(&data)
+            .with_cancellation(token)
+            .dump(Vec::new())
+            .unwrap_err();
+        assert!(e
+            .downcast_ref::<Cancelled>()
+            .unwrap()
+            .is_deadline_exceeded());
+    }
+}
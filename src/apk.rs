@@ -0,0 +1,138 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// An Android package (.apk or .aab) is a zip file: the shared libraries are in lib/<abi>/
+// and are usually stored uncompressed (and page-aligned) so they can be mapped from the apk.
+
+use log::info;
+use std::borrow::Cow;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::Arc;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::common::{self, FileType};
+use crate::linux::elf::{ElfInfo, Platform};
+use crate::mapping::PathMappings;
+use crate::options::Options;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// A shared library in the zip file
+pub(crate) struct Library {
+    pub info: ElfInfo,
+    /// The abi and the name (e.g. arm64-v8a.libfoo.so) to distinguish the libraries in the output names
+    pub id: String,
+}
+
+pub(crate) fn is_zip(buf: &[u8]) -> bool {
+    buf.starts_with(ZIP_MAGIC)
+}
+
+fn is_library(name: &str) -> bool {
+    name.ends_with(".so") || name.contains(".so.")
+}
+
+/// Get the content of the shared libraries: the stored ones aren't copied
+fn get_libraries_data(buf: &[u8]) -> common::Result<Vec<(String, Cow<[u8]>)>> {
+    let mut zip = ZipArchive::new(Cursor::new(buf))?;
+    let mut libraries = Vec::new();
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        if !file.is_file() || !is_library(file.name()) {
+            continue;
+        }
+
+        let name = file.name().to_string();
+        let data = if file.compression() == CompressionMethod::Stored {
+            let start = file.data_start() as usize;
+            let data = buf
+                .get(start..start + file.size() as usize)
+                .ok_or_else(|| format!("Invalid entry {} in the zip file", name))?;
+            Cow::Borrowed(data)
+        } else {
+            let mut data = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut data)?;
+            Cow::Owned(data)
+        };
+        libraries.push((name, data));
+    }
+
+    Ok(libraries)
+}
+
+/// Get the shared libraries of a zip file (e.g. an apk)
+pub(crate) fn get_libraries(
+    buf: &[u8],
+    path: &Path,
+    mapping: Option<Arc<PathMappings>>,
+    options: Arc<Options>,
+) -> common::Result<Vec<Library>> {
+    let mut libraries = Vec::new();
+    for (name, data) in get_libraries_data(buf)? {
+        if !matches!(FileType::from_buf(&data), FileType::Elf) {
+            info!("Entry {} skipped: not an ELF file", name);
+            continue;
+        }
+
+        let entry = Path::new(&name);
+        let file_name = entry.file_name().unwrap().to_string_lossy();
+        let id = match entry.parent().and_then(|p| p.file_name()) {
+            Some(abi) => format!("{}.{}", abi.to_string_lossy(), file_name),
+            _ => file_name.to_string(),
+        };
+        let info = ElfInfo::new(
+            &data,
+            path,
+            &file_name,
+            Platform::Linux,
+            mapping.clone(),
+            options.clone(),
+        )?;
+        libraries.push(Library { info, id });
+    }
+
+    if libraries.is_empty() {
+        return Err("No shared library in the zip file".into());
+    }
+
+    Ok(libraries)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::path::PathBuf;
+
+    use crate::common::Dumpable;
+
+    #[test]
+    fn test_apk() {
+        let path = PathBuf::from("./test_data/android/basic.apk");
+        let buf = std::fs::read(&path).unwrap();
+        assert!(matches!(FileType::from_buf(&buf), FileType::Zip));
+
+        let libraries = get_libraries(&buf, &path, None, Arc::default()).unwrap();
+        let ids: Vec<_> = libraries.iter().map(|l| l.id.as_str()).collect();
+        assert_eq!(ids, vec!["x86_64.libcompressed.so", "x86_64.libbasic.so"]);
+
+        // The stored library and the deflated one are the same
+        let elf = std::fs::read("./test_data/linux/libbasic.so.1.2.3").unwrap();
+        let elf = ElfInfo::new(
+            &elf,
+            &path,
+            "libbasic.so",
+            Platform::Linux,
+            None,
+            Arc::default(),
+        )
+        .unwrap();
+        for library in libraries {
+            assert_eq!(library.info.get_name(), "libbasic.so.1");
+            assert_eq!(library.info.get_debug_id(), elf.get_debug_id());
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A static library (.a or .lib) is an ar archive: each object is dumped as a module
+// or all of them are concatenated in a single module.
+
+use goblin::archive::Archive;
+use log::info;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use symbolic::common::Arch;
+
+use crate::breakpad::sym::SymInfo;
+use crate::common::{self, Dumpable, FileType};
+use crate::linux::elf::{ElfInfo, Platform};
+use crate::mac::macho::MachoInfo;
+use crate::mapping::PathMappings;
+use crate::options::Options;
+use crate::visitor::Visitor;
+use crate::windows::coff::CoffObjectInfo;
+
+const ARCHIVE_MAGIC: &[u8] = b"!<arch>\n";
+
+pub(crate) enum MemberInfo {
+    Elf(ElfInfo),
+    Macho(MachoInfo),
+    Coff(CoffObjectInfo),
+}
+
+impl Dumpable for MemberInfo {
+    fn dump<W: Write>(&self, writer: W) -> common::Result<()> {
+        match self {
+            Self::Elf(info) => info.dump(writer),
+            Self::Macho(info) => info.dump(writer),
+            Self::Coff(info) => info.dump(writer),
+        }
+    }
+
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        match self {
+            Self::Elf(info) => info.visit(visitor),
+            Self::Macho(info) => info.visit(visitor),
+            Self::Coff(info) => info.visit(visitor),
+        }
+    }
+
+    fn get_name(&self) -> &str {
+        match self {
+            Self::Elf(info) => info.get_name(),
+            Self::Macho(info) => info.get_name(),
+            Self::Coff(info) => info.get_name(),
+        }
+    }
+
+    fn get_debug_id(&self) -> &str {
+        match self {
+            Self::Elf(info) => info.get_debug_id(),
+            Self::Macho(info) => info.get_debug_id(),
+            Self::Coff(info) => info.get_debug_id(),
+        }
+    }
+
+    fn get_source_files(&self) -> Vec<(String, PathBuf)> {
+        match self {
+            Self::Elf(info) => info.get_source_files(),
+            Self::Macho(info) => info.get_source_files(),
+            Self::Coff(info) => info.get_source_files(),
+        }
+    }
+}
+
+pub(crate) fn is_archive(buf: &[u8]) -> bool {
+    buf.starts_with(ARCHIVE_MAGIC)
+}
+
+fn get_member(
+    buf: &[u8],
+    path: &Path,
+    name: &str,
+    arch: Arch,
+    mapping: Option<Arc<PathMappings>>,
+    options: Arc<Options>,
+) -> common::Result<Option<MemberInfo>> {
+    let info = match FileType::from_buf(buf) {
+        FileType::Elf => Some(MemberInfo::Elf(ElfInfo::new(
+            buf,
+            path,
+            name,
+            Platform::Linux,
+            mapping,
+            options,
+        )?)),
+        FileType::Macho => Some(MemberInfo::Macho(MachoInfo::new(
+            buf, name, arch, mapping, options,
+        )?)),
+        _ if CoffObjectInfo::is_coff_object(buf) => {
+            let info = CoffObjectInfo::new(buf, name)?;
+            // The objects of an import library don't define any function
+            if info.is_empty() {
+                None
+            } else {
+                Some(MemberInfo::Coff(info))
+            }
+        }
+        _ => None,
+    };
+
+    Ok(info)
+}
+
+/// Get the data of a member of an archive
+pub(crate) fn get_member_data<'a>(buf: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    let archive = Archive::parse(buf).ok()?;
+    let member = archive.get(name)?;
+    let start = member.offset as usize;
+    buf.get(start..start.checked_add(member.size())?)
+}
+
+/// Get the objects of an archive (the members which aren't objects are skipped)
+pub(crate) fn get_members(
+    buf: &[u8],
+    path: &Path,
+    arch: Arch,
+    mapping: Option<Arc<PathMappings>>,
+    options: Arc<Options>,
+) -> common::Result<Vec<MemberInfo>> {
+    let archive = Archive::parse(buf)?;
+    let mut members = Vec::new();
+    for i in 0..archive.len() {
+        let member = archive.get_at(i).unwrap();
+        let name = member.extended_name();
+        let start = member.offset as usize;
+        let data = buf
+            .get(start..start + member.size())
+            .ok_or_else(|| format!("Invalid member {} in the archive", name))?;
+
+        match get_member(data, path, name, arch, mapping.clone(), options.clone())? {
+            Some(info) => members.push(info),
+            _ => info!("Member {} skipped: not an object", name),
+        }
+    }
+
+    if members.is_empty() {
+        return Err("No object in the archive".into());
+    }
+
+    Ok(members)
+}
+
+/// Concatenate the objects of an archive in a single module
+pub(crate) fn merge(file_name: &str, members: Vec<MemberInfo>) -> common::Result<SymInfo> {
+    let mut infos = Vec::with_capacity(members.len());
+    for member in members {
+        let mut buf = Vec::new();
+        member.dump(&mut buf)?;
+        infos.push(SymInfo::new(&buf, None)?);
+    }
+
+    SymInfo::concat(file_name, infos)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::path::PathBuf;
+
+    fn dump<D: Dumpable>(info: D) -> String {
+        let mut buf = Vec::new();
+        info.dump(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_elf_archive() {
+        let path = PathBuf::from("./test_data/linux/libbasic.a");
+        let buf = std::fs::read(&path).unwrap();
+        assert!(matches!(FileType::from_buf(&buf), FileType::Archive));
+
+        let members = get_members(&buf, &path, Arch::Amd64, None, Arc::default()).unwrap();
+        let names: Vec<_> = members.iter().map(|m| m.get_name().to_string()).collect();
+        assert_eq!(names, vec!["basic.o", "basic_opt.o"]);
+
+        let output = dump(merge("libbasic.a", members).unwrap());
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "MODULE Linux x86_64 000000000000000000000000000000000 libbasic.a"
+        );
+        // foo is defined in each object
+        assert_eq!(lines.filter(|l| l.ends_with(" foo(int)")).count(), 2);
+    }
+
+    #[test]
+    fn test_static_lib() {
+        let path = PathBuf::from("./test_data/windows/static.lib");
+        let buf = std::fs::read(&path).unwrap();
+        assert!(matches!(FileType::from_buf(&buf), FileType::Archive));
+
+        // The members of the import library are skipped
+        let members = get_members(&buf, &path, Arch::Amd64, None, Arc::default()).unwrap();
+        let outputs: Vec<_> = members.into_iter().map(dump).collect();
+        assert_eq!(
+            outputs,
+            vec![
+                "MODULE windows x86_64 000000000000000000000000000000000 foo.obj\n\
+                 PUBLIC 0 0 ?foo@@YAHH@Z\n\
+                 PUBLIC 4 0 ?bar@@YAHH@Z\n",
+                "MODULE windows x86_64 000000000000000000000000000000000 main.obj\n\
+                 PUBLIC 0 0 main\n",
+            ]
+        );
+    }
+}
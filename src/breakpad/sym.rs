@@ -0,0 +1,422 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A Breakpad symbol file used as input: the records are parsed and written back
+// so the path mappings, the max name length or a new module name can be applied
+// without the original binary. Several files can be concatenated in one module too.
+
+use log::error;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::common::{self, Dumpable, Mergeable};
+use crate::mapping::PathMappings;
+use crate::visitor::{self, Visitor};
+
+const MODULE_PREFIX: &[u8] = b"MODULE ";
+
+#[derive(Debug)]
+enum Record {
+    /// FUNC or PUBLIC: everything before the name and the name
+    Symbol { head: String, name: String },
+    /// INLINE_ORIGIN id name
+    InlineOrigin { id: u32, name: String },
+    /// address size line file
+    Line { head: String, file: u32 },
+    /// INLINE nest_level call_line call_file origin ranges
+    Inline {
+        head: String,
+        file: u32,
+        origin: u32,
+        ranges: String,
+    },
+    /// Everything else (INFO, STACK, ...) is written as is
+    Other(String),
+}
+
+#[derive(Debug)]
+pub struct SymInfo {
+    os: String,
+    cpu: String,
+    debug_id: String,
+    file_name: String,
+    files: BTreeMap<u32, String>,
+    records: Vec<Record>,
+    n_funcs: usize,
+}
+
+impl Display for SymInfo {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "MODULE {} {} {} {}",
+            self.os, self.cpu, self.debug_id, self.file_name
+        )?;
+
+        let mut files = self.files.iter().peekable();
+        for record in self.records.iter() {
+            // The FILE records are written after the INFO ones
+            if files.peek().is_some()
+                && !matches!(record, Record::Other(r) if r.starts_with("INFO "))
+            {
+                for (n, file_name) in files.by_ref() {
+                    writeln!(f, "FILE {} {}", n, file_name)?;
+                }
+            }
+
+            match record {
+                Record::Symbol { head, name } => writeln!(f, "{} {}", head, name)?,
+                Record::InlineOrigin { id, name } => writeln!(f, "INLINE_ORIGIN {} {}", id, name)?,
+                Record::Line { head, file } => writeln!(f, "{} {}", head, file)?,
+                Record::Inline {
+                    head,
+                    file,
+                    origin,
+                    ranges,
+                } => writeln!(f, "INLINE {} {} {} {}", head, file, origin, ranges)?,
+                Record::Other(record) => writeln!(f, "{}", record)?,
+            }
+        }
+
+        for (n, file_name) in files {
+            writeln!(f, "FILE {} {}", n, file_name)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn map_file(name: &str, mapping: Option<&Arc<PathMappings>>) -> String {
+    let new_name = mapping.and_then(|mapping| match mapping.map(name) {
+        Ok(name) => name,
+        Err(e) => {
+            error!("Mapping error: {}", e);
+            None
+        }
+    });
+    new_name.unwrap_or_else(|| name.to_string())
+}
+
+/// Split a FUNC or a PUBLIC record in its head and its name:
+/// FUNC [m] address size param_size name and PUBLIC [m] address param_size name
+fn split_symbol(record: &str, n_fields: usize) -> Option<(&str, &str)> {
+    let n_fields = if record.split(' ').nth(1) == Some("m") {
+        n_fields + 1
+    } else {
+        n_fields
+    };
+
+    split_fields(record, n_fields)
+}
+
+/// Split a record in its first n fields (joined) and the rest
+fn split_fields(record: &str, n_fields: usize) -> Option<(&str, &str)> {
+    let mut end = 0;
+    for _ in 0..n_fields {
+        end += record[end..].find(' ')? + 1;
+    }
+    Some((&record[..end - 1], &record[end..]))
+}
+
+fn parse_id(id: &str) -> Option<u32> {
+    id.parse::<u32>().ok()
+}
+
+impl SymInfo {
+    pub(crate) fn is_sym(buf: &[u8]) -> bool {
+        buf.starts_with(MODULE_PREFIX)
+    }
+
+    pub fn new(buf: &[u8], mapping: Option<Arc<PathMappings>>) -> common::Result<Self> {
+        let data = std::str::from_utf8(buf)?;
+        let mut lines = data.lines().map(|l| l.trim_end_matches('\r'));
+
+        let module = lines
+            .next()
+            .filter(|l| l.starts_with("MODULE "))
+            .ok_or("Not a Breakpad symbol file")?;
+        let module: Vec<_> = module.splitn(5, ' ').collect();
+        if module.len() != 5 {
+            return Err("Invalid MODULE record".into());
+        }
+
+        let mut files = BTreeMap::new();
+        let mut records = Vec::new();
+        let mut n_funcs = 0;
+        for (i, line) in lines.enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            // The MODULE record is on the first line
+            let invalid = || format!("Invalid record at line {}: {}", i + 2, line);
+
+            let (kind, rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            let rest = rest.trim_start();
+            match kind {
+                "FILE" => {
+                    let mut parts = rest.splitn(2, ' ');
+                    let n = parts
+                        .next()
+                        .and_then(|n| n.parse::<u32>().ok())
+                        .ok_or_else(invalid)?;
+                    let name = parts.next().unwrap_or("");
+                    files.insert(n, map_file(name, mapping.as_ref()));
+                }
+                "FUNC" | "PUBLIC" => {
+                    let n_fields = if kind == "FUNC" { 4 } else { 3 };
+                    let (head, name) = split_symbol(line, n_fields).ok_or_else(invalid)?;
+                    if kind == "FUNC" {
+                        n_funcs += 1;
+                    }
+                    records.push(Record::Symbol {
+                        head: head.to_string(),
+                        name: name.to_string(),
+                    });
+                }
+                "INLINE_ORIGIN" => {
+                    let mut parts = rest.splitn(2, ' ');
+                    let id = parts.next().and_then(parse_id).ok_or_else(invalid)?;
+                    records.push(Record::InlineOrigin {
+                        id,
+                        name: parts.next().unwrap_or("").to_string(),
+                    });
+                }
+                "INLINE" => {
+                    let (head, rest) = split_fields(rest, 2).ok_or_else(invalid)?;
+                    let mut parts = rest.splitn(3, ' ');
+                    let file = parts.next().and_then(parse_id).ok_or_else(invalid)?;
+                    let origin = parts.next().and_then(parse_id).ok_or_else(invalid)?;
+                    records.push(Record::Inline {
+                        head: head.to_string(),
+                        file,
+                        origin,
+                        ranges: parts.next().unwrap_or("").to_string(),
+                    });
+                }
+                _ if u64::from_str_radix(kind, 16).is_ok() => {
+                    let (head, file) = split_fields(line, 3).ok_or_else(invalid)?;
+                    records.push(Record::Line {
+                        head: head.to_string(),
+                        file: parse_id(file).ok_or_else(invalid)?,
+                    });
+                }
+                _ => records.push(Record::Other(line.to_string())),
+            }
+        }
+
+        Ok(Self {
+            os: module[1].to_string(),
+            cpu: module[2].to_string(),
+            debug_id: module[3].to_string(),
+            file_name: module[4].to_string(),
+            files,
+            records,
+            n_funcs,
+        })
+    }
+
+    /// Concatenate the records of several files in one module (with a null debug id):
+    /// the FILE and INLINE_ORIGIN ids of each file are shifted to not overlap.
+    pub(crate) fn concat(file_name: &str, infos: Vec<SymInfo>) -> common::Result<Self> {
+        let first = infos.first().ok_or("Nothing to concatenate")?;
+        let mut res = Self {
+            os: first.os.clone(),
+            cpu: first.cpu.clone(),
+            debug_id: "0".repeat(33),
+            file_name: file_name.to_string(),
+            files: BTreeMap::new(),
+            records: Vec::new(),
+            n_funcs: 0,
+        };
+
+        let mut n_files = 0;
+        let mut n_origins = 0;
+        for info in infos {
+            let file_offset = n_files;
+            let origin_offset = n_origins;
+            for (n, file_name) in info.files {
+                n_files = n_files.max(n + file_offset + 1);
+                res.files.insert(n + file_offset, file_name);
+            }
+
+            for record in info.records {
+                let record = match record {
+                    Record::Other(r) if r.starts_with("INFO ") => continue,
+                    Record::InlineOrigin { id, name } => {
+                        n_origins = n_origins.max(id + origin_offset + 1);
+                        Record::InlineOrigin {
+                            id: id + origin_offset,
+                            name,
+                        }
+                    }
+                    Record::Line { head, file } => Record::Line {
+                        head,
+                        file: file + file_offset,
+                    },
+                    Record::Inline {
+                        head,
+                        file,
+                        origin,
+                        ranges,
+                    } => Record::Inline {
+                        head,
+                        file: file + file_offset,
+                        origin: origin + origin_offset,
+                        ranges,
+                    },
+                    record => record,
+                };
+                res.records.push(record);
+            }
+            res.n_funcs += info.n_funcs;
+        }
+
+        Ok(res)
+    }
+
+    pub(crate) fn with_module_name(mut self, name: Option<&str>) -> Self {
+        if let Some(name) = name {
+            self.file_name = name.to_string();
+        }
+        self
+    }
+}
+
+impl Mergeable for SymInfo {
+    fn merge(left: SymInfo, right: SymInfo) -> common::Result<SymInfo> {
+        if left.debug_id != right.debug_id {
+            return Err(format!(
+                "The files don't have the same debug id: {} and {}",
+                left.debug_id, right.debug_id
+            )
+            .into());
+        }
+
+        // The one with the most functions is preferred
+        if right.n_funcs > left.n_funcs {
+            Ok(right)
+        } else {
+            Ok(left)
+        }
+    }
+}
+
+impl Dumpable for SymInfo {
+    fn dump<W: Write>(&self, mut writer: W) -> common::Result<()> {
+        write!(writer, "{}", self)?;
+        Ok(())
+    }
+
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        // The records are the ones read in the .sym file (with the files mapped)
+        visitor::visit_text(visitor, &self.to_string())
+    }
+
+    fn get_debug_id(&self) -> &str {
+        &self.debug_id
+    }
+
+    fn get_name(&self) -> &str {
+        &self.file_name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::common::FileType;
+
+    const SYM: &str = "MODULE Linux x86_64 0F1E2D3C4B5A69788796A5B4C3D2E1F00 libfoo.so\n\
+                       INFO CODE_ID 3C2D1E0F5A4B7869\n\
+                       FILE 0 /build/foo/a.cpp\n\
+                       FILE 1 /build/foo/b.h\n\
+                       INLINE_ORIGIN 0 inline_1(int)\n\
+                       FUNC m 1000 2a 0 foo(int, char const*)\n\
+                       1000 10 12 0\n\
+                       INLINE 0 13 1 0 1004 8\n\
+                       1010 1a 14 1\n\
+                       PUBLIC 2000 0 bar\n\
+                       PUBLIC m 2010 0 baz qux\n\
+                       STACK CFI INIT 1000 2a .cfa: $rsp 8 + .ra: .cfa -8 + ^\n";
+
+    #[test]
+    fn test_round_trip() {
+        assert!(matches!(
+            FileType::from_buf(SYM.as_bytes()),
+            FileType::Breakpad
+        ));
+
+        let info = SymInfo::new(SYM.as_bytes(), None).unwrap();
+        assert_eq!(info.get_name(), "libfoo.so");
+        assert_eq!(info.get_debug_id(), "0F1E2D3C4B5A69788796A5B4C3D2E1F00");
+
+        let mut output = Vec::new();
+        info.dump(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), SYM);
+    }
+
+    #[test]
+    fn test_module_name() {
+        let info = SymInfo::new(SYM.as_bytes(), None)
+            .unwrap()
+            .with_module_name(Some("libfoo.so.1"));
+        let mut output = Vec::new();
+        info.dump(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output
+            .starts_with("MODULE Linux x86_64 0F1E2D3C4B5A69788796A5B4C3D2E1F00 libfoo.so.1\n"));
+    }
+
+    #[test]
+    fn test_concat() {
+        let other = "MODULE Linux x86_64 1F1E2D3C4B5A69788796A5B4C3D2E1F00 libbar.so\n\
+                     FILE 0 /build/bar/c.cpp\n\
+                     INLINE_ORIGIN 0 inline_2(int)\n\
+                     FUNC 3000 10 0 bar()\n\
+                     INLINE 0 5 0 0 3004 4\n\
+                     3000 10 4 0\n";
+        let infos = vec![
+            SymInfo::new(SYM.as_bytes(), None).unwrap(),
+            SymInfo::new(other.as_bytes(), None).unwrap(),
+        ];
+        let info = SymInfo::concat("libfoo.a", infos).unwrap();
+        let mut output = Vec::new();
+        info.dump(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(
+            output,
+            "MODULE Linux x86_64 000000000000000000000000000000000 libfoo.a\n\
+             FILE 0 /build/foo/a.cpp\n\
+             FILE 1 /build/foo/b.h\n\
+             FILE 2 /build/bar/c.cpp\n\
+             INLINE_ORIGIN 0 inline_1(int)\n\
+             FUNC m 1000 2a 0 foo(int, char const*)\n\
+             1000 10 12 0\n\
+             INLINE 0 13 1 0 1004 8\n\
+             1010 1a 14 1\n\
+             PUBLIC 2000 0 bar\n\
+             PUBLIC m 2010 0 baz qux\n\
+             STACK CFI INIT 1000 2a .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+             INLINE_ORIGIN 1 inline_2(int)\n\
+             FUNC 3000 10 0 bar()\n\
+             INLINE 0 5 2 1 3004 4\n\
+             3000 10 4 2\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(SymInfo::new(b"FUNC 1000 2a 0 foo\n", None).is_err());
+        assert!(SymInfo::new(b"MODULE Linux x86_64 ID\n", None).is_err());
+
+        let err = SymInfo::new(b"MODULE Linux x86_64 ID foo\nFUNC 1000 2a\n", None).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid record at line 2: FUNC 1000 2a");
+    }
+}
@@ -3,40 +3,236 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use crossbeam::channel::Receiver;
 use dirs::home_dir;
+#[cfg(feature = "network")]
 use futures::{stream, StreamExt};
-use reqwest::{self, blocking, header::USER_AGENT, Client};
+use http::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+#[cfg(feature = "network")]
+use http::header::{CONTENT_RANGE, RANGE};
+use http::Method;
+#[cfg(feature = "network")]
+use http::StatusCode;
+use log::warn;
+use regex::Regex;
+#[cfg(feature = "network")]
+use reqwest::{blocking, Client};
+use sha2::{Digest, Sha256};
 use std::fs::{self, File};
-use std::io::{BufWriter, Read, Write};
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::io::{Read, Write};
+#[cfg(feature = "network")]
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "network")]
+use std::sync::Mutex;
+use std::sync::{Arc, RwLock};
+#[cfg(feature = "network")]
 use tokio::runtime::Runtime;
 use url::Url;
 
 use crate::common;
+use crate::config::{ProjectConfig, ServerConfig};
+#[cfg(feature = "network")]
+use crate::pipeline;
+use crate::pipeline::RemoteFile;
+use crate::shutdown;
 use crate::utils;
 
+lazy_static! {
+    static ref ACTIVE_CONFIG: ActiveConfig = ActiveConfig::default();
+}
+
 const DEFAULT_STORE: &str = "https://msdl.microsoft.com/download/symbols";
 const DEFAULT_USER_AGENT: &str = "Microsoft-Symbol-Server/6.3.0.0";
+#[cfg(not(feature = "network"))]
+const NO_NETWORK: &str = "dump_syms has been built without the network feature";
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SymbolServer {
     cache: Option<String>,
     server: String,
+    headers: HeaderMap,
+    hook: Option<RequestHook>,
+    filter: Option<FileFilter>,
+}
+
+/// A request to a symbol server which is about to be sent
+#[derive(Clone, Debug)]
+pub struct SymbolRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+}
+
+/// A callback called just before sending a request to a symbol server:
+/// it can change the url and the headers, for example to sign them
+/// (AWS SigV4, GCS signed urls, ...).
+#[derive(Clone)]
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+pub struct RequestHook(Arc<dyn Fn(&mut SymbolRequest) + Send + Sync>);
+
+impl RequestHook {
+    pub fn new<F: Fn(&mut SymbolRequest) + Send + Sync + 'static>(hook: F) -> Self {
+        Self(Arc::new(hook))
+    }
+}
+
+impl std::fmt::Debug for RequestHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "RequestHook")
+    }
 }
 
+/// Some file names matching some globs (case insensitive) or a regex
+/// (e.g. the debug files for which a symbol server must be queried)
 #[derive(Clone, Debug)]
+pub(crate) struct FileFilter {
+    patterns: Vec<Regex>,
+}
+
+impl FileFilter {
+    fn glob_to_regex(glob: &str) -> String {
+        let mut re = String::from("(?i)^");
+        for c in glob.chars() {
+            match c {
+                '*' => re.push_str(".*"),
+                '?' => re.push('.'),
+                _ => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        re.push('$');
+        re
+    }
+
+    pub(crate) fn new(globs: &[String], regex: Option<&str>) -> common::Result<Option<Self>> {
+        let mut patterns = Vec::new();
+        for glob in globs {
+            patterns.push(Regex::new(&Self::glob_to_regex(glob))?);
+        }
+        if let Some(regex) = regex {
+            patterns.push(Regex::new(regex)?);
+        }
+
+        if patterns.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Self { patterns }))
+        }
+    }
+
+    pub(crate) fn matches(&self, file_name: &str) -> bool {
+        self.patterns.iter().any(|p| p.is_match(file_name))
+    }
+}
+
+/// Parse a header KEY:VALUE
+pub(crate) fn parse_header(header: &str) -> common::Result<(HeaderName, HeaderValue)> {
+    let mut parts = header.splitn(2, ':');
+    let (key, value) = match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) => (key.trim(), value.trim()),
+        _ => return Err(format!("Invalid header {}: must be KEY:VALUE", header).into()),
+    };
+    let key = HeaderName::from_bytes(key.as_bytes())
+        .map_err(|_| format!("Invalid header name: {}", key))?;
+    let value =
+        HeaderValue::from_str(value).map_err(|_| format!("Invalid header value: {}", value))?;
+
+    Ok((key, value))
+}
+
+/// The user-agent and the extra headers used in the requests to the symbol servers
+#[derive(Clone, Debug, Default)]
+pub struct HttpOptions {
+    user_agent: Option<String>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    hook: Option<RequestHook>,
+}
+
+impl HttpOptions {
+    /// Each header is a string KEY:VALUE
+    pub fn new(user_agent: Option<&str>, headers: &Option<Vec<&str>>) -> common::Result<Self> {
+        let mut res = Self {
+            user_agent: user_agent.map(|ua| ua.to_string()),
+            headers: Vec::new(),
+            hook: None,
+        };
+
+        for header in headers.iter().flatten() {
+            res.headers.push(parse_header(header)?);
+        }
+
+        if let Some(user_agent) = res.user_agent.as_ref() {
+            HeaderValue::from_str(user_agent)
+                .map_err(|_| format!("Invalid user-agent: {}", user_agent))?;
+        }
+
+        Ok(res)
+    }
+
+    /// Set a callback to modify each request before sending it
+    pub fn with_request_hook(mut self, hook: RequestHook) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    pub(crate) fn get_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let user_agent = self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        headers.insert(USER_AGENT, HeaderValue::from_str(user_agent).unwrap());
+        for (key, value) in self.headers.iter() {
+            headers.append(key.clone(), value.clone());
+        }
+        headers
+    }
+
+    /// A request with the headers (modified by the hook if any)
+    #[cfg(feature = "network")]
+    pub(crate) fn get_request(&self, method: Method, url: &str) -> SymbolRequest {
+        let mut request = SymbolRequest {
+            method,
+            url: url.to_string(),
+            headers: self.get_headers(),
+        };
+        if let Some(hook) = self.hook.as_ref() {
+            (hook.0)(&mut request);
+        }
+        request
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
 struct Job {
     cache: Option<PathBuf>,
     url: String,
+    headers: HeaderMap,
+    hook: Option<RequestHook>,
 }
 
 impl Job {
-    fn new(cache: Option<PathBuf>, url: String) -> common::Result<Self> {
+    fn new(cache: Option<PathBuf>, url: String, server: &SymbolServer) -> common::Result<Self> {
         if Url::parse(&url).is_err() {
             return Err(From::from(format!("Invalid url: {}", url)));
         }
-        Ok(Self { cache, url })
+        Ok(Self {
+            cache,
+            url,
+            headers: server.headers.clone(),
+            hook: server.hook.clone(),
+        })
+    }
+
+    #[cfg_attr(not(feature = "network"), allow(dead_code))]
+    fn get_request(&self, method: Method) -> SymbolRequest {
+        let mut request = SymbolRequest {
+            method,
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+        };
+        if let Some(hook) = self.hook.as_ref() {
+            (hook.0)(&mut request);
+        }
+        request
     }
 }
 
@@ -62,14 +258,23 @@ fn parse_srv(path: &str) -> Option<SymbolServer> {
         1 => SymbolServer {
             cache: None,
             server: DEFAULT_STORE.to_string(),
+            headers: HeaderMap::new(),
+            hook: None,
+            filter: None,
         },
         2 => SymbolServer {
             cache: None,
             server: parts[1].to_string(),
+            headers: HeaderMap::new(),
+            hook: None,
+            filter: None,
         },
         3 => SymbolServer {
             cache: Some(correct_path(parts[1])),
             server: parts[2].to_string(),
+            headers: HeaderMap::new(),
+            hook: None,
+            filter: None,
         },
         _ => return None,
     };
@@ -83,27 +288,130 @@ fn parse_sympath(path: &str) -> Vec<SymbolServer> {
         .collect()
 }
 
-fn read_config() -> Option<Vec<SymbolServer>> {
-    let home = match home_dir() {
-        Some(h) => h,
-        _ => return None,
-    };
+fn get_user_config_path() -> Option<PathBuf> {
+    home_dir().map(|home| home.join(".dump_syms").join("config"))
+}
 
-    let conf = home.join(".dump_syms").join("config");
-    if !conf.exists() {
-        return None;
-    }
+fn read_user_config(conf: Option<PathBuf>) -> common::Result<Option<Vec<SymbolServer>>> {
+    let conf = match conf {
+        Some(conf) if conf.exists() => conf,
+        _ => return Ok(None),
+    };
 
     let mut file = File::open(&conf)
-        .unwrap_or_else(|_| panic!("Unable to open the file {}", conf.to_str().unwrap()));
+        .map_err(|_| format!("Unable to open the file {}", conf.to_str().unwrap()))?;
     let mut buf = Vec::new();
     file.read_to_end(&mut buf)
-        .unwrap_or_else(|_| panic!("Unable to read the file {}", conf.to_str().unwrap()));
+        .map_err(|_| format!("Unable to read the file {}", conf.to_str().unwrap()))?;
 
     let content = String::from_utf8(buf)
-        .unwrap_or_else(|_| panic!("Not utf-8 data in the file {}", conf.to_str().unwrap()));
+        .map_err(|_| format!("Not utf-8 data in the file {}", conf.to_str().unwrap()))?;
+
+    Ok(read_config_from_str(&content))
+}
+
+fn read_project_config(path: Option<PathBuf>) -> common::Result<Option<Vec<SymbolServer>>> {
+    let config = match path {
+        Some(path) => ProjectConfig::read(path)?,
+        None => return Ok(None),
+    };
+    let dir = config.get_dir();
+    let mut servers = Vec::new();
+    for server in config.symbol_servers.iter() {
+        match server {
+            ServerConfig::Path(path) => servers.extend(parse_sympath(path)),
+            ServerConfig::Filtered(filtered) => {
+                for mut server in parse_sympath(&filtered.path) {
+                    server.filter = FileFilter::new(&filtered.files, filtered.regex.as_deref())
+                        .map_err(|e| {
+                            format!(
+                                "Invalid filter for {} in {}: {}",
+                                filtered.path,
+                                config.path.to_str().unwrap(),
+                                e
+                            )
+                        })?;
+                    servers.push(server);
+                }
+            }
+        }
+    }
+
+    // A relative cache is relative to the directory containing the config
+    for server in servers.iter_mut() {
+        if let Some(cache) = server.cache.as_mut() {
+            if PathBuf::from(&cache).is_relative() {
+                *cache = dir.join(&cache).to_str().unwrap().to_string();
+            }
+        }
+    }
+
+    Ok(Some(servers))
+}
 
-    read_config_from_str(&content)
+fn read_config_files(
+    user: Option<PathBuf>,
+    project: Option<PathBuf>,
+) -> common::Result<Option<Vec<SymbolServer>>> {
+    // The project config is merged under the user one
+    let mut servers = read_user_config(user)?.unwrap_or_default();
+    if let Some(project_servers) = read_project_config(project)? {
+        servers.extend(project_servers);
+    }
+
+    if servers.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(servers))
+    }
+}
+
+fn try_read_config() -> common::Result<Option<Vec<SymbolServer>>> {
+    read_config_files(get_user_config_path(), ProjectConfig::find())
+}
+
+/// The config of a long-running process: it's only replaced by a valid one
+#[derive(Default)]
+struct ActiveConfig {
+    servers: RwLock<Option<Option<Vec<SymbolServer>>>>,
+}
+
+impl ActiveConfig {
+    fn get(&self) -> Option<Option<Vec<SymbolServer>>> {
+        self.servers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn reload(&self, servers: common::Result<Option<Vec<SymbolServer>>>) -> common::Result<()> {
+        let servers = servers?;
+        *self.servers.write().unwrap_or_else(|e| e.into_inner()) = Some(servers);
+        Ok(())
+    }
+}
+
+fn read_config() -> common::Result<Option<Vec<SymbolServer>>> {
+    // A long-running process uses the last valid config
+    if let Some(servers) = ACTIVE_CONFIG.get() {
+        return Ok(servers);
+    }
+    try_read_config()
+}
+
+/// Get the paths of the config files (the user one and the project one) which exist
+pub(crate) fn get_config_paths() -> Vec<PathBuf> {
+    get_user_config_path()
+        .filter(|p| p.is_file())
+        .into_iter()
+        .chain(ProjectConfig::find())
+        .collect()
+}
+
+/// Read the config files and make it the config of the process:
+/// an invalid config is rejected and the previous one is kept
+pub(crate) fn reload_config() -> common::Result<()> {
+    ACTIVE_CONFIG.reload(try_read_config())
 }
 
 fn read_config_from_str(s: &str) -> Option<Vec<SymbolServer>> {
@@ -115,42 +423,102 @@ fn read_config_from_str(s: &str) -> Option<Vec<SymbolServer>> {
     }
 }
 
-pub fn get_sym_servers(symbol_server: Option<&str>) -> Option<Vec<SymbolServer>> {
-    symbol_server.map_or_else(read_config, read_config_from_str)
+pub fn get_sym_servers(
+    symbol_server: Option<&str>,
+    http: &HttpOptions,
+) -> common::Result<Option<Vec<SymbolServer>>> {
+    let servers = match symbol_server {
+        Some(symbol_server) => read_config_from_str(symbol_server),
+        None => read_config()?,
+    };
+    let mut servers = match servers {
+        Some(servers) => servers,
+        None => return Ok(None),
+    };
+    let headers = http.get_headers();
+    for server in servers.iter_mut() {
+        server.headers = headers.clone();
+        server.hook = http.hook.clone();
+    }
+    Ok(Some(servers))
 }
 
-fn copy_in_cache(path: Option<PathBuf>, data: &[u8]) -> bool {
-    if data.is_empty() || data.starts_with(b"Symbol Not Found") {
-        return false;
+pub fn get_caches(sym_servers: Option<&Vec<SymbolServer>>) -> Vec<PathBuf> {
+    let mut caches: Vec<PathBuf> = Vec::new();
+    for cache in sym_servers
+        .into_iter()
+        .flatten()
+        .filter_map(|s| s.cache.as_ref())
+    {
+        let cache = PathBuf::from(cache);
+        if !caches.contains(&cache) {
+            caches.push(cache);
+        }
     }
+    caches
+}
 
-    let path = match path {
-        Some(p) => p,
-        _ => return true,
-    };
+fn is_valid_data(data: &[u8]) -> bool {
+    !data.is_empty() && !data.starts_with(b"Symbol Not Found")
+}
 
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+fn create_in_cache(path: &Path) -> common::Result<shutdown::AtomicFile> {
     if let Some(parent) = path.parent() {
         if !parent.exists() {
-            fs::create_dir_all(parent).unwrap_or_else(|_| {
-                panic!(
-                    "Unable to create cache directory {}",
-                    parent.to_str().unwrap()
+            fs::create_dir_all(parent).map_err(|e| {
+                format!(
+                    "Unable to create cache directory {}: {}",
+                    parent.to_str().unwrap(),
+                    e
                 )
-            });
+            })?;
         }
     }
+    shutdown::AtomicFile::create(path)
+}
 
-    let output = File::create(&path)
-        .unwrap_or_else(|_| panic!("Cannot open file {} for writing", path.to_str().unwrap()));
-    let mut output = BufWriter::new(output);
-    output
-        .write_all(data)
-        .unwrap_or_else(|_| panic!("Cannot write file {}", path.to_str().unwrap()));
+/// Write the downloaded chunks in the cache while the file is downloaded
+#[cfg_attr(not(feature = "network"), allow(dead_code))]
+fn write_in_cache(path: Option<PathBuf>, chunks: Receiver<Vec<u8>>) -> common::Result<bool> {
+    let mut file = None;
+    let mut valid = None;
 
-    true
+    for chunk in chunks {
+        // The first chunk is enough to know if the server has sent an error
+        let valid = *valid.get_or_insert_with(|| is_valid_data(&chunk));
+        if !valid {
+            continue;
+        }
+
+        let path = match path.as_ref() {
+            Some(p) => p,
+            _ => continue,
+        };
+
+        if file.is_none() {
+            file = Some(create_in_cache(path)?);
+        }
+
+        file.as_mut()
+            .unwrap()
+            .write_all(&chunk)
+            .map_err(|e| format!("Cannot write file {}: {}", path.to_str().unwrap(), e))?;
+    }
+
+    if valid != Some(true) {
+        // The temporary file (if any) is removed
+        return Ok(false);
+    }
+
+    if let Some(file) = file {
+        file.commit()?;
+    }
+
+    Ok(true)
 }
 
-fn get_base(file_name: &str) -> PathBuf {
+pub(crate) fn get_base(file_name: &str) -> PathBuf {
     // The file is stored at cache/xul.pdb/DEBUG_ID/xul.pd_
     // the xul.pdb represents the base
     let path = PathBuf::from(file_name);
@@ -170,7 +538,16 @@ fn get_base(file_name: &str) -> PathBuf {
 pub fn get_path_for_sym(file_name: &str, id: &str) -> PathBuf {
     let base = get_base(file_name);
     let file_name = PathBuf::from(file_name);
-    let file_name = file_name.with_extension("sym");
+    // Keep the version of a soname: libfoo.so.1 => libfoo.so.1.sym
+    let is_versioned = file_name
+        .extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| e.chars().all(|c| c.is_ascii_digit()));
+    let file_name = if is_versioned {
+        PathBuf::from(format!("{}.sym", file_name.display()))
+    } else {
+        file_name.with_extension("sym")
+    };
     base.join(id).join(file_name)
 }
 
@@ -192,7 +569,11 @@ fn search_in_cache(
 fn get_jobs(servers: &[SymbolServer], id: &str, base: &PathBuf, file_name: &str) -> Vec<Job> {
     // The query urls are: https://symbols.mozilla.org/xul.pdb/DEBUG_ID/xul.pd_
     let mut jobs = Vec::new();
-    for server in servers.iter() {
+    let base_name = base.to_str().unwrap();
+    for server in servers
+        .iter()
+        .filter(|s| s.filter.as_ref().map_or(true, |f| f.matches(base_name)))
+    {
         let path = if let Some(cache) = server.cache.as_ref() {
             Some(PathBuf::from(cache).join(base).join(id).join(&file_name))
         } else {
@@ -201,6 +582,7 @@ fn get_jobs(servers: &[SymbolServer], id: &str, base: &PathBuf, file_name: &str)
         let job = Job::new(
             path.clone(),
             format!("{}/{}/{}/{}", server.server, file_name, id, file_name),
+            server,
         )
         .unwrap_or_else(|e| panic!("{}", e));
         jobs.push(job);
@@ -214,6 +596,7 @@ fn get_jobs(servers: &[SymbolServer], id: &str, base: &PathBuf, file_name: &str)
                     id,
                     &file_name[..file_name.len() - 1]
                 ),
+                server,
             )
             .unwrap_or_else(|e| panic!("{}", e));
             jobs.push(job);
@@ -223,10 +606,12 @@ fn get_jobs(servers: &[SymbolServer], id: &str, base: &PathBuf, file_name: &str)
     jobs
 }
 
+#[cfg(feature = "network")]
 async fn check_if_file_exists(results: Arc<Mutex<Vec<Job>>>, client: &Client, job: Job) {
+    let request = job.get_request(Method::HEAD);
     if let Ok(res) = client
-        .head(&job.url)
-        .header(USER_AGENT, DEFAULT_USER_AGENT)
+        .request(request.method, &request.url)
+        .headers(request.headers)
         .send()
         .await
     {
@@ -237,6 +622,7 @@ async fn check_if_file_exists(results: Arc<Mutex<Vec<Job>>>, client: &Client, jo
     }
 }
 
+#[cfg(feature = "network")]
 fn check_data(jobs: Vec<Job>) -> Option<Job> {
     let client = Client::new();
     let n_queries = jobs.len();
@@ -258,27 +644,141 @@ fn check_data(jobs: Vec<Job>) -> Option<Job> {
     results.first().cloned()
 }
 
-fn fetch_data(jobs: Vec<Job>) -> Option<Vec<u8>> {
-    if let Some(job) = check_data(jobs) {
-        let mut buf = Vec::new();
-        let client = blocking::Client::new();
-        let resp = client
-            .get(&job.url)
-            .header(USER_AGENT, DEFAULT_USER_AGENT)
-            .send();
-        if let Ok(mut resp) = resp {
-            if resp.copy_to(&mut buf).is_err() {
-                None
-            } else if copy_in_cache(job.cache, &buf) {
-                Some(buf)
-            } else {
-                None
+/// The files are only searched in the caches
+#[cfg(not(feature = "network"))]
+fn check_data(jobs: Vec<Job>) -> Option<Job> {
+    if let Some(job) = jobs.first() {
+        warn!("{}: {} isn't queried", NO_NETWORK, job.url);
+    }
+    None
+}
+
+/// Download the data (they're written in the cache while they're downloaded):
+/// None if the server has sent an error page
+#[cfg(feature = "network")]
+fn download(job: Job) -> common::Result<Option<Vec<u8>>> {
+    let client = blocking::Client::new();
+    let request = job.get_request(Method::GET);
+    let mut resp = client
+        .request(request.method, &request.url)
+        .headers(request.headers)
+        .send()?;
+    if !resp.status().is_success() {
+        return Err(format!("Status {} for {}", resp.status(), request.url).into());
+    }
+
+    let mut buf = Vec::new();
+    let cached = pipeline::pipe(
+        |writer| {
+            let mut chunk = vec![0; 64 * 1024];
+            loop {
+                let n = resp.read(&mut chunk)?;
+                if n == 0 {
+                    return Ok(());
+                }
+                buf.extend_from_slice(&chunk[..n]);
+                writer.write_all(&chunk[..n])?;
             }
-        } else {
-            None
+        },
+        |chunks| write_in_cache(job.cache, chunks),
+    )?;
+
+    Ok(Some(buf).filter(|_| cached))
+}
+
+#[cfg(not(feature = "network"))]
+fn download(job: Job) -> common::Result<Option<Vec<u8>>> {
+    Err(format!("{}: {} can't be downloaded", NO_NETWORK, job.url).into())
+}
+
+#[cfg(feature = "network")]
+fn send_range(
+    client: &blocking::Client,
+    job: &Job,
+    range: &str,
+) -> common::Result<blocking::Response> {
+    let mut request = job.get_request(Method::GET);
+    request
+        .headers
+        .insert(RANGE, HeaderValue::from_str(&format!("bytes={}", range))?);
+    Ok(client
+        .request(request.method, &request.url)
+        .headers(request.headers)
+        .send()?)
+}
+
+/// The size of the file in the Content-Range of a partial response (bytes 0-1023/4096)
+#[cfg(feature = "network")]
+fn get_size(resp: &blocking::Response) -> Option<u64> {
+    let range = resp.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    range.rsplit('/').next()?.parse().ok()
+}
+
+/// Read the file while it's downloaded (it's written in the cache at the end of the download):
+/// None if the server doesn't handle the range requests
+#[cfg(feature = "network")]
+fn open_remote(job: Job) -> common::Result<Option<RemoteFile>> {
+    let client = blocking::Client::new();
+    let resp = send_range(&client, &job, "0-")?;
+    let size = match (resp.status(), get_size(&resp)) {
+        (StatusCode::PARTIAL_CONTENT, Some(size)) => size,
+        _ => return Ok(None),
+    };
+
+    let cache = job.cache.clone();
+    let fetch = move |range: Range<u64>| -> common::Result<Box<dyn Read + Send>> {
+        let range = format!("{}-{}", range.start, range.end - 1);
+        let resp = send_range(&client, &job, &range)?;
+        if resp.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(format!("Status {} for {} ({})", resp.status(), job.url, range).into());
         }
-    } else {
+        Ok(Box::new(resp))
+    };
+    let file = RemoteFile::start(size, Some(Box::new(resp)), Box::new(fetch), move |data| {
+        let path = match cache.filter(|_| is_valid_data(data)) {
+            Some(path) => path,
+            _ => return,
+        };
+        let res = create_in_cache(&path).and_then(|mut file| {
+            file.write_all(data)?;
+            file.commit()
+        });
+        if let Err(e) = res {
+            warn!("Cannot write file {}: {}", path.display(), e);
+        }
+    })?;
+
+    Ok(Some(file))
+}
+
+fn fetch_data(jobs: Vec<Job>) -> Option<Vec<u8>> {
+    let job = check_data(jobs)?;
+    let url = job.url.clone();
+    download(job).unwrap_or_else(|e| {
+        warn!("Unable to download {}: {}", url, e);
         None
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prefetched {
+    Cached,
+    Downloaded,
+    NotFound,
+}
+
+/// Download the file in the caches (if not already there) without reading it
+pub fn prefetch_file(file_name: &str, id: &str, servers: &[SymbolServer]) -> Prefetched {
+    let base = get_base(file_name);
+    if search_in_cache(servers, id, &base, file_name).is_some() {
+        return Prefetched::Cached;
+    }
+
+    let jobs = get_jobs(servers, id, &base, file_name);
+    if fetch_data(jobs).is_some() {
+        Prefetched::Downloaded
+    } else {
+        Prefetched::NotFound
     }
 }
 
@@ -295,6 +795,7 @@ pub fn search_file(
         Some(s) => s,
         _ => return (None, file_name),
     };
+    let _span = utils::Span::new(format!("fetch {}/{}", file_name, id));
 
     let base = get_base(&file_name);
 
@@ -317,3 +818,324 @@ pub fn search_file(
         (None, file_name)
     }
 }
+
+/// Open a file of the symbol servers to parse it while it's downloaded: None when it's in a cache,
+/// when it's compressed or when the servers don't handle the range requests (it must be fetched)
+#[cfg(feature = "network")]
+pub(crate) fn open_file(
+    file_name: &str,
+    id: &str,
+    sym_servers: Option<&Vec<SymbolServer>>,
+) -> Option<RemoteFile> {
+    let servers = sym_servers?;
+    if file_name.is_empty() || file_name.ends_with('_') {
+        return None;
+    }
+    let base = get_base(file_name);
+    if search_in_cache(servers, id, &base, file_name).is_some() {
+        return None;
+    }
+
+    // A cabinet file can't be read before the end of its download
+    let jobs = get_jobs(servers, id, &base, file_name)
+        .into_iter()
+        .filter(|job| !job.url.ends_with('_'))
+        .collect();
+    let job = check_data(jobs)?;
+    let url = job.url.clone();
+    open_remote(job).unwrap_or_else(|e| {
+        warn!("Unable to download {}: {}", url, e);
+        None
+    })
+}
+
+#[cfg(not(feature = "network"))]
+pub(crate) fn open_file(
+    _file_name: &str,
+    _id: &str,
+    _sym_servers: Option<&Vec<SymbolServer>>,
+) -> Option<RemoteFile> {
+    None
+}
+
+/// Where a debug file would be got from (nothing is downloaded)
+pub(crate) enum Location {
+    Cached(PathBuf),
+    // The urls which would be queried
+    Servers(Vec<String>),
+}
+
+pub(crate) fn locate_file(
+    file_name: &str,
+    id: &str,
+    sym_servers: Option<&Vec<SymbolServer>>,
+) -> Location {
+    let servers = match sym_servers {
+        Some(s) => s,
+        _ => return Location::Servers(Vec::new()),
+    };
+
+    let base = get_base(file_name);
+    if let Some(path) = search_in_cache(servers, id, &base, file_name) {
+        return Location::Cached(path);
+    }
+
+    Location::Servers(
+        get_jobs(servers, id, &base, file_name)
+            .into_iter()
+            .map(|job| job.url)
+            .collect(),
+    )
+}
+
+/// The path in the cache of a file which has already been downloaded from its url
+pub(crate) fn locate_url(url: &Url, sym_servers: Option<&Vec<SymbolServer>>) -> Option<PathBuf> {
+    let (_, cache_path) = get_url_cache_path(url)?;
+    get_caches(sym_servers)
+        .into_iter()
+        .next()
+        .map(|cache| cache.join(cache_path))
+        .filter(|path| path.exists())
+}
+
+/// Get the url when the path of a file to dump is an http(s) one
+pub fn parse_url(path: &str) -> Option<Url> {
+    Url::parse(path)
+        .ok()
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+}
+
+/// The file name in the url and the path in the cache (file_name/URL_HASH/file_name)
+fn get_url_cache_path(url: &Url) -> Option<(String, PathBuf)> {
+    let file_name = url
+        .path_segments()?
+        .last()
+        .filter(|name| !name.is_empty())?;
+    let hash = Sha256::digest(url.as_str().as_bytes());
+    let id: String = hash[..16].iter().map(|b| format!("{:02X}", b)).collect();
+
+    Some((
+        file_name.to_string(),
+        PathBuf::from(file_name).join(id).join(file_name),
+    ))
+}
+
+/// Download a file from its url (e.g. a build artifact): it's put in the cache of the first
+/// symbol server (if any) so it isn't downloaded again.
+pub fn download_file(
+    url: &Url,
+    sym_servers: Option<&Vec<SymbolServer>>,
+    http: &HttpOptions,
+) -> common::Result<(Vec<u8>, String)> {
+    let (file_name, cache_path) =
+        get_url_cache_path(url).ok_or_else(|| format!("No file name in the url {}", url))?;
+    let cache = get_caches(sym_servers)
+        .into_iter()
+        .next()
+        .map(|cache| cache.join(&cache_path));
+
+    if let Some(path) = cache.as_ref().filter(|path| path.exists()) {
+        return Ok((utils::read_file(path), file_name));
+    }
+
+    let job = Job {
+        cache,
+        url: url.to_string(),
+        headers: http.get_headers(),
+        hook: http.hook.clone(),
+    };
+    let buf = download(job)?.ok_or_else(|| format!("Unable to download {}", url))?;
+    let buf = utils::read_cabinet(buf, PathBuf::from(&file_name))
+        .ok_or_else(|| format!("Unable to read the file {} from {}", file_name, url))?;
+
+    Ok((buf, file_name))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use tempfile::Builder;
+
+    use crate::config::PROJECT_CONFIG;
+
+    use super::*;
+
+    #[test]
+    fn test_path_for_sym() {
+        assert_eq!(
+            get_path_for_sym("xul.pdb", "ABCD1"),
+            PathBuf::from("xul.pdb/ABCD1/xul.sym")
+        );
+        assert_eq!(
+            get_path_for_sym("libfoo.so.1", "ABCD0"),
+            PathBuf::from("libfoo.so.1/ABCD0/libfoo.so.1.sym")
+        );
+    }
+
+    #[test]
+    fn test_file_filter() {
+        let filter = FileFilter::new(&["ntdll.pdb".to_string(), "kernel*.pdb".to_string()], None)
+            .unwrap()
+            .unwrap();
+        assert!(filter.matches("ntdll.pdb"));
+        assert!(filter.matches("NTDLL.PDB"));
+        assert!(filter.matches("kernel32.pdb"));
+        assert!(!filter.matches("xul.pdb"));
+        assert!(!filter.matches("ntdllxpdb"));
+
+        let filter = FileFilter::new(&[], Some(r"^(user|gdi)32\.pdb$"))
+            .unwrap()
+            .unwrap();
+        assert!(filter.matches("user32.pdb"));
+        assert!(!filter.matches("xul.pdb"));
+
+        assert!(FileFilter::new(&[], None).unwrap().is_none());
+        assert!(FileFilter::new(&[], Some("(")).is_err());
+    }
+
+    #[test]
+    fn test_filtered_jobs() {
+        let mut servers = parse_sympath(
+            "SRV*https://symbols.mozilla.org/;SRV*https://msdl.microsoft.com/download/symbols",
+        );
+        servers[1].filter = FileFilter::new(&["ntdll.pdb".to_string()], None).unwrap();
+
+        let base = get_base("xul.pdb");
+        let jobs = get_jobs(&servers, "ABCDEF1", &base, "xul.pdb");
+        assert!(jobs
+            .iter()
+            .all(|j| j.url.starts_with("https://symbols.mozilla.org/")));
+
+        let base = get_base("ntdll.pdb");
+        let jobs = get_jobs(&servers, "ABCDEF1", &base, "ntdll.pdb");
+        assert!(jobs
+            .iter()
+            .any(|j| j.url.starts_with("https://msdl.microsoft.com/")));
+    }
+
+    #[test]
+    fn test_request_hook() {
+        let http = HttpOptions::default().with_request_hook(RequestHook::new(|request| {
+            if request.method == Method::GET {
+                request.url.push_str("?signature=123");
+            }
+            request
+                .headers
+                .insert("x-amz-date", HeaderValue::from_static("20210101T000000Z"));
+        }));
+        let servers = get_sym_servers(Some("SRV*https://symbols.mozilla.org/"), &http)
+            .unwrap()
+            .unwrap();
+        let base = get_base("xul.pdb");
+        let job = &get_jobs(&servers, "ABCDEF1", &base, "xul.pdb")[0];
+
+        let request = job.get_request(Method::HEAD);
+        assert_eq!(request.url, job.url);
+        assert_eq!(
+            request.headers.get("x-amz-date").unwrap(),
+            "20210101T000000Z"
+        );
+
+        let request = job.get_request(Method::GET);
+        assert_eq!(request.url, format!("{}?signature=123", job.url));
+    }
+
+    #[test]
+    fn test_url() {
+        let url = parse_url("https://artifacts.example.com/build/123/xul.pdb").unwrap();
+        let (file_name, path) = get_url_cache_path(&url).unwrap();
+        assert_eq!(file_name, "xul.pdb");
+        assert!(path.starts_with("xul.pdb"));
+        assert!(path.ends_with("xul.pdb"));
+        assert_eq!(path.components().count(), 3);
+
+        let other = parse_url("https://artifacts.example.com/build/124/xul.pdb").unwrap();
+        assert_ne!(get_url_cache_path(&other).unwrap().1, path);
+
+        assert!(parse_url("xul.pdb").is_none());
+        assert!(parse_url("C:\\build\\xul.pdb").is_none());
+        assert!(parse_url("ftp://example.com/xul.pdb").is_none());
+        assert!(get_url_cache_path(&parse_url("https://example.com/").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_http_options() {
+        let http = HttpOptions::new(
+            Some("my-agent/1.0"),
+            &Some(vec!["Auth-Token: 123abc", "X-Foo:bar:baz"]),
+        )
+        .unwrap();
+        let headers = http.get_headers();
+
+        assert_eq!(headers.get(USER_AGENT).unwrap(), "my-agent/1.0");
+        assert_eq!(headers.get("auth-token").unwrap(), "123abc");
+        assert_eq!(headers.get("x-foo").unwrap(), "bar:baz");
+
+        let headers = HttpOptions::default().get_headers();
+        assert_eq!(headers.get(USER_AGENT).unwrap(), DEFAULT_USER_AGENT);
+
+        assert!(HttpOptions::new(None, &Some(vec!["foo"])).is_err());
+        assert!(HttpOptions::new(None, &Some(vec!["foo bar:baz"])).is_err());
+    }
+
+    #[test]
+    fn test_reload_invalid_config() {
+        let tmp_dir = Builder::new().prefix("reload_config").tempdir().unwrap();
+        let conf = tmp_dir.path().join(PROJECT_CONFIG);
+        let config = ActiveConfig::default();
+        assert!(config.get().is_none());
+
+        fs::write(
+            &conf,
+            r#"symbol_servers = ["SRV*https://symbols.mozilla.org/"]"#,
+        )
+        .unwrap();
+        config
+            .reload(read_config_files(None, Some(conf.clone())))
+            .unwrap();
+
+        // The invalid config is rejected and the previous one is still active
+        fs::write(&conf, "symbol_servers = [").unwrap();
+        assert!(config
+            .reload(read_config_files(None, Some(conf.clone())))
+            .is_err());
+
+        let servers = config.get().unwrap().unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].server, "https://symbols.mozilla.org/");
+    }
+
+    #[test]
+    fn test_write_in_cache() {
+        let tmp_dir = Builder::new().prefix("write_in_cache").tempdir().unwrap();
+        let send = |chunks: &[&[u8]]| {
+            let (sender, receiver) = crossbeam::channel::unbounded();
+            for chunk in chunks {
+                sender.send(chunk.to_vec()).unwrap();
+            }
+            receiver
+        };
+
+        let path = tmp_dir
+            .path()
+            .join("xul.pdb")
+            .join("ABCDEF1")
+            .join("xul.pd_");
+        assert!(write_in_cache(Some(path.clone()), send(&[b"abc", b"def"])).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"abcdef");
+
+        // An error page isn't cached
+        let path = tmp_dir.path().join("foo.pdb");
+        assert!(!write_in_cache(Some(path.clone()), send(&[b"Symbol Not Found"])).unwrap());
+        assert!(!path.exists());
+
+        // The cache directory can't be created since its parent is a file
+        let path = tmp_dir
+            .path()
+            .join("xul.pdb")
+            .join("ABCDEF1")
+            .join("xul.pd_")
+            .join("x");
+        assert!(write_in_cache(Some(path), send(&[b"abc"])).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::common;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub(crate) enum CacheAction {
+    List,
+    Gc {
+        max_age: Option<u64>,
+        max_size: Option<u64>,
+    },
+    Clear,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    age: Duration,
+}
+
+impl CacheEntry {
+    fn days(&self) -> u64 {
+        self.age.as_secs() / SECS_PER_DAY
+    }
+}
+
+/// Parse a size like 123, 10K, 512M or 2G
+pub(crate) fn parse_size(s: &str) -> common::Result<u64> {
+    let s = s.trim();
+    let (num, factor) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+
+    num.parse::<u64>()
+        .map(|n| n * factor)
+        .map_err(|_| format!("Invalid size {}", s).into())
+}
+
+fn collect_entries(dir: &Path, now: SystemTime, entries: &mut Vec<CacheEntry>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_entries(&entry.path(), now, entries)?;
+        } else {
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|m| now.duration_since(m).ok())
+                .unwrap_or_default();
+            entries.push(CacheEntry {
+                path: entry.path(),
+                size: metadata.len(),
+                age,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn get_entries(caches: &[PathBuf]) -> common::Result<Vec<CacheEntry>> {
+    let now = SystemTime::now();
+    let mut entries = Vec::new();
+    for cache in caches.iter().filter(|c| c.is_dir()) {
+        collect_entries(cache, now, &mut entries)?;
+    }
+
+    // The oldest first
+    entries.sort_by(|a, b| b.age.cmp(&a.age));
+
+    Ok(entries)
+}
+
+fn remove_empty_dirs(dir: &Path, is_root: bool) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_empty_dirs(&entry.path(), false)?;
+        }
+    }
+
+    if !is_root && fs::read_dir(dir)?.next().is_none() {
+        fs::remove_dir(dir)?;
+    }
+
+    Ok(())
+}
+
+fn remove(entry: &CacheEntry) -> bool {
+    if let Err(e) = fs::remove_file(&entry.path) {
+        warn!(
+            "Unable to remove the file {}: {}",
+            entry.path.to_str().unwrap(),
+            e
+        );
+        false
+    } else {
+        info!("Remove {}", entry.path.to_str().unwrap());
+        true
+    }
+}
+
+fn list(caches: &[PathBuf]) -> common::Result<()> {
+    let entries = get_entries(caches)?;
+    let mut total = 0;
+    for entry in entries.iter() {
+        println!(
+            "{:>12} {:>5}d {}",
+            entry.size,
+            entry.days(),
+            entry.path.to_str().unwrap()
+        );
+        total += entry.size;
+    }
+    println!("{} files, {} bytes", entries.len(), total);
+
+    Ok(())
+}
+
+fn gc(caches: &[PathBuf], max_age: Option<u64>, max_size: Option<u64>) -> common::Result<()> {
+    let entries = get_entries(caches)?;
+    let mut total: u64 = entries.iter().map(|e| e.size).sum();
+    let mut removed = 0;
+
+    for entry in entries.iter() {
+        let too_old = max_age.map_or(false, |days| entry.days() >= days);
+        let too_big = max_size.map_or(false, |size| total > size);
+        if !too_old && !too_big {
+            // The entries are sorted from the oldest to the newest
+            // so we can stop here.
+            break;
+        }
+        if remove(entry) {
+            total -= entry.size;
+            removed += 1;
+        }
+    }
+
+    for cache in caches.iter().filter(|c| c.is_dir()) {
+        remove_empty_dirs(cache, true)?;
+    }
+
+    println!("{} files removed, {} bytes remaining", removed, total);
+
+    Ok(())
+}
+
+fn clear(caches: &[PathBuf]) -> common::Result<()> {
+    let entries = get_entries(caches)?;
+    let removed = entries.iter().filter(|e| remove(e)).count();
+
+    for cache in caches.iter().filter(|c| c.is_dir()) {
+        remove_empty_dirs(cache, true)?;
+    }
+
+    println!("{} files removed", removed);
+
+    Ok(())
+}
+
+impl CacheAction {
+    pub(crate) fn action(&self, caches: &[PathBuf]) -> common::Result<()> {
+        if caches.is_empty() {
+            return Err("No cache directory in the symbol server configuration".into());
+        }
+
+        match self {
+            Self::List => list(caches),
+            Self::Gc { max_age, max_size } => gc(caches, *max_age, *max_size),
+            Self::Clear => clear(caches),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use tempfile::Builder;
+
+    use super::*;
+
+    fn create_cache(dir: &Path) {
+        for (name, size) in &[("a.pdb", 10), ("b.pdb", 20), ("c.pdb", 30)] {
+            let path = dir.join(name).join("ABCDEF1").join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, vec![0; *size]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("123").unwrap(), 123);
+        assert_eq!(parse_size("2k").unwrap(), 2048);
+        assert_eq!(parse_size("3M").unwrap(), 3 * 1024 * 1024);
+        assert_eq!(parse_size("1G").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_size("foo").is_err());
+    }
+
+    #[test]
+    fn test_gc_max_size() {
+        let tmp_dir = Builder::new().prefix("cache_gc").tempdir().unwrap();
+        let cache = tmp_dir.path().to_path_buf();
+        create_cache(&cache);
+
+        let caches = vec![cache];
+        CacheAction::Gc {
+            max_age: None,
+            max_size: Some(35),
+        }
+        .action(&caches)
+        .unwrap();
+
+        let entries = get_entries(&caches).unwrap();
+        let total: u64 = entries.iter().map(|e| e.size).sum();
+        assert!(total <= 35);
+        assert!(!entries.is_empty());
+    }
+
+    #[test]
+    fn test_clear() {
+        let tmp_dir = Builder::new().prefix("cache_clear").tempdir().unwrap();
+        let cache = tmp_dir.path().to_path_buf();
+        create_cache(&cache);
+
+        let caches = vec![cache.clone()];
+        CacheAction::Clear.action(&caches).unwrap();
+
+        assert!(get_entries(&caches).unwrap().is_empty());
+        assert!(cache.is_dir());
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,168 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A dump made with the library can be aborted with a CancellationToken (cancelled by another
+// thread or with a deadline). The token is the current one of the thread which dumps (and of the
+// threads helping it) and it's checked between the phases of a dump and in the loops on the
+// compilation units (or the PDB modules): the dump then fails with a Cancelled error.
+
+use std::cell::RefCell;
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::common;
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// A token to abort a dump: the clones share the cancellation
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The dump is aborted once the deadline has passed
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// The dump is aborted once the timeout has elapsed (from now)
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Abort the dumps using this token (or one of its clones)
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.get_error().is_some()
+    }
+
+    fn get_error(&self) -> Option<Cancelled> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Some(Cancelled {
+                deadline_exceeded: false,
+            })
+        } else if self.deadline.map_or(false, |d| Instant::now() >= d) {
+            Some(Cancelled {
+                deadline_exceeded: true,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// The error of a dump which has been aborted by its CancellationToken
+#[derive(Debug)]
+pub struct Cancelled {
+    deadline_exceeded: bool,
+}
+
+impl Cancelled {
+    /// True when the dump has been aborted by the deadline (and not by cancel)
+    pub fn is_deadline_exceeded(&self) -> bool {
+        self.deadline_exceeded
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.deadline_exceeded {
+            write!(f, "The deadline of the dump has been exceeded")
+        } else {
+            write!(f, "The dump has been cancelled")
+        }
+    }
+}
+
+impl error::Error for Cancelled {}
+
+/// The token is the current one of the thread until the guard is dropped
+pub(crate) struct CurrentGuard {
+    previous: Option<CancellationToken>,
+}
+
+impl Drop for CurrentGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+pub(crate) fn set_current(token: Option<CancellationToken>) -> CurrentGuard {
+    let previous = CURRENT.with(|current| current.replace(token));
+    CurrentGuard { previous }
+}
+
+pub(crate) fn get_current() -> Option<CancellationToken> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// True when the current dump must be aborted (to leave a loop before calling check)
+pub(crate) fn is_cancelled() -> bool {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map_or(false, |t| t.is_cancelled())
+    })
+}
+
+/// Fail when the current dump must be aborted
+pub(crate) fn check() -> common::Result<()> {
+    let error = CURRENT.with(|current| current.borrow().as_ref().and_then(|t| t.get_error()));
+    match error {
+        Some(error) => Err(Box::new(error)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_cancel() {
+        assert!(check().is_ok());
+
+        let token = CancellationToken::new();
+        {
+            let _guard = set_current(Some(token.clone()));
+            assert!(check().is_ok());
+            token.clone().cancel();
+            assert!(is_cancelled());
+            let e = check().unwrap_err();
+            assert!(!e
+                .downcast_ref::<Cancelled>()
+                .unwrap()
+                .is_deadline_exceeded());
+        }
+        assert!(check().is_ok() && get_current().is_none());
+
+        let token = CancellationToken::new().with_timeout(Duration::from_secs(0));
+        let _guard = set_current(Some(token));
+        let e = check().unwrap_err();
+        assert!(e
+            .downcast_ref::<Cancelled>()
+            .unwrap()
+            .is_deadline_exceeded());
+        assert_eq!(e.to_string(), "The deadline of the dump has been exceeded");
+    }
+}
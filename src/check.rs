@@ -0,0 +1,244 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --check, nothing is written: the records of each module are counted (as with --stats)
+// and a report lists what's missing (functions, line tables, CFI, ids). The run fails when
+// something essential is missing, so it can be used in a CI to check the debug info of a build.
+// With --strict, the modules are written and checked in the same way but the warnings (e.g. some
+// functions without line info) make the run fail too.
+
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+
+use crate::common::{self, ErrorKind};
+use crate::stats::ModuleStats;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq)]
+struct Problem {
+    severity: Severity,
+    category: &'static str,
+    message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{} [{}]: {}", severity, self.category, self.message)
+    }
+}
+
+#[derive(Debug)]
+struct ModuleReport {
+    name: String,
+    debug_id: String,
+    problems: Vec<Problem>,
+}
+
+impl ModuleReport {
+    fn new(stats: &ModuleStats) -> Self {
+        let mut problems = Vec::new();
+        let mut add = |severity, category, message: String| {
+            problems.push(Problem {
+                severity,
+                category,
+                message,
+            })
+        };
+
+        if stats.debug_id.is_empty() || stats.debug_id.bytes().all(|c| c == b'0') {
+            add(Severity::Error, "debug-id", "no debug id".to_string());
+        }
+        if stats.code_id.is_none() {
+            add(
+                Severity::Error,
+                "build-id",
+                "no code id (the build id of an ELF, the PE of a PDB, ...)".to_string(),
+            );
+        }
+        if stats.functions == 0 {
+            add(
+                Severity::Error,
+                "functions",
+                "no FUNC records: the debug info is missing".to_string(),
+            );
+        } else if stats.lines == 0 {
+            add(Severity::Error, "lines", "no line tables".to_string());
+        } else if stats.functions_without_lines != 0 {
+            add(
+                Severity::Warning,
+                "lines",
+                format!(
+                    "{} of the {} functions have no line info",
+                    stats.functions_without_lines, stats.functions
+                ),
+            );
+        }
+        // There is no unwind info in a wasm module
+        if stats.os != "wasm" && stats.cfi == 0 && stats.stack_win == 0 {
+            add(
+                Severity::Error,
+                "cfi",
+                "no STACK CFI or STACK WIN records".to_string(),
+            );
+        }
+
+        Self {
+            name: stats.name.clone(),
+            debug_id: stats.debug_id.clone(),
+            problems,
+        }
+    }
+
+    fn has_errors(&self) -> bool {
+        self.problems.iter().any(|p| p.severity == Severity::Error)
+    }
+
+    /// With --strict, a warning is a failure too
+    fn is_failure(&self, strict: bool) -> bool {
+        if strict {
+            !self.problems.is_empty()
+        } else {
+            self.has_errors()
+        }
+    }
+}
+
+impl Display for ModuleReport {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        if self.problems.is_empty() {
+            return writeln!(f, "{} {}: OK", self.name, self.debug_id);
+        }
+        writeln!(f, "{} {}:", self.name, self.debug_id)?;
+        for problem in self.problems.iter() {
+            writeln!(f, "  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// The reports of the modules checked with --check (or written with --strict)
+#[derive(Debug)]
+pub(crate) struct Checker {
+    // With --check, nothing is written
+    check: bool,
+    // A warning is a failure too
+    strict: bool,
+    reports: Mutex<Vec<ModuleReport>>,
+}
+
+impl Checker {
+    pub(crate) fn new(check: bool, strict: bool) -> Self {
+        Self {
+            check,
+            strict,
+            reports: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Check the debug info instead of writing the symbols
+    pub(crate) fn is_check(&self) -> bool {
+        self.check
+    }
+
+    /// Check the records of a module
+    pub(crate) fn add(&self, stats: &ModuleStats) {
+        self.reports
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(ModuleReport::new(stats));
+    }
+
+    /// Print the report and fail if something essential is missing
+    pub(crate) fn finish(&self) -> common::Result<()> {
+        let mut reports =
+            std::mem::take(&mut *self.reports.lock().unwrap_or_else(|e| e.into_inner()));
+        // The modules can be checked in parallel
+        reports.sort_by(|a, b| (&a.name, &a.debug_id).cmp(&(&b.name, &b.debug_id)));
+
+        for report in reports.iter() {
+            if self.check {
+                print!("{}", report);
+            } else if !report.problems.is_empty() {
+                // The symbols can be on stdout
+                eprint!("{}", report);
+            }
+        }
+
+        let failed = reports.iter().filter(|r| r.is_failure(self.strict)).count();
+        match failed {
+            0 => Ok(()),
+            n => Err(ErrorKind::Incomplete
+                .error(format!("The debug info of {} module(s) is incomplete", n))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::stats::StatsCounter;
+    use std::io::Write;
+
+    fn get_report(data: &str) -> ModuleReport {
+        let mut counter = StatsCounter::default();
+        counter.write_all(data.as_bytes()).unwrap();
+        ModuleReport::new(&counter.finish())
+    }
+
+    #[test]
+    fn test_check() {
+        let report = get_report(
+            "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full
+INFO CODE_ID B060AD20C6B47781552708AA192E7739
+FILE 0 /tmp/basic.cpp
+FUNC 1000 10 0 main
+1000 10 2 0
+STACK CFI INIT 1000 10 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+",
+        );
+        assert!(!report.has_errors());
+        assert_eq!(
+            report.to_string(),
+            "basic.full 20AD60B0B4C68177552708AA192E77390: OK\n"
+        );
+
+        let report = get_report(
+            "MODULE Linux x86_64 000000000000000000000000000000000 basic.stripped
+PUBLIC 1000 0 main
+",
+        );
+        assert!(report.has_errors());
+        let categories: Vec<_> = report.problems.iter().map(|p| p.category).collect();
+        assert_eq!(categories, vec!["debug-id", "build-id", "functions", "cfi"]);
+
+        let report = get_report(
+            "MODULE wasm wasm32 20AD60B0B4C68177552708AA192E77390 basic.wasm
+INFO CODE_ID 20AD60B0B4C68177552708AA192E7739
+FUNC 10 10 0 foo
+10 10 2 0
+FUNC 20 10 0 bar
+",
+        );
+        assert!(!report.has_errors());
+        assert!(!report.is_failure(false));
+        assert!(report.is_failure(true));
+        assert_eq!(
+            report.to_string(),
+            "basic.wasm 20AD60B0B4C68177552708AA192E77390:
+  warning [lines]: 1 of the 2 functions have no line info
+"
+        );
+    }
+}
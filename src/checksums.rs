@@ -0,0 +1,132 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The checksum of a source file (the MD5 in a DWARF 5 line table or the one in a PDB) is appended
+// to its FILE record so a symbolication server can check it has fetched the right revision:
+//   FILE 0 /build/foo/a.cpp md5:6bc87b8e1a5c2aa0b1a9f1d4ea3c4a10
+
+use hashbrown::HashMap;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ChecksumKind {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumKind {
+    fn name(self) -> &'static str {
+        match self {
+            ChecksumKind::Md5 => "md5",
+            ChecksumKind::Sha1 => "sha1",
+            ChecksumKind::Sha256 => "sha256",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Checksum {
+    kind: ChecksumKind,
+    value: Vec<u8>,
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}:", self.kind.name())?;
+        for b in self.value.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl Checksum {
+    /// Get a checksum (None when it's empty or zero: some producers don't set it)
+    pub(crate) fn new(kind: ChecksumKind, value: &[u8]) -> Option<Self> {
+        if value.iter().all(|b| *b == 0) {
+            return None;
+        }
+        Some(Self {
+            kind,
+            value: value.to_vec(),
+        })
+    }
+}
+
+/// The checksums of the source files by name (as written in the FILE records)
+pub(crate) type Checksums = HashMap<String, Checksum>;
+
+/// Write a FILE record with the checksum of the file (if any)
+pub(crate) fn write_file_record(
+    f: &mut Formatter,
+    id: usize,
+    name: &str,
+    checksums: &Checksums,
+) -> std::fmt::Result {
+    match checksums.get(name) {
+        Some(checksum) => writeln!(f, "FILE {} {} {}", id, name, checksum),
+        None => writeln!(f, "FILE {} {}", id, name),
+    }
+}
+
+/// Split the name and the checksum in the last field of a FILE record
+pub(crate) fn split_file_name(name: &str) -> (&str, Option<&str>) {
+    if let Some(pos) = name.rfind(' ') {
+        let checksum = &name[pos + 1..];
+        let is_checksum = matches!(checksum.split_once(':'), Some((kind, value))
+            if ["md5", "sha1", "sha256"].contains(&kind)
+                && !value.is_empty()
+                && value.bytes().all(|c| c.is_ascii_hexdigit()));
+        if is_checksum {
+            return (&name[..pos], Some(checksum));
+        }
+    }
+    (name, None)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    struct Files(Vec<String>, Checksums);
+
+    impl Display for Files {
+        fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+            for (n, name) in self.0.iter().enumerate() {
+                write_file_record(f, n, name, &self.1)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert!(Checksum::new(ChecksumKind::Md5, &[0; 16]).is_none());
+        let checksum = Checksum::new(ChecksumKind::Sha1, &[0xab, 0x01, 0x2f]).unwrap();
+        assert_eq!(checksum.to_string(), "sha1:ab012f");
+
+        let mut checksums = Checksums::default();
+        checksums.insert("/build/foo/a.cpp".to_string(), checksum);
+        let files = Files(
+            vec!["/build/foo/a.cpp".to_string(), "/build/foo/b.h".to_string()],
+            checksums,
+        );
+        assert_eq!(
+            files.to_string(),
+            "FILE 0 /build/foo/a.cpp sha1:ab012f\nFILE 1 /build/foo/b.h\n"
+        );
+
+        assert_eq!(
+            split_file_name("/build/foo/a.cpp sha1:ab012f"),
+            ("/build/foo/a.cpp", Some("sha1:ab012f"))
+        );
+        assert_eq!(
+            split_file_name("c:\\my files\\a.cpp"),
+            ("c:\\my files\\a.cpp", None)
+        );
+    }
+}
@@ -0,0 +1,1500 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The command line: the binary only calls run.
+
+use clap::{crate_authors, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use log::error;
+use simplelog::{Config, LevelFilter, TermLogger, TerminalMode};
+use std::ops::Deref;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::action::Action;
+use crate::cache::{self, HttpOptions};
+use crate::cache_management::{self, CacheAction};
+use crate::check::Checker;
+use crate::common::{self, FileType};
+use crate::debug_records;
+use crate::demangling;
+use crate::diff;
+use crate::dry_run;
+use crate::dumper;
+use crate::env_args::{self, EnvArg, Vars};
+use crate::folding;
+use crate::http_server::ServerOptions;
+use crate::info;
+use crate::linux;
+use crate::minidump;
+use crate::mock_server::{self, Layout, MockServer};
+use crate::options::Options;
+use crate::output;
+use crate::output_template;
+use crate::parallel;
+use crate::prefetch;
+use crate::publics;
+use crate::recurse;
+use crate::serve::{self, ServeOptions};
+use crate::shutdown;
+use crate::stats;
+use crate::symbols_zip;
+use crate::upload::{self, UploadOptions};
+use crate::utils;
+use crate::watch;
+
+/// The value of an option or exit with its error
+fn unwrap_or_exit<T>(res: common::Result<T>) -> T {
+    res.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+fn cache_subcommand(matches: &ArgMatches) -> common::Result<()> {
+    // symbol-server is global so it's in the matches of the subcommand
+    let symbol_server = matches
+        .subcommand()
+        .1
+        .and_then(|m| m.value_of("symbol-server"))
+        .or_else(|| matches.value_of("symbol-server"));
+    let action = match matches.subcommand() {
+        ("list", _) => CacheAction::List,
+        ("gc", Some(matches)) => {
+            let max_age = if let Some(days) = matches.value_of("max_age") {
+                Some(
+                    days.parse::<u64>()
+                        .map_err(|_| format!("Invalid number of days: {}", days))?,
+                )
+            } else {
+                None
+            };
+            let max_size = if let Some(size) = matches.value_of("max_size") {
+                Some(cache_management::parse_size(size)?)
+            } else {
+                None
+            };
+            if max_age.is_none() && max_size.is_none() {
+                return Err("gc requires --max-age and/or --max-size".into());
+            }
+            CacheAction::Gc { max_age, max_size }
+        }
+        _ => CacheAction::Clear,
+    };
+
+    let symbol_server = cache::get_sym_servers(symbol_server, &HttpOptions::default())?;
+    let caches = cache::get_caches(symbol_server.as_ref());
+
+    action.action(&caches)
+}
+
+fn prefetch_subcommand(matches: &ArgMatches) -> common::Result<()> {
+    let manifest = matches.value_of("manifest").unwrap();
+    let headers = matches.values_of("header").map(|v| v.collect::<Vec<_>>());
+    let http = HttpOptions::new(matches.value_of("user_agent"), &headers)?;
+    let symbol_server = cache::get_sym_servers(matches.value_of("symbol-server"), &http)?;
+    let num_jobs = matches
+        .value_of("num_jobs")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or_else(num_cpus::get);
+
+    prefetch::prefetch(manifest, symbol_server, num_jobs)
+}
+
+fn minidump_subcommand(matches: &ArgMatches) -> common::Result<()> {
+    let minidump = matches.value_of("minidump").unwrap();
+    let headers = matches.values_of("header").map(|v| v.collect::<Vec<_>>());
+    let num_jobs = matches
+        .value_of("num_jobs")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or_else(num_cpus::get);
+
+    let config = dumper::Config {
+        output: "-",
+        symbol_server: matches.value_of("symbol-server"),
+        store: matches.value_of("store"),
+        debug_id: None,
+        code_id: None,
+        arch: common::get_compile_time_arch(),
+        file_type: FileType::Unknown,
+        num_jobs,
+        mapping_var: None,
+        mapping_src: None,
+        mapping_dest: None,
+        mapping_file: None,
+        vcs_info: None,
+        user_agent: matches.value_of("user_agent"),
+        headers,
+        allow_export_only: env_args::is_present(matches, "allow_export_only"),
+        all_archs: false,
+        module_name: None,
+        merge: false,
+        supplier: None,
+        options: Arc::default(),
+    };
+
+    minidump::dump(minidump, &config)
+}
+
+fn mock_server_subcommand(matches: &ArgMatches) -> common::Result<()> {
+    let root = matches.value_of("root").unwrap();
+    let layout = Layout::from_str(matches.value_of("layout").unwrap())?;
+    let headers = matches
+        .values_of("require_header")
+        .map(|v| v.collect::<Vec<_>>());
+    let port = matches.value_of("port").unwrap();
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| format!("Invalid port: {}", port))?;
+    let fail_first = matches.value_of("fail_first").unwrap();
+    let fail_first = fail_first
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid number of failures: {}", fail_first))?;
+
+    let server = MockServer::new(root, layout, &headers, fail_first)?;
+    mock_server::serve(server, port)
+}
+
+fn diff_subcommand(matches: &ArgMatches) -> common::Result<()> {
+    let diff = diff::diff(
+        matches.value_of("old").unwrap(),
+        matches.value_of("new").unwrap(),
+    )?;
+    diff::print(&diff, matches.value_of("json"))
+}
+
+fn serve_subcommand(matches: &ArgMatches) -> common::Result<()> {
+    let port = matches.value_of("port").unwrap();
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| format!("Invalid port: {}", port))?;
+    let queue_size = matches.value_of("queue_size").unwrap();
+    let queue_size = queue_size
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid queue size: {}", queue_size))?;
+    let max_body_size = cache_management::parse_size(matches.value_of("max_body_size").unwrap())?;
+    let num_jobs = matches
+        .value_of("num_jobs")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or_else(num_cpus::get);
+
+    let server = ServerOptions {
+        host: matches.value_of("bind").unwrap().to_string(),
+        port,
+        max_body_size,
+        // The requests waiting in the queue keep their connection
+        num_connections: num_jobs + queue_size,
+    };
+    let options = ServeOptions {
+        symbol_server: matches.value_of("symbol-server").map(|s| s.to_string()),
+        user_agent: matches.value_of("user_agent").map(|s| s.to_string()),
+        headers: matches
+            .values_of("header")
+            .map_or_else(Vec::new, |v| v.map(|h| h.to_string()).collect()),
+        allow_export_only: env_args::is_present(matches, "allow_export_only"),
+        num_jobs,
+        queue_size,
+    };
+
+    serve::serve(options, server)
+}
+
+fn upload_subcommand(matches: &ArgMatches) -> common::Result<()> {
+    let paths: Vec<_> = matches.values_of("files").unwrap().collect();
+    let headers = matches.values_of("header").map(|v| v.collect::<Vec<_>>());
+    let retries = matches.value_of("retries").unwrap();
+    let retries = retries
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid number of retries: {}", retries))?;
+
+    let options = UploadOptions {
+        url: matches.value_of("url").unwrap().to_string(),
+        token: matches.value_of("token").unwrap().to_string(),
+        max_size: cache_management::parse_size(matches.value_of("max_size").unwrap())?,
+        retries,
+        retry_delay: std::time::Duration::from_secs(1),
+        http: HttpOptions::new(matches.value_of("user_agent"), &headers)?,
+    };
+
+    upload::upload(&paths, &options)
+}
+
+/// Run dump_syms with the command line arguments
+/// The values of the options in the environment are looked up in vars
+fn get_app<'a, 'b>(vars: &'a Vars) -> App<'a, 'b> {
+    App::new("dump_syms")
+        .version(crate_version!())
+        .author(crate_authors!("\n"))
+        .about("Dump debug symbols to breakpad symbols")
+        .after_help("Each long option can be set in a DUMP_SYMS_* environment variable (e.g. DUMP_SYMS_SYMBOL_SERVER or DUMP_SYMS_NO_CFI=1), with a value per line for a repeated option: the command line wins over the environment which wins over the config files.")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Manage the local symbol cache")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .arg(
+                    Arg::with_name("symbol-server")
+                        .help("Symbol Server configuration containing the cache directories (by default the one from the config files)")
+                        .long("symbol-server")
+                        .global(true)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_SYMBOL_SERVER", vars),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the cached files with their size and their age")
+                )
+                .subcommand(
+                    SubCommand::with_name("gc")
+                        .about("Remove the old files and shrink the cache to the given size")
+                        .arg(
+                            Arg::with_name("max_age")
+                                .help("Remove the files older than the given number of days")
+                                .long("max-age")
+                                .value_name("DAYS")
+                                .takes_value(true)
+                                .env_in("DUMP_SYMS_MAX_AGE", vars),
+                        )
+                        .arg(
+                            Arg::with_name("max_size")
+                                .help("Remove the oldest files until the cache size is lower than the given size (e.g. 500M, 10G)")
+                                .long("max-size")
+                                .value_name("SIZE")
+                                .takes_value(true)
+                                .env_in("DUMP_SYMS_MAX_SIZE", vars),
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Remove all the cached files")
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("prefetch")
+                .about("Download the debug files listed in a manifest into the cache without dumping them")
+                .arg(
+                    Arg::with_name("manifest")
+                        .help("A file (or - for stdin) containing \"debug_file debug_id\" lines or a json list of modules with debug_file and debug_id fields")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("symbol-server")
+                        .help("Symbol Server configuration (by default the one from the config files)")
+                        .long("symbol-server")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_SYMBOL_SERVER", vars),
+                )
+                .arg(
+                    Arg::with_name("user_agent")
+                        .help("User-Agent used in the requests to the symbol servers")
+                        .long("user-agent")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_USER_AGENT", vars),
+                )
+                .arg(
+                    Arg::with_name("header")
+                        .help("An extra header KEY:VALUE to add in the requests to the symbol servers")
+                        .long("header")
+                        .value_delimiter("\n")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_HEADER", vars),
+                )
+                .arg(
+                    Arg::with_name("num_jobs")
+                        .help("Number of parallel downloads")
+                        .short("j")
+                        .value_name("NUMBER")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("minidump")
+                .about("Dump in a store the debug files of the modules of a minidump or of an ELF core dump (fetched from the symbol servers)")
+                .arg(
+                    Arg::with_name("minidump")
+                        .help("The minidump or the core file")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("store")
+                        .help("Store output file as FILENAME.pdb/DEBUG_ID/FILENAME.sym in the given directory")
+                        .short("s")
+                        .long("store")
+                        .required(true)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_STORE", vars),
+                )
+                .arg(
+                    Arg::with_name("symbol-server")
+                        .help("Symbol Server configuration (by default the one from the config files)")
+                        .long("symbol-server")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_SYMBOL_SERVER", vars),
+                )
+                .arg(
+                    Arg::with_name("user_agent")
+                        .help("User-Agent used in the requests to the symbol servers")
+                        .long("user-agent")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_USER_AGENT", vars),
+                )
+                .arg(
+                    Arg::with_name("header")
+                        .help("An extra header KEY:VALUE to add in the requests to the symbol servers")
+                        .long("header")
+                        .value_delimiter("\n")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_HEADER", vars),
+                )
+                .arg(
+                    Arg::with_name("allow_export_only")
+                        .help("When the pdb file of a dll/exe can't be found, dump the symbols from the export table and from the COFF symbol table")
+                        .long("allow-export-only"),
+                )
+                .arg(
+                    Arg::with_name("num_jobs")
+                        .help("Number of modules dumped in parallel")
+                        .short("j")
+                        .value_name("NUMBER")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serve the symbols on demand: POST a file to /dump/FILENAME or GET /dump/DEBUG_FILE/DEBUG_ID to get its symbols")
+                .arg(
+                    Arg::with_name("port")
+                        .help("The port to listen on (0 for a free one)")
+                        .long("port")
+                        .default_value("8080")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_PORT", vars),
+                )
+                .arg(
+                    Arg::with_name("bind")
+                        .help("The address to listen on (e.g. 0.0.0.0 to accept the requests from other hosts)")
+                        .long("bind")
+                        .value_name("ADDRESS")
+                        .default_value("127.0.0.1")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_BIND", vars),
+                )
+                .arg(
+                    Arg::with_name("symbol-server")
+                        .help("Symbol Server configuration (by default the one from the config files, reloaded when they change)")
+                        .long("symbol-server")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_SYMBOL_SERVER", vars),
+                )
+                .arg(
+                    Arg::with_name("user_agent")
+                        .help("User-Agent used in the requests to the symbol servers")
+                        .long("user-agent")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_USER_AGENT", vars),
+                )
+                .arg(
+                    Arg::with_name("header")
+                        .help("An extra header KEY:VALUE to add in the requests to the symbol servers")
+                        .long("header")
+                        .value_delimiter("\n")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_HEADER", vars),
+                )
+                .arg(
+                    Arg::with_name("allow_export_only")
+                        .help("When the pdb file of a dll/exe can't be found, dump the symbols from the export table and from the COFF symbol table")
+                        .long("allow-export-only"),
+                )
+                .arg(
+                    Arg::with_name("num_jobs")
+                        .help("Number of files dumped in parallel")
+                        .short("j")
+                        .value_name("NUMBER")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("queue_size")
+                        .help("Number of requests waiting for a job: the next ones get a 503")
+                        .long("queue-size")
+                        .value_name("NUMBER")
+                        .default_value("64")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_QUEUE_SIZE", vars),
+                )
+                .arg(
+                    Arg::with_name("max_body_size")
+                        .help("Maximal size of a posted file (e.g. 500M): a bigger one gets a 413")
+                        .long("max-body-size")
+                        .value_name("SIZE")
+                        .default_value("1G")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_MAX_BODY_SIZE", vars),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare two symbol files record by record (functions, lines, inlines, CFI) and print a summary")
+                .arg(
+                    Arg::with_name("old")
+                        .help("The old .sym file")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .help("The new .sym file")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .help("Write the differences in JSON in the given file (- for stdout, instead of the summary)")
+                        .long("json")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_JSON", vars),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Print the module info of a file (os, arch, debug id, code id, debug file, path in a store and the kinds of debug info) without dumping it")
+                .arg(
+                    Arg::with_name("file")
+                        .help("The file to read")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .help("Print the modules in JSON")
+                        .long("json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mock-server")
+                .about("Run a local symbol server serving the files of a directory (e.g. to test a symbol server configuration)")
+                .arg(
+                    Arg::with_name("root")
+                        .help("The directory containing the files: ROOT/xul.pdb/DEBUG_ID/xul.pdb")
+                        .long("root")
+                        .required(true)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_ROOT", vars),
+                )
+                .arg(
+                    Arg::with_name("port")
+                        .help("The port to listen on (0 to use a free one)")
+                        .long("port")
+                        .default_value("0")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_PORT", vars),
+                )
+                .arg(
+                    Arg::with_name("layout")
+                        .help("ms: case-insensitive paths like the Microsoft symbol server, tecken: redirections to a storage like symbols.mozilla.org")
+                        .long("layout")
+                        .possible_values(&["ms", "tecken"])
+                        .default_value("ms")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_LAYOUT", vars),
+                )
+                .arg(
+                    Arg::with_name("require_header")
+                        .help("A header KEY:VALUE which must be in the requests (e.g. \"Auth-Token:123abc\"), else the response is 403")
+                        .long("require-header")
+                        .value_delimiter("\n")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_REQUIRE_HEADER", vars),
+                )
+                .arg(
+                    Arg::with_name("fail_first")
+                        .help("The number of 503 responses for each path before serving it")
+                        .long("fail-first")
+                        .value_name("NUMBER")
+                        .default_value("0")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_FAIL_FIRST", vars),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("upload")
+                .about("Upload the symbol files of some zip files (written with --zip) or of some symbol stores to a Tecken server")
+                .arg(
+                    Arg::with_name("files")
+                        .help("The zip files or the symbol store directories")
+                        .required(true)
+                        .multiple(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .help("The API token sent in the Auth-Token header")
+                        .long("token")
+                        .hide_env_values(true)
+                        .required(true)
+                        .takes_value(true)
+                        .env_in("SYMBOLS_UPLOAD_TOKEN", vars),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .help("The upload url of the server")
+                        .long("url")
+                        .default_value(upload::DEFAULT_URL)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_URL", vars),
+                )
+                .arg(
+                    Arg::with_name("max_size")
+                        .help("The max size of the symbol files in an uploaded zip file (e.g. 500M): the files are split in several uploads")
+                        .long("max-size")
+                        .value_name("SIZE")
+                        .default_value("500M")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_MAX_SIZE", vars),
+                )
+                .arg(
+                    Arg::with_name("retries")
+                        .help("The number of retries of an upload after a network error or a server error")
+                        .long("retries")
+                        .value_name("NUMBER")
+                        .default_value("3")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_RETRIES", vars),
+                )
+                .arg(
+                    Arg::with_name("user_agent")
+                        .help("User-Agent used in the requests to the server")
+                        .long("user-agent")
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_USER_AGENT", vars),
+                )
+                .arg(
+                    Arg::with_name("header")
+                        .help("An extra header KEY:VALUE to add in the requests to the server")
+                        .long("header")
+                        .value_delimiter("\n")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .env_in("DUMP_SYMS_HEADER", vars),
+                ),
+        )
+        .arg(
+            Arg::with_name("filenames")
+                .help("Files to dump (.dll, .exe, .pdb, .pd_, .so, .dbg, .wasm), - to read the file from stdin or @FILE to read the arguments in FILE (one per line)")
+                .required_unless("recurse")
+                .multiple(true)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("recurse")
+                .help("Dump the debug files found in a directory tree (the other files are skipped) and print a summary")
+                .long("recurse")
+                .value_name("DIR")
+                .conflicts_with_all(&["filenames", "list_arch"])
+                .takes_value(true)
+                .env_in("DUMP_SYMS_RECURSE", vars),
+        )
+        .arg(
+            Arg::with_name("include")
+                .help("A glob matching the paths (relative to the directory) of the files to dump with --recurse (e.g. '*.so')")
+                .long("include")
+                .value_delimiter("\n")
+                .requires("recurse")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .env_in("DUMP_SYMS_INCLUDE", vars),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .help("A glob matching the paths (relative to the directory) of the files to skip with --recurse (e.g. '*test*')")
+                .long("exclude")
+                .value_delimiter("\n")
+                .requires("recurse")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .env_in("DUMP_SYMS_EXCLUDE", vars),
+        )
+        .arg(
+            Arg::with_name("follow_symlinks")
+                .help("Follow the symbolic links with --recurse")
+                .long("follow-symlinks")
+                .requires("recurse"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .help("Dump the files in the store and dump them again each time they change (e.g. relinked) until the process is killed")
+                .long("watch")
+                .requires("store")
+                .conflicts_with_all(&["recurse", "check", "zip", "list_arch"]),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .help("Print the files which would be read, fetched from the symbol servers (or found in the caches) and written without downloading or writing anything")
+                .long("dry-run")
+                .conflicts_with_all(&["watch", "list_arch"]),
+        )
+        .arg(
+            Arg::with_name("output")
+                .help("Output file or - for stdout")
+                .short("o")
+                .long("output")
+                .default_value("-")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_OUTPUT", vars),
+        )
+        .arg(
+            Arg::with_name("output_template")
+                .help("Output file name with some placeholders replaced by the values of the module: {debug_file}, {debug_id}, {code_id}, {arch} and {os} (e.g. '{debug_file}-{debug_id}.sym')")
+                .long("output-template")
+                .value_name("TEMPLATE")
+                .conflicts_with("output")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_OUTPUT_TEMPLATE", vars),
+        )
+        .arg(
+            Arg::with_name("store")
+                .help("Store output file as FILENAME.pdb/DEBUG_ID/FILENAME.sym in the given directory")
+                .short("s")
+                .long("store")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_STORE", vars),
+        )
+        .arg(
+            Arg::with_name("debug_id")
+                .help("Get the pdb file passed as argument from the cache or from symbol server using the debug id")
+                .long("debug-id")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_DEBUG_ID", vars),
+        )
+        .arg(
+            Arg::with_name("code_id")
+                .help("Get the dll/exe file passed as argument from the cache or from symbol server using the code id")
+                .long("code-id")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_CODE_ID", vars),
+        )
+        .arg(
+            Arg::with_name("symbol-server")
+                .help("Symbol Server configuration\n(e.g. \"SRV*c:\\symcache\\*https://symbols.mozilla.org/\")\nIt can be in file $HOME/.dump_syms/config or in a .dump_syms.toml in the current directory or in one of its parents.")
+                .long("symbol-server")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_SYMBOL_SERVER", vars),
+        )
+        .arg(
+            Arg::with_name("user_agent")
+                .help("User-Agent used in the requests to the symbol servers")
+                .long("user-agent")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_USER_AGENT", vars),
+        )
+        .arg(
+            Arg::with_name("header")
+                .help("An extra header KEY:VALUE to add in the requests to the symbol servers (e.g. \"Auth-Token:123abc\")")
+                .long("header")
+                .value_delimiter("\n")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .env_in("DUMP_SYMS_HEADER", vars),
+        )
+        .arg(
+            Arg::with_name("allow_export_only")
+                .help("When the pdb file of a dll/exe can't be found, dump the symbols from the export table and from the COFF symbol table")
+                .long("allow-export-only"),
+        )
+        .arg(
+            Arg::with_name("debug_records")
+                .help("Print on stderr the raw debug data (DWARF rows, PDB line fragments) used to build the records of the functions selected with --function and/or --address")
+                .long("debug-records"),
+        )
+        .arg(
+            Arg::with_name("function")
+                .help("With --debug-records, select the functions whose name contains the given string")
+                .long("function")
+                .requires("debug_records")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_FUNCTION", vars),
+        )
+        .arg(
+            Arg::with_name("address")
+                .help("With --debug-records, select the function containing the given address (e.g. 0x1234)")
+                .long("address")
+                .requires("debug_records")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_ADDRESS", vars),
+        )
+        .arg(
+            Arg::with_name("dwp")
+                .help("Set the dwp package containing the split DWARF of an ELF file (default: FILE.dwp)")
+                .long("dwp")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_DWP", vars),
+        )
+        .arg(
+            Arg::with_name("dwo_dir")
+                .help("Set the directory where to look for the .dwo files of an ELF file built with -gsplit-dwarf")
+                .long("dwo-dir")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_DWO_DIR", vars),
+        )
+        .arg(
+            Arg::with_name("debug_dir")
+                .help("Add a directory where to look for the debug files of the stripped ELF files (before /usr/lib/debug)")
+                .long("debug-dir")
+                .value_delimiter("\n")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .env_in("DUMP_SYMS_DEBUG_DIR", vars),
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("Format of the output: the Breakpad text format, a JSON document, a JSON object per record (ndjson) or a SymCache (the files in a symbol store are always some Breakpad ones)")
+                .long("format")
+                .possible_values(&["breakpad", "json", "ndjson", "symcache"])
+                .default_value("breakpad")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_FORMAT", vars),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .help("Compress the output and the files in the symbol store (.gz or .zst is appended to their names)")
+                .long("compress")
+                .possible_values(&["gzip", "zstd"])
+                .takes_value(true)
+                .env_in("DUMP_SYMS_COMPRESS", vars),
+        )
+        .arg(
+            Arg::with_name("zip")
+                .help("Write the symbol files in a zip file with their paths in a symbol store (e.g. symbols.zip for an upload to symbols.mozilla.org)")
+                .long("zip")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_ZIP", vars),
+        )
+        .arg(
+            Arg::with_name("no_cfi")
+                .help("Don't write the CFI (the STACK records) in the output")
+                .long("no-cfi")
+                .conflicts_with_all(&["only_cfi", "split_cfi"]),
+        )
+        .arg(
+            Arg::with_name("only_cfi")
+                .help("Only write the CFI (the STACK records) in the output")
+                .long("only-cfi")
+                .conflicts_with("split_cfi"),
+        )
+        .arg(
+            Arg::with_name("split_cfi")
+                .help("Write the CFI (the STACK records) in a .cfi file next to the output (e.g. xul.cfi for xul.sym)")
+                .long("split-cfi"),
+        )
+        .arg(
+            Arg::with_name("filter_symbols")
+                .help("Only write the FUNC and PUBLIC records whose name matches the regex in the output")
+                .long("filter-symbols")
+                .value_name("REGEX")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_FILTER_SYMBOLS", vars),
+        )
+        .arg(
+            Arg::with_name("range")
+                .help("Only write the FUNC and PUBLIC records in the address range START-END (in hexadecimal, e.g. 0x1000-0x2000) in the output")
+                .long("range")
+                .value_name("START-END")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_RANGE", vars),
+        )
+        .arg(
+            Arg::with_name("info")
+                .help("Add an INFO record (e.g. --info URL https://hg.mozilla.org/mozilla-central): can be repeated")
+                .long("info")
+                .value_names(&["KEY", "VALUE"])
+                .number_of_values(2)
+                .multiple(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("inlines")
+                .help("Emit some INLINE and INLINE_ORIGIN records for the inlined functions")
+                .long("inlines"),
+        )
+        .arg(
+            Arg::with_name("keep_publics")
+                .help("Write the PUBLIC records at the address of a FUNC (by default, they're dropped)")
+                .long("keep-publics")
+                .conflicts_with("dedup"),
+        )
+        .arg(
+            Arg::with_name("dedup")
+                .help("Drop the PUBLIC records inside the range of a FUNC (by default, only the ones at the address of a FUNC are dropped)")
+                .long("dedup"),
+        )
+        .arg(
+            Arg::with_name("symtab_functions")
+                .help("Write a FUNC record (without lines) for each symbol with a size of an ELF or a Mach-O without debug info (by default, they're some PUBLIC records)")
+                .long("symtab-functions"),
+        )
+        .arg(
+            Arg::with_name("folded")
+                .help("Functions folded by the linker (same address): the smallest name with the m marker, the smallest name only or all the names with the m marker")
+                .long("folded")
+                .possible_values(&["multiple", "first", "aliases"])
+                .default_value("multiple")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_FOLDED", vars),
+        )
+        .arg(
+            Arg::with_name("checksums")
+                .help("Append the checksum of the source file (from the DWARF 5 line tables or the PDB) to the FILE records")
+                .long("checksums"),
+        )
+        .arg(
+            Arg::with_name("embed_sources")
+                .help("Write the source files read from the disk in a source bundle next to the symbol file (e.g. xul.src.zip for xul.sym)")
+                .long("embed-sources"),
+        )
+        .arg(
+            Arg::with_name("srcsrv")
+                .help("Replace the paths in the FILE records of a source-indexed PDB by the urls of its srcsrv stream (the --mapping-* options win)")
+                .long("srcsrv"),
+        )
+        .arg(
+            Arg::with_name("stack_win_cfi")
+                .help("Translate the STACK WIN records with a program string (x86 FrameData) into some STACK CFI records too")
+                .long("stack-win-cfi"),
+        )
+        .arg(
+            Arg::with_name("force_id_match")
+                .help("Combine a PE and a PDB given together even when their debug ids differ (e.g. a local rebuild): the symbols get the debug id of the PE")
+                .long("force-id-match"),
+        )
+        .arg(
+            Arg::with_name("type_server_path")
+                .help("Directory where the type server PDBs (e.g. vc140.pdb) referenced by a PDB are searched when they aren't at their recorded path")
+                .long("type-server-path")
+                .value_delimiter("\n")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .env_in("DUMP_SYMS_TYPE_SERVER_PATH", vars),
+        )
+        .arg(
+            Arg::with_name("with_signatures")
+                .help("Write the full signatures (with the return types) of the functions built from the PDB type info in the FUNC records")
+                .long("with-signatures"),
+        )
+        .arg(
+            Arg::with_name("demangle")
+                .help("Demangling of the names (MSVC, C++ Itanium, Rust legacy and v0) in the FUNC and PUBLIC records: the full signature, the qualified name only or the raw mangled name")
+                .long("demangle")
+                .possible_values(&["full", "name", "none"])
+                .default_value("full")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_DEMANGLE", vars),
+        )
+        .arg(
+            Arg::with_name("check")
+                .help("Check the debug info (functions, line tables, CFI, ids) without writing anything: the run fails if something essential is missing")
+                .long("check")
+                .conflicts_with_all(&["store", "zip", "list_arch"]),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .help("Fail (with the exit code 5) when the debug info of a module is incomplete, even with only some warnings (missing CFI, functions without line info, ...)")
+                .long("strict"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .help("Write a report about the dumped modules (number of functions, functions without line info, CFI entries, time spent in each phase, ...) in the given file (- for stderr)")
+                .long("stats")
+                .value_name("FILE")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_STATS", vars),
+        )
+        .arg(
+            Arg::with_name("stats_format")
+                .help("Format of the report written with --stats")
+                .long("stats-format")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_STATS_FORMAT", vars),
+        )
+        .arg(
+            Arg::with_name("max_name_length")
+                .help("Truncate the function names longer than the given number of chars (the middle is replaced by an ellipsis and a hash of the name is appended)")
+                .long("max-name-length")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_MAX_NAME_LENGTH", vars),
+        )
+        .arg(
+            Arg::with_name("num_threads")
+                .help("Set the number of threads used to process a file (the number of cpus by default)")
+                .long("num-threads")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_NUM_THREADS", vars),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .help("Set the level of verbosity (off, error (default), warn, info, debug, trace)")
+                .long("verbose")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_VERBOSE", vars),
+        )
+        .arg(
+            Arg::with_name("v")
+                .help("Increase the verbosity (-v: warn, -vv: info, -vvv: debug with the time spent in each phase, -vvvv: trace)")
+                .short("v")
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .help("Don't log anything")
+                .short("q")
+                .long("quiet")
+                .conflicts_with("v"),
+        )
+        .arg(
+            Arg::with_name("arch")
+                .help("Set the architecture to select in fat binaries (e.g. x86_64, arm64, arm64e)")
+                .short("a")
+                .long("arch")
+                .default_value(common::get_compile_time_arch())
+                .takes_value(true)
+                .env_in("DUMP_SYMS_ARCH", vars),
+        )
+        .arg(
+            Arg::with_name("type")
+                .help("Debug file type to use instead of the detected one, can be elf, macho, pdb, pe, ppdb (Portable PDB), sym (Breakpad) or wasm (with several files, the ones with the same debug id are merged, else each file is dumped independently)")
+                .short("t")
+                .long("type")
+                .default_value("")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_TYPE", vars),
+        )
+        .arg(
+            Arg::with_name("all_archs")
+                .help("Dump all the architectures present in a fat binary: the architecture is inserted in the output file name (e.g. foo.x86_64.sym)")
+                .long("all-archs"),
+        )
+        .arg(
+            Arg::with_name("module_name")
+                .help("Set the module name of an ELF file: by default it's the soname for a shared library, else the file name")
+                .long("module-name")
+                .value_name("NAME")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_MODULE_NAME", vars),
+        )
+        .arg(
+            Arg::with_name("merge")
+                .help("Dump the objects of a static library (.a, .lib) in a single module instead of one module per object")
+                .long("merge"),
+        )
+        .arg(
+            Arg::with_name("list_arch")
+                .help("List the architectures present in the fat binaries")
+                .long("list-arch")
+        )
+        .arg(
+            Arg::with_name("num_jobs")
+                .help("Number of jobs (by default, the number of CPUs)")
+                .short("j")
+                .value_name("NUMBER")
+                .default_value("")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mapping_var")
+                .help("A pair var=value such as rev=123abcd")
+                .long("mapping-var")
+                .value_delimiter("\n")
+                .multiple(true)
+                .takes_value(true)
+                .env_in("DUMP_SYMS_MAPPING_VAR", vars),
+        )
+        .arg(
+            Arg::with_name("mapping_src")
+                .help("Regex to match a path with capturing groups")
+                .long("mapping-src")
+                .value_delimiter("\n")
+                .multiple(true)
+                .takes_value(true)
+                .env_in("DUMP_SYMS_MAPPING_SRC", vars),
+        )
+        .arg(
+            Arg::with_name("mapping_dest")
+                .help(r#"A replacement string using groups, variables (set with --mapping-var), special variable like DIGEST or digest.
+For example with --mapping-var="rev=123abc" --mapping-src="/foo/bar/(.*)" --mapping-dest="https://my.source.org/{rev}/{digest}/{1}" a path like "/foo/bar/myfile.cpp" will be transformed into "https://my.source.org/123abc/sha512_of_myfile.cpp/myfile.cpp"
+"#)
+                .long("mapping-dest")
+                .value_delimiter("\n")
+                .multiple(true)
+                .takes_value(true)
+                .env_in("DUMP_SYMS_MAPPING_DEST", vars),
+        )
+        .arg(
+            Arg::with_name("mapping_file")
+                .help("A json file containing mapping")
+                .long("mapping-file")
+                .takes_value(true)
+                .env_in("DUMP_SYMS_MAPPING_FILE", vars),
+        )
+        .arg(
+            Arg::with_name("vcs_info")
+                .help("Write in FILENAME.sym.vcs.json the revision and the blob hash of each source file (only git is supported)")
+                .long("vcs-info")
+                .possible_values(&["git"])
+                .takes_value(true)
+                .env_in("DUMP_SYMS_VCS_INFO", vars),
+        )
+}
+
+pub fn run() {
+    let args = match utils::expand_response_files(std::env::args_os()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let vars = env_args::get_vars();
+    let matches = get_app(&vars).get_matches_from(args);
+
+    // --verbose wins over -q and -v which win over RUST_LOG
+    let verbosity = if let Some(verbose) = matches.value_of("verbose") {
+        match verbose {
+            "off" => LevelFilter::Off,
+            "warn" => LevelFilter::Warn,
+            "info" => LevelFilter::Info,
+            "debug" => LevelFilter::Debug,
+            "trace" => LevelFilter::Trace,
+            _ => LevelFilter::Error,
+        }
+    } else if env_args::is_present(&matches, "quiet") {
+        LevelFilter::Off
+    } else if matches.is_present("v") {
+        match matches.occurrences_of("v") {
+            1 => LevelFilter::Warn,
+            2 => LevelFilter::Info,
+            3 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    } else {
+        std::env::var("RUST_LOG")
+            .ok()
+            .and_then(|v| utils::parse_rust_log(&v))
+            .unwrap_or(LevelFilter::Error)
+    };
+
+    // Init the logger
+    let _ = TermLogger::init(verbosity, Config::default(), TerminalMode::Stderr);
+
+    // Set a panic hook to redirect to the logger
+    panic::set_hook(Box::new(|panic_info| {
+        let (filename, line) = panic_info
+            .location()
+            .map(|loc| (loc.file(), loc.line()))
+            .unwrap_or(("<unknown>", 0));
+        let cause = panic_info
+            .payload()
+            .downcast_ref::<String>()
+            .map(String::deref)
+            .unwrap_or_else(|| {
+                panic_info
+                    .payload()
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .unwrap_or("<cause unknown>")
+            });
+        error!("A panic occurred at {}:{}: {}", filename, line, cause);
+    }));
+
+    // Remove the partially written files on Ctrl-C
+    shutdown::init();
+
+    if let Some(matches) = matches.subcommand_matches("cache") {
+        if let Err(e) = cache_subcommand(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("prefetch") {
+        if let Err(e) = prefetch_subcommand(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("minidump") {
+        if let Err(e) = minidump_subcommand(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("mock-server") {
+        if let Err(e) = mock_server_subcommand(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("diff") {
+        if let Err(e) = diff_subcommand(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("info") {
+        if let Err(e) = info::print(
+            matches.value_of("file").unwrap(),
+            env_args::is_present(matches, "json"),
+        ) {
+            eprintln!("{}", e);
+            std::process::exit(common::get_exit_code(&e));
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("serve") {
+        if let Err(e) = serve_subcommand(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("upload") {
+        if let Err(e) = upload_subcommand(matches) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if env_args::is_present(&matches, "debug_records") {
+        if let Err(e) =
+            debug_records::init(matches.value_of("function"), matches.value_of("address"))
+        {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let split_dwarf = unwrap_or_exit(linux::split_dwarf::get_options(
+        matches.value_of("dwp"),
+        matches.value_of("dwo_dir"),
+    ));
+    let debug_dirs = matches
+        .values_of("debug_dir")
+        .map(|v| v.collect::<Vec<_>>());
+    let debug_dirs = unwrap_or_exit(linux::debuglink::get_debug_dirs(debug_dirs));
+    let format = unwrap_or_exit(output::parse_format(matches.value_of("format").unwrap()));
+    let compression = match matches.value_of("compress") {
+        Some(compression) => unwrap_or_exit(output::parse_compression(compression)),
+        None => output::Compression::None,
+    };
+    let records = unwrap_or_exit(output::get_records(
+        env_args::is_present(&matches, "no_cfi"),
+        env_args::is_present(&matches, "only_cfi"),
+        env_args::is_present(&matches, "split_cfi"),
+    ));
+    let symbol_selection = unwrap_or_exit(output::get_symbol_selection(
+        matches.value_of("filter_symbols"),
+        matches.value_of("range"),
+    ));
+    let output_template = matches
+        .value_of("output_template")
+        .map(|template| unwrap_or_exit(output_template::parse_output_template(template)));
+    let info_records = match env_args::values_of(&matches, "info", 2) {
+        Some(info) => {
+            let info: Vec<_> = info.iter().map(String::as_str).collect();
+            unwrap_or_exit(output::parse_info_records(&info))
+        }
+        None => Vec::new(),
+    };
+    let publics_policy = unwrap_or_exit(publics::get_publics_policy(
+        env_args::is_present(&matches, "keep_publics"),
+        env_args::is_present(&matches, "dedup"),
+    ));
+    let folding_policy = unwrap_or_exit(folding::parse_folding_policy(
+        matches.value_of("folded").unwrap(),
+    ));
+    let demangling = unwrap_or_exit(demangling::parse_demangling(
+        matches.value_of("demangle").unwrap(),
+    ));
+
+    if let Some(zip) = matches.value_of("zip") {
+        if let Err(e) = symbols_zip::init(zip) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // The reports of the modules are kept until the end with --check or --strict
+    let check = env_args::is_present(&matches, "check");
+    let strict = env_args::is_present(&matches, "strict");
+    let checker = if check || strict {
+        Some(Arc::new(Checker::new(check, strict)))
+    } else {
+        None
+    };
+
+    if let Some(path) = matches.value_of("stats") {
+        if let Err(e) = stats::init(path, matches.value_of("stats_format").unwrap()) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let max_name_length = match matches.value_of("max_name_length") {
+        Some(max_name_length) => unwrap_or_exit(match max_name_length.parse::<usize>() {
+            Ok(len) => common::check_max_name_length(len),
+            _ => Err(format!("Invalid max name length: {}", max_name_length).into()),
+        }),
+        None => 0,
+    };
+
+    let num_threads = match matches.value_of("num_threads") {
+        Some(num_threads) => unwrap_or_exit(match num_threads.parse::<usize>() {
+            Ok(n) => parallel::check_num_threads(n),
+            _ => Err(format!("Invalid number of threads: {}", num_threads).into()),
+        }),
+        None => num_cpus::get(),
+    };
+
+    let options = Options {
+        format,
+        compression,
+        records,
+        info_records,
+        symbol_selection,
+        output_template,
+        publics_policy,
+        symtab_functions: env_args::is_present(&matches, "symtab_functions"),
+        folding_policy,
+        max_name_length,
+        demangling,
+        signatures: env_args::is_present(&matches, "with_signatures"),
+        inlines: env_args::is_present(&matches, "inlines"),
+        checksums: env_args::is_present(&matches, "checksums"),
+        embed_sources: env_args::is_present(&matches, "embed_sources"),
+        srcsrv: env_args::is_present(&matches, "srcsrv"),
+        stack_win_cfi: env_args::is_present(&matches, "stack_win_cfi"),
+        force_id_match: env_args::is_present(&matches, "force_id_match"),
+        type_server_paths: matches
+            .values_of("type_server_path")
+            .map_or_else(Vec::new, |paths| paths.map(PathBuf::from).collect()),
+        split_dwarf,
+        debug_dirs,
+        num_threads,
+        checker: checker.clone(),
+    };
+
+    let output = matches.value_of("output").unwrap();
+    let filenames: Vec<_> = matches
+        .values_of("filenames")
+        .map_or_else(Vec::new, |v| v.collect());
+    let symbol_server = matches.value_of("symbol-server");
+    let store = matches.value_of("store");
+    let debug_id = matches.value_of("debug_id");
+    let code_id = matches.value_of("code_id");
+    let arch = matches.value_of("arch").unwrap();
+    let mapping_var = matches
+        .values_of("mapping_var")
+        .map(|v| v.collect::<Vec<_>>());
+    let mapping_src = matches
+        .values_of("mapping_src")
+        .map(|v| v.collect::<Vec<_>>());
+    let mapping_dest = matches
+        .values_of("mapping_dest")
+        .map(|v| v.collect::<Vec<_>>());
+    let mapping_file = matches.value_of("mapping_file");
+    let vcs_info = matches.value_of("vcs_info");
+    let user_agent = matches.value_of("user_agent");
+    let headers = matches.values_of("header").map(|v| v.collect::<Vec<_>>());
+    let allow_export_only = env_args::is_present(&matches, "allow_export_only");
+    let all_archs = env_args::is_present(&matches, "all_archs");
+    let module_name = matches.value_of("module_name");
+    let merge = env_args::is_present(&matches, "merge");
+    let num_jobs = if let Ok(num_jobs) = matches.value_of("num_jobs").unwrap().parse::<usize>() {
+        num_jobs
+    } else {
+        num_cpus::get()
+    };
+    let typ = matches.value_of("type").unwrap();
+    if all_archs && filenames.len() >= 2 {
+        eprintln!("--all-archs can only be used with a single file");
+        std::process::exit(1);
+    }
+    if module_name.is_some() && filenames.len() >= 2 {
+        eprintln!("--module-name can only be used with a single file");
+        std::process::exit(1);
+    }
+    let from_stdin = filenames.iter().filter(|f| **f == utils::STDIN).count();
+    if from_stdin >= 2 || (from_stdin == 1 && env_args::is_present(&matches, "watch")) {
+        eprintln!("stdin (-) can only be read once and can't be watched");
+        std::process::exit(1);
+    }
+    // Several files without a type are dumped independently
+    let file_type = if typ.is_empty() {
+        FileType::Unknown
+    } else {
+        let t = common::FileType::from_str(typ);
+        match t {
+            FileType::Pe if filenames.len() >= 2 => {
+                eprintln!("--type pe can only be used with a single file");
+                std::process::exit(1);
+            }
+            FileType::Elf
+            | FileType::Macho
+            | FileType::Pdb
+            | FileType::Pe
+            | FileType::PortablePdb
+            | FileType::Breakpad
+            | FileType::Wasm => t,
+            _ => {
+                eprintln!("Type must be one of the values: elf, macho, pdb, pe, ppdb, sym or wasm");
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let action = if env_args::is_present(&matches, "list_arch") {
+        Action::ListArch
+    } else {
+        Action::Dump(dumper::Config {
+            output,
+            symbol_server,
+            store,
+            debug_id,
+            code_id,
+            arch,
+            file_type,
+            num_jobs,
+            mapping_var,
+            mapping_src,
+            mapping_dest,
+            mapping_file,
+            vcs_info,
+            user_agent,
+            headers,
+            allow_export_only,
+            all_archs,
+            module_name,
+            merge,
+            supplier: None,
+            options: Arc::new(options),
+        })
+    };
+
+    let walker = || {
+        let globs = |name| {
+            matches
+                .values_of(name)
+                .map_or_else(Vec::new, |v| v.map(String::from).collect())
+        };
+        recurse::Walker::new(
+            &globs("include"),
+            &globs("exclude"),
+            env_args::is_present(&matches, "follow_symlinks"),
+        )
+    };
+
+    let res = match (&action, matches.value_of("recurse")) {
+        (Action::Dump(config), Some(root)) if env_args::is_present(&matches, "dry_run") => walker()
+            .and_then(|walker| recurse::get_dumpable_files(config, root, &walker))
+            .and_then(|files| {
+                let files: Vec<_> = files.iter().map(|f| f.to_str().unwrap()).collect();
+                dry_run::dry_run(config, &files)
+            }),
+        (Action::Dump(config), Some(root)) => {
+            walker().and_then(|walker| recurse::dump(config, root, &walker))
+        }
+        (Action::Dump(config), _) if env_args::is_present(&matches, "dry_run") => {
+            dry_run::dry_run(config, &filenames)
+        }
+        (Action::Dump(config), _) if env_args::is_present(&matches, "watch") => {
+            watch::watch(config, &filenames)
+        }
+        _ => action.action(&filenames),
+    };
+
+    if let Err(e) = res
+        .and_then(|_| symbols_zip::finish())
+        .and_then(|_| stats::finish())
+        .and_then(|_| checker.map_or(Ok(()), |checker| checker.finish()))
+    {
+        eprintln!("{}", e);
+        std::process::exit(common::get_exit_code(&e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_env() {
+        let vars: Vars = [
+            ("DUMP_SYMS_ROOT", "/symbols"),
+            ("DUMP_SYMS_HEADER", "Foo: 1\nBar: 2"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let app = get_app(&vars);
+
+        // A required option can be set in the environment
+        let matches = app
+            .clone()
+            .get_matches_from_safe(vec!["dump_syms", "mock-server"])
+            .unwrap();
+        let (_, server) = matches.subcommand();
+        assert_eq!(server.unwrap().value_of("root"), Some("/symbols"));
+
+        let matches = app
+            .clone()
+            .get_matches_from_safe(vec!["dump_syms", "mock-server", "--root", "/store"])
+            .unwrap();
+        let (_, server) = matches.subcommand();
+        assert_eq!(server.unwrap().value_of("root"), Some("/store"));
+
+        let matches = app
+            .get_matches_from_safe(vec!["dump_syms", "prefetch", "manifest.txt"])
+            .unwrap();
+        let (_, prefetch) = matches.subcommand();
+        assert_eq!(
+            prefetch
+                .unwrap()
+                .values_of("header")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec!["Foo: 1", "Bar: 2"]
+        );
+    }
+}
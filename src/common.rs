@@ -4,31 +4,117 @@
 // copied, modified, or distributed except according to those terms.
 
 use regex::Regex;
+use std::borrow::Cow;
 use std::env::consts::ARCH;
 use std::error;
+use std::fmt;
 use std::io::Write;
+use std::path::PathBuf;
 use std::result;
 use symbolic::common::{Arch, Name};
 use symbolic::debuginfo::{peek, FileFormat};
 
-type Error = Box<dyn error::Error + std::marker::Send + std::marker::Sync>;
+use crate::apk;
+use crate::archive;
+use crate::breakpad::sym::SymInfo;
+use crate::cancel::Cancelled;
+use crate::dotnet::ppdb::PortablePdbInfo;
+use crate::visitor::Visitor;
+
+pub type Error = Box<dyn error::Error + std::marker::Send + std::marker::Sync>;
 pub type Result<T> = result::Result<T, Error>;
 
+/// The kind of an error which gives a specific exit code (1 for the other errors)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ErrorKind {
+    /// No debug info found (e.g. no PDB for a PE)
+    NoDebugInfo,
+    /// A file can't be fetched from a symbol server or from an url
+    Fetch,
+    /// A file can't be parsed
+    Parse,
+    /// Some files can't be dumped or the debug info is incomplete (--check, --strict)
+    Incomplete,
+}
+
+impl ErrorKind {
+    pub(crate) fn exit_code(self) -> i32 {
+        match self {
+            Self::NoDebugInfo => 2,
+            Self::Fetch => 3,
+            Self::Parse => 4,
+            Self::Incomplete => 5,
+        }
+    }
+
+    /// Make an error of this kind (an error which already has a kind keeps it)
+    pub(crate) fn error<E: Into<Error>>(self, e: E) -> Error {
+        let e = e.into();
+        if e.is::<KindError>() || e.is::<Cancelled>() {
+            return e;
+        }
+        Box::new(KindError {
+            kind: self,
+            message: e.to_string(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct KindError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl fmt::Display for KindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for KindError {}
+
+/// Get the exit code of the process for an error
+pub(crate) fn get_exit_code(e: &Error) -> i32 {
+    e.downcast_ref::<KindError>()
+        .map_or(1, |e| e.kind.exit_code())
+}
+
+#[derive(Clone, PartialEq)]
 pub(crate) enum FileType {
     Pdb,
     Pe,
     Elf,
     Macho,
+    Wasm,
+    PortablePdb,
+    Breakpad,
+    Archive,
+    Zip,
     Unknown,
 }
 
 impl FileType {
     pub(crate) fn from_buf(buf: &[u8]) -> Self {
+        if PortablePdbInfo::is_portable_pdb(buf) {
+            return Self::PortablePdb;
+        }
+        if SymInfo::is_sym(buf) {
+            return Self::Breakpad;
+        }
+        if archive::is_archive(buf) {
+            return Self::Archive;
+        }
+        if apk::is_zip(buf) {
+            return Self::Zip;
+        }
+
         match peek(buf, true /* check for fat binary */) {
             FileFormat::Pdb => Self::Pdb,
             FileFormat::Pe => Self::Pe,
             FileFormat::Elf => Self::Elf,
             FileFormat::MachO => Self::Macho,
+            FileFormat::Wasm => Self::Wasm,
             _ => Self::Unknown,
         }
     }
@@ -37,8 +123,12 @@ impl FileType {
         let s = s.to_lowercase();
         match s.as_str() {
             "pdb" => Self::Pdb,
+            "pe" => Self::Pe,
             "elf" => Self::Elf,
             "macho" => Self::Macho,
+            "wasm" => Self::Wasm,
+            "ppdb" => Self::PortablePdb,
+            "sym" | "breakpad" => Self::Breakpad,
             _ => Self::Unknown,
         }
     }
@@ -46,14 +136,27 @@ impl FileType {
 
 pub(crate) trait Dumpable {
     fn dump<W: Write>(&self, writer: W) -> Result<()>;
+    /// Visit the records written by dump
+    fn visit(&self, visitor: &mut dyn Visitor) -> Result<()>;
     fn get_name(&self) -> &str;
     fn get_debug_id(&self) -> &str;
+
+    /// The source files (the names in the FILE records with their paths on the disk)
+    fn get_source_files(&self) -> Vec<(String, PathBuf)> {
+        Vec::new()
+    }
 }
 
 pub(crate) trait Mergeable {
     fn merge(left: Self, right: Self) -> Result<Self>
     where
         Self: Sized;
+
+    /// The debug id can't be computed (e.g. a split debug file without build-id),
+    /// so the file must be merged with the binary whatever its id is.
+    fn has_unknown_id(&self) -> bool {
+        false
+    }
 }
 
 pub(crate) trait LineFinalizer<M> {
@@ -92,11 +195,160 @@ pub(crate) fn fix_symbol_name<'a>(name: &'a Name<'a>) -> Name<'a> {
     Name::new(fixed, name.mangling(), name.language())
 }
 
+// Below this length there is no room for something useful around the hash
+pub(crate) const MIN_NAME_LENGTH: usize = 64;
+
+/// Check the maximal number of chars in a name given with --max-name-length (0 means no limit)
+pub(crate) fn check_max_name_length(len: usize) -> Result<usize> {
+    if len != 0 && len < MIN_NAME_LENGTH {
+        return Err(format!(
+            "Invalid max name length {}: must be at least {}",
+            len, MIN_NAME_LENGTH
+        )
+        .into());
+    }
+
+    Ok(len)
+}
+
+/// FNV-1a: unlike the std hasher, the result is the same from one version to another
+fn get_stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Shorten a name with more than max chars: the middle is replaced by an ellipsis
+/// and a hash of the full name is appended so two truncated names are still different.
+pub(crate) fn truncate_name(name: &str, max: usize) -> Cow<str> {
+    if name.chars().count() <= max {
+        return Cow::Borrowed(name);
+    }
+
+    const ELLIPSIS: &str = "...";
+    let hash = format!(" [{:016x}]", get_stable_hash(name.as_bytes()));
+    let keep = max - ELLIPSIS.len() - hash.len();
+    let tail = keep / 2;
+    let head = keep - tail;
+
+    // The name is cut on char boundaries
+    let head_end = name.char_indices().nth(head).map_or(name.len(), |(i, _)| i);
+    let tail_start = if tail == 0 {
+        name.len()
+    } else {
+        name.char_indices()
+            .rev()
+            .nth(tail - 1)
+            .map_or(0, |(i, _)| i)
+    };
+
+    Cow::Owned(format!(
+        "{}{}{}{}",
+        &name[..head_end],
+        ELLIPSIS,
+        &name[tail_start..],
+        hash
+    ))
+}
+
+/// Sort the file names so the FILE records don't depend on the order of the files in the debug info
+/// (or on the order of the merged files): get the new id of each file (None when they're already sorted)
+pub(crate) fn sort_files(files: &mut Vec<String>) -> Option<Vec<u32>> {
+    if files.windows(2).all(|w| w[0] <= w[1]) {
+        return None;
+    }
+
+    let mut old_files: Vec<_> = std::mem::take(files).into_iter().map(Some).collect();
+    let mut order: Vec<_> = (0..old_files.len()).collect();
+    order.sort_by(|a, b| old_files[*a].cmp(&old_files[*b]));
+
+    let mut remapping = vec![0; old_files.len()];
+    for (new_id, old_id) in order.into_iter().enumerate() {
+        remapping[old_id] = new_id as u32;
+        files.push(old_files[old_id].take().unwrap());
+    }
+
+    Some(remapping)
+}
+
+fn get_stack_address(line: &str) -> Option<u64> {
+    let address = if let Some(rest) = line.strip_prefix("STACK CFI INIT ") {
+        rest.split(' ').next()
+    } else if let Some(rest) = line.strip_prefix("STACK WIN ") {
+        // STACK WIN type rva ...
+        rest.split(' ').nth(1)
+    } else {
+        None
+    }?;
+    u64::from_str_radix(address, 16).ok()
+}
+
+/// Sort the STACK records by address (the STACK CFI records of a function stay after its STACK CFI INIT)
+pub(crate) fn sort_stack(stack: &str) -> String {
+    let mut blocks: Vec<(u64, Vec<&str>)> = Vec::new();
+    for line in stack.lines() {
+        match get_stack_address(line) {
+            Some(address) => blocks.push((address, vec![line])),
+            _ => match blocks.last_mut() {
+                Some((_, block)) => block.push(line),
+                _ => blocks.push((0, vec![line])),
+            },
+        }
+    }
+
+    // The sort is stable so the records at the same address keep their order
+    blocks.sort_by_key(|(address, _)| *address);
+
+    let mut sorted = String::with_capacity(stack.len() + 1);
+    for line in blocks.iter().flat_map(|(_, block)| block) {
+        sorted.push_str(line);
+        sorted.push('\n');
+    }
+    sorted
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use symbolic::common::{Language, NameMangling};
 
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(get_exit_code(&"foo".into()), 1);
+        assert_eq!(get_exit_code(&ErrorKind::Fetch.error("foo")), 3);
+
+        let e = ErrorKind::Parse.error(ErrorKind::NoDebugInfo.error("No pdb file found"));
+        assert_eq!(get_exit_code(&e), 2);
+        assert_eq!(e.to_string(), "No pdb file found");
+    }
+
+    #[test]
+    fn test_sort_records() {
+        let mut files = vec![
+            "b.cpp".to_string(),
+            "c.cpp".to_string(),
+            "a.cpp".to_string(),
+        ];
+        assert_eq!(sort_files(&mut files), Some(vec![1, 2, 0]));
+        assert_eq!(files, vec!["a.cpp", "b.cpp", "c.cpp"]);
+        assert_eq!(sort_files(&mut files), None);
+
+        let stack = "STACK CFI INIT 2000 10 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+                     STACK CFI 2001 .cfa: $rsp 16 +\n\
+                     STACK WIN 4 1500 10 0 0 0 0 0 0 1 $eip 4 + ^ = $esp $ebp 8 + = \n\
+                     STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+                     STACK CFI 1004 .cfa: $rsp 16 +\n";
+        assert_eq!(
+            sort_stack(stack),
+            "STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+             STACK CFI 1004 .cfa: $rsp 16 +\n\
+             STACK WIN 4 1500 10 0 0 0 0 0 0 1 $eip 4 + ^ = $esp $ebp 8 + = \n\
+             STACK CFI INIT 2000 10 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+             STACK CFI 2001 .cfa: $rsp 16 +\n"
+        );
+        assert_eq!(sort_stack(""), "");
+    }
+
     #[test]
     fn test_fix_symbol_name() {
         let name = Name::new("hello", NameMangling::Mangled, Language::Unknown);
@@ -127,4 +379,26 @@ mod tests {
             normalize_anonymous_namespace(&name)
         );
     }
+
+    #[test]
+    fn test_truncate_name() {
+        let name = "foo::bar(int)";
+        assert_eq!(truncate_name(name, 64), name);
+
+        let name = format!("foo<{}>(int)", "std::vector<int>, ".repeat(100));
+        let truncated = truncate_name(&name, 64);
+        assert_eq!(truncated.chars().count(), 64);
+        assert!(truncated.starts_with("foo<std::vector<int>,...::vector<int>, >(int) ["));
+        assert_eq!(truncated, truncate_name(&name, 64));
+
+        // Same prefix and suffix but a different hash
+        let other = format!("foo<{}>(int)", "std::vector<long>, ".repeat(100));
+        assert_ne!(truncated, truncate_name(&other, 64));
+
+        // The chars aren't split
+        let name = "é".repeat(100);
+        let truncated = truncate_name(&name, 64);
+        assert_eq!(truncated.chars().count(), 64);
+        assert!(truncated.starts_with(&format!("{}...", "é".repeat(21))));
+    }
 }
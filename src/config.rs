@@ -0,0 +1,134 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use log::info;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::common;
+use crate::utils;
+
+pub(crate) const PROJECT_CONFIG: &str = ".dump_syms.toml";
+
+/// A symbol server which is only queried for the debug files matching
+/// one of the globs (e.g. "ntdll.pdb", "*.pdb") or the regex.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FilteredServer {
+    pub path: String,
+    pub files: Vec<String>,
+    pub regex: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ServerConfig {
+    Path(String),
+    Filtered(FilteredServer),
+}
+
+/// A configuration file committed alongside the code, e.g.:
+///   symbol_servers = [
+///     "SRV*.symcache*https://symbols.mozilla.org/",
+///     { path = "SRV*.symcache*https://msdl.microsoft.com/download/symbols", files = ["ntdll.pdb", "kernel32.pdb"] },
+///   ]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ProjectConfig {
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub symbol_servers: Vec<ServerConfig>,
+}
+
+impl ProjectConfig {
+    fn parse(path: PathBuf, data: &[u8]) -> common::Result<Self> {
+        let data = std::str::from_utf8(data)?;
+        let mut config: Self = toml::from_str(data)?;
+        config.path = path;
+
+        Ok(config)
+    }
+
+    /// Find the closest config file in the given directory or in one of its ancestors
+    pub(crate) fn find_from<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+        dir.as_ref()
+            .ancestors()
+            .map(|d| d.join(PROJECT_CONFIG))
+            .find(|p| p.is_file())
+    }
+
+    /// Find the project config starting the search in the current directory
+    pub(crate) fn find() -> Option<PathBuf> {
+        let cwd = std::env::current_dir().ok()?;
+        Self::find_from(cwd)
+    }
+
+    /// Read and parse the project config
+    pub(crate) fn read(path: PathBuf) -> common::Result<Self> {
+        let data = utils::read(&path)
+            .map_err(|e| format!("Unable to read the file {}: {}", path.to_str().unwrap(), e))?;
+
+        info!("Use project config {}", path.to_str().unwrap());
+
+        Self::parse(path.clone(), &data)
+            .map_err(|e| format!("Invalid config file {}: {}", path.to_str().unwrap(), e).into())
+    }
+
+    /// The directory containing the config file: relative paths are relative to it
+    pub(crate) fn get_dir(&self) -> &Path {
+        self.path.parent().unwrap_or_else(|| Path::new("."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_find_project_config() {
+        let tmp_dir = Builder::new().prefix("project_config").tempdir().unwrap();
+        let nested = tmp_dir.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(ProjectConfig::find_from(&nested), None);
+
+        let conf = tmp_dir.path().join(PROJECT_CONFIG);
+        fs::write(&conf, "").unwrap();
+
+        assert_eq!(ProjectConfig::find_from(&nested), Some(conf));
+    }
+
+    #[test]
+    fn test_parse_project_config() {
+        let path = PathBuf::from("/foo/bar").join(PROJECT_CONFIG);
+        let data = br#"symbol_servers = ["SRV*cache*https://symbols.mozilla.org/"]"#;
+        let config = ProjectConfig::parse(path, data).unwrap();
+
+        assert_eq!(config.symbol_servers.len(), 1);
+        assert!(matches!(
+            &config.symbol_servers[0],
+            ServerConfig::Path(p) if p == "SRV*cache*https://symbols.mozilla.org/"
+        ));
+        assert_eq!(config.get_dir(), Path::new("/foo/bar"));
+
+        let data = br#"symbol_servers = [
+            { path = "SRV*cache*https://msdl.microsoft.com/download/symbols", files = ["ntdll.pdb"] },
+        ]"#;
+        let config = ProjectConfig::parse(PathBuf::new(), data).unwrap();
+        match &config.symbol_servers[0] {
+            ServerConfig::Filtered(server) => {
+                assert_eq!(server.files, vec!["ntdll.pdb"]);
+                assert_eq!(server.regex, None);
+            }
+            _ => panic!("Expected a filtered server"),
+        }
+
+        assert!(ProjectConfig::parse(PathBuf::new(), b"foo = 1").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::sync::RwLock;
+
+use crate::common;
+
+/// The functions for which the raw debug data (DWARF rows, PDB line fragments)
+/// must be printed alongside the emitted records.
+#[derive(Debug, Default)]
+struct RecordFilter {
+    function: Option<String>,
+    address: Option<u64>,
+}
+
+impl RecordFilter {
+    fn matches(&self, name: &str, rva: u64, len: u64) -> bool {
+        if let Some(function) = self.function.as_ref() {
+            if name.contains(function.as_str()) {
+                return true;
+            }
+        }
+        if let Some(address) = self.address {
+            if rva <= address && address < rva + len.max(1) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+lazy_static! {
+    static ref FILTER: RwLock<Option<RecordFilter>> = RwLock::new(None);
+}
+
+pub(crate) fn parse_address(s: &str) -> common::Result<u64> {
+    let s = s.trim();
+    let res = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else {
+        s.parse::<u64>()
+    };
+
+    res.map_err(|_| format!("Invalid address {}", s).into())
+}
+
+/// Enable the debug records for the functions matching the name and/or containing the address
+pub(crate) fn init(function: Option<&str>, address: Option<&str>) -> common::Result<()> {
+    if function.is_none() && address.is_none() {
+        return Err("--debug-records requires --function and/or --address".into());
+    }
+
+    let filter = RecordFilter {
+        function: function.map(|f| f.to_string()),
+        address: address.map(parse_address).transpose()?,
+    };
+    *FILTER.write().unwrap() = Some(filter);
+
+    Ok(())
+}
+
+/// Check if the records for the function must be printed
+pub(crate) fn matches(name: &str, rva: u64, len: u64) -> bool {
+    FILTER
+        .read()
+        .unwrap()
+        .as_ref()
+        .map_or(false, |f| f.matches(name, rva, len))
+}
+
+/// Print on stderr (stdout can be the output) the given data
+pub(crate) fn print(title: &str, name: &str, rva: u64, data: &str) {
+    eprintln!("== {} for {} at 0x{:x}", title, name, rva);
+    eprint!("{}", data);
+    eprintln!();
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_record_filter() {
+        let filter = RecordFilter {
+            function: Some("Button1Click".to_string()),
+            address: None,
+        };
+        assert!(filter.matches("Unit1::TForm1::Button1Click", 0x1000, 0x10));
+        assert!(!filter.matches("main", 0x1000, 0x10));
+
+        let filter = RecordFilter {
+            function: None,
+            address: Some(parse_address("0x1008").unwrap()),
+        };
+        assert!(filter.matches("main", 0x1000, 0x10));
+        assert!(!filter.matches("main", 0x1000, 0x8));
+        assert!(!filter.matches("main", 0x1010, 0x10));
+
+        assert_eq!(parse_address("4104").unwrap(), 0x1008);
+        assert!(parse_address("0xfoo").is_err());
+    }
+}
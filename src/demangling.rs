@@ -0,0 +1,121 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The decorated MSVC names of the PUBLIC records and of the FUNC ones are undecorated according
+// to --demangle:
+//   full (default): void foo::bar(int)
+//   name: foo::bar
+//   none: ?bar@foo@@QAEXH@Z
+// The private symbols of a PDB have no decorated name, so with none, their FUNC records get the
+// qualified name without the arguments as with name.
+// Their types come from the TPI stream and, with --with-signatures, the return type is written
+// before the name (as in the undecorated PUBLIC names): void foo::bar(int).
+// The names of the ELF and Mach-O files (C++ Itanium, legacy Rust and Rust v0) follow --demangle
+// too. The Rust v0 names start with _R (__R with the underscore of the Mach-O symbols):
+//   _RNvCs1234_7mycrate3foo => mycrate::foo
+
+use symbolic::common::{Language, Name, NameMangling};
+use symbolic::demangle::{Demangle, DemangleOptions};
+
+use crate::common;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Demangling {
+    Full = 0,
+    Name = 1,
+    None = 2,
+}
+
+pub(crate) fn parse_demangling(demangling: &str) -> common::Result<Demangling> {
+    Ok(match demangling {
+        "full" => Demangling::Full,
+        "name" => Demangling::Name,
+        "none" => Demangling::None,
+        _ => {
+            return Err(format!(
+                "Invalid demangling {}: must be full, name or none",
+                demangling
+            )
+            .into())
+        }
+    })
+}
+
+/// Get the Rust v0 name (without the underscore of the Mach-O symbols) when it's one
+fn get_rust_v0(name: &str) -> Option<&str> {
+    let name = name
+        .strip_prefix('_')
+        .filter(|n| n.starts_with("_R"))
+        .unwrap_or(name);
+    Some(name).filter(|n| {
+        n.starts_with("_R")
+            && Name::new(*n, NameMangling::Mangled, Language::Rust)
+                .demangle(DemangleOptions::name_only())
+                .is_some()
+    })
+}
+
+/// Get the language of a mangled name (Unknown when it isn't one)
+pub(crate) fn detect_language(name: &str) -> Language {
+    if get_rust_v0(name).is_some() {
+        Language::Rust
+    } else {
+        Name::new(name, NameMangling::Mangled, Language::Unknown).detect_language()
+    }
+}
+
+/// Demangle a name according to --demangle: None when it can't be demangled
+pub(crate) fn demangle(name: &Name, demangling: Demangling) -> Option<String> {
+    let opts = match demangling {
+        Demangling::Full => DemangleOptions::complete(),
+        Demangling::Name => DemangleOptions::name_only(),
+        Demangling::None => return Some(name.as_str().to_string()),
+    };
+    match get_rust_v0(name.as_str()) {
+        Some(v0) => Name::new(v0, NameMangling::Mangled, Language::Rust).demangle(opts),
+        None => name.demangle(opts),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn demangle_str(name: &str, demangling: Demangling) -> Option<String> {
+        let name = Name::new(name, NameMangling::Mangled, detect_language(name));
+        demangle(&name, demangling)
+    }
+
+    #[test]
+    fn test_rust_v0() {
+        assert_eq!(detect_language("_RNvC6_123foo3bar"), Language::Rust);
+        assert_eq!(detect_language("__RNvC6_123foo3bar"), Language::Rust);
+        assert_eq!(detect_language("_Reserved"), Language::Unknown);
+
+        let names = [
+            ("_RNvC6_123foo3bar", "123foo::bar"),
+            ("__RNvC6_123foo3bar", "123foo::bar"),
+            (
+                "_RNvNvMCs4fqI2P2rA04_13const_genericINtB4_3FooKpE3foo3FOO",
+                "<const_generic::Foo<_>>::foo::FOO",
+            ),
+            ("_ZN3foo3bar17h0123456789abcdefE", "foo::bar"),
+            ("_ZN3foo3barEi", "foo::bar(int)"),
+        ];
+        for (name, demangled) in names.iter() {
+            assert_eq!(demangle_str(name, Demangling::Full).unwrap(), *demangled);
+        }
+
+        assert_eq!(
+            demangle_str("_RNvC6_123foo3bar", Demangling::None).unwrap(),
+            "_RNvC6_123foo3bar"
+        );
+        assert_eq!(
+            demangle_str("_ZN3foo3barEi", Demangling::Name).unwrap(),
+            "foo::bar"
+        );
+    }
+}
@@ -0,0 +1,528 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The diff subcommand compares two .sym files record by record: the functions are matched by
+// name and their lines and inlines are compared relatively to their address (with the file names
+// instead of the ids), so a module rebuilt at another address or dumped in another order only
+// shows the real differences. A summary is printed and a JSON diff can be written.
+
+use hashbrown::HashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{self, Write};
+
+use crate::checksums;
+use crate::common;
+use crate::shutdown;
+
+#[derive(Debug, Default, PartialEq)]
+struct Function {
+    size: u64,
+    // (offset in the function, size, line, file)
+    lines: Vec<(u64, u64, u32, String)>,
+    inlines: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct SymFile {
+    module: String,
+    files: BTreeSet<String>,
+    // Several static functions can have the same name: they're sorted by address
+    functions: BTreeMap<String, Vec<Function>>,
+    publics: BTreeSet<String>,
+    // The STACK CFI and STACK WIN records by (address, kind)
+    cfi: BTreeMap<(u64, String), String>,
+}
+
+fn parse_hex(s: Option<&str>) -> Option<u64> {
+    s.and_then(|s| u64::from_str_radix(s, 16).ok())
+}
+
+impl SymFile {
+    fn parse(data: &str) -> common::Result<Self> {
+        let mut sym = Self::default();
+        let mut file_names = HashMap::new();
+        let mut origins = HashMap::new();
+        // The current function with its name and its address
+        let mut func: Option<(String, u64, Function)> = None;
+        let mut cfi_address = None;
+
+        let name_of = |names: &HashMap<u32, String>, id: &str| -> String {
+            id.parse::<u32>()
+                .ok()
+                .and_then(|id| names.get(&id).cloned())
+                .unwrap_or_else(|| format!("?{}", id))
+        };
+
+        for (n, line) in data.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let invalid = || format!("Invalid record at line {}: {}", n + 1, line);
+            let mut fields = line.splitn(2, ' ');
+            let kind = fields.next().unwrap_or_default();
+            let rest = fields.next().unwrap_or_default();
+
+            if let Some(address) = parse_hex(Some(kind)) {
+                let (func_address, func) = match func.as_mut() {
+                    Some((_, func_address, func)) => (*func_address, func),
+                    None => return Err(invalid().into()),
+                };
+                let mut fields = rest.split(' ');
+                let size = parse_hex(fields.next()).ok_or_else(invalid)?;
+                let line = fields
+                    .next()
+                    .and_then(|l| l.parse().ok())
+                    .ok_or_else(invalid)?;
+                let file = fields.next().ok_or_else(invalid)?;
+                func.lines.push((
+                    address.wrapping_sub(func_address),
+                    size,
+                    line,
+                    name_of(&file_names, file),
+                ));
+                continue;
+            }
+
+            match kind {
+                "MODULE" => sym.module = rest.to_string(),
+                "FILE" => {
+                    let mut fields = rest.splitn(2, ' ');
+                    let id = fields.next().and_then(|id| id.parse().ok());
+                    let name = fields.next().map(checksums::split_file_name);
+                    let (id, (name, _)) = id.zip(name).ok_or_else(invalid)?;
+                    file_names.insert(id, name.to_string());
+                    sym.files.insert(name.to_string());
+                }
+                "INLINE_ORIGIN" => {
+                    let mut fields = rest.splitn(2, ' ');
+                    let id = fields.next().and_then(|id| id.parse::<u32>().ok());
+                    let (id, name) = id.zip(fields.next()).ok_or_else(invalid)?;
+                    origins.insert(id, name.to_string());
+                }
+                "INLINE" => {
+                    let (func_address, func) = match func.as_mut() {
+                        Some((_, func_address, func)) => (*func_address, func),
+                        None => return Err(invalid().into()),
+                    };
+                    let fields: Vec<_> = rest.split(' ').collect();
+                    if fields.len() < 6 || fields.len() % 2 != 0 {
+                        return Err(invalid().into());
+                    }
+                    let mut inline = format!(
+                        "{} {} {} {}",
+                        fields[0],
+                        fields[1],
+                        name_of(&file_names, fields[2]),
+                        name_of(&origins, fields[3])
+                    );
+                    for range in fields[4..].chunks(2) {
+                        let address = parse_hex(Some(range[0])).ok_or_else(invalid)?;
+                        let size = parse_hex(Some(range[1])).ok_or_else(invalid)?;
+                        inline.push_str(&format!(
+                            " {:x} {:x}",
+                            address.wrapping_sub(func_address),
+                            size
+                        ));
+                    }
+                    func.inlines.push(inline);
+                }
+                "FUNC" | "PUBLIC" => {
+                    let rest = rest.strip_prefix("m ").unwrap_or(rest);
+                    let count = if kind == "FUNC" { 4 } else { 3 };
+                    let fields: Vec<_> = rest.splitn(count, ' ').collect();
+                    if fields.len() != count {
+                        return Err(invalid().into());
+                    }
+                    let address = parse_hex(Some(fields[0])).ok_or_else(invalid)?;
+                    let name = fields[count - 1].to_string();
+                    if kind == "FUNC" {
+                        sym.add_function(func.take());
+                        let size = parse_hex(Some(fields[1])).ok_or_else(invalid)?;
+                        func = Some((
+                            name,
+                            address,
+                            Function {
+                                size,
+                                ..Default::default()
+                            },
+                        ));
+                    } else {
+                        sym.publics.insert(name);
+                    }
+                }
+                "STACK" => {
+                    let mut fields = rest.splitn(3, ' ');
+                    match (fields.next(), fields.next(), fields.next()) {
+                        (Some("CFI"), Some("INIT"), Some(rules)) => {
+                            let mut fields = rules.splitn(2, ' ');
+                            let address = parse_hex(fields.next()).ok_or_else(invalid)?;
+                            cfi_address = Some(address);
+                            sym.cfi.insert(
+                                (address, "CFI".to_string()),
+                                fields.next().unwrap_or_default().to_string(),
+                            );
+                        }
+                        (Some("CFI"), Some(address), Some(rules)) => {
+                            let init = cfi_address.ok_or_else(invalid)?;
+                            let address = parse_hex(Some(address)).ok_or_else(invalid)?;
+                            let init_rules = sym
+                                .cfi
+                                .get_mut(&(init, "CFI".to_string()))
+                                .ok_or_else(invalid)?;
+                            init_rules.push_str(&format!(
+                                "; {:x} {}",
+                                address.wrapping_sub(init),
+                                rules
+                            ));
+                        }
+                        (Some("WIN"), Some(ty), Some(rest)) => {
+                            let mut fields = rest.splitn(2, ' ');
+                            let address = parse_hex(fields.next()).ok_or_else(invalid)?;
+                            sym.cfi.insert(
+                                (address, format!("WIN {}", ty)),
+                                fields.next().unwrap_or_default().to_string(),
+                            );
+                        }
+                        _ => return Err(invalid().into()),
+                    }
+                }
+                // INFO records and blank lines
+                _ => {}
+            }
+        }
+        sym.add_function(func);
+
+        for functions in sym.functions.values_mut() {
+            for func in functions.iter_mut() {
+                func.lines.sort();
+                func.inlines.sort();
+            }
+        }
+
+        Ok(sym)
+    }
+
+    fn add_function(&mut self, func: Option<(String, u64, Function)>) {
+        if let Some((name, _, func)) = func {
+            self.functions.entry(name).or_default().push(func);
+        }
+    }
+}
+
+/// A changed value
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Change {
+    old: String,
+    new: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct FunctionChange {
+    name: String,
+    // What changed: size, lines, inlines
+    changes: Vec<&'static str>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct RecordsDiff<T> {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<T>,
+}
+
+impl<T> RecordsDiff<T> {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    fn fmt_counts(&self, f: &mut Formatter, kind: &str) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}: {} added, {} removed, {} changed",
+            kind,
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct SetDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl SetDiff {
+    fn new(old: &BTreeSet<String>, new: &BTreeSet<String>) -> Self {
+        Self {
+            added: new.difference(old).cloned().collect(),
+            removed: old.difference(new).cloned().collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The differences between two .sym files
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct Diff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module: Option<Change>,
+    functions: RecordsDiff<FunctionChange>,
+    publics: SetDiff,
+    files: SetDiff,
+    // The addresses (hex) of the STACK records
+    cfi: RecordsDiff<String>,
+}
+
+impl Diff {
+    fn new(old: &SymFile, new: &SymFile) -> Self {
+        let mut diff = Self::default();
+
+        if old.module != new.module {
+            diff.module = Some(Change {
+                old: old.module.clone(),
+                new: new.module.clone(),
+            });
+        }
+
+        let empty = Vec::new();
+        let names: BTreeSet<_> = old.functions.keys().chain(new.functions.keys()).collect();
+        for name in names {
+            let old_funcs = old.functions.get(name).unwrap_or(&empty);
+            let new_funcs = new.functions.get(name).unwrap_or(&empty);
+            for (old_func, new_func) in old_funcs.iter().zip(new_funcs.iter()) {
+                let mut changes = Vec::new();
+                if old_func.size != new_func.size {
+                    changes.push("size");
+                }
+                if old_func.lines != new_func.lines {
+                    changes.push("lines");
+                }
+                if old_func.inlines != new_func.inlines {
+                    changes.push("inlines");
+                }
+                if !changes.is_empty() {
+                    diff.functions.changed.push(FunctionChange {
+                        name: name.clone(),
+                        changes,
+                    });
+                }
+            }
+            for _ in new_funcs.len()..old_funcs.len() {
+                diff.functions.removed.push(name.clone());
+            }
+            for _ in old_funcs.len()..new_funcs.len() {
+                diff.functions.added.push(name.clone());
+            }
+        }
+
+        diff.publics = SetDiff::new(&old.publics, &new.publics);
+        diff.files = SetDiff::new(&old.files, &new.files);
+
+        let addresses: BTreeSet<_> = old.cfi.keys().chain(new.cfi.keys()).collect();
+        for key in addresses {
+            let address = format!("{:x}", key.0);
+            match (old.cfi.get(key), new.cfi.get(key)) {
+                (Some(_), None) => diff.cfi.removed.push(address),
+                (None, Some(_)) => diff.cfi.added.push(address),
+                (Some(old), Some(new)) if old != new => diff.cfi.changed.push(address),
+                _ => {}
+            }
+        }
+
+        diff
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.module.is_none()
+            && self.functions.is_empty()
+            && self.publics.is_empty()
+            && self.files.is_empty()
+            && self.cfi.is_empty()
+    }
+}
+
+impl Display for Diff {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No differences");
+        }
+
+        if let Some(module) = self.module.as_ref() {
+            writeln!(f, "MODULE: {} -> {}", module.old, module.new)?;
+        }
+
+        self.functions.fmt_counts(f, "FUNC")?;
+        for name in self.functions.added.iter() {
+            writeln!(f, "  + {}", name)?;
+        }
+        for name in self.functions.removed.iter() {
+            writeln!(f, "  - {}", name)?;
+        }
+        for change in self.functions.changed.iter() {
+            writeln!(f, "  ~ {} ({})", change.name, change.changes.join(", "))?;
+        }
+
+        for (kind, records) in [("PUBLIC", &self.publics), ("FILE", &self.files)].iter() {
+            writeln!(
+                f,
+                "{}: {} added, {} removed",
+                kind,
+                records.added.len(),
+                records.removed.len()
+            )?;
+            for name in records.added.iter() {
+                writeln!(f, "  + {}", name)?;
+            }
+            for name in records.removed.iter() {
+                writeln!(f, "  - {}", name)?;
+            }
+        }
+
+        // Too many addresses to be useful in the summary
+        self.cfi.fmt_counts(f, "STACK")
+    }
+}
+
+/// Compare two .sym files
+pub(crate) fn diff(old: &str, new: &str) -> common::Result<Diff> {
+    let read = |path: &str| -> common::Result<SymFile> {
+        let data = fs::read(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        SymFile::parse(&String::from_utf8_lossy(&data))
+            .map_err(|e| format!("{}: {}", path, e).into())
+    };
+    Ok(Diff::new(&read(old)?, &read(new)?))
+}
+
+/// Print the summary of the differences and write the JSON diff (if any, - for stdout)
+pub(crate) fn print(diff: &Diff, json: Option<&str>) -> common::Result<()> {
+    match json {
+        Some("-") => {
+            let mut stdout = io::stdout();
+            serde_json::to_writer_pretty(&mut stdout, diff)?;
+            writeln!(stdout)?;
+        }
+        Some(path) => {
+            print!("{}", diff);
+            shutdown::write_file(path, |w| {
+                serde_json::to_writer_pretty(&mut *w, diff)?;
+                writeln!(w)?;
+                Ok(())
+            })?;
+        }
+        None => print!("{}", diff),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let old = SymFile::parse(
+            "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full
+FILE 0 /tmp/basic.cpp
+FILE 1 /tmp/basic.h
+INLINE_ORIGIN 0 inlined()
+FUNC 1000 20 0 main
+INLINE 0 3 0 0 1004 8
+1000 10 2 0
+1010 10 3 1
+FUNC 1020 10 0 foo()
+1020 10 5 0
+FUNC 1030 10 0 bar()
+1030 10 7 0
+PUBLIC 1040 0 baz
+STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+STACK CFI 1004 .cfa: $rsp 16 +
+STACK CFI INIT 1020 10 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+",
+        )
+        .unwrap();
+
+        // The same module at another address with the files in another order
+        let moved = SymFile::parse(
+            "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full
+FILE 0 /tmp/basic.h
+FILE 1 /tmp/basic.cpp
+INLINE_ORIGIN 0 inlined()
+FUNC 2030 10 0 bar()
+2030 10 7 1
+FUNC 2000 20 0 main
+INLINE 0 3 1 0 2004 8
+2010 10 3 0
+2000 10 2 1
+FUNC 2020 10 0 foo()
+2020 10 5 1
+PUBLIC 2040 0 baz
+STACK CFI INIT 2000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+STACK CFI 2004 .cfa: $rsp 16 +
+STACK CFI INIT 2020 10 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+",
+        )
+        .unwrap();
+        let diff = Diff::new(&old, &moved);
+        assert!(diff.functions.is_empty());
+        assert!(diff.publics.is_empty());
+        assert!(diff.files.is_empty());
+
+        let new = SymFile::parse(
+            "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full
+FILE 0 /tmp/basic.cpp
+FILE 1 /tmp/basic.h
+FILE 2 /tmp/qux.cpp
+FUNC 1000 20 0 main
+1000 10 2 0
+1010 10 4 1
+FUNC 1020 10 0 foo()
+1020 10 5 0
+FUNC 1030 10 0 qux()
+1030 10 1 2
+STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+STACK CFI 1004 .cfa: $rsp 24 +
+STACK CFI INIT 1030 10 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+",
+        )
+        .unwrap();
+        let diff = Diff::new(&old, &new);
+        assert_eq!(diff.functions.added, vec!["qux()"]);
+        assert_eq!(diff.functions.removed, vec!["bar()"]);
+        assert_eq!(
+            diff.functions.changed,
+            vec![FunctionChange {
+                name: "main".to_string(),
+                changes: vec!["lines", "inlines"],
+            }]
+        );
+        assert_eq!(diff.publics.removed, vec!["baz"]);
+        assert_eq!(diff.files.added, vec!["/tmp/qux.cpp"]);
+        assert_eq!(diff.cfi.added, vec!["1030"]);
+        assert_eq!(diff.cfi.removed, vec!["1020"]);
+        assert_eq!(diff.cfi.changed, vec!["1000"]);
+
+        assert_eq!(
+            diff.to_string(),
+            "FUNC: 1 added, 1 removed, 1 changed
+  + qux()
+  - bar()
+  ~ main (lines, inlines)
+PUBLIC: 0 added, 1 removed
+  - baz
+FILE: 1 added, 0 removed
+  + /tmp/qux.cpp
+STACK: 1 added, 1 removed, 1 changed
+"
+        );
+        assert_eq!(Diff::new(&new, &new).to_string(), "No differences\n");
+    }
+}
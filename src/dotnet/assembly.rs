@@ -0,0 +1,297 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A .NET assembly is a PE file with a CLI header pointing to the metadata: the method names
+// are in the metadata tables and the debug directory references a Portable PDB
+// (or contains it when it's embedded).
+
+use flate2::read::DeflateDecoder;
+use goblin::pe::{options::ParseOptions, utils::find_offset, PE};
+use hashbrown::HashMap;
+use std::convert::TryInto;
+use std::io::Read;
+use symbolic::debuginfo::pe::PeObject;
+
+use super::metadata::{self, Metadata, Reader};
+use crate::common;
+
+const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+const IMAGE_DEBUG_TYPE_EMBEDDED_PORTABLE_PDB: u32 = 17;
+// The minor version of the CodeView entry of a Portable PDB ('PM')
+const PORTABLE_CODEVIEW_VERSION: u16 = 0x504d;
+const EMBEDDED_SIGNATURE: &[u8] = b"MPDB";
+
+/// A method defined in the assembly
+pub(super) struct Method {
+    pub name: String,
+    pub code_size: u32,
+}
+
+/// The reference to the Portable PDB in the debug directory
+pub(super) struct PdbRef {
+    pub name: String,
+    pub debug_id: String,
+}
+
+pub(super) struct Assembly {
+    pub name: String,
+    pub code_id: Option<String>,
+    pub pdb: Option<PdbRef>,
+    pub embedded_pdb: Option<Vec<u8>>,
+    // The methods by row in the MethodDef table
+    pub methods: HashMap<u32, Method>,
+}
+
+/// The debug id of a Portable PDB: the guid in its id and FFFFFFFF instead of the age
+/// (as in the symbol server keys).
+pub(super) fn get_debug_id(guid: &[u8]) -> String {
+    let data1 = u32::from_le_bytes(guid[0..4].try_into().unwrap());
+    let data2 = u16::from_le_bytes(guid[4..6].try_into().unwrap());
+    let data3 = u16::from_le_bytes(guid[6..8].try_into().unwrap());
+    let data4: String = guid[8..16].iter().map(|b| format!("{:02X}", b)).collect();
+
+    format!("{:08X}{:04X}{:04X}{}FFFFFFFF", data1, data2, data3, data4)
+}
+
+/// Check if the file is a .NET assembly with a Portable PDB
+pub(crate) fn has_portable_pdb(buf: &[u8]) -> bool {
+    let pe = match PE::parse(buf) {
+        Ok(pe) => pe,
+        _ => return false,
+    };
+
+    get_metadata(&pe, buf).is_some()
+        && get_debug_entries(&pe, buf).iter().any(|e| {
+            e.typ == IMAGE_DEBUG_TYPE_EMBEDDED_PORTABLE_PDB
+                || (e.typ == IMAGE_DEBUG_TYPE_CODEVIEW && e.minor == PORTABLE_CODEVIEW_VERSION)
+        })
+}
+
+fn get_file_alignment(pe: &PE) -> u32 {
+    pe.header
+        .optional_header
+        .map_or(0x200, |h| h.windows_fields.file_alignment)
+}
+
+fn get_data<'a>(pe: &PE, buf: &'a [u8], rva: u32, size: u32) -> Option<&'a [u8]> {
+    let offset = find_offset(
+        rva as usize,
+        &pe.sections,
+        get_file_alignment(pe),
+        &ParseOptions::default(),
+    )?;
+    buf.get(offset..offset.checked_add(size as usize)?)
+}
+
+fn get_metadata<'a>(pe: &PE, buf: &'a [u8]) -> Option<&'a [u8]> {
+    let clr = (*pe
+        .header
+        .optional_header?
+        .data_directories
+        .get_clr_runtime_header())?;
+    let mut header = Reader::new(get_data(pe, buf, clr.virtual_address, clr.size)?);
+    // cb, MajorRuntimeVersion and MinorRuntimeVersion
+    header.skip(8).ok()?;
+    let rva = header.u32().ok()?;
+    let size = header.u32().ok()?;
+
+    get_data(pe, buf, rva, size).filter(|data| metadata::is_metadata(data))
+}
+
+struct DebugEntry<'a> {
+    typ: u32,
+    minor: u16,
+    data: &'a [u8],
+}
+
+fn get_debug_entries<'a>(pe: &PE, buf: &'a [u8]) -> Vec<DebugEntry<'a>> {
+    let mut entries = Vec::new();
+    let dir = match pe
+        .header
+        .optional_header
+        .and_then(|h| *h.data_directories.get_debug_table())
+    {
+        Some(dir) => dir,
+        _ => return entries,
+    };
+    let data = match get_data(pe, buf, dir.virtual_address, dir.size) {
+        Some(data) => data,
+        _ => return entries,
+    };
+
+    // Each entry is an IMAGE_DEBUG_DIRECTORY (28 bytes)
+    for entry in data.chunks_exact(28) {
+        let mut reader = Reader::new(entry);
+        let _ = reader.skip(10);
+        let minor = reader.u16().unwrap_or_default();
+        let typ = reader.u32().unwrap_or_default();
+        let size = reader.u32().unwrap_or_default() as usize;
+        let _ = reader.skip(4);
+        let offset = reader.u32().unwrap_or_default() as usize;
+        if let Some(data) = buf.get(offset..offset.saturating_add(size)) {
+            entries.push(DebugEntry { typ, minor, data });
+        }
+    }
+
+    entries
+}
+
+fn get_pdb_ref(entry: &DebugEntry) -> Option<PdbRef> {
+    let mut reader = Reader::new(entry.data);
+    if reader.bytes(4).ok()? != b"RSDS" {
+        return None;
+    }
+    let guid = reader.bytes(16).ok()?;
+    reader.skip(4).ok()?;
+    let path = reader.bytes(entry.data.len() - 24).ok()?;
+    let path = path.split(|c| *c == 0).next()?;
+    let path = String::from_utf8_lossy(path).replace("\\", "/");
+    let name = path.rsplit('/').next()?.to_string();
+
+    Some(PdbRef {
+        name,
+        debug_id: get_debug_id(guid),
+    })
+}
+
+fn get_embedded_pdb(entry: &DebugEntry) -> common::Result<Vec<u8>> {
+    let mut reader = Reader::new(entry.data);
+    if reader.bytes(4)? != EMBEDDED_SIGNATURE {
+        return Err("Invalid embedded Portable PDB signature".into());
+    }
+    let size = reader.u32()? as usize;
+
+    let mut pdb = Vec::with_capacity(size);
+    DeflateDecoder::new(&entry.data[8..]).read_to_end(&mut pdb)?;
+
+    Ok(pdb)
+}
+
+/// Get the size of the IL code in the body of a method (ECMA-335 II.25.4)
+fn get_code_size(pe: &PE, buf: &[u8], rva: u32) -> u32 {
+    if rva == 0 {
+        return 0;
+    }
+
+    let mut header = match get_data(pe, buf, rva, 8) {
+        Some(data) => Reader::new(data),
+        _ => return 0,
+    };
+    let first = header.u8().unwrap_or_default();
+    match first & 0x3 {
+        // Tiny header: the size is in the 6 upper bits
+        0x2 => u32::from(first >> 2),
+        // Fat header: flags and size (2 bytes), MaxStack (2 bytes) and CodeSize (4 bytes)
+        0x3 => {
+            let _ = header.skip(3);
+            header.u32().unwrap_or_default()
+        }
+        _ => 0,
+    }
+}
+
+fn get_methods(pe: &PE, buf: &[u8], md: &Metadata) -> common::Result<HashMap<u32, Method>> {
+    let n_methods = md.get_rows(metadata::METHOD_DEF);
+    let n_types = md.get_rows(metadata::TYPE_DEF);
+
+    // The methods of a type are the ones from its MethodList to the MethodList of the next type
+    let mut types = Vec::with_capacity(n_types as usize);
+    for rid in 1..=n_types {
+        let mut row = md.get_row(metadata::TYPE_DEF, rid)?;
+        row.skip(4)?;
+        let name = md.get_string(row.index(md.string_size())?)?;
+        let namespace = md.get_string(row.index(md.string_size())?)?;
+        row.skip(md.coded_index_size(&[
+            metadata::TYPE_DEF,
+            metadata::TYPE_REF,
+            metadata::TYPE_SPEC,
+        ]))?;
+        row.skip(md.index_size(metadata::FIELD))?;
+        let method_list = row.index(md.index_size(metadata::METHOD_DEF))?;
+
+        // The global functions are in the <Module> type
+        let name = if rid == 1 && name == "<Module>" {
+            String::new()
+        } else if namespace.is_empty() {
+            format!("{}.", name)
+        } else {
+            format!("{}.{}.", namespace, name)
+        };
+        types.push((method_list, name));
+    }
+
+    let mut methods = HashMap::default();
+    for (i, (start, type_name)) in types.iter().enumerate() {
+        let end = types.get(i + 1).map_or(n_methods + 1, |t| t.0);
+        for rid in *start..end.min(n_methods + 1) {
+            let mut row = md.get_row(metadata::METHOD_DEF, rid)?;
+            let rva = row.u32()?;
+            row.skip(4)?;
+            let name = md.get_string(row.index(md.string_size())?)?;
+            methods.insert(
+                rid,
+                Method {
+                    name: format!("{}{}", type_name, name),
+                    code_size: get_code_size(pe, buf, rva),
+                },
+            );
+        }
+    }
+
+    Ok(methods)
+}
+
+impl Assembly {
+    pub(super) fn parse(buf: &[u8], name: &str) -> common::Result<Self> {
+        let pe = PE::parse(buf)?;
+        let md = get_metadata(&pe, buf).ok_or("Not a .NET assembly")?;
+        let md = Metadata::parse(md)?;
+
+        let mut pdb = None;
+        let mut embedded_pdb = None;
+        for entry in get_debug_entries(&pe, buf) {
+            match entry.typ {
+                IMAGE_DEBUG_TYPE_CODEVIEW if entry.minor == PORTABLE_CODEVIEW_VERSION => {
+                    pdb = get_pdb_ref(&entry);
+                }
+                IMAGE_DEBUG_TYPE_EMBEDDED_PORTABLE_PDB => {
+                    embedded_pdb = Some(get_embedded_pdb(&entry)?);
+                }
+                _ => {}
+            }
+        }
+
+        let code_id = PeObject::parse(buf)
+            .ok()
+            .and_then(|pe| pe.code_id())
+            .map(|id| id.as_str().to_uppercase());
+
+        Ok(Self {
+            name: name.to_string(),
+            code_id,
+            pdb,
+            embedded_pdb,
+            methods: get_methods(&pe, buf, &md)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_debug_id() {
+        let guid = [
+            0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x78, 0x56, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+            0x07, 0x08,
+        ];
+        assert_eq!(
+            get_debug_id(&guid),
+            "12345678123456780102030405060708FFFFFFFF"
+        );
+    }
+}
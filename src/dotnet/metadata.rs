@@ -0,0 +1,430 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The metadata of a .NET assembly or of a Portable PDB (ECMA-335 II.24):
+// a root followed by some streams: #~ for the tables, #Strings and #Blob for the heaps
+// and #Pdb for the Portable PDB header.
+
+use std::convert::TryInto;
+
+use crate::common;
+
+const METADATA_SIGNATURE: &[u8] = b"BSJB";
+
+// The tables used here (ECMA-335 II.22 and Portable PDB)
+pub(super) const MODULE: usize = 0x00;
+pub(super) const TYPE_REF: usize = 0x01;
+pub(super) const TYPE_DEF: usize = 0x02;
+pub(super) const FIELD_PTR: usize = 0x03;
+pub(super) const FIELD: usize = 0x04;
+pub(super) const METHOD_PTR: usize = 0x05;
+pub(super) const METHOD_DEF: usize = 0x06;
+pub(super) const PARAM: usize = 0x08;
+pub(super) const MODULE_REF: usize = 0x1a;
+pub(super) const TYPE_SPEC: usize = 0x1b;
+pub(super) const ASSEMBLY_REF: usize = 0x23;
+pub(super) const DOCUMENT: usize = 0x30;
+pub(super) const METHOD_DEBUG_INFORMATION: usize = 0x31;
+
+pub(super) fn is_metadata(buf: &[u8]) -> bool {
+    buf.starts_with(METADATA_SIGNATURE)
+}
+
+/// A cursor on some little-endian data
+#[derive(Clone)]
+pub(super) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub(super) fn bytes(&mut self, len: usize) -> common::Result<&'a [u8]> {
+        let end = self.pos.saturating_add(len);
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or("Unexpected end of the metadata")?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub(super) fn skip(&mut self, len: usize) -> common::Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub(super) fn u8(&mut self) -> common::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(super) fn u16(&mut self) -> common::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub(super) fn u32(&mut self) -> common::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub(super) fn u64(&mut self) -> common::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// An index in a table or in a heap (2 or 4 bytes)
+    pub(super) fn index(&mut self, size: usize) -> common::Result<u32> {
+        if size == 2 {
+            self.u16().map(u32::from)
+        } else {
+            self.u32()
+        }
+    }
+
+    /// A compressed unsigned integer (ECMA-335 II.23.2) and its number of bits
+    fn compressed_with_width(&mut self) -> common::Result<(u32, u32)> {
+        let first = self.u8()?;
+        if first & 0x80 == 0 {
+            Ok((u32::from(first), 7))
+        } else if first & 0xc0 == 0x80 {
+            let second = self.u8()?;
+            Ok(((u32::from(first & 0x3f) << 8) | u32::from(second), 14))
+        } else if first & 0xe0 == 0xc0 {
+            let rest = self.bytes(3)?;
+            Ok((
+                (u32::from(first & 0x1f) << 24)
+                    | (u32::from(rest[0]) << 16)
+                    | (u32::from(rest[1]) << 8)
+                    | u32::from(rest[2]),
+                29,
+            ))
+        } else {
+            Err("Invalid compressed integer".into())
+        }
+    }
+
+    pub(super) fn compressed(&mut self) -> common::Result<u32> {
+        self.compressed_with_width().map(|(value, _)| value)
+    }
+
+    /// A compressed signed integer: the sign bit has been rotated in the lowest bit
+    pub(super) fn compressed_signed(&mut self) -> common::Result<i32> {
+        let (value, width) = self.compressed_with_width()?;
+        let res = (value >> 1) as i32;
+        if value & 1 == 0 {
+            Ok(res)
+        } else {
+            Ok(res - (1 << (width - 1)))
+        }
+    }
+
+    fn c_string(&mut self) -> common::Result<&'a str> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|c| *c == 0)
+            .ok_or("Unterminated string in the metadata")?;
+        let s = std::str::from_utf8(&rest[..len])?;
+        self.pos += len + 1;
+        Ok(s)
+    }
+}
+
+/// The #~ stream: the number of rows of each table and the tables themselves
+struct Tables<'a> {
+    heap_sizes: u8,
+    rows: [u32; 64],
+    offsets: [usize; 64],
+    data: &'a [u8],
+}
+
+impl<'a> Tables<'a> {
+    fn parse(data: &'a [u8], external_rows: &[(usize, u32)]) -> common::Result<Self> {
+        let mut reader = Reader::new(data);
+        reader.skip(6)?;
+        let heap_sizes = reader.u8()?;
+        reader.skip(1)?;
+        let valid = reader.u64()?;
+        reader.skip(8)?;
+
+        let mut rows = [0; 64];
+        for (table, rows) in rows.iter_mut().enumerate() {
+            if valid & (1 << table) != 0 {
+                *rows = reader.u32()?;
+            }
+        }
+        if heap_sizes & 0x40 != 0 {
+            // Some extra data
+            reader.skip(4)?;
+        }
+
+        // The rows of the type system tables referenced in a Portable PDB are in the #Pdb stream
+        for (table, n) in external_rows {
+            rows[*table] = *n;
+        }
+
+        let mut tables = Self {
+            heap_sizes,
+            rows,
+            offsets: [0; 64],
+            data: &data[reader.pos..],
+        };
+
+        let mut offset = 0;
+        for table in 0..64 {
+            if valid & (1 << table) == 0 {
+                continue;
+            }
+            tables.offsets[table] = offset;
+            match tables.row_size(table) {
+                Some(size) => offset += size * tables.rows[table] as usize,
+                // The offsets of the next tables can't be computed
+                _ => break,
+            }
+        }
+
+        Ok(tables)
+    }
+
+    fn string_size(&self) -> usize {
+        if self.heap_sizes & 0x01 != 0 {
+            4
+        } else {
+            2
+        }
+    }
+
+    fn guid_size(&self) -> usize {
+        if self.heap_sizes & 0x02 != 0 {
+            4
+        } else {
+            2
+        }
+    }
+
+    fn blob_size(&self) -> usize {
+        if self.heap_sizes & 0x04 != 0 {
+            4
+        } else {
+            2
+        }
+    }
+
+    fn index_size(&self, table: usize) -> usize {
+        if self.rows[table] < 0x10000 {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn coded_index_size(&self, tables: &[usize]) -> usize {
+        let tag_bits = (tables.len() as u32).next_power_of_two().trailing_zeros();
+        let max_rows = tables.iter().map(|t| self.rows[*t]).max().unwrap_or(0);
+        if max_rows < (1 << (16 - tag_bits)) {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// The size of a row (only for the tables read here and the ones before them)
+    fn row_size(&self, table: usize) -> Option<usize> {
+        let size = match table {
+            MODULE => 2 + self.string_size() + 3 * self.guid_size(),
+            TYPE_REF => {
+                self.coded_index_size(&[MODULE, MODULE_REF, ASSEMBLY_REF, TYPE_REF])
+                    + 2 * self.string_size()
+            }
+            TYPE_DEF => {
+                4 + 2 * self.string_size()
+                    + self.coded_index_size(&[TYPE_DEF, TYPE_REF, TYPE_SPEC])
+                    + self.index_size(FIELD)
+                    + self.index_size(METHOD_DEF)
+            }
+            FIELD_PTR => self.index_size(FIELD),
+            FIELD => 2 + self.string_size() + self.blob_size(),
+            METHOD_PTR => self.index_size(METHOD_DEF),
+            METHOD_DEF => 8 + self.string_size() + self.blob_size() + self.index_size(PARAM),
+            DOCUMENT => 2 * self.blob_size() + 2 * self.guid_size(),
+            METHOD_DEBUG_INFORMATION => self.index_size(DOCUMENT) + self.blob_size(),
+            _ => return None,
+        };
+        Some(size)
+    }
+}
+
+/// The metadata streams
+pub(super) struct Metadata<'a> {
+    tables: Tables<'a>,
+    strings: &'a [u8],
+    blobs: &'a [u8],
+    pdb: Option<&'a [u8]>,
+}
+
+impl<'a> Metadata<'a> {
+    pub(super) fn parse(buf: &'a [u8]) -> common::Result<Self> {
+        if !is_metadata(buf) {
+            return Err("Invalid metadata signature".into());
+        }
+
+        let mut reader = Reader::new(buf);
+        reader.skip(12)?;
+        let version_len = reader.u32()? as usize;
+        reader.skip(version_len)?;
+        reader.skip(2)?;
+        let n_streams = reader.u16()?;
+
+        let mut tables = None;
+        let mut strings = &[][..];
+        let mut blobs = &[][..];
+        let mut pdb = None;
+        for _ in 0..n_streams {
+            let offset = reader.u32()? as usize;
+            let size = reader.u32()? as usize;
+            let name = reader.c_string()?;
+            // The name is padded to 4 bytes
+            reader.pos = (reader.pos + 3) & !3;
+
+            let data = buf
+                .get(offset..offset.saturating_add(size))
+                .ok_or_else(|| format!("Invalid stream {} in the metadata", name))?;
+            match name {
+                "#~" => tables = Some(data),
+                "#Strings" => strings = data,
+                "#Blob" => blobs = data,
+                "#Pdb" => pdb = Some(data),
+                "#-" => return Err("The uncompressed metadata tables aren't supported".into()),
+                _ => {}
+            }
+        }
+
+        let external_rows = pdb.map_or_else(|| Ok(Vec::new()), Self::get_external_rows)?;
+        let tables = Tables::parse(tables.ok_or("No tables in the metadata")?, &external_rows)?;
+
+        Ok(Self {
+            tables,
+            strings,
+            blobs,
+            pdb,
+        })
+    }
+
+    /// Get the rows of the type system tables (which are in the assembly) from the #Pdb stream
+    fn get_external_rows(pdb: &[u8]) -> common::Result<Vec<(usize, u32)>> {
+        let mut reader = Reader::new(pdb);
+        // PdbId and EntryPoint
+        reader.skip(24)?;
+        let referenced = reader.u64()?;
+
+        let mut rows = Vec::new();
+        for table in 0..64 {
+            if referenced & (1 << table) != 0 {
+                rows.push((table, reader.u32()?));
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// The #Pdb stream of a Portable PDB
+    pub(super) fn get_pdb_stream(&self) -> Option<&'a [u8]> {
+        self.pdb
+    }
+
+    pub(super) fn get_rows(&self, table: usize) -> u32 {
+        self.tables.rows[table]
+    }
+
+    /// A reader on the row `rid` (1-based) of the table
+    pub(super) fn get_row(&self, table: usize, rid: u32) -> common::Result<Reader<'a>> {
+        if rid == 0 || rid > self.tables.rows[table] {
+            return Err(format!("Invalid row {} in table {:#x}", rid, table).into());
+        }
+        let size = self
+            .tables
+            .row_size(table)
+            .ok_or_else(|| format!("The table {:#x} isn't supported", table))?;
+        let start = self.tables.offsets[table] + size * (rid as usize - 1);
+        let data = self
+            .tables
+            .data
+            .get(start..start + size)
+            .ok_or("Unexpected end of the metadata tables")?;
+
+        Ok(Reader::new(data))
+    }
+
+    pub(super) fn string_size(&self) -> usize {
+        self.tables.string_size()
+    }
+
+    pub(super) fn blob_size(&self) -> usize {
+        self.tables.blob_size()
+    }
+
+    pub(super) fn index_size(&self, table: usize) -> usize {
+        self.tables.index_size(table)
+    }
+
+    pub(super) fn coded_index_size(&self, tables: &[usize]) -> usize {
+        self.tables.coded_index_size(tables)
+    }
+
+    pub(super) fn get_string(&self, index: u32) -> common::Result<&'a str> {
+        let mut reader = Reader::new(self.strings);
+        reader.skip(index as usize)?;
+        reader.c_string()
+    }
+
+    pub(super) fn get_blob(&self, index: u32) -> common::Result<&'a [u8]> {
+        let mut reader = Reader::new(self.blobs);
+        reader.skip(index as usize)?;
+        let len = reader.compressed()? as usize;
+        reader.bytes(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_compressed() {
+        // The examples in ECMA-335 II.23.2
+        for (data, value) in &[
+            (&[0x03][..], 0x03),
+            (&[0x7f], 0x7f),
+            (&[0x80, 0x80], 0x80),
+            (&[0xae, 0x57], 0x2e57),
+            (&[0xbf, 0xff], 0x3fff),
+            (&[0xc0, 0x00, 0x40, 0x00], 0x4000),
+            (&[0xdf, 0xff, 0xff, 0xff], 0x1fff_ffff),
+        ] {
+            assert_eq!(Reader::new(data).compressed().unwrap(), *value);
+        }
+
+        for (data, value) in &[
+            (&[0x06][..], 3),
+            (&[0x7b], -3),
+            (&[0x80, 0x80], 64),
+            (&[0x01], -64),
+            (&[0xc0, 0x00, 0x40, 0x00], 8192),
+            (&[0x80, 0x01], -8192),
+            (&[0xdf, 0xff, 0xff, 0xfe], 268_435_455),
+            (&[0xc0, 0x00, 0x00, 0x01], -268_435_456),
+        ] {
+            assert_eq!(Reader::new(data).compressed_signed().unwrap(), *value);
+        }
+
+        assert!(Reader::new(&[0xe0]).compressed().is_err());
+        assert!(Reader::new(&[0x80]).compressed().is_err());
+    }
+}
@@ -0,0 +1,568 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A Portable PDB contains the sequence points (the IL offsets with their source location)
+// of the methods of a .NET assembly, but not their names which are in the assembly.
+// There is no native code so the address of a FUNC is the method token shifted by 32 bits
+// and the address of a LINE is the IL offset added to the one of its FUNC.
+
+use log::{error, warn};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::assembly::{self, Assembly};
+use super::metadata::{self, Metadata, Reader};
+use crate::common::{self, Dumpable, Mergeable};
+use crate::mapping::PathMappings;
+use crate::sources::{self, SourcePaths};
+use crate::utils;
+use crate::visitor::{self, FuncRecord, LineRecord, Visitor};
+
+const METHOD_DEF_TOKEN: u64 = 0x0600_0000;
+
+#[derive(Debug, PartialEq)]
+struct SequencePoint {
+    il_offset: u32,
+    line: u32,
+    document: u32,
+}
+
+#[derive(Debug)]
+struct Line {
+    il_offset: u32,
+    size: u32,
+    line: u32,
+    file: u32,
+}
+
+#[derive(Debug)]
+struct Method {
+    name: String,
+    size: u32,
+    lines: Vec<Line>,
+}
+
+#[derive(Debug)]
+pub struct PortablePdbInfo {
+    pdb_name: String,
+    debug_id: String,
+    code_id: Option<String>,
+    pe_name: String,
+    files: Vec<String>,
+    source_paths: SourcePaths,
+    // The methods by token
+    methods: BTreeMap<u64, Method>,
+}
+
+impl Display for PortablePdbInfo {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "MODULE dotnet il {} {}", self.debug_id, self.pdb_name)?;
+
+        if let Some(code_id) = self.code_id.as_ref() {
+            writeln!(f, "INFO CODE_ID {} {}", code_id, self.pe_name)?;
+        }
+
+        for (n, file_name) in self.files.iter().enumerate() {
+            writeln!(f, "FILE {} {}", n, file_name)?;
+        }
+
+        for (token, method) in self.methods.iter() {
+            let address = token << 32;
+            writeln!(f, "FUNC {:x} {:x} 0 {}", address, method.size, method.name)?;
+            for line in method.lines.iter() {
+                writeln!(
+                    f,
+                    "{:x} {:x} {} {}",
+                    address + u64::from(line.il_offset),
+                    line.size,
+                    line.line,
+                    line.file
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the name of a document: a separator followed by the parts which are in the blob heap
+fn get_document_name(md: &Metadata, blob: &[u8]) -> common::Result<String> {
+    let mut reader = Reader::new(blob);
+    let separator = reader.u8()?;
+    let mut parts = Vec::new();
+    while !reader.is_empty() {
+        let part = md.get_blob(reader.compressed()?)?;
+        parts.push(String::from_utf8_lossy(part));
+    }
+
+    if separator == 0 {
+        Ok(parts.concat())
+    } else {
+        Ok(parts.join(&char::from(separator).to_string()))
+    }
+}
+
+/// Decode the sequence points of a method (the hidden ones have a null line)
+fn get_sequence_points(blob: &[u8], document: u32) -> common::Result<Vec<SequencePoint>> {
+    let mut reader = Reader::new(blob);
+    let mut points = Vec::new();
+
+    // LocalSignature
+    reader.compressed()?;
+    let mut document = if document == 0 {
+        reader.compressed()?
+    } else {
+        document
+    };
+
+    let mut il_offset = 0;
+    let mut start_line: Option<i64> = None;
+    let mut start_column = 0;
+    while !reader.is_empty() {
+        let delta_il = reader.compressed()?;
+        if delta_il == 0 && !points.is_empty() {
+            // A document record
+            document = reader.compressed()?;
+            continue;
+        }
+        il_offset += delta_il;
+
+        let delta_lines = reader.compressed()?;
+        let delta_columns = if delta_lines == 0 {
+            i64::from(reader.compressed()?)
+        } else {
+            i64::from(reader.compressed_signed()?)
+        };
+
+        if delta_lines == 0 && delta_columns == 0 {
+            points.push(SequencePoint {
+                il_offset,
+                line: 0,
+                document,
+            });
+            continue;
+        }
+
+        let line = if let Some(line) = start_line {
+            line + i64::from(reader.compressed_signed()?)
+        } else {
+            i64::from(reader.compressed()?)
+        };
+        start_column = if start_line.is_some() {
+            start_column + i64::from(reader.compressed_signed()?)
+        } else {
+            i64::from(reader.compressed()?)
+        };
+        start_line = Some(line);
+
+        points.push(SequencePoint {
+            il_offset,
+            line: line as u32,
+            document,
+        });
+    }
+
+    Ok(points)
+}
+
+fn get_lines(points: &[SequencePoint], size: u32) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for (i, point) in points.iter().enumerate() {
+        if point.line == 0 {
+            continue;
+        }
+
+        // The code from a sequence point goes up to the next one
+        let end = points.get(i + 1).map_or(size, |p| p.il_offset);
+        if end <= point.il_offset {
+            continue;
+        }
+
+        lines.push(Line {
+            il_offset: point.il_offset,
+            size: end - point.il_offset,
+            line: point.line,
+            // The FILE numbers start at 0
+            file: point.document - 1,
+        });
+    }
+
+    lines
+}
+
+fn map_file(name: String, mapping: Option<&Arc<PathMappings>>) -> String {
+    let new_name = mapping.and_then(|mapping| match mapping.map(&name) {
+        Ok(name) => name,
+        Err(e) => {
+            error!("Mapping error: {}", e);
+            None
+        }
+    });
+    new_name.unwrap_or(name)
+}
+
+impl PortablePdbInfo {
+    /// Check the signature of the metadata (the whole file is required to check the streams)
+    pub(crate) fn has_metadata_signature(buf: &[u8]) -> bool {
+        metadata::is_metadata(buf)
+    }
+
+    pub(crate) fn is_portable_pdb(buf: &[u8]) -> bool {
+        metadata::is_metadata(buf)
+            && Metadata::parse(buf).map_or(false, |md| md.get_pdb_stream().is_some())
+    }
+
+    fn new(
+        buf: &[u8],
+        pdb_name: &str,
+        assembly: Option<Assembly>,
+        mapping: Option<Arc<PathMappings>>,
+    ) -> common::Result<Self> {
+        let md = Metadata::parse(buf)?;
+        let mut pdb = Reader::new(md.get_pdb_stream().ok_or("Not a Portable PDB")?);
+        let debug_id = assembly::get_debug_id(pdb.bytes(16)?);
+
+        let assembly = assembly.filter(|assembly| {
+            let id = assembly.pdb.as_ref().map(|pdb| pdb.debug_id.as_str());
+            if id != Some(debug_id.as_str()) {
+                warn!(
+                    "The assembly {} doesn't match the pdb {}: the method names are unknown",
+                    assembly.name, pdb_name
+                );
+                return false;
+            }
+            true
+        });
+
+        let mut files = Vec::new();
+        let mut source_paths = SourcePaths::default();
+        for rid in 1..=md.get_rows(metadata::DOCUMENT) {
+            let mut row = md.get_row(metadata::DOCUMENT, rid)?;
+            let name = md.get_blob(row.index(md.blob_size())?)?;
+            let name = get_document_name(&md, name)?;
+            let mapped = map_file(name.clone(), mapping.as_ref());
+            if mapped != name {
+                source_paths.insert(mapped.clone(), PathBuf::from(name));
+            }
+            files.push(mapped);
+        }
+
+        let mut methods = BTreeMap::new();
+        for rid in 1..=md.get_rows(metadata::METHOD_DEBUG_INFORMATION) {
+            let mut row = md.get_row(metadata::METHOD_DEBUG_INFORMATION, rid)?;
+            let document = row.index(md.index_size(metadata::DOCUMENT))?;
+            let blob = row.index(md.blob_size())?;
+            if blob == 0 {
+                continue;
+            }
+
+            let points = get_sequence_points(md.get_blob(blob)?, document)?;
+            let (name, size) = match assembly.as_ref().and_then(|a| a.methods.get(&rid)) {
+                Some(method) if method.code_size != 0 => (method.name.clone(), method.code_size),
+                method => {
+                    // Without the body, the last sequence point is considered as one byte long
+                    let size = points.last().map_or(0, |p| p.il_offset + 1);
+                    let name = method.map_or_else(
+                        || format!("<method {:#x}>", METHOD_DEF_TOKEN | u64::from(rid)),
+                        |m| m.name.clone(),
+                    );
+                    (name, size)
+                }
+            };
+
+            methods.insert(
+                METHOD_DEF_TOKEN | u64::from(rid),
+                Method {
+                    name,
+                    size,
+                    lines: get_lines(&points, size),
+                },
+            );
+        }
+
+        if let Some(remapping) = common::sort_files(&mut files) {
+            for line in methods.values_mut().flat_map(|m| m.lines.iter_mut()) {
+                line.file = remapping[line.file as usize];
+            }
+        }
+
+        let (code_id, pe_name) = assembly.map_or((None, String::new()), |assembly| {
+            (assembly.code_id, assembly.name)
+        });
+
+        Ok(Self {
+            pdb_name: pdb_name.to_string(),
+            debug_id,
+            code_id,
+            pe_name,
+            files,
+            source_paths,
+            methods,
+        })
+    }
+
+    /// Dump a Portable PDB: the method names are in the assembly next to it (if any)
+    pub(crate) fn from_pdb(
+        buf: &[u8],
+        path: &Path,
+        pdb_name: &str,
+        mapping: Option<Arc<PathMappings>>,
+    ) -> common::Result<Self> {
+        let assembly = ["dll", "exe"].iter().find_map(|ext| {
+            let assembly_path = path.with_extension(ext);
+            if !assembly_path.is_file() {
+                return None;
+            }
+            let assembly_buf = utils::read_file(&assembly_path);
+            Assembly::parse(&assembly_buf, &utils::get_filename(&assembly_path)).ok()
+        });
+        if assembly.is_none() {
+            warn!(
+                "No assembly found for {}: the method names are unknown",
+                pdb_name
+            );
+        }
+
+        Self::new(buf, pdb_name, assembly, mapping)
+    }
+
+    /// Dump the Portable PDB of an assembly: the pdb is either embedded in the assembly,
+    /// or next to it, or on a symbol server.
+    pub(crate) fn from_assembly<F>(
+        buf: &[u8],
+        file_name: &str,
+        mapping: Option<Arc<PathMappings>>,
+        find_pdb: F,
+    ) -> common::Result<Self>
+    where
+        F: FnOnce(&str, &str) -> Option<Vec<u8>>,
+    {
+        let mut assembly = Assembly::parse(buf, file_name)?;
+        if let Some(pdb_buf) = assembly.embedded_pdb.take() {
+            let pdb_name = assembly.pdb.as_ref().map_or_else(
+                || {
+                    Path::new(file_name)
+                        .with_extension("pdb")
+                        .display()
+                        .to_string()
+                },
+                |pdb| pdb.name.clone(),
+            );
+            return Self::new(&pdb_buf, &pdb_name, Some(assembly), mapping);
+        }
+
+        let (pdb_name, debug_id) = match assembly.pdb.as_ref() {
+            Some(pdb) => (pdb.name.clone(), pdb.debug_id.clone()),
+            _ => return Err(format!("No Portable PDB referenced in {}", file_name).into()),
+        };
+        let pdb_buf = find_pdb(&pdb_name, &debug_id)
+            .ok_or_else(|| format!("The Portable PDB {} can't be found", pdb_name))?;
+
+        Self::new(&pdb_buf, &pdb_name, Some(assembly), mapping)
+    }
+}
+
+impl Mergeable for PortablePdbInfo {
+    fn merge(left: PortablePdbInfo, right: PortablePdbInfo) -> common::Result<PortablePdbInfo> {
+        if left.debug_id != right.debug_id {
+            return Err(format!(
+                "The files don't have the same debug id: {} and {}",
+                left.debug_id, right.debug_id
+            )
+            .into());
+        }
+
+        // The one with the method names is preferred
+        if left.code_id.is_none() && right.code_id.is_some() {
+            Ok(right)
+        } else {
+            Ok(left)
+        }
+    }
+}
+
+impl Dumpable for PortablePdbInfo {
+    fn dump<W: Write>(&self, mut writer: W) -> common::Result<()> {
+        write!(writer, "{}", self)?;
+        Ok(())
+    }
+
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        visitor::visit_module(visitor, "dotnet", "il", &self.debug_id, &self.pdb_name);
+        if let Some(code_id) = self.code_id.as_ref() {
+            visitor::visit_code_id(visitor, code_id, Some(&self.pe_name));
+        }
+        visitor::visit_files(visitor, &self.files, None);
+
+        for (token, method) in self.methods.iter() {
+            let address = token << 32;
+            visitor.func(&FuncRecord {
+                address,
+                size: u64::from(method.size),
+                parameter_size: 0,
+                multiple: false,
+                name: method.name.clone(),
+            });
+            for line in method.lines.iter() {
+                visitor.line(&LineRecord {
+                    address: address + u64::from(line.il_offset),
+                    size: u64::from(line.size),
+                    line: line.line,
+                    file: line.file,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn get_debug_id(&self) -> &str {
+        &self.debug_id
+    }
+
+    fn get_name(&self) -> &str {
+        &self.pdb_name
+    }
+
+    fn get_source_files(&self) -> Vec<(String, PathBuf)> {
+        sources::get_source_files(&self.files, &self.source_paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_sequence_points() {
+        let blob = [
+            // LocalSignature and InitialDocument (the method is in several documents)
+            0x00, 0x01, //
+            // IL 0: lines 10-10, columns 5-9
+            0x00, 0x00, 0x04, 0x0a, 0x05, //
+            // IL 3: lines 11-12 (+1), columns 5-6 (+0)
+            0x03, 0x01, 0x02, 0x02, 0x00, //
+            // Hidden at IL 7
+            0x04, 0x00, 0x00, //
+            // Document 2
+            0x00, 0x02, //
+            // IL 9: lines 8-8 (-3), columns 1-2 (-4)
+            0x02, 0x00, 0x01, 0x7b, 0x79,
+        ];
+
+        let points = get_sequence_points(&blob, 0).unwrap();
+        assert_eq!(
+            points,
+            vec![
+                SequencePoint {
+                    il_offset: 0,
+                    line: 10,
+                    document: 1
+                },
+                SequencePoint {
+                    il_offset: 3,
+                    line: 11,
+                    document: 1
+                },
+                SequencePoint {
+                    il_offset: 7,
+                    line: 0,
+                    document: 1
+                },
+                SequencePoint {
+                    il_offset: 9,
+                    line: 8,
+                    document: 2
+                },
+            ]
+        );
+
+        let lines: Vec<_> = get_lines(&points, 12)
+            .iter()
+            .map(|l| (l.il_offset, l.size, l.line, l.file))
+            .collect();
+        assert_eq!(lines, vec![(0, 3, 10, 0), (3, 4, 11, 0), (9, 3, 8, 1)]);
+    }
+
+    fn build_pdb() -> Vec<u8> {
+        let mut blobs = vec![0];
+        let mut add_blob = |data: &[u8]| {
+            let index = blobs.len() as u8;
+            blobs.push(data.len() as u8);
+            blobs.extend_from_slice(data);
+            index
+        };
+        let src = add_blob(b"src");
+        let foo = add_blob(b"Foo.cs");
+        let name = add_blob(&[b'/', src, foo]);
+        let seq = add_blob(&[
+            0x00, 0x00, 0x00, 0x04, 0x0a, 0x05, 0x03, 0x01, 0x02, 0x02, 0x00,
+        ]);
+        while blobs.len() % 4 != 0 {
+            blobs.push(0);
+        }
+
+        // PdbId, EntryPoint and the rows of the MethodDef table in the assembly
+        let mut pdb = (1..=20).collect::<Vec<u8>>();
+        pdb.extend_from_slice(&0x0600_0001u32.to_le_bytes());
+        pdb.extend_from_slice(&(1u64 << 6).to_le_bytes());
+        pdb.extend_from_slice(&2u32.to_le_bytes());
+
+        // One Document and two MethodDebugInformation (the second one has no sequence points)
+        let mut tables = vec![0, 0, 0, 0, 2, 0, 0, 1];
+        tables.extend_from_slice(&((1u64 << 0x30) | (1u64 << 0x31)).to_le_bytes());
+        tables.extend_from_slice(&0u64.to_le_bytes());
+        tables.extend_from_slice(&1u32.to_le_bytes());
+        tables.extend_from_slice(&2u32.to_le_bytes());
+        tables.extend_from_slice(&[name, 0, 0, 0, 0, 0, 0, 0]);
+        tables.extend_from_slice(&[1, 0, seq, 0, 0, 0, 0, 0]);
+
+        let streams: Vec<(&[u8], &[u8])> = vec![
+            (b"#Pdb\0\0\0\0", &pdb),
+            (b"#~\0\0", &tables),
+            (b"#Blob\0\0\0", &blobs),
+        ];
+        let mut buf = b"BSJB\x01\x00\x01\x00\x00\x00\x00\x00".to_vec();
+        buf.extend_from_slice(&12u32.to_le_bytes());
+        buf.extend_from_slice(b"PDB v1.0\0\0\0\0\0\0");
+        buf.extend_from_slice(&(streams.len() as u16).to_le_bytes());
+
+        let mut offset = buf.len() + streams.iter().map(|s| 8 + s.0.len()).sum::<usize>();
+        for (name, data) in streams.iter() {
+            buf.extend_from_slice(&(offset as u32).to_le_bytes());
+            buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            buf.extend_from_slice(name);
+            offset += data.len();
+        }
+        for (_, data) in streams.iter() {
+            buf.extend_from_slice(data);
+        }
+
+        buf
+    }
+
+    #[test]
+    fn test_portable_pdb() {
+        let buf = build_pdb();
+        assert!(PortablePdbInfo::is_portable_pdb(&buf));
+
+        let info = PortablePdbInfo::new(&buf, "Foo.pdb", None, None).unwrap();
+        let mut output = Vec::new();
+        info.dump(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(
+            output,
+            "MODULE dotnet il 0403020106050807090A0B0C0D0E0F10FFFFFFFF Foo.pdb\n\
+             FILE 0 src/Foo.cs\n\
+             FUNC 600000100000000 4 0 <method 0x6000001>\n\
+             600000100000000 3 10 0\n\
+             600000100000003 1 11 0\n"
+        );
+    }
+}
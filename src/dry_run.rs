@@ -0,0 +1,262 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --dry-run, nothing is downloaded or written: for each input, the files which would be
+// read, found in a cache or queried on the symbol servers and the files which would be written
+// are printed. The local files and the cached ones are read to get their debug ids.
+
+use failure::Fail;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use symbolic::common::Arch;
+
+use crate::cache::{self, HttpOptions, Location, SymbolServer};
+use crate::common::{self, FileType};
+use crate::dumper::{Config, Destinations};
+use crate::info::{self, ModuleInfo};
+use crate::output_template;
+use crate::symbols_zip;
+use crate::utils;
+
+fn locate(
+    file_name: &str,
+    id: &str,
+    servers: Option<&Vec<SymbolServer>>,
+    steps: &mut Vec<String>,
+) -> Option<PathBuf> {
+    match cache::locate_file(file_name, id, servers) {
+        Location::Cached(path) => {
+            steps.push(format!(
+                "read {} {} from the cache: {}",
+                file_name,
+                id,
+                path.display()
+            ));
+            Some(path)
+        }
+        Location::Servers(urls) if urls.is_empty() => {
+            steps.push(format!(
+                "no symbol server to fetch {} {} from",
+                file_name, id
+            ));
+            None
+        }
+        Location::Servers(urls) => {
+            steps.push(format!(
+                "fetch {} {} from {}",
+                file_name,
+                id,
+                urls.join(", ")
+            ));
+            None
+        }
+    }
+}
+
+fn get_module(
+    config: &Config,
+    path: &Path,
+    buf: &[u8],
+    servers: Option<&Vec<SymbolServer>>,
+    steps: &mut Vec<String>,
+) -> common::Result<Option<ModuleInfo>> {
+    let file_type = match config.file_type {
+        FileType::Unknown => FileType::from_buf(buf),
+        ref file_type => file_type.clone(),
+    };
+    // The modules are known once the members have been parsed
+    if let FileType::Archive | FileType::Zip | FileType::PortablePdb = file_type {
+        return Ok(None);
+    }
+
+    let modules = info::get_modules(buf, path, &file_type)?;
+    let arch = Arch::from_str(config.arch).map_err(|e| e.compat())?;
+    let single = modules.len() == 1;
+    let mut module = modules
+        .into_iter()
+        .find(|m| single || m.arch == arch.name())
+        .ok_or_else(|| format!("No module for the arch {}", config.arch))?;
+
+    if file_type != FileType::Pe {
+        if let Some(name) = config.module_name {
+            module.debug_file = name.to_string();
+        }
+        return Ok(Some(module));
+    }
+
+    // The symbols of a PE are the ones of its PDB: it's next to the PE, in the current
+    // directory or on the symbol servers
+    let local = Some(path.with_file_name(&module.debug_file))
+        .into_iter()
+        .chain(Some(PathBuf::from(&module.debug_file)))
+        .find(|p| p.is_file());
+    match local {
+        Some(local) => steps.push(format!("read {}", local.display())),
+        _ => {
+            locate(&module.debug_file, &module.debug_id, servers, steps);
+        }
+    }
+
+    Ok(Some(module))
+}
+
+fn get_steps(
+    config: &Config,
+    filename: &str,
+    servers: Option<&Vec<SymbolServer>>,
+) -> common::Result<Vec<String>> {
+    let mut steps = Vec::new();
+
+    // The path of the data when they're already here
+    let (path, data) = match cache::parse_url(filename) {
+        Some(url) => match cache::locate_url(&url, servers) {
+            Some(cached) => {
+                steps.push(format!("read {} from the cache: {}", url, cached.display()));
+                (cached.clone(), Some(cached))
+            }
+            _ => {
+                steps.push(format!("download {}", url));
+                (PathBuf::from(url.path()), None)
+            }
+        },
+        _ if filename == utils::STDIN => {
+            steps.push("read stdin".to_string());
+            (PathBuf::from(config.module_name.unwrap_or("stdin")), None)
+        }
+        _ => {
+            let path = PathBuf::from(filename);
+            match config.debug_id.or(config.code_id) {
+                Some(id) => {
+                    let data = locate(&utils::get_filename(&path), id, servers, &mut steps);
+                    (path, data)
+                }
+                _ => {
+                    steps.push(format!("read {}", filename));
+                    (path.clone(), Some(path))
+                }
+            }
+        }
+    };
+
+    let (module, info) = match data {
+        Some(data) => {
+            let buf = utils::try_read_file(&data)?;
+            let info = get_module(config, &path, &buf, servers, &mut steps)?;
+            let module = info
+                .as_ref()
+                .map(|m| (m.debug_file.clone(), m.debug_id.clone()));
+            (module, info)
+        }
+        _ => (
+            config
+                .debug_id
+                .map(|id| (utils::get_filename(&path), id.to_string())),
+            None,
+        ),
+    };
+    // An output named with a template is only known with the module info
+    let template = config.options.output_template.as_ref();
+    let output = match (template, info.as_ref()) {
+        (Some(template), Some(info)) => Some(output_template::expand(template, info)),
+        (None, _) => Some(config.output.to_string()),
+        _ => None,
+    };
+
+    match (module, output) {
+        _ if config.options.is_check() => steps.push("check the debug info".to_string()),
+        (Some((name, debug_id)), Some(output)) => {
+            for destination in
+                Destinations::new(&output, config.store, &name, &debug_id, &config.options)?
+                    .describe()
+            {
+                steps.push(format!("write {}", destination));
+            }
+        }
+        // The output doesn't depend on the module without a store
+        (_, Some(_)) if config.store.is_none() && !symbols_zip::is_enabled() => {
+            let destinations = Destinations::new(config.output, None, "", "", &config.options)?;
+            for destination in destinations.describe() {
+                steps.push(format!("write {}", destination));
+            }
+        }
+        _ => steps.push("write the symbols of the modules found in the file".to_string()),
+    }
+
+    Ok(steps)
+}
+
+/// Print what would be read, fetched and written for each file
+pub(crate) fn dry_run(config: &Config, filenames: &[&str]) -> common::Result<()> {
+    let http = HttpOptions::new(config.user_agent, &config.headers)?;
+    let servers = cache::get_sym_servers(config.symbol_server, &http)?;
+
+    for filename in filenames {
+        println!("{}", filename);
+        match get_steps(config, filename, servers.as_ref()) {
+            Ok(steps) => {
+                for step in steps {
+                    println!("  {}", step);
+                }
+            }
+            Err(e) => println!("  error: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_dry_run_steps() {
+        let config = Config {
+            output: "-",
+            symbol_server: None,
+            store: Some("symbols"),
+            debug_id: None,
+            code_id: None,
+            arch: common::get_compile_time_arch(),
+            file_type: FileType::Unknown,
+            num_jobs: 1,
+            mapping_var: None,
+            mapping_src: None,
+            mapping_dest: None,
+            mapping_file: None,
+            vcs_info: None,
+            user_agent: None,
+            headers: None,
+            allow_export_only: false,
+            all_archs: false,
+            module_name: None,
+            merge: false,
+            supplier: None,
+            options: Arc::default(),
+        };
+
+        let steps = get_steps(&config, "./test_data/linux/basic.full", None).unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                "read ./test_data/linux/basic.full".to_string(),
+                format!(
+                    "write {}",
+                    Path::new("symbols")
+                        .join("basic.full/20AD60B0B4C68177552708AA192E77390/basic.sym")
+                        .display()
+                ),
+            ]
+        );
+
+        let steps = get_steps(&config, "./test_data/windows/basic64.dll", None).unwrap();
+        assert_eq!(steps[0], "read ./test_data/windows/basic64.dll");
+        assert!(steps[1].starts_with("read ") && steps[1].ends_with("basic64.pdb"));
+        assert!(steps[2].ends_with("basic64.sym"));
+    }
+}
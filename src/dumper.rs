@@ -3,27 +3,50 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-use crossbeam::channel::{bounded, Receiver, Sender};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::deque::{Injector, Steal};
 use failure::Fail;
-use hashbrown::HashMap;
-use log::{error, info};
+use log::{error, info, warn};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 use symbolic::common::Arch;
 use symbolic::debuginfo::pe::PeObject;
 
-use crate::cache;
-use crate::common::{self, Dumpable, FileType, Mergeable};
+use crate::apk;
+use crate::archive;
+use crate::breakpad::sym::SymInfo;
+use crate::cache::{self, HttpOptions};
+use crate::cancel;
+use crate::common::{self, Dumpable, ErrorKind, FileType, Mergeable};
+use crate::dotnet::{self, ppdb::PortablePdbInfo};
+use crate::json::NdjsonWriter;
 use crate::linux::elf::{ElfInfo, Platform};
 use crate::mac::macho::MachoInfo;
 use crate::mapping::PathMappings;
-use crate::utils;
+use crate::options::Options;
+use crate::output::{
+    self, Compressor, InfoInjector, NameLimiter, OutputFormat, RecordFilter, Records,
+};
+use crate::output_template;
+use crate::pipeline;
+use crate::recurse;
+use crate::shutdown;
+use crate::sources;
+use crate::stats::{self, StatsCounter};
+use crate::supplier::{SymbolServers, SymbolSupplier};
+use crate::symbols_zip;
+use crate::utils::{self, FileBuffer, Span};
+use crate::vcs::VcsKind;
+use crate::wasm::module::WasmInfo;
 use crate::windows::{self, pdb::PDBInfo, pdb::PEInfo};
 
+#[derive(Clone)]
 pub(crate) struct Config<'a> {
     pub output: &'a str,
     pub symbol_server: Option<&'a str>,
@@ -37,6 +60,26 @@ pub(crate) struct Config<'a> {
     pub mapping_src: Option<Vec<&'a str>>,
     pub mapping_dest: Option<Vec<&'a str>>,
     pub mapping_file: Option<&'a str>,
+    pub vcs_info: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub headers: Option<Vec<&'a str>>,
+    pub allow_export_only: bool,
+    pub all_archs: bool,
+    pub module_name: Option<&'a str>,
+    pub merge: bool,
+    // Where the debug files are fetched from instead of the symbol servers
+    pub supplier: Option<Arc<dyn SymbolSupplier>>,
+    // What's read in the debug info and how the records are written
+    pub options: Arc<Options>,
+}
+
+/// The supplier of the debug files which aren't next to the dumped file
+fn get_supplier(conf: &Config) -> common::Result<Arc<dyn SymbolSupplier>> {
+    if let Some(supplier) = conf.supplier.as_ref() {
+        return Ok(supplier.clone());
+    }
+    let http = HttpOptions::new(conf.user_agent, &conf.headers)?;
+    Ok(Arc::new(SymbolServers::new(conf.symbol_server, &http)?))
 }
 
 pub(crate) trait Creator: Mergeable + Dumpable + Sized {
@@ -46,6 +89,7 @@ pub(crate) trait Creator: Mergeable + Dumpable + Sized {
         path: &PathBuf,
         filename: &str,
         mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
     ) -> common::Result<Self>;
 
     fn get_pe<'a>(
@@ -63,11 +107,12 @@ impl Creator for ElfInfo {
     fn get_dbg(
         _arch: Arch,
         buf: &[u8],
-        _path: &PathBuf,
+        path: &PathBuf,
         filename: &str,
         mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
     ) -> common::Result<Self> {
-        Self::new(&buf, filename, Platform::Linux, mapping)
+        Self::new(&buf, path, filename, Platform::Linux, mapping, options)
     }
 }
 
@@ -78,8 +123,35 @@ impl Creator for MachoInfo {
         _path: &PathBuf,
         filename: &str,
         mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
+    ) -> common::Result<Self> {
+        Self::new(&buf, filename, arch, mapping, options)
+    }
+}
+
+impl Creator for WasmInfo {
+    fn get_dbg(
+        _arch: Arch,
+        buf: &[u8],
+        _path: &PathBuf,
+        filename: &str,
+        mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
+    ) -> common::Result<Self> {
+        Self::new(&buf, filename, mapping, options)
+    }
+}
+
+impl Creator for SymInfo {
+    fn get_dbg(
+        _arch: Arch,
+        buf: &[u8],
+        _path: &PathBuf,
+        _filename: &str,
+        mapping: Option<Arc<PathMappings>>,
+        _options: Arc<Options>,
     ) -> common::Result<Self> {
-        Self::new(&buf, filename, arch, mapping)
+        Self::new(&buf, mapping)
     }
 }
 
@@ -90,8 +162,9 @@ impl Creator for PDBInfo {
         path: &PathBuf,
         filename: &str,
         mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
     ) -> common::Result<Self> {
-        let mut pdb = Self::new(&buf, filename, "", None, mapping)?;
+        let mut pdb = Self::new(&buf, filename, "", None, mapping, options)?;
         windows::utils::try_to_set_pe(&path, &mut pdb, &buf);
         Ok(pdb)
     }
@@ -103,11 +176,18 @@ impl Creator for PDBInfo {
         filename: &str,
         mapping: Option<Arc<PathMappings>>,
     ) -> common::Result<Self> {
-        let symbol_server = cache::get_sym_servers(conf.symbol_server);
-        let res = windows::utils::get_pe_pdb_buf(path, &buf, symbol_server.as_ref());
+        let supplier = get_supplier(conf)?;
+        let res = windows::utils::get_pe_pdb_buf(path, &buf, supplier.as_ref());
 
-        if let Some((pe, pdb_buf, pdb_name)) = res {
-            let pdb = Self::new(&pdb_buf, &pdb_name, filename, Some(pe), mapping)?;
+        if let Some((pe, pdb_file, pdb_name)) = res {
+            let pdb = Self::from_source(
+                pdb_file.source(),
+                &pdb_name,
+                filename,
+                Some(pe),
+                mapping,
+                conf.options.clone(),
+            )?;
             Ok(pdb)
         } else {
             Err("No pdb file found".into())
@@ -122,51 +202,387 @@ impl Creator for PEInfo {
         _path: &PathBuf,
         _filename: &str,
         _mapping: Option<Arc<PathMappings>>,
+        _options: Arc<Options>,
     ) -> common::Result<Self> {
         Err("Not implemented".into())
     }
 
     fn get_pe<'a>(
-        _conf: &Config<'a>,
+        conf: &Config<'a>,
         buf: &[u8],
         path: &PathBuf,
         filename: &str,
         _mapping: Option<Arc<PathMappings>>,
     ) -> common::Result<Self> {
-        let pe = PeObject::parse(&buf)
-            .unwrap_or_else(|_| panic!("Unable to parse the PE file {}", path.to_str().unwrap()));
-        let pe = Self::new(filename, pe)?;
+        let pe = PeObject::parse(&buf).map_err(|e| {
+            format!(
+                "Unable to parse the PE file {}: {}",
+                path.to_str().unwrap(),
+                e
+            )
+        })?;
+        let mut symbols = windows::map::get_map_symbols(path, buf);
+        symbols.extend(windows::coff::get_coff_symbols(buf));
+        let pe = Self::new(filename, pe, symbols, conf.options.clone())?;
         Ok(pe)
     }
 }
 
+impl Creator for PortablePdbInfo {
+    fn get_dbg(
+        _arch: Arch,
+        buf: &[u8],
+        path: &PathBuf,
+        filename: &str,
+        mapping: Option<Arc<PathMappings>>,
+        _options: Arc<Options>,
+    ) -> common::Result<Self> {
+        Self::from_pdb(&buf, path, filename, mapping)
+    }
+
+    fn get_pe<'a>(
+        conf: &Config<'a>,
+        buf: &[u8],
+        path: &PathBuf,
+        filename: &str,
+        mapping: Option<Arc<PathMappings>>,
+    ) -> common::Result<Self> {
+        let supplier = get_supplier(conf)?;
+        Self::from_assembly(&buf, filename, mapping, |pdb_name, debug_id| {
+            let pdb_path = path.with_file_name(pdb_name);
+            if pdb_path.is_file() {
+                return Some(utils::read_file(&pdb_path));
+            }
+
+            supplier.fetch(pdb_name, debug_id)
+        })
+    }
+}
+
+fn write_vcs_info(sym_path: &str, mapping: Option<&Arc<PathMappings>>) -> common::Result<()> {
+    if let Some(vcs) = mapping.and_then(|m| m.get_vcs_info()) {
+        if sym_path.is_empty() || sym_path == "-" {
+            warn!("The vcs info cannot be written when the symbols are written on stdout");
+        } else {
+            vcs.write(format!("{}.vcs.json", sym_path))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_sources<D: Dumpable>(
+    sym_path: &str,
+    dumpable: &D,
+    options: &Options,
+) -> common::Result<()> {
+    if !options.embed_sources {
+        return Ok(());
+    }
+    if sym_path.is_empty() || sym_path == "-" {
+        warn!("The source bundle cannot be written when the symbols are written on stdout");
+        return Ok(());
+    }
+
+    let path = sources::get_bundle_path(sym_path);
+    let files = dumpable.get_source_files();
+    let mut count = 0;
+    shutdown::write_file(&path, |w| {
+        count = sources::write_bundle(w, dumpable.get_name(), dumpable.get_debug_id(), &files)?;
+        Ok(())
+    })?;
+    info!("Write {} source files at {}", count, path);
+    Ok(())
+}
+
+/// Where the symbols of a module are written
+pub(crate) struct Destinations {
+    store: Option<PathBuf>,
+    zip_entry: Option<String>,
+    use_output: bool,
+    use_stdout: bool,
+    records: Records,
+    output: String,
+    cfi_output: String,
+}
+
+impl Destinations {
+    pub(crate) fn new(
+        output: &str,
+        store: Option<&str>,
+        name: &str,
+        debug_id: &str,
+        options: &Options,
+    ) -> common::Result<Self> {
+        let compression = options.compression;
+        let store_path = PathBuf::from(
+            compression.get_path(cache::get_path_for_sym(name, debug_id).to_str().unwrap()),
+        );
+        let store = store
+            .filter(|p| !p.is_empty())
+            .map(|p| PathBuf::from(p).join(&store_path));
+        let zip_entry = if symbols_zip::is_enabled() {
+            Some(symbols_zip::get_entry_name(&store_path))
+        } else {
+            None
+        };
+        let use_output = output != "-" || (store.is_none() && zip_entry.is_none());
+        let use_stdout = use_output && (output.is_empty() || output == "-");
+        let records = if use_output {
+            options.records
+        } else {
+            Records::All
+        };
+        if records == Records::SplitCfi && use_stdout {
+            return Err("--split-cfi requires an output file".into());
+        }
+        let cfi_output = compression.get_path(&output::get_cfi_path(output));
+        let output = if use_output && !use_stdout {
+            compression.get_path(output)
+        } else {
+            output.to_string()
+        };
+
+        Ok(Self {
+            store,
+            zip_entry,
+            use_output,
+            use_stdout,
+            records,
+            output,
+            cfi_output,
+        })
+    }
+
+    /// The written files (and zip entries)
+    pub(crate) fn describe(&self) -> Vec<String> {
+        let mut destinations = Vec::new();
+        if let Some(store) = self.store.as_ref() {
+            destinations.push(store.display().to_string());
+        }
+        if let Some(entry) = self.zip_entry.as_ref() {
+            destinations.push(format!("{} in the zip file", entry));
+        }
+        if self.use_stdout {
+            destinations.push("stdout".to_string());
+        } else if self.use_output {
+            destinations.push(self.output.clone());
+        }
+        if self.records == Records::SplitCfi {
+            destinations.push(self.cfi_output.clone());
+        }
+        destinations
+    }
+}
+
 fn store<D: Dumpable, S1: AsRef<str>, S2: AsRef<str>>(
     output: S1,
     store: Option<S2>,
     dumpable: D,
+    mapping: Option<&Arc<PathMappings>>,
+    options: &Options,
 ) -> common::Result<()> {
-    let output = output.as_ref();
-    let store = store.filter(|p| !p.as_ref().is_empty()).map(|p| {
-        PathBuf::from(p.as_ref()).join(cache::get_path_for_sym(
-            &dumpable.get_name(),
-            dumpable.get_debug_id(),
-        ))
-    });
+    // Nothing is written with --check
+    if let Some(checker) = options.checker.as_ref().filter(|c| c.is_check()) {
+        let mut counter = StatsCounter::default();
+        dumpable.dump(&mut counter)?;
+        checker.add(&counter.finish());
+        return Ok(());
+    }
+
+    let _span = Span::new(format!("emit {}", dumpable.get_name()));
+    let output = match options.output_template.as_ref() {
+        Some(template) => {
+            // The placeholders can be in the directories
+            let output = output_template::get_output(template, &dumpable)?;
+            if let Some(parent) = Path::new(&output).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            output
+        }
+        _ => output.as_ref().to_string(),
+    };
+    let compression = options.compression;
+    let Destinations {
+        store,
+        zip_entry,
+        use_output,
+        use_stdout,
+        records,
+        output,
+        cfi_output,
+    } = Destinations::new(
+        &output,
+        store.as_ref().map(|s| s.as_ref()),
+        dumpable.get_name(),
+        dumpable.get_debug_id(),
+        options,
+    )?;
 
+    // The symbols are formatted once and written in the outputs while they're formatted
+    let mut store_file = None;
     if let Some(store) = store.as_ref() {
         fs::create_dir_all(store.parent().unwrap())?;
-        let store = store.to_str().unwrap();
-        let output = utils::get_writer_for_sym(store);
-        if let Err(e) = dumpable.dump(output) {
-            return Err(e);
+        store_file = Some(Compressor::new(
+            shutdown::AtomicFile::create(store)?,
+            compression,
+        )?);
+    }
+    // The zip file gets the same data as the store
+    let mut zip_data = None;
+    if zip_entry.is_some() {
+        zip_data = Some(Compressor::new(Vec::new(), compression)?);
+    }
+    let mut output_file = None;
+    if use_output && !use_stdout {
+        output_file = Some(Compressor::new(
+            shutdown::AtomicFile::create(&output)?,
+            compression,
+        )?);
+    }
+    let mut cfi_file = None;
+    if records == Records::SplitCfi {
+        let file = Compressor::new(shutdown::AtomicFile::create(&cfi_output)?, compression)?;
+        cfi_file = Some(RecordFilter::new(file, Records::OnlyCfi));
+    }
+    let use_converter = use_output && options.format != OutputFormat::Breakpad;
+    let use_ndjson = use_output && options.format == OutputFormat::Ndjson;
+    // The written records are checked with --strict
+    let mut stats_counter = if stats::is_enabled() || options.checker.is_some() {
+        Some(StatsCounter::default())
+    } else {
+        None
+    };
+
+    let start = Instant::now();
+    let module_stats = pipeline::pipe(
+        |writer| match (options.info_records.is_empty(), options.max_name_length) {
+            (true, 0) => dumpable.dump(writer),
+            (true, max) => {
+                let mut limiter = NameLimiter::new(writer, max);
+                dumpable.dump(&mut limiter)?;
+                limiter.finish()?;
+                Ok(())
+            }
+            (false, max) => {
+                let mut injector = InfoInjector::new(writer, &options.info_records);
+                if max == 0 {
+                    dumpable.dump(&mut injector)?;
+                } else {
+                    let mut limiter = NameLimiter::new(&mut injector, max);
+                    dumpable.dump(&mut limiter)?;
+                    limiter.finish()?;
+                }
+                injector.finish()?;
+                Ok(())
+            }
+        },
+        move |chunks| {
+            let mut stdout = if use_stdout {
+                Some(Compressor::new(
+                    utils::get_writer_for_sym("-"),
+                    compression,
+                )?)
+            } else {
+                None
+            };
+            // The output gets the converted document once all the records have been received
+            let mut converter = if use_converter {
+                output::get_converter(options.format, options.checksums)
+            } else {
+                None
+            };
+            let mut outputs: Vec<&mut dyn Write> = store_file
+                .iter_mut()
+                .map(|f| f as &mut dyn Write)
+                .chain(zip_data.iter_mut().map(|d| d as &mut dyn Write))
+                .chain(stats_counter.iter_mut().map(|c| c as &mut dyn Write))
+                .collect();
+            let output_side: Vec<&mut dyn Write> = match converter.as_mut() {
+                Some(converter) => vec![converter.as_mut()],
+                _ => output_file
+                    .iter_mut()
+                    .map(|f| f as &mut dyn Write)
+                    .chain(stdout.iter_mut().map(|s| s as &mut dyn Write))
+                    .collect(),
+            };
+            // With ndjson, the records are converted while they're written
+            let mut ndjson: Vec<_> = Vec::new();
+            let output_side: Vec<&mut dyn Write> = if use_ndjson {
+                ndjson = output_side
+                    .into_iter()
+                    .map(|w| NdjsonWriter::new(w, options.checksums))
+                    .collect();
+                ndjson.iter_mut().map(|w| w as &mut dyn Write).collect()
+            } else {
+                output_side
+                    .into_iter()
+                    .map(|w| w as &mut dyn Write)
+                    .collect()
+            };
+            // Only the output gets the filtered records
+            let mut filters: Vec<_> = output_side
+                .into_iter()
+                .map(|w| {
+                    RecordFilter::new(w, records).with_selection(options.symbol_selection.clone())
+                })
+                .collect();
+            outputs.extend(filters.iter_mut().map(|f| f as &mut dyn Write));
+            outputs.extend(cfi_file.iter_mut().map(|f| f as &mut dyn Write));
+            pipeline::write_chunks(chunks, &mut outputs)?;
+            drop(outputs);
+            for filter in filters {
+                filter.finish()?;
+            }
+            for writer in ndjson {
+                writer.finish()?;
+            }
+
+            if let Some(mut converter) = converter {
+                if let Some(file) = output_file.as_mut() {
+                    converter.write_document(file)?;
+                }
+                if let Some(stdout) = stdout.as_mut() {
+                    converter.write_document(stdout)?;
+                }
+            }
+
+            if let Some(stdout) = stdout {
+                stdout.finish()?.flush()?;
+            }
+            if let Some(cfi_file) = cfi_file {
+                cfi_file.finish()?.finish()?.commit()?;
+            }
+            for file in store_file.into_iter().chain(output_file) {
+                file.finish()?.commit()?;
+            }
+            if let (Some(data), Some(entry)) = (zip_data, zip_entry.as_ref()) {
+                symbols_zip::add(entry, &data.finish()?)?;
+            }
+            Ok(stats_counter.map(StatsCounter::finish))
+        },
+    )?;
+    if let Some(module_stats) = module_stats {
+        if let Some(checker) = options.checker.as_ref() {
+            checker.add(&module_stats);
         }
+        stats::add(module_stats, start.elapsed());
+    }
+
+    if let Some(store) = store.as_ref() {
+        let store = store.to_str().unwrap();
         info!("Write symbols at {}", store);
+        write_vcs_info(store, mapping)?;
+        write_sources(store, &dumpable, options)?;
     }
 
-    if output != "-" || store.is_none() {
-        let output_stream = utils::get_writer_for_sym(output);
-        dumpable.dump(output_stream)?;
+    if use_output {
         info!("Write symbols at {}", output);
+        write_vcs_info(&output, mapping)?;
+        write_sources(&output, &dumpable, options)?;
+    }
+
+    if records == Records::SplitCfi {
+        info!("Write CFI at {}", cfi_output);
     }
     Ok(())
 }
@@ -175,69 +591,427 @@ fn get_from_id(
     config: &Config,
     path: &PathBuf,
     filename: String,
-) -> common::Result<(Vec<u8>, String)> {
+) -> common::Result<(FileBuffer, String)> {
     for id in &[config.debug_id, config.code_id] {
         if let Some(id) = id {
-            let symbol_server = cache::get_sym_servers(config.symbol_server);
-            let (buf, filename) = cache::search_file(filename, id, symbol_server.as_ref());
-            return if let Some(buf) = buf {
-                Ok((buf, filename))
+            return if let Some(buf) = get_supplier(config)?.fetch(&filename, id) {
+                Ok((buf.into(), filename))
             } else {
-                Err(format!("Impossible to get file {} with id {}", filename, id).into())
+                Err(ErrorKind::Fetch.error(format!(
+                    "Impossible to get file {} with id {}",
+                    filename, id
+                )))
             };
         }
     }
 
-    Ok((utils::read_file(&path), filename))
+    Ok((utils::try_map_file(&path)?, filename))
 }
 
-pub(crate) fn single_file(config: &Config, filename: &str) -> common::Result<()> {
-    let path = PathBuf::from(filename);
-    let filename = utils::get_filename(&path);
+/// The symbol files of several files are stored in the current directory unless they're zipped
+/// or named with a template
+fn use_default_store(options: &Options) -> bool {
+    !symbols_zip::is_enabled() && options.output_template.is_none()
+}
+
+/// Insert a part (e.g. the arch) in the output file name: foo.sym => foo.x86_64.sym
+fn insert_in_output_name(output: &str, part: &str) -> String {
+    if output.is_empty() || output == "-" {
+        return output.to_string();
+    }
+
+    let path = PathBuf::from(output);
+    let stem = path
+        .file_stem()
+        .map_or_else(String::new, |s| s.to_string_lossy().to_string());
+    let name = if let Some(ext) = path.extension() {
+        format!("{}.{}.{}", stem, part, ext.to_string_lossy())
+    } else {
+        format!("{}.{}", stem, part)
+    };
+
+    path.with_file_name(name).to_str().unwrap().to_string()
+}
 
-    let (buf, filename) = get_from_id(config, &path, filename)?;
+/// Parse a debug file in a timed span: the errors are parse errors
+fn parse<T, F: FnOnce() -> common::Result<T>>(filename: &str, parse: F) -> common::Result<T> {
+    let _span = Span::new(format!("parse {}", filename));
+    parse().map_err(|e| ErrorKind::Parse.error(e))
+}
+
+pub(crate) fn single_file(config: &Config, filename: &str) -> common::Result<()> {
+    let fetch = Span::new(format!("fetch {}", filename));
+    let (path, buf, filename) = match cache::parse_url(filename) {
+        Some(url) => {
+            let http = HttpOptions::new(config.user_agent, &config.headers)?;
+            let symbol_server = cache::get_sym_servers(config.symbol_server, &http)?;
+            let (buf, filename) = cache::download_file(&url, symbol_server.as_ref(), &http)
+                .map_err(|e| ErrorKind::Fetch.error(e))?;
+            (PathBuf::from(&filename), buf.into(), filename)
+        }
+        // Without a file name, the module is named with --module-name
+        _ if filename == utils::STDIN => {
+            let filename = config.module_name.unwrap_or("stdin").to_string();
+            (
+                PathBuf::from(&filename),
+                utils::read_stdin()?.into(),
+                filename,
+            )
+        }
+        _ => {
+            let path = PathBuf::from(filename);
+            let filename = utils::get_filename(&path);
+            let (buf, filename) = get_from_id(config, &path, filename)?;
+            (path, buf, filename)
+        }
+    };
+    drop(fetch);
+    let vcs = config.vcs_info.map(VcsKind::from_str).transpose()?;
     let file_mapping = PathMappings::new(
         &config.mapping_var,
         &config.mapping_src,
         &config.mapping_dest,
         &config.mapping_file,
+        vcs,
     )?
     .map(Arc::new);
+    dump_buf(
+        config,
+        &buf,
+        &path,
+        &filename,
+        file_mapping,
+        &mut StoreEmitter {
+            output: config.output,
+            store: config.store,
+            options: &config.options,
+        },
+    )
+}
+
+/// Where the modules of a file are written
+pub(crate) trait Emitter {
+    /// Write a module: the part (e.g. the arch) identifies it in a file with several modules
+    fn emit<D: Dumpable>(
+        &mut self,
+        part: Option<&str>,
+        dumpable: D,
+        mapping: Option<&Arc<PathMappings>>,
+    ) -> common::Result<()>;
+}
+
+/// Write the modules in the output file (the part is inserted in its name) and in the store
+struct StoreEmitter<'a> {
+    output: &'a str,
+    store: Option<&'a str>,
+    options: &'a Options,
+}
+
+impl Emitter for StoreEmitter<'_> {
+    fn emit<D: Dumpable>(
+        &mut self,
+        part: Option<&str>,
+        dumpable: D,
+        mapping: Option<&Arc<PathMappings>>,
+    ) -> common::Result<()> {
+        cancel::check()?;
+        match part {
+            Some(part) => store(
+                insert_in_output_name(self.output, part),
+                self.store,
+                dumpable,
+                mapping,
+                self.options,
+            ),
+            None => store(self.output, self.store, dumpable, mapping, self.options),
+        }
+    }
+}
+
+/// Parse the data of a file and emit its modules
+pub(crate) fn dump_buf<E: Emitter>(
+    config: &Config,
+    buf: &[u8],
+    path: &PathBuf,
+    filename: &str,
+    file_mapping: Option<Arc<PathMappings>>,
+    emitter: &mut E,
+) -> common::Result<()> {
+    cancel::check()?;
     let arch = Arch::from_str(config.arch).map_err(|e| e.compat())?;
 
-    match FileType::from_buf(&buf) {
-        FileType::Elf => store(
-            config.output,
-            config.store,
-            ElfInfo::get_dbg(arch, &buf, &path, &filename, file_mapping)?,
+    // The type set with --type is used instead of the detected one
+    let file_type = match config.file_type {
+        FileType::Unknown => FileType::from_buf(buf),
+        ref file_type => file_type.clone(),
+    };
+
+    match file_type {
+        FileType::Elf => emitter.emit(
+            None,
+            parse(filename, || {
+                ElfInfo::get_dbg(
+                    arch,
+                    buf,
+                    path,
+                    filename,
+                    file_mapping.clone(),
+                    config.options.clone(),
+                )
+            })?
+            .with_debug_link(buf, path, file_mapping.clone())?
+            .with_module_name(config.module_name),
+            file_mapping.as_ref(),
+        ),
+        FileType::Pdb => emitter.emit(
+            None,
+            parse(filename, || {
+                PDBInfo::get_dbg(
+                    arch,
+                    buf,
+                    path,
+                    filename,
+                    file_mapping.clone(),
+                    config.options.clone(),
+                )
+            })?,
+            file_mapping.as_ref(),
         ),
-        FileType::Pdb => store(
-            config.output,
-            config.store,
-            PDBInfo::get_dbg(arch, &buf, &path, &filename, file_mapping)?,
+        FileType::Pe if dotnet::assembly::has_portable_pdb(buf) => emitter.emit(
+            None,
+            parse(filename, || {
+                PortablePdbInfo::get_pe(config, buf, path, filename, file_mapping.clone())
+            })?,
+            file_mapping.as_ref(),
         ),
         FileType::Pe => {
-            if let Ok(pdb_info) = PDBInfo::get_pe(config, &buf, &path, &filename, file_mapping) {
-                store(config.output, config.store, pdb_info)
+            // An invalid config is reported instead of falling back on the exported symbols
+            let config = &Config {
+                supplier: Some(get_supplier(config)?),
+                ..config.clone()
+            };
+            if let Ok(pdb_info) = parse(filename, || {
+                PDBInfo::get_pe(config, buf, path, filename, file_mapping.clone())
+            }) {
+                emitter.emit(None, pdb_info, file_mapping.as_ref())
+            } else if !config.allow_export_only {
+                Err(ErrorKind::NoDebugInfo.error(format!(
+                    "No pdb file found for {}: use --allow-export-only to dump the symbols from the export table",
+                    filename
+                )))
             } else {
-                store(
-                    config.output,
-                    config.store,
-                    PEInfo::get_pe(config, &buf, &path, &filename, None)?,
+                warn!(
+                    "No pdb file found for {}: only the exported symbols are dumped",
+                    filename
+                );
+                emitter.emit(
+                    None,
+                    parse(filename, || {
+                        PEInfo::get_pe(config, buf, path, filename, None)
+                    })?,
+                    None,
                 )
             }
         }
-        FileType::Macho => store(
-            config.output,
-            config.store,
-            MachoInfo::get_dbg(arch, &buf, &path, &filename, file_mapping)?,
+        FileType::Macho if config.all_archs => {
+            if (config.output.is_empty() || config.output == "-") && config.store.is_none() {
+                return Err("--all-archs requires an output file or a store directory".into());
+            }
+            for info in parse(filename, || {
+                MachoInfo::new_all(buf, filename, file_mapping.clone(), config.options.clone())
+            })? {
+                let arch = info.get_arch().to_string();
+                emitter.emit(Some(&arch), info, file_mapping.as_ref())?;
+            }
+            Ok(())
+        }
+        FileType::Macho => emitter.emit(
+            None,
+            parse(filename, || {
+                MachoInfo::get_dbg(
+                    arch,
+                    buf,
+                    path,
+                    filename,
+                    file_mapping.clone(),
+                    config.options.clone(),
+                )
+            })?,
+            file_mapping.as_ref(),
+        ),
+        FileType::Wasm => emitter.emit(
+            None,
+            parse(filename, || {
+                WasmInfo::get_dbg(
+                    arch,
+                    buf,
+                    path,
+                    filename,
+                    file_mapping.clone(),
+                    config.options.clone(),
+                )
+            })?,
+            file_mapping.as_ref(),
+        ),
+        FileType::Breakpad => emitter.emit(
+            None,
+            parse(filename, || {
+                SymInfo::get_dbg(
+                    arch,
+                    buf,
+                    path,
+                    filename,
+                    file_mapping.clone(),
+                    config.options.clone(),
+                )
+            })?
+            .with_module_name(config.module_name),
+            file_mapping.as_ref(),
+        ),
+        FileType::PortablePdb => emitter.emit(
+            None,
+            parse(filename, || {
+                PortablePdbInfo::get_dbg(
+                    arch,
+                    buf,
+                    path,
+                    filename,
+                    file_mapping.clone(),
+                    config.options.clone(),
+                )
+            })?,
+            file_mapping.as_ref(),
         ),
-        FileType::Unknown => Err("Unknown file format".into()),
+        FileType::Archive if config.merge => emitter.emit(
+            None,
+            archive::merge(
+                filename,
+                parse(filename, || {
+                    archive::get_members(
+                        buf,
+                        path,
+                        arch,
+                        file_mapping.clone(),
+                        config.options.clone(),
+                    )
+                })?,
+            )?,
+            file_mapping.as_ref(),
+        ),
+        FileType::Archive => {
+            for info in parse(filename, || {
+                archive::get_members(
+                    buf,
+                    path,
+                    arch,
+                    file_mapping.clone(),
+                    config.options.clone(),
+                )
+            })? {
+                let name = info.get_name().to_string();
+                emitter.emit(Some(&name), info, file_mapping.as_ref())?;
+            }
+            Ok(())
+        }
+        FileType::Zip => {
+            for library in parse(filename, || {
+                apk::get_libraries(buf, path, file_mapping.clone(), config.options.clone())
+            })? {
+                emitter.emit(Some(&library.id), library.info, file_mapping.as_ref())?;
+            }
+            Ok(())
+        }
+        FileType::Unknown => Err(ErrorKind::Parse.error("Unknown file format")),
     }
 }
 
+/// Get the PE and the PDB (in this order) when the two files are a PE and a PDB
+pub(crate) fn get_pe_and_pdb<'a>(filenames: &[&'a str]) -> Option<(&'a str, &'a str)> {
+    if filenames.len() != 2 {
+        return None;
+    }
+    let types: Vec<_> = filenames
+        .iter()
+        .map(|f| recurse::get_file_type(Path::new(f)).ok())
+        .collect();
+    match (&types[0], &types[1]) {
+        (Some(FileType::Pe), Some(FileType::Pdb)) => Some((filenames[0], filenames[1])),
+        (Some(FileType::Pdb), Some(FileType::Pe)) => Some((filenames[1], filenames[0])),
+        _ => None,
+    }
+}
+
+/// Get the stripped ELF binary and its debug file (the binary first) when the two files make one module
+pub(crate) fn get_elf_and_debug<'a>(filenames: &[&'a str]) -> Option<(&'a str, &'a str)> {
+    if filenames.len() != 2 {
+        return None;
+    }
+    let split: Vec<_> = filenames
+        .iter()
+        .map(|f| {
+            let path = Path::new(f);
+            if recurse::get_file_type(path).ok()? != FileType::Elf {
+                return None;
+            }
+            let buf = utils::try_map_file(path).ok()?;
+            Some(ElfInfo::is_split_debug(&buf))
+        })
+        .collect();
+    match (split[0], split[1]) {
+        (Some(false), Some(true)) => Some((filenames[0], filenames[1])),
+        (Some(true), Some(false)) => Some((filenames[1], filenames[0])),
+        _ => None,
+    }
+}
+
+/// Dump a PDB with the CODE_ID, the version and the unwind info of its PE
+pub(crate) fn pe_and_pdb(config: &Config, pe_name: &str, pdb_name: &str) -> common::Result<()> {
+    let pe_path = PathBuf::from(pe_name);
+    let pdb_path = PathBuf::from(pdb_name);
+    let pe_buf = utils::try_map_file(&pe_path)?;
+    let pdb_buf = utils::try_map_file(&pdb_path)?;
+    let pe = PeObject::parse(&pe_buf).map_err(|e| format!("Invalid PE file {}: {}", pe_name, e))?;
+
+    let vcs = config.vcs_info.map(VcsKind::from_str).transpose()?;
+    let file_mapping = PathMappings::new(
+        &config.mapping_var,
+        &config.mapping_src,
+        &config.mapping_dest,
+        &config.mapping_file,
+        vcs,
+    )?
+    .map(Arc::new);
+    let mut pdb = PDBInfo::new(
+        &pdb_buf,
+        &utils::get_filename(&pdb_path),
+        "",
+        None,
+        file_mapping.clone(),
+        config.options.clone(),
+    )?;
+    let pe_filename = utils::get_filename(&pe_path);
+    if config.options.force_id_match {
+        pdb.force_pe(pe_filename, pe, &pdb_buf);
+    } else if !pdb.set_pe(pe_filename, pe, &pdb_buf) {
+        return Err(format!(
+            "{} isn't the PDB of {}: the debug ids differ (use --force-id-match to combine them anyway)",
+            pdb_name, pe_name
+        )
+        .into());
+    }
+
+    store(
+        config.output,
+        config.store,
+        pdb,
+        file_mapping.as_ref(),
+        &config.options,
+    )
+}
+
 enum JobType<D: Dumpable> {
-    Get,
+    // The position of the file in the command line
+    Get(usize),
     Dump(D),
 }
 
@@ -247,23 +1021,56 @@ struct JobItem<D: Dumpable> {
     mapping: Option<Arc<PathMappings>>,
 }
 
+/// Merge the files with the same debug id in the order of the command line
+/// so the output doesn't depend on the order the threads have read them
+fn merge_results<T: Creator>(mut results: Vec<(usize, T)>) -> common::Result<Vec<T>> {
+    results.sort_by_key(|(index, _)| *index);
+
+    let mut merged: Vec<T> = Vec::with_capacity(results.len());
+    for (_, info) in results {
+        let pos = merged
+            .iter()
+            .position(|r| r.get_debug_id() == info.get_debug_id())
+            .or_else(|| {
+                merged
+                    .iter()
+                    .position(|r| r.has_unknown_id() != info.has_unknown_id())
+            });
+        match pos {
+            Some(pos) => {
+                let prev = merged.remove(pos);
+                merged.insert(pos, T::merge(prev, info)?);
+            }
+            _ => merged.push(info),
+        }
+    }
+
+    Ok(merged)
+}
+
 fn send_store_jobs<T: Creator>(
     sender: &Sender<Option<JobItem<T>>>,
-    results: &mut HashMap<String, T>,
+    results: Vec<(usize, T)>,
     num_threads: usize,
     output: &str,
     store: &Option<String>,
+    mapping: Option<Arc<PathMappings>>,
+    options: &Options,
 ) -> common::Result<()> {
+    let mut results = merge_results(results).map_err(|e| {
+        poison_queue(sender, num_threads);
+        e
+    })?;
     if results.len() == 1 {
-        let (_, d) = results.drain().take(1).next().unwrap();
-        self::store(&output, store.as_ref(), d)?;
+        let d = results.pop().unwrap();
+        self::store(&output, store.as_ref(), d, mapping.as_ref(), options)?;
     } else {
-        for (_, d) in results.drain() {
+        for d in results {
             sender
                 .send(Some(JobItem {
                     file: "".to_string(),
                     typ: JobType::Dump(d),
-                    mapping: None,
+                    mapping: mapping.as_ref().map(Arc::clone),
                 }))
                 .unwrap();
         }
@@ -285,11 +1092,12 @@ fn consumer<T: Creator>(
     arch: Arch,
     sender: Sender<Option<JobItem<T>>>,
     receiver: Receiver<Option<JobItem<T>>>,
-    results: Arc<Mutex<HashMap<String, T>>>,
+    results: Arc<Mutex<Vec<(usize, T)>>>,
     counter: Arc<AtomicUsize>,
     num_threads: usize,
     output: String,
     store: Option<String>,
+    options: Arc<Options>,
 ) -> common::Result<()> {
     while let Ok(job) = receiver.recv() {
         if job.is_none() {
@@ -299,42 +1107,49 @@ fn consumer<T: Creator>(
         let JobItem { file, typ, mapping } = job.unwrap();
 
         match typ {
-            JobType::Get => {
+            JobType::Get(index) => {
                 let path = PathBuf::from(file);
                 let filename = utils::get_filename(&path);
-                let buf = utils::read_file(&path);
+                let buf = utils::map_file(&path);
 
-                let info = T::get_dbg(arch, &buf, &path, &filename, mapping).map_err(|e| {
+                let info = T::get_dbg(
+                    arch,
+                    &buf,
+                    &path,
+                    &filename,
+                    mapping.clone(),
+                    options.clone(),
+                )
+                .map_err(|e| {
                     poison_queue(&sender, num_threads);
                     e
                 })?;
 
-                let mut results = results.lock().unwrap();
-                let info = if let Some(prev) = results.remove(info.get_debug_id()) {
-                    T::merge(info, prev).map_err(|e| {
-                        poison_queue(&sender, num_threads);
-                        e
-                    })?
-                } else {
-                    info
-                };
-                results.insert(info.get_debug_id().to_string(), info);
+                results.lock().unwrap().push((index, info));
             }
             JobType::Dump(d) => {
                 let cwd = ".".to_string();
-                let store = Some(store.as_ref().unwrap_or(&cwd));
-                self::store(&output, store.as_ref(), d)?;
+                let store = store
+                    .as_ref()
+                    .or_else(|| Some(&cwd).filter(|_| use_default_store(&options)));
+                self::store(&output, store.as_ref(), d, mapping.as_ref(), &options)?;
                 continue;
             }
         }
 
-        if counter.load(Ordering::SeqCst) == 1 {
+        if counter.fetch_sub(1, Ordering::SeqCst) == 1 {
             // it was the last file: so we just have to add jobs to dump & store
             // and then poison the queue
-            let mut results = results.lock().unwrap();
-            send_store_jobs(&sender, &mut results, num_threads, &output, &store)?;
-        } else {
-            counter.fetch_sub(1, Ordering::SeqCst);
+            let results = std::mem::take(&mut *results.lock().unwrap());
+            send_store_jobs(
+                &sender,
+                results,
+                num_threads,
+                &output,
+                &store,
+                mapping,
+                &options,
+            )?;
         }
     }
 
@@ -345,19 +1160,22 @@ pub(crate) fn several_files<T: 'static + Creator + std::marker::Send>(
     config: &Config,
     filenames: &[&str],
 ) -> common::Result<()> {
+    let vcs = config.vcs_info.map(VcsKind::from_str).transpose()?;
     let file_mapping = PathMappings::new(
         &config.mapping_var,
         &config.mapping_src,
         &config.mapping_dest,
         &config.mapping_file,
+        vcs,
     )?
     .map(Arc::new);
     let arch = Arch::from_str(config.arch).map_err(|e| e.compat())?;
-    let results = Arc::new(Mutex::new(HashMap::default()));
+    let results = Arc::new(Mutex::new(Vec::new()));
     let num_jobs = config.num_jobs.min(filenames.len());
     let counter = Arc::new(AtomicUsize::new(filenames.len()));
 
-    let (sender, receiver) = bounded(num_jobs + 1);
+    // The consumers send the store jobs and the poison in the queue: it mustn't block them
+    let (sender, receiver) = unbounded();
 
     let mut receivers = Vec::with_capacity(num_jobs);
     for i in 0..num_jobs {
@@ -367,12 +1185,13 @@ pub(crate) fn several_files<T: 'static + Creator + std::marker::Send>(
         let counter = Arc::clone(&counter);
         let output = config.output.to_string();
         let store = config.store.map(|s| s.to_string());
+        let options = Arc::clone(&config.options);
 
         let t = thread::Builder::new()
             .name(format!("dump-syms {}", i))
             .spawn(move || {
                 consumer::<T>(
-                    arch, sender, receiver, results, counter, num_jobs, output, store,
+                    arch, sender, receiver, results, counter, num_jobs, output, store, options,
                 )
             })
             .unwrap();
@@ -380,11 +1199,11 @@ pub(crate) fn several_files<T: 'static + Creator + std::marker::Send>(
         receivers.push(t);
     }
 
-    for f in filenames {
+    for (index, f) in filenames.iter().enumerate() {
         sender
             .send(Some(JobItem {
                 file: f.to_string(),
-                typ: JobType::Get,
+                typ: JobType::Get(index),
                 mapping: file_mapping.as_ref().map(Arc::clone),
             }))
             .unwrap();
@@ -398,3 +1217,88 @@ pub(crate) fn several_files<T: 'static + Creator + std::marker::Send>(
 
     Ok(())
 }
+
+/// Dump some files of any type independently (e.g. a.pdb b.so c.dylib) and get the result for
+/// each of them: the idle threads steal the next file in the queue so a huge file doesn't hold
+/// back the other ones
+pub(crate) fn dump_files(
+    config: &Config,
+    filenames: &[&str],
+) -> common::Result<Vec<common::Result<()>>> {
+    if !config.output.is_empty() && config.output != "-" {
+        return Err(
+            "Several files without --type can't be dumped in one output: use --store".into(),
+        );
+    }
+
+    let config = Config {
+        store: config
+            .store
+            .or_else(|| Some(".").filter(|_| use_default_store(&config.options))),
+        ..config.clone()
+    };
+    let queue = Injector::new();
+    for file in filenames.iter().enumerate() {
+        queue.push(file);
+    }
+    let results = Mutex::new(Vec::with_capacity(filenames.len()));
+    let num_jobs = config.num_jobs.min(filenames.len()).max(1);
+
+    crossbeam::scope(|scope| {
+        for i in 0..num_jobs {
+            let (config, queue, results) = (&config, &queue, &results);
+            scope
+                .builder()
+                .name(format!("dump-syms {}", i))
+                .spawn(move |_| loop {
+                    match queue.steal() {
+                        Steal::Success((index, filename)) => {
+                            let res = single_file(config, filename);
+                            results.lock().unwrap().push((index, res));
+                        }
+                        Steal::Retry => {}
+                        Steal::Empty => break,
+                    }
+                })
+                .unwrap();
+        }
+    })
+    .map_err(|_| "A dump thread has panicked")?;
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, res)| res).collect())
+}
+
+/// Dump some files of any type independently
+pub(crate) fn batch(config: &Config, filenames: &[&str]) -> common::Result<()> {
+    let mut failures = 0;
+    for (res, filename) in dump_files(config, filenames)?.into_iter().zip(filenames) {
+        if let Err(e) = res {
+            error!("{}: {}", filename, e);
+            failures += 1;
+        }
+    }
+
+    match failures {
+        0 => Ok(()),
+        n => Err(ErrorKind::Incomplete.error(format!(
+            "{} of the {} files can't be dumped",
+            n,
+            filenames.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_output_for_arch() {
+        assert_eq!(insert_in_output_name("-", "arm64"), "-");
+        assert_eq!(insert_in_output_name("foo.sym", "x86_64"), "foo.x86_64.sym");
+        assert_eq!(insert_in_output_name("out/foo", "arm64e"), "out/foo.arm64e");
+    }
+}
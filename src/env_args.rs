@@ -0,0 +1,149 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Each long option of the command (or of the subcommand) can be set in the environment: e.g.
+// --symbol-server with DUMP_SYMS_SYMBOL_SERVER or --allow-export-only with
+// DUMP_SYMS_ALLOW_EXPORT_ONLY=1. The command line wins over the environment which wins over the
+// config files.
+// The options with a value get their variable when the app is built (EnvArg::env_in, like
+// Arg::env but with the variables given to the app) but a flag or an option with several values
+// per occurrence (--info) can't be set this way: they're read here.
+
+use clap::{Arg, ArgMatches};
+use hashbrown::HashMap;
+
+const PREFIX: &str = "DUMP_SYMS_";
+
+/// The variables of the environment: the ones of the process or some given ones in the tests
+pub(crate) type Vars = HashMap<String, String>;
+
+pub(crate) fn get_vars() -> Vars {
+    std::env::vars().collect()
+}
+
+pub(crate) trait EnvArg<'a> {
+    /// Use the value of the variable name (if any) when the argument isn't on the command line
+    fn env_in(self, name: &'a str, vars: &'a Vars) -> Self;
+}
+
+impl<'a, 'b> EnvArg<'a> for Arg<'a, 'b> {
+    fn env_in(self, name: &'a str, vars: &'a Vars) -> Self {
+        // Like the variables read by clap, a default value is used if the argument isn't given
+        // and is split with the value delimiter
+        match vars.get(name) {
+            Some(value) => self.default_value(value).hide_default_value(true),
+            None => self,
+        }
+    }
+}
+
+/// The variable for an argument: symbol_server => DUMP_SYMS_SYMBOL_SERVER
+fn get_var_name(name: &str) -> String {
+    format!("{}{}", PREFIX, name.to_uppercase())
+}
+
+fn is_on(value: &str) -> bool {
+    matches!(
+        value.trim().to_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+fn is_present_with<F>(matches: &ArgMatches, name: &str, get_var: F) -> bool
+where
+    F: Fn(&str) -> Option<String>,
+{
+    matches.is_present(name) || matches!(get_var(&get_var_name(name)), Some(v) if is_on(&v))
+}
+
+fn values_of_with<F>(
+    matches: &ArgMatches,
+    name: &str,
+    num_vals: usize,
+    get_var: F,
+) -> Option<Vec<String>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if let Some(values) = matches.values_of(name) {
+        return Some(values.map(String::from).collect());
+    }
+    // An occurrence per line
+    let value = get_var(&get_var_name(name))?;
+    Some(
+        value
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .flat_map(|l| l.trim().splitn(num_vals, ' '))
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Check if a flag is on the command line or is set (to 1, true, yes or on) in the environment
+pub(crate) fn is_present(matches: &ArgMatches, name: &str) -> bool {
+    is_present_with(matches, name, |var| std::env::var(var).ok())
+}
+
+/// Get the values of an option with num_vals values per occurrence from the command line or else
+/// from the environment
+pub(crate) fn values_of(matches: &ArgMatches, name: &str, num_vals: usize) -> Option<Vec<String>> {
+    values_of_with(matches, name, num_vals, |var| std::env::var(var).ok())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use clap::App;
+
+    use super::*;
+
+    #[test]
+    fn test_env_args() {
+        let app = App::new("dump_syms")
+            .arg(Arg::with_name("allow_export_only").long("allow-export-only"))
+            .arg(Arg::with_name("check").long("check"))
+            .arg(Arg::with_name("strict").long("strict"))
+            .arg(
+                Arg::with_name("info")
+                    .long("info")
+                    .number_of_values(2)
+                    .multiple(true)
+                    .takes_value(true),
+            );
+        let vars: HashMap<_, _> = [
+            ("DUMP_SYMS_ALLOW_EXPORT_ONLY", "1"),
+            ("DUMP_SYMS_CHECK", "0"),
+            ("DUMP_SYMS_INFO", "URL https://hg.mozilla.org\nCI_JOB 1234"),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let get_var = |name: &str| vars.get(name).map(|v| v.to_string());
+
+        let matches = app
+            .clone()
+            .get_matches_from_safe(vec!["dump_syms", "--strict"])
+            .unwrap();
+        assert!(is_present_with(&matches, "allow_export_only", get_var));
+        assert!(!is_present_with(&matches, "check", get_var));
+        assert!(is_present_with(&matches, "strict", get_var));
+        assert_eq!(
+            values_of_with(&matches, "info", 2, get_var).unwrap(),
+            vec!["URL", "https://hg.mozilla.org", "CI_JOB", "1234"]
+        );
+
+        // The command line wins
+        let matches = app
+            .get_matches_from_safe(vec!["dump_syms", "--info", "URL", "https://foo"])
+            .unwrap();
+        assert_eq!(
+            values_of_with(&matches, "info", 2, get_var).unwrap(),
+            vec!["URL", "https://foo"]
+        );
+        assert!(values_of_with(&matches, "info", 2, |_| None).is_some());
+        assert!(!is_present_with(&matches, "strict", |_| None));
+    }
+}
@@ -0,0 +1,199 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With the feature dump_syms_c, a C API (declared in include/dump_syms.h) is exported so the
+// library can be built as a cdylib and linked by a C/C++ program instead of running dump_syms.
+// The functions return 0 on success and -1 on failure (a panic doesn't unwind into the C code:
+// it's a failure too): the error message of the last failure of the thread is got with
+// dump_syms_last_error.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, UnwindSafe};
+
+use crate::api::Dumper;
+use crate::common;
+use crate::shutdown;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The options of a dump (as the ones of the command line): NULL strings are unset
+#[repr(C)]
+pub struct DumpSymsOptions {
+    pub arch: *const c_char,
+    pub inlines: bool,
+    pub cfi: bool,
+    pub mapping_file: *const c_char,
+    pub symbol_server: *const c_char,
+    pub allow_export_only: bool,
+}
+
+unsafe fn to_str<'a>(s: *const c_char, name: &str) -> common::Result<Option<&'a str>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(Some)
+        .map_err(|_| format!("The {} isn't a valid UTF-8 string", name).into())
+}
+
+unsafe fn dump_file(
+    path: *const c_char,
+    options: *const DumpSymsOptions,
+    out_path: *const c_char,
+) -> common::Result<()> {
+    let path = to_str(path, "path")?.ok_or("The path is NULL")?;
+    let mut dumper = Dumper::from_path(path);
+    if let Some(options) = options.as_ref() {
+        if let Some(arch) = to_str(options.arch, "arch")? {
+            dumper = dumper.with_arch(arch);
+        }
+        if let Some(file) = to_str(options.mapping_file, "mapping file")? {
+            dumper = dumper.with_mapping_file(file);
+        }
+        if let Some(server) = to_str(options.symbol_server, "symbol server")? {
+            dumper = dumper.with_symbol_server(server);
+        }
+        dumper = dumper
+            .with_inlines(options.inlines)
+            .with_cfi(options.cfi)
+            .with_allow_export_only(options.allow_export_only);
+    }
+
+    match to_str(out_path, "output path")? {
+        // A failed dump doesn't leave a truncated file at out_path
+        Some(out_path) => shutdown::write_file(out_path, |output| dumper.dump(output))?,
+        None => dumper.dump(io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+/// Run f and get a panic as an error
+fn catch_panic<F>(f: F) -> common::Result<()>
+where
+    F: FnOnce() -> common::Result<()> + UnwindSafe,
+{
+    panic::catch_unwind(f).unwrap_or_else(|payload| {
+        let cause = payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| payload.downcast_ref::<&str>().copied())
+            .unwrap_or("<cause unknown>");
+        Err(format!("A panic occurred: {}", cause).into())
+    })
+}
+
+fn set_last_error(error: Option<common::Error>) {
+    let error = error.map(|e| CString::new(e.to_string().replace('\0', "")).unwrap_or_default());
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+}
+
+/// Fill the options with the default values (no inlines, with the CFI)
+///
+/// # Safety
+///
+/// options must be NULL or point to a (maybe uninitialized) DumpSymsOptions.
+#[no_mangle]
+pub unsafe extern "C" fn dump_syms_default_options(options: *mut DumpSymsOptions) {
+    if !options.is_null() {
+        options.write(DumpSymsOptions {
+            arch: std::ptr::null(),
+            inlines: false,
+            cfi: true,
+            mapping_file: std::ptr::null(),
+            symbol_server: std::ptr::null(),
+            allow_export_only: false,
+        });
+    }
+}
+
+/// Dump the symbols of the file at path in the file at out_path (stdout when it's NULL)
+///
+/// # Safety
+///
+/// The strings must be NULL or some nul-terminated strings and options must be NULL (for the
+/// default options) or point to a DumpSymsOptions.
+#[no_mangle]
+pub unsafe extern "C" fn dump_syms_dump_file(
+    path: *const c_char,
+    options: *const DumpSymsOptions,
+    out_path: *const c_char,
+) -> c_int {
+    match catch_panic(|| dump_file(path, options, out_path)) {
+        Ok(()) => {
+            set_last_error(None);
+            0
+        }
+        Err(e) => {
+            set_last_error(Some(e));
+            -1
+        }
+    }
+}
+
+/// The error message of the last failure of the thread (NULL when the last call succeeded):
+/// it's valid until the next call
+#[no_mangle]
+pub extern "C" fn dump_syms_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |e| e.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::read_to_string;
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_dump_file() {
+        let tmp_dir = Builder::new().prefix("ffi").tempdir().unwrap();
+        let out = tmp_dir.path().join("basic.sym");
+        let path = CString::new("./test_data/linux/basic.full.sym").unwrap();
+        let out_path = CString::new(out.to_str().unwrap()).unwrap();
+
+        let mut options = std::mem::MaybeUninit::<DumpSymsOptions>::uninit();
+        let options = unsafe {
+            dump_syms_default_options(options.as_mut_ptr());
+            let mut options = options.assume_init();
+            options.cfi = false;
+            options
+        };
+        let res = unsafe { dump_syms_dump_file(path.as_ptr(), &options, out_path.as_ptr()) };
+        assert_eq!(res, 0);
+        assert!(dump_syms_last_error().is_null());
+        let sym = read_to_string(&out).unwrap();
+        assert!(sym.starts_with("MODULE Linux x86_64 "));
+        assert!(sym.contains("\nFUNC ") && !sym.contains("\nSTACK "));
+
+        let path = CString::new("./test_data/linux/missing").unwrap();
+        let res =
+            unsafe { dump_syms_dump_file(path.as_ptr(), std::ptr::null(), out_path.as_ptr()) };
+        assert_eq!(res, -1);
+        let error = unsafe { CStr::from_ptr(dump_syms_last_error()) };
+        assert!(!error.to_str().unwrap().is_empty());
+
+        // The failed dump has left the previous file as is
+        assert_eq!(read_to_string(&out).unwrap(), sym);
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        assert!(catch_panic(|| Ok(())).is_ok());
+        let error = catch_panic(|| panic!("Invalid {}", "data")).unwrap_err();
+        assert_eq!(error.to_string(), "A panic occurred: Invalid data");
+    }
+}
@@ -0,0 +1,101 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// When the linker folds some identical functions (ICF), several names share the same address.
+// The name kept for the FUNC is always the smallest one (so the output doesn't depend on the
+// order of the debug info) and --folded says how the other ones are written:
+//   - multiple (default): the FUNC has the "m" marker from the Breakpad spec;
+//   - first: no marker, only the kept name;
+//   - aliases: the "m" marker and all the names separated by " | ".
+
+use crate::common;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FoldingPolicy {
+    Multiple = 0,
+    First = 1,
+    Aliases = 2,
+}
+
+pub(crate) fn parse_folding_policy(policy: &str) -> common::Result<FoldingPolicy> {
+    Ok(match policy {
+        "multiple" => FoldingPolicy::Multiple,
+        "first" => FoldingPolicy::First,
+        "aliases" => FoldingPolicy::Aliases,
+        _ => {
+            return Err(format!(
+                "Invalid folding policy {}: must be multiple, first or aliases",
+                policy
+            )
+            .into())
+        }
+    })
+}
+
+/// A FUNC or a PUBLIC written with the folding policy of the dump
+pub(crate) struct Folded<'a, S>(pub &'a S, pub FoldingPolicy);
+
+/// Check if a FUNC or a PUBLIC is marked as sharing its address with other symbols
+pub(crate) fn is_multiple(is_multiple: bool, policy: FoldingPolicy) -> bool {
+    is_multiple && policy != FoldingPolicy::First
+}
+
+/// Get the "m " marker of a FUNC or a PUBLIC sharing its address with other symbols
+pub(crate) fn get_marker(is_multiple: bool, policy: FoldingPolicy) -> &'static str {
+    if self::is_multiple(is_multiple, policy) {
+        "m "
+    } else {
+        ""
+    }
+}
+
+/// Get the name to write for a symbol and the other names at its address
+pub(crate) fn get_name(name: &str, aliases: &[String], policy: FoldingPolicy) -> String {
+    if policy != FoldingPolicy::Aliases || aliases.is_empty() {
+        return name.to_string();
+    }
+    let mut names = vec![name];
+    names.extend(aliases.iter().map(|alias| alias.as_str()));
+    names.join(" | ")
+}
+
+/// Keep the smallest name and put the other one in the aliases
+pub(crate) fn fold_names(name: &mut String, aliases: &mut Vec<String>, other: String) {
+    if other == *name || aliases.contains(&other) {
+        return;
+    }
+    let other = if other < *name {
+        std::mem::replace(name, other)
+    } else {
+        other
+    };
+    aliases.push(other);
+    aliases.sort();
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_fold_names() {
+        let mut name = "foo".to_string();
+        let mut aliases = Vec::new();
+        fold_names(&mut name, &mut aliases, "bar".to_string());
+        fold_names(&mut name, &mut aliases, "zoo".to_string());
+        fold_names(&mut name, &mut aliases, "foo".to_string());
+        fold_names(&mut name, &mut aliases, "baz".to_string());
+        assert_eq!(name, "bar");
+        assert_eq!(aliases, vec!["baz", "foo", "zoo"]);
+
+        assert_eq!(
+            get_name(&name, &aliases, FoldingPolicy::Aliases),
+            "bar | baz | foo | zoo"
+        );
+        assert_eq!(get_name(&name, &aliases, FoldingPolicy::First), "bar");
+        assert_eq!(get_name(&name, &[], FoldingPolicy::Aliases), "bar");
+    }
+}
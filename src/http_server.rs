@@ -0,0 +1,286 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A minimal HTTP/1.1 server (keep-alive, Content-Length bodies) shared by the mock symbol server
+// and the dump server. The connections are handled by a fixed number of threads (the next ones
+// wait to be accepted) and a request whose body is too big gets a 413.
+
+use crossbeam::channel::bounded;
+use log::{info, warn};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::common;
+
+// An idle keep-alive connection is closed to free its thread
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_BODY_SIZE: u64 = 1 << 30;
+const DEFAULT_NUM_CONNECTIONS: usize = 16;
+
+/// The address of a server and its limits
+#[derive(Clone, Debug)]
+pub(crate) struct ServerOptions {
+    pub host: String,
+    // 0 to get a free port
+    pub port: u16,
+    pub max_body_size: u64,
+    // The number of connections handled in parallel
+    pub num_connections: usize,
+}
+
+impl ServerOptions {
+    /// A server on 127.0.0.1:port
+    pub(crate) fn local(port: u16) -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            num_connections: DEFAULT_NUM_CONNECTIONS,
+        }
+    }
+}
+
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub keep_alive: bool,
+    pub body: Vec<u8>,
+}
+
+pub(crate) struct Response {
+    pub status: u16,
+    pub reason: &'static str,
+    pub location: Option<String>,
+    pub content_type: Option<&'static str>,
+    // The range of the body in a partial response (bytes 0-1023/4096)
+    pub content_range: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn new(status: u16, reason: &'static str) -> Self {
+        Self {
+            status,
+            reason,
+            location: None,
+            content_type: None,
+            content_range: None,
+            body: Vec::new(),
+        }
+    }
+}
+
+/// Bind a server and send the responses of the handler in some background threads
+pub(crate) fn start<H>(
+    name: &str,
+    options: &ServerOptions,
+    handler: H,
+) -> common::Result<SocketAddr>
+where
+    H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind((options.host.as_str(), options.port))?;
+    let addr = listener.local_addr()?;
+    let handler = Arc::new(handler);
+
+    // A connection is only accepted when a thread is free to handle it
+    let (sender, receiver) = bounded::<TcpStream>(0);
+    for i in 0..options.num_connections.max(1) {
+        let receiver = receiver.clone();
+        let handler = Arc::clone(&handler);
+        let name = name.to_string();
+        let max_body_size = options.max_body_size;
+        thread::Builder::new()
+            .name(format!("dump-syms {} {}", name, i))
+            .spawn(move || {
+                for stream in receiver {
+                    if let Err(e) = handle_connection(stream, max_body_size, handler.as_ref()) {
+                        warn!("{}: {}", name, e);
+                    }
+                }
+            })?;
+    }
+
+    thread::Builder::new()
+        .name(format!("dump-syms {}", name))
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if sender.send(stream).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+    Ok(addr)
+}
+
+fn write_response(
+    writer: &mut TcpStream,
+    request: &Request,
+    response: &Response,
+) -> common::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n",
+        response.status,
+        response.reason,
+        response.body.len()
+    )?;
+    if let Some(location) = response.location.as_ref() {
+        write!(writer, "Location: {}\r\n", location)?;
+    }
+    if let Some(content_type) = response.content_type {
+        write!(writer, "Content-Type: {}\r\n", content_type)?;
+    }
+    if let Some(content_range) = response.content_range.as_ref() {
+        write!(writer, "Content-Range: {}\r\n", content_range)?;
+    }
+    if !request.keep_alive {
+        write!(writer, "Connection: close\r\n")?;
+    }
+    write!(writer, "\r\n")?;
+    if request.method != "HEAD" {
+        writer.write_all(&response.body)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn handle_connection<H: Fn(&Request) -> Response>(
+    stream: TcpStream,
+    max_body_size: u64,
+    handler: &H,
+) -> common::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    while let Some((mut request, content_length)) = read_request(&mut reader)? {
+        let response = if content_length > max_body_size {
+            // The body isn't read so the connection can't be used anymore
+            request.keep_alive = false;
+            Response::new(413, "Payload Too Large")
+        } else {
+            request.body.resize(content_length as usize, 0);
+            reader.read_exact(&mut request.body)?;
+            handler(&request)
+        };
+        info!("{} {} {}", request.method, request.path, response.status);
+
+        write_response(&mut writer, &request, &response)?;
+        if !request.keep_alive {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the request line and the headers: the body (with the returned length) is read after
+fn read_request(reader: &mut BufReader<TcpStream>) -> common::Result<Option<(Request, u64)>> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => return Ok(None),
+        Ok(_) => {}
+        // The connection is idle
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut parts = line.split_whitespace();
+    let (method, path, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) => (method, path, version),
+        _ => return Err(format!("Invalid request line: {}", line.trim()).into()),
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        // The query isn't used
+        path: path.split('?').next().unwrap().to_string(),
+        headers: Vec::new(),
+        keep_alive: version == "HTTP/1.1",
+        body: Vec::new(),
+    };
+    let mut content_length = 0;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(pos) = line.find(':') {
+            let key = line[..pos].trim().to_lowercase();
+            let value = line[pos + 1..].trim().to_string();
+            if key == "connection" {
+                request.keep_alive = value.eq_ignore_ascii_case("keep-alive");
+            } else if key == "content-length" {
+                content_length = value
+                    .parse()
+                    .map_err(|_| format!("Invalid content length: {}", value))?;
+            }
+            request.headers.push((key, value));
+        }
+    }
+
+    Ok(Some((request, content_length)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn send(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_max_body_size() {
+        let options = ServerOptions {
+            max_body_size: 16,
+            num_connections: 1,
+            ..ServerOptions::local(0)
+        };
+        let addr = start("test", &options, |request| {
+            let mut response = Response::new(200, "OK");
+            response.body = request.body.clone();
+            response
+        })
+        .unwrap();
+
+        let response = send(
+            addr,
+            "POST /foo HTTP/1.1\r\nContent-Length: 1000\r\nConnection: keep-alive\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+
+        let response = send(
+            addr,
+            "POST /foo HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+}
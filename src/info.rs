@@ -0,0 +1,206 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// dump_syms info: the MODULE-level metadata of a file (os, arch, ids, debug file and the kinds of
+// debug info it contains) are read from its headers without dumping the symbols, for example to
+// get the path of its symbol file in a store. A fat binary has one module per arch.
+
+use failure::Fail;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use symbolic::debuginfo::{Archive, FileFormat, Object};
+
+use crate::cache;
+use crate::common::{self, ErrorKind, FileType};
+use crate::utils;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ModuleInfo {
+    pub os: String,
+    pub arch: String,
+    pub debug_id: String,
+    pub code_id: Option<String>,
+    // The name in the MODULE record (the PDB one for a PE)
+    pub debug_file: String,
+    pub store_path: String,
+    pub has_debug_info: bool,
+    pub has_unwind_info: bool,
+    pub has_symbols: bool,
+}
+
+impl ModuleInfo {
+    fn new(object: &Object, file_name: String) -> Self {
+        let os = match object.file_format() {
+            FileFormat::Pe | FileFormat::Pdb => "windows",
+            FileFormat::MachO => "Mac",
+            FileFormat::Wasm => "wasm",
+            _ => "Linux",
+        };
+        let debug_file = match object {
+            Object::Pe(pe) => pe
+                .debug_file_name()
+                .and_then(|pdb| {
+                    Path::new(&pdb.replace('\\', "/"))
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                })
+                .unwrap_or(file_name),
+            _ => file_name,
+        };
+        let debug_id = object.debug_id().breakpad().to_string();
+
+        Self {
+            os: os.to_string(),
+            arch: object.arch().name().to_string(),
+            store_path: cache::get_path_for_sym(&debug_file, &debug_id)
+                .display()
+                .to_string(),
+            debug_id,
+            code_id: object
+                .code_id()
+                .map(|id| id.as_str().to_string().to_uppercase()),
+            debug_file,
+            has_debug_info: object.has_debug_info(),
+            has_unwind_info: object.has_unwind_info(),
+            has_symbols: object.has_symbols(),
+        }
+    }
+
+    /// The MODULE and INFO CODE_ID records of a symbol file
+    pub(crate) fn from_sym(buf: &[u8]) -> common::Result<Self> {
+        let header = buf.split(|c| *c == b'\n').next().unwrap_or_default();
+        let header = String::from_utf8_lossy(header);
+        let parts: Vec<_> = header.trim_end().splitn(5, ' ').collect();
+        let (os, arch, debug_id, debug_file) = match parts.as_slice() {
+            ["MODULE", os, arch, id, name] => (*os, *arch, *id, *name),
+            _ => return Err(ErrorKind::Parse.error("Invalid MODULE record")),
+        };
+        let code_id = String::from_utf8_lossy(buf)
+            .lines()
+            .take_while(|l| l.starts_with("MODULE ") || l.starts_with("INFO "))
+            .find_map(|l| {
+                l.strip_prefix("INFO CODE_ID ")
+                    .and_then(|id| id.split(' ').next())
+                    .map(String::from)
+            });
+        let has_record = |prefix: &[u8]| {
+            buf.split(|c| *c == b'\n')
+                .any(|line| line.starts_with(prefix))
+        };
+
+        Ok(Self {
+            os: os.to_string(),
+            arch: arch.to_string(),
+            debug_id: debug_id.to_string(),
+            code_id,
+            debug_file: debug_file.to_string(),
+            store_path: cache::get_path_for_sym(debug_file, debug_id)
+                .display()
+                .to_string(),
+            has_debug_info: has_record(b"FUNC "),
+            has_unwind_info: has_record(b"STACK "),
+            has_symbols: has_record(b"PUBLIC ") || has_record(b"FUNC "),
+        })
+    }
+}
+
+impl Display for ModuleInfo {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let yes_no = |b| if b { "yes" } else { "no" };
+        writeln!(f, "os: {}", self.os)?;
+        writeln!(f, "arch: {}", self.arch)?;
+        writeln!(f, "debug id: {}", self.debug_id)?;
+        writeln!(f, "code id: {}", self.code_id.as_deref().unwrap_or("-"))?;
+        writeln!(f, "debug file: {}", self.debug_file)?;
+        writeln!(f, "store path: {}", self.store_path)?;
+        writeln!(f, "debug info: {}", yes_no(self.has_debug_info))?;
+        writeln!(f, "unwind info: {}", yes_no(self.has_unwind_info))?;
+        writeln!(f, "symbols: {}", yes_no(self.has_symbols))
+    }
+}
+
+/// Get the modules in a file (several ones in a fat binary)
+pub(crate) fn get_modules(
+    buf: &[u8],
+    path: &Path,
+    file_type: &FileType,
+) -> common::Result<Vec<ModuleInfo>> {
+    let file_type = match file_type {
+        FileType::Unknown => FileType::from_buf(buf),
+        file_type => file_type.clone(),
+    };
+    match file_type {
+        FileType::Unknown => return Err(ErrorKind::Parse.error("Unknown file format")),
+        FileType::Breakpad => return Ok(vec![ModuleInfo::from_sym(buf)?]),
+        FileType::Archive | FileType::Zip | FileType::PortablePdb => {
+            return Err(ErrorKind::Parse.error(format!(
+                "The modules in {} are only known once it's dumped",
+                path.display()
+            )))
+        }
+        _ => {}
+    }
+
+    let file_name = utils::get_filename(&path.to_path_buf());
+    let archive = Archive::parse(buf).map_err(|e| ErrorKind::Parse.error(e.compat()))?;
+    archive
+        .objects()
+        .map(|object| {
+            object
+                .map(|object| ModuleInfo::new(&object, file_name.clone()))
+                .map_err(|e| ErrorKind::Parse.error(e.compat()))
+        })
+        .collect()
+}
+
+/// Print the modules of a file (in JSON with json)
+pub(crate) fn print(filename: &str, json: bool) -> common::Result<()> {
+    let path = Path::new(filename);
+    let buf = utils::try_read_file(path)?;
+    let modules = get_modules(&buf, path, &FileType::Unknown)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&modules)?);
+    } else {
+        let modules: Vec<_> = modules.iter().map(|m| m.to_string()).collect();
+        print!("{}", modules.join("\n"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_info() {
+        let path = Path::new("./test_data/windows/basic64.dll");
+        let modules = get_modules(&utils::read_file(path), path, &FileType::Unknown).unwrap();
+        assert_eq!(modules.len(), 1);
+        let module = &modules[0];
+        assert_eq!(module.os, "windows");
+        assert_eq!(module.arch, "x86_64");
+        assert_eq!(module.debug_id, "D09EA7D6D2C24C1EBFFE78B0C866BB7F2");
+        assert_eq!(module.debug_file, "basic64.pdb");
+        assert_eq!(
+            Path::new(&module.store_path),
+            Path::new("basic64.pdb/D09EA7D6D2C24C1EBFFE78B0C866BB7F2/basic64.sym")
+        );
+        assert!(module.code_id.is_some());
+        assert!(module.has_unwind_info);
+
+        let sym = b"MODULE Linux x86_64 0F1E2D3C4B5A69788796A5B4C3D2E1F00 libfoo.so\n\
+                    INFO CODE_ID 3C2D1E0F5A4B7869\n\
+                    PUBLIC 1000 0 foo\n";
+        let module = ModuleInfo::from_sym(sym).unwrap();
+        assert_eq!(module.os, "Linux");
+        assert_eq!(module.debug_file, "libfoo.so");
+        assert_eq!(module.code_id.as_deref(), Some("3C2D1E0F5A4B7869"));
+        assert!(module.has_symbols && !module.has_debug_info && !module.has_unwind_info);
+    }
+}
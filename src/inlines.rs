@@ -0,0 +1,166 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The inlined functions are described with some INLINE_ORIGIN records (the name of each
+// inlined function) and some INLINE records after a FUNC:
+//   INLINE nest_level call_line call_file origin [address size]+
+// where the nest level is 0 for a function inlined in the FUNC itself.
+
+use hashbrown::{hash_map, HashMap};
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InlineOrigins {
+    names: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl Display for InlineOrigins {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for (n, name) in self.names.iter().enumerate() {
+            writeln!(f, "INLINE_ORIGIN {} {}", n, name)?;
+        }
+        Ok(())
+    }
+}
+
+impl InlineOrigins {
+    pub(crate) fn get_names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub(crate) fn get_id(&mut self, name: &str) -> u32 {
+        match self.ids.entry(name.to_string()) {
+            hash_map::Entry::Occupied(e) => *e.get(),
+            hash_map::Entry::Vacant(e) => {
+                let id = self.names.len() as u32;
+                self.names.push(e.key().clone());
+                e.insert(id);
+                id
+            }
+        }
+    }
+
+    /// Add the origins of other and get the new ids of its origins
+    pub(crate) fn merge(&mut self, other: &InlineOrigins) -> Vec<u32> {
+        other.names.iter().map(|name| self.get_id(name)).collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Inline {
+    pub depth: u32,
+    pub call_line: u32,
+    pub call_file: u32,
+    pub origin: u32,
+    /// The address and the size of the ranges
+    pub ranges: Vec<(u32, u32)>,
+}
+
+impl Display for Inline {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "INLINE {} {} {} {}",
+            self.depth, self.call_line, self.call_file, self.origin
+        )?;
+        for (address, size) in self.ranges.iter() {
+            write!(f, " {:x} {:x}", address, size)?;
+        }
+        writeln!(f)
+    }
+}
+
+impl Inline {
+    /// Get the ranges (sorted and merged when they're contiguous) from some (address, size)
+    pub(crate) fn get_ranges<I: IntoIterator<Item = (u32, u32)>>(ranges: I) -> Vec<(u32, u32)> {
+        let mut ranges: Vec<_> = ranges.into_iter().filter(|(_, size)| *size != 0).collect();
+        ranges.sort_unstable();
+
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (address, size) in ranges {
+            match merged.last_mut() {
+                Some((last, last_size)) if address <= *last + *last_size => {
+                    *last_size = (*last_size).max(address + size - *last);
+                }
+                _ => merged.push((address, size)),
+            }
+        }
+        merged
+    }
+
+    /// Set the new file ids of the call sites
+    pub(crate) fn remap_files(inlines: &mut [Inline], remapping: &[u32]) {
+        for inline in inlines.iter_mut() {
+            inline.call_file = remapping[inline.call_file as usize];
+        }
+    }
+
+    /// Get the part of the inlines in [start, start + len[ (e.g. for a piece of a split function)
+    pub(crate) fn retain(inlines: &[Inline], start: u32, len: u32) -> Vec<Inline> {
+        let end = start + len;
+        inlines
+            .iter()
+            .filter_map(|inline| {
+                let ranges: Vec<_> = inline
+                    .ranges
+                    .iter()
+                    .filter_map(|(address, size)| {
+                        let (a, b) = ((*address).max(start), (address + size).min(end));
+                        if a < b {
+                            Some((a, b - a))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                if ranges.is_empty() {
+                    None
+                } else {
+                    Some(Inline {
+                        ranges,
+                        ..inline.clone()
+                    })
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_inlines() {
+        let mut origins = InlineOrigins::default();
+        assert_eq!(origins.get_id("foo(int)"), 0);
+        assert_eq!(origins.get_id("bar()"), 1);
+        assert_eq!(origins.get_id("foo(int)"), 0);
+
+        let mut other = InlineOrigins::default();
+        other.get_id("baz()");
+        other.get_id("bar()");
+        assert_eq!(origins.merge(&other), vec![2, 1]);
+        assert_eq!(
+            origins.to_string(),
+            "INLINE_ORIGIN 0 foo(int)\nINLINE_ORIGIN 1 bar()\nINLINE_ORIGIN 2 baz()\n"
+        );
+
+        let inline = Inline {
+            depth: 0,
+            call_line: 13,
+            call_file: 1,
+            origin: 0,
+            ranges: Inline::get_ranges(vec![(0x1014, 4), (0x1004, 8), (0x100c, 4), (0x1020, 0)]),
+        };
+        assert_eq!(inline.to_string(), "INLINE 0 13 1 0 1004 c 1014 4\n");
+
+        let inlines = Inline::retain(&[inline], 0x1008, 0x10);
+        assert_eq!(inlines[0].ranges, vec![(0x1008, 8), (0x1014, 4)]);
+        assert!(Inline::retain(&inlines, 0x2000, 0x10).is_empty());
+    }
+}
@@ -0,0 +1,707 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --format json, the Breakpad records written for the output are converted into
+// a JSON document so the tools consuming the symbols don't need a .sym parser.
+// With --format ndjson, each record is converted into a JSON object (on its own line) as soon
+// as it's written, so a consumer can process the symbols of a huge module as a stream.
+// The files in a symbol store are still some .sym ones.
+// The document is the in-memory model of a module returned by the library (Dumper::symbols).
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+use crate::checksums;
+use crate::common;
+use crate::output::Converter;
+use crate::visitor::{self, Visitor};
+
+/// A MODULE record
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Module {
+    pub os: String,
+    pub cpu: String,
+    pub debug_id: String,
+    pub name: String,
+}
+
+/// An INFO record (the key is in lower case)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Info {
+    pub key: String,
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_file: Option<String>,
+}
+
+/// A FILE or an INLINE_ORIGIN record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct File {
+    pub id: u32,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+/// A line record of the last FUNC record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Line {
+    pub address: u64,
+    pub size: u64,
+    pub line: u32,
+    pub file: u32,
+}
+
+/// An address range of an INLINE record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Range {
+    pub address: u64,
+    pub size: u64,
+}
+
+/// An INLINE record of the last FUNC record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Inline {
+    pub depth: u32,
+    pub call_line: u32,
+    pub call_file: u32,
+    pub origin: u32,
+    pub ranges: Vec<Range>,
+}
+
+/// A FUNC record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Func {
+    pub address: u64,
+    pub size: u64,
+    pub parameter_size: u64,
+    pub multiple: bool,
+    pub name: String,
+}
+
+/// A FUNC record with its LINE and INLINE records
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Function {
+    #[serde(flatten)]
+    pub func: Func,
+    pub lines: Vec<Line>,
+    pub inlines: Vec<Inline>,
+}
+
+/// A PUBLIC record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Public {
+    pub address: u64,
+    pub parameter_size: u64,
+    pub multiple: bool,
+    pub name: String,
+}
+
+/// A STACK CFI record for the last STACK CFI INIT record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CfiDelta {
+    pub address: u64,
+    pub rules: String,
+}
+
+/// A STACK CFI INIT record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CfiInit {
+    pub address: u64,
+    pub size: u64,
+    pub rules: String,
+}
+
+/// A STACK CFI INIT record with its STACK CFI records
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Cfi {
+    #[serde(flatten)]
+    pub init: CfiInit,
+    pub deltas: Vec<CfiDelta>,
+}
+
+/// A STACK WIN record
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StackWin {
+    #[serde(rename = "type")]
+    pub typ: u32,
+    pub address: u64,
+    pub size: u64,
+    pub prolog_size: u32,
+    pub epilog_size: u32,
+    pub parameter_size: u32,
+    pub saved_register_size: u32,
+    pub local_size: u32,
+    pub max_stack_size: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allocates_base_pointer: Option<bool>,
+}
+
+/// A Breakpad record (the object written on a line with ndjson)
+#[derive(Debug, Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub(crate) enum Record {
+    Module(Module),
+    Info(Info),
+    File(File),
+    InlineOrigin(File),
+    Func(Func),
+    Line(Line),
+    Inline(Inline),
+    Public(Public),
+    StackCfiInit(CfiInit),
+    StackCfi(CfiDelta),
+    StackWin(StackWin),
+}
+
+/// The symbols of a module: the records are grouped by kind (in the order of the .sym file)
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Document {
+    pub module: Module,
+    /// The INFO records (key, value) in the order of the .sym file
+    pub info: Vec<(String, String)>,
+    pub files: Vec<File>,
+    pub inline_origins: Vec<File>,
+    pub functions: Vec<Function>,
+    pub publics: Vec<Public>,
+    pub stack_cfi: Vec<Cfi>,
+    pub stack_win: Vec<StackWin>,
+}
+
+fn hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+fn hex32(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+fn dec(s: &str) -> Option<u32> {
+    s.parse().ok()
+}
+
+/// Split the optional "m " (multiple) at the beginning of a FUNC or PUBLIC record
+fn split_multiple(rest: &str) -> (bool, &str) {
+    match rest.strip_prefix("m ") {
+        Some(rest) => (true, rest),
+        _ => (false, rest),
+    }
+}
+
+fn parse_record(record: &str, checksums: bool) -> Option<Record> {
+    let (kind, rest) = match record.find(' ') {
+        Some(pos) => (&record[..pos], &record[pos + 1..]),
+        _ => (record, ""),
+    };
+
+    let record = match kind {
+        "MODULE" => {
+            let mut it = rest.splitn(4, ' ');
+            Record::Module(Module {
+                os: it.next()?.to_string(),
+                cpu: it.next()?.to_string(),
+                debug_id: it.next()?.to_string(),
+                name: it.next().unwrap_or_default().to_string(),
+            })
+        }
+        "INFO" => {
+            let mut it = rest.splitn(2, ' ');
+            let key = it.next()?;
+            let value = it.next().unwrap_or_default();
+            if key == "CODE_ID" {
+                let mut it = value.splitn(2, ' ');
+                Record::Info(Info {
+                    key: "code_id".to_string(),
+                    value: it.next()?.to_string(),
+                    code_file: it.next().map(|file| file.to_string()),
+                })
+            } else {
+                Record::Info(Info {
+                    key: key.to_lowercase(),
+                    value: value.to_string(),
+                    code_file: None,
+                })
+            }
+        }
+        "FILE" | "INLINE_ORIGIN" => {
+            let mut it = rest.splitn(2, ' ');
+            let id = dec(it.next()?)?;
+            let name = it.next().unwrap_or_default();
+            let (name, checksum) = if kind == "FILE" && checksums {
+                checksums::split_file_name(name)
+            } else {
+                (name, None)
+            };
+            let file = File {
+                id,
+                name: name.to_string(),
+                checksum: checksum.map(|c| c.to_string()),
+            };
+            if kind == "FILE" {
+                Record::File(file)
+            } else {
+                Record::InlineOrigin(file)
+            }
+        }
+        "FUNC" => {
+            let (multiple, rest) = split_multiple(rest);
+            let mut it = rest.splitn(4, ' ');
+            Record::Func(Func {
+                address: hex(it.next()?)?,
+                size: hex(it.next()?)?,
+                parameter_size: hex(it.next()?)?,
+                multiple,
+                name: it.next().unwrap_or_default().to_string(),
+            })
+        }
+        "PUBLIC" => {
+            let (multiple, rest) = split_multiple(rest);
+            let mut it = rest.splitn(3, ' ');
+            Record::Public(Public {
+                address: hex(it.next()?)?,
+                parameter_size: hex(it.next()?)?,
+                multiple,
+                name: it.next().unwrap_or_default().to_string(),
+            })
+        }
+        "INLINE" => {
+            let mut it = rest.split(' ');
+            let mut inline = Inline {
+                depth: dec(it.next()?)?,
+                call_line: dec(it.next()?)?,
+                call_file: dec(it.next()?)?,
+                origin: dec(it.next()?)?,
+                ranges: Vec::new(),
+            };
+            while let Some(address) = it.next() {
+                inline.ranges.push(Range {
+                    address: hex(address)?,
+                    size: hex(it.next()?)?,
+                });
+            }
+            Record::Inline(inline)
+        }
+        "STACK" => {
+            let mut it = rest.splitn(2, ' ');
+            match it.next()? {
+                "CFI" => parse_cfi(it.next()?)?,
+                "WIN" => parse_win(it.next()?)?,
+                _ => return None,
+            }
+        }
+        _ => {
+            // A line record of the last function: address size line file
+            let mut it = record.split(' ');
+            Record::Line(Line {
+                address: hex(it.next()?)?,
+                size: hex(it.next()?)?,
+                line: dec(it.next()?)?,
+                file: dec(it.next()?)?,
+            })
+        }
+    };
+
+    Some(record)
+}
+
+fn parse_cfi(rest: &str) -> Option<Record> {
+    match rest.strip_prefix("INIT ") {
+        Some(rest) => {
+            let mut it = rest.splitn(3, ' ');
+            Some(Record::StackCfiInit(CfiInit {
+                address: hex(it.next()?)?,
+                size: hex(it.next()?)?,
+                rules: it.next().unwrap_or_default().to_string(),
+            }))
+        }
+        _ => {
+            let mut it = rest.splitn(2, ' ');
+            Some(Record::StackCfi(CfiDelta {
+                address: hex(it.next()?)?,
+                rules: it.next().unwrap_or_default().to_string(),
+            }))
+        }
+    }
+}
+
+fn parse_win(rest: &str) -> Option<Record> {
+    let mut it = rest.splitn(11, ' ');
+    let mut win = StackWin {
+        typ: hex32(it.next()?)?,
+        address: hex(it.next()?)?,
+        size: hex(it.next()?)?,
+        prolog_size: hex32(it.next()?)?,
+        epilog_size: hex32(it.next()?)?,
+        parameter_size: hex32(it.next()?)?,
+        saved_register_size: hex32(it.next()?)?,
+        local_size: hex32(it.next()?)?,
+        max_stack_size: hex32(it.next()?)?,
+        program: None,
+        allocates_base_pointer: None,
+    };
+    let has_program = it.next()? == "1";
+    let last = it.next().unwrap_or_default();
+    if has_program {
+        win.program = Some(last.to_string());
+    } else {
+        win.allocates_base_pointer = Some(last == "1");
+    }
+    Some(Record::StackWin(win))
+}
+
+/// Parse a line (without its newline): the empty ones are skipped and the checksums are split
+/// from the names of the files when they've been appended with --checksums
+pub(crate) fn parse_line(line: &[u8], checksums: bool) -> io::Result<Option<Record>> {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim_end_matches('\r');
+    if line.is_empty() {
+        return Ok(None);
+    }
+    parse_record(line, checksums).map(Some).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid Breakpad record: {}", line),
+        )
+    })
+}
+
+/// Call f on each complete line of buf (line has the beginning of a line split between two writes)
+pub(crate) fn for_each_line<F>(line: &mut Vec<u8>, buf: &[u8], mut f: F) -> io::Result<()>
+where
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    let mut start = 0;
+    for (i, _) in buf.iter().enumerate().filter(|(_, c)| **c == b'\n') {
+        if line.is_empty() {
+            f(&buf[start..i])?;
+        } else {
+            let mut full = std::mem::take(line);
+            full.extend_from_slice(&buf[start..i]);
+            f(&full)?;
+        }
+        start = i + 1;
+    }
+    line.extend_from_slice(&buf[start..]);
+    Ok(())
+}
+
+/// A visitor building the document of a module from its records
+#[derive(Debug, Default)]
+pub(crate) struct DocumentBuilder {
+    doc: Document,
+}
+
+impl DocumentBuilder {
+    pub(crate) fn finish(self) -> Document {
+        self.doc
+    }
+}
+
+impl Visitor for DocumentBuilder {
+    fn module(&mut self, module: &Module) {
+        self.doc.module = module.clone();
+    }
+
+    fn info(&mut self, info: &Info) {
+        let doc = &mut self.doc;
+        doc.info.push((info.key.clone(), info.value.clone()));
+        if let Some(file) = info.code_file.as_ref() {
+            doc.info.push(("code_file".to_string(), file.clone()));
+        }
+    }
+
+    fn file(&mut self, file: &File) {
+        self.doc.files.push(file.clone());
+    }
+
+    fn inline_origin(&mut self, origin: &File) {
+        self.doc.inline_origins.push(origin.clone());
+    }
+
+    fn func(&mut self, func: &Func) {
+        self.doc.functions.push(Function {
+            func: func.clone(),
+            lines: Vec::new(),
+            inlines: Vec::new(),
+        });
+    }
+
+    fn line(&mut self, line: &Line) {
+        if let Some(function) = self.doc.functions.last_mut() {
+            function.lines.push(line.clone());
+        }
+    }
+
+    fn inline(&mut self, inline: &Inline) {
+        if let Some(function) = self.doc.functions.last_mut() {
+            function.inlines.push(inline.clone());
+        }
+    }
+
+    fn public(&mut self, public: &Public) {
+        self.doc.publics.push(public.clone());
+    }
+
+    fn cfi_init(&mut self, init: &CfiInit) {
+        self.doc.stack_cfi.push(Cfi {
+            init: init.clone(),
+            deltas: Vec::new(),
+        });
+    }
+
+    fn cfi(&mut self, cfi: &CfiDelta) {
+        if let Some(init) = self.doc.stack_cfi.last_mut() {
+            init.deltas.push(cfi.clone());
+        }
+    }
+
+    fn stack_win(&mut self, win: &StackWin) {
+        self.doc.stack_win.push(win.clone());
+    }
+}
+
+/// A writer parsing the Breakpad records written in it: the JSON document is written at the end
+#[derive(Debug, Default)]
+pub(crate) struct JsonWriter {
+    line: Vec<u8>,
+    builder: DocumentBuilder,
+    checksums: bool,
+}
+
+impl JsonWriter {
+    pub(crate) fn new(checksums: bool) -> Self {
+        Self {
+            checksums,
+            ..Default::default()
+        }
+    }
+
+    fn add_record(&mut self, record: Record) -> Option<()> {
+        // The line and INLINE records follow a FUNC and the STACK CFI ones a STACK CFI INIT
+        let doc = &self.builder.doc;
+        match record {
+            Record::Line(_) | Record::Inline(_) if doc.functions.is_empty() => return None,
+            Record::StackCfi(_) if doc.stack_cfi.is_empty() => return None,
+            _ => visitor::visit_record(&mut self.builder, &record),
+        }
+        Some(())
+    }
+
+    fn parse_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if let Some(record) = parse_line(line, self.checksums)? {
+            self.add_record(record).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid Breakpad record: {}", String::from_utf8_lossy(line)),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Parse the last record when it hasn't a newline
+    fn parse_last_line(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.parse_line(&line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Converter for JsonWriter {
+    fn write_document(&mut self, output: &mut dyn Write) -> common::Result<()> {
+        self.parse_last_line()?;
+        serde_json::to_writer(&mut *output, &self.builder.doc)?;
+        writeln!(output)?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+impl Write for JsonWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut line = std::mem::take(&mut self.line);
+        let res = for_each_line(&mut line, buf, |l| self.parse_line(l));
+        self.line = line;
+        res?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer converting the Breakpad records written in it into some JSON objects (one per line)
+pub(crate) struct NdjsonWriter<W: Write> {
+    output: W,
+    line: Vec<u8>,
+    checksums: bool,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub(crate) fn new(output: W, checksums: bool) -> Self {
+        Self {
+            output,
+            line: Vec::new(),
+            checksums,
+        }
+    }
+
+    fn write_record(output: &mut W, line: &[u8], checksums: bool) -> io::Result<()> {
+        if let Some(record) = parse_line(line, checksums)? {
+            serde_json::to_writer(&mut *output, &record)?;
+            output.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Write the last record (if it doesn't end with a newline) and get the underlying writer
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            Self::write_record(&mut self.output, &line, self.checksums)?;
+        }
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> Write for NdjsonWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (output, checksums) = (&mut self.output, self.checksums);
+        for_each_line(&mut self.line, buf, |line| {
+            Self::write_record(output, line, checksums)
+        })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let sym = "MODULE windows x86_64 9FE7A5C7A0E9D7C94C4C44205044422E1 basic64.pdb\n\
+                   INFO CODE_ID 5F6E0B8E5000 basic64.dll\n\
+                   FILE 0 c:\\basic.cpp\n\
+                   INLINE_ORIGIN 0 inlined()\n\
+                   FUNC m 1000 20 0 main\n\
+                   1000 10 3 0\n\
+                   INLINE 0 4 0 0 1010 8\n\
+                   1010 10 4 0\n\
+                   PUBLIC 1030 4 _foo@4\n\
+                   STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa 8 - ^\n\
+                   STACK CFI 1004 .cfa: $rsp 16 +\n\
+                   STACK WIN 4 1030 8 1 0 4 0 0 0 1 $T0 .raSearch =\n\
+                   STACK WIN 4 1038 2 0 0 0 0 0 0 0 1\n";
+
+        // The records are split in several writes
+        let mut writer = JsonWriter::default();
+        for chunk in sym.as_bytes().chunks(7) {
+            writer.write_all(chunk).unwrap();
+        }
+        let mut output = Vec::new();
+        writer.write_document(&mut output).unwrap();
+        let doc: Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(doc["module"]["cpu"], "x86_64");
+        assert_eq!(doc["module"]["name"], "basic64.pdb");
+        assert_eq!(
+            doc["info"],
+            json!([["code_id", "5F6E0B8E5000"], ["code_file", "basic64.dll"]])
+        );
+        assert_eq!(doc["files"], json!([{"id": 0, "name": "c:\\basic.cpp"}]));
+        assert_eq!(
+            doc["functions"],
+            json!([{
+                "address": 4096,
+                "size": 32,
+                "parameter_size": 0,
+                "multiple": true,
+                "name": "main",
+                "lines": [
+                    {"address": 4096, "size": 16, "line": 3, "file": 0},
+                    {"address": 4112, "size": 16, "line": 4, "file": 0},
+                ],
+                "inlines": [{
+                    "depth": 0,
+                    "call_line": 4,
+                    "call_file": 0,
+                    "origin": 0,
+                    "ranges": [{"address": 4112, "size": 8}],
+                }],
+            }])
+        );
+        assert_eq!(doc["publics"][0]["name"], "_foo@4");
+        assert_eq!(doc["publics"][0]["parameter_size"], 4);
+        assert_eq!(
+            doc["stack_cfi"][0]["deltas"],
+            json!([{"address": 4100, "rules": ".cfa: $rsp 16 +"}])
+        );
+        assert_eq!(doc["stack_win"][0]["program"], "$T0 .raSearch =");
+        assert_eq!(doc["stack_win"][1]["allocates_base_pointer"], true);
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let sym = "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n\
+                   INFO CODE_ID B060AD20C6B47781552708AA192E7739FAC7C84A\n\
+                   FILE 0 /tmp/basic.cpp\n\
+                   FUNC 1000 20 0 main\n\
+                   1000 20 3 0\n\
+                   STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+                   STACK CFI 1001 .cfa: $rsp 16 +";
+
+        let mut writer = NdjsonWriter::new(Vec::new(), false);
+        for chunk in sym.as_bytes().chunks(7) {
+            writer.write_all(chunk).unwrap();
+        }
+        let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let records: Vec<Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 7);
+        assert_eq!(records[0]["record"], "module");
+        assert_eq!(
+            records[1],
+            json!({"record": "info", "key": "code_id", "value": "B060AD20C6B47781552708AA192E7739FAC7C84A"})
+        );
+        assert_eq!(
+            records[3],
+            json!({"record": "func", "address": 4096, "size": 32, "parameter_size": 0, "multiple": false, "name": "main"})
+        );
+        assert_eq!(
+            records[4],
+            json!({"record": "line", "address": 4096, "size": 32, "line": 3, "file": 0})
+        );
+        assert_eq!(records[5]["record"], "stack_cfi_init");
+        assert_eq!(
+            records[6],
+            json!({"record": "stack_cfi", "address": 4097, "rules": ".cfa: $rsp 16 +"})
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut writer = JsonWriter::default();
+        assert!(writer.write_all(b"FUNC zz 1 0 foo\n").is_err());
+        assert!(writer.write_all(b"1000 20 3 0\n").is_err());
+        let mut writer = NdjsonWriter::new(Vec::new(), false);
+        assert!(writer.write_all(b"FUNC zz 1 0 foo\n").is_err());
+    }
+}
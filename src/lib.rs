@@ -0,0 +1,82 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Dump the debug info of a file (ELF, Mach-O, PDB, PE, wasm, ...) to Breakpad symbols with a
+//! [`Dumper`].
+
+mod action;
+mod api;
+mod apk;
+mod archive;
+mod breakpad;
+mod cache;
+mod cache_management;
+mod cancel;
+mod check;
+mod checksums;
+#[doc(hidden)]
+pub mod cli;
+mod common;
+mod config;
+mod debug_records;
+mod demangling;
+mod diff;
+mod dotnet;
+mod dry_run;
+mod dumper;
+mod env_args;
+#[cfg(feature = "dump_syms_c")]
+mod ffi;
+mod folding;
+mod http_server;
+mod info;
+mod inlines;
+mod json;
+mod line;
+mod linux;
+mod mac;
+mod mapping;
+mod minidump;
+mod mock_server;
+mod options;
+mod output;
+mod output_template;
+mod parallel;
+mod pipeline;
+mod prefetch;
+mod publics;
+mod recurse;
+mod reload;
+mod serve;
+mod shutdown;
+mod sources;
+mod stats;
+mod supplier;
+mod symbols_zip;
+mod symcache;
+mod upload;
+mod utils;
+mod vcs;
+mod visitor;
+mod wasm;
+mod watch;
+mod windows;
+
+#[macro_use]
+extern crate lazy_static;
+
+pub use crate::api::Dumper;
+pub use crate::cache::{HttpOptions, RequestHook, SymbolRequest};
+pub use crate::cancel::{CancellationToken, Cancelled};
+pub use crate::common::{Error, Result};
+pub use crate::json::{Cfi as StackCfi, Document as Symbols, Function};
+pub use crate::pipeline::RemoteFile;
+#[cfg(feature = "network")]
+pub use crate::supplier::Debuginfod;
+pub use crate::supplier::{LocalStore, SymbolServers, SymbolSupplier};
+pub use crate::visitor::{
+    CfiInitRecord, CfiRecord, FileRecord, FuncRecord, InfoRecord, InlineRecord, LineRecord,
+    ModuleRecord, PublicRecord, RangeRecord, StackWinRecord, Visitor,
+};
@@ -138,4 +138,11 @@ impl Lines {
 
         Some(self.find_lines_for_range(rva, len))
     }
+
+    /// Set the new file ids (e.g. once the files are sorted)
+    pub(crate) fn remap_files(&mut self, remapping: &[u32]) {
+        for line in self.lines.iter_mut() {
+            line.file_id = remapping[line.file_id as usize];
+        }
+    }
 }
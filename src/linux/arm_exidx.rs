@@ -0,0 +1,388 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The 32-bit ARM binaries (e.g. the Android libraries) have often no DWARF CFI but only the
+// exception tables of the ARM EHABI: .ARM.exidx (PT_ARM_EXIDX) is sorted by address and each
+// entry is a function (prel31 offset) followed by either EXIDX_CANTUNWIND, some inline unwind
+// opcodes or the prel31 offset of its opcodes in .ARM.extab.
+// The opcodes (pop some registers, add to vsp, ...) are evaluated symbolically from vsp = sp
+// to get a STACK CFI INIT record for each function:
+//   a8 b0 b0 (pop {r4, lr}) => .cfa: sp 8 + .ra: .cfa -4 + ^ r4: .cfa -8 + ^
+
+use goblin::elf::{
+    header::EM_ARM,
+    program_header::{PF_X, PT_ARM_EXIDX, PT_LOAD},
+    section_header::SHT_NOBITS,
+    Elf,
+};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+const EXIDX_CANTUNWIND: u32 = 1;
+const COMPACT: u32 = 0x8000_0000;
+
+const SP: usize = 13;
+const LR: usize = 14;
+const PC: usize = 15;
+
+const REGISTERS: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr",
+    "pc",
+];
+
+/// The data of the file by address (the PT_LOAD segments)
+struct Memory<'a> {
+    segments: Vec<(u64, &'a [u8])>,
+    little_endian: bool,
+}
+
+impl Memory<'_> {
+    fn read_u32(&self, address: u64) -> Option<u32> {
+        let (start, data) = self
+            .segments
+            .iter()
+            .find(|(start, data)| *start <= address && address < start + data.len() as u64)?;
+        let offset = (address - start) as usize;
+        let bytes = data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+}
+
+/// Get the address pointed by a prel31 offset (relative to its own address)
+fn prel31(word: u32, address: u64) -> u64 {
+    let offset = ((word << 1) as i32 >> 1) as i64;
+    address.wrapping_add(offset as u64)
+}
+
+/// Get the unwind opcodes of an entry of .ARM.exidx: None when it can't be unwound
+fn get_opcodes(word: u32, address: u64, memory: &Memory) -> Option<Vec<u8>> {
+    if word == EXIDX_CANTUNWIND {
+        return None;
+    }
+    let (data, extab) = if word & COMPACT != 0 {
+        (word, None)
+    } else {
+        let extab = prel31(word, address);
+        (memory.read_u32(extab)?, Some(extab))
+    };
+
+    let (mut opcodes, count, extab) = if data & COMPACT != 0 {
+        match (data >> 24) & 0xf {
+            // Su16: three opcodes
+            0 => return Some(vec![(data >> 16) as u8, (data >> 8) as u8, data as u8]),
+            // Lu16 and Lu32: two opcodes and the number of words of opcodes after them
+            1 | 2 => (
+                vec![(data >> 8) as u8, data as u8],
+                (data >> 16) & 0xff,
+                extab,
+            ),
+            _ => return None,
+        }
+    } else {
+        // A generic personality routine (e.g. __gxx_personality_v0): the number of words
+        // and three opcodes follow it
+        let extab = extab? + 4;
+        let data = memory.read_u32(extab)?;
+        let opcodes = vec![(data >> 16) as u8, (data >> 8) as u8, data as u8];
+        (opcodes, data >> 24, Some(extab))
+    };
+
+    if count != 0 {
+        let extab = extab?;
+        for i in 1..=u64::from(count) {
+            opcodes.extend_from_slice(&memory.read_u32(extab + 4 * i)?.to_be_bytes());
+        }
+    }
+    Some(opcodes)
+}
+
+/// An address relative to an expression: expr offset +
+#[derive(Clone, Debug)]
+struct Address {
+    expr: String,
+    offset: i64,
+}
+
+impl Address {
+    fn deref(&self) -> String {
+        if self.offset == 0 {
+            format!("{} ^", self.expr)
+        } else {
+            format!("{} {} + ^", self.expr, self.offset)
+        }
+    }
+}
+
+/// Evaluate the opcodes: None when they're invalid or when they can't be translated
+fn get_rules(opcodes: &[u8]) -> Option<String> {
+    let mut vsp = Address {
+        expr: REGISTERS[SP].to_string(),
+        offset: 0,
+    };
+    let mut saved: BTreeMap<usize, Address> = BTreeMap::new();
+    let mut iter = opcodes.iter().copied();
+
+    // The registers are popped from the lowest one
+    let pop = |vsp: &mut Address, saved: &mut BTreeMap<usize, Address>, mask: u16, first: usize| {
+        let mut new_sp = None;
+        for i in 0..16 {
+            if mask & (1 << i) != 0 {
+                if first + i == SP {
+                    new_sp = Some(vsp.deref());
+                } else {
+                    saved.insert(first + i, vsp.clone());
+                }
+                vsp.offset += 4;
+            }
+        }
+        if let Some(expr) = new_sp {
+            *vsp = Address { expr, offset: 0 };
+        }
+    };
+
+    while let Some(op) = iter.next() {
+        match op {
+            0x00..=0x3f => vsp.offset += (i64::from(op & 0x3f) << 2) + 4,
+            0x40..=0x7f => vsp.offset -= (i64::from(op & 0x3f) << 2) + 4,
+            0x80..=0x8f => {
+                let mask = (u16::from(op & 0xf) << 8) | u16::from(iter.next()?);
+                if mask == 0 {
+                    // Refuse to unwind
+                    return None;
+                }
+                pop(&mut vsp, &mut saved, mask, 4);
+            }
+            0x90..=0x9f => {
+                let register = usize::from(op & 0xf);
+                if register == SP || register == PC {
+                    return None;
+                }
+                let expr = saved
+                    .get(&register)
+                    .map_or_else(|| REGISTERS[register].to_string(), Address::deref);
+                vsp = Address { expr, offset: 0 };
+            }
+            0xa0..=0xa7 => pop(&mut vsp, &mut saved, (2 << (op & 0x7)) - 1, 4),
+            0xa8..=0xaf => {
+                pop(&mut vsp, &mut saved, (2 << (op & 0x7)) - 1, 4);
+                pop(&mut vsp, &mut saved, 1, LR);
+            }
+            0xb0 => break,
+            0xb1 => {
+                let mask = iter.next()?;
+                if mask == 0 || mask & 0xf0 != 0 {
+                    return None;
+                }
+                pop(&mut vsp, &mut saved, u16::from(mask), 0);
+            }
+            0xb2 => {
+                let mut value = 0u64;
+                let mut shift = 0;
+                loop {
+                    let byte = iter.next()?;
+                    value |= u64::from(byte & 0x7f).checked_shl(shift)?;
+                    shift += 7;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                vsp.offset += 0x204 + (value << 2) as i64;
+            }
+            // The VFP registers (FSTMFDX adds a word)
+            0xb3 => vsp.offset += i64::from(iter.next()? & 0xf) * 8 + 12,
+            0xb8..=0xbf => vsp.offset += i64::from(op & 0x7) * 8 + 12,
+            // The iWMMXt registers
+            0xc0..=0xc5 => vsp.offset += i64::from(op & 0x7) * 8 + 8,
+            0xc6 => vsp.offset += i64::from(iter.next()? & 0xf) * 8 + 8,
+            0xc7 => {
+                let mask = iter.next()?;
+                if mask == 0 || mask & 0xf0 != 0 {
+                    return None;
+                }
+                vsp.offset += i64::from(mask.count_ones()) * 4;
+            }
+            // The VFP registers (FSTMFDD)
+            0xc8 | 0xc9 => vsp.offset += i64::from(iter.next()? & 0xf) * 8 + 8,
+            0xd0..=0xd7 => vsp.offset += i64::from(op & 0x7) * 8 + 8,
+            _ => return None,
+        }
+    }
+
+    let cfa = &vsp;
+    let get_rule = |address: &Address| {
+        if address.expr == cfa.expr {
+            format!(".cfa {} + ^", address.offset - cfa.offset)
+        } else {
+            address.deref()
+        }
+    };
+    // The return address is in the saved pc or else in the saved lr
+    let ra = saved
+        .get(&PC)
+        .or_else(|| saved.get(&LR))
+        .map_or_else(|| REGISTERS[LR].to_string(), get_rule);
+    let mut rules = format!(".cfa: {} {} + .ra: {}", cfa.expr, cfa.offset, ra);
+    for (register, address) in saved.iter() {
+        if *register == PC || (*register == LR && !saved.contains_key(&PC)) {
+            continue;
+        }
+        rules.push_str(&format!(" {}: {}", REGISTERS[*register], get_rule(address)));
+    }
+    Some(rules)
+}
+
+/// Get the STACK CFI INIT records of the entries of .ARM.exidx
+fn get_records(exidx: u64, count: u64, end: u64, memory: &Memory, load_address: u64) -> String {
+    let mut entries = Vec::new();
+    for i in 0..count {
+        let address = exidx + 8 * i;
+        match (memory.read_u32(address), memory.read_u32(address + 4)) {
+            (Some(function), Some(word)) => {
+                entries.push((prel31(function, address), word, address + 4));
+            }
+            _ => break,
+        }
+    }
+
+    let mut records = String::new();
+    for (i, (function, word, address)) in entries.iter().enumerate() {
+        // A function ends where the next one starts
+        let next = entries.get(i + 1).map_or(end, |e| e.0);
+        if next <= *function {
+            continue;
+        }
+        if let Some(rules) = get_opcodes(*word, *address, memory).and_then(|o| get_rules(&o)) {
+            records.push_str(&format!(
+                "STACK CFI INIT {:x} {:x} {}\n",
+                function.wrapping_sub(load_address),
+                next - function,
+                rules
+            ));
+        }
+    }
+    records
+}
+
+/// Get the STACK CFI records from the .ARM.exidx of a 32-bit ARM ELF file
+pub(super) fn get_stack_cfi(buf: &[u8], load_address: u64) -> String {
+    let elf = match Elf::parse(buf) {
+        Ok(elf) if elf.header.e_machine == EM_ARM => elf,
+        _ => return String::new(),
+    };
+    let exidx = match elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == PT_ARM_EXIDX)
+    {
+        Some(ph) => ph,
+        _ => return String::new(),
+    };
+    // The data aren't in a debug file
+    if elf
+        .section_headers
+        .iter()
+        .any(|sh| sh.sh_addr == exidx.p_vaddr && sh.sh_type == SHT_NOBITS)
+    {
+        return String::new();
+    }
+
+    let segments = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD)
+        .filter_map(|ph| {
+            let start = ph.p_offset as usize;
+            let data = buf.get(start..start.checked_add(ph.p_filesz as usize)?)?;
+            Some((ph.p_vaddr, data))
+        })
+        .collect();
+    let memory = Memory {
+        segments,
+        little_endian: elf.little_endian,
+    };
+    // The last function ends with the code
+    let end = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD && ph.p_flags & PF_X != 0)
+        .map(|ph| ph.p_vaddr + ph.p_memsz)
+        .max()
+        .unwrap_or_default();
+
+    get_records(exidx.p_vaddr, exidx.p_memsz / 8, end, &memory, load_address)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_exidx_rules() {
+        // pop {r4, lr}
+        assert_eq!(
+            get_rules(&[0xa8, 0xb0, 0xb0]).unwrap(),
+            ".cfa: sp 8 + .ra: .cfa -4 + ^ r4: .cfa -8 + ^"
+        );
+        // add sp, #16; pop {r4-r7, r11, lr}
+        assert_eq!(
+            get_rules(&[0x03, 0x84, 0x8f, 0xb0]).unwrap(),
+            ".cfa: sp 40 + .ra: .cfa -4 + ^ r4: .cfa -24 + ^ r5: .cfa -20 + ^ r6: .cfa -16 + ^ \
+             r7: .cfa -12 + ^ r11: .cfa -8 + ^"
+        );
+        // mov sp, r7; pop {r7, lr}
+        assert_eq!(
+            get_rules(&[0x97, 0x84, 0x08]).unwrap(),
+            ".cfa: r7 8 + .ra: .cfa -4 + ^ r7: .cfa -8 + ^"
+        );
+        // A leaf function
+        assert_eq!(
+            get_rules(&[0xb0, 0xb0, 0xb0]).unwrap(),
+            ".cfa: sp 0 + .ra: lr"
+        );
+        // Refuse to unwind and spare
+        assert!(get_rules(&[0x80, 0x00]).is_none());
+        assert!(get_rules(&[0xb4]).is_none());
+    }
+
+    #[test]
+    fn test_exidx_records() {
+        // Three functions at 0x1000, 0x1010 and 0x1040 and the exidx at 0x2000
+        let offset =
+            |target: u64, address: u64| (target.wrapping_sub(address) as u32) & 0x7fff_ffff;
+        let words = [
+            // Inline: pop {r4, lr}
+            offset(0x1000, 0x2000),
+            0x80a8_b0b0,
+            // In .ARM.extab with __gxx_personality_v0: pop {r4, r5, lr}
+            offset(0x1010, 0x2008),
+            offset(0x2020, 0x200c),
+            offset(0x1040, 0x2010),
+            EXIDX_CANTUNWIND,
+            0,
+            0,
+            // .ARM.extab: the personality, no word and three opcodes
+            offset(0x1800, 0x2020),
+            0x00a9_b0b0,
+        ];
+        let data: Vec<u8> = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        let memory = Memory {
+            segments: vec![(0x2000, &data[..])],
+            little_endian: true,
+        };
+        assert_eq!(
+            get_records(0x2000, 3, 0x1080, &memory, 0),
+            "STACK CFI INIT 1000 10 .cfa: sp 8 + .ra: .cfa -4 + ^ r4: .cfa -8 + ^\n\
+             STACK CFI INIT 1010 30 .cfa: sp 12 + .ra: .cfa -4 + ^ r4: .cfa -12 + ^ r5: .cfa -8 + ^\n"
+        );
+    }
+}
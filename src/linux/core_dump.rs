@@ -0,0 +1,241 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The NT_FILE note of an ELF core dump lists the files mapped in the process and,
+// when the first page of a mapped ELF file has been dumped (the default coredump_filter),
+// its build-id can be read from its notes.
+
+use goblin::container::{Container, Ctx};
+use goblin::elf::{
+    header::ET_CORE,
+    note::{NT_FILE, NT_GNU_BUILD_ID},
+    program_header::{PT_LOAD, PT_NOTE},
+    Elf, ProgramHeader,
+};
+use log::info;
+use std::convert::TryInto;
+
+use crate::common;
+
+/// A file mapped in the process
+#[derive(Debug, PartialEq)]
+pub(crate) struct CoreModule {
+    pub path: String,
+    pub build_id: Vec<u8>,
+    pub little_endian: bool,
+}
+
+pub(crate) fn is_core(buf: &[u8]) -> bool {
+    Elf::parse_header(buf).map_or(false, |h| h.e_type == ET_CORE)
+}
+
+fn read_word(data: &[u8], offset: usize, is_64: bool, little_endian: bool) -> Option<u64> {
+    if is_64 {
+        let bytes = data.get(offset..offset + 8)?.try_into().ok()?;
+        Some(if little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    } else {
+        let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(u64::from(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }))
+    }
+}
+
+/// Parse the NT_FILE note: the mappings (start, end, offset in pages) followed by the paths
+fn parse_nt_file(desc: &[u8], is_64: bool, little_endian: bool) -> Option<Vec<(u64, u64, String)>> {
+    let size = if is_64 { 8 } else { 4 };
+    let count = read_word(desc, 0, is_64, little_endian)? as usize;
+    let mut paths = desc.get(2 * size + 3 * size * count..)?.split(|c| *c == 0);
+
+    let mut mappings = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = 2 * size + 3 * size * i;
+        let start = read_word(desc, entry, is_64, little_endian)?;
+        let file_ofs = read_word(desc, entry + 2 * size, is_64, little_endian)?;
+        let path = String::from_utf8_lossy(paths.next()?).to_string();
+        mappings.push((start, file_ofs, path));
+    }
+
+    Some(mappings)
+}
+
+/// Get the build-id from the notes of an ELF file whose first pages are in data
+fn get_build_id(data: &[u8]) -> Option<Vec<u8>> {
+    let header = Elf::parse_header(data).ok()?;
+    let container = header.container().ok()?;
+    let endianness = header.endianness().ok()?;
+    let ctx = Ctx::new(container, endianness);
+    let phdrs =
+        ProgramHeader::parse(data, header.e_phoff as usize, header.e_phnum as usize, ctx).ok()?;
+    let little_endian = endianness.is_little();
+    let read_u32 = |offset: usize| read_word(data, offset, false, little_endian);
+
+    for phdr in phdrs.iter().filter(|p| p.p_type == PT_NOTE) {
+        // The offsets in the first page are the same in the file and in the memory
+        let mut offset = phdr.p_offset as usize;
+        let end = offset + phdr.p_filesz as usize;
+        while offset + 12 <= end {
+            let name_size = read_u32(offset)? as usize;
+            let desc_size = read_u32(offset + 4)? as usize;
+            let typ = read_u32(offset + 8)?;
+            let name = offset + 12;
+            let desc = name + ((name_size + 3) & !3);
+            if typ == u64::from(NT_GNU_BUILD_ID) && data.get(name..name + name_size)? == b"GNU\0" {
+                return data.get(desc..desc + desc_size).map(|id| id.to_vec());
+            }
+            offset = desc + ((desc_size + 3) & !3);
+        }
+    }
+
+    None
+}
+
+/// Get the ELF files mapped in the process with their build-id
+pub(crate) fn get_modules(buf: &[u8]) -> common::Result<Vec<CoreModule>> {
+    let elf = Elf::parse(buf)?;
+    if elf.header.e_type != ET_CORE {
+        return Err("Not a core dump".into());
+    }
+    let is_64 = elf.header.container()? == Container::Big;
+    let little_endian = elf.little_endian;
+
+    // The note iterator doesn't move on after an error so stop at the first one
+    let mappings = elf
+        .iter_note_headers(buf)
+        .and_then(|notes| {
+            notes
+                .take_while(|note| note.is_ok())
+                .filter_map(|note| note.ok())
+                .find(|note| note.n_type == NT_FILE && note.name == "CORE")
+        })
+        .and_then(|note| parse_nt_file(note.desc, is_64, little_endian))
+        .ok_or("No NT_FILE note in the core dump")?;
+
+    let mut modules: Vec<CoreModule> = Vec::new();
+    for (start, file_ofs, path) in mappings {
+        // The ELF header is in the mapping of the beginning of the file
+        if file_ofs != 0 || modules.iter().any(|m| m.path == path) {
+            continue;
+        }
+
+        let data = elf
+            .program_headers
+            .iter()
+            .find(|p| p.p_type == PT_LOAD && p.p_vaddr == start && p.p_filesz != 0)
+            .and_then(|p| buf.get(p.p_offset as usize..(p.p_offset + p.p_filesz) as usize));
+        match data.and_then(get_build_id) {
+            Some(build_id) => modules.push(CoreModule {
+                path,
+                build_id,
+                little_endian,
+            }),
+            _ => info!("No build-id for {} in the core dump", path),
+        }
+    }
+
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn push_note(buf: &mut Vec<u8>, name: &[u8], typ: u32, desc: &[u8]) {
+        buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&typ.to_le_bytes());
+        buf.extend_from_slice(name);
+        buf.resize((buf.len() + 3) & !3, 0);
+        buf.extend_from_slice(desc);
+        buf.resize((buf.len() + 3) & !3, 0);
+    }
+
+    fn push_phdr(buf: &mut Vec<u8>, typ: u32, offset: u64, vaddr: u64, size: u64, align: u64) {
+        buf.extend_from_slice(&typ.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&offset.to_le_bytes());
+        buf.extend_from_slice(&vaddr.to_le_bytes());
+        buf.extend_from_slice(&0u64.to_le_bytes());
+        buf.extend_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(&align.to_le_bytes());
+    }
+
+    /// A core dump with basic.full mapped at 0x1000 (and its first page) and a data file at 0x10000
+    fn build_core(page: &[u8]) -> Vec<u8> {
+        let mut nt_file = Vec::new();
+        for word in &[
+            3u64, 0x1000, 0x1000, 0x2000, 0, 0x2000, 0x3000, 1, 0x10000, 0x11000, 0,
+        ] {
+            nt_file.extend_from_slice(&word.to_le_bytes());
+        }
+        nt_file.extend_from_slice(b"/usr/bin/basic\0/usr/bin/basic\0/usr/share/data.bin\0");
+        let mut notes = Vec::new();
+        push_note(&mut notes, b"CORE\0", NT_FILE, &nt_file);
+
+        // The ELF header (64 bytes) and 2 program headers (56 bytes each)
+        let notes_offset = 64 + 2 * 56;
+        let page_offset = notes_offset + notes.len();
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"\x7fELF\x02\x01\x01");
+        buf.resize(16, 0);
+        buf.extend_from_slice(&ET_CORE.to_le_bytes());
+        buf.extend_from_slice(&0x3eu16.to_le_bytes());
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend_from_slice(&0u64.to_le_bytes());
+        buf.extend_from_slice(&64u64.to_le_bytes());
+        buf.extend_from_slice(&0u64.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        for half in &[64u16, 56, 2, 64, 0, 0] {
+            buf.extend_from_slice(&half.to_le_bytes());
+        }
+        push_phdr(
+            &mut buf,
+            PT_NOTE,
+            notes_offset as u64,
+            0,
+            notes.len() as u64,
+            4,
+        );
+        push_phdr(
+            &mut buf,
+            PT_LOAD,
+            page_offset as u64,
+            0x1000,
+            page.len() as u64,
+            0x1000,
+        );
+        buf.extend_from_slice(&notes);
+        buf.extend_from_slice(page);
+
+        buf
+    }
+
+    #[test]
+    fn test_core_modules() {
+        let basic = std::fs::read("./test_data/linux/basic.full").unwrap();
+        let build_id = get_build_id(&basic).unwrap();
+        let core = build_core(&basic[..0x1000]);
+        assert!(is_core(&core));
+        assert!(!is_core(&basic));
+
+        assert_eq!(
+            get_modules(&core).unwrap(),
+            vec![CoreModule {
+                path: "/usr/bin/basic".to_string(),
+                build_id,
+                little_endian: true,
+            }]
+        );
+    }
+}
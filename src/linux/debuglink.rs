@@ -0,0 +1,366 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The binaries in the Linux distributions are stripped and their debug info are in some
+// separate files referenced with a .gnu_debuglink section (a file name and a crc)
+// or stored under /usr/lib/debug/.build-id/ with a path made from their build-id.
+// These debug files are often processed with dwz which moves the DWARF data shared by several
+// files in a supplementary file referenced with a .gnu_debugaltlink section (a path and a build-id).
+
+use goblin::elf::{note::NT_GNU_BUILD_ID, Elf};
+use log::{info, warn};
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::decompression;
+use super::dwarf::{collect_units, get_section, is_compile_unit, load_dwarf, References};
+use super::elf::Collector;
+use super::source::SourceFiles;
+use crate::cancel;
+use crate::common;
+
+pub(crate) const DEBUG_DIR: &str = "/usr/lib/debug";
+
+/// Get the directories where to look for the debug files: the given ones and then /usr/lib/debug
+pub(crate) fn get_debug_dirs(dirs: Option<Vec<&str>>) -> common::Result<Vec<PathBuf>> {
+    let mut debug_dirs = Vec::new();
+    for dir in dirs.unwrap_or_default() {
+        let dir = PathBuf::from(dir);
+        if !dir.is_dir() {
+            return Err(format!("The debug directory {} doesn't exist", dir.display()).into());
+        }
+        debug_dirs.push(dir);
+    }
+    debug_dirs.push(PathBuf::from(DEBUG_DIR));
+
+    Ok(debug_dirs)
+}
+
+/// The path of a debug file in a .build-id directory: .build-id/xx/rest.debug
+fn get_build_id_path(dir: &Path, build_id: &[u8]) -> Option<PathBuf> {
+    if build_id.len() < 2 {
+        return None;
+    }
+
+    let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+    Some(
+        dir.join(".build-id")
+            .join(&hex[..2])
+            .join(format!("{}.debug", &hex[2..])),
+    )
+}
+
+/// Read a file and check that its build-id is the expected one
+fn read_with_build_id(path: &Path, build_id: &[u8]) -> Option<Vec<u8>> {
+    let data = fs::read(path).ok()?;
+    let id = Elf::parse(&data)
+        .ok()
+        .and_then(|elf| get_build_id(&elf, &data).map(|id| id.to_vec()));
+    if id.as_deref() == Some(build_id) {
+        Some(decompression::decompress_file(data))
+    } else {
+        warn!(
+            "The debug file {} doesn't match the build-id {}",
+            path.display(),
+            build_id
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        );
+        None
+    }
+}
+
+/// Get the file name and the crc in the .gnu_debuglink section
+fn get_debuglink(elf: &Elf, buf: &[u8]) -> Option<(String, u32)> {
+    let data = get_section(elf, buf, ".gnu_debuglink");
+    let len = data.iter().position(|c| *c == 0)?;
+    let name = std::str::from_utf8(&data[..len]).ok()?.to_string();
+
+    // The crc is 4-byte aligned
+    let pos = (len + 4) & !3;
+    let crc = data.get(pos..pos + 4)?.try_into().ok()?;
+    let crc = if elf.little_endian {
+        u32::from_le_bytes(crc)
+    } else {
+        u32::from_be_bytes(crc)
+    };
+
+    Some((name, crc))
+}
+
+/// Get the path and the build-id in the .gnu_debugaltlink section
+fn get_debugaltlink<'a>(elf: &Elf, buf: &'a [u8]) -> Option<(String, &'a [u8])> {
+    let data = get_section(elf, buf, ".gnu_debugaltlink");
+    let len = data.iter().position(|c| *c == 0)?;
+    let name = std::str::from_utf8(&data[..len]).ok()?.to_string();
+
+    Some((name, &data[len + 1..]))
+}
+
+pub(crate) fn get_build_id<'a>(elf: &Elf<'a>, buf: &'a [u8]) -> Option<&'a [u8]> {
+    elf.iter_note_sections(buf, Some(".note.gnu.build-id"))
+        .and_then(|mut notes| {
+            notes.find_map(|note| note.ok().filter(|n| n.n_type == NT_GNU_BUILD_ID))
+        })
+        .map(|note| note.desc)
+}
+
+/// Find the debug file of a stripped binary.
+/// Like gdb, the file is first searched with the build-id in the .build-id directory
+/// of the debug directories and then with the name in the .gnu_debuglink section:
+/// next to the binary, in the .debug subdirectory and in the debug directories.
+pub(super) fn find_debug_file(buf: &[u8], path: &Path, debug_dirs: &[PathBuf]) -> Option<PathBuf> {
+    let elf = Elf::parse(buf).ok()?;
+    let same_file =
+        |candidate: &Path| fs::canonicalize(candidate).ok() == fs::canonicalize(path).ok();
+
+    if let Some(build_id) = get_build_id(&elf, buf) {
+        for dir in debug_dirs {
+            let candidate = match get_build_id_path(dir, build_id) {
+                Some(candidate) => candidate,
+                _ => break,
+            };
+            if candidate.is_file()
+                && !same_file(&candidate)
+                && read_with_build_id(&candidate, build_id).is_some()
+            {
+                info!("Found the debug file {}", candidate.display());
+                return Some(candidate);
+            }
+        }
+    }
+
+    let (name, crc) = get_debuglink(&elf, buf)?;
+
+    let dir = fs::canonicalize(path)
+        .ok()
+        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+        .unwrap_or_else(|| path.parent().map(|p| p.to_path_buf()).unwrap_or_default());
+    let mut candidates = vec![dir.join(&name), dir.join(".debug").join(&name)];
+    if let Ok(rel_dir) = dir.strip_prefix("/") {
+        for debug_dir in debug_dirs {
+            candidates.push(debug_dir.join(rel_dir).join(&name));
+        }
+    }
+
+    for candidate in candidates {
+        if !candidate.is_file() || same_file(&candidate) {
+            continue;
+        }
+        let data = match fs::read(&candidate) {
+            Ok(data) => data,
+            _ => continue,
+        };
+        if crc32fast::hash(&data) == crc {
+            info!("Found the debug file {}", candidate.display());
+            return Some(candidate);
+        }
+        warn!(
+            "The debug file {} doesn't match the crc in .gnu_debuglink",
+            candidate.display()
+        );
+    }
+
+    warn!("The debug file {} can't be found", name);
+
+    None
+}
+
+/// Find the supplementary file referenced in the .gnu_debugaltlink section of a debug file
+/// and check its build-id
+fn find_alt_file(
+    name: &str,
+    build_id: &[u8],
+    path: &Path,
+    debug_dirs: &[PathBuf],
+) -> Option<(PathBuf, Vec<u8>)> {
+    let mut candidates = Vec::new();
+    let alt = Path::new(name);
+    if alt.is_absolute() {
+        candidates.push(alt.to_path_buf());
+    } else if let Some(dir) = path.parent() {
+        candidates.push(dir.join(alt));
+    }
+    if let Some(file_name) = alt.file_name() {
+        for dir in debug_dirs.iter() {
+            candidates.push(dir.join(".dwz").join(file_name));
+        }
+    }
+    candidates.extend(
+        debug_dirs
+            .iter()
+            .filter_map(|dir| get_build_id_path(dir, build_id)),
+    );
+
+    for candidate in candidates {
+        if !candidate.is_file() {
+            continue;
+        }
+        if let Some(data) = read_with_build_id(&candidate, build_id) {
+            info!("Found the supplementary debug file {}", candidate.display());
+            return Some((candidate, data));
+        }
+    }
+
+    None
+}
+
+/// Collect the functions of a debug file processed with dwz: symbolic can't read the data
+/// in the supplementary file so the units are read here.
+/// Return false when there is no supplementary file.
+pub(super) fn collect_dwz_functions(
+    buf: &[u8],
+    path: &Path,
+    load_address: u64,
+    collector: &mut Collector,
+    source: &mut SourceFiles,
+) -> common::Result<bool> {
+    let elf = match Elf::parse(buf) {
+        Ok(elf) => elf,
+        _ => return Ok(false),
+    };
+    let (name, build_id) = match get_debugaltlink(&elf, buf) {
+        Some(altlink) => altlink,
+        _ => return Ok(false),
+    };
+    let debug_dirs = &collector.options.debug_dirs;
+    let (_, alt_buf) = match find_alt_file(&name, build_id, path, debug_dirs) {
+        Some(alt) => alt,
+        _ => {
+            warn!(
+                "The supplementary debug file {} can't be found: some names may be missing",
+                name
+            );
+            return Ok(false);
+        }
+    };
+    let alt_elf = Elf::parse(&alt_buf)?;
+
+    let main = load_dwarf(&elf, buf, Some((&alt_elf, &alt_buf)))?;
+    let alt = load_dwarf(&alt_elf, &alt_buf, None)?;
+    let references = References::new(&main, Some(&alt))?;
+
+    let mut compile_units = Vec::new();
+    let mut units = main.units();
+    while let Some(header) = units.next()? {
+        cancel::check()?;
+        let unit = main.unit(header)?;
+        if is_compile_unit(&unit) {
+            compile_units.push(unit);
+        }
+    }
+
+    collect_units(
+        &main,
+        &compile_units,
+        &references,
+        load_address,
+        collector,
+        source,
+    )?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::common::Dumpable;
+    use crate::linux::elf::{ElfInfo, Platform};
+
+    #[test]
+    fn test_find_debug_file() {
+        let stripped = PathBuf::from("./test_data/linux/basic_debuglink.stripped");
+        let buf = fs::read(&stripped).unwrap();
+        let debug_dirs = get_debug_dirs(None).unwrap();
+        let debug = find_debug_file(&buf, &stripped, &debug_dirs).unwrap();
+
+        assert_eq!(debug.file_name().unwrap(), "basic_debuglink.debug");
+
+        // The crc of basic.dbg doesn't match
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("debuglink")
+            .tempdir()
+            .unwrap();
+        let tmp_stripped = tmp_dir.path().join("basic_debuglink.stripped");
+        fs::copy(&stripped, &tmp_stripped).unwrap();
+        fs::copy(
+            "./test_data/linux/basic.dbg",
+            tmp_dir.path().join("basic_debuglink.debug"),
+        )
+        .unwrap();
+
+        assert!(find_debug_file(&buf, &tmp_stripped, &debug_dirs).is_none());
+    }
+
+    #[test]
+    fn test_find_debug_file_by_build_id() {
+        // basic.stripped has no .gnu_debuglink but it has the same build-id as basic.dbg
+        let stripped = PathBuf::from("./test_data/linux/basic.stripped");
+        let buf = fs::read(&stripped).unwrap();
+        let elf = Elf::parse(&buf).unwrap();
+        assert!(get_debuglink(&elf, &buf).is_none());
+
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("build_id")
+            .tempdir()
+            .unwrap();
+        let debug_dirs = vec![tmp_dir.path().to_path_buf()];
+        let debug = get_build_id_path(tmp_dir.path(), get_build_id(&elf, &buf).unwrap()).unwrap();
+        fs::create_dir_all(debug.parent().unwrap()).unwrap();
+
+        // The build-id of basic_debuglink.debug doesn't match
+        fs::copy("./test_data/linux/basic_debuglink.debug", &debug).unwrap();
+        assert!(find_debug_file(&buf, &stripped, &debug_dirs).is_none());
+
+        fs::copy("./test_data/linux/basic.dbg", &debug).unwrap();
+        assert_eq!(
+            find_debug_file(&buf, &stripped, &debug_dirs).unwrap(),
+            debug
+        );
+    }
+
+    #[test]
+    fn test_debugaltlink() {
+        // The supplementary file is basic_debuglink.debug
+        let path = PathBuf::from("./test_data/linux/basic_debugaltlink.debug");
+        let buf = fs::read(&path).unwrap();
+        let elf = Elf::parse(&buf).unwrap();
+        let (name, build_id) = get_debugaltlink(&elf, &buf).unwrap();
+
+        assert_eq!(name, "basic_debuglink.debug");
+        let debug_dirs = get_debug_dirs(None).unwrap();
+        assert!(find_alt_file(&name, build_id, &path, &debug_dirs).is_some());
+        assert!(find_alt_file(&name, &build_id[1..], &path, &debug_dirs).is_none());
+
+        let info =
+            ElfInfo::new(&buf, &path, "basic", Platform::Linux, None, Arc::default()).unwrap();
+        let mut output = Vec::new();
+        info.dump(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let funcs: Vec<_> = output
+            .lines()
+            .filter(|l| l.starts_with("FUNC "))
+            .map(|l| l.splitn(5, ' ').last().unwrap())
+            .collect();
+
+        assert_eq!(
+            funcs,
+            vec![
+                "inline_1(int)",
+                "inline_2(int)",
+                "inline_3(int)",
+                "inline_4(int)",
+                "foo(int)",
+                "main"
+            ]
+        );
+    }
+}
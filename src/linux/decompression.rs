@@ -0,0 +1,205 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The debug sections can be compressed (e.g. objcopy --compress-debug-sections or ld
+// --compress-debug-sections):
+//   - SHF_COMPRESSED: the data starts with an Elf_Chdr giving the algorithm (zlib or zstd)
+//     and the size of the uncompressed data;
+//   - zlib-gnu: the sections are named .zdebug_* and their data starts with "ZLIB" and the
+//     size (u64 big-endian).
+// The sections are decompressed at the end of a copy of the file and their headers are
+// patched to point on the uncompressed data, so all the readers (symbolic, gimli) see
+// some plain debug sections.
+
+use flate2::read::ZlibDecoder;
+use goblin::{
+    container::{Container, Ctx},
+    elf::{
+        compression_header::{CompressionHeader, ELFCOMPRESS_ZLIB},
+        section_header::{SHF_COMPRESSED, SHT_NOBITS},
+        Elf,
+    },
+};
+use log::{info, warn};
+use std::convert::TryInto;
+use std::io::Read;
+
+use crate::common;
+
+// Not in goblin yet
+const ELFCOMPRESS_ZSTD: u32 = 2;
+
+const ZLIB_GNU_MAGIC: &[u8] = b"ZLIB";
+
+fn inflate(data: &[u8], size: usize) -> common::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(size);
+    ZlibDecoder::new(data).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+fn decompress(data: &[u8], ctx: Ctx) -> common::Result<Vec<u8>> {
+    let chdr = CompressionHeader::parse(data, 0, ctx)?;
+    let data = data.get(CompressionHeader::size(ctx)..).unwrap_or_default();
+    let output = match chdr.ch_type {
+        ELFCOMPRESS_ZLIB => inflate(data, chdr.ch_size as usize)?,
+        ELFCOMPRESS_ZSTD => zstd::stream::decode_all(data)?,
+        _ => return Err(format!("unknown compression type {}", chdr.ch_type).into()),
+    };
+    if output.len() as u64 != chdr.ch_size {
+        return Err("invalid size of the uncompressed data".into());
+    }
+    Ok(output)
+}
+
+fn decompress_gnu(data: &[u8]) -> common::Result<Vec<u8>> {
+    let size = data
+        .get(4..12)
+        .and_then(|size| size.try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or("invalid header")?;
+    let output = inflate(&data[12..], size as usize)?;
+    if output.len() as u64 != size {
+        return Err("invalid size of the uncompressed data".into());
+    }
+    Ok(output)
+}
+
+fn write(buf: &mut [u8], offset: usize, size: usize, value: u64, little_endian: bool) {
+    let data = &mut buf[offset..offset + size];
+    if little_endian {
+        data.copy_from_slice(&value.to_le_bytes()[..size]);
+    } else {
+        data.copy_from_slice(&value.to_be_bytes()[8 - size..]);
+    }
+}
+
+/// Get a copy of the file with its compressed sections decompressed:
+/// None when there is nothing to decompress.
+pub(super) fn decompress_sections(buf: &[u8]) -> Option<Vec<u8>> {
+    let elf = Elf::parse(buf).ok()?;
+    let container = elf.header.container().ok()?;
+    let ctx = Ctx::new(container, elf.header.endianness().ok()?);
+    let is_64 = container == Container::Big;
+    let little_endian = elf.little_endian;
+    let shstrtab = elf.section_headers.get(elf.header.e_shstrndx as usize)?;
+
+    let mut output: Option<Vec<u8>> = None;
+    for (i, sh) in elf.section_headers.iter().enumerate() {
+        if sh.sh_type == SHT_NOBITS {
+            continue;
+        }
+        let name = elf.shdr_strtab.get(sh.sh_name).and_then(|n| n.ok());
+        let name = match name {
+            Some(name) => name,
+            _ => continue,
+        };
+        let is_compressed = sh.sh_flags & u64::from(SHF_COMPRESSED) != 0;
+        let is_gnu = name.starts_with(".zdebug_");
+        if !is_compressed && !is_gnu {
+            continue;
+        }
+
+        let start = sh.sh_offset as usize;
+        let end = start.saturating_add(sh.sh_size as usize);
+        let data = buf.get(start..end).unwrap_or_default();
+        let res = if is_compressed {
+            decompress(data, ctx)
+        } else if data.starts_with(ZLIB_GNU_MAGIC) {
+            decompress_gnu(data)
+        } else {
+            // A .zdebug section which is too small to be compressed
+            continue;
+        };
+        let data = match res {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Cannot decompress the section {}: {}", name, e);
+                continue;
+            }
+        };
+        info!("Decompress the section {}", name);
+
+        let output = output.get_or_insert_with(|| buf.to_vec());
+        let offset = (output.len() + 7) & !7;
+        output.resize(offset, 0);
+        output.extend_from_slice(&data);
+
+        // sh_flags, sh_offset and sh_size
+        let sh_offset = elf.header.e_shoff as usize + i * elf.header.e_shentsize as usize;
+        let (word, fields) = if is_64 {
+            (8, [8, 24, 32])
+        } else {
+            (4, [8, 16, 20])
+        };
+        let flags = sh.sh_flags & !u64::from(SHF_COMPRESSED);
+        let values = [flags, offset as u64, data.len() as u64];
+        for (field, value) in fields.iter().zip(values.iter()) {
+            write(output, sh_offset + field, word, *value, little_endian);
+        }
+
+        if is_gnu {
+            // .zdebug_foo\0 => .debug_foo\0\0
+            let name_offset = shstrtab.sh_offset as usize + sh.sh_name;
+            let name = &mut output[name_offset + 1..name_offset + name.len()];
+            name.rotate_left(1);
+            name[name.len() - 1] = 0;
+        }
+    }
+
+    output
+}
+
+/// Decompress the compressed sections of a file read from the disk (if any)
+pub(super) fn decompress_file(buf: Vec<u8>) -> Vec<u8> {
+    decompress_sections(&buf).unwrap_or(buf)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn get_sections(buf: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let elf = Elf::parse(buf).unwrap();
+        elf.section_headers
+            .iter()
+            .filter(|sh| sh.sh_type != SHT_NOBITS)
+            .map(|sh| {
+                let name = elf.shdr_strtab.get(sh.sh_name).unwrap().unwrap();
+                let start = sh.sh_offset as usize;
+                let data = buf[start..start + sh.sh_size as usize].to_vec();
+                (name.to_string(), data)
+            })
+            .filter(|(name, _)| name.starts_with(".debug_"))
+            .collect()
+    }
+
+    #[test]
+    fn test_decompress_zlib() {
+        let basic_dir = PathBuf::from("./test_data/linux");
+        let buf = fs::read(basic_dir.join("basic.dbg")).unwrap();
+        assert!(decompress_sections(&buf).is_none());
+        let expected = get_sections(&buf);
+        assert!(!expected.is_empty());
+
+        for name in &["basic_zlib.dbg", "basic_zlib_gnu.dbg"] {
+            let buf = fs::read(basic_dir.join(name)).unwrap();
+            let buf = decompress_sections(&buf).unwrap();
+            assert_eq!(get_sections(&buf), expected);
+        }
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        let basic_dir = PathBuf::from("./test_data/linux");
+        let expected = get_sections(&fs::read(basic_dir.join("basic.dbg")).unwrap());
+
+        let buf = fs::read(basic_dir.join("basic_zstd.dbg")).unwrap();
+        let buf = decompress_sections(&buf).unwrap();
+        assert_eq!(get_sections(&buf), expected);
+    }
+}
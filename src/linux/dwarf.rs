@@ -0,0 +1,686 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Some DWARF layouts can't be read through symbolic (split units, dwz supplementary files, ...),
+// so here the functions are built from the DIEs and the line table of a unit with gimli
+// and then they're collected as the other ones.
+// symbolic doesn't load .debug_addr either, so the functions of the DWARF 5 units with their
+// addresses in it (DW_FORM_addrx, e.g. with clang) are read here too: the strings
+// (DW_FORM_strx, DW_FORM_line_strp), the range lists and the v5 line tables are read by gimli.
+
+use gimli::{
+    constants, AttributeValue, DebugInfoOffset, DebuggingInformationEntry, Dwarf, EndianSlice,
+    RunTimeEndian, Unit, UnitHeader, UnitType,
+};
+use goblin::elf::{
+    section_header::{SHF_COMPRESSED, SHT_NOBITS},
+    Elf,
+};
+use hashbrown::HashMap;
+use log::warn;
+use symbolic::common::{Language, Name, NameMangling};
+use symbolic::debuginfo::{FileInfo, Function, LineInfo};
+
+use super::elf::Collector;
+use super::source::SourceFiles;
+use crate::cancel;
+use crate::checksums::{Checksum, ChecksumKind};
+use crate::common;
+use crate::parallel;
+
+pub(super) type Slice<'a> = EndianSlice<'a, RunTimeEndian>;
+
+pub(super) fn get_section<'a>(elf: &Elf, buf: &'a [u8], name: &str) -> &'a [u8] {
+    for sh in elf.section_headers.iter() {
+        let sh_name = elf.shdr_strtab.get(sh.sh_name).and_then(|n| n.ok());
+        if sh_name != Some(name) || sh.sh_type == SHT_NOBITS {
+            continue;
+        }
+        if sh.sh_flags & u64::from(SHF_COMPRESSED) != 0 {
+            warn!("The compressed section {} isn't supported", name);
+            return &[];
+        }
+
+        let start = sh.sh_offset as usize;
+        let end = start.saturating_add(sh.sh_size as usize);
+        return buf.get(start..end).unwrap_or(&[]);
+    }
+
+    &[]
+}
+
+pub(super) fn get_endian(elf: &Elf) -> RunTimeEndian {
+    if elf.little_endian {
+        RunTimeEndian::Little
+    } else {
+        RunTimeEndian::Big
+    }
+}
+
+/// Load the DWARF sections of an ELF file: the supplementary sections (if any) are the ones
+/// of the file shared by several debug files (created by dwz).
+pub(super) fn load_dwarf<'a>(
+    elf: &Elf,
+    buf: &'a [u8],
+    sup: Option<(&Elf, &'a [u8])>,
+) -> gimli::Result<Dwarf<Slice<'a>>> {
+    let endian = get_endian(elf);
+    Dwarf::load(
+        |id| -> gimli::Result<Slice<'a>> {
+            Ok(EndianSlice::new(get_section(elf, buf, id.name()), endian))
+        },
+        |id| -> gimli::Result<Slice<'a>> {
+            let data = sup.map_or(&[][..], |(elf, buf)| get_section(elf, buf, id.name()));
+            Ok(EndianSlice::new(data, endian))
+        },
+    )
+}
+
+fn get_file_info<'a>(
+    dwarf: &Dwarf<Slice<'a>>,
+    unit: &Unit<Slice<'a>>,
+    header: &gimli::LineProgramHeader<Slice<'a>>,
+    file: &gimli::FileEntry<Slice<'a>>,
+) -> FileInfo<'a> {
+    let name = dwarf
+        .attr_string(unit, file.path_name())
+        .map_or(&[][..], |s| s.slice());
+    let dir = file
+        .directory(header)
+        .and_then(|dir| dwarf.attr_string(unit, dir).ok())
+        .map_or(&[][..], |s| s.slice());
+    FileInfo { name, dir }
+}
+
+/// Set the checksums of the files in the line tables (MD5 in DWARF 5): it must be done
+/// before the functions are collected
+pub(super) fn collect_checksums(buf: &[u8], source: &mut SourceFiles) -> gimli::Result<()> {
+    let elf = match Elf::parse(buf) {
+        Ok(elf) => elf,
+        _ => return Ok(()),
+    };
+    let dwarf = load_dwarf(&elf, buf, None)?;
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let header = match unit.line_program.as_ref() {
+            Some(program) if program.header().file_has_md5() => program.header(),
+            _ => continue,
+        };
+        let comp_dir = unit.comp_dir.map_or(&[][..], |d| d.slice());
+        for file in header.file_names() {
+            if let Some(checksum) = Checksum::new(ChecksumKind::Md5, file.md5()) {
+                let file = get_file_info(&dwarf, &unit, header, file);
+                source.add_checksum(comp_dir, &file, checksum);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct Row<'a> {
+    address: u64,
+    size: u64,
+    file: FileInfo<'a>,
+    line: u64,
+}
+
+/// The rows of the line table of a unit
+pub(super) struct LineTable<'a> {
+    rows: Vec<Row<'a>>,
+    files: HashMap<u64, FileInfo<'a>>,
+}
+
+impl<'a> LineTable<'a> {
+    pub(super) fn new(dwarf: &Dwarf<Slice<'a>>, unit: &Unit<Slice<'a>>) -> gimli::Result<Self> {
+        let mut table = Self {
+            rows: Vec::new(),
+            files: HashMap::default(),
+        };
+
+        let program = match unit.line_program.clone() {
+            Some(program) => program,
+            _ => return Ok(table),
+        };
+
+        let mut rows = program.rows();
+        let mut prev: Option<(u64, u64, u64)> = None;
+        while let Some((header, row)) = rows.next_row()? {
+            if let Some((address, file_index, line)) = prev {
+                if row.address() > address {
+                    let file = table.get_file(dwarf, unit, header, file_index);
+                    table.rows.push(Row {
+                        address,
+                        size: row.address() - address,
+                        file,
+                        line,
+                    });
+                }
+            }
+
+            prev = if row.end_sequence() {
+                None
+            } else {
+                Some((row.address(), row.file_index(), row.line().unwrap_or(0)))
+            };
+        }
+
+        // Get the files for the call locations too
+        if let Some(program) = unit.line_program.as_ref() {
+            let header = program.header();
+            let n_files = header.file_names().len() as u64;
+            for index in 0..=n_files {
+                table.get_file(dwarf, unit, header, index);
+            }
+        }
+
+        table.rows.sort_by_key(|r| r.address);
+
+        Ok(table)
+    }
+
+    fn get_file(
+        &mut self,
+        dwarf: &Dwarf<Slice<'a>>,
+        unit: &Unit<Slice<'a>>,
+        header: &gimli::LineProgramHeader<Slice<'a>>,
+        index: u64,
+    ) -> FileInfo<'a> {
+        if let Some(file) = self.files.get(&index) {
+            return file.clone();
+        }
+
+        let file = header.file(index).map_or_else(FileInfo::default, |file| {
+            get_file_info(dwarf, unit, header, file)
+        });
+        self.files.insert(index, file.clone());
+
+        file
+    }
+
+    /// The rows in the given range
+    fn get_rows(&self, start: u64, end: u64) -> &[Row<'a>] {
+        let first = self.rows.partition_point(|r| r.address < start);
+        let last = self.rows.partition_point(|r| r.address < end);
+        &self.rows[first..last]
+    }
+}
+
+/// The unit headers of a file to find the DIEs referenced from another unit (DW_FORM_ref_addr)
+/// or from another file (DW_FORM_GNU_ref_alt)
+pub(super) struct References<'a, 'b> {
+    dwarf: &'b Dwarf<Slice<'a>>,
+    headers: Vec<UnitHeader<Slice<'a>>>,
+    sup: Option<Box<References<'a, 'b>>>,
+}
+
+impl<'a, 'b> References<'a, 'b> {
+    pub(super) fn new(
+        dwarf: &'b Dwarf<Slice<'a>>,
+        sup: Option<&'b Dwarf<Slice<'a>>>,
+    ) -> gimli::Result<Self> {
+        let mut headers = Vec::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            headers.push(header);
+        }
+
+        Ok(Self {
+            dwarf,
+            headers,
+            sup: sup
+                .map(|sup| References::new(sup, None).map(Box::new))
+                .transpose()?,
+        })
+    }
+
+    fn find(&self, offset: DebugInfoOffset) -> Option<(&'b Dwarf<Slice<'a>>, Unit<Slice<'a>>)> {
+        let start = |h: &UnitHeader<Slice>| h.offset().as_debug_info_offset().map_or(0, |o| o.0);
+        let pos = self.headers.partition_point(|h| start(h) <= offset.0);
+        let header = self.headers.get(pos.checked_sub(1)?)?;
+        offset.to_unit_offset(header)?;
+
+        Some((self.dwarf, self.dwarf.unit(*header).ok()?))
+    }
+}
+
+/// An inlined function: the code in the ranges comes from the call location
+struct CallSite {
+    ranges: Vec<(u64, u64)>,
+    file: u64,
+    line: u64,
+}
+
+pub(super) struct DwarfUnit<'a, 'b> {
+    dwarf: &'b Dwarf<Slice<'a>>,
+    unit: &'b Unit<Slice<'a>>,
+    lines: &'b LineTable<'a>,
+    references: Option<&'b References<'a, 'b>>,
+    comp_dir: &'a [u8],
+    language: Language,
+    load_address: u64,
+}
+
+impl<'a, 'b> DwarfUnit<'a, 'b> {
+    /// The DIEs are read in unit but the line table and the compilation directory
+    /// can come from another one (e.g. a skeleton unit).
+    pub(super) fn new(
+        dwarf: &'b Dwarf<Slice<'a>>,
+        unit: &'b Unit<Slice<'a>>,
+        lines: &'b LineTable<'a>,
+        comp_dir: &'a [u8],
+        load_address: u64,
+    ) -> Self {
+        Self {
+            dwarf,
+            unit,
+            lines,
+            references: None,
+            comp_dir,
+            language: get_language(unit),
+            load_address,
+        }
+    }
+
+    pub(super) fn with_references(mut self, references: &'b References<'a, 'b>) -> Self {
+        self.references = Some(references);
+        self
+    }
+
+    fn get_ranges(
+        &self,
+        entry: &DebuggingInformationEntry<Slice<'a>>,
+    ) -> gimli::Result<Vec<(u64, u64)>> {
+        let mut low_pc = None;
+        let mut high_pc = None;
+        let mut size = None;
+        let mut ranges = Vec::new();
+
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            match attr.name() {
+                constants::DW_AT_low_pc => {
+                    low_pc = self.dwarf.attr_address(self.unit, attr.value())?;
+                }
+                constants::DW_AT_high_pc => match attr.value() {
+                    AttributeValue::Udata(val) => size = Some(val),
+                    value => high_pc = self.dwarf.attr_address(self.unit, value)?,
+                },
+                constants::DW_AT_ranges => {
+                    if let Some(mut iter) = self.dwarf.attr_ranges(self.unit, attr.value())? {
+                        while let Some(range) = iter.next()? {
+                            if range.begin < range.end {
+                                ranges.push((range.begin, range.end));
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(low_pc) = low_pc {
+            let high_pc = high_pc.or_else(|| size.map(|s| low_pc + s));
+            if let Some(high_pc) = high_pc {
+                if low_pc < high_pc {
+                    ranges.push((low_pc, high_pc));
+                }
+            }
+        }
+
+        Ok(ranges)
+    }
+
+    /// Get the linkage name if any, else the name (from the declaration if needed).
+    /// The declaration can be in another unit or in the supplementary file.
+    fn get_name(
+        &self,
+        dwarf: &Dwarf<Slice<'a>>,
+        unit: &Unit<Slice<'a>>,
+        references: Option<&References<'a, 'b>>,
+        entry: &DebuggingInformationEntry<Slice<'a>>,
+        depth: usize,
+    ) -> Option<(String, bool)> {
+        let mut name = None;
+        let mut origin = None;
+
+        let mut attrs = entry.attrs();
+        while let Ok(Some(attr)) = attrs.next() {
+            match attr.name() {
+                constants::DW_AT_linkage_name | constants::DW_AT_MIPS_linkage_name => {
+                    if let Ok(s) = dwarf.attr_string(unit, attr.value()) {
+                        return Some((s.to_string_lossy().to_string(), true));
+                    }
+                }
+                constants::DW_AT_name => {
+                    name = dwarf
+                        .attr_string(unit, attr.value())
+                        .ok()
+                        .map(|s| s.to_string_lossy().to_string());
+                }
+                constants::DW_AT_specification | constants::DW_AT_abstract_origin => {
+                    origin = Some(attr.value());
+                }
+                _ => {}
+            }
+        }
+
+        let res = match origin.filter(|_| depth < 8) {
+            Some(AttributeValue::UnitRef(offset)) => unit
+                .entry(offset)
+                .ok()
+                .and_then(|e| self.get_name(dwarf, unit, references, &e, depth + 1)),
+            Some(AttributeValue::DebugInfoRef(offset)) => {
+                references.and_then(|r| self.get_ref_name(r, offset, depth))
+            }
+            Some(AttributeValue::DebugInfoRefSup(offset)) => references
+                .and_then(|r| r.sup.as_deref())
+                .and_then(|r| self.get_ref_name(r, offset, depth)),
+            _ => None,
+        };
+
+        match res {
+            Some(res) if res.1 || name.is_none() => Some(res),
+            _ => name.map(|n| (n, false)),
+        }
+    }
+
+    fn get_ref_name(
+        &self,
+        references: &References<'a, 'b>,
+        offset: DebugInfoOffset,
+        depth: usize,
+    ) -> Option<(String, bool)> {
+        let (dwarf, unit) = references.find(offset)?;
+        let entry = unit.entry(offset.to_unit_offset(&unit.header)?).ok()?;
+        self.get_name(dwarf, &unit, Some(references), &entry, depth + 1)
+    }
+
+    fn get_call_site(
+        &self,
+        entry: &DebuggingInformationEntry<Slice<'a>>,
+    ) -> gimli::Result<CallSite> {
+        let mut file = 0;
+        let mut line = 0;
+        if let Some(AttributeValue::FileIndex(index)) =
+            entry.attr_value(constants::DW_AT_call_file)?
+        {
+            file = index;
+        }
+        if let Some(value) = entry.attr_value(constants::DW_AT_call_line)? {
+            line = value.udata_value().unwrap_or(0);
+        }
+
+        Ok(CallSite {
+            ranges: self.get_ranges(entry)?,
+            file,
+            line,
+        })
+    }
+
+    /// Collect the inlined functions (but not the ones inlined in an inlined function)
+    fn collect_call_sites(
+        &self,
+        node: gimli::EntriesTreeNode<Slice<'a>>,
+        call_sites: &mut Vec<CallSite>,
+    ) -> gimli::Result<()> {
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            match child.entry().tag() {
+                constants::DW_TAG_inlined_subroutine => {
+                    call_sites.push(self.get_call_site(child.entry())?);
+                }
+                constants::DW_TAG_lexical_block => {
+                    self.collect_call_sites(child, call_sites)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn make_function(
+        &self,
+        name: Name<'a>,
+        ranges: &[(u64, u64)],
+        call_sites: &[CallSite],
+    ) -> Function<'a> {
+        let start = ranges.iter().map(|r| r.0).min().unwrap();
+        let end = ranges.iter().map(|r| r.1).max().unwrap();
+
+        let mut lines = Vec::new();
+        for (range_start, range_end) in ranges {
+            for row in self.lines.get_rows(*range_start, *range_end) {
+                let call_site = call_sites.iter().find(|c| {
+                    c.ranges
+                        .iter()
+                        .any(|(s, e)| *s <= row.address && row.address < *e)
+                });
+                let (file, line) = match call_site {
+                    Some(c) if c.line != 0 => (
+                        self.lines
+                            .files
+                            .get(&c.file)
+                            .cloned()
+                            .unwrap_or_else(|| row.file.clone()),
+                        c.line,
+                    ),
+                    _ => (row.file.clone(), row.line),
+                };
+                lines.push(LineInfo {
+                    address: row.address - self.load_address,
+                    size: Some(row.size),
+                    file,
+                    line,
+                });
+            }
+        }
+        lines.sort_by_key(|l| l.address);
+
+        Function {
+            address: start - self.load_address,
+            size: end - start,
+            name,
+            compilation_dir: self.comp_dir,
+            lines,
+            inlinees: Vec::new(),
+            inline: false,
+        }
+    }
+
+    fn collect_functions(
+        &self,
+        node: gimli::EntriesTreeNode<Slice<'a>>,
+        scope: &mut Vec<String>,
+        functions: &mut Vec<Function<'a>>,
+    ) -> gimli::Result<()> {
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                constants::DW_TAG_subprogram => {
+                    let ranges = self.get_ranges(entry)?;
+                    if ranges.is_empty() || ranges.iter().all(|r| r.0 < self.load_address) {
+                        continue;
+                    }
+
+                    let name = match self.get_name(self.dwarf, self.unit, self.references, entry, 0)
+                    {
+                        Some((name, true)) => Name::new(name, NameMangling::Mangled, self.language),
+                        Some((name, false)) => {
+                            let name = if scope.is_empty() {
+                                name
+                            } else {
+                                format!("{}::{}", scope.join("::"), name)
+                            };
+                            Name::new(name, NameMangling::Unmangled, self.language)
+                        }
+                        _ => continue,
+                    };
+
+                    let mut call_sites = Vec::new();
+                    self.collect_call_sites(child, &mut call_sites)?;
+                    functions.push(self.make_function(name, &ranges, &call_sites));
+                }
+                constants::DW_TAG_namespace
+                | constants::DW_TAG_class_type
+                | constants::DW_TAG_structure_type
+                | constants::DW_TAG_union_type => {
+                    let name = self
+                        .get_name(self.dwarf, self.unit, self.references, entry, 0)
+                        .map_or_else(|| "(anonymous namespace)".to_string(), |n| n.0);
+                    scope.push(name);
+                    self.collect_functions(child, scope, functions)?;
+                    scope.pop();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the functions defined in the unit
+    pub(super) fn get_functions(&self) -> gimli::Result<Vec<Function<'a>>> {
+        let mut functions = Vec::new();
+        let mut tree = self.unit.entries_tree(None)?;
+        self.collect_functions(tree.root()?, &mut Vec::new(), &mut functions)?;
+
+        Ok(functions)
+    }
+
+    /// Collect the functions defined in the unit
+    pub(super) fn collect(
+        &self,
+        collector: &mut Collector,
+        source: &mut SourceFiles,
+    ) -> gimli::Result<()> {
+        for fun in self.get_functions()?.iter() {
+            collector.collect_function(fun, source);
+        }
+
+        Ok(())
+    }
+}
+
+/// Check if the unit is a compilation unit: the partial units are only imported in them and
+/// the DIEs of the skeleton units are in some dwo files
+pub(super) fn is_compile_unit(unit: &Unit<Slice>) -> bool {
+    let mut entries = unit.entries();
+    match entries.next_dfs() {
+        Ok(Some((_, root))) => {
+            root.tag() == constants::DW_TAG_compile_unit
+                && !matches!(unit.header.type_(), UnitType::Skeleton(_))
+                && !matches!(root.attr_value(constants::DW_AT_dwo_name), Ok(Some(_)))
+                && !matches!(root.attr_value(constants::DW_AT_GNU_dwo_name), Ok(Some(_)))
+        }
+        _ => false,
+    }
+}
+
+/// Check if the addresses of the unit can be in .debug_addr
+fn has_addr_base(unit: &Unit<Slice>) -> bool {
+    let mut entries = unit.entries();
+    match entries.next_dfs() {
+        Ok(Some((_, root))) => matches!(root.attr_value(constants::DW_AT_addr_base), Ok(Some(_))),
+        _ => false,
+    }
+}
+
+/// Collect the functions of the units: they're decoded in parallel and collected in order
+pub(super) fn collect_units<'a>(
+    dwarf: &Dwarf<Slice<'a>>,
+    units: &[Unit<Slice<'a>>],
+    references: &References<'a, '_>,
+    load_address: u64,
+    collector: &mut Collector,
+    source: &mut SourceFiles,
+) -> common::Result<()> {
+    let functions = parallel::map(collector.options.num_threads, units, |unit| {
+        let lines = LineTable::new(dwarf, unit)?;
+        let comp_dir = unit.comp_dir.map_or(&[][..], |d| d.slice());
+        DwarfUnit::new(dwarf, unit, &lines, comp_dir, load_address)
+            .with_references(references)
+            .get_functions()
+    });
+    cancel::check()?;
+    for functions in functions {
+        for fun in functions?.iter() {
+            collector.collect_function(fun, source);
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect the functions of the compilation units when some of them have their addresses in
+/// .debug_addr: false when there are none (and the functions must be collected by symbolic)
+pub(super) fn collect_debug_addr_functions(
+    buf: &[u8],
+    load_address: u64,
+    collector: &mut Collector,
+    source: &mut SourceFiles,
+) -> common::Result<bool> {
+    let elf = match Elf::parse(buf) {
+        Ok(elf) => elf,
+        _ => return Ok(false),
+    };
+    if get_section(&elf, buf, ".debug_addr").is_empty() {
+        return Ok(false);
+    }
+
+    let dwarf = load_dwarf(&elf, buf, None)?;
+    let mut compile_units = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        cancel::check()?;
+        let unit = dwarf.unit(header)?;
+        if is_compile_unit(&unit) {
+            compile_units.push(unit);
+        }
+    }
+    if !compile_units.iter().any(has_addr_base) {
+        return Ok(false);
+    }
+
+    let references = References::new(&dwarf, None)?;
+    collect_units(
+        &dwarf,
+        &compile_units,
+        &references,
+        load_address,
+        collector,
+        source,
+    )?;
+
+    Ok(true)
+}
+
+fn get_language(unit: &Unit<Slice>) -> Language {
+    let mut entries = unit.entries();
+    let lang = match entries.next_dfs() {
+        Ok(Some((_, root))) => root.attr_value(constants::DW_AT_language).ok().flatten(),
+        _ => None,
+    };
+
+    match lang {
+        Some(AttributeValue::Language(lang)) => match lang {
+            constants::DW_LANG_C
+            | constants::DW_LANG_C89
+            | constants::DW_LANG_C99
+            | constants::DW_LANG_C11 => Language::C,
+            constants::DW_LANG_C_plus_plus
+            | constants::DW_LANG_C_plus_plus_03
+            | constants::DW_LANG_C_plus_plus_11
+            | constants::DW_LANG_C_plus_plus_14 => Language::Cpp,
+            constants::DW_LANG_Rust => Language::Rust,
+            _ => Language::Unknown,
+        },
+        _ => Language::Unknown,
+    }
+}
@@ -0,0 +1,371 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The release binaries have often no .debug_frame but only the .eh_frame used for the exceptions.
+// symbolic stops at the first entry it can't read (e.g. a pointer relative to the GOT), so when it
+// gives nothing the STACK CFI records are built here from the .eh_frame, FDE by FDE: the entries
+// which can't be read (or whose CFA is a DWARF expression, like in the .plt) are skipped.
+// Without section headers the .eh_frame is found with the .eh_frame_hdr (PT_GNU_EH_FRAME).
+
+use gimli::{
+    BaseAddresses, CfaRule, CieOrFde, EhFrame, EhFrameHdr, EhFrameOffset, FrameDescriptionEntry,
+    Pointer, Register, RegisterRule, RunTimeEndian, UninitializedUnwindContext, UnwindSection,
+};
+use goblin::elf::{
+    header::{EM_386, EM_AARCH64, EM_ARM, EM_PPC64, EM_RISCV, EM_S390, EM_X86_64},
+    program_header::{PT_GNU_EH_FRAME, PT_LOAD},
+    Elf,
+};
+use hashbrown::HashMap;
+use log::warn;
+
+use super::dwarf::{get_endian, get_section, Slice};
+
+// The names of the registers by DWARF number
+const X86: [&str; 9] = [
+    "$eax", "$ecx", "$edx", "$ebx", "$esp", "$ebp", "$esi", "$edi", "$eip",
+];
+const X86_64: [&str; 17] = [
+    "$rax", "$rdx", "$rcx", "$rbx", "$rsi", "$rdi", "$rbp", "$rsp", "$r8", "$r9", "$r10", "$r11",
+    "$r12", "$r13", "$r14", "$r15", "$rip",
+];
+const ARM: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr",
+    "pc",
+];
+const ARM64: [&str; 32] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27",
+    "x28", "x29", "x30", "sp",
+];
+const S390X: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "r13", "r14",
+    "r15",
+];
+// The link register (the return address) is the column 65
+const PPC64: [&str; 32] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "r13", "r14",
+    "r15", "r16", "r17", "r18", "r19", "r20", "r21", "r22", "r23", "r24", "r25", "r26", "r27",
+    "r28", "r29", "r30", "r31",
+];
+// x0 is always zero so its number is used for the pc (like in Breakpad)
+const RISCV: [&str; 32] = [
+    "pc", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5",
+    "t6",
+];
+
+fn get_register_names(machine: u16) -> Option<&'static [&'static str]> {
+    match machine {
+        EM_386 => Some(&X86),
+        EM_X86_64 => Some(&X86_64),
+        EM_ARM => Some(&ARM),
+        EM_AARCH64 => Some(&ARM64),
+        EM_S390 => Some(&S390X),
+        EM_PPC64 => Some(&PPC64),
+        EM_RISCV => Some(&RISCV),
+        _ => None,
+    }
+}
+
+fn get_section_address(elf: &Elf, name: &str) -> Option<u64> {
+    elf.section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get(sh.sh_name).and_then(|n| n.ok()) == Some(name))
+        .map(|sh| sh.sh_addr)
+}
+
+/// Get the data of the file mapped at the address (until the end of its segment)
+fn get_segment_data<'a>(elf: &Elf, buf: &'a [u8], address: u64) -> &'a [u8] {
+    elf.program_headers
+        .iter()
+        .find(|ph| {
+            ph.p_type == PT_LOAD
+                && ph.p_vaddr <= address
+                && address < ph.p_vaddr.saturating_add(ph.p_filesz)
+        })
+        .and_then(|ph| {
+            let start = (ph.p_offset + address - ph.p_vaddr) as usize;
+            let end = ph.p_offset.saturating_add(ph.p_filesz) as usize;
+            buf.get(start..end)
+        })
+        .unwrap_or(&[])
+}
+
+/// Get the .eh_frame (its data and its address) and the base addresses of the pointers in it
+fn find_eh_frame<'a>(elf: &Elf, buf: &'a [u8]) -> Option<(&'a [u8], BaseAddresses)> {
+    let text = get_section_address(elf, ".text").unwrap_or_default();
+    // The pointers relative to the data are relative to the GOT (_GLOBAL_OFFSET_TABLE_)
+    let got = get_section_address(elf, ".got.plt")
+        .or_else(|| get_section_address(elf, ".got"))
+        .unwrap_or_default();
+    let bases = BaseAddresses::default().set_text(text).set_got(got);
+
+    if !elf.section_headers.is_empty() {
+        let data = get_section(elf, buf, ".eh_frame");
+        let address = get_section_address(elf, ".eh_frame")?;
+        let hdr = get_section_address(elf, ".eh_frame_hdr").unwrap_or_default();
+        return Some((data, bases.set_eh_frame(address).set_eh_frame_hdr(hdr)))
+            .filter(|(data, _)| !data.is_empty());
+    }
+
+    let ph = elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == PT_GNU_EH_FRAME)?;
+    let start = ph.p_offset as usize;
+    let hdr_data = buf.get(start..start.checked_add(ph.p_filesz as usize)?)?;
+    let bases = bases.set_eh_frame_hdr(ph.p_vaddr);
+    let address_size = if elf.is_64 { 8 } else { 4 };
+    let hdr = EhFrameHdr::new(hdr_data, get_endian(elf))
+        .parse(&bases, address_size)
+        .ok()?;
+    let address = match hdr.eh_frame_ptr() {
+        Pointer::Direct(address) => address,
+        Pointer::Indirect(_) => return None,
+    };
+    let data = get_segment_data(elf, buf, address);
+    Some((data, bases.set_eh_frame(address))).filter(|(data, _)| !data.is_empty())
+}
+
+struct CfiWriter<'a> {
+    names: &'static [&'static str],
+    eh_frame: EhFrame<Slice<'a>>,
+    bases: BaseAddresses,
+    load_address: u64,
+    ctx: UninitializedUnwindContext<Slice<'a>>,
+}
+
+fn get_name(names: &[&'static str], register: Register) -> Option<&'static str> {
+    names.get(register.0 as usize).copied()
+}
+
+fn get_cfa(names: &[&'static str], cfa: &CfaRule<Slice>) -> Option<String> {
+    match cfa {
+        CfaRule::RegisterAndOffset { register, offset } => {
+            Some(format!("{} {} +", get_name(names, *register)?, offset))
+        }
+        CfaRule::Expression(_) => None,
+    }
+}
+
+fn get_rule(
+    names: &[&'static str],
+    rule: &RegisterRule<Slice>,
+    register: Register,
+) -> Option<String> {
+    match rule {
+        RegisterRule::SameValue => Some(get_name(names, register)?.to_string()),
+        RegisterRule::Offset(offset) => Some(format!(".cfa {} + ^", offset)),
+        RegisterRule::ValOffset(offset) => Some(format!(".cfa {} +", offset)),
+        RegisterRule::Register(other) => Some(get_name(names, *other)?.to_string()),
+        _ => None,
+    }
+}
+
+impl<'a> CfiWriter<'a> {
+    /// Get the STACK CFI records of an FDE
+    fn get_records(&mut self, fde: &FrameDescriptionEntry<Slice<'a>>) -> Option<String> {
+        let ra = fde.cie().return_address_register();
+        let mut table = fde.rows(&self.eh_frame, &self.bases, &mut self.ctx).ok()?;
+        let mut records = String::new();
+        let mut previous: Option<HashMap<&'static str, String>> = None;
+        let names = self.names;
+        let load_address = self.load_address;
+
+        while let Ok(Some(row)) = table.next_row() {
+            let mut rules = HashMap::new();
+            // The rows after a CFA which can't be written are dropped
+            let cfa = match get_cfa(names, row.cfa()) {
+                Some(cfa) => cfa,
+                _ => break,
+            };
+            rules.insert(".cfa", cfa);
+            for (register, rule) in row.registers() {
+                let name = match get_name(names, *register) {
+                    _ if *register == ra => ".ra",
+                    Some(name) => name,
+                    _ => continue,
+                };
+                if let Some(rule) = get_rule(names, rule, *register) {
+                    rules.insert(name, rule);
+                }
+            }
+
+            let address = row.start_address().wrapping_sub(load_address);
+            let mut changes: Vec<(&str, String)> = rules
+                .iter()
+                .filter(|(name, rule)| previous.as_ref().and_then(|p| p.get(*name)) != Some(rule))
+                .map(|(name, rule)| (*name, rule.clone()))
+                .collect();
+            if let Some(previous) = previous.as_ref() {
+                // A register without rule has its value again
+                for name in previous.keys().filter(|name| !rules.contains_key(*name)) {
+                    changes.push((name, name.to_string()));
+                }
+            }
+            if changes.is_empty() {
+                continue;
+            }
+            // .cfa and .ra first
+            changes.sort_by_key(|(name, _)| (!name.starts_with('.'), *name));
+            let changes = changes
+                .iter()
+                .map(|(name, rule)| format!("{}: {}", name, rule))
+                .collect::<Vec<_>>()
+                .join(" ");
+            if previous.is_none() {
+                records.push_str(&format!(
+                    "STACK CFI INIT {:x} {:x} {}\n",
+                    address,
+                    fde.len(),
+                    changes
+                ));
+            } else {
+                records.push_str(&format!("STACK CFI {:x} {}\n", address, changes));
+            }
+            previous = Some(rules);
+        }
+
+        Some(records)
+    }
+}
+
+/// Get the STACK CFI records from the .eh_frame of an ELF file
+pub(super) fn get_stack_cfi(buf: &[u8], load_address: u64) -> String {
+    let elf = match Elf::parse(buf) {
+        Ok(elf) => elf,
+        _ => return String::new(),
+    };
+    let names = match get_register_names(elf.header.e_machine) {
+        Some(names) => names,
+        _ => return String::new(),
+    };
+    let (data, bases) = match find_eh_frame(&elf, buf) {
+        Some(eh_frame) => eh_frame,
+        _ => return String::new(),
+    };
+
+    let mut eh_frame = EhFrame::new(data, get_endian(&elf));
+    eh_frame.set_address_size(if elf.is_64 { 8 } else { 4 });
+    let mut writer = CfiWriter {
+        names,
+        eh_frame,
+        bases,
+        load_address,
+        ctx: UninitializedUnwindContext::new(),
+    };
+
+    let mut stack = String::new();
+    let mut fdes = Vec::new();
+    let mut entries = writer.eh_frame.entries(&writer.bases);
+    loop {
+        match entries.next() {
+            Ok(Some(CieOrFde::Fde(partial))) => {
+                match partial.parse(|section, bases, offset| section.cie_from_offset(bases, offset))
+                {
+                    Ok(fde) => fdes.push(fde),
+                    Err(e) => warn!("Invalid FDE in .eh_frame: {}", e),
+                }
+            }
+            Ok(Some(CieOrFde::Cie(_))) => {}
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Cannot read the .eh_frame: {}", e);
+                break;
+            }
+        }
+    }
+    for fde in fdes.iter() {
+        if let Some(records) = writer.get_records(fde) {
+            stack.push_str(&records);
+        }
+    }
+
+    stack
+}
+
+/// Get the STACK CFI records of the FDE at an offset in an .eh_frame (e.g. the __eh_frame of a
+/// Mach-O file referenced by its compact unwind info) for an ELF machine (EM_*)
+pub(crate) fn get_fde_stack_cfi(
+    machine: u16,
+    data: &[u8],
+    bases: BaseAddresses,
+    offset: usize,
+    load_address: u64,
+) -> Option<String> {
+    let names = get_register_names(machine)?;
+    let mut eh_frame = EhFrame::new(data, RunTimeEndian::Little);
+    eh_frame.set_address_size(if machine == EM_386 || machine == EM_ARM {
+        4
+    } else {
+        8
+    });
+    let mut writer = CfiWriter {
+        names,
+        eh_frame,
+        bases,
+        load_address,
+        ctx: UninitializedUnwindContext::new(),
+    };
+    let fde = writer
+        .eh_frame
+        .fde_from_offset(
+            &writer.bases,
+            EhFrameOffset(offset),
+            EhFrame::cie_from_offset,
+        )
+        .ok()?;
+    writer.get_records(&fde)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::convert::TryInto;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_eh_frame() {
+        let basic_dir = PathBuf::from("./test_data/linux");
+        let buf = fs::read(basic_dir.join("basic.full")).unwrap();
+        let sym = fs::read_to_string(basic_dir.join("basic.full.sym")).unwrap();
+        let expected: String = sym
+            .lines()
+            .filter(|l| l.starts_with("STACK CFI"))
+            .map(|l| format!("{}\n", l))
+            .collect();
+        assert_eq!(get_stack_cfi(&buf, 0), expected);
+
+        // Without section headers (e_shoff, e_shnum and e_shstrndx): found with PT_GNU_EH_FRAME
+        let mut buf = buf;
+        buf[0x28..0x30].fill(0);
+        buf[0x3c..0x40].fill(0);
+        assert_eq!(get_stack_cfi(&buf, 0), expected);
+    }
+
+    #[test]
+    fn test_fde_stack_cfi() {
+        let basic_dir = PathBuf::from("./test_data/linux");
+        let buf = fs::read(basic_dir.join("basic.full")).unwrap();
+        let sym = fs::read_to_string(basic_dir.join("basic.full.sym")).unwrap();
+        let elf = Elf::parse(&buf).unwrap();
+        let (data, bases) = find_eh_frame(&elf, &buf).unwrap();
+
+        // The first FDE is after the first CIE (its length and its data)
+        let offset = 4 + u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+        let records = get_fde_stack_cfi(EM_X86_64, data, bases, offset, 0).unwrap();
+        let expected = sym
+            .lines()
+            .filter(|l| l.starts_with("STACK CFI"))
+            .enumerate()
+            .take_while(|(i, l)| *i == 0 || !l.starts_with("STACK CFI INIT"))
+            .map(|(_, l)| format!("{}\n", l))
+            .collect::<String>();
+        assert_eq!(records, expected);
+    }
+}
@@ -4,22 +4,53 @@
 // copied, modified, or distributed except according to those terms.
 
 use failure::Fail;
+use goblin::elf::{
+    header::{EM_RISCV, EM_S390, ET_DYN},
+    note::NT_GNU_BUILD_ID,
+    section_header::{SHT_DYNAMIC, SHT_NOBITS},
+    Elf,
+};
 use hashbrown::HashMap;
 use log::{error, warn};
-use std::collections::btree_map;
+use std::collections::{btree_map, BTreeMap};
 use std::fmt::{Display, Formatter};
 use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use symbolic::common::{Language, Name, NameMangling};
-use symbolic::debuginfo::{Function, Object, ObjectDebugSession};
-use symbolic::demangle::{Demangle, DemangleOptions};
+use symbolic::common::{Arch, Language, Name, NameMangling};
+use symbolic::debuginfo::{FileFormat, Function, Object, ObjectDebugSession};
 use symbolic::minidump::cfi::AsciiCfiWriter;
-
+use uuid::Uuid;
+
+use super::arm_exidx;
+use super::debuglink;
+use super::decompression;
+use super::dwarf;
+use super::eh_frame;
+use super::gopclntab;
+use super::minidebuginfo;
+use super::relocation;
 use super::source::{SourceFiles, SourceMap};
-use super::symbol::{ElfSymbol, ElfSymbols};
+use super::split_dwarf;
+use super::symbol::{self, ElfSymbol, ElfSymbols};
+use crate::cancel;
+use crate::checksums;
 use crate::common::{self, Dumpable, LineFinalizer, Mergeable};
+use crate::debug_records;
+use crate::demangling::{self, Demangling};
+use crate::folding::{self, Folded};
+use crate::inlines::{Inline, InlineOrigins};
 use crate::line::Lines;
+use crate::mac::compact_unwind;
 use crate::mapping::PathMappings;
+use crate::options::Options;
+use crate::parallel;
+use crate::publics::{Public, Publics, PublicsPolicy};
+use crate::utils;
+use crate::visitor::{self, Visitor};
+
+// The number of functions kept in memory to be demangled in parallel
+const FUNCTIONS_BATCH_SIZE: usize = 4096;
 
 #[derive(Debug, PartialEq)]
 pub enum Type {
@@ -31,6 +62,7 @@ pub enum Type {
 pub enum Platform {
     Linux,
     Mac,
+    Wasm,
 }
 
 impl Display for Platform {
@@ -38,6 +70,7 @@ impl Display for Platform {
         let p = match self {
             Self::Linux => "Linux",
             Self::Mac => "Mac",
+            Self::Wasm => "wasm",
         };
         write!(f, "{}", p)
     }
@@ -46,7 +79,9 @@ impl Display for Platform {
 #[derive(Debug)]
 pub struct ElfInfo {
     symbols: ElfSymbols,
+    publics: Publics,
     files: SourceMap,
+    inline_origins: InlineOrigins,
     file_name: String,
     cpu: &'static str,
     debug_id: String,
@@ -54,6 +89,11 @@ pub struct ElfInfo {
     stack: String,
     bin_type: Type,
     platform: Platform,
+    // A split debug file without build-id: the debug id can't be computed
+    unknown_id: bool,
+    // No debug info and --symtab-functions: the PUBLIC records with a size are some FUNC
+    symtab_functions: bool,
+    options: Arc<Options>,
 }
 
 impl Display for ElfInfo {
@@ -68,12 +108,27 @@ impl Display for ElfInfo {
             writeln!(f, "INFO CODE_ID {}", code_id)?;
         }
 
+        let checksums = self.files.get_checksums();
         for (n, file_name) in self.files.get_mapping().iter().enumerate() {
-            writeln!(f, "FILE {} {}", n, file_name)?;
+            checksums::write_file_record(f, n, file_name, checksums)?;
         }
 
-        for (_, sym) in self.symbols.iter() {
-            write!(f, "{}", sym)?;
+        write!(f, "{}", self.inline_origins)?;
+
+        for (rva, sym) in self.symbols.iter() {
+            if self.symtab_functions && sym.is_public && sym.len != 0 {
+                // A FUNC without lines from the symbol table
+                let func = ElfSymbol {
+                    is_public: false,
+                    ..sym.clone()
+                };
+                write!(f, "{}", Folded(&func, self.options.folding_policy))?;
+            } else {
+                write!(f, "{}", Folded(sym, self.options.folding_policy))?;
+            }
+            if let Some(public) = self.publics.get(rva) {
+                write!(f, "{}", public)?;
+            }
         }
 
         write!(f, "{}", self.stack)?;
@@ -190,31 +245,45 @@ impl InlineeManager {
 #[derive(Debug, Default)]
 pub struct Collector {
     syms: ElfSymbols,
+    // The publics at the address of a function (with --keep-publics)
+    publics: Publics,
+    origins: InlineOrigins,
+    // The functions of a relocatable object can start at address 0
+    relocatable: bool,
+    pub(super) options: Arc<Options>,
 }
 
 impl Collector {
-    #[allow(dead_code)]
-    fn debug_function(fun: &Function, level: String) {
-        println!("{}Name: {}", level, fun.name.as_str());
-        println!("{}Address: 0x{:x}", level, fun.address);
-        println!("{}Size: 0x{:x}", level, fun.size);
+    fn debug_function(fun: &Function, level: String, out: &mut String) {
+        use std::fmt::Write;
+
+        let _ = writeln!(out, "{}Name: {}", level, fun.name.as_str());
+        let _ = writeln!(out, "{}Address: 0x{:x}", level, fun.address);
+        let _ = writeln!(out, "{}Size: 0x{:x}", level, fun.size);
         for line in fun.lines.iter() {
-            println!("{}Line: {:?}", level, line);
+            let _ = writeln!(
+                out,
+                "{}Line: 0x{:x} size={:?} {}:{}",
+                level,
+                line.address,
+                line.size,
+                line.file.path_str(),
+                line.line
+            );
         }
-        println!("{}Inlinees:", level);
+        let _ = writeln!(out, "{}Inlinees:", level);
         for f in fun.inlinees.iter() {
-            Self::debug_function(&f, "  ".to_string() + &level);
+            Self::debug_function(&f, "  ".to_string() + &level, out);
         }
-        println!();
     }
 
-    fn demangle(name: &Name) -> String {
+    fn demangle(name: &Name, demangling: Demangling) -> String {
         let name = common::fix_symbol_name(name);
         if let Language::C = name.language() {
             return name.as_str().to_string();
         }
 
-        match name.demangle(DemangleOptions::complete()) {
+        match demangling::demangle(&name, demangling) {
             Some(demangled) => demangled,
             None => {
                 let aname = name.as_str();
@@ -224,8 +293,8 @@ impl Collector {
         }
     }
 
-    fn demangle_str(name: &str) -> String {
-        let lang = Name::new(name, NameMangling::Mangled, Language::Unknown).detect_language();
+    fn demangle_str(name: &str, demangling: Demangling) -> String {
+        let lang = demangling::detect_language(name);
         if lang == Language::Unknown {
             return name.to_string();
         }
@@ -233,7 +302,7 @@ impl Collector {
         let name = Name::new(name, NameMangling::Mangled, lang);
         let name = common::fix_symbol_name(&name);
 
-        match name.demangle(DemangleOptions::complete()) {
+        match demangling::demangle(&name, demangling) {
             Some(demangled) => demangled,
             None => {
                 warn!("Didn't manage to demangle {}", name);
@@ -242,11 +311,7 @@ impl Collector {
         }
     }
 
-    pub fn collect_function(&mut self, fun: &Function, source: &mut SourceFiles) {
-        if fun.address == 0 {
-            return;
-        }
-
+    fn collect_lines(fun: &Function, source: &mut SourceFiles) -> Lines {
         let mut inlinee_manager = InlineeManager::default();
         inlinee_manager.add_inlinees(fun, source);
 
@@ -272,21 +337,171 @@ impl Collector {
             }
         }
 
+        lines
+    }
+
+    fn add_rows(fun: &Function, rows: &mut BTreeMap<u64, ElfLineInfo>, source: &mut SourceFiles) {
+        for line in fun.lines.iter().filter(|line| line.line != 0) {
+            let file_id = source.get_id(fun.compilation_dir, &line.file);
+            rows.insert(
+                line.address,
+                ElfLineInfo {
+                    file_id,
+                    line: line.line as u32,
+                },
+            );
+        }
+    }
+
+    fn collect_inlinee(
+        &mut self,
+        parent: &Function,
+        fun: &Function,
+        depth: u32,
+        rows: &mut BTreeMap<u64, ElfLineInfo>,
+        inlines: &mut Vec<Inline>,
+        source: &mut SourceFiles,
+    ) {
+        // The line of the parent at the start of the inlinee is the call location
+        let (call_line, call_file) = parent
+            .lines
+            .iter()
+            .filter(|line| line.address <= fun.address)
+            .max_by_key(|line| line.address)
+            .map_or((0, 0), |line| {
+                let file_id = source.get_id(parent.compilation_dir, &line.file);
+                (line.line as u32, source.get_true_id(file_id))
+            });
+
+        let mut ranges = Inline::get_ranges(
+            fun.lines
+                .iter()
+                .map(|line| (line.address as u32, line.size.unwrap_or(0) as u32)),
+        );
+        if ranges.is_empty() {
+            ranges.push((fun.address as u32, fun.size as u32));
+        }
+
+        inlines.push(Inline {
+            depth,
+            call_line,
+            call_file,
+            origin: self
+                .origins
+                .get_id(&Self::demangle(&fun.name, self.options.demangling)),
+            ranges,
+        });
+
+        Self::add_rows(fun, rows, source);
+        for inlinee in fun.inlinees.iter() {
+            self.collect_inlinee(fun, inlinee, depth + 1, rows, inlines, source);
+        }
+    }
+
+    /// Get the lines of the innermost inlinees (instead of the ones of the call locations)
+    /// and the INLINE records
+    fn collect_inlines(
+        &mut self,
+        fun: &Function,
+        source: &mut SourceFiles,
+    ) -> (Lines, Vec<Inline>) {
+        let mut rows = BTreeMap::new();
+        let mut inlines = Vec::new();
+
+        Self::add_rows(fun, &mut rows, source);
+        for inlinee in fun.inlinees.iter() {
+            self.collect_inlinee(fun, inlinee, 0, &mut rows, &mut inlines, source);
+        }
+
+        let mut lines = Lines::new();
+        let mut last = None;
+        for (address, line_info) in rows {
+            if last.as_ref().map_or(true, |prev| *prev != line_info) {
+                lines.add_line(
+                    address as u32,
+                    line_info.line,
+                    source.get_true_id(line_info.file_id),
+                );
+                last = Some(line_info);
+            }
+        }
+
+        (lines, inlines)
+    }
+
+    pub fn collect_function(&mut self, fun: &Function, source: &mut SourceFiles) {
+        let name = Self::demangle(&fun.name, self.options.demangling);
+        self.collect_function_with_name(fun, name, source);
+    }
+
+    fn collect_function_with_name(
+        &mut self,
+        fun: &Function,
+        name: String,
+        source: &mut SourceFiles,
+    ) {
+        if fun.address == 0 && !self.relocatable {
+            return;
+        }
+
+        let (mut lines, inlines) = if self.options.inlines {
+            self.collect_inlines(fun, source)
+        } else {
+            (Self::collect_lines(fun, source), Vec::new())
+        };
+
         // compute line length
         lines.finalize(fun.address as u32, fun.size as u32, &());
 
-        self.syms.insert(
-            fun.address as u32,
-            ElfSymbol {
-                name: Self::demangle(&fun.name),
-                is_public: false,
-                is_multiple: false,
-                rva: fun.address as u32,
-                len: fun.size as u32,
-                parameter_size: 0,
-                source: lines,
-            },
-        );
+        if debug_records::matches(&name, fun.address, fun.size) {
+            let mut raw = String::new();
+            Self::debug_function(fun, String::new(), &mut raw);
+            debug_records::print("Raw DWARF rows", &name, fun.address, &raw);
+        }
+
+        self.insert_function(ElfSymbol {
+            name,
+            is_public: false,
+            is_multiple: false,
+            aliases: Vec::new(),
+            rva: fun.address as u32,
+            len: fun.size as u32,
+            parameter_size: 0,
+            source: lines,
+            inlines,
+        });
+    }
+
+    /// Insert a FUNC: when there is already one at its address (identical functions folded
+    /// by the linker), the smallest name is kept and the other one is an alias
+    fn insert_function(&mut self, sym: ElfSymbol) {
+        match self.syms.entry(sym.rva) {
+            btree_map::Entry::Occupied(mut e) if !e.get().is_public => {
+                let a_sym = e.get_mut();
+                let other = if sym.name < a_sym.name {
+                    // The smallest name comes with its lines
+                    let mut sym = sym;
+                    sym.aliases = std::mem::take(&mut a_sym.aliases);
+                    std::mem::swap(a_sym, &mut sym);
+                    sym
+                } else {
+                    sym
+                };
+                if a_sym.source.lines.is_empty() && a_sym.inlines.is_empty() {
+                    a_sym.source = other.source;
+                    a_sym.inlines = other.inlines;
+                }
+                a_sym.len = a_sym.len.max(other.len);
+                folding::fold_names(&mut a_sym.name, &mut a_sym.aliases, other.name);
+                a_sym.is_multiple = !a_sym.aliases.is_empty();
+            }
+            btree_map::Entry::Occupied(mut e) => {
+                e.insert(sym);
+            }
+            btree_map::Entry::Vacant(e) => {
+                e.insert(sym);
+            }
+        }
     }
 
     pub fn collect_functions(
@@ -301,14 +516,26 @@ impl Collector {
             unreachable!();
         };
 
-        for fun in ds.functions() {
-            match fun {
-                Ok(fun) => {
-                    self.collect_function(&fun, source);
-                }
-                Err(e) => {
-                    error!("Function collection: {:?}", e);
-                }
+        // The functions are decoded by batches and the names of a batch are demangled in parallel
+        let demangling = self.options.demangling;
+        let num_threads = self.options.num_threads;
+        let mut functions = ds.functions();
+        loop {
+            let batch: Vec<_> = functions.by_ref().take(FUNCTIONS_BATCH_SIZE).collect();
+            if batch.is_empty() {
+                break;
+            }
+            cancel::check()?;
+
+            let batch: Vec<_> = batch
+                .into_iter()
+                .filter_map(|fun| fun.map_err(|e| error!("Function collection: {:?}", e)).ok())
+                .collect();
+            let names = parallel::map(num_threads, &batch, |fun| {
+                Self::demangle(&fun.name, demangling)
+            });
+            for (fun, name) in batch.iter().zip(names) {
+                self.collect_function_with_name(fun, name, source);
             }
         }
 
@@ -316,25 +543,65 @@ impl Collector {
     }
 
     fn collect_publics(&mut self, o: &Object) {
+        if self.options.symtab_functions {
+            // The symbols without a size (e.g. in a Mach-O) end at the next one
+            for sym in o.symbol_map().iter() {
+                self.add_public(sym.name.as_deref(), sym.address as u32, sym.size as u32);
+            }
+            return;
+        }
         for sym in o.symbols() {
-            match self.syms.entry(sym.address as u32) {
-                btree_map::Entry::Occupied(_) => {}
-                btree_map::Entry::Vacant(e) => {
-                    let sym_name = sym.name.map_or_else(
-                        || "<name omitted>".to_string(),
-                        |n| Self::demangle_str(&n.to_owned()),
-                    );
-                    e.insert(ElfSymbol {
-                        name: sym_name,
-                        is_public: true,
-                        is_multiple: false,
-                        rva: sym.address as u32,
-                        len: sym.size as u32,
-                        parameter_size: 0,
-                        source: Lines::default(),
-                    });
+            self.add_public(sym.name.as_deref(), sym.address as u32, sym.size as u32);
+        }
+    }
+
+    /// Add a FUNC whose lines have been collected elsewhere (e.g. in .gopclntab)
+    pub(super) fn add_function(&mut self, name: &str, rva: u32, len: u32, source: Lines) {
+        self.insert_function(ElfSymbol {
+            name: name.to_string(),
+            is_public: false,
+            is_multiple: false,
+            aliases: Vec::new(),
+            rva,
+            len,
+            parameter_size: 0,
+            source,
+            inlines: Vec::new(),
+        });
+    }
+
+    /// Add a PUBLIC if there is nothing at this address
+    pub(super) fn add_public(&mut self, name: Option<&str>, rva: u32, len: u32) {
+        let demangling = self.options.demangling;
+        match self.syms.entry(rva) {
+            btree_map::Entry::Occupied(e) => {
+                if !e.get().is_public && self.options.publics_policy == PublicsPolicy::Keep {
+                    if let Some(name) = name {
+                        self.publics.entry(rva).or_insert_with(|| Public {
+                            rva,
+                            parameter_size: 0,
+                            name: Self::demangle_str(name, demangling),
+                        });
+                    }
                 }
             }
+            btree_map::Entry::Vacant(e) => {
+                let sym_name = name.map_or_else(
+                    || "<name omitted>".to_string(),
+                    |name| Self::demangle_str(name, demangling),
+                );
+                e.insert(ElfSymbol {
+                    name: sym_name,
+                    is_public: true,
+                    is_multiple: false,
+                    aliases: Vec::new(),
+                    rva,
+                    len,
+                    parameter_size: 0,
+                    source: Lines::default(),
+                    inlines: Vec::new(),
+                });
+            }
         }
     }
 
@@ -343,23 +610,279 @@ impl Collector {
         let writer = Cursor::new(&mut buf);
 
         let mut cfi_writer = AsciiCfiWriter::new(writer);
-        if let Err(e) = cfi_writer.process(o) {
+        let res = cfi_writer.process(o);
+        if let Err(e) = &res {
             error!("CFI: {:?}", e);
         }
+        let stack = String::from_utf8(buf).unwrap();
+
+        // The compact unwind info of a Mach-O file describes all its functions (the ones which
+        // can't be written in the compact format have an FDE in __eh_frame)
+        if o.file_format() == FileFormat::MachO {
+            let cfi = compact_unwind::get_stack_cfi(o.data(), o.load_address());
+            if !cfi.is_empty() {
+                return cfi;
+            }
+        }
 
-        String::from_utf8(buf).unwrap()
+        // Nothing from symbolic (e.g. an unreadable entry in .eh_frame): read the .eh_frame here
+        // and then the exception tables of a 32-bit ARM binary
+        if (res.is_err() || stack.is_empty()) && o.file_format() == FileFormat::Elf {
+            let mut cfi = eh_frame::get_stack_cfi(o.data(), o.load_address());
+            if cfi.is_empty() {
+                cfi = arm_exidx::get_stack_cfi(o.data(), o.load_address());
+            }
+            if !cfi.is_empty() {
+                return cfi;
+            }
+        }
+
+        stack
     }
 }
 
 impl ElfInfo {
     pub(crate) fn new(
         buf: &[u8],
+        path: &Path,
         file_name: &str,
         platform: Platform,
         mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
     ) -> common::Result<Self> {
+        // The debug sections can be compressed with zlib or zstd
+        let decompressed = decompression::decompress_sections(buf);
+        let buf = decompressed.as_deref().unwrap_or(buf);
+        // The sections of a relocatable object (e.g. a kernel module) have no address
+        let relocated = relocation::relocate(buf);
+        let relocatable = relocated.is_some();
+        let buf = relocated.as_deref().unwrap_or(buf);
         let o = Object::parse(&buf).map_err(|e| e.compat())?;
-        Self::from_object(&o, file_name, platform, mapping)
+        let load_address = o.load_address();
+        // A shared library is loaded with its soname (e.g. libfoo.so.1 for libfoo.so.1.2.3)
+        let soname = Self::get_soname(buf);
+        let file_name = soname.as_deref().unwrap_or(file_name);
+        let collect_functions =
+            |o: &Object, collector: &mut Collector, source: &mut SourceFiles| {
+                collector.relocatable = relocatable;
+                if collector.options.checksums {
+                    if let Err(e) = dwarf::collect_checksums(buf, source) {
+                        warn!("Cannot read the checksums of the files: {}", e);
+                    }
+                }
+                if !debuglink::collect_dwz_functions(buf, path, load_address, collector, source)?
+                    && !dwarf::collect_debug_addr_functions(buf, load_address, collector, source)?
+                {
+                    collector.collect_functions(o, source)?;
+                }
+                // A Go binary without DWARF has still its functions and lines in .gopclntab
+                if !o.has_debug_info() {
+                    gopclntab::collect_functions(buf, load_address, collector, source);
+                }
+                // The symbols of a stripped binary can be in .gnu_debugdata
+                minidebuginfo::collect_publics(buf, load_address, collector);
+                // The functions of the skeleton units (-gsplit-dwarf) are in some .dwo files
+                split_dwarf::collect_functions(buf, path, load_address, collector, source)
+            };
+        let mut info =
+            Self::from_object_with(&o, file_name, platform, mapping, options, collect_functions)?;
+        info.fix_split_debug(buf);
+
+        Ok(info)
+    }
+
+    /// Get the cpu of the MODULE record: symbolic doesn't know s390 and RISC-V
+    fn get_cpu_name(o: &Object) -> &'static str {
+        let arch = o.arch();
+        if arch != Arch::Unknown || o.file_format() != FileFormat::Elf {
+            return arch.name();
+        }
+        let elf = match Elf::parse(o.data()) {
+            Ok(elf) => elf,
+            _ => return arch.name(),
+        };
+        match (elf.header.e_machine, elf.is_64) {
+            (EM_S390, true) => "s390x",
+            (EM_S390, false) => "s390",
+            (EM_RISCV, true) => "riscv64",
+            (EM_RISCV, false) => "riscv32",
+            _ => arch.name(),
+        }
+    }
+
+    /// Get the DT_SONAME of a shared library.
+    /// The dynamic section of a split debug file is NOBITS so there is no soname.
+    fn get_soname(buf: &[u8]) -> Option<String> {
+        let elf = Elf::parse(buf).ok()?;
+        if elf.header.e_type != ET_DYN {
+            return None;
+        }
+
+        let has_dynamic = elf
+            .section_headers
+            .iter()
+            .any(|sh| sh.sh_type == SHT_DYNAMIC);
+        if !has_dynamic {
+            return None;
+        }
+
+        elf.soname
+            .filter(|soname| !soname.is_empty())
+            .map(|soname| soname.to_string())
+    }
+
+    /// Add the functions of an object file (from the debug map of a Mach-O): the ones at an address
+    /// of the map (the address in the object => the rva in the binary) are moved and the other ones
+    /// are dropped (e.g. dead-stripped by the linker).
+    pub(crate) fn with_object_functions(
+        self,
+        mut object: ElfInfo,
+        addresses: &HashMap<u32, u32>,
+    ) -> common::Result<Self> {
+        object.symbols = std::mem::take(&mut object.symbols)
+            .into_iter()
+            .filter(|(_, sym)| !sym.is_public)
+            .filter_map(|(rva, mut sym)| {
+                let rva = *addresses.get(&rva)?;
+                sym.rebase(rva);
+                Some((rva, sym))
+            })
+            .collect();
+        object.publics.clear();
+        object.stack.clear();
+        object.debug_id = self.debug_id.clone();
+        object.code_id = None;
+        object.file_name = self.file_name.clone();
+
+        Self::merge(self, object)
+    }
+
+    /// Set the module name (e.g. from the command line) in place of the soname or the file name.
+    pub(crate) fn with_module_name(mut self, name: Option<&str>) -> Self {
+        if let Some(name) = name {
+            let dummy = symbol::get_dummy_name(&self.file_name);
+            if let Some(sym) = self
+                .symbols
+                .values_mut()
+                .next_back()
+                .filter(|sym| sym.name == dummy)
+            {
+                sym.name = symbol::get_dummy_name(name);
+            }
+            self.file_name = name.to_string();
+        }
+        self
+    }
+
+    /// The debug info of a stripped binary can be in a separate file referenced
+    /// in its .gnu_debuglink section: in this case they're merged.
+    pub(crate) fn with_debug_link(
+        self,
+        buf: &[u8],
+        path: &Path,
+        mapping: Option<Arc<PathMappings>>,
+    ) -> common::Result<Self> {
+        if self.bin_type != Type::Stripped {
+            return Ok(self);
+        }
+        let debug_path = match debuglink::find_debug_file(buf, path, &self.options.debug_dirs) {
+            Some(debug_path) => debug_path,
+            _ => return Ok(self),
+        };
+
+        // The debug info are the ones of the binary so use its name
+        let debug_buf = utils::read_file(&debug_path);
+        let file_name = self.file_name.clone();
+        let debug = Self::new(
+            &debug_buf,
+            &debug_path,
+            &file_name,
+            self.platform,
+            mapping,
+            self.options.clone(),
+        )?;
+
+        Self::merge(self, debug)
+    }
+
+    /// A debug file created with objcopy --only-keep-debug has no code (.text is NOBITS)
+    /// and its program headers point to some data which aren't in the file anymore.
+    /// So the ids must be computed from the build-id note section only.
+    fn has_nobits_text(elf: &Elf) -> bool {
+        elf.section_headers.iter().any(|sh| {
+            sh.sh_type == SHT_NOBITS
+                && elf
+                    .shdr_strtab
+                    .get(sh.sh_name)
+                    .and_then(|name| name.ok())
+                    .map_or(false, |name| name == ".text")
+        })
+    }
+
+    /// Check if an ELF file is a split debug file (its code isn't in it)
+    pub(crate) fn is_split_debug(buf: &[u8]) -> bool {
+        match Elf::parse(buf) {
+            Ok(elf) => Self::has_nobits_text(&elf),
+            _ => false,
+        }
+    }
+
+    fn fix_split_debug(&mut self, buf: &[u8]) {
+        let elf = match Elf::parse(buf) {
+            Ok(elf) => elf,
+            _ => return,
+        };
+        if !Self::has_nobits_text(&elf) {
+            return;
+        }
+
+        let build_id = elf
+            .iter_note_sections(buf, Some(".note.gnu.build-id"))
+            .and_then(|mut notes| {
+                notes.find_map(|note| note.ok().filter(|n| n.n_type == NT_GNU_BUILD_ID))
+            })
+            .map(|note| note.desc)
+            .filter(|desc| !desc.is_empty());
+
+        if let Some(build_id) = build_id {
+            self.debug_id = Self::get_debug_id_from_build_id(build_id, elf.little_endian);
+            self.code_id = Some(
+                build_id
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<String>(),
+            );
+        } else {
+            warn!(
+                "The debug file {} has no build-id so its debug id is unknown: the stripped binary should be dumped with it",
+                self.file_name
+            );
+            self.unknown_id = true;
+        }
+    }
+
+    /// Compute the debug id the same way as Breakpad: the first 16 bytes of the build-id
+    /// are used as a uuid (in network byte order).
+    pub(crate) fn get_debug_id_from_build_id(build_id: &[u8], little_endian: bool) -> String {
+        let mut data = [0; 16];
+        let len = build_id.len().min(16);
+        data[..len].copy_from_slice(&build_id[..len]);
+
+        if little_endian {
+            data[0..4].reverse();
+            data[4..6].reverse();
+            data[6..8].reverse();
+        }
+
+        let uuid = Uuid::from_slice(&data).unwrap();
+        format!(
+            "{}0",
+            uuid.to_simple().encode_upper(&mut Uuid::encode_buffer())
+        )
+    }
+
+    pub fn get_cpu(&self) -> &str {
+        self.cpu
     }
 
     pub fn from_object(
@@ -367,27 +890,77 @@ impl ElfInfo {
         file_name: &str,
         platform: Platform,
         mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
     ) -> common::Result<Self> {
-        let mut collector = Collector::default();
+        Self::from_object_with(
+            o,
+            file_name,
+            platform,
+            mapping,
+            options,
+            |o, collector, source| collector.collect_functions(o, source),
+        )
+    }
+
+    /// Same as from_object but the functions are collected with the given function
+    /// (e.g. to get some functions which aren't in the object itself)
+    fn from_object_with<F>(
+        o: &Object,
+        file_name: &str,
+        platform: Platform,
+        mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
+        collect_functions: F,
+    ) -> common::Result<Self>
+    where
+        F: FnOnce(&Object, &mut Collector, &mut SourceFiles) -> common::Result<()>,
+    {
+        let mut collector = Collector {
+            options: options.clone(),
+            ..Default::default()
+        };
         let mut source = SourceFiles::new(mapping);
         let debug_id = format!("{}", o.debug_id().breakpad());
         let code_id = o.code_id().map(|c| c.as_str().to_string().to_uppercase());
-        let cpu = o.arch().name();
+        let cpu = Self::get_cpu_name(o);
         let bin_type = if o.has_debug_info() {
             Type::DebugInfo
         } else {
             Type::Stripped
         };
 
-        collector.collect_functions(&o, &mut source)?;
+        collect_functions(o, &mut collector, &mut source)?;
         collector.collect_publics(&o);
 
-        let stack = Collector::get_stack_info(&o);
-        let symbols = crate::linux::symbol::append_dummy_symbol(collector.syms, file_name);
+        // There is no unwind info in a wasm module
+        let stack = if platform == Platform::Wasm {
+            String::new()
+        } else {
+            Collector::get_stack_info(&o)
+        };
+        let mut symbols = collector.syms;
+        if options.publics_policy == PublicsPolicy::Dedup {
+            symbol::remove_covered_publics(&mut symbols);
+        }
+        let symbols = symbol::append_dummy_symbol(symbols, file_name);
+
+        for sym in symbols.values() {
+            if debug_records::matches(&sym.name, sym.rva as u64, sym.len as u64) {
+                debug_records::print(
+                    "Emitted records",
+                    &sym.name,
+                    sym.rva as u64,
+                    &Folded(sym, options.folding_policy).to_string(),
+                );
+            }
+        }
 
-        Ok(Self {
+        let symtab_functions = bin_type == Type::Stripped && options.symtab_functions;
+        let mut info = Self {
             symbols,
+            publics: collector.publics,
             files: source.get_mapping(),
+            inline_origins: collector.origins,
             file_name: String::from(file_name),
             cpu,
             debug_id,
@@ -395,12 +968,42 @@ impl ElfInfo {
             stack,
             bin_type,
             platform,
-        })
+            unknown_id: false,
+            symtab_functions,
+            options,
+        };
+        info.sort_records();
+
+        Ok(info)
+    }
+
+    /// Sort the FILE and the STACK records (the FUNC and PUBLIC ones are sorted by address)
+    /// so the output doesn't depend on the order of the data in the debug info
+    fn sort_records(&mut self) {
+        if let Some(remapping) = self.files.sort() {
+            for sym in self.symbols.values_mut() {
+                sym.remap_files(&remapping);
+            }
+        }
+        self.stack = common::sort_stack(&self.stack);
     }
 }
 
 impl Mergeable for ElfInfo {
-    fn merge(left: ElfInfo, right: ElfInfo) -> common::Result<ElfInfo> {
+    fn merge(mut left: ElfInfo, mut right: ElfInfo) -> common::Result<ElfInfo> {
+        // The ids of the binary are preferred to the ones of a split debug file without build-id
+        if left.unknown_id && !right.unknown_id {
+            left.debug_id = right.debug_id.clone();
+            left.code_id = right.code_id.clone();
+            left.cpu = right.cpu;
+            left.unknown_id = false;
+        } else if right.unknown_id && !left.unknown_id {
+            right.debug_id = left.debug_id.clone();
+            right.code_id = left.code_id.clone();
+            right.cpu = left.cpu;
+            right.unknown_id = false;
+        }
+
         if left.debug_id != right.debug_id {
             return Err(format!(
                 "The files don't have the same debug id: {} and {}",
@@ -429,6 +1032,8 @@ impl Mergeable for ElfInfo {
         // If the two files contains some FUNC they may have differents FILE number associated with
         // So merge them and get an array to remap files from 'right' with the new correct id
         let remapping = left.files.merge(&mut right.files);
+        let origins = left.inline_origins.merge(&right.inline_origins);
+        let policy = left.options.publics_policy;
 
         for (addr, sym) in right.symbols.iter_mut() {
             if sym.is_public {
@@ -437,7 +1042,7 @@ impl Mergeable for ElfInfo {
                 // Check that the symbol isn't inside another one (it happens sometimes IRL)
                 let last = left.symbols.range(0..*addr).next_back();
                 if let Some(last) = last {
-                    if *addr < last.1.rva + last.1.len {
+                    if *addr < last.1.rva + last.1.len && policy != PublicsPolicy::Keep {
                         continue;
                     }
                 }
@@ -445,7 +1050,11 @@ impl Mergeable for ElfInfo {
                 match left.symbols.entry(*addr) {
                     btree_map::Entry::Occupied(mut e) => {
                         // we already have one so just discard this one
-                        e.get_mut().is_multiple = true;
+                        let a_sym = e.get_mut();
+                        a_sym.is_multiple = true;
+                        if !a_sym.is_public && policy == PublicsPolicy::Keep {
+                            left.publics.entry(*addr).or_insert_with(|| sym.to_public());
+                        }
                     }
                     btree_map::Entry::Vacant(e) => {
                         e.insert(sym.clone());
@@ -461,18 +1070,28 @@ impl Mergeable for ElfInfo {
                     if a_sym.is_public {
                         // FUNC is more interesting than the PUBLIC
                         // so just keep the FUNC
-                        sym.fix_lines(remapping.as_ref());
+                        sym.fix_lines(remapping.as_ref(), &origins);
                         std::mem::swap(a_sym, sym);
+                        if policy == PublicsPolicy::Keep {
+                            left.publics.entry(*addr).or_insert_with(|| sym.to_public());
+                        }
                     }
                     a_sym.is_multiple = true;
                 }
                 btree_map::Entry::Vacant(e) => {
-                    sym.fix_lines(remapping.as_ref());
+                    sym.fix_lines(remapping.as_ref(), &origins);
                     e.insert(sym.clone());
                 }
             }
         }
 
+        for (addr, public) in right.publics {
+            left.publics.entry(addr).or_insert(public);
+        }
+        if policy == PublicsPolicy::Dedup {
+            symbol::remove_covered_publics(&mut left.symbols);
+        }
+
         if left.code_id.is_none() && right.code_id.is_some() {
             left.code_id = right.code_id;
         }
@@ -480,9 +1099,16 @@ impl Mergeable for ElfInfo {
         if right.bin_type == Type::Stripped {
             left.file_name = right.file_name;
         }
+        left.symtab_functions &= right.symtab_functions;
+
+        left.sort_records();
 
         Ok(left)
     }
+
+    fn has_unknown_id(&self) -> bool {
+        self.unknown_id
+    }
 }
 
 impl Dumpable for ElfInfo {
@@ -491,6 +1117,45 @@ impl Dumpable for ElfInfo {
         Ok(())
     }
 
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        let platform = self.platform.to_string();
+        visitor::visit_module(
+            visitor,
+            &platform,
+            self.cpu,
+            &self.debug_id,
+            &self.file_name,
+        );
+        if let Some(code_id) = self.code_id.as_ref() {
+            visitor::visit_code_id(visitor, code_id, None);
+        }
+        visitor::visit_files(
+            visitor,
+            self.files.get_mapping(),
+            Some(self.files.get_checksums()),
+        );
+        visitor::visit_inline_origins(visitor, &self.inline_origins);
+
+        let policy = self.options.folding_policy;
+        for (rva, sym) in self.symbols.iter() {
+            if self.symtab_functions && sym.is_public && sym.len != 0 {
+                // A FUNC without lines from the symbol table
+                let func = ElfSymbol {
+                    is_public: false,
+                    ..sym.clone()
+                };
+                Folded(&func, policy).visit(visitor);
+            } else {
+                Folded(sym, policy).visit(visitor);
+            }
+            if let Some(public) = self.publics.get(rva) {
+                visitor::visit_public(visitor, public);
+            }
+        }
+
+        visitor::visit_text(visitor, &self.stack)
+    }
+
     fn get_debug_id(&self) -> &str {
         &self.debug_id
     }
@@ -498,4 +1163,261 @@ impl Dumpable for ElfInfo {
     fn get_name(&self) -> &str {
         &self.file_name
     }
+
+    fn get_source_files(&self) -> Vec<(String, PathBuf)> {
+        self.files.get_source_files()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_debug_addr() {
+        // No unit with its addresses in .debug_addr: the skeleton units are skipped
+        for name in &["libbasic.so.1.2.3", "basic_split.full"] {
+            let buf = std::fs::read(format!("./test_data/linux/{}", name)).unwrap();
+            let mut collector = Collector::default();
+            let mut source = SourceFiles::new(None);
+            assert!(
+                !dwarf::collect_debug_addr_functions(&buf, 0, &mut collector, &mut source).unwrap()
+            );
+        }
+
+        // The DWARF 5 units (with DW_FORM_line_strp in the line table) read with gimli
+        let buf = std::fs::read("./test_data/linux/libbasic.so.1.2.3").unwrap();
+        let o = Object::parse(&buf).unwrap();
+        let load_address = o.load_address();
+        let from_gimli = ElfInfo::from_object_with(
+            &o,
+            "libbasic.so.1",
+            Platform::Linux,
+            None,
+            Arc::default(),
+            |_, c, s| {
+                let elf = Elf::parse(&buf)?;
+                let dwarf = dwarf::load_dwarf(&elf, &buf, None)?;
+                let references = dwarf::References::new(&dwarf, None)?;
+                let mut units = Vec::new();
+                let mut headers = dwarf.units();
+                while let Some(header) = headers.next()? {
+                    units.push(dwarf.unit(header)?);
+                }
+                dwarf::collect_units(&dwarf, &units, &references, load_address, c, s)
+            },
+        )
+        .unwrap();
+        let from_symbolic =
+            ElfInfo::from_object(&o, "libbasic.so.1", Platform::Linux, None, Arc::default())
+                .unwrap();
+        let from_gimli = from_gimli.to_string();
+        let from_symbolic = from_symbolic.to_string();
+
+        // The same lines and files but foo doesn't get the name of its alias in the symtab
+        assert!(from_gimli.contains("\nFUNC 11b8 d9 0 foo(int)\n"));
+        let records = |s: &str| -> Vec<String> {
+            s.lines()
+                .filter(|l| !l.starts_with("FUNC "))
+                .map(String::from)
+                .collect()
+        };
+        assert_eq!(records(&from_gimli), records(&from_symbolic));
+    }
+
+    #[test]
+    fn test_dwarf5_checksums() {
+        // The MD5 of the source files are in the file table of a DWARF 5 line program
+        let buf = std::fs::read("./test_data/linux/basic_md5.full").unwrap();
+        let o = Object::parse(&buf).unwrap();
+        let info = ElfInfo::from_object_with(
+            &o,
+            "basic_md5.full",
+            Platform::Linux,
+            None,
+            Arc::default(),
+            |o, c, s| {
+                dwarf::collect_checksums(&buf, s)?;
+                c.collect_functions(o, s)
+            },
+        )
+        .unwrap();
+        let info = info.to_string();
+        let files: Vec<_> = info.lines().filter(|l| l.starts_with("FILE ")).collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("/basic.cpp md5:c09b70580fba44c4ef9a9808ce7a99cf"));
+    }
+
+    #[test]
+    fn test_symtab_functions() {
+        let path = PathBuf::from("./test_data/linux/basic.nodebug");
+        let buf = std::fs::read(&path).unwrap();
+        let mut info = ElfInfo::new(
+            &buf,
+            &path,
+            "basic.nodebug",
+            Platform::Linux,
+            None,
+            Arc::default(),
+        )
+        .unwrap();
+        assert_eq!(info.bin_type, Type::Stripped);
+        assert!(info.to_string().contains("\nPUBLIC 12bd 0 main\n"));
+
+        // With --symtab-functions: the symbols with a size are some FUNC
+        info.symtab_functions = true;
+        let records: Vec<_> = info
+            .to_string()
+            .lines()
+            .filter(|l| l.starts_with("FUNC ") || l.starts_with("PUBLIC "))
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            &records[..9],
+            &[
+                "PUBLIC 1000 0 _init",
+                "FUNC 1040 2b 0 _start",
+                "PUBLIC 1070 0 deregister_tm_clones",
+                "PUBLIC 10a0 0 register_tm_clones",
+                "PUBLIC 10e0 0 __do_global_dtors_aux",
+                "PUBLIC 1120 0 frame_dummy",
+                "FUNC 1125 26 0 inline_1(int)",
+                "FUNC 114b 2c 0 inline_2(int)",
+                "FUNC 1177 33 0 inline_3(int)",
+            ]
+        );
+        assert!(records.contains(&"FUNC 12bd 1c 0 main".to_string()));
+    }
+
+    #[test]
+    fn test_object_functions() {
+        let dir = PathBuf::from("./test_data/linux");
+        let buf = std::fs::read(dir.join("basic.full")).unwrap();
+        let object = ElfInfo::new(
+            &buf,
+            &dir,
+            "basic.full",
+            Platform::Linux,
+            None,
+            Arc::default(),
+        )
+        .unwrap();
+        let buf = std::fs::read(dir.join("basic.nodebug")).unwrap();
+        let binary = ElfInfo::new(
+            &buf,
+            &dir,
+            "basic.nodebug",
+            Platform::Linux,
+            None,
+            Arc::default(),
+        )
+        .unwrap();
+
+        // main stays at 0x12bd and inline_1(int) is moved from 0x1125 to 0x1025: the other
+        // functions of the object aren't in the binary
+        let addresses = [(0x12bd, 0x12bd), (0x1125, 0x1025)]
+            .iter()
+            .cloned()
+            .collect();
+        let info = binary.with_object_functions(object, &addresses).unwrap();
+        assert_eq!(info.file_name, "basic.nodebug");
+        let funcs: Vec<_> = info.symbols.values().filter(|sym| !sym.is_public).collect();
+        assert_eq!(funcs.len(), 2);
+        assert_eq!(
+            (funcs[0].name.as_str(), funcs[0].rva, funcs[0].len),
+            ("inline_1(int)", 0x1025, 0x26)
+        );
+        assert!(funcs[0]
+            .source
+            .lines
+            .iter()
+            .all(|line| line.rva >= 0x1025 && line.rva < 0x1025 + 0x26));
+        assert_eq!((funcs[1].name.as_str(), funcs[1].rva), ("main", 0x12bd));
+    }
+
+    #[test]
+    fn test_inlines() {
+        let buf = std::fs::read("./test_data/linux/basic.full").unwrap();
+        let o = Object::parse(&buf).unwrap();
+        let ds = o.debug_session().unwrap();
+        let fun = ds
+            .functions()
+            .filter_map(|fun| fun.ok())
+            .find(|fun| fun.name.as_str() == "_Z3fooi")
+            .unwrap();
+
+        let mut collector = Collector::default();
+        let mut source = SourceFiles::new(None);
+        let (lines, inlines) = collector.collect_inlines(&fun, &mut source);
+
+        // inline_4 is called at line 26 and inlines inline_3, inline_2 and inline_1
+        let records: Vec<_> = inlines.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            &records[..4],
+            &[
+                "INLINE 0 26 0 0 120e 20\n",
+                "INLINE 1 20 0 1 120e 1f\n",
+                "INLINE 2 15 0 2 120e 1e\n",
+                "INLINE 3 10 0 3 120e 1d\n",
+            ]
+        );
+        assert_eq!(
+            collector.origins.to_string(),
+            "INLINE_ORIGIN 0 inline_4(int)\nINLINE_ORIGIN 1 inline_3(int)\n\
+             INLINE_ORIGIN 2 inline_2(int)\nINLINE_ORIGIN 3 inline_1(int)\n"
+        );
+
+        // The lines in the inlinees are the ones of inline_1
+        let line = lines.lines.iter().find(|l| l.rva == 0x120e).unwrap();
+        assert_eq!(line.num, 3);
+    }
+
+    #[test]
+    fn test_folded_functions() {
+        let mut lines = Lines::new();
+        lines.add_line(0x1000, 12, 0);
+
+        // Whatever the order, the smallest name is kept with the lines
+        let mut collector = Collector::default();
+        collector.add_function("foo", 0x1000, 0x10, Lines::new());
+        collector.add_function("bar", 0x1000, 0x10, lines);
+        collector.add_function("baz", 0x1000, 0x10, Lines::new());
+        collector.add_function("foo", 0x1000, 0x10, Lines::new());
+        collector.add_function("qux", 0x2000, 0x10, Lines::new());
+
+        let sym = &collector.syms[&0x1000];
+        assert_eq!(sym.name, "bar");
+        assert_eq!(sym.aliases, vec!["baz", "foo"]);
+        assert!(sym.is_multiple);
+        assert_eq!(sym.source.lines.len(), 1);
+        assert!(!collector.syms[&0x2000].is_multiple);
+    }
+
+    #[test]
+    fn test_big_endian() {
+        // Some executables and some objects (the addresses in the debug sections are relocated)
+        for name in &[
+            "basic_s390x.full",
+            "basic_s390x.o",
+            "basic_ppc64.full",
+            "basic_ppc64.o",
+        ] {
+            let path = PathBuf::from(format!("./test_data/linux/{}", name));
+            let buf = std::fs::read(&path).unwrap();
+            let info =
+                ElfInfo::new(&buf, &path, name, Platform::Linux, None, Arc::default()).unwrap();
+            let mut output = Vec::new();
+            info.dump(&mut output).unwrap();
+
+            let expected =
+                std::fs::read_to_string(format!("./test_data/linux/{}.sym", name)).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                expected,
+                "Bad dump for {}",
+                name
+            );
+        }
+    }
 }
@@ -0,0 +1,530 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Go binaries are often built without DWARF (-ldflags "-w") but the runtime needs
+// the .gopclntab section to print the stack traces: it contains the functions with
+// their names and some pc-value tables for their files and their lines.
+// See https://golang.org/src/debug/gosym/pclntab.go for the different versions.
+
+use goblin::elf::Elf;
+use log::{info, warn};
+use std::convert::TryInto;
+use symbolic::debuginfo::FileInfo;
+
+use super::dwarf::get_section;
+use super::elf::Collector;
+use super::source::SourceFiles;
+use crate::common::LineFinalizer;
+use crate::line::Lines;
+
+const GO12_MAGIC: u32 = 0xffff_fffb;
+const GO116_MAGIC: u32 = 0xffff_fffa;
+const GO118_MAGIC: u32 = 0xffff_fff0;
+const GO120_MAGIC: u32 = 0xffff_fff1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Version {
+    Go12,
+    Go116,
+    Go118,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) struct GoLine<'a> {
+    pub address: u64,
+    pub file: &'a [u8],
+    pub line: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) struct GoFunction<'a> {
+    pub name: String,
+    pub entry: u64,
+    pub end: u64,
+    pub lines: Vec<GoLine<'a>>,
+}
+
+struct PcLnTab<'a> {
+    version: Version,
+    little_endian: bool,
+    quantum: u64,
+    ptr_size: usize,
+    nfunc: usize,
+    text_start: u64,
+    funcnames: &'a [u8],
+    cutab: &'a [u8],
+    filetab: &'a [u8],
+    pctab: &'a [u8],
+    funcdata: &'a [u8],
+    functab: &'a [u8],
+}
+
+fn read_u32(data: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+    Some(if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    })
+}
+
+fn read_uintptr(data: &[u8], offset: usize, ptr_size: usize, little_endian: bool) -> Option<u64> {
+    if ptr_size == 4 {
+        return read_u32(data, offset, little_endian).map(u64::from);
+    }
+    let bytes = data.get(offset..offset.checked_add(8)?)?.try_into().ok()?;
+    Some(if little_endian {
+        u64::from_le_bytes(bytes)
+    } else {
+        u64::from_be_bytes(bytes)
+    })
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let (byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_str(data: &[u8], offset: usize) -> Option<&[u8]> {
+    let data = data.get(offset..)?;
+    let len = data.iter().position(|c| *c == 0)?;
+    Some(&data[..len])
+}
+
+impl<'a> PcLnTab<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let (version, little_endian) = [true, false].iter().find_map(|&le| {
+            let version = match read_u32(data, 0, le)? {
+                GO12_MAGIC => Version::Go12,
+                GO116_MAGIC => Version::Go116,
+                GO118_MAGIC | GO120_MAGIC => Version::Go118,
+                _ => return None,
+            };
+            Some((version, le))
+        })?;
+
+        let quantum = *data.get(6)?;
+        let ptr_size = *data.get(7)? as usize;
+        if data[4..6] != [0, 0] || ![1, 2, 4].contains(&quantum) || ![4, 8].contains(&ptr_size) {
+            return None;
+        }
+        let word = |i: usize| read_uintptr(data, 8 + i * ptr_size, ptr_size, little_endian);
+        let slice = |i: usize| data.get(word(i)? as usize..);
+        let nfunc = word(0)? as usize;
+
+        let tab = match version {
+            Version::Go12 => {
+                let functab = data.get(8 + ptr_size..)?;
+                let filetab_offset = read_u32(functab, (2 * nfunc + 1) * ptr_size, little_endian)?;
+                Self {
+                    version,
+                    little_endian,
+                    quantum: quantum.into(),
+                    ptr_size,
+                    nfunc,
+                    text_start: 0,
+                    funcnames: data,
+                    cutab: &[],
+                    filetab: data.get(filetab_offset as usize..)?,
+                    pctab: data,
+                    funcdata: data,
+                    functab,
+                }
+            }
+            Version::Go116 => Self {
+                version,
+                little_endian,
+                quantum: quantum.into(),
+                ptr_size,
+                nfunc,
+                text_start: 0,
+                funcnames: slice(2)?,
+                cutab: slice(3)?,
+                filetab: slice(4)?,
+                pctab: slice(5)?,
+                funcdata: slice(6)?,
+                functab: slice(6)?,
+            },
+            Version::Go118 => Self {
+                version,
+                little_endian,
+                quantum: quantum.into(),
+                ptr_size,
+                nfunc,
+                text_start: word(2)?,
+                funcnames: slice(3)?,
+                cutab: slice(4)?,
+                filetab: slice(5)?,
+                pctab: slice(6)?,
+                funcdata: slice(7)?,
+                functab: slice(7)?,
+            },
+        };
+
+        Some(tab)
+    }
+
+    /// Get the entry point of the function i and the offset of its data in funcdata
+    fn get_func(&self, i: usize) -> Option<(u64, usize)> {
+        if self.version == Version::Go118 {
+            let entry = read_u32(self.functab, 8 * i, self.little_endian)?;
+            let offset = read_u32(self.functab, 8 * i + 4, self.little_endian)?;
+            Some((self.text_start + u64::from(entry), offset as usize))
+        } else {
+            let size = self.ptr_size;
+            let entry = read_uintptr(self.functab, 2 * size * i, size, self.little_endian)?;
+            let offset = read_uintptr(self.functab, (2 * i + 1) * size, size, self.little_endian)?;
+            Some((entry, offset as usize))
+        }
+    }
+
+    /// Read the field n of a _func structure (the first one is the entry point)
+    fn field(&self, func: usize, n: usize) -> Option<u32> {
+        let first = if self.version == Version::Go118 {
+            4
+        } else {
+            self.ptr_size
+        };
+        read_u32(
+            self.funcdata,
+            func + first + (n - 1) * 4,
+            self.little_endian,
+        )
+    }
+
+    fn get_file(&self, cu_offset: u32, index: i32) -> Option<&'a [u8]> {
+        if index < 0 {
+            return None;
+        }
+        let offset = if self.version == Version::Go12 {
+            read_u32(self.filetab, 4 * index as usize, self.little_endian)?
+        } else {
+            let i = cu_offset.checked_add(index as u32)? as usize;
+            read_u32(self.cutab, 4 * i, self.little_endian).filter(|o| *o != u32::MAX)?
+        };
+
+        // The offsets of the file names are from the beginning of the table in Go 1.2
+        let names = if self.version == Version::Go12 {
+            self.funcnames
+        } else {
+            self.filetab
+        };
+        read_str(names, offset as usize).filter(|name| !name.is_empty())
+    }
+
+    /// Decode a pc-value table: each value is valid from its address to the next one
+    fn get_values(&self, offset: u32, entry: u64, end: u64) -> Option<Vec<(u64, i32)>> {
+        let mut data = self.pctab.get(offset as usize..)?;
+        let mut values = Vec::new();
+        let mut pc = entry;
+        let mut value = -1i32;
+
+        while pc < end {
+            let delta = read_varint(&mut data)?;
+            if delta == 0 && pc != entry {
+                break;
+            }
+            let delta = if delta & 1 != 0 {
+                !(delta >> 1)
+            } else {
+                delta >> 1
+            };
+            value = value.wrapping_add(delta as i32);
+            values.push((pc, value));
+            pc += u64::from(read_varint(&mut data)?) * self.quantum;
+        }
+
+        Some(values)
+    }
+
+    fn get_lines(&self, func: usize, entry: u64, end: u64) -> Option<Vec<GoLine<'a>>> {
+        let pcfile = self.field(func, 5)?;
+        let pcln = self.field(func, 6)?;
+        let cu_offset = if self.version == Version::Go12 {
+            0
+        } else {
+            self.field(func, 8)?
+        };
+        if pcfile == 0 || pcln == 0 {
+            return Some(Vec::new());
+        }
+
+        let files = self.get_values(pcfile, entry, end)?;
+        let lines = self.get_values(pcln, entry, end)?;
+        let value_at = |values: &[(u64, i32)], pc: u64| {
+            let i = match values.binary_search_by_key(&pc, |(start, _)| *start) {
+                Ok(i) => i,
+                Err(i) => i.checked_sub(1)?,
+            };
+            Some(values[i].1)
+        };
+
+        let mut addresses: Vec<_> = files.iter().chain(lines.iter()).map(|v| v.0).collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+
+        let mut res: Vec<GoLine> = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let file = value_at(&files, address).and_then(|i| self.get_file(cu_offset, i));
+            let line = value_at(&lines, address).filter(|l| *l > 0);
+            if let (Some(file), Some(line)) = (file, line) {
+                let line = line as u32;
+                if res
+                    .last()
+                    .map_or(true, |l| l.file != file || l.line != line)
+                {
+                    res.push(GoLine {
+                        address,
+                        file,
+                        line,
+                    });
+                }
+            }
+        }
+
+        Some(res)
+    }
+
+    fn get_functions(&self) -> Option<Vec<GoFunction<'a>>> {
+        let mut functions = Vec::with_capacity(self.nfunc);
+        for i in 0..self.nfunc {
+            let (entry, func) = self.get_func(i)?;
+            // The table ends with the end of the last function
+            let (end, _) = self.get_func(i + 1)?;
+            let name = read_str(self.funcnames, self.field(func, 1)? as usize)?;
+            functions.push(GoFunction {
+                name: String::from_utf8_lossy(name).to_string(),
+                entry,
+                end,
+                lines: self.get_lines(func, entry, end)?,
+            });
+        }
+
+        Some(functions)
+    }
+}
+
+/// Get the functions with their lines from the content of a .gopclntab section
+pub(super) fn parse(data: &[u8]) -> Option<Vec<GoFunction>> {
+    PcLnTab::new(data)?.get_functions()
+}
+
+/// Add the functions of the .gopclntab section when the binary has no debug info
+pub(super) fn collect_functions(
+    buf: &[u8],
+    load_address: u64,
+    collector: &mut Collector,
+    source: &mut SourceFiles,
+) {
+    let elf = match Elf::parse(buf) {
+        Ok(elf) => elf,
+        _ => return,
+    };
+    let data = get_section(&elf, buf, ".gopclntab");
+    if data.is_empty() {
+        return;
+    }
+
+    let functions = match parse(data) {
+        Some(functions) => functions,
+        _ => {
+            warn!("Unable to parse the .gopclntab section");
+            return;
+        }
+    };
+
+    let count = add_functions(&functions, load_address, collector, source);
+    info!("{} functions found in .gopclntab", count);
+}
+
+/// Add the functions with their lines to the collector and get how many have been added
+fn add_functions(
+    functions: &[GoFunction],
+    load_address: u64,
+    collector: &mut Collector,
+    source: &mut SourceFiles,
+) -> usize {
+    let mut count = 0;
+    for fun in functions.iter().filter(|f| f.entry >= load_address) {
+        let rva = (fun.entry - load_address) as u32;
+        // The entries are sorted in a valid table
+        let len = match fun.end.checked_sub(fun.entry) {
+            Some(len) => len as u32,
+            None => {
+                warn!("Invalid range for {} in .gopclntab", fun.name);
+                continue;
+            }
+        };
+        let mut lines = Lines::new();
+        for line in fun.lines.iter() {
+            let file = FileInfo {
+                name: line.file,
+                dir: &[],
+            };
+            let file_id = source.get_id(&[], &file);
+            lines.add_line(
+                (line.address - load_address) as u32,
+                line.line,
+                source.get_true_id(file_id),
+            );
+        }
+        lines.finalize(rva, len, &());
+        collector.add_function(&fun.name, rva, len, lines);
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn push_varint(buf: &mut Vec<u8>, mut value: u32) {
+        while value >= 0x80 {
+            buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    /// Encode a pc-value table from some (value, size) pairs
+    fn pcvalue(buf: &mut Vec<u8>, values: &[(i32, u32)]) -> u32 {
+        let offset = buf.len() as u32;
+        let mut prev = -1;
+        for (value, size) in values {
+            let delta = value - prev;
+            push_varint(buf, ((delta << 1) ^ (delta >> 31)) as u32);
+            push_varint(buf, *size);
+            prev = *value;
+        }
+        buf.push(0);
+        offset
+    }
+
+    /// A Go 1.20 table with main.main at 0x401000 and main.foo (inlined in main.main) at 0x401040
+    fn build_pclntab() -> Vec<u8> {
+        let funcnames = b"\0main.main\0main.foo\0";
+        let filetab = b"\0/src/main.go\0/src/foo.go\0";
+        let mut cutab = Vec::new();
+        for offset in &[1u32, 14] {
+            cutab.extend_from_slice(&offset.to_le_bytes());
+        }
+        let mut pctab = vec![0];
+        let main_file = pcvalue(&mut pctab, &[(0, 0x20), (1, 0x10), (0, 0x10)]);
+        let main_line = pcvalue(&mut pctab, &[(5, 0x10), (6, 0x10), (12, 0x10), (7, 0x10)]);
+        let foo_file = pcvalue(&mut pctab, &[(1, 0x20)]);
+        let foo_line = pcvalue(&mut pctab, &[(11, 0x8), (12, 0x18)]);
+
+        // The functab (2 functions and the end) followed by the _func structures
+        let mut funcdata = Vec::new();
+        for word in &[0u32, 24, 0x40, 68, 0x60] {
+            funcdata.extend_from_slice(&word.to_le_bytes());
+        }
+        funcdata.extend_from_slice(&0u32.to_le_bytes());
+        for (entry, name, pcfile, pcln) in &[
+            (0u32, 1u32, main_file, main_line),
+            (0x40, 11, foo_file, foo_line),
+        ] {
+            for word in &[*entry, *name, 0, 0, 0, *pcfile, *pcln, 0, 0, 0, 0] {
+                funcdata.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        let header_size = 8 + 8 * 8;
+        let mut offset = header_size;
+        let mut offsets = Vec::new();
+        for table in &[&funcnames[..], &cutab, &filetab[..], &pctab] {
+            offsets.push(offset as u64);
+            offset += table.len();
+        }
+        offsets.push(offset as u64);
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&GO120_MAGIC.to_le_bytes());
+        buf.extend_from_slice(&[0, 0, 1, 8]);
+        for word in [2u64, 2, 0x401000].iter().chain(offsets.iter()) {
+            buf.extend_from_slice(&word.to_le_bytes());
+        }
+        buf.extend_from_slice(funcnames);
+        buf.extend_from_slice(&cutab);
+        buf.extend_from_slice(filetab);
+        buf.extend_from_slice(&pctab);
+        buf.extend_from_slice(&funcdata);
+
+        buf
+    }
+
+    #[test]
+    fn test_parse() {
+        let buf = build_pclntab();
+        let main_go = &b"/src/main.go"[..];
+        let foo_go = &b"/src/foo.go"[..];
+        let line = |address, file, line| GoLine {
+            address,
+            file,
+            line,
+        };
+
+        assert_eq!(
+            parse(&buf).unwrap(),
+            vec![
+                GoFunction {
+                    name: "main.main".to_string(),
+                    entry: 0x401000,
+                    end: 0x401040,
+                    lines: vec![
+                        line(0x401000, main_go, 5),
+                        line(0x401010, main_go, 6),
+                        line(0x401020, foo_go, 12),
+                        line(0x401030, main_go, 7),
+                    ],
+                },
+                GoFunction {
+                    name: "main.foo".to_string(),
+                    entry: 0x401040,
+                    end: 0x401060,
+                    lines: vec![line(0x401040, foo_go, 11), line(0x401048, foo_go, 12)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_functions() {
+        let buf = build_pclntab();
+        let mut functions = parse(&buf).unwrap();
+        // An entry of a corrupted table ending before its beginning is skipped
+        functions.push(GoFunction {
+            name: "main.bar".to_string(),
+            entry: 0x401060,
+            end: 0x401050,
+            lines: Vec::new(),
+        });
+
+        let mut collector = Collector::default();
+        let mut source = SourceFiles::new(None);
+        assert_eq!(
+            add_functions(&functions, 0x400000, &mut collector, &mut source),
+            2
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut buf = build_pclntab();
+        assert!(parse(&buf[..40]).is_none());
+        buf[0] = 0;
+        assert!(parse(&buf).is_none());
+    }
+}
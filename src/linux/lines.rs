@@ -8,6 +8,32 @@ use crate::line::Lines;
 
 impl LineFinalizer<()> for Lines {
     fn finalize(&mut self, sym_rva: u32, sym_len: u32, _map: &()) {
+        // With the linker relaxation (e.g. on RISC-V), the code between two rows can be removed:
+        // some rows are at the same address (the last one wins) or after the end of the function
+        if sym_len != 0 {
+            self.lines
+                .retain(|line| line.rva >= sym_rva && line.rva - sym_rva < sym_len);
+        }
         self.compute_len(sym_rva, sym_len);
+        self.lines.retain(|line| line.len != 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_relaxed_lines() {
+        let mut lines = Lines::new();
+        lines.add_line(0x1000, 3, 0);
+        lines.add_line(0x1004, 4, 0);
+        lines.add_line(0x1004, 5, 0);
+        lines.add_line(0x1010, 6, 0);
+        lines.add_line(0x1020, 7, 0);
+        lines.finalize(0x1000, 0x20, &());
+
+        assert_eq!(lines.to_string(), "1000 4 3 0\n1004 c 5 0\n1010 10 6 0\n");
     }
 }
@@ -0,0 +1,110 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Some distributions (e.g. Fedora) keep a minimal symbol table in their stripped binaries:
+// the .gnu_debugdata section contains an xz-compressed ELF file whose .symtab contains
+// the functions which aren't in the .dynsym (MiniDebugInfo in gdb).
+
+use goblin::elf::{sym::STT_FUNC, Elf};
+use log::{info, warn};
+
+use super::dwarf::get_section;
+use super::elf::Collector;
+use crate::common;
+
+fn decompress(data: &[u8]) -> common::Result<Vec<u8>> {
+    let mut input = data;
+    let mut output = Vec::new();
+    lzma_rs::xz_decompress(&mut input, &mut output)?;
+
+    Ok(output)
+}
+
+/// Add the functions of the symbol table in .gnu_debugdata as PUBLIC
+/// when the binary has no symbol table itself.
+pub(super) fn collect_publics(buf: &[u8], load_address: u64, collector: &mut Collector) {
+    let elf = match Elf::parse(buf) {
+        Ok(elf) => elf,
+        _ => return,
+    };
+    if !elf.syms.is_empty() {
+        return;
+    }
+
+    let data = get_section(&elf, buf, ".gnu_debugdata");
+    if data.is_empty() {
+        return;
+    }
+
+    let mini = match decompress(data) {
+        Ok(mini) => mini,
+        Err(e) => {
+            warn!("Unable to decompress the .gnu_debugdata section: {}", e);
+            return;
+        }
+    };
+    let mini_elf = match Elf::parse(&mini) {
+        Ok(mini_elf) => mini_elf,
+        Err(e) => {
+            warn!("Unable to parse the .gnu_debugdata section: {}", e);
+            return;
+        }
+    };
+
+    let mut count = 0;
+    for sym in mini_elf.syms.iter() {
+        if sym.st_type() != STT_FUNC || sym.st_value < load_address {
+            continue;
+        }
+        let name = mini_elf.strtab.get(sym.st_name).and_then(|n| n.ok());
+        collector.add_public(
+            name.filter(|n| !n.is_empty()),
+            (sym.st_value - load_address) as u32,
+            sym.st_size as u32,
+        );
+        count += 1;
+    }
+
+    info!("{} functions found in .gnu_debugdata", count);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::common::Dumpable;
+    use crate::linux::elf::{ElfInfo, Platform};
+
+    #[test]
+    fn test_minidebuginfo() {
+        let path = PathBuf::from("./test_data/linux/basic_minidebuginfo.stripped");
+        let buf = fs::read(&path).unwrap();
+        let info =
+            ElfInfo::new(&buf, &path, "basic", Platform::Linux, None, Arc::default()).unwrap();
+        let mut output = Vec::new();
+        info.dump(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let publics: Vec<_> = output
+            .lines()
+            .filter(|l| l.starts_with("PUBLIC "))
+            .map(|l| l.splitn(4, ' ').last().unwrap())
+            .collect();
+
+        assert!(!output.contains("\nFUNC "));
+        for name in &[
+            "inline_1(int)",
+            "inline_2(int)",
+            "inline_3(int)",
+            "inline_4(int)",
+            "foo(int)",
+            "main",
+        ] {
+            assert!(publics.contains(name), "{} is missing", name);
+        }
+    }
+}
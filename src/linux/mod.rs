@@ -5,6 +5,16 @@
 
 pub mod elf;
 
+mod arm_exidx;
+pub(crate) mod core_dump;
+pub(crate) mod debuglink;
+mod decompression;
+mod dwarf;
+pub(crate) mod eh_frame;
+mod gopclntab;
 mod lines;
+mod minidebuginfo;
+mod relocation;
 mod source;
+pub(crate) mod split_dwarf;
 mod symbol;
@@ -0,0 +1,419 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// In a relocatable object (e.g. a kernel module) all the sections are at address 0
+// and the addresses in the debug sections are some relocations.
+// So the allocated sections are laid out like the kernel module loader does (the code
+// first, then the read-only data and then the writable data) and the relocations of
+// the debug sections are applied on a copy of the file. A PT_LOAD segment is added at
+// the base of the layout so the addresses in the output are the offsets from it.
+
+use goblin::elf::{
+    header::{EM_386, EM_AARCH64, EM_PPC64, EM_RISCV, EM_S390, EM_X86_64, ET_REL},
+    program_header::{PF_R, PF_X, PT_LOAD},
+    reloc::{
+        R_386_32, R_386_PC32, R_AARCH64_ABS32, R_AARCH64_ABS64, R_AARCH64_PREL32, R_AARCH64_PREL64,
+        R_X86_64_32, R_X86_64_32S, R_X86_64_64, R_X86_64_PC32, R_X86_64_PC64,
+    },
+    section_header::{SectionHeader, SHF_COMPRESSED, SHN_ABS, SHN_LORESERVE, SHT_SYMTAB},
+    Elf,
+};
+use log::{info, warn};
+
+// The DWARF rows at address 0 are skipped (code removed by the linker) so the layout
+// doesn't start at 0
+const LAYOUT_BASE: u64 = 0x1000;
+
+// Not in goblin yet
+const R_390_32: u32 = 4;
+const R_390_PC32: u32 = 5;
+const R_390_64: u32 = 22;
+const R_390_PC64: u32 = 23;
+const R_PPC64_ADDR32: u32 = 1;
+const R_PPC64_REL32: u32 = 26;
+const R_PPC64_ADDR64: u32 = 38;
+const R_PPC64_REL64: u32 = 44;
+const R_RISCV_32: u32 = 1;
+const R_RISCV_64: u32 = 2;
+const R_RISCV_ADD8: u32 = 33;
+const R_RISCV_ADD16: u32 = 34;
+const R_RISCV_ADD32: u32 = 35;
+const R_RISCV_ADD64: u32 = 36;
+const R_RISCV_SUB8: u32 = 37;
+const R_RISCV_SUB16: u32 = 38;
+const R_RISCV_SUB32: u32 = 39;
+const R_RISCV_SUB64: u32 = 40;
+const R_RISCV_SUB6: u32 = 52;
+const R_RISCV_SET6: u32 = 53;
+const R_RISCV_SET8: u32 = 54;
+const R_RISCV_SET16: u32 = 55;
+const R_RISCV_SET32: u32 = 56;
+const R_RISCV_32_PCREL: u32 = 57;
+
+struct Writer {
+    buf: Vec<u8>,
+    is_64: bool,
+    little_endian: bool,
+}
+
+impl Writer {
+    fn read(&self, offset: usize, size: usize) -> Option<u64> {
+        let data = self.buf.get(offset..offset.checked_add(size)?)?;
+        let mut bytes = [0; 8];
+        Some(if self.little_endian {
+            bytes[..size].copy_from_slice(data);
+            u64::from_le_bytes(bytes)
+        } else {
+            bytes[8 - size..].copy_from_slice(data);
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        self.read(offset, 4).map(|value| value as u32)
+    }
+
+    fn write(&mut self, offset: usize, size: usize, value: u64) -> Option<()> {
+        let data = self.buf.get_mut(offset..offset.checked_add(size)?)?;
+        let bytes = if self.little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        };
+        if self.little_endian {
+            data.copy_from_slice(&bytes[..size]);
+        } else {
+            data.copy_from_slice(&bytes[8 - size..]);
+        }
+        Some(())
+    }
+
+    fn write_word(&mut self, offset: usize, value: u64) -> Option<()> {
+        self.write(offset, if self.is_64 { 8 } else { 4 }, value)
+    }
+
+    /// Append a PT_LOAD program header for the layout (a relocatable object has none)
+    fn add_segment(&mut self, start: u64, end: u64) -> Option<()> {
+        let offset = (self.buf.len() + 7) & !7;
+        let flags = u64::from(PF_R | PF_X);
+        let (sizes, values, entsize, phoff, phentsize) = if self.is_64 {
+            // p_type, p_flags, p_offset, p_vaddr, p_paddr, p_filesz, p_memsz, p_align
+            let sizes = [4, 4, 8, 8, 8, 8, 8, 8];
+            let values = [
+                PT_LOAD.into(),
+                flags,
+                0,
+                start,
+                start,
+                0,
+                end - start,
+                0x1000,
+            ];
+            (sizes, values, 56, 0x20, 0x36)
+        } else {
+            // p_type, p_offset, p_vaddr, p_paddr, p_filesz, p_memsz, p_flags, p_align
+            let sizes = [4; 8];
+            let values = [
+                PT_LOAD.into(),
+                0,
+                start,
+                start,
+                0,
+                end - start,
+                flags,
+                0x1000,
+            ];
+            (sizes, values, 32, 0x1c, 0x2a)
+        };
+        self.buf.resize(offset + entsize, 0);
+
+        let mut field_offset = offset;
+        for (size, value) in sizes.iter().zip(values.iter()) {
+            self.write(field_offset, *size, *value)?;
+            field_offset += size;
+        }
+        // e_phoff, e_phentsize and e_phnum
+        self.write_word(phoff, offset as u64)?;
+        self.write(phentsize, 2, entsize as u64)?;
+        self.write(phentsize + 2, 2, 1)
+    }
+}
+
+/// Get the address of each allocated section in the layout and the end of the layout
+fn layout(sections: &[SectionHeader]) -> (Vec<Option<u64>>, u64) {
+    let mut addresses = vec![None; sections.len()];
+    let mut address = LAYOUT_BASE;
+    let classes: [&dyn Fn(&SectionHeader) -> bool; 3] = [
+        &|sh| sh.is_executable(),
+        &|sh| !sh.is_executable() && !sh.is_writable(),
+        &|sh| sh.is_writable(),
+    ];
+
+    for class in classes.iter() {
+        for (i, sh) in sections.iter().enumerate() {
+            if !sh.is_alloc() || sh.sh_size == 0 || !class(sh) {
+                continue;
+            }
+            let align = sh.sh_addralign.max(1);
+            address = (address + align - 1) & !(align - 1);
+            addresses[i] = Some(address);
+            address += sh.sh_size;
+        }
+    }
+
+    (addresses, address)
+}
+
+/// Compute the RISC-V relocations: because of the linker relaxation, the distances between two
+/// labels (e.g. a length or an advance in a line table) are some pairs ADD/SUB applied on the
+/// value at the place
+fn compute_riscv<F: Fn(usize) -> Option<u64>>(
+    typ: u32,
+    abs: u64,
+    rel: u64,
+    current: F,
+) -> Option<(u64, usize)> {
+    let size = match typ {
+        R_RISCV_ADD8 | R_RISCV_SUB8 | R_RISCV_SET8 | R_RISCV_SUB6 | R_RISCV_SET6 => 1,
+        R_RISCV_ADD16 | R_RISCV_SUB16 | R_RISCV_SET16 => 2,
+        R_RISCV_32 | R_RISCV_ADD32 | R_RISCV_SUB32 | R_RISCV_SET32 | R_RISCV_32_PCREL => 4,
+        R_RISCV_64 | R_RISCV_ADD64 | R_RISCV_SUB64 => 8,
+        _ => return None,
+    };
+    let value = match typ {
+        R_RISCV_32 | R_RISCV_64 | R_RISCV_SET8 | R_RISCV_SET16 | R_RISCV_SET32 => abs,
+        R_RISCV_32_PCREL => rel,
+        R_RISCV_ADD8 | R_RISCV_ADD16 | R_RISCV_ADD32 | R_RISCV_ADD64 => {
+            current(size)?.wrapping_add(abs)
+        }
+        R_RISCV_SUB8 | R_RISCV_SUB16 | R_RISCV_SUB32 | R_RISCV_SUB64 => {
+            current(size)?.wrapping_sub(abs)
+        }
+        // The 6 low bits of a byte (e.g. in DW_CFA_advance_loc)
+        R_RISCV_SET6 => (current(size)? & 0xc0) | (abs & 0x3f),
+        _ => {
+            let current = current(size)?;
+            (current & 0xc0) | (current.wrapping_sub(abs) & 0x3f)
+        }
+    };
+    Some((value, size))
+}
+
+/// Compute the value of a relocation and its size (None when its type isn't supported)
+fn compute<F: Fn(usize) -> Option<u64>>(
+    machine: u16,
+    typ: u32,
+    sym: u64,
+    addend: i64,
+    place: u64,
+    current: F,
+) -> Option<(u64, usize)> {
+    let abs = sym.wrapping_add(addend as u64);
+    let rel = abs.wrapping_sub(place);
+    if machine == EM_RISCV {
+        return compute_riscv(typ, abs, rel, current);
+    }
+    match (machine, typ) {
+        (EM_X86_64, R_X86_64_64)
+        | (EM_AARCH64, R_AARCH64_ABS64)
+        | (EM_S390, R_390_64)
+        | (EM_PPC64, R_PPC64_ADDR64) => Some((abs, 8)),
+        (EM_X86_64, R_X86_64_32)
+        | (EM_X86_64, R_X86_64_32S)
+        | (EM_AARCH64, R_AARCH64_ABS32)
+        | (EM_386, R_386_32)
+        | (EM_S390, R_390_32)
+        | (EM_PPC64, R_PPC64_ADDR32) => Some((abs, 4)),
+        (EM_X86_64, R_X86_64_PC64)
+        | (EM_AARCH64, R_AARCH64_PREL64)
+        | (EM_S390, R_390_PC64)
+        | (EM_PPC64, R_PPC64_REL64) => Some((rel, 8)),
+        (EM_X86_64, R_X86_64_PC32)
+        | (EM_AARCH64, R_AARCH64_PREL32)
+        | (EM_386, R_386_PC32)
+        | (EM_S390, R_390_PC32)
+        | (EM_PPC64, R_PPC64_REL32) => Some((rel, 4)),
+        _ => None,
+    }
+}
+
+/// The relocations are only applied to the debug sections and to .eh_frame
+fn is_relocated(elf: &Elf, sh: &SectionHeader) -> bool {
+    if sh.sh_flags & u64::from(SHF_COMPRESSED) != 0 {
+        return false;
+    }
+    !sh.is_alloc() || elf.shdr_strtab.get(sh.sh_name).and_then(|n| n.ok()) == Some(".eh_frame")
+}
+
+/// Get a copy of a relocatable object where the allocated sections have an address
+/// and the relocations of the debug sections are applied (None if it isn't a relocatable object)
+pub(super) fn relocate(buf: &[u8]) -> Option<Vec<u8>> {
+    let elf = Elf::parse(buf).ok()?;
+    if elf.header.e_type != ET_REL {
+        return None;
+    }
+    let machine = elf.header.e_machine;
+    if ![EM_X86_64, EM_AARCH64, EM_386, EM_S390, EM_PPC64, EM_RISCV].contains(&machine) {
+        warn!(
+            "The relocations for the machine {} aren't supported",
+            machine
+        );
+        return None;
+    }
+
+    let (addresses, end) = layout(&elf.section_headers);
+    let address = |shndx: usize| addresses.get(shndx).copied().flatten();
+    let sym_value = |index: usize| {
+        elf.syms.get(index).map_or(0, |sym| match sym.st_shndx {
+            i if i == SHN_ABS as usize => sym.st_value,
+            i if i < SHN_LORESERVE as usize => address(i).map_or(0, |a| a + sym.st_value),
+            _ => 0,
+        })
+    };
+
+    let mut writer = Writer {
+        buf: buf.to_vec(),
+        is_64: elf.is_64,
+        little_endian: elf.little_endian,
+    };
+    let mut count = 0;
+    let mut unsupported = 0;
+
+    for (index, relocs) in elf.shdr_relocs.iter() {
+        let target_index = elf.section_headers[*index].sh_info as usize;
+        let target = match elf.section_headers.get(target_index) {
+            Some(target) if is_relocated(&elf, target) => target,
+            _ => continue,
+        };
+
+        for reloc in relocs.iter() {
+            let offset = (target.sh_offset + reloc.r_offset) as usize;
+            // The addend of a REL relocation is the value at the place
+            let addend = match reloc.r_addend {
+                Some(addend) => addend,
+                _ => i64::from(writer.read_u32(offset)? as i32),
+            };
+            let place = address(target_index).unwrap_or(0) + reloc.r_offset;
+            let current = |size| writer.read(offset, size);
+            match compute(
+                machine,
+                reloc.r_type,
+                sym_value(reloc.r_sym),
+                addend,
+                place,
+                current,
+            ) {
+                Some((value, size)) => {
+                    writer.write(offset, size, value)?;
+                    count += 1;
+                }
+                _ => unsupported += 1,
+            }
+        }
+    }
+
+    if unsupported != 0 {
+        warn!("{} relocations with an unsupported type", unsupported);
+    }
+    info!("{} relocations applied", count);
+
+    // The symbols in the allocated sections
+    if let Some(symtab) = elf
+        .section_headers
+        .iter()
+        .find(|sh| sh.sh_type == SHT_SYMTAB)
+    {
+        let (entsize, value_offset) = if elf.is_64 { (24, 8) } else { (16, 4) };
+        for (i, sym) in elf.syms.iter().enumerate() {
+            if let Some(address) = address(sym.st_shndx) {
+                let offset = symtab.sh_offset as usize + i * entsize + value_offset;
+                writer.write_word(offset, address + sym.st_value)?;
+            }
+        }
+    }
+
+    // The addresses of the allocated sections
+    let (shoff, shentsize) = (elf.header.e_shoff, elf.header.e_shentsize);
+    let addr_offset = if elf.is_64 { 16 } else { 12 };
+    for (i, address) in addresses.iter().enumerate() {
+        if let Some(address) = address {
+            let offset = shoff as usize + i * shentsize as usize + addr_offset;
+            writer.write_word(offset, *address)?;
+        }
+    }
+
+    writer.add_segment(LAYOUT_BASE, end)?;
+
+    Some(writer.buf)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::common::Dumpable;
+    use crate::linux::elf::{ElfInfo, Platform};
+
+    #[test]
+    fn test_relocatable() {
+        let path = PathBuf::from("./test_data/linux/basic.ko");
+        let buf = fs::read(&path).unwrap();
+        let info = ElfInfo::new(
+            &buf,
+            &path,
+            "basic.ko",
+            Platform::Linux,
+            None,
+            Arc::default(),
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        info.dump(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        // Each function is in its own section: they're laid out one after the other
+        let funcs: Vec<_> = output.lines().filter(|l| l.starts_with("FUNC ")).collect();
+        assert_eq!(
+            funcs,
+            vec![
+                "FUNC 0 26 0 inline_1(int)",
+                "FUNC 26 2c 0 inline_2(int)",
+                "FUNC 52 33 0 inline_3(int)",
+                "FUNC 85 3a 0 inline_4(int)",
+                "FUNC bf d9 0 foo(int)",
+                "FUNC 198 1c 0 main",
+            ]
+        );
+        assert!(output.contains("\nFUNC 198 1c 0 main\n198 f 35 0\n1a7 b 36 0\n1b2 2 37 0\n"));
+    }
+
+    #[test]
+    fn test_riscv() {
+        // A length (e.g. DW_AT_high_pc) between two labels in .text at 0x1000: .L2 - .L1
+        let current = |_| Some(0);
+        let (value, size) = compute(EM_RISCV, R_RISCV_ADD32, 0x1000, 0x24, 0, current).unwrap();
+        assert_eq!((value, size), (0x1024, 4));
+        let current = |_| Some(value);
+        let (value, size) = compute(EM_RISCV, R_RISCV_SUB32, 0x1000, 0x4, 0, current).unwrap();
+        assert_eq!((value, size), (0x20, 4));
+
+        // DW_CFA_advance_loc (0x40) with a delta of 6
+        let current = |_| Some(0x40);
+        assert_eq!(
+            compute(EM_RISCV, R_RISCV_SET6, 0x1000, 0xa, 0, current),
+            Some((0x4a, 1))
+        );
+        let current = |_| Some(0x4a);
+        assert_eq!(
+            compute(EM_RISCV, R_RISCV_SUB6, 0x1000, 0x4, 0, current),
+            Some((0x46, 1))
+        );
+        assert_eq!(compute(EM_RISCV, 51, 0x1000, 0, 0, |_| Some(0)), None);
+    }
+}
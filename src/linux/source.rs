@@ -10,7 +10,10 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use symbolic::debuginfo::FileInfo;
 
+use crate::checksums::{Checksum, Checksums};
+use crate::common;
 use crate::mapping::PathMappings;
+use crate::sources::{self, SourcePaths};
 use crate::utils;
 
 type SliceRef = (*const u8, usize);
@@ -22,12 +25,17 @@ pub struct SourceFiles {
     id_to_ref: Vec<String>,
     cache: HashMap<(SliceRef, SliceRef, SliceRef), u32>,
     mapping: Option<Arc<PathMappings>>,
+    // The checksums from the line tables (by path) and the ones of the files with an id (by name)
+    path_checksums: HashMap<PathBuf, Checksum>,
+    checksums: Checksums,
+    paths: SourcePaths,
 }
 
 #[derive(Debug, Default)]
 pub struct SourceMap {
-    ref_to_id: HashMap<PathBuf, u32>,
     id_to_ref: Vec<String>,
+    checksums: Checksums,
+    paths: SourcePaths,
 }
 
 impl SourceFiles {
@@ -74,6 +82,17 @@ impl SourceFiles {
         }
     }
 
+    /// Set the checksum of a file (it must be set before the file gets an id)
+    pub(super) fn add_checksum(
+        &mut self,
+        compilation_dir: &[u8],
+        file: &FileInfo,
+        checksum: Checksum,
+    ) {
+        let path = Self::get_path(compilation_dir, file);
+        self.path_checksums.insert(path, checksum);
+    }
+
     /// For a given compilation_dir, file return the index in self.ref_to_id
     pub(super) fn get_id(&mut self, compilation_dir: &[u8], file: &FileInfo) -> u32 {
         // A lot of paths are a slice on the same string in the debug file
@@ -103,7 +122,16 @@ impl SourceFiles {
                         } else {
                             None
                         };
-                        let path = new_path.unwrap_or_else(|| path.to_str().unwrap().to_string());
+                        let path = match new_path {
+                            Some(new_path) => {
+                                self.paths.insert(new_path.clone(), path);
+                                new_path
+                            }
+                            _ => path.to_str().unwrap().to_string(),
+                        };
+                        if let Some(checksum) = self.path_checksums.get(e.key()) {
+                            self.checksums.insert(path.clone(), checksum.clone());
+                        }
                         e.insert(id);
                         self.fake_id_to_ref.push((None, path));
                         id
@@ -131,8 +159,9 @@ impl SourceFiles {
 
     pub(super) fn get_mapping(self) -> SourceMap {
         SourceMap {
-            ref_to_id: self.ref_to_id,
             id_to_ref: self.id_to_ref,
+            checksums: self.checksums,
+            paths: self.paths,
         }
     }
 }
@@ -142,7 +171,22 @@ impl SourceMap {
         &self.id_to_ref
     }
 
+    pub(super) fn get_checksums(&self) -> &Checksums {
+        &self.checksums
+    }
+
+    pub(super) fn get_source_files(&self) -> Vec<(String, PathBuf)> {
+        sources::get_source_files(&self.id_to_ref, &self.paths)
+    }
+
     pub(super) fn merge(&mut self, other: &mut SourceMap) -> Option<Vec<u32>> {
+        for (name, checksum) in other.checksums.drain() {
+            self.checksums.entry(name).or_insert(checksum);
+        }
+        for (name, path) in other.paths.drain() {
+            self.paths.entry(name).or_insert(path);
+        }
+
         // No FUNC so nothing to do
         if other.id_to_ref.is_empty() {
             return None;
@@ -151,27 +195,32 @@ impl SourceMap {
         // This one has no FUNC
         if self.id_to_ref.is_empty() {
             // Just steal the data from the other
-            std::mem::swap(&mut self.ref_to_id, &mut other.ref_to_id);
             std::mem::swap(&mut self.id_to_ref, &mut other.id_to_ref);
             return None;
         }
 
-        // will contain the new ids
-        let mut remapping = vec![0; other.id_to_ref.len()];
-        self.id_to_ref.reserve(other.id_to_ref.len());
+        let mut ids: HashMap<String, u32> = self.id_to_ref.iter().cloned().zip(0..).collect();
 
-        for (path, id) in other.ref_to_id.iter() {
-            let id = *id as usize;
-            if let Some(an_id) = self.ref_to_id.get(path) {
+        // will contain the new ids
+        let remapping = other
+            .id_to_ref
+            .iter()
+            .map(|path| match ids.entry(path.clone()) {
                 // self has already this path so map the id to the existing one
-                remapping[id] = *an_id;
-            } else {
-                let new_id = self.id_to_ref.len() as u32;
-                remapping[id] = new_id;
-                self.id_to_ref.push(other.id_to_ref[id].clone());
-            }
-        }
+                hash_map::Entry::Occupied(e) => *e.get(),
+                hash_map::Entry::Vacant(e) => {
+                    let new_id = self.id_to_ref.len() as u32;
+                    self.id_to_ref.push(path.clone());
+                    *e.insert(new_id)
+                }
+            })
+            .collect();
 
         Some(remapping)
     }
+
+    /// Sort the files by name and get the new ids (if they've changed)
+    pub(super) fn sort(&mut self) -> Option<Vec<u32>> {
+        common::sort_files(&mut self.id_to_ref)
+    }
 }
@@ -0,0 +1,459 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With -gsplit-dwarf, the binary only contains some skeleton units: the line tables are there
+// but the DIEs (functions, inlinees, ...) are in .dwo files (one per compilation unit) or
+// in a .dwp package. So here we read the DIEs from the dwo files and we use the line table
+// from the skeleton unit to build the functions.
+
+use gimli::{
+    constants, AttributeValue, Dwarf, DwarfFileType, DwoId, EndianSlice, Reader, SectionId, Unit,
+    UnitType,
+};
+use goblin::elf::Elf;
+use hashbrown::HashMap;
+use log::{error, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::decompression;
+use super::dwarf::{get_endian, get_section, load_dwarf, DwarfUnit, LineTable, Slice};
+use super::elf::Collector;
+use super::source::SourceFiles;
+use crate::common;
+
+/// Where to find the dwo files when they aren't at the place written in the skeleton units
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SplitDwarfOptions {
+    dwp: Option<PathBuf>,
+    dwo_dir: Option<PathBuf>,
+}
+
+/// Get the options from the .dwp package and/or the directory containing the .dwo files
+pub(crate) fn get_options(
+    dwp: Option<&str>,
+    dwo_dir: Option<&str>,
+) -> common::Result<SplitDwarfOptions> {
+    let dwp = dwp.map(PathBuf::from);
+    if let Some(dwp) = dwp.as_ref() {
+        if !dwp.is_file() {
+            return Err(format!("The dwp file {} doesn't exist", dwp.display()).into());
+        }
+    }
+
+    let dwo_dir = dwo_dir.map(PathBuf::from);
+    if let Some(dwo_dir) = dwo_dir.as_ref() {
+        if !dwo_dir.is_dir() {
+            return Err(format!("The dwo directory {} doesn't exist", dwo_dir.display()).into());
+        }
+    }
+
+    Ok(SplitDwarfOptions { dwp, dwo_dir })
+}
+
+/// A row of the index of a dwp package: for each section, the offset and the size
+/// of the contribution of the unit.
+#[derive(Debug)]
+struct DwpRow {
+    sections: Vec<(u32, u32, u32)>,
+}
+
+// The section identifiers used in the dwp index (DW_SECT_*)
+const DW_SECT_INFO: u32 = 1;
+const DW_SECT_ABBREV: u32 = 3;
+const DW_SECT_LINE: u32 = 4;
+const DW_SECT_LOC: u32 = 5;
+const DW_SECT_STR_OFFSETS: u32 = 6;
+const DW_SECT_RNGLISTS: u32 = 8;
+
+/// The .debug_cu_index section of a dwp package (version 2 for GNU DWARF 4 and 5 for DWARF 5)
+#[derive(Debug, Default)]
+struct DwpIndex {
+    rows: HashMap<u64, DwpRow>,
+}
+
+impl DwpIndex {
+    fn parse(data: Slice) -> gimli::Result<Self> {
+        let mut reader = data;
+        if reader.is_empty() {
+            return Ok(Self::default());
+        }
+
+        // version is an u32 in v2 and an u16 followed by 2 padding bytes in v5
+        let version = reader.read_u32()?;
+        let version = if version == 2 || version == 5 {
+            version
+        } else if version >> 16 == 5 {
+            5
+        } else {
+            return Err(gimli::Error::UnknownVersion(u64::from(version)));
+        };
+
+        let n_columns = reader.read_u32()? as usize;
+        let n_units = reader.read_u32()? as usize;
+        let n_slots = reader.read_u32()? as usize;
+
+        let mut signatures = Vec::with_capacity(n_slots);
+        for _ in 0..n_slots {
+            signatures.push(reader.read_u64()?);
+        }
+        let mut indices = Vec::with_capacity(n_slots);
+        for _ in 0..n_slots {
+            indices.push(reader.read_u32()? as usize);
+        }
+
+        let mut columns = Vec::with_capacity(n_columns);
+        for _ in 0..n_columns {
+            columns.push(reader.read_u32()?);
+        }
+
+        let mut offsets = Vec::with_capacity(n_units * n_columns);
+        for _ in 0..n_units * n_columns {
+            offsets.push(reader.read_u32()?);
+        }
+        let mut sizes = Vec::with_capacity(n_units * n_columns);
+        for _ in 0..n_units * n_columns {
+            sizes.push(reader.read_u32()?);
+        }
+
+        let mut rows = HashMap::default();
+        for (signature, index) in signatures.into_iter().zip(indices) {
+            // index is 1-based and 0 means an empty slot
+            if index == 0 || index > n_units {
+                continue;
+            }
+            let row = (index - 1) * n_columns;
+            let sections = columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| (*column, offsets[row + i], sizes[row + i]))
+                .collect();
+            rows.insert(signature, DwpRow { sections });
+        }
+
+        info!(
+            "Found {} units in the dwp index (version {})",
+            rows.len(),
+            version
+        );
+
+        Ok(Self { rows })
+    }
+}
+
+impl DwpRow {
+    fn get_section<'a>(&self, id: SectionId, data: &'a [u8]) -> &'a [u8] {
+        let column = match id {
+            SectionId::DebugInfo => DW_SECT_INFO,
+            SectionId::DebugAbbrev => DW_SECT_ABBREV,
+            SectionId::DebugLine => DW_SECT_LINE,
+            SectionId::DebugLoc | SectionId::DebugLocLists => DW_SECT_LOC,
+            SectionId::DebugStrOffsets => DW_SECT_STR_OFFSETS,
+            SectionId::DebugRngLists => DW_SECT_RNGLISTS,
+            // The strings are shared by all the units
+            _ => return data,
+        };
+
+        match self.sections.iter().find(|(c, _, _)| *c == column) {
+            Some((_, offset, size)) => {
+                let start = *offset as usize;
+                let end = start.saturating_add(*size as usize);
+                data.get(start..end).unwrap_or(&[])
+            }
+            // Not in the index: the unit has no contribution in this section
+            _ => &[],
+        }
+    }
+}
+
+/// Load the dwo sections of a .dwo file or of a unit in a .dwp package
+fn load_dwo<'a>(elf: &Elf, buf: &'a [u8], row: Option<&DwpRow>) -> Dwarf<Slice<'a>> {
+    let endian = get_endian(elf);
+    let mut dwarf = Dwarf::load(
+        |id| -> gimli::Result<Slice<'a>> {
+            let data = id.dwo_name().map_or(&[][..], |n| get_section(elf, buf, n));
+            let data = row.map_or(data, |row| row.get_section(id, data));
+            Ok(EndianSlice::new(data, endian))
+        },
+        |_| -> gimli::Result<Slice<'a>> { Ok(EndianSlice::new(&[], endian)) },
+    )
+    .unwrap();
+    dwarf.file_type = DwarfFileType::Dwo;
+
+    dwarf
+}
+
+struct Skeleton {
+    dwo_id: Option<DwoId>,
+    dwo_name: String,
+}
+
+impl Skeleton {
+    fn new(dwarf: &Dwarf<Slice>, unit: &Unit<Slice>) -> gimli::Result<Option<Self>> {
+        let mut dwo_id = match unit.header.type_() {
+            UnitType::Skeleton(dwo_id) => Some(dwo_id),
+            _ => None,
+        };
+        let mut dwo_name = None;
+
+        let mut entries = unit.entries();
+        let root = match entries.next_dfs()? {
+            Some((_, root)) => root,
+            _ => return Ok(None),
+        };
+        let mut attrs = root.attrs();
+        while let Some(attr) = attrs.next()? {
+            match attr.name() {
+                constants::DW_AT_dwo_name | constants::DW_AT_GNU_dwo_name => {
+                    dwo_name = Some(dwarf.attr_string(unit, attr.value())?);
+                }
+                constants::DW_AT_GNU_dwo_id => {
+                    if let AttributeValue::DwoId(id) = attr.value() {
+                        dwo_id = Some(id);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(dwo_name.map(|name| Self {
+            dwo_id,
+            dwo_name: name.to_string_lossy().to_string(),
+        }))
+    }
+
+    /// The dwo file is generally at comp_dir/dwo_name
+    fn find_dwo(
+        &self,
+        path: &Path,
+        comp_dir: Option<&str>,
+        dwo_dir: Option<&Path>,
+    ) -> Option<PathBuf> {
+        let dwo_name = PathBuf::from(&self.dwo_name);
+        let file_name = PathBuf::from(dwo_name.file_name()?);
+        let bin_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+
+        let mut candidates = Vec::new();
+        if let Some(dwo_dir) = dwo_dir {
+            candidates.push(dwo_dir.join(&dwo_name));
+            candidates.push(dwo_dir.join(&file_name));
+        }
+        if let Some(comp_dir) = comp_dir {
+            candidates.push(PathBuf::from(comp_dir).join(&dwo_name));
+        }
+        candidates.push(bin_dir.join(&dwo_name));
+        candidates.push(bin_dir.join(&file_name));
+
+        candidates.into_iter().find(|p| p.is_file())
+    }
+}
+
+/// Find the split unit corresponding to the skeleton one in a dwo file or in a dwp unit
+fn find_split_unit<'a>(
+    dwarf: &Dwarf<Slice<'a>>,
+    dwo_id: Option<DwoId>,
+) -> gimli::Result<Option<Unit<Slice<'a>>>> {
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let id = match unit.header.type_() {
+            UnitType::SplitCompilation(id) => Some(id),
+            UnitType::Compilation => {
+                let mut entries = unit.entries();
+                match entries.next_dfs()? {
+                    Some((_, root)) => match root.attr_value(constants::DW_AT_GNU_dwo_id)? {
+                        Some(AttributeValue::DwoId(id)) => Some(id),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            _ => continue,
+        };
+
+        if dwo_id.is_none() || id.is_none() || id == dwo_id {
+            return Ok(Some(unit));
+        }
+    }
+
+    Ok(None)
+}
+
+fn collect_split_unit<'a>(
+    main: &Dwarf<Slice<'a>>,
+    skeleton: &Unit<Slice<'a>>,
+    dwo: &mut Dwarf<Slice<'a>>,
+    dwo_id: Option<DwoId>,
+    load_address: u64,
+    collector: &mut Collector,
+    source: &mut SourceFiles,
+) -> gimli::Result<bool> {
+    // The addresses are always in the binary
+    dwo.debug_addr = main.debug_addr;
+
+    let mut unit = match find_split_unit(dwo, dwo_id)? {
+        Some(unit) => unit,
+        _ => return Ok(false),
+    };
+    unit.copy_relocated_attributes(skeleton);
+    if unit.header.version() < 5 {
+        // With the GNU extension, the ranges are in the binary too
+        dwo.ranges = main.ranges;
+    }
+
+    let lines = LineTable::new(main, skeleton)?;
+    let comp_dir = skeleton.comp_dir.map_or(&[][..], |d| d.slice());
+    DwarfUnit::new(dwo, &unit, &lines, comp_dir, load_address).collect(collector, source)?;
+
+    Ok(true)
+}
+
+/// Collect the functions in the dwo files (or in the dwp package) for the skeleton units in buf
+pub(crate) fn collect_functions(
+    buf: &[u8],
+    path: &Path,
+    load_address: u64,
+    collector: &mut Collector,
+    source: &mut SourceFiles,
+) -> common::Result<()> {
+    let elf = match Elf::parse(buf) {
+        Ok(elf) => elf,
+        _ => return Ok(()),
+    };
+    let main = load_dwarf(&elf, buf, None)?;
+
+    let mut skeletons = Vec::new();
+    let mut units = main.units();
+    while let Some(header) = units.next()? {
+        let unit = main.unit(header)?;
+        if let Some(skeleton) = Skeleton::new(&main, &unit)? {
+            skeletons.push((unit, skeleton));
+        }
+    }
+
+    if skeletons.is_empty() {
+        return Ok(());
+    }
+
+    let options = collector.options.split_dwarf.clone();
+
+    // By default the dwp package is next to the binary: foo => foo.dwp
+    let dwp_path = options.dwp.clone().unwrap_or_else(|| {
+        let mut dwp = path.as_os_str().to_os_string();
+        dwp.push(".dwp");
+        PathBuf::from(dwp)
+    });
+    let dwp_buf = if dwp_path.is_file() {
+        info!("Use the dwp package {}", dwp_path.display());
+        Some(decompression::decompress_file(fs::read(&dwp_path)?))
+    } else {
+        None
+    };
+    let dwp_elf = dwp_buf.as_ref().and_then(|buf| Elf::parse(buf).ok());
+    let dwp_index = match (dwp_buf.as_ref(), dwp_elf.as_ref()) {
+        (Some(buf), Some(elf)) => DwpIndex::parse(EndianSlice::new(
+            get_section(elf, buf, ".debug_cu_index"),
+            get_endian(elf),
+        ))?,
+        _ => DwpIndex::default(),
+    };
+
+    let mut missing = 0;
+    for (unit, skeleton) in skeletons.iter() {
+        // First try in the dwp package
+        let row = skeleton.dwo_id.and_then(|id| dwp_index.rows.get(&id.0));
+        if let (Some(row), Some(buf), Some(elf)) = (row, dwp_buf.as_ref(), dwp_elf.as_ref()) {
+            let mut dwo = load_dwo(elf, buf, Some(row));
+            if collect_split_unit(
+                &main,
+                unit,
+                &mut dwo,
+                skeleton.dwo_id,
+                load_address,
+                collector,
+                source,
+            )? {
+                continue;
+            }
+        }
+
+        let comp_dir = unit.comp_dir.map(|d| d.to_string_lossy().to_string());
+        let dwo_path =
+            match skeleton.find_dwo(path, comp_dir.as_deref(), options.dwo_dir.as_deref()) {
+                Some(dwo_path) => dwo_path,
+                _ => {
+                    missing += 1;
+                    warn!("The dwo file {} can't be found", skeleton.dwo_name);
+                    continue;
+                }
+            };
+
+        let dwo_buf = decompression::decompress_file(fs::read(&dwo_path)?);
+        let dwo_elf = match Elf::parse(&dwo_buf) {
+            Ok(elf) => elf,
+            Err(e) => {
+                error!("Invalid dwo file {}: {}", dwo_path.display(), e);
+                continue;
+            }
+        };
+
+        let mut dwo = load_dwo(&dwo_elf, &dwo_buf, None);
+        if !collect_split_unit(
+            &main,
+            unit,
+            &mut dwo,
+            skeleton.dwo_id,
+            load_address,
+            collector,
+            source,
+        )? {
+            warn!("No unit matching the skeleton in {}", dwo_path.display());
+        }
+    }
+
+    if missing != 0 {
+        warn!(
+            "{} dwo files are missing: use --dwp or --dwo-dir to set where they are",
+            missing
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use gimli::RunTimeEndian;
+
+    use super::*;
+
+    #[test]
+    fn test_dwp_index() {
+        let mut data = Vec::new();
+        // version 5 (u16 + padding), 2 columns, 1 unit, 2 slots
+        for x in &[5u32, 2, 1, 2] {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+        // signatures
+        for x in &[0x1234_5678_9abc_def0_u64, 0] {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+        // indices, columns, offsets and sizes
+        for x in &[1u32, 0, DW_SECT_INFO, DW_SECT_ABBREV, 2, 1, 3, 2] {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+
+        let index = DwpIndex::parse(EndianSlice::new(&data, RunTimeEndian::Little)).unwrap();
+        assert_eq!(index.rows.len(), 1);
+
+        let row = index.rows.get(&0x1234_5678_9abc_def0).unwrap();
+        let section = b"abcdef";
+        assert_eq!(row.get_section(SectionId::DebugInfo, section), b"cde");
+        assert_eq!(row.get_section(SectionId::DebugAbbrev, section), b"bc");
+        assert_eq!(row.get_section(SectionId::DebugLine, section), b"");
+        assert_eq!(row.get_section(SectionId::DebugStr, section), b"abcdef");
+    }
+}
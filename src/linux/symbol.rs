@@ -6,58 +6,138 @@
 use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 
+use crate::folding::{self, Folded};
+use crate::inlines::Inline;
 use crate::line::Lines;
+use crate::publics::{self, Public};
+use crate::visitor::{self, FuncRecord, PublicRecord, Visitor};
 
 #[derive(Clone, Debug, Default)]
 pub(super) struct ElfSymbol {
     pub name: String,
     pub is_public: bool,
     pub is_multiple: bool,
+    /// The other names at the same address (folded identical functions)
+    pub aliases: Vec<String>,
     pub rva: u32,
     pub len: u32,
     pub parameter_size: u32,
     pub source: Lines,
+    pub inlines: Vec<Inline>,
 }
 
 pub(super) type ElfSymbols = BTreeMap<u32, ElfSymbol>;
 
-impl Display for ElfSymbol {
+impl Display for Folded<'_, ElfSymbol> {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        if self.is_public {
+        let Folded(sym, policy) = *self;
+        if sym.is_public {
             writeln!(
                 f,
                 "PUBLIC {}{:x} {:x} {}",
-                if self.is_multiple { "m " } else { "" },
-                self.rva,
-                self.parameter_size,
-                self.name,
+                folding::get_marker(sym.is_multiple, policy),
+                sym.rva,
+                sym.parameter_size,
+                sym.name,
             )?;
         } else {
             writeln!(
                 f,
                 "FUNC {}{:x} {:x} {:x} {}",
-                if self.is_multiple { "m " } else { "" },
-                self.rva,
-                self.len,
-                self.parameter_size,
-                self.name,
+                folding::get_marker(sym.is_multiple, policy),
+                sym.rva,
+                sym.len,
+                sym.parameter_size,
+                folding::get_name(&sym.name, &sym.aliases, policy),
             )?;
 
-            write!(f, "{}", self.source)?;
+            for inline in sym.inlines.iter() {
+                write!(f, "{}", inline)?;
+            }
+            write!(f, "{}", sym.source)?;
         }
 
         Ok(())
     }
 }
 
+impl Folded<'_, ElfSymbol> {
+    /// Visit the records written for the symbol
+    pub(super) fn visit(&self, visitor: &mut dyn Visitor) {
+        let Folded(sym, policy) = *self;
+        let multiple = folding::is_multiple(sym.is_multiple, policy);
+        if sym.is_public {
+            visitor.public(&PublicRecord {
+                address: u64::from(sym.rva),
+                parameter_size: u64::from(sym.parameter_size),
+                multiple,
+                name: sym.name.clone(),
+            });
+        } else {
+            let func = FuncRecord {
+                address: u64::from(sym.rva),
+                size: u64::from(sym.len),
+                parameter_size: u64::from(sym.parameter_size),
+                multiple,
+                name: folding::get_name(&sym.name, &sym.aliases, policy),
+            };
+            visitor::visit_func(visitor, func, &sym.inlines, &sym.source);
+        }
+    }
+}
+
 impl ElfSymbol {
-    pub(super) fn fix_lines(&mut self, remapping: Option<&Vec<u32>>) {
+    pub(super) fn fix_lines(&mut self, remapping: Option<&Vec<u32>>, origins: &[u32]) {
         if let Some(remapping) = remapping {
-            for line in self.source.lines.iter_mut() {
-                line.file_id = remapping[line.file_id as usize];
+            self.remap_files(remapping);
+        }
+        for inline in self.inlines.iter_mut() {
+            inline.origin = origins[inline.origin as usize];
+        }
+    }
+
+    pub(super) fn remap_files(&mut self, remapping: &[u32]) {
+        self.source.remap_files(remapping);
+        Inline::remap_files(&mut self.inlines, remapping);
+    }
+
+    /// Move the function, its lines and its inlines at the given address
+    pub(super) fn rebase(&mut self, rva: u32) {
+        let delta = rva.wrapping_sub(self.rva);
+        self.rva = rva;
+        for line in self.source.lines.iter_mut() {
+            line.rva = line.rva.wrapping_add(delta);
+        }
+        for inline in self.inlines.iter_mut() {
+            for range in inline.ranges.iter_mut() {
+                range.0 = range.0.wrapping_add(delta);
             }
         }
     }
+
+    pub(super) fn to_public(&self) -> Public {
+        Public {
+            rva: self.rva,
+            parameter_size: self.parameter_size,
+            name: self.name.clone(),
+        }
+    }
+}
+
+/// Remove the PUBLIC records inside the range of a FUNC
+pub(super) fn remove_covered_publics(syms: &mut ElfSymbols) {
+    let covered = publics::get_covered_publics(syms.values().map(|s| (s.rva, s.len, s.is_public)));
+    for rva in covered {
+        syms.remove(&rva);
+    }
+}
+
+pub(super) fn get_dummy_name(file_name: &str) -> String {
+    if file_name.is_empty() {
+        String::from("<unknown>")
+    } else {
+        format!("<unknown in {}>", file_name)
+    }
 }
 
 pub(super) fn append_dummy_symbol(mut syms: ElfSymbols, name: &str) -> ElfSymbols {
@@ -69,11 +149,7 @@ pub(super) fn append_dummy_symbol(mut syms: ElfSymbols, name: &str) -> ElfSymbol
 
     let rva = if len == 0 { rva + len + 1 } else { rva + len };
 
-    let name = if name.is_empty() {
-        String::from("<unknown>")
-    } else {
-        format!("<unknown in {}>", name)
-    };
+    let name = get_dummy_name(name);
 
     syms.insert(
         rva,
@@ -81,10 +157,12 @@ pub(super) fn append_dummy_symbol(mut syms: ElfSymbols, name: &str) -> ElfSymbol
             name,
             is_public: true,
             is_multiple: false,
+            aliases: Vec::new(),
             rva,
             len: 0,
             parameter_size: 0,
             source: Lines::new(),
+            inlines: Vec::new(),
         },
     );
 
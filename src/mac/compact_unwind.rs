@@ -0,0 +1,441 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The linker of macOS writes the unwind info of the functions in __TEXT,__unwind_info and only
+// keeps in __eh_frame the FDEs which can't be written in the compact format.
+// Each entry of __unwind_info (in a regular or a compressed second-level page) is the offset of
+// a function (from the image base) with a 32-bit encoding:
+//   - a frame-based function (rbp or x29): the CFA is the frame pointer + 2 words and the saved
+//     registers are under the frame pointer;
+//   - a frameless function: the CFA is the stack pointer + the stack size (an immediate or a
+//     value read in the sub instruction of the function) and the saved registers are under the
+//     return address (x86) or under the CFA (arm64);
+//   - a DWARF escape: the offset of the FDE in __eh_frame.
+// The entries are sorted by address, so each one ends at the next one, and they're converted
+// into STACK CFI INIT records (only the body of the functions is described, like in the compact
+// unwind info itself).
+
+use gimli::BaseAddresses;
+use goblin::elf::header::{EM_386, EM_AARCH64, EM_X86_64};
+use goblin::mach::{
+    constants::cputype::{CPU_TYPE_ARM64, CPU_TYPE_X86, CPU_TYPE_X86_64},
+    MachO,
+};
+use std::convert::TryInto;
+
+use crate::linux::eh_frame;
+
+const REGULAR_PAGE: u32 = 2;
+const COMPRESSED_PAGE: u32 = 3;
+
+const MODE_MASK: u32 = 0x0f00_0000;
+
+const X86_MODE_FRAME: u32 = 0x0100_0000;
+const X86_MODE_STACK_IMMD: u32 = 0x0200_0000;
+const X86_MODE_STACK_IND: u32 = 0x0300_0000;
+const X86_MODE_DWARF: u32 = 0x0400_0000;
+
+const ARM64_MODE_FRAMELESS: u32 = 0x0200_0000;
+const ARM64_MODE_DWARF: u32 = 0x0300_0000;
+const ARM64_MODE_FRAME: u32 = 0x0400_0000;
+
+const DWARF_OFFSET_MASK: u32 = 0x00ff_ffff;
+
+// The saved registers by number in the encoding (0 is none)
+const X86_REGISTERS: [&str; 7] = ["", "$ebx", "$ecx", "$edx", "$edi", "$esi", "$ebp"];
+const X86_64_REGISTERS: [&str; 7] = ["", "$rbx", "$r12", "$r13", "$r14", "$r15", "$rbp"];
+// The pairs of registers saved by a function (the floating point ones are ignored)
+const ARM64_PAIRS: [(&str, &str); 5] = [
+    ("x19", "x20"),
+    ("x21", "x22"),
+    ("x23", "x24"),
+    ("x25", "x26"),
+    ("x27", "x28"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cpu {
+    X86,
+    X86_64,
+    Arm64,
+}
+
+impl Cpu {
+    fn from_cputype(cputype: u32) -> Option<Self> {
+        match cputype {
+            CPU_TYPE_X86 => Some(Self::X86),
+            CPU_TYPE_X86_64 => Some(Self::X86_64),
+            CPU_TYPE_ARM64 => Some(Self::Arm64),
+            _ => None,
+        }
+    }
+
+    fn get_machine(self) -> u16 {
+        match self {
+            Self::X86 => EM_386,
+            Self::X86_64 => EM_X86_64,
+            Self::Arm64 => EM_AARCH64,
+        }
+    }
+
+    fn is_dwarf(self, encoding: u32) -> bool {
+        let mode = encoding & MODE_MASK;
+        match self {
+            Self::X86 | Self::X86_64 => mode == X86_MODE_DWARF,
+            Self::Arm64 => mode == ARM64_MODE_DWARF,
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Get the entries (the offset of a function and its encoding) of __unwind_info sorted by
+/// address: the last one (with no encoding) is the end of the last function
+fn get_entries(data: &[u8]) -> Option<Vec<(u32, u32)>> {
+    if read_u32(data, 0)? != 1 {
+        return None;
+    }
+    let common_offset = read_u32(data, 4)? as usize;
+    let common_count = read_u32(data, 8)? as usize;
+    let index_offset = read_u32(data, 20)? as usize;
+    let index_count = read_u32(data, 24)? as usize;
+    let common = (0..common_count)
+        .map(|i| read_u32(data, common_offset + 4 * i))
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut entries = Vec::new();
+    for i in 0..index_count {
+        let index = index_offset + 12 * i;
+        let function = read_u32(data, index)?;
+        let page = read_u32(data, index + 4)? as usize;
+        if page == 0 {
+            // The end of the functions
+            entries.push((function, 0));
+            continue;
+        }
+
+        let entry_offset = page + read_u16(data, page + 4)? as usize;
+        let entry_count = read_u16(data, page + 6)? as usize;
+        match read_u32(data, page)? {
+            REGULAR_PAGE => {
+                for j in 0..entry_count {
+                    let entry = entry_offset + 8 * j;
+                    entries.push((read_u32(data, entry)?, read_u32(data, entry + 4)?));
+                }
+            }
+            COMPRESSED_PAGE => {
+                let encodings_offset = page + read_u16(data, page + 8)? as usize;
+                for j in 0..entry_count {
+                    let entry = read_u32(data, entry_offset + 4 * j)?;
+                    let index = (entry >> 24) as usize;
+                    let encoding = match common.get(index) {
+                        Some(encoding) => *encoding,
+                        _ => read_u32(data, encodings_offset + 4 * (index - common.len()))?,
+                    };
+                    entries.push((function + (entry & 0x00ff_ffff), encoding));
+                }
+            }
+            _ => return None,
+        }
+    }
+    entries.sort_by_key(|(function, _)| *function);
+
+    Some(entries)
+}
+
+/// Get the numbers of the registers saved by a frameless x86 function from their permutation
+fn get_permuted_registers(count: usize, mut permutation: u32) -> Option<Vec<usize>> {
+    if count > 6 {
+        return None;
+    }
+    // The index of each register among the ones which haven't been used
+    let mut indices = Vec::with_capacity(count);
+    for i in 0..count {
+        let divisor: u32 = (i + 1..count).map(|k| 6 - k as u32).product();
+        indices.push(permutation / divisor);
+        permutation %= divisor;
+    }
+
+    let mut used = [false; 7];
+    let mut registers = Vec::with_capacity(count);
+    for index in indices {
+        let register = (1..7)
+            .filter(|register| !used[*register])
+            .nth(index as usize)?;
+        used[register] = true;
+        registers.push(register);
+    }
+
+    Some(registers)
+}
+
+/// Get the rules of an x86 or x86_64 function (None for a DWARF entry or without unwind info)
+fn get_x86_rules<F: Fn(u64) -> Option<u32>>(
+    cpu: Cpu,
+    encoding: u32,
+    function: u64,
+    read: F,
+) -> Option<Vec<(&'static str, String)>> {
+    let (names, word, sp, fp) = if cpu == Cpu::X86 {
+        (&X86_REGISTERS, 4, "$esp", "$ebp")
+    } else {
+        (&X86_64_REGISTERS, 8, "$rsp", "$rbp")
+    };
+    let mut rules = vec![(".ra", format!(".cfa {} + ^", -word))];
+
+    match encoding & MODE_MASK {
+        X86_MODE_FRAME => {
+            rules.push((".cfa", format!("{} {} +", fp, 2 * word)));
+            rules.push((fp, format!(".cfa {} + ^", -2 * word)));
+            let offset = i64::from((encoding >> 16) & 0xff);
+            for i in 0..5 {
+                let register = ((encoding >> (3 * i)) & 0x7) as usize;
+                if register != 0 {
+                    let location = -2 * word - offset * word + i * word;
+                    rules.push((names.get(register)?, format!(".cfa {} + ^", location)));
+                }
+            }
+        }
+        X86_MODE_STACK_IMMD | X86_MODE_STACK_IND => {
+            let size = u64::from((encoding >> 16) & 0xff);
+            let size = if encoding & MODE_MASK == X86_MODE_STACK_IMMD {
+                size * word as u64
+            } else {
+                // The size is read in the sub instruction of the function
+                let adjust = u64::from((encoding >> 13) & 0x7);
+                u64::from(read(function + size)?) + adjust * word as u64
+            };
+            rules.push((".cfa", format!("{} {} +", sp, size)));
+            let count = ((encoding >> 10) & 0x7) as usize;
+            let registers = get_permuted_registers(count, encoding & 0x3ff)?;
+            for (i, register) in registers.iter().enumerate() {
+                let location = -word - word * count as i64 + word * i as i64;
+                rules.push((names.get(*register)?, format!(".cfa {} + ^", location)));
+            }
+        }
+        _ => return None,
+    }
+
+    Some(rules)
+}
+
+/// Get the rules of an arm64 function (None for a DWARF entry or without unwind info)
+fn get_arm64_rules(encoding: u32) -> Option<Vec<(&'static str, String)>> {
+    let (mut rules, mut location) = match encoding & MODE_MASK {
+        ARM64_MODE_FRAME => (
+            vec![
+                (".cfa", "x29 16 +".to_string()),
+                (".ra", ".cfa -8 + ^".to_string()),
+                ("x29", ".cfa -16 + ^".to_string()),
+            ],
+            -24,
+        ),
+        ARM64_MODE_FRAMELESS => {
+            let size = ((encoding >> 12) & 0xfff) * 16;
+            (
+                vec![
+                    (".cfa", format!("sp {} +", size)),
+                    (".ra", "x30".to_string()),
+                ],
+                -8,
+            )
+        }
+        _ => return None,
+    };
+
+    for (i, (first, second)) in ARM64_PAIRS.iter().enumerate() {
+        if encoding & (1 << i) != 0 {
+            rules.push((first, format!(".cfa {} + ^", location)));
+            rules.push((second, format!(".cfa {} + ^", location - 8)));
+            location -= 16;
+        }
+    }
+
+    Some(rules)
+}
+
+fn format_rules(mut rules: Vec<(&'static str, String)>) -> String {
+    // .cfa and .ra first
+    rules.sort_by_key(|(name, _)| (!name.starts_with('.'), *name));
+    rules
+        .iter()
+        .map(|(name, rule)| format!("{}: {}", name, rule))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Get the STACK CFI records from the __unwind_info of a Mach-O file
+pub(crate) fn get_stack_cfi(buf: &[u8], load_address: u64) -> String {
+    let macho = match MachO::parse(buf, 0) {
+        Ok(macho) => macho,
+        _ => return String::new(),
+    };
+    let cpu = match Cpu::from_cputype(macho.header.cputype) {
+        Some(cpu) => cpu,
+        _ => return String::new(),
+    };
+
+    // The sections with their address (the ones with no data in a dSYM are skipped)
+    let sections = macho
+        .segments
+        .iter()
+        .filter_map(|segment| segment.sections().ok())
+        .flatten()
+        .filter(|(section, data)| section.offset != 0 && !data.is_empty())
+        .collect::<Vec<_>>();
+    let get_section = |name: &str| {
+        sections
+            .iter()
+            .find(|(section, _)| section.name().ok() == Some(name))
+            .map(|(section, data)| (section.addr, *data))
+    };
+    let entries = match get_section("__unwind_info").and_then(|(_, data)| get_entries(data)) {
+        Some(entries) => entries,
+        _ => return String::new(),
+    };
+    let read = |address: u64| {
+        sections.iter().find_map(|(section, data)| {
+            let offset = address.checked_sub(section.addr)? as usize;
+            read_u32(data, offset)
+        })
+    };
+    let eh_frame = get_section("__eh_frame").map(|(address, data)| {
+        let text = get_section("__text").map_or(0, |(address, _)| address);
+        let bases = BaseAddresses::default()
+            .set_eh_frame(address)
+            .set_text(text);
+        (data, bases)
+    });
+
+    let mut stack = String::new();
+    for (entry, next) in entries.iter().zip(entries.iter().skip(1)) {
+        let (function, encoding) = *entry;
+        let len = next.0.saturating_sub(function);
+        if encoding & MODE_MASK == 0 || len == 0 {
+            continue;
+        }
+
+        if cpu.is_dwarf(encoding) {
+            if let Some((data, bases)) = eh_frame.as_ref() {
+                let offset = (encoding & DWARF_OFFSET_MASK) as usize;
+                if let Some(records) = eh_frame::get_fde_stack_cfi(
+                    cpu.get_machine(),
+                    data,
+                    bases.clone(),
+                    offset,
+                    load_address,
+                ) {
+                    stack.push_str(&records);
+                }
+            }
+            continue;
+        }
+
+        let rules = match cpu {
+            Cpu::Arm64 => get_arm64_rules(encoding),
+            _ => get_x86_rules(cpu, encoding, load_address + u64::from(function), read),
+        };
+        if let Some(rules) = rules {
+            stack.push_str(&format!(
+                "STACK CFI INIT {:x} {:x} {}\n",
+                function,
+                len,
+                format_rules(rules)
+            ));
+        }
+    }
+
+    stack
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn push_u32s(data: &mut Vec<u8>, values: &[u32]) {
+        for value in values {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_unwind_info() {
+        let mut data = Vec::new();
+        // The header: one common encoding (at 28) and three index entries (at 32)
+        push_u32s(&mut data, &[1, 28, 1, 0, 0, 32, 3]);
+        push_u32s(&mut data, &[0x0100_0000]);
+        // The index: a regular page at 68 and a compressed one at 92 and the end at 0x1100
+        push_u32s(&mut data, &[0x1000, 68, 0, 0x1040, 92, 0, 0x1100, 0, 0]);
+        // The regular page: two entries
+        push_u32s(&mut data, &[REGULAR_PAGE, 8 | (2 << 16)]);
+        push_u32s(&mut data, &[0x1000, 0x0100_0000, 0x1020, 0x0200_0000]);
+        // The compressed page: two entries (a common encoding and one of the page)
+        push_u32s(
+            &mut data,
+            &[COMPRESSED_PAGE, 12 | (2 << 16), 20 | (1 << 16)],
+        );
+        push_u32s(&mut data, &[0x10, (1 << 24) | 0x40, 0x0400_0123]);
+
+        assert_eq!(
+            get_entries(&data).unwrap(),
+            vec![
+                (0x1000, 0x0100_0000),
+                (0x1020, 0x0200_0000),
+                (0x1050, 0x0100_0000),
+                (0x1080, 0x0400_0123),
+                (0x1100, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rules() {
+        // push rbp; mov rbp, rsp; push rbx; push r12 (saved at rbp-16)
+        let encoding = X86_MODE_FRAME | (2 << 16) | 1 | (2 << 3);
+        assert_eq!(
+            format_rules(get_x86_rules(Cpu::X86_64, encoding, 0, |_| None).unwrap()),
+            ".cfa: $rbp 16 + .ra: .cfa -8 + ^ $r12: .cfa -24 + ^ $rbp: .cfa -16 + ^ $rbx: .cfa -32 + ^"
+        );
+
+        // push r12; push rbx; sub rsp, 8
+        let encoding = X86_MODE_STACK_IMMD | (4 << 16) | (2 << 10);
+        assert_eq!(
+            format_rules(get_x86_rules(Cpu::X86_64, encoding, 0, |_| None).unwrap()),
+            ".cfa: $rsp 32 + .ra: .cfa -8 + ^ $r12: .cfa -16 + ^ $rbx: .cfa -24 + ^"
+        );
+        assert_eq!(get_permuted_registers(2, 13), Some(vec![3, 5]));
+        assert_eq!(get_permuted_registers(3, 0), Some(vec![1, 2, 3]));
+
+        // The size is in the sub instruction at the offset 5 from the function
+        let encoding = X86_MODE_STACK_IND | (5 << 16) | (1 << 13);
+        let read = |address| if address == 0x1005 { Some(0x28) } else { None };
+        assert_eq!(
+            format_rules(get_x86_rules(Cpu::X86_64, encoding, 0x1000, read).unwrap()),
+            ".cfa: $rsp 48 + .ra: .cfa -8 + ^"
+        );
+
+        let encoding = ARM64_MODE_FRAME | 0x1 | 0x4;
+        assert_eq!(
+            format_rules(get_arm64_rules(encoding).unwrap()),
+            ".cfa: x29 16 + .ra: .cfa -8 + ^ x19: .cfa -24 + ^ x20: .cfa -32 + ^ x23: .cfa -40 + ^ \
+             x24: .cfa -48 + ^ x29: .cfa -16 + ^"
+        );
+        let encoding = ARM64_MODE_FRAMELESS | (2 << 12) | 0x2;
+        assert_eq!(
+            format_rules(get_arm64_rules(encoding).unwrap()),
+            ".cfa: sp 32 + .ra: x30 x21: .cfa -8 + ^ x22: .cfa -16 + ^"
+        );
+        assert!(get_arm64_rules(ARM64_MODE_DWARF | 0x10).is_none());
+    }
+}
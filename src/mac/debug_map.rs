@@ -0,0 +1,219 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// When dsymutil isn't run, the DWARF stays in the object files and the linker only writes a
+// debug map (some STABS) in the symbol table of the binary:
+//   - N_OSO: the path to an object (or libfoo.a(foo.o)) and its mtime;
+//   - N_FUN: the name of a function and its address in the binary (then an N_FUN with an empty
+//     name and the size);
+//   - an empty N_SO ends the object.
+// Like dsymutil, the DWARF of each object is dumped and its functions are moved at their address
+// in the binary (found with their name in the symbol table of the object).
+
+use failure::Fail;
+use goblin::mach::{
+    symbols::{N_FUN, N_OSO, N_SECT, N_SO},
+    MachO,
+};
+use hashbrown::HashMap;
+use log::{info, warn};
+use std::fs;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use symbolic::common::Arch;
+use symbolic::debuginfo::{Archive, Object};
+
+use crate::archive;
+use crate::common;
+use crate::linux::elf::{ElfInfo, Platform};
+use crate::mapping::PathMappings;
+use crate::options::Options;
+
+#[derive(Debug, Default, PartialEq)]
+struct DebugMapObject {
+    path: String,
+    mtime: u64,
+    /// The functions with their address in the binary
+    functions: Vec<(String, u64)>,
+}
+
+/// Parse the debug map from the (name, type, value) of the STABS
+fn parse_debug_map<'a>(symbols: impl Iterator<Item = (&'a str, u8, u64)>) -> Vec<DebugMapObject> {
+    let mut objects = Vec::new();
+    let mut current: Option<DebugMapObject> = None;
+
+    for (name, typ, value) in symbols {
+        match typ {
+            N_OSO => {
+                objects.extend(current.take());
+                current = Some(DebugMapObject {
+                    path: name.to_string(),
+                    mtime: value,
+                    functions: Vec::new(),
+                });
+            }
+            N_SO if name.is_empty() => objects.extend(current.take()),
+            N_FUN if !name.is_empty() => {
+                if let Some(current) = current.as_mut() {
+                    current.functions.push((name.to_string(), value));
+                }
+            }
+            _ => {}
+        }
+    }
+    objects.extend(current);
+
+    objects
+}
+
+fn check_mtime(path: &str, mtime: u64) {
+    let modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+    if let Some(modified) = modified {
+        if mtime != 0 && modified.as_secs() != mtime {
+            warn!(
+                "The object {} has been modified after the link: its debug info may be wrong",
+                path
+            );
+        }
+    }
+}
+
+fn read_object(path: &str, mtime: u64) -> common::Result<Vec<u8>> {
+    // An object in a static library: /path/to/libfoo.a(foo.o)
+    if let Some((file, member)) = path.strip_suffix(')').and_then(|p| p.rsplit_once('(')) {
+        let buf = fs::read(file)?;
+        return archive::get_member_data(&buf, member)
+            .map(|data| data.to_vec())
+            .ok_or_else(|| format!("Cannot find {} in {}", member, file).into());
+    }
+
+    check_mtime(path, mtime);
+    Ok(fs::read(path)?)
+}
+
+/// Dump an object and get the map between the addresses of its functions and their rva in the binary
+fn dump_object(
+    entry: &DebugMapObject,
+    arch: Arch,
+    rvas: &HashMap<&str, u32>,
+    mapping: Option<Arc<PathMappings>>,
+    options: Arc<Options>,
+) -> common::Result<(ElfInfo, HashMap<u32, u32>)> {
+    let buf = read_object(&entry.path, entry.mtime)?;
+    let archive = Archive::parse(&buf).map_err(|e| e.compat())?;
+    let object = archive
+        .objects()
+        .filter_map(|o| o.ok())
+        .find(|o| o.arch() == arch || archive.object_count() == 1)
+        .ok_or_else(|| format!("No object for architecture {}", arch.name()))?;
+
+    let load_address = object.load_address();
+    let mut addresses = HashMap::new();
+    for (name, nlist) in MachO::parse(object.data(), 0)?
+        .symbols()
+        .filter_map(|s| s.ok())
+    {
+        if nlist.is_stab() || nlist.get_type() != N_SECT {
+            continue;
+        }
+        if let (Some(rva), Some(address)) =
+            (rvas.get(name), nlist.n_value.checked_sub(load_address))
+        {
+            addresses.entry(address as u32).or_insert(*rva);
+        }
+    }
+
+    let info = ElfInfo::from_object(&object, &entry.path, Platform::Mac, mapping, options)?;
+    Ok((info, addresses))
+}
+
+/// Add the functions from the objects of the debug map of a binary (if any)
+pub(super) fn add_objects(
+    mut info: ElfInfo,
+    object: &Object,
+    mapping: Option<Arc<PathMappings>>,
+    options: Arc<Options>,
+) -> common::Result<ElfInfo> {
+    let macho = match MachO::parse(object.data(), 0) {
+        Ok(macho) => macho,
+        _ => return Ok(info),
+    };
+    let symbols = macho
+        .symbols()
+        .filter_map(|s| s.ok())
+        .filter(|(_, nlist)| nlist.is_stab())
+        .map(|(name, nlist)| (name, nlist.n_type, nlist.n_value));
+    let load_address = object.load_address();
+
+    for entry in parse_debug_map(symbols) {
+        let rvas = entry
+            .functions
+            .iter()
+            .filter_map(|(name, address)| {
+                Some((name.as_str(), address.checked_sub(load_address)? as u32))
+            })
+            .collect::<HashMap<_, _>>();
+        match dump_object(
+            &entry,
+            object.arch(),
+            &rvas,
+            mapping.clone(),
+            options.clone(),
+        ) {
+            Ok((object_info, addresses)) => {
+                info!("Read the debug info from the object {}", entry.path);
+                info = info.with_object_functions(object_info, &addresses)?;
+            }
+            Err(e) => warn!("Cannot read the object {}: {}", entry.path, e),
+        }
+    }
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use goblin::mach::symbols::{N_BNSYM, N_ENSYM};
+
+    #[test]
+    fn test_parse_debug_map() {
+        let symbols = vec![
+            ("/src/", N_SO, 0),
+            ("foo.c", N_SO, 0),
+            ("/build/foo.o", N_OSO, 1234),
+            ("", N_BNSYM, 0x1000),
+            ("_foo", N_FUN, 0x1000),
+            ("", N_FUN, 0x20),
+            ("", N_ENSYM, 0x1000),
+            ("", N_SO, 0),
+            ("/build/libbar.a(bar.o)", N_OSO, 0),
+            ("_bar", N_FUN, 0x1020),
+            ("", N_FUN, 0x10),
+            ("_baz", N_FUN, 0x1030),
+            ("", N_FUN, 0x10),
+        ];
+        let objects = parse_debug_map(symbols.into_iter());
+        assert_eq!(
+            objects,
+            vec![
+                DebugMapObject {
+                    path: "/build/foo.o".to_string(),
+                    mtime: 1234,
+                    functions: vec![("_foo".to_string(), 0x1000)],
+                },
+                DebugMapObject {
+                    path: "/build/libbar.a(bar.o)".to_string(),
+                    mtime: 0,
+                    functions: vec![("_bar".to_string(), 0x1020), ("_baz".to_string(), 0x1030)],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// On macOS, Spotlight indexes the dSYM bundles with the UUIDs of their DWARF files, so (like lldb
+// or atos) the dSYM of a binary without debug info is found with:
+//   mdfind "com_apple_xcode_dsym_uuids == <UUID>"
+// and the DWARF file with the same UUID is read from Contents/Resources/DWARF in the bundle.
+
+use failure::Fail;
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use symbolic::common::DebugId;
+use symbolic::debuginfo::{Archive, Object};
+
+use crate::common;
+use crate::linux::elf::{ElfInfo, Platform};
+use crate::mapping::PathMappings;
+use crate::options::Options;
+
+fn get_query(debug_id: &DebugId) -> String {
+    format!(
+        "com_apple_xcode_dsym_uuids == {}",
+        debug_id.uuid().to_string().to_uppercase()
+    )
+}
+
+fn parse_mdfind_output(output: &str) -> Vec<PathBuf> {
+    output
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn mdfind(debug_id: &DebugId) -> Vec<PathBuf> {
+    let output = match Command::new("mdfind").arg(get_query(debug_id)).output() {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+    parse_mdfind_output(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn get_dwarf_files(bundle: &Path) -> Vec<PathBuf> {
+    let mut files = fs::read_dir(bundle.join("Contents/Resources/DWARF"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+fn dump_dwarf_file(
+    path: &Path,
+    debug_id: &DebugId,
+    file_name: &str,
+    mapping: Option<Arc<PathMappings>>,
+    options: Arc<Options>,
+) -> common::Result<Option<ElfInfo>> {
+    let buf = fs::read(path)?;
+    let archive = Archive::parse(&buf).map_err(|e| e.compat())?;
+    let object = archive
+        .objects()
+        .filter_map(|o| o.ok())
+        .find(|o| o.debug_id() == *debug_id);
+    object
+        .map(|object| ElfInfo::from_object(&object, file_name, Platform::Mac, mapping, options))
+        .transpose()
+}
+
+/// Find the dSYM of a binary with Spotlight (only on macOS) and get its debug info
+pub(super) fn find_dsym(
+    object: &Object,
+    file_name: &str,
+    mapping: Option<Arc<PathMappings>>,
+    options: Arc<Options>,
+) -> Option<ElfInfo> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+
+    let debug_id = object.debug_id();
+    for bundle in mdfind(&debug_id) {
+        for path in get_dwarf_files(&bundle) {
+            match dump_dwarf_file(
+                &path,
+                &debug_id,
+                file_name,
+                mapping.clone(),
+                options.clone(),
+            ) {
+                Ok(Some(info)) => {
+                    info!("Read the debug info from the dSYM {}", path.display());
+                    return Some(info);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Cannot read the dSYM {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_query() {
+        let debug_id = DebugId::from_str("8e0e5c9f0c3a3d5e9bb8a2c5d1e1f4a70").unwrap();
+        assert_eq!(
+            get_query(&debug_id),
+            "com_apple_xcode_dsym_uuids == 8E0E5C9F-0C3A-3D5E-9BB8-A2C5D1E1F4A7"
+        );
+        assert_eq!(
+            parse_mdfind_output("/a/foo.dSYM\n\n/b/foo.dSYM\n"),
+            vec![PathBuf::from("/a/foo.dSYM"), PathBuf::from("/b/foo.dSYM")]
+        );
+    }
+}
@@ -6,13 +6,17 @@
 use failure::Fail;
 use std::fmt::{Display, Formatter};
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::Arc;
 use symbolic::common::Arch;
-use symbolic::debuginfo::Archive;
+use symbolic::debuginfo::{Archive, Object};
 
+use super::{debug_map, dsym};
 use crate::common::{self, Dumpable, Mergeable};
 use crate::linux::elf::{ElfInfo, Platform};
 use crate::mapping::PathMappings;
+use crate::options::Options;
+use crate::visitor::Visitor;
 
 #[derive(Debug)]
 pub struct MachoInfo {
@@ -31,6 +35,7 @@ impl MachoInfo {
         file_name: &str,
         arch: Arch,
         mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
     ) -> common::Result<Self> {
         // Fat files may contain several objects for different architectures
         // So if there is only one object, then we don't care about the arch (as argument)
@@ -47,18 +52,76 @@ impl MachoInfo {
 
         if let Some(object) = object {
             Ok(Self {
-                elf: ElfInfo::from_object(&object, file_name, Platform::Mac, mapping)?,
+                elf: Self::get_info(&object, file_name, mapping, options)?,
             })
         } else {
+            let archs = archive
+                .objects()
+                .filter_map(|o| o.ok())
+                .map(|o| o.arch().name())
+                .collect::<Vec<_>>();
             Err(format!(
-                "Cannot find a valid object for architecture {} in file {}",
+                "Cannot find a valid object for architecture {} in file {} (available: {})",
                 arch.name(),
-                file_name
+                file_name,
+                archs.join(", ")
             )
             .into())
         }
     }
 
+    /// Get the info for each object present in the fat file
+    pub fn new_all(
+        buf: &[u8],
+        file_name: &str,
+        mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
+    ) -> common::Result<Vec<Self>> {
+        let archive = Archive::parse(buf).map_err(|e| e.compat())?;
+        let mut infos = Vec::with_capacity(archive.object_count());
+        for object in archive.objects() {
+            let object = object.map_err(|e| e.compat())?;
+            infos.push(Self {
+                elf: Self::get_info(
+                    &object,
+                    file_name,
+                    mapping.as_ref().map(Arc::clone),
+                    options.clone(),
+                )?,
+            });
+        }
+
+        Ok(infos)
+    }
+
+    fn get_info(
+        object: &Object,
+        file_name: &str,
+        mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
+    ) -> common::Result<ElfInfo> {
+        let elf = ElfInfo::from_object(
+            object,
+            file_name,
+            Platform::Mac,
+            mapping.clone(),
+            options.clone(),
+        )?;
+        if object.has_debug_info() {
+            return Ok(elf);
+        }
+
+        // The dSYM found by Spotlight or the debug info in the objects from the debug map
+        if let Some(dsym) = dsym::find_dsym(object, file_name, mapping.clone(), options.clone()) {
+            return ElfInfo::merge(elf, dsym);
+        }
+        debug_map::add_objects(elf, object, mapping, options)
+    }
+
+    pub fn get_arch(&self) -> &str {
+        self.elf.get_cpu()
+    }
+
     /// Print on screen the cpu arch for the different objects present in the fat file
     pub fn print_architectures(buf: &[u8], file_name: String) -> common::Result<()> {
         let archive = Archive::parse(buf).map_err(|e| e.compat())?;
@@ -91,6 +154,10 @@ impl Dumpable for MachoInfo {
         Ok(())
     }
 
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        self.elf.visit(visitor)
+    }
+
     fn get_debug_id(&self) -> &str {
         &self.elf.get_debug_id()
     }
@@ -98,4 +165,8 @@ impl Dumpable for MachoInfo {
     fn get_name(&self) -> &str {
         &self.elf.get_name()
     }
+
+    fn get_source_files(&self) -> Vec<(String, PathBuf)> {
+        self.elf.get_source_files()
+    }
 }
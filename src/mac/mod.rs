@@ -3,4 +3,7 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+pub(crate) mod compact_unwind;
+mod debug_map;
+mod dsym;
 pub mod macho;
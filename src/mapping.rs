@@ -13,6 +13,7 @@ use std::sync::Mutex;
 
 use crate::common;
 use crate::utils;
+use crate::vcs::{VcsInfo, VcsKind};
 
 #[derive(Debug, Default, Deserialize)]
 struct Mapping {
@@ -181,6 +182,7 @@ impl PathMappingGenerator {
 pub struct PathMappings {
     mappings: Vec<PathMappingGenerator>,
     cache: Mutex<HashMap<String, String>>,
+    vcs: Option<VcsInfo>,
 }
 
 impl PathMappings {
@@ -189,6 +191,7 @@ impl PathMappings {
         sources: &Option<Vec<&str>>,
         destinations: &Option<Vec<&str>>,
         file: &Option<&str>,
+        vcs: Option<VcsKind>,
     ) -> common::Result<Option<Self>> {
         let vars = Self::get_variables(variables)?;
         let mut mappings = Vec::new();
@@ -196,16 +199,21 @@ impl PathMappings {
         Self::get_mappings_from_file(&vars, file, &mut mappings)?;
         Self::get_mappings(&vars, sources, destinations, &mut mappings)?;
 
-        Ok(if mappings.is_empty() {
+        Ok(if mappings.is_empty() && vcs.is_none() {
             None
         } else {
             Some(PathMappings {
                 mappings,
                 cache: Mutex::new(HashMap::default()),
+                vcs: vcs.map(VcsInfo::new),
             })
         })
     }
 
+    pub(crate) fn get_vcs_info(&self) -> Option<&VcsInfo> {
+        self.vcs.as_ref()
+    }
+
     fn get_variables(vars: &Option<Vec<&str>>) -> common::Result<HashMap<String, String>> {
         let mut variables = HashMap::default();
         if let Some(vars) = vars {
@@ -331,13 +339,19 @@ impl PathMappings {
             }
         }
 
+        if let Some(vcs) = self.vcs.as_ref() {
+            vcs.collect(file, res.as_deref().unwrap_or(file_str));
+        }
+
         if let Some(res) = res {
             let mut cache = self.cache.lock().unwrap();
             cache.insert(file_str.to_string(), res.clone());
             return Ok(Some(res));
         }
 
-        warn!("Cannot find a mapping for file {}", file_str);
+        if !self.mappings.is_empty() {
+            warn!("Cannot find a mapping for file {}", file_str);
+        }
 
         Ok(None)
     }
@@ -368,9 +382,10 @@ mod tests {
 
     #[test]
     fn test_path_mapping_file() {
-        let mappings = PathMappings::new(&None, &None, &None, &Some("./test_data/mapping.json"))
-            .unwrap()
-            .unwrap();
+        let mappings =
+            PathMappings::new(&None, &None, &None, &Some("./test_data/mapping.json"), None)
+                .unwrap()
+                .unwrap();
 
         let files = vec![
             ("/home/worker/a/c/ddd.cpp", "hg:hg.mozilla.org/mozilla-central:a/c/ddd.cpp:6639deb894172375b05d6791f5f8c7d53ca79723"),
@@ -0,0 +1,358 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Dump the debug files of the modules of a minidump: the module list stream contains
+// for each module its CodeView record (the pdb name and its guid/age or an ELF build-id)
+// which is enough to get the debug file from a symbol server.
+// The modules of an ELF core dump are read from its NT_FILE note.
+
+use crossbeam::channel::unbounded;
+use goblin::elf::Elf;
+use log::{info, warn};
+use std::convert::TryInto;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use uuid::Uuid;
+
+use crate::cache;
+use crate::common;
+use crate::dumper::{self, Config};
+use crate::linux::{core_dump, debuglink, elf::ElfInfo};
+use crate::utils;
+
+const MINIDUMP_SIGNATURE: &[u8] = b"MDMP";
+const MODULE_LIST_STREAM: u32 = 4;
+const MODULE_SIZE: usize = 108;
+const CV_SIGNATURE_PDB70: &[u8] = b"RSDS";
+const CV_SIGNATURE_ELF: &[u8] = b"BpEL";
+
+#[derive(Debug, PartialEq)]
+struct Module {
+    code_file: String,
+    debug_file: String,
+    debug_id: String,
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> common::Result<u32> {
+    let data = buf
+        .get(offset..offset + 4)
+        .ok_or("Unexpected end of the minidump")?;
+    Ok(u32::from_le_bytes(data.try_into().unwrap()))
+}
+
+fn get_data(buf: &[u8], rva: u32, size: u32) -> common::Result<&[u8]> {
+    let start = rva as usize;
+    buf.get(start..start.saturating_add(size as usize))
+        .ok_or_else(|| "Invalid location in the minidump".into())
+}
+
+/// A MINIDUMP_STRING: its size in bytes followed by the UTF-16 chars
+fn get_string(buf: &[u8], rva: u32) -> common::Result<String> {
+    let size = read_u32(buf, rva as usize)?;
+    let data = get_data(buf, rva + 4, size)?;
+    let chars: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    Ok(String::from_utf16_lossy(&chars))
+}
+
+/// The guid is stored with its first 3 fields in little endian (like in a Breakpad debug id)
+fn get_guid(data: &[u8]) -> String {
+    let mut guid = [0; 16];
+    let len = data.len().min(16);
+    guid[..len].copy_from_slice(&data[..len]);
+    guid[0..4].reverse();
+    guid[4..6].reverse();
+    guid[6..8].reverse();
+
+    Uuid::from_bytes(guid)
+        .to_simple()
+        .encode_upper(&mut Uuid::encode_buffer())
+        .to_string()
+}
+
+fn get_file_name(path: &str) -> &str {
+    path.rsplit(|c| c == '/' || c == '\\')
+        .next()
+        .unwrap_or(path)
+}
+
+/// Get the debug file and the debug id from the CodeView record of a module
+fn get_debug_info(code_file: &str, cv: &[u8]) -> Option<(String, String)> {
+    if cv.starts_with(CV_SIGNATURE_PDB70) && cv.len() > 24 {
+        let age = u32::from_le_bytes(cv[20..24].try_into().unwrap());
+        let pdb = cv[24..].split(|c| *c == 0).next()?;
+        let pdb = String::from_utf8_lossy(pdb);
+        Some((
+            get_file_name(&pdb).to_string(),
+            format!("{}{:x}", get_guid(&cv[4..20]), age),
+        ))
+    } else if cv.starts_with(CV_SIGNATURE_ELF) && cv.len() > 4 {
+        // The debug file of an ELF module is the module itself
+        Some((
+            get_file_name(code_file).to_string(),
+            format!("{}0", get_guid(&cv[4..])),
+        ))
+    } else {
+        None
+    }
+}
+
+fn get_modules(buf: &[u8]) -> common::Result<Vec<Module>> {
+    if !buf.starts_with(MINIDUMP_SIGNATURE) {
+        return Err("Not a minidump".into());
+    }
+
+    let n_streams = read_u32(buf, 8)?;
+    let directory = read_u32(buf, 12)? as usize;
+    let mut list = None;
+    for i in 0..n_streams as usize {
+        // A MINIDUMP_DIRECTORY: StreamType, DataSize and Rva
+        let entry = directory + 12 * i;
+        if read_u32(buf, entry)? == MODULE_LIST_STREAM {
+            list = Some(read_u32(buf, entry + 8)?);
+            break;
+        }
+    }
+    let list = list.ok_or("No module list in the minidump")?;
+
+    let n_modules = read_u32(buf, list as usize)?;
+    let mut modules = Vec::with_capacity(n_modules as usize);
+    for i in 0..n_modules as usize {
+        // A MINIDUMP_MODULE: the name is at offset 20 and the CodeView record at offset 76
+        let module = list as usize + 4 + MODULE_SIZE * i;
+        let code_file = get_string(buf, read_u32(buf, module + 20)?)?;
+        let cv = get_data(
+            buf,
+            read_u32(buf, module + 80)?,
+            read_u32(buf, module + 76)?,
+        )?;
+
+        match get_debug_info(&code_file, cv) {
+            Some((debug_file, debug_id)) => modules.push(Module {
+                code_file,
+                debug_file,
+                debug_id,
+            }),
+            _ => warn!("No debug info for module {}", code_file),
+        }
+    }
+
+    modules.sort_by(|a, b| (&a.debug_file, &a.debug_id).cmp(&(&b.debug_file, &b.debug_id)));
+    modules.dedup_by(|a, b| a.debug_file == b.debug_file && a.debug_id == b.debug_id);
+
+    Ok(modules)
+}
+
+fn get_core_modules(buf: &[u8]) -> common::Result<Vec<Module>> {
+    let mut modules: Vec<_> = core_dump::get_modules(buf)?
+        .into_iter()
+        .map(|m| Module {
+            debug_file: get_file_name(&m.path).to_string(),
+            debug_id: ElfInfo::get_debug_id_from_build_id(&m.build_id, m.little_endian),
+            code_file: m.path,
+        })
+        .collect();
+    modules.sort_by(|a, b| (&a.debug_file, &a.debug_id).cmp(&(&b.debug_file, &b.debug_id)));
+
+    Ok(modules)
+}
+
+/// Check if the module is an ELF file on this machine with the same build-id
+fn is_local_file(module: &Module) -> bool {
+    let path = Path::new(&module.code_file);
+    if !path.is_file() {
+        return false;
+    }
+
+    let buf = match utils::read(path) {
+        Ok(buf) => buf,
+        _ => return false,
+    };
+    Elf::parse(&buf).ok().map_or(false, |elf| {
+        debuglink::get_build_id(&elf, &buf).map_or(false, |id| {
+            ElfInfo::get_debug_id_from_build_id(id, elf.little_endian) == module.debug_id
+        })
+    })
+}
+
+fn dump_module(module: &Module, config: &Config) -> common::Result<()> {
+    let server_config = Config {
+        output: "-",
+        debug_id: Some(&module.debug_id),
+        code_id: None,
+        ..config.clone()
+    };
+    let res = dumper::single_file(&server_config, &module.debug_file);
+    if res.is_err() && is_local_file(module) {
+        // The debug file isn't on the symbol servers but the module is on this machine
+        let local_config = Config {
+            output: "-",
+            debug_id: None,
+            code_id: None,
+            ..config.clone()
+        };
+        return dumper::single_file(&local_config, &module.code_file);
+    }
+
+    res
+}
+
+/// Dump in the store the debug files (fetched from the symbol servers) of the modules
+/// of a minidump or of an ELF core dump
+pub(crate) fn dump(minidump: &str, config: &Config) -> common::Result<()> {
+    let store = config.store.ok_or("A store directory is required")?;
+    let buf = utils::read(minidump)?;
+    let modules = if core_dump::is_core(&buf) {
+        get_core_modules(&buf)?
+    } else {
+        get_modules(&buf)?
+    };
+    let total = modules.len();
+    let num_jobs = config.num_jobs.min(total).max(1);
+
+    let dumped = AtomicUsize::new(0);
+    let stored = AtomicUsize::new(0);
+    let (sender, receiver) = unbounded::<Module>();
+    for module in modules {
+        sender.send(module).unwrap();
+    }
+    // Close the channel to stop the threads once the queue is empty
+    drop(sender);
+
+    crossbeam::scope(|scope| {
+        for _ in 0..num_jobs {
+            scope.spawn(|_| {
+                while let Ok(module) = receiver.recv() {
+                    let sym = Path::new(store).join(cache::get_path_for_sym(
+                        &module.debug_file,
+                        &module.debug_id,
+                    ));
+                    if sym.is_file() {
+                        info!(
+                            "{} {} already in the store",
+                            module.debug_file, module.debug_id
+                        );
+                        stored.fetch_add(1, Ordering::SeqCst);
+                        continue;
+                    }
+
+                    match dump_module(&module, config) {
+                        Ok(_) => {
+                            info!("{} {} dumped", module.debug_file, module.debug_id);
+                            dumped.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Err(e) => warn!(
+                            "Unable to dump {} ({} {}): {}",
+                            module.code_file, module.debug_file, module.debug_id, e
+                        ),
+                    }
+                }
+            });
+        }
+    })
+    .map_err(|_| "A dump thread panicked")?;
+
+    let dumped = dumped.load(Ordering::SeqCst);
+    let stored = stored.load(Ordering::SeqCst);
+    println!(
+        "{} modules dumped, {} already in the store, {} failed",
+        dumped,
+        stored,
+        total - dumped - stored
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn add_string(buf: &mut Vec<u8>, s: &str) -> u32 {
+        let rva = buf.len() as u32;
+        let chars: Vec<u16> = s.encode_utf16().collect();
+        buf.extend_from_slice(&(2 * chars.len() as u32).to_le_bytes());
+        for c in chars {
+            buf.extend_from_slice(&c.to_le_bytes());
+        }
+        rva
+    }
+
+    fn build_minidump(modules: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        // The header and a directory with the module list only
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MINIDUMP_SIGNATURE);
+        buf.extend_from_slice(&[0x93, 0xa7, 0, 0]);
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend_from_slice(&32u32.to_le_bytes());
+        buf.resize(32, 0);
+
+        let size = 4 + MODULE_SIZE * modules.len();
+        buf.extend_from_slice(&MODULE_LIST_STREAM.to_le_bytes());
+        buf.extend_from_slice(&(size as u32).to_le_bytes());
+        buf.extend_from_slice(&44u32.to_le_bytes());
+
+        buf.extend_from_slice(&(modules.len() as u32).to_le_bytes());
+        let list = buf.len();
+        buf.resize(list + MODULE_SIZE * modules.len(), 0);
+
+        for (i, (name, cv)) in modules.iter().enumerate() {
+            let module = list + MODULE_SIZE * i;
+            let name = add_string(&mut buf, name);
+            buf[module + 20..module + 24].copy_from_slice(&name.to_le_bytes());
+            let rva = buf.len() as u32;
+            buf.extend_from_slice(cv);
+            buf[module + 76..module + 80].copy_from_slice(&(cv.len() as u32).to_le_bytes());
+            buf[module + 80..module + 84].copy_from_slice(&rva.to_le_bytes());
+        }
+
+        buf
+    }
+
+    #[test]
+    fn test_get_modules() {
+        let guid = [
+            0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x78, 0x56, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+            0x07, 0x08,
+        ];
+        let mut pdb = CV_SIGNATURE_PDB70.to_vec();
+        pdb.extend_from_slice(&guid);
+        pdb.extend_from_slice(&0x2au32.to_le_bytes());
+        pdb.extend_from_slice(b"c:\\builds\\obj\\xul.pdb\0");
+
+        let mut elf = CV_SIGNATURE_ELF.to_vec();
+        elf.extend_from_slice(&guid);
+        elf.extend_from_slice(&[0xaa, 0xbb, 0xcc, 0xdd]);
+
+        let buf = build_minidump(&[
+            ("C:\\Program Files\\Firefox\\xul.dll", pdb.clone()),
+            ("/usr/lib/libfoo.so.1", elf),
+            ("C:\\Windows\\foo.dll", Vec::new()),
+            ("C:\\Program Files\\Firefox\\xul.dll", pdb),
+        ]);
+
+        assert_eq!(
+            get_modules(&buf).unwrap(),
+            vec![
+                Module {
+                    code_file: "/usr/lib/libfoo.so.1".to_string(),
+                    debug_file: "libfoo.so.1".to_string(),
+                    debug_id: "123456781234567801020304050607080".to_string(),
+                },
+                Module {
+                    code_file: "C:\\Program Files\\Firefox\\xul.dll".to_string(),
+                    debug_file: "xul.pdb".to_string(),
+                    debug_id: "123456781234567801020304050607082a".to_string(),
+                },
+            ]
+        );
+
+        assert!(get_modules(b"MDMP").is_err());
+        assert!(get_modules(b"ELF").is_err());
+    }
+}
@@ -0,0 +1,459 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use hashbrown::HashMap;
+use http::header::{HeaderName, HeaderValue};
+use log::{info, warn};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use zip::ZipArchive;
+
+use crate::cache;
+use crate::common;
+use crate::http_server::{self, Request, Response, ServerOptions};
+
+// The prefix of the urls where the files are stored with the Tecken layout
+const STORAGE: &str = "/_storage";
+// The url where the zip files are uploaded with the Tecken layout
+const UPLOAD: &str = "/upload/";
+
+/// How the files are served
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Layout {
+    /// Like the Microsoft symbol server: the files are at /xul.pdb/DEBUG_ID/xul.pd_
+    /// and the paths are case-insensitive
+    Microsoft,
+    /// Like Tecken (symbols.mozilla.org): the files are at /xul.pdb/DEBUG_ID/xul.sym
+    /// and the requests are redirected to the storage (the zip files posted to /upload/
+    /// are extracted in the root)
+    Tecken,
+}
+
+impl Layout {
+    pub(crate) fn from_str(s: &str) -> common::Result<Self> {
+        match s {
+            "ms" | "microsoft" => Ok(Self::Microsoft),
+            "tecken" => Ok(Self::Tecken),
+            _ => Err(format!("Invalid layout {}: must be ms or tecken", s).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MockServer {
+    root: PathBuf,
+    layout: Layout,
+    // The headers which must be in the requests (e.g. an auth token)
+    required_headers: Vec<(HeaderName, HeaderValue)>,
+    // The number of 503 responses sent for each path before serving it
+    fail_first: usize,
+    failures: Mutex<HashMap<String, usize>>,
+}
+
+impl MockServer {
+    pub(crate) fn new(
+        root: &str,
+        layout: Layout,
+        required_headers: &Option<Vec<&str>>,
+        fail_first: usize,
+    ) -> common::Result<Self> {
+        let root = PathBuf::from(root);
+        if !root.is_dir() {
+            return Err(format!("The root {} is not a directory", root.display()).into());
+        }
+
+        Ok(Self {
+            root,
+            layout,
+            required_headers: required_headers
+                .iter()
+                .flatten()
+                .map(|h| cache::parse_header(h))
+                .collect::<common::Result<_>>()?,
+            fail_first,
+            failures: Mutex::new(HashMap::default()),
+        })
+    }
+
+    /// Bind the server on 127.0.0.1:port (0 to get a free port) and serve the requests
+    /// in a background thread
+    pub(crate) fn start(self, port: u16) -> common::Result<SocketAddr> {
+        let server = Arc::new(self);
+        http_server::start("mock-server", &ServerOptions::local(port), move |request| {
+            server.get_response(request)
+        })
+    }
+
+    fn has_required_headers(&self, request: &Request) -> bool {
+        self.required_headers.iter().all(|(key, value)| {
+            request
+                .headers
+                .iter()
+                .any(|(k, v)| k == key.as_str() && value.to_str().map_or(false, |value| v == value))
+        })
+    }
+
+    fn must_fail(&self, path: &str) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        let count = failures.entry(path.to_string()).or_insert(0);
+        if *count < self.fail_first {
+            *count += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Extract the zip file in the multipart body of an upload
+    fn extract_upload(&self, body: &[u8]) -> common::Result<usize> {
+        let start = body
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or("Invalid multipart body")?
+            + 4;
+        // The zip file is followed by \r\n--boundary--\r\n
+        let end = body
+            .windows(4)
+            .rposition(|w| w == b"\r\n--")
+            .filter(|end| *end >= start)
+            .ok_or("Invalid multipart body")?;
+
+        let mut zip = ZipArchive::new(Cursor::new(&body[start..end]))?;
+        let mut count = 0;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            let path = match file.enclosed_name() {
+                Some(path) if file.is_file() => self.root.join(path),
+                _ => continue,
+            };
+            fs::create_dir_all(path.parent().unwrap())?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            fs::write(&path, data)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    fn get_upload_response(&self, request: &Request) -> Response {
+        match self.extract_upload(&request.body) {
+            Ok(count) => {
+                info!("Mock server: {} files uploaded", count);
+                let mut response = Response::new(201, "Created");
+                response.body = format!("{{\"upload\": {{\"files\": {}}}}}", count).into_bytes();
+                response
+            }
+            Err(e) => {
+                warn!("Mock server: invalid upload: {}", e);
+                Response::new(400, "Bad Request")
+            }
+        }
+    }
+
+    fn get_response(&self, request: &Request) -> Response {
+        let is_upload =
+            self.layout == Layout::Tecken && request.method == "POST" && request.path == UPLOAD;
+        if request.method != "GET" && request.method != "HEAD" && !is_upload {
+            return Response::new(405, "Method Not Allowed");
+        }
+        if !self.has_required_headers(request) {
+            return Response::new(403, "Forbidden");
+        }
+        if self.must_fail(&request.path) {
+            return Response::new(503, "Service Unavailable");
+        }
+        if is_upload {
+            return self.get_upload_response(request);
+        }
+
+        let (path, redirect) = match self.layout {
+            Layout::Microsoft => (request.path.as_str(), false),
+            Layout::Tecken => match request.path.strip_prefix(STORAGE) {
+                Some(path) => (path, false),
+                _ => (request.path.as_str(), true),
+            },
+        };
+
+        let file = match self.find_file(path) {
+            Some(file) => file,
+            _ => return Response::new(404, "Not Found"),
+        };
+
+        if redirect {
+            let mut response = Response::new(302, "Found");
+            response.location = Some(format!("{}{}", STORAGE, request.path));
+            return response;
+        }
+
+        match fs::read(&file) {
+            Ok(body) => {
+                info!("Mock server: serve {}", file.display());
+                match get_range(request, body.len()) {
+                    Some(range) => {
+                        let mut response = Response::new(206, "Partial Content");
+                        response.content_range = Some(format!(
+                            "bytes {}-{}/{}",
+                            range.start,
+                            range.end - 1,
+                            body.len()
+                        ));
+                        response.body = body[range].to_vec();
+                        response
+                    }
+                    _ => {
+                        let mut response = Response::new(200, "OK");
+                        response.body = body;
+                        response
+                    }
+                }
+            }
+            _ => Response::new(500, "Internal Server Error"),
+        }
+    }
+
+    fn find_file(&self, path: &str) -> Option<PathBuf> {
+        let mut file = self.root.clone();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if component == "." || component == ".." || component.contains('\\') {
+                return None;
+            }
+            file = match self.layout {
+                Layout::Microsoft => Self::find_entry(&file, component)?,
+                Layout::Tecken => file.join(component),
+            };
+        }
+
+        if file.is_file() {
+            Some(file)
+        } else {
+            None
+        }
+    }
+
+    /// The Microsoft symbol server is case-insensitive
+    fn find_entry(dir: &Path, name: &str) -> Option<PathBuf> {
+        let path = dir.join(name);
+        if path.exists() {
+            return Some(path);
+        }
+
+        fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .find(|e| e.file_name().to_string_lossy().eq_ignore_ascii_case(name))
+            .map(|e| e.path())
+    }
+}
+
+/// The range in a Range header (bytes=0-1023 or bytes=1024-): None to send the whole file
+fn get_range(request: &Request, size: usize) -> Option<Range<usize>> {
+    let (_, value) = request.headers.iter().find(|(k, _)| k == "range")?;
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let start: usize = start.parse().ok()?;
+    let end = match end {
+        "" => size,
+        end => end.parse::<usize>().ok()?.saturating_add(1).min(size),
+    };
+    Some(start..end).filter(|r| r.start < r.end)
+}
+
+/// Serve the files in root until the process is killed
+pub(crate) fn serve(server: MockServer, port: u16) -> common::Result<()> {
+    let layout = server.layout;
+    let addr = server.start(port)?;
+    println!(
+        "Mock symbol server ({:?} layout) listening on http://{}",
+        layout, addr
+    );
+
+    loop {
+        thread::park();
+    }
+}
+
+// The servers are queried with the network feature
+#[cfg(all(test, feature = "network"))]
+mod tests {
+
+    use std::fs::{copy, create_dir_all, read};
+    use tempfile::Builder;
+
+    use super::*;
+    use crate::cache::HttpOptions;
+    use crate::upload::{self, UploadOptions};
+    use std::time::Duration;
+
+    const DEBUG_ID: &str = "D09EA7D6D2C24C1EBFFE78B0C866BB7F2";
+
+    fn get_root() -> tempfile::TempDir {
+        let root = Builder::new().prefix("mock_root").tempdir().unwrap();
+        let dir = root.path().join("basic64.pdb").join(DEBUG_ID);
+        create_dir_all(&dir).unwrap();
+        copy("./test_data/windows/basic64.pdb", dir.join("basic64.pdb")).unwrap();
+        root
+    }
+
+    fn search(addr: SocketAddr, headers: Option<Vec<&str>>) -> Option<Vec<u8>> {
+        let cache = Builder::new().prefix("mock_cache").tempdir().unwrap();
+        let sympath = format!("SRV*{}*http://{}", cache.path().display(), addr);
+        let http = HttpOptions::new(None, &headers).unwrap();
+        let servers = cache::get_sym_servers(Some(&sympath), &http).unwrap();
+
+        cache::search_file("basic64.pdb".to_string(), DEBUG_ID, servers.as_ref()).0
+    }
+
+    #[test]
+    fn test_microsoft_layout() {
+        let root = get_root();
+        let server = MockServer::new(
+            root.path().to_str().unwrap(),
+            Layout::Microsoft,
+            &Some(vec!["Auth-Token:123abc"]),
+            0,
+        )
+        .unwrap();
+        let addr = server.start(0).unwrap();
+
+        assert!(search(addr, None).is_none());
+        assert!(search(addr, Some(vec!["Auth-Token:foo"])).is_none());
+
+        let pdb = search(addr, Some(vec!["Auth-Token:123abc"])).unwrap();
+        assert_eq!(pdb, read("./test_data/windows/basic64.pdb").unwrap());
+
+        // Case-insensitive
+        assert!(server_has(
+            addr,
+            &format!("/BASIC64.PDB/{}/basic64.PDB", DEBUG_ID),
+            Some("123abc")
+        ));
+    }
+
+    #[test]
+    fn test_tecken_layout() {
+        let root = get_root();
+        let server =
+            MockServer::new(root.path().to_str().unwrap(), Layout::Tecken, &None, 0).unwrap();
+        let addr = server.start(0).unwrap();
+
+        let pdb = search(addr, None).unwrap();
+        assert_eq!(pdb, read("./test_data/windows/basic64.pdb").unwrap());
+
+        assert!(!server_has(
+            addr,
+            &format!("/BASIC64.PDB/{}/basic64.PDB", DEBUG_ID),
+            None
+        ));
+    }
+
+    #[test]
+    fn test_fail_first() {
+        let root = get_root();
+        let server =
+            MockServer::new(root.path().to_str().unwrap(), Layout::Microsoft, &None, 1).unwrap();
+        let addr = server.start(0).unwrap();
+        let path = format!("/basic64.pdb/{}/basic64.pdb", DEBUG_ID);
+
+        assert!(!server_has(addr, &path, None));
+        assert!(server_has(addr, &path, None));
+    }
+
+    #[test]
+    fn test_open_file() {
+        let root = get_root();
+        let server =
+            MockServer::new(root.path().to_str().unwrap(), Layout::Tecken, &None, 0).unwrap();
+        let addr = server.start(0).unwrap();
+        let cache = Builder::new().prefix("mock_cache").tempdir().unwrap();
+        let sympath = format!("SRV*{}*http://{}", cache.path().display(), addr);
+        let servers = cache::get_sym_servers(Some(&sympath), &HttpOptions::default()).unwrap();
+
+        // The PDB is got with some range requests and it's written in the cache once downloaded
+        let pdb = read("./test_data/windows/basic64.pdb").unwrap();
+        let file = cache::open_file("basic64.pdb", DEBUG_ID, servers.as_ref()).unwrap();
+        assert_eq!(*file.wait().unwrap(), pdb);
+        let cached = cache
+            .path()
+            .join("basic64.pdb")
+            .join(DEBUG_ID)
+            .join("basic64.pdb");
+        assert_eq!(read(cached).unwrap(), pdb);
+        assert!(cache::open_file("basic64.pdb", DEBUG_ID, servers.as_ref()).is_none());
+
+        // The PDB of the PE is parsed while it's downloaded
+        let cache = Builder::new().prefix("mock_cache").tempdir().unwrap();
+        let sympath = format!("SRV*{}*http://{}", cache.path().display(), addr);
+        let pe = read("./test_data/windows/basic64.dll").unwrap();
+        let dump = |dumper: crate::Dumper| {
+            let mut sym = Vec::new();
+            dumper.dump(&mut sym).unwrap();
+            sym
+        };
+        let sym = dump(
+            crate::Dumper::from_bytes(&pe)
+                .with_name("basic64.dll")
+                .with_symbol_server(&sympath),
+        );
+        assert_eq!(
+            sym,
+            dump(crate::Dumper::from_path("./test_data/windows/basic64.dll"))
+        );
+    }
+
+    #[test]
+    fn test_upload() {
+        let root = Builder::new().prefix("mock_root").tempdir().unwrap();
+        let server = MockServer::new(
+            root.path().to_str().unwrap(),
+            Layout::Tecken,
+            &Some(vec!["Auth-Token:123abc"]),
+            1,
+        )
+        .unwrap();
+        let addr = server.start(0).unwrap();
+
+        let store = Builder::new().prefix("mock_store").tempdir().unwrap();
+        let dir = store.path().join("basic64.pdb").join(DEBUG_ID);
+        create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("basic64.sym"), "MODULE windows x86_64").unwrap();
+
+        let mut options = UploadOptions {
+            url: format!("http://{}{}", addr, UPLOAD),
+            token: "foo".to_string(),
+            max_size: 1024,
+            retries: 1,
+            retry_delay: Duration::from_millis(0),
+            http: HttpOptions::default(),
+        };
+        let paths = [store.path().to_str().unwrap()];
+        assert!(upload::upload(&paths, &options).is_err());
+
+        // The first upload gets a 503 so it's retried
+        options.token = "123abc".to_string();
+        upload::upload(&paths, &options).unwrap();
+
+        let sym = read(
+            root.path()
+                .join("basic64.pdb")
+                .join(DEBUG_ID)
+                .join("basic64.sym"),
+        );
+        assert_eq!(sym.unwrap(), b"MODULE windows x86_64");
+    }
+
+    fn server_has(addr: SocketAddr, path: &str, token: Option<&str>) -> bool {
+        let url = format!("http://{}{}", addr, path);
+        let mut request = reqwest::blocking::Client::new().head(&url);
+        if let Some(token) = token {
+            request = request.header("Auth-Token", token);
+        }
+        request.send().map_or(false, |r| r.status() == 200)
+    }
+}
@@ -0,0 +1,88 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The settings of a dump (what's read in the debug info and how the records are written) given
+// on the command line or by the Dumper of the library. They're in the Config of the dump and
+// passed down to the dumpers, so several dumps with different settings can run in a process.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::check::Checker;
+use crate::demangling::Demangling;
+use crate::folding::FoldingPolicy;
+use crate::linux::debuglink;
+use crate::linux::split_dwarf::SplitDwarfOptions;
+use crate::output::{Compression, OutputFormat, Records, SymbolSelection};
+use crate::publics::PublicsPolicy;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Options {
+    pub format: OutputFormat,
+    pub compression: Compression,
+    pub records: Records,
+    // The INFO records given with --info
+    pub info_records: Vec<(String, String)>,
+    pub symbol_selection: Option<SymbolSelection>,
+    pub output_template: Option<String>,
+    pub publics_policy: PublicsPolicy,
+    pub symtab_functions: bool,
+    pub folding_policy: FoldingPolicy,
+    // The maximal number of chars in a name (0 means no limit)
+    pub max_name_length: usize,
+    pub demangling: Demangling,
+    pub signatures: bool,
+    pub inlines: bool,
+    pub checksums: bool,
+    pub embed_sources: bool,
+    pub srcsrv: bool,
+    pub stack_win_cfi: bool,
+    pub force_id_match: bool,
+    pub type_server_paths: Vec<PathBuf>,
+    pub split_dwarf: SplitDwarfOptions,
+    // Where the debug files of the stripped binaries are searched
+    pub debug_dirs: Vec<PathBuf>,
+    // The number of threads used inside a module
+    pub num_threads: usize,
+    // With --check or --strict, the reports of the modules
+    pub checker: Option<Arc<Checker>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Breakpad,
+            compression: Compression::None,
+            records: Records::All,
+            info_records: Vec::new(),
+            symbol_selection: None,
+            output_template: None,
+            publics_policy: PublicsPolicy::Default,
+            symtab_functions: false,
+            folding_policy: FoldingPolicy::Multiple,
+            max_name_length: 0,
+            demangling: Demangling::Full,
+            signatures: false,
+            inlines: false,
+            checksums: false,
+            embed_sources: false,
+            srcsrv: false,
+            stack_win_cfi: false,
+            force_id_match: false,
+            type_server_paths: Vec::new(),
+            split_dwarf: SplitDwarfOptions::default(),
+            debug_dirs: vec![PathBuf::from(debuglink::DEBUG_DIR)],
+            num_threads: num_cpus::get(),
+            checker: None,
+        }
+    }
+}
+
+impl Options {
+    /// With --check, the modules are checked instead of being written
+    pub(crate) fn is_check(&self) -> bool {
+        self.checker.as_ref().map_or(false, |c| c.is_check())
+    }
+}
@@ -0,0 +1,697 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The Breakpad records are the model shared by all the dumpers: with another output format,
+// they're converted by a writer and the document is written once all of them have been received.
+// The files in a symbol store are always some Breakpad ones.
+// The outputs can be compressed while they're written (xul.sym.gz, xul.sym.zst).
+// The CFI (the STACK records) can be removed from the output or written alone (or in another file)
+// for the consumers which only need the unwind info or only the symbols.
+// Some INFO records given with --info (e.g. the url of the repository or the id of the CI job)
+// are added after the MODULE and INFO ones written by the dumpers.
+// The FUNC (with their lines and inlines) and PUBLIC records in the output can be restricted to
+// the ones whose name matches a regex (--filter-symbols) and/or in an address range (--range).
+// The names longer than --max-name-length are shortened while the records are written.
+
+use flate2::write::GzEncoder;
+use regex::Regex;
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use crate::common;
+use crate::json::JsonWriter;
+use crate::symcache::SymCacheConverter;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum OutputFormat {
+    Breakpad = 0,
+    Json = 1,
+    SymCache = 2,
+    /// A JSON object per record, written while the records are received
+    Ndjson = 3,
+}
+
+pub(crate) fn parse_format(format: &str) -> common::Result<OutputFormat> {
+    Ok(match format {
+        "breakpad" => OutputFormat::Breakpad,
+        "json" => OutputFormat::Json,
+        "symcache" => OutputFormat::SymCache,
+        "ndjson" => OutputFormat::Ndjson,
+        _ => {
+            return Err(format!(
+                "Invalid format {}: must be breakpad, json, ndjson or symcache",
+                format
+            )
+            .into())
+        }
+    })
+}
+
+/// A writer receiving the Breakpad records and writing them in another format
+pub(crate) trait Converter: Write + Send {
+    fn write_document(&mut self, output: &mut dyn Write) -> common::Result<()>;
+}
+
+/// Get the converter for the output format (None for Breakpad and ndjson which is converted
+/// while it's written)
+pub(crate) fn get_converter(format: OutputFormat, checksums: bool) -> Option<Box<dyn Converter>> {
+    match format {
+        OutputFormat::Breakpad | OutputFormat::Ndjson => None,
+        OutputFormat::Json => Some(Box::new(JsonWriter::new(checksums))),
+        OutputFormat::SymCache => Some(Box::new(SymCacheConverter::default())),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Compression {
+    None = 0,
+    Gzip = 1,
+    Zstd = 2,
+}
+
+pub(crate) fn parse_compression(compression: &str) -> common::Result<Compression> {
+    Ok(match compression {
+        "gzip" => Compression::Gzip,
+        "zstd" => Compression::Zstd,
+        _ => {
+            return Err(format!("Invalid compression {}: must be gzip or zstd", compression).into())
+        }
+    })
+}
+
+impl Compression {
+    /// Append the extension of the compression to a path (unless it's already there)
+    pub(crate) fn get_path(self, path: &str) -> String {
+        let ext = match self {
+            Compression::None => return path.to_string(),
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        };
+        if path.ends_with(ext) {
+            path.to_string()
+        } else {
+            format!("{}{}", path, ext)
+        }
+    }
+}
+
+/// A writer compressing the data while they're written
+pub(crate) enum Compressor<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Compressor<W> {
+    pub(crate) fn new(output: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Self::None(output),
+            Compression::Gzip => Self::Gzip(GzEncoder::new(output, flate2::Compression::default())),
+            Compression::Zstd => Self::Zstd(zstd::Encoder::new(output, 0)?),
+        })
+    }
+
+    /// Write the end of the compressed stream and get the underlying writer
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Self::None(output) => Ok(output),
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::None(output) => output.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::None(output) => output.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// The records written in the output (the files in a symbol store have all of them)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Records {
+    All = 0,
+    NoCfi = 1,
+    OnlyCfi = 2,
+    /// The CFI are written in a .cfi file next to the output
+    SplitCfi = 3,
+}
+
+pub(crate) fn get_records(
+    no_cfi: bool,
+    only_cfi: bool,
+    split_cfi: bool,
+) -> common::Result<Records> {
+    Ok(match (no_cfi, only_cfi, split_cfi) {
+        (false, false, false) => Records::All,
+        (true, false, false) => Records::NoCfi,
+        (false, true, false) => Records::OnlyCfi,
+        (false, false, true) => Records::SplitCfi,
+        _ => return Err("--no-cfi, --only-cfi and --split-cfi are mutually exclusive".into()),
+    })
+}
+
+/// The FUNC and PUBLIC records to keep in the output
+#[derive(Clone, Debug)]
+pub(crate) struct SymbolSelection {
+    regex: Option<Regex>,
+    // [start, end)
+    range: Option<(u64, u64)>,
+}
+
+impl SymbolSelection {
+    fn new(regex: Option<&str>, range: Option<&str>) -> common::Result<Self> {
+        let regex = regex
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("Invalid regex for --filter-symbols: {}", e))?;
+        let range = range.map(Self::parse_range).transpose()?;
+        Ok(Self { regex, range })
+    }
+
+    fn parse_range(range: &str) -> common::Result<(u64, u64)> {
+        let parse = |addr: &str| {
+            let addr = addr.trim();
+            let addr = addr
+                .strip_prefix("0x")
+                .or_else(|| addr.strip_prefix("0X"))
+                .unwrap_or(addr);
+            u64::from_str_radix(addr, 16).ok()
+        };
+        match range.split_once('-').map(|(s, e)| (parse(s), parse(e))) {
+            Some((Some(start), Some(end))) if start < end => Ok((start, end)),
+            _ => Err(format!(
+                "Invalid range {}: must be START-END (hexadecimal addresses)",
+                range
+            )
+            .into()),
+        }
+    }
+
+    fn is_selected(&self, name: &str, address: u64, size: u64) -> bool {
+        self.regex.as_ref().map_or(true, |re| re.is_match(name))
+            && self.range.map_or(true, |(start, end)| {
+                address < end && address + size.max(1) > start
+            })
+    }
+
+    /// Check a FUNC or a PUBLIC record (None for the other ones)
+    fn is_selected_record(&self, line: &str) -> Option<bool> {
+        let (is_func, rest) = if let Some(rest) = line.strip_prefix("FUNC ") {
+            (true, rest)
+        } else {
+            (false, line.strip_prefix("PUBLIC ")?)
+        };
+        let rest = rest.strip_prefix("m ").unwrap_or(rest);
+        // FUNC address size parameter_size name, PUBLIC address parameter_size name
+        let mut parts = rest.splitn(if is_func { 4 } else { 3 }, ' ');
+        let address = u64::from_str_radix(parts.next()?, 16).ok()?;
+        let size = if is_func {
+            u64::from_str_radix(parts.next()?, 16).ok()?
+        } else {
+            0
+        };
+        let name = parts.nth(1).unwrap_or_default().trim_end();
+        Some(self.is_selected(name, address, size))
+    }
+}
+
+/// Get the selection from --filter-symbols and --range (None when there is nothing to filter)
+pub(crate) fn get_symbol_selection(
+    regex: Option<&str>,
+    range: Option<&str>,
+) -> common::Result<Option<SymbolSelection>> {
+    if regex.is_some() || range.is_some() {
+        Ok(Some(SymbolSelection::new(regex, range)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Get the INFO records to add from the KEY VALUE pairs given with --info
+pub(crate) fn parse_info_records(values: &[&str]) -> common::Result<Vec<(String, String)>> {
+    let mut records = Vec::new();
+    for pair in values.chunks(2) {
+        let (key, value) = match pair {
+            [key, value] => (key.trim(), value.trim()),
+            _ => return Err("--info requires a key and a value".into()),
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!("Invalid INFO key: \"{}\"", key).into());
+        }
+        if key == "CODE_ID" || key == "RELEASE" {
+            return Err(format!("INFO {} is written by dump_syms", key).into());
+        }
+        if value.contains('\n') || value.contains('\r') {
+            return Err(format!("The value of INFO {} can't contain a newline", key).into());
+        }
+        records.push((key.to_string(), value.to_string()));
+    }
+    Ok(records)
+}
+
+/// A writer adding the INFO records given with --info after the MODULE and INFO ones
+pub(crate) struct InfoInjector<W: Write> {
+    output: W,
+    // The records to add (empty once they've been written)
+    records: Vec<u8>,
+    // The beginning of a line split between two writes
+    line: Vec<u8>,
+}
+
+impl<W: Write> InfoInjector<W> {
+    pub(crate) fn new(output: W, records: &[(String, String)]) -> Self {
+        Self {
+            output,
+            records: records
+                .iter()
+                .map(|(key, value)| format!("INFO {} {}\n", key, value))
+                .collect::<String>()
+                .into_bytes(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Write the records if there is nothing after the header and get the underlying writer
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        let line = std::mem::take(&mut self.line);
+        if !line.is_empty() && !line.ends_with(b"\n") {
+            // The last line of the header doesn't end with a newline
+            self.output.write_all(&line)?;
+            self.output.write_all(b"\n")?;
+        } else {
+            self.output.write_all(&line)?;
+        }
+        self.output.write_all(&std::mem::take(&mut self.records))?;
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> Write for InfoInjector<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.records.is_empty() {
+            return self.output.write(buf);
+        }
+
+        let mut rest = buf;
+        while let Some(pos) = rest.iter().position(|c| *c == b'\n') {
+            let (line, tail) = rest.split_at(pos + 1);
+            let mut full = std::mem::take(&mut self.line);
+            full.extend_from_slice(line);
+            if !full.starts_with(b"MODULE ") && !full.starts_with(b"INFO ") {
+                // The end of the header
+                self.output.write_all(&std::mem::take(&mut self.records))?;
+                self.output.write_all(&full)?;
+                self.output.write_all(tail)?;
+                return Ok(buf.len());
+            }
+            self.output.write_all(&full)?;
+            rest = tail;
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// A writer shortening the names of the FUNC, PUBLIC and INLINE_ORIGIN records with more than
+/// max chars
+pub(crate) struct NameLimiter<W: Write> {
+    output: W,
+    max: usize,
+    // The beginning of a line split between two writes
+    line: Vec<u8>,
+}
+
+impl<W: Write> NameLimiter<W> {
+    pub(crate) fn new(output: W, max: usize) -> Self {
+        Self {
+            output,
+            max,
+            line: Vec::new(),
+        }
+    }
+
+    /// Get the position of the name in a FUNC, PUBLIC or INLINE_ORIGIN record
+    fn get_name_pos(line: &str) -> Option<usize> {
+        // FUNC address size parameter_size name, PUBLIC address parameter_size name
+        let (rest, fields) = if let Some(rest) = line.strip_prefix("FUNC ") {
+            (rest.strip_prefix("m ").unwrap_or(rest), 3)
+        } else if let Some(rest) = line.strip_prefix("PUBLIC ") {
+            (rest.strip_prefix("m ").unwrap_or(rest), 2)
+        } else {
+            (line.strip_prefix("INLINE_ORIGIN ")?, 1)
+        };
+        let mut pos = line.len() - rest.len();
+        for _ in 0..fields {
+            pos += line[pos..].find(' ')? + 1;
+        }
+        Some(pos)
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line,
+            _ => return self.output.write_all(line),
+        };
+        let record = line.trim_end_matches(&['\n', '\r'][..]);
+        match Self::get_name_pos(record) {
+            Some(pos) => match common::truncate_name(&record[pos..], self.max) {
+                Cow::Owned(name) => {
+                    self.output.write_all(&record.as_bytes()[..pos])?;
+                    self.output.write_all(name.as_bytes())?;
+                    self.output.write_all(&line.as_bytes()[record.len()..])
+                }
+                Cow::Borrowed(_) => self.output.write_all(line.as_bytes()),
+            },
+            _ => self.output.write_all(line.as_bytes()),
+        }
+    }
+
+    /// Write the last line (if it doesn't end with a newline) and get the underlying writer
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        let line = std::mem::take(&mut self.line);
+        self.write_line(&line)?;
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> Write for NameLimiter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(pos) = rest.iter().position(|c| *c == b'\n') {
+            let (line, tail) = rest.split_at(pos + 1);
+            if self.line.is_empty() {
+                self.write_line(line)?;
+            } else {
+                let mut full = std::mem::take(&mut self.line);
+                full.extend_from_slice(line);
+                self.write_line(&full)?;
+            }
+            rest = tail;
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Get the path of the CFI file next to a symbol file (xul.sym => xul.cfi)
+pub(crate) fn get_cfi_path(path: &str) -> String {
+    format!("{}.cfi", path.strip_suffix(".sym").unwrap_or(path))
+}
+
+/// A writer keeping the records to write in an output: the STACK ones for the CFI and
+/// the other ones for the symbols (MODULE and INFO are always kept)
+pub(crate) struct RecordFilter<W: Write> {
+    output: W,
+    records: Records,
+    selection: Option<SymbolSelection>,
+    // The line and INLINE records are kept with their FUNC
+    in_selected_func: bool,
+    // The beginning of a line split between two writes
+    line: Vec<u8>,
+}
+
+impl<W: Write> RecordFilter<W> {
+    pub(crate) fn new(output: W, records: Records) -> Self {
+        Self {
+            output,
+            records,
+            selection: None,
+            in_selected_func: true,
+            line: Vec::new(),
+        }
+    }
+
+    /// Only keep the FUNC and PUBLIC records in the selection
+    pub(crate) fn with_selection(mut self, selection: Option<SymbolSelection>) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    fn is_selected(&mut self, line: &[u8]) -> bool {
+        let selection = match self.selection.as_ref() {
+            Some(selection) => selection,
+            _ => return true,
+        };
+        let is_line_record = line.split(|c| *c == b' ').next().map_or(false, |addr| {
+            !addr.is_empty() && addr.iter().all(u8::is_ascii_hexdigit)
+        });
+        if is_line_record || line.starts_with(b"INLINE ") {
+            return self.in_selected_func;
+        }
+        match selection.is_selected_record(&String::from_utf8_lossy(line)) {
+            Some(selected) => {
+                if line.starts_with(b"FUNC ") {
+                    self.in_selected_func = selected;
+                }
+                selected
+            }
+            _ => true,
+        }
+    }
+
+    fn is_kept(&mut self, line: &[u8]) -> bool {
+        if line.starts_with(b"MODULE ") || line.starts_with(b"INFO ") {
+            return true;
+        }
+        if !self.is_selected(line) {
+            return false;
+        }
+        self.records == Records::All
+            || line.starts_with(b"STACK ") == (self.records == Records::OnlyCfi)
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.line.is_empty() {
+            if self.is_kept(line) {
+                self.output.write_all(line)?;
+            }
+        } else {
+            let mut full = std::mem::take(&mut self.line);
+            full.extend_from_slice(line);
+            if self.is_kept(&full) {
+                self.output.write_all(&full)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the last line (if it doesn't end with a newline) and get the underlying writer
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            if self.is_kept(&line) {
+                self.output.write_all(&line)?;
+            }
+        }
+        Ok(self.output)
+    }
+}
+
+impl<W: Write> Write for RecordFilter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.records == Records::All && self.selection.is_none() {
+            return self.output.write(buf);
+        }
+
+        let mut rest = buf;
+        while let Some(pos) = rest.iter().position(|c| *c == b'\n') {
+            let (line, tail) = rest.split_at(pos + 1);
+            self.write_line(line)?;
+            rest = tail;
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_compression() {
+        assert_eq!(Compression::None.get_path("xul.sym"), "xul.sym");
+        assert_eq!(Compression::Gzip.get_path("xul.sym"), "xul.sym.gz");
+        assert_eq!(Compression::Gzip.get_path("xul.sym.gz"), "xul.sym.gz");
+        assert_eq!(Compression::Zstd.get_path("xul.sym"), "xul.sym.zst");
+
+        let data = b"MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n";
+        let mut compressor = Compressor::new(Vec::new(), Compression::Gzip).unwrap();
+        compressor.write_all(data).unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn test_record_filter() {
+        let data = "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n\
+                       INFO CODE_ID B060AD20C6B47781552708AA192E7739FAC7C84A\n\
+                       FILE 0 /tmp/basic.cpp\n\
+                       FUNC 1000 20 0 main\n\
+                       1000 20 3 0\n\
+                       STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+                       STACK CFI 1001 .cfa: $rsp 16 +\n";
+
+        let filter = |records| {
+            let mut filter = RecordFilter::new(Vec::new(), records);
+            // Some lines are split between two writes
+            for chunk in data.as_bytes().chunks(7) {
+                filter.write_all(chunk).unwrap();
+            }
+            String::from_utf8(filter.finish().unwrap()).unwrap()
+        };
+
+        let (cfi, symbols) = (filter(Records::OnlyCfi), filter(Records::NoCfi));
+        assert_eq!(filter(Records::All), data);
+        assert_eq!(cfi.lines().count(), 4);
+        assert!(cfi.lines().skip(2).all(|l| l.starts_with("STACK ")));
+        assert_eq!(symbols.lines().count(), 5);
+        assert!(!symbols.contains("STACK "));
+        assert!(cfi.starts_with("MODULE ") && symbols.starts_with("MODULE "));
+
+        assert_eq!(get_cfi_path("xul.sym"), "xul.cfi");
+        assert_eq!(get_cfi_path("xul"), "xul.cfi");
+    }
+
+    #[test]
+    fn test_symbol_selection() {
+        let data = "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n\
+                    FILE 0 /tmp/basic.cpp\n\
+                    INLINE_ORIGIN 0 inlined\n\
+                    FUNC 1000 20 0 main\n\
+                    1000 20 3 0\n\
+                    FUNC m 2000 10 0 foo::bar\n\
+                    INLINE 0 4 0 0 2004 4\n\
+                    2000 10 5 0\n\
+                    PUBLIC 2010 0 foo::baz\n\
+                    PUBLIC 3000 0 _start\n\
+                    STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n";
+
+        let filter = |regex, range| {
+            let selection = SymbolSelection::new(regex, range).unwrap();
+            let mut filter =
+                RecordFilter::new(Vec::new(), Records::All).with_selection(Some(selection));
+            for chunk in data.as_bytes().chunks(7) {
+                filter.write_all(chunk).unwrap();
+            }
+            String::from_utf8(filter.finish().unwrap()).unwrap()
+        };
+
+        let foo = filter(Some("^foo::"), None);
+        assert!(!foo.contains("main") && !foo.contains("1000 20 3 0") && !foo.contains("_start"));
+        assert!(foo.contains("FUNC m 2000 10 0 foo::bar\nINLINE 0 4 0 0 2004 4\n2000 10 5 0\n"));
+        assert!(foo.contains("PUBLIC 2010 0 foo::baz\n"));
+        assert!(
+            foo.contains("FILE 0 ") && foo.contains("INLINE_ORIGIN 0 ") && foo.contains("STACK ")
+        );
+
+        let range = filter(None, Some("0x1010-2004"));
+        assert!(range.contains("FUNC 1000 20 0 main\n1000 20 3 0\n"));
+        assert!(range.contains("FUNC m 2000 10 0 foo::bar\n"));
+        assert!(!range.contains("PUBLIC"));
+
+        let both = filter(Some("bar"), Some("1000-1fff"));
+        assert!(!both.contains("FUNC"));
+
+        assert!(SymbolSelection::new(None, Some("2000-1000")).is_err());
+        assert!(SymbolSelection::new(None, Some("foo")).is_err());
+        assert!(SymbolSelection::new(Some("("), None).is_err());
+    }
+
+    #[test]
+    fn test_info_injector() {
+        let data = "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n\
+                    INFO CODE_ID B060AD20C6B47781552708AA192E7739FAC7C84A\n\
+                    FILE 0 /tmp/basic.cpp\n";
+
+        assert!(parse_info_records(&["CODE_ID", "1234"]).is_err());
+        assert!(parse_info_records(&["CI JOB", "1234"]).is_err());
+        let records = parse_info_records(&[
+            "URL",
+            "https://hg.mozilla.org/mozilla-central",
+            "CI_JOB",
+            "1234",
+        ])
+        .unwrap();
+
+        let mut injector = InfoInjector::new(Vec::new(), &records);
+        // Some lines are split between two writes
+        for chunk in data.as_bytes().chunks(7) {
+            injector.write_all(chunk).unwrap();
+        }
+        let output = String::from_utf8(injector.finish().unwrap()).unwrap();
+
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[2], "INFO URL https://hg.mozilla.org/mozilla-central");
+        assert_eq!(lines[3], "INFO CI_JOB 1234");
+        assert_eq!(lines[4], "FILE 0 /tmp/basic.cpp");
+    }
+
+    #[test]
+    fn test_name_limiter() {
+        let long = format!("foo<{}>(int)", "std::vector<int>, ".repeat(10));
+        let data = format!(
+            "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n\
+             FILE 0 /tmp/{}.cpp\n\
+             INLINE_ORIGIN 0 {}\n\
+             FUNC m 1000 20 0 {}\n\
+             1000 20 3 0\n\
+             PUBLIC 2010 0 {}\n\
+             PUBLIC 3000 0 _start",
+            long, long, long, long
+        );
+
+        let mut limiter = NameLimiter::new(Vec::new(), 64);
+        // Some lines are split between two writes
+        for chunk in data.as_bytes().chunks(7) {
+            limiter.write_all(chunk).unwrap();
+        }
+        let output = String::from_utf8(limiter.finish().unwrap()).unwrap();
+
+        let truncated = common::truncate_name(&long, 64);
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1], format!("FILE 0 /tmp/{}.cpp", long));
+        assert_eq!(lines[2], format!("INLINE_ORIGIN 0 {}", truncated));
+        assert_eq!(lines[3], format!("FUNC m 1000 20 0 {}", truncated));
+        assert_eq!(lines[5], format!("PUBLIC 2010 0 {}", truncated));
+        assert_eq!(lines[6], "PUBLIC 3000 0 _start");
+        assert!(!output.ends_with('\n'));
+    }
+}
@@ -0,0 +1,163 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --output-template, the output file name is built from the module: the placeholders
+// ({debug_file}, {debug_id}, {code_id}, {arch} and {os}) are replaced by the values in the
+// MODULE and INFO CODE_ID records. These records are the first ones written by a dump, so the
+// dump is stopped once they've been received to get them before writing the output.
+
+use std::io::{self, Write};
+
+use crate::common::{self, Dumpable};
+use crate::info::ModuleInfo;
+
+const PLACEHOLDERS: &[&str] = &["debug_file", "debug_id", "code_id", "arch", "os"];
+
+fn check_template(template: &str) -> common::Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed placeholder in the output template {}", template))?;
+        let name = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "Unknown placeholder {{{}}} in the output template: must be one of {{{}}}",
+                name,
+                PLACEHOLDERS.join("}, {")
+            )
+            .into());
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err(format!("Unopened placeholder in the output template {}", template).into());
+    }
+    Ok(())
+}
+
+/// Check the template given with --output-template
+pub(crate) fn parse_output_template(template: &str) -> common::Result<String> {
+    if template.is_empty() {
+        return Err("The output template is empty".into());
+    }
+    check_template(template)?;
+    Ok(template.to_string())
+}
+
+/// Replace the placeholders in the template (a module without code id gets an empty one)
+pub(crate) fn expand(template: &str, module: &ModuleInfo) -> String {
+    template
+        .replace("{debug_file}", &module.debug_file)
+        .replace("{debug_id}", &module.debug_id)
+        .replace("{code_id}", module.code_id.as_deref().unwrap_or_default())
+        .replace("{arch}", &module.arch)
+        .replace("{os}", &module.os)
+}
+
+/// A writer collecting the MODULE and INFO records and failing once they've been received
+#[derive(Default)]
+struct HeaderWriter {
+    header: Vec<u8>,
+    // The beginning of the current line
+    line: usize,
+    done: bool,
+}
+
+impl Write for HeaderWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.header.extend_from_slice(buf);
+        while let Some(pos) = self.header[self.line..].iter().position(|c| *c == b'\n') {
+            let line = &self.header[self.line..];
+            if !line.starts_with(b"MODULE ") && !line.starts_with(b"INFO ") {
+                self.header.truncate(self.line);
+                self.done = true;
+                return Err(io::Error::other("The header is complete"));
+            }
+            self.line += pos + 1;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Get the module info in the MODULE and INFO records written by the dump
+fn get_module<D: Dumpable>(dumpable: &D) -> common::Result<ModuleInfo> {
+    let mut writer = HeaderWriter::default();
+    let res = dumpable.dump(&mut writer);
+    if !writer.done {
+        // A dump with only a header
+        res?;
+    }
+    ModuleInfo::from_sym(&writer.header)
+}
+
+/// The output file name of a module with the template
+pub(crate) fn get_output<D: Dumpable>(template: &str, dumpable: &D) -> common::Result<String> {
+    Ok(expand(template, &get_module(dumpable)?))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::visitor::{visit_text, Visitor};
+
+    struct Module(&'static str);
+
+    impl Dumpable for Module {
+        fn dump<W: Write>(&self, mut writer: W) -> common::Result<()> {
+            // The records are written in small pieces
+            for piece in self.0.as_bytes().chunks(5) {
+                writer.write_all(piece)?;
+            }
+            Ok(())
+        }
+
+        fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+            visit_text(visitor, self.0)
+        }
+
+        fn get_name(&self) -> &str {
+            ""
+        }
+
+        fn get_debug_id(&self) -> &str {
+            ""
+        }
+    }
+
+    #[test]
+    fn test_output_template() {
+        let module = Module(
+            "MODULE Linux x86_64 0F1E2D3C4B5A69788796A5B4C3D2E1F00 libfoo.so\n\
+             INFO CODE_ID 3C2D1E0F5A4B7869\n\
+             FILE 0 foo.c\n\
+             PUBLIC 1000 0 foo\n",
+        );
+        assert_eq!(
+            get_output(
+                "out/{os}/{arch}/{debug_file}-{debug_id}-{code_id}.sym",
+                &module
+            )
+            .unwrap(),
+            "out/Linux/x86_64/libfoo.so-0F1E2D3C4B5A69788796A5B4C3D2E1F00-3C2D1E0F5A4B7869.sym"
+        );
+
+        let module = Module("MODULE windows x86 ABCD1 foo.pdb\n");
+        assert_eq!(
+            get_output("{debug_file}.{code_id}.sym", &module).unwrap(),
+            "foo.pdb..sym"
+        );
+
+        assert!(check_template("{debug_file}-{debug_id}.sym").is_ok());
+        assert!(check_template("{name}.sym").is_err());
+        assert!(check_template("{debug_file.sym").is_err());
+        assert!(check_template("debug_file}.sym").is_err());
+    }
+}
@@ -0,0 +1,113 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The work inside a module (e.g. the decoding of the units, the demangling of the function names
+// or the formatting of the records) is split between --num-threads threads (the number of cpus
+// by default) given in the options of the dump. The results are always in the order of the inputs so the output doesn't depend
+// on the number of threads.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::cancel;
+use crate::common;
+
+/// Check the number of threads given with --num-threads
+pub(crate) fn check_num_threads(num_threads: usize) -> common::Result<usize> {
+    if num_threads == 0 {
+        return Err("The number of threads must be greater than 0".into());
+    }
+    Ok(num_threads)
+}
+
+/// Apply f to the items in parallel and get the results in the order of the items
+pub(crate) fn map<T, R, F>(num_threads: usize, items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    map_with(num_threads, items, |_: &mut (), item| f(item))
+}
+
+/// Same as map but each thread has its own state (e.g. a reader which can't be shared)
+/// created when the thread starts
+pub(crate) fn map_with<T, S, R, F>(num_threads: usize, items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    S: Default,
+    R: Send,
+    F: Fn(&mut S, &T) -> R + Sync,
+{
+    let num_threads = num_threads.min(items.len());
+    if num_threads <= 1 {
+        let mut state = S::default();
+        return items.iter().map(|item| f(&mut state, item)).collect();
+    }
+
+    // The chunks are small enough to keep all the threads busy when some items are bigger
+    let chunks: Vec<_> = items
+        .chunks((items.len() / (num_threads * 8)).max(1))
+        .collect();
+    let next = AtomicUsize::new(0);
+    // The threads check the cancellation of the current dump
+    let token = cancel::get_current();
+    let (f, chunks, next, token) = (&f, &chunks, &next, &token);
+
+    let mut done: Vec<(usize, Vec<R>)> = crossbeam::scope(|scope| {
+        let threads: Vec<_> = (0..num_threads)
+            .map(|_| {
+                scope.spawn(move |_| {
+                    let _guard = cancel::set_current(token.clone());
+                    let mut state = S::default();
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        match chunks.get(i) {
+                            Some(chunk) => done
+                                .push((i, chunk.iter().map(|item| f(&mut state, item)).collect())),
+                            None => return done,
+                        }
+                    }
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect()
+    })
+    .unwrap();
+
+    done.sort_unstable_by_key(|(i, _)| *i);
+    done.into_iter().flat_map(|(_, results)| results).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_map() {
+        let items: Vec<u64> = (0..1000).collect();
+        let squares: Vec<u64> = items.iter().map(|x| x * x).collect();
+
+        for num_threads in &[1, 3, 16] {
+            assert_eq!(map(*num_threads, &items, |x| x * x), squares);
+        }
+        assert!(map(4, &[] as &[u64], |x| x * x).is_empty());
+        assert!(check_num_threads(0).is_err());
+
+        // The state is the number of items already done by the thread
+        let counts = map_with(4, &items, |count: &mut u64, _| {
+            *count += 1;
+            *count
+        });
+        assert_eq!(counts.len(), items.len());
+        // A thread may start when all the chunks are taken
+        let first = counts.iter().filter(|c| **c == 1).count();
+        assert!((1..=4).contains(&first));
+    }
+}
@@ -0,0 +1,494 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The stages of the dump of a module overlap:
+//  - a PDB is parsed while it's downloaded: it's a RemoteFile downloaded in chunks in a thread and
+//    the chunks read by the parser are fetched first with some range requests (the stream
+//    directory is at the end of the file so the parser can't wait for a sequential download);
+//  - a downloaded file is written in the cache while it's received (or at the end of the
+//    download of a RemoteFile);
+//  - the symbols are written while they're formatted.
+// The other files (compressed cabinet files, files on servers without range requests, ...) are
+// parsed once downloaded.
+// The channels are bounded so a slow stage makes the previous one wait
+// instead of buffering everything in memory.
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard};
+use std::thread;
+
+use crate::common;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const QUEUE_SIZE: usize = 16;
+// A read in a RemoteFile waits for the whole chunks it needs
+const REMOTE_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// A writer sending the written data in chunks to the next stage
+pub(crate) struct ChannelWriter {
+    buf: Vec<u8>,
+    sender: Sender<Vec<u8>>,
+}
+
+impl ChannelWriter {
+    fn new(sender: Sender<Vec<u8>>) -> Self {
+        Self {
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            sender,
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.sender.send(chunk).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The next stage of the pipeline has stopped",
+            )
+        })
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+/// Run the producer in the current thread and the consumer in another one:
+/// the consumer gets the data as soon as a chunk has been written by the producer.
+/// The error of the consumer (if any) is preferred since it makes the producer fail.
+pub(crate) fn pipe<P, C, T>(producer: P, consumer: C) -> common::Result<T>
+where
+    P: FnOnce(&mut ChannelWriter) -> common::Result<()>,
+    C: FnOnce(Receiver<Vec<u8>>) -> common::Result<T> + Send,
+    T: Send,
+{
+    let (sender, receiver) = bounded(QUEUE_SIZE);
+
+    crossbeam::scope(|scope| {
+        let consumer = scope.spawn(move |_| consumer(receiver));
+
+        let mut writer = ChannelWriter::new(sender);
+        let res = producer(&mut writer).and_then(|_| writer.flush().map_err(|e| e.into()));
+        // Close the channel to stop the consumer
+        drop(writer);
+
+        let consumed = consumer
+            .join()
+            .unwrap_or_else(|_| Err("The consumer has panicked".into()));
+        match (res, consumed) {
+            (_, Err(e)) => Err(e),
+            (Err(e), _) => Err(e),
+            (Ok(_), Ok(t)) => Ok(t),
+        }
+    })
+    .unwrap_or_else(|_| Err("The producer has panicked".into()))
+}
+
+/// Write the chunks in all the outputs as soon as they're received
+pub(crate) fn write_chunks(
+    chunks: Receiver<Vec<u8>>,
+    outputs: &mut [&mut dyn Write],
+) -> common::Result<()> {
+    for chunk in chunks {
+        for output in outputs.iter_mut() {
+            output.write_all(&chunk)?;
+        }
+    }
+    for output in outputs.iter_mut() {
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Get a range of a remote file (e.g. the response to a range request)
+pub(crate) type FetchRange =
+    dyn Fn(Range<u64>) -> common::Result<Box<dyn Read + Send>> + Send + Sync;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Chunk {
+    Missing,
+    Fetching,
+    Fetched,
+}
+
+struct RemoteState {
+    chunks: Vec<Chunk>,
+    // The first error of a fetch: the readers fail once the download has failed
+    error: Option<String>,
+    complete: bool,
+}
+
+struct Remote {
+    size: u64,
+    data: RwLock<Vec<u8>>,
+    state: Mutex<RemoteState>,
+    changed: Condvar,
+    fetch: Box<FetchRange>,
+}
+
+impl Remote {
+    fn chunk_range(&self, index: usize) -> Range<u64> {
+        let start = index as u64 * REMOTE_CHUNK_SIZE;
+        start..(start + REMOTE_CHUNK_SIZE).min(self.size)
+    }
+
+    fn get_error(state: &RemoteState) -> common::Result<()> {
+        match state.error.as_ref() {
+            Some(e) => Err(format!("The download has failed: {}", e).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Claim the chunk if it's missing: false when it's fetched (or being fetched) by another
+    /// thread
+    fn try_claim(&self, index: usize) -> common::Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        Self::get_error(&state)?;
+        if state.chunks[index] == Chunk::Missing {
+            state.chunks[index] = Chunk::Fetching;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Wait for the chunk: true when it's missing and it must be fetched by this thread
+    fn claim(&self, index: usize) -> common::Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            Self::get_error(&state)?;
+            match state.chunks[index] {
+                Chunk::Missing => {
+                    state.chunks[index] = Chunk::Fetching;
+                    return Ok(true);
+                }
+                Chunk::Fetching => state = self.changed.wait(state).unwrap(),
+                Chunk::Fetched => return Ok(false),
+            }
+        }
+    }
+
+    /// Read the chunk from the reader and wake up the threads waiting for it
+    fn read_chunk(&self, index: usize, reader: &mut dyn Read) -> common::Result<()> {
+        let range = self.chunk_range(index);
+        let mut chunk = vec![0; (range.end - range.start) as usize];
+        let res = reader.read_exact(&mut chunk);
+        if res.is_ok() {
+            self.data.write().unwrap()[range.start as usize..range.end as usize]
+                .copy_from_slice(&chunk);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        self.changed.notify_all();
+        match res {
+            Ok(_) => {
+                state.chunks[index] = Chunk::Fetched;
+                Ok(())
+            }
+            Err(e) => Err(self.fail(&mut state, e.into())),
+        }
+    }
+
+    fn fail(&self, state: &mut RemoteState, e: common::Error) -> common::Error {
+        state.error.get_or_insert_with(|| e.to_string());
+        self.changed.notify_all();
+        e
+    }
+
+    /// Get the chunks of the range: the missing ones are fetched by this thread
+    fn ensure(&self, range: Range<u64>) -> common::Result<()> {
+        let first = (range.start / REMOTE_CHUNK_SIZE) as usize;
+        let last = range.end.div_ceil(REMOTE_CHUNK_SIZE) as usize;
+        for index in first..last {
+            if self.claim(index)? {
+                let mut reader = (self.fetch)(self.chunk_range(index))
+                    .map_err(|e| self.fail(&mut self.state.lock().unwrap(), e))?;
+                self.read_chunk(index, &mut reader)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetch the chunks in order: a response is read until a chunk which has been fetched by
+    /// a reader (the next missing one is fetched with another request)
+    fn download(&self, first: Option<Box<dyn Read + Send>>) -> common::Result<()> {
+        let count = self.state.lock().unwrap().chunks.len();
+        let mut first = first;
+        let mut index = 0;
+        while index < count {
+            if self.state.lock().unwrap().chunks[index] != Chunk::Missing {
+                index += 1;
+                continue;
+            }
+            // The first response starts at the beginning of the file
+            let mut reader = match first.take().filter(|_| index == 0) {
+                Some(reader) => reader,
+                None => (self.fetch)(self.chunk_range(index).start..self.size)
+                    .map_err(|e| self.fail(&mut self.state.lock().unwrap(), e))?,
+            };
+            while index < count && self.try_claim(index)? {
+                self.read_chunk(index, &mut reader)?;
+                index += 1;
+            }
+        }
+
+        // Wait for the chunks fetched by the readers
+        let mut state = self.state.lock().unwrap();
+        loop {
+            Self::get_error(&state)?;
+            if state.chunks.iter().all(|c| *c == Chunk::Fetched) {
+                return Ok(());
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+/// A file downloaded in a background thread which can be read before the end of the download:
+/// the chunks which are read are fetched first
+pub struct RemoteFile(Arc<Remote>);
+
+impl RemoteFile {
+    /// The file has the given size and its ranges are got with fetch (e.g. with range requests)
+    pub fn new<F>(size: u64, fetch: F) -> common::Result<Self>
+    where
+        F: Fn(Range<u64>) -> common::Result<Box<dyn Read + Send>> + Send + Sync + 'static,
+    {
+        Self::start(size, None, Box::new(fetch), |_| {})
+    }
+
+    /// The download starts with the first response (if any) and the whole file is given to
+    /// complete at the end of the download (e.g. to write it in a cache)
+    pub(crate) fn start<C>(
+        size: u64,
+        first: Option<Box<dyn Read + Send>>,
+        fetch: Box<FetchRange>,
+        complete: C,
+    ) -> common::Result<Self>
+    where
+        C: FnOnce(&[u8]) + Send + 'static,
+    {
+        let count = size.div_ceil(REMOTE_CHUNK_SIZE) as usize;
+        let remote = Arc::new(Remote {
+            size,
+            data: RwLock::new(vec![0; size as usize]),
+            state: Mutex::new(RemoteState {
+                chunks: vec![Chunk::Missing; count],
+                error: None,
+                complete: false,
+            }),
+            changed: Condvar::new(),
+            fetch,
+        });
+
+        let downloader = Arc::clone(&remote);
+        thread::Builder::new()
+            .name("dump-syms download".to_string())
+            .spawn(move || {
+                if downloader.download(first).is_ok() {
+                    complete(&downloader.data.read().unwrap());
+                }
+                let mut state = downloader.state.lock().unwrap();
+                state.complete = true;
+                downloader.changed.notify_all();
+            })?;
+
+        Ok(Self(remote))
+    }
+
+    /// A reader of the file (each thread must have its own reader)
+    pub(crate) fn reader(&self) -> RemoteReader<'_> {
+        RemoteReader {
+            remote: &self.0,
+            pos: 0,
+        }
+    }
+
+    /// Wait for the end of the download to get the whole file
+    pub(crate) fn wait(&self) -> io::Result<RwLockReadGuard<'_, Vec<u8>>> {
+        let mut state = self.0.state.lock().unwrap();
+        while !state.complete && state.error.is_none() {
+            state = self.0.changed.wait(state).unwrap();
+        }
+        Remote::get_error(&state).map_err(io::Error::other)?;
+        drop(state);
+        Ok(self.0.data.read().unwrap())
+    }
+}
+
+impl fmt::Debug for RemoteFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RemoteFile({} bytes)", self.0.size)
+    }
+}
+
+/// Read a RemoteFile: a read waits for the chunks it needs
+pub(crate) struct RemoteReader<'a> {
+    remote: &'a Remote,
+    pos: u64,
+}
+
+impl fmt::Debug for RemoteReader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "RemoteReader({} bytes at {})",
+            self.remote.size, self.pos
+        )
+    }
+}
+
+impl Read for RemoteReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let end = (self.pos + buf.len() as u64).min(self.remote.size);
+        if end <= self.pos {
+            return Ok(0);
+        }
+        self.remote
+            .ensure(self.pos..end)
+            .map_err(io::Error::other)?;
+
+        let len = (end - self.pos) as usize;
+        let data = self.remote.data.read().unwrap();
+        buf[..len].copy_from_slice(&data[self.pos as usize..end as usize]);
+        self.pos = end;
+        Ok(len)
+    }
+}
+
+impl Seek for RemoteReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(offset) => self.remote.size as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek in a remote file",
+            ));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use pdb::{FallibleIterator, Source, PDB};
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_pipe() {
+        let data: Vec<u8> = (0..(3 * CHUNK_SIZE + 17)).map(|i| i as u8).collect();
+        let res = pipe(
+            |w| {
+                for part in data.chunks(1000) {
+                    w.write_all(part)?;
+                }
+                Ok(())
+            },
+            |chunks| {
+                let mut a = Vec::new();
+                let mut b = Vec::new();
+                write_chunks(chunks, &mut [&mut a, &mut b])?;
+                Ok((a, b))
+            },
+        )
+        .unwrap();
+
+        assert_eq!(res.0, data);
+        assert_eq!(res.1, data);
+
+        // The producer stops when the consumer fails
+        let res: common::Result<()> = pipe(
+            |w| loop {
+                w.write_all(&data)?;
+            },
+            |_| Err("Cannot write".into()),
+        );
+        assert_eq!(res.unwrap_err().to_string(), "Cannot write");
+    }
+
+    #[test]
+    fn test_remote_file() {
+        let data = Arc::new(crate::utils::read_file("./test_data/windows/basic64.pdb"));
+        let size = data.len() as u64;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        // The download is blocked until the sender is dropped
+        let (sender, gate) = bounded::<()>(0);
+        let file = RemoteFile::new(size, {
+            let data = Arc::clone(&data);
+            let requests = Arc::clone(&requests);
+            move |range: Range<u64>| {
+                if range.end - range.start > REMOTE_CHUNK_SIZE {
+                    let _ = gate.recv();
+                } else {
+                    requests.lock().unwrap().push(range.clone());
+                }
+                let chunk = data[range.start as usize..range.end as usize].to_vec();
+                Ok(Box::new(Cursor::new(chunk)) as Box<dyn Read + Send>)
+            }
+        })
+        .unwrap();
+
+        // The PDB is parsed before the end of the download
+        fn count_symbols<'s, S: 's + Source<'s>>(mut pdb: PDB<'s, S>) -> (usize, usize) {
+            let dbi = pdb.debug_information().unwrap();
+            let modules = dbi.modules().unwrap().count().unwrap();
+            let globals = pdb.global_symbols().unwrap();
+            let symbols = globals.iter().count().unwrap();
+            (modules, symbols)
+        }
+        let counts = count_symbols(PDB::open(file.reader()).unwrap());
+        assert_eq!(
+            counts,
+            count_symbols(PDB::open(Cursor::new(&data[..])).unwrap())
+        );
+        assert!(counts.0 != 0 && counts.1 != 0);
+
+        // The stream directory is in the last chunk
+        let requests = requests.lock().unwrap().clone();
+        assert!(requests.iter().any(|r| r.end == size));
+
+        drop(sender);
+        assert_eq!(*file.wait().unwrap(), *data);
+        let mut reader = file.reader();
+        reader.seek(SeekFrom::End(-4)).unwrap();
+        let mut end = Vec::new();
+        reader.read_to_end(&mut end).unwrap();
+        assert_eq!(end, &data[data.len() - 4..]);
+
+        // The readers fail with the download
+        let file = RemoteFile::new(size, |_| Err("Not found".into())).unwrap();
+        assert!(file.reader().read(&mut [0; 16]).is_err());
+        assert_eq!(
+            file.wait().unwrap_err().to_string(),
+            "The download has failed: Not found"
+        );
+    }
+}
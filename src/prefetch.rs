@@ -0,0 +1,246 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crossbeam::channel::unbounded;
+use log::{info, warn};
+use serde::Deserialize;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::cache::{self, Prefetched, SymbolServer};
+use crate::common;
+use crate::utils;
+
+#[derive(Debug, Deserialize)]
+struct Module {
+    debug_file: Option<String>,
+    debug_id: Option<String>,
+}
+
+/// A json manifest is either a list of modules or an object with a modules field
+/// (like the output of minidump-stackwalk)
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Manifest {
+    Modules(Vec<Module>),
+    Dump { modules: Vec<Module> },
+}
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    debug_file: String,
+    debug_id: String,
+}
+
+impl Entry {
+    fn new(debug_file: &str, debug_id: &str) -> Option<Self> {
+        let debug_file = debug_file.trim();
+        // The ids in the server urls are in uppercase and without dashes
+        let debug_id: String = debug_id
+            .trim()
+            .chars()
+            .filter(|c| *c != '-')
+            .collect::<String>()
+            .to_uppercase();
+        if debug_file.is_empty() || debug_id.is_empty() {
+            None
+        } else {
+            Some(Self {
+                debug_file: debug_file.to_string(),
+                debug_id,
+            })
+        }
+    }
+}
+
+fn parse_json(data: &str) -> common::Result<Vec<Entry>> {
+    let modules = match serde_json::from_str(data)? {
+        Manifest::Modules(modules) => modules,
+        Manifest::Dump { modules } => modules,
+    };
+
+    Ok(modules
+        .iter()
+        .filter_map(|m| Entry::new(m.debug_file.as_ref()?, m.debug_id.as_ref()?))
+        .collect())
+}
+
+/// Each line is a pair "debug_file debug_id", empty lines and lines starting with # are ignored
+fn parse_text(data: &str) -> common::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (n, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let entry = match (parts.next(), parts.next(), parts.next()) {
+            (Some(debug_file), Some(debug_id), None) => Entry::new(debug_file, debug_id),
+            _ => None,
+        };
+        match entry {
+            Some(entry) => entries.push(entry),
+            _ => return Err(format!("Invalid line {}: {}", n + 1, line).into()),
+        }
+    }
+
+    Ok(entries)
+}
+
+fn parse(data: &str) -> common::Result<Vec<Entry>> {
+    let mut entries = if data.trim_start().starts_with(|c| c == '[' || c == '{') {
+        parse_json(data)?
+    } else {
+        parse_text(data)?
+    };
+
+    entries.sort_by(|a, b| (&a.debug_file, &a.debug_id).cmp(&(&b.debug_file, &b.debug_id)));
+    entries.dedup();
+
+    Ok(entries)
+}
+
+fn read_manifest(manifest: &str) -> common::Result<String> {
+    let data = if manifest == "-" {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data)?;
+        data
+    } else {
+        utils::read(manifest)?
+    };
+
+    Ok(String::from_utf8(data)?)
+}
+
+/// Download all the files listed in the manifest in the caches
+pub(crate) fn prefetch(
+    manifest: &str,
+    sym_servers: Option<Vec<SymbolServer>>,
+    num_jobs: usize,
+) -> common::Result<()> {
+    let sym_servers = sym_servers.ok_or("No symbol server configuration")?;
+    if cache::get_caches(Some(&sym_servers)).is_empty() {
+        return Err("No cache directory in the symbol server configuration".into());
+    }
+
+    let entries = parse(&read_manifest(manifest)?)?;
+    let total = entries.len();
+    let num_jobs = num_jobs.min(total).max(1);
+
+    let sym_servers = Arc::new(sym_servers);
+    let downloaded = Arc::new(AtomicUsize::new(0));
+    let cached = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = unbounded::<Entry>();
+
+    let mut threads = Vec::with_capacity(num_jobs);
+    for i in 0..num_jobs {
+        let receiver = receiver.clone();
+        let sym_servers = Arc::clone(&sym_servers);
+        let downloaded = Arc::clone(&downloaded);
+        let cached = Arc::clone(&cached);
+
+        let t = thread::Builder::new()
+            .name(format!("dump-syms prefetch {}", i))
+            .spawn(move || {
+                while let Ok(entry) = receiver.recv() {
+                    let res =
+                        cache::prefetch_file(&entry.debug_file, &entry.debug_id, &sym_servers);
+                    match res {
+                        Prefetched::Cached => {
+                            info!("{} {} already in cache", entry.debug_file, entry.debug_id);
+                            cached.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Prefetched::Downloaded => {
+                            info!("{} {} downloaded", entry.debug_file, entry.debug_id);
+                            downloaded.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Prefetched::NotFound => {
+                            warn!("{} {} not found", entry.debug_file, entry.debug_id);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+
+        threads.push(t);
+    }
+
+    for entry in entries {
+        sender.send(entry).unwrap();
+    }
+    // Close the channel to stop the threads once the queue is empty
+    drop(sender);
+
+    for t in threads {
+        if t.join().is_err() {
+            return Err("A prefetch thread panicked".into());
+        }
+    }
+
+    let downloaded = downloaded.load(Ordering::SeqCst);
+    let cached = cached.load(Ordering::SeqCst);
+    println!(
+        "{} files downloaded, {} already in cache, {} not found",
+        downloaded,
+        cached,
+        total - downloaded - cached
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_text() {
+        let data = r#"
+# A comment
+xul.pdb 5A2B3A6C1B2D4C3E9F8A7B6C5D4E3F2A1
+ntdll.pdb    0b2d9a07-44f3-4b8a-8f4e-3f2a1b0c9d8e-1
+xul.pdb 5A2B3A6C1B2D4C3E9F8A7B6C5D4E3F2A1
+"#;
+        let entries = parse(data).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Entry::new("ntdll.pdb", "0B2D9A0744F34B8A8F4E3F2A1B0C9D8E1").unwrap(),
+                Entry::new("xul.pdb", "5A2B3A6C1B2D4C3E9F8A7B6C5D4E3F2A1").unwrap(),
+            ]
+        );
+
+        assert!(parse("xul.pdb").is_err());
+        assert!(parse("xul.pdb 1234 foo").is_err());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let data =
+            r#"[{"debug_file": "xul.pdb", "debug_id": "5A2B3A6C1B2D4C3E9F8A7B6C5D4E3F2A1"}]"#;
+        let entries = parse(data).unwrap();
+        assert_eq!(
+            entries,
+            vec![Entry::new("xul.pdb", "5A2B3A6C1B2D4C3E9F8A7B6C5D4E3F2A1").unwrap()]
+        );
+
+        let data = r#"{
+            "status": "OK",
+            "modules": [
+                {"filename": "xul.dll", "debug_file": "xul.pdb", "debug_id": "5A2B3A6C1B2D4C3E9F8A7B6C5D4E3F2A1"},
+                {"filename": "foo.dll", "debug_file": null, "debug_id": null}
+            ]
+        }"#;
+        let entries = parse(data).unwrap();
+        assert_eq!(
+            entries,
+            vec![Entry::new("xul.pdb", "5A2B3A6C1B2D4C3E9F8A7B6C5D4E3F2A1").unwrap()]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// By default, a PUBLIC at the address of a FUNC is dropped (the FUNC has a better name and
+// the lines) but the ones inside the range of a FUNC are kept.
+// With --keep-publics, the PUBLIC at the address of a FUNC is written after it and with --dedup,
+// the PUBLIC records inside the range of a FUNC are dropped too.
+// With --symtab-functions, the PUBLIC records with a size in the symbol table of a binary without
+// debug info are written as FUNC records (without lines).
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use crate::common;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PublicsPolicy {
+    Default = 0,
+    Keep = 1,
+    Dedup = 2,
+}
+
+pub(crate) fn get_publics_policy(keep_publics: bool, dedup: bool) -> common::Result<PublicsPolicy> {
+    Ok(match (keep_publics, dedup) {
+        (false, false) => PublicsPolicy::Default,
+        (true, false) => PublicsPolicy::Keep,
+        (false, true) => PublicsPolicy::Dedup,
+        _ => return Err("--keep-publics and --dedup are mutually exclusive".into()),
+    })
+}
+
+/// A PUBLIC at the address of a FUNC (only kept with --keep-publics)
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Public {
+    pub rva: u32,
+    pub parameter_size: u32,
+    pub name: String,
+}
+
+impl Display for Public {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "PUBLIC {:x} {:x} {}",
+            self.rva, self.parameter_size, self.name,
+        )
+    }
+}
+
+/// The PUBLIC records at the address of a FUNC by address
+pub(crate) type Publics = BTreeMap<u32, Public>;
+
+/// Get the addresses of the PUBLIC records inside the range of a FUNC
+/// from some (address, length, is_public) sorted by address
+pub(crate) fn get_covered_publics<I: IntoIterator<Item = (u32, u32, bool)>>(
+    symbols: I,
+) -> Vec<u32> {
+    let mut covered = Vec::new();
+    let mut func_end = 0u64;
+    for (rva, len, is_public) in symbols {
+        if is_public {
+            if u64::from(rva) < func_end {
+                covered.push(rva);
+            }
+        } else {
+            func_end = func_end.max(u64::from(rva) + u64::from(len));
+        }
+    }
+    covered
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_covered_publics() {
+        let symbols = vec![
+            (0x1000, 0x10, false),
+            (0x1004, 0, true),
+            (0x1010, 0, true),
+            (0x1020, 0x20, false),
+            (0x1028, 0x4, false),
+            (0x1030, 0, true),
+            (0x1040, 0, true),
+        ];
+        assert_eq!(get_covered_publics(symbols), vec![0x1004, 0x1030]);
+
+        let public = Public {
+            rva: 0x1000,
+            parameter_size: 8,
+            name: "_foo@8".to_string(),
+        };
+        assert_eq!(public.to_string(), "PUBLIC 1000 8 _foo@8\n");
+    }
+}
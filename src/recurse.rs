@@ -0,0 +1,292 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --recurse, the files of a directory tree matching the --include globs (all by default)
+// and none of the --exclude ones are dumped independently: their type is detected from their
+// first bytes and the ones which aren't debug files are skipped. A summary is printed at the end.
+
+use hashbrown::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::cache::FileFilter;
+use crate::common::{self, ErrorKind, FileType};
+use crate::dotnet::ppdb::PortablePdbInfo;
+use crate::dumper::{self, Config};
+
+// Enough to detect the type of a file (e.g. a fat Mach-O with its architectures)
+const HEADER_SIZE: u64 = 4096;
+
+pub(crate) struct Walker {
+    include: Option<FileFilter>,
+    exclude: Option<FileFilter>,
+    follow_symlinks: bool,
+}
+
+impl Walker {
+    pub(crate) fn new(
+        include: &[String],
+        exclude: &[String],
+        follow_symlinks: bool,
+    ) -> common::Result<Self> {
+        Ok(Self {
+            include: FileFilter::new(include, None)?,
+            exclude: FileFilter::new(exclude, None)?,
+            follow_symlinks,
+        })
+    }
+
+    /// Check if a path relative to the root (e.g. lib/libxul.so) is selected by the globs
+    fn is_selected(&self, path: &str) -> bool {
+        let included = match self.include.as_ref() {
+            Some(include) => include.matches(path),
+            None => true,
+        };
+        included && !matches!(self.exclude.as_ref(), Some(exclude) if exclude.matches(path))
+    }
+
+    /// Get the selected files of a directory tree (sorted by path)
+    pub(crate) fn get_files(&self, root: &Path) -> common::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        self.walk(root, root, &mut visited, &mut files)?;
+        Ok(files)
+    }
+
+    fn walk(
+        &self,
+        root: &Path,
+        dir: &Path,
+        visited: &mut HashSet<PathBuf>,
+        files: &mut Vec<PathBuf>,
+    ) -> common::Result<()> {
+        // A link to a parent directory mustn't make an infinite loop
+        if !visited.insert(fs::canonicalize(dir)?) {
+            return Ok(());
+        }
+
+        let mut entries = fs::read_dir(dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        for path in entries {
+            let metadata = fs::symlink_metadata(&path)?;
+            let metadata = if metadata.file_type().is_symlink() {
+                if !self.follow_symlinks {
+                    continue;
+                }
+                match fs::metadata(&path) {
+                    Ok(metadata) => metadata,
+                    // A dangling link
+                    _ => continue,
+                }
+            } else {
+                metadata
+            };
+
+            if metadata.is_dir() {
+                self.walk(root, &path, visited, files)?;
+            } else if metadata.is_file() {
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                if self.is_selected(&relative) {
+                    files.push(path);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the type of a file from its first bytes
+pub(crate) fn get_file_type(path: &Path) -> io::Result<FileType> {
+    let mut header = Vec::new();
+    File::open(path)?
+        .take(HEADER_SIZE)
+        .read_to_end(&mut header)?;
+
+    Ok(match FileType::from_buf(&header) {
+        FileType::Unknown if PortablePdbInfo::has_metadata_signature(&header) => {
+            FileType::from_buf(&fs::read(path)?)
+        }
+        // Only the zip files containing some libraries (e.g. not the source bundles)
+        FileType::Zip
+            if !matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("apk") | Some("aab")
+            ) =>
+        {
+            FileType::Unknown
+        }
+        file_type => file_type,
+    })
+}
+
+enum Status {
+    Dumped,
+    Skipped,
+    Failed(String),
+}
+
+#[derive(Default)]
+struct Summary {
+    files: Vec<(PathBuf, Status)>,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let (mut dumped, mut skipped, mut failed) = (0, 0, 0);
+        for (path, status) in self.files.iter() {
+            match status {
+                Status::Dumped => {
+                    dumped += 1;
+                    writeln!(f, "dumped  {}", path.display())?;
+                }
+                Status::Skipped => {
+                    skipped += 1;
+                    writeln!(f, "skipped {}", path.display())?;
+                }
+                Status::Failed(e) => {
+                    failed += 1;
+                    writeln!(f, "failed  {}: {}", path.display(), e)?;
+                }
+            }
+        }
+        writeln!(
+            f,
+            "{} dumped, {} skipped, {} failed",
+            dumped, skipped, failed
+        )
+    }
+}
+
+/// The debug files of a directory tree which would be dumped
+pub(crate) fn get_dumpable_files(
+    config: &Config,
+    root: &str,
+    walker: &Walker,
+) -> common::Result<Vec<PathBuf>> {
+    Ok(walker
+        .get_files(Path::new(root))?
+        .into_iter()
+        .filter(|path| match get_file_type(path) {
+            Ok(FileType::Unknown) | Err(_) => false,
+            Ok(file_type) => config.file_type == FileType::Unknown || file_type == config.file_type,
+        })
+        .collect())
+}
+
+/// Dump the debug files of a directory tree and print a summary
+pub(crate) fn dump(config: &Config, root: &str, walker: &Walker) -> common::Result<()> {
+    let mut summary = Summary::default();
+    let mut dumpable = Vec::new();
+    for path in walker.get_files(Path::new(root))? {
+        let status = match get_file_type(&path) {
+            Ok(FileType::Unknown) => Status::Skipped,
+            // With --type, only the files of this type are dumped
+            Ok(file_type)
+                if config.file_type != FileType::Unknown && file_type != config.file_type =>
+            {
+                Status::Skipped
+            }
+            Ok(_) => {
+                dumpable.push(summary.files.len());
+                Status::Dumped
+            }
+            Err(e) => Status::Failed(e.to_string()),
+        };
+        summary.files.push((path, status));
+    }
+
+    let filenames: Vec<_> = dumpable
+        .iter()
+        .map(|i| summary.files[*i].0.to_str().unwrap().to_string())
+        .collect();
+    let filenames: Vec<_> = filenames.iter().map(|f| f.as_str()).collect();
+    for (i, res) in dumpable.iter().zip(dumper::dump_files(config, &filenames)?) {
+        if let Err(e) = res {
+            summary.files[*i].1 = Status::Failed(e.to_string());
+        }
+    }
+
+    print!("{}", summary);
+
+    if summary
+        .files
+        .iter()
+        .any(|(_, status)| matches!(status, Status::Failed(_)))
+    {
+        Err(ErrorKind::Incomplete.error("Some files can't be dumped"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn test_walker() {
+        let tmp_dir = Builder::new().prefix("recurse").tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("lib/test")).unwrap();
+        fs::copy("./test_data/linux/basic.full", root.join("lib/libbasic.so")).unwrap();
+        fs::copy(
+            "./test_data/linux/basic.full",
+            root.join("lib/test/libtest.so"),
+        )
+        .unwrap();
+        fs::copy("./test_data/windows/basic64.pdb", root.join("basic64.pdb")).unwrap();
+        fs::write(root.join("lib/readme.txt"), "Not a debug file").unwrap();
+
+        let walker = Walker::new(&[], &["*test*".to_string()], false).unwrap();
+        let files: Vec<_> = walker
+            .get_files(root)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("basic64.pdb"),
+                PathBuf::from("lib/libbasic.so"),
+                PathBuf::from("lib/readme.txt")
+            ]
+        );
+
+        let walker = Walker::new(&["*.so".to_string()], &[], false).unwrap();
+        assert_eq!(walker.get_files(root).unwrap().len(), 2);
+
+        assert!(get_file_type(&root.join("basic64.pdb")).unwrap() == FileType::Pdb);
+        assert!(get_file_type(&root.join("lib/libbasic.so")).unwrap() == FileType::Elf);
+        assert!(get_file_type(&root.join("lib/readme.txt")).unwrap() == FileType::Unknown);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        let tmp_dir = Builder::new().prefix("recurse").tempdir().unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::copy("./test_data/linux/basic.full", root.join("lib/libbasic.so")).unwrap();
+        std::os::unix::fs::symlink(root.join("lib"), root.join("link")).unwrap();
+        // A loop
+        std::os::unix::fs::symlink(root, root.join("lib/root")).unwrap();
+
+        let walker = Walker::new(&[], &[], false).unwrap();
+        assert_eq!(walker.get_files(root).unwrap().len(), 1);
+
+        let walker = Walker::new(&[], &[], true).unwrap();
+        assert_eq!(walker.get_files(root).unwrap().len(), 1);
+    }
+}
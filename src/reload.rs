@@ -0,0 +1,94 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A long-running process reads the config files once and then reloads them when they change
+// (they're checked every few seconds) or when it receives SIGHUP: a valid new config replaces
+// the active one and an invalid one is rejected (the previous one is kept).
+
+use log::{error, info};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::cache;
+use crate::common;
+
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_hangup(_: libc::c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+/// Reload the config on SIGHUP instead of exiting (it replaces the handler set in shutdown::init)
+#[cfg(unix)]
+fn handle_hangup() {
+    let handler = on_hangup as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGHUP, handler) } == libc::SIG_ERR {
+        log::warn!("Unable to set the SIGHUP handler");
+    }
+}
+
+#[cfg(not(unix))]
+fn handle_hangup() {}
+
+fn get_config_state() -> Vec<(PathBuf, Option<SystemTime>)> {
+    cache::get_config_paths()
+        .into_iter()
+        .map(|p| {
+            let modified = fs::metadata(&p).and_then(|m| m.modified()).ok();
+            (p, modified)
+        })
+        .collect()
+}
+
+/// Reload the config files when they change or on SIGHUP
+fn watch_config() {
+    let mut state = get_config_state();
+    let mut elapsed = Duration::default();
+    loop {
+        thread::sleep(SIGNAL_CHECK_INTERVAL);
+        elapsed += SIGNAL_CHECK_INTERVAL;
+
+        let hangup = HANGUP.swap(false, Ordering::SeqCst);
+        if !hangup && elapsed < CONFIG_CHECK_INTERVAL {
+            continue;
+        }
+        elapsed = Duration::default();
+
+        let new_state = get_config_state();
+        if !hangup && new_state == state {
+            continue;
+        }
+        state = new_state;
+        match cache::reload_config() {
+            Ok(_) => info!(
+                "Config reloaded from {}",
+                state
+                    .iter()
+                    .map(|(p, _)| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Err(e) => error!("Invalid config (the previous one is kept): {}", e),
+        }
+    }
+}
+
+/// Read the config files and reload them in a thread when they change or on SIGHUP
+pub(crate) fn start_config_watcher() -> common::Result<()> {
+    cache::reload_config()?;
+    handle_hangup();
+    thread::Builder::new()
+        .name("dump-syms config".to_string())
+        .spawn(watch_config)?;
+
+    Ok(())
+}
@@ -0,0 +1,305 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// dump_syms serve: the symbols are generated on demand. A client POSTs a file to /dump/FILENAME
+// or GETs /dump/DEBUG_FILE/DEBUG_ID (the debug file is fetched from the symbol servers) and
+// receives the Breakpad symbols. The dumps are queued and run by a pool of threads: when the
+// queue is full, the server answers 503.
+// The config files are reloaded when they change or on SIGHUP: a valid new config replaces the
+// active one without restarting the server and an invalid one is rejected.
+
+use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+use log::{error, info};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::cache::{self, HttpOptions};
+use crate::common::{self, FileType};
+use crate::dumper::{self, Config};
+use crate::http_server::{self, Request, Response, ServerOptions};
+use crate::reload;
+
+const DUMP: &str = "/dump/";
+
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ServeOptions {
+    pub symbol_server: Option<String>,
+    pub user_agent: Option<String>,
+    pub headers: Vec<String>,
+    pub allow_export_only: bool,
+    // The number of dumps running in parallel
+    pub num_jobs: usize,
+    // The number of dumps waiting for a thread
+    pub queue_size: usize,
+}
+
+enum Source {
+    // The posted file
+    File(Vec<u8>),
+    // A debug file to fetch with its debug id
+    DebugId(String),
+}
+
+enum JobError {
+    NotFound(String),
+    Failed(String),
+}
+
+struct Job {
+    name: String,
+    source: Source,
+    result: Sender<Result<Vec<u8>, JobError>>,
+}
+
+/// A temporary directory removed when dropped
+struct TmpDir(PathBuf);
+
+impl TmpDir {
+    fn new() -> common::Result<Self> {
+        let n = TMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let dir =
+            std::env::temp_dir().join(format!("dump_syms-serve.{}.{}", std::process::id(), n));
+        fs::create_dir_all(&dir)?;
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn failed<E: ToString>(e: E) -> JobError {
+    JobError::Failed(e.to_string())
+}
+
+fn dump(options: &ServeOptions, name: &str, source: &Source) -> Result<Vec<u8>, JobError> {
+    let headers = Some(options.headers.iter().map(|h| h.as_str()).collect());
+    let tmp = TmpDir::new().map_err(failed)?;
+
+    let input = match source {
+        Source::File(data) => {
+            let input = tmp.0.join(name);
+            fs::write(&input, data).map_err(failed)?;
+            input
+        }
+        Source::DebugId(id) => {
+            let http = HttpOptions::new(options.user_agent.as_deref(), &headers).map_err(failed)?;
+            let servers =
+                cache::get_sym_servers(options.symbol_server.as_deref(), &http).map_err(failed)?;
+            match cache::search_file(name.to_string(), id, servers.as_ref()) {
+                (Some(data), name) => {
+                    let input = tmp.0.join(name);
+                    fs::write(&input, data).map_err(failed)?;
+                    input
+                }
+                _ => {
+                    return Err(JobError::NotFound(format!(
+                        "{} with id {} isn't on the symbol servers",
+                        name, id
+                    )))
+                }
+            }
+        }
+    };
+    let output = tmp.0.join(".output.sym");
+
+    let config = Config {
+        output: output.to_str().unwrap(),
+        symbol_server: options.symbol_server.as_deref(),
+        store: None,
+        debug_id: None,
+        code_id: None,
+        arch: common::get_compile_time_arch(),
+        file_type: FileType::Unknown,
+        num_jobs: 1,
+        mapping_var: None,
+        mapping_src: None,
+        mapping_dest: None,
+        mapping_file: None,
+        vcs_info: None,
+        user_agent: options.user_agent.as_deref(),
+        headers,
+        allow_export_only: options.allow_export_only,
+        all_archs: false,
+        module_name: None,
+        merge: false,
+        supplier: None,
+        options: Arc::default(),
+    };
+
+    dumper::single_file(&config, input.to_str().unwrap()).map_err(failed)?;
+    fs::read(&output).map_err(failed)
+}
+
+fn run_jobs(options: ServeOptions, jobs: Receiver<Job>) {
+    for job in jobs {
+        let start = std::time::Instant::now();
+        let res = dump(&options, &job.name, &job.source);
+        match res.as_ref() {
+            Ok(_) => info!("{} dumped in {:?}", job.name, start.elapsed()),
+            Err(JobError::NotFound(e)) | Err(JobError::Failed(e)) => {
+                error!("{}: {}", job.name, e)
+            }
+        }
+        // The client may be gone
+        let _ = job.result.send(res);
+    }
+}
+
+pub(crate) struct DumpServer {
+    jobs: Sender<Job>,
+}
+
+impl DumpServer {
+    /// Start the threads running the dumps
+    pub(crate) fn new(options: ServeOptions) -> common::Result<Self> {
+        let (sender, receiver) = bounded(options.queue_size);
+        for i in 0..options.num_jobs.max(1) {
+            let options = options.clone();
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("dump-syms serve {}", i))
+                .spawn(move || run_jobs(options, receiver))?;
+        }
+
+        Ok(Self { jobs: sender })
+    }
+
+    /// Bind the server and serve the requests in some background threads
+    pub(crate) fn start(self, server: &ServerOptions) -> common::Result<SocketAddr> {
+        http_server::start("serve", server, move |request| self.get_response(request))
+    }
+
+    fn get_job(request: &Request) -> Option<(String, Source)> {
+        let path = request.path.strip_prefix(DUMP)?;
+        let parts: Vec<_> = path.split('/').collect();
+        let is_valid =
+            |name: &str| !name.is_empty() && name != "." && name != ".." && !name.contains('\\');
+        match (request.method.as_str(), parts.as_slice()) {
+            ("POST", [name]) if is_valid(name) => {
+                Some((name.to_string(), Source::File(request.body.clone())))
+            }
+            ("GET", [name, id]) if is_valid(name) && !id.is_empty() => {
+                Some((name.to_string(), Source::DebugId(id.to_string())))
+            }
+            _ => None,
+        }
+    }
+
+    fn get_response(&self, request: &Request) -> Response {
+        let (name, source) = match Self::get_job(request) {
+            Some(job) => job,
+            None if !request.path.starts_with(DUMP) => return Response::new(404, "Not Found"),
+            None if request.method == "GET" || request.method == "POST" => {
+                return Response::new(400, "Bad Request")
+            }
+            None => return Response::new(405, "Method Not Allowed"),
+        };
+
+        let (sender, receiver) = bounded(1);
+        let job = Job {
+            name,
+            source,
+            result: sender,
+        };
+        match self.jobs.try_send(job) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => return Response::new(503, "Service Unavailable"),
+            Err(TrySendError::Disconnected(_)) => {
+                return Response::new(500, "Internal Server Error")
+            }
+        }
+
+        let (mut response, body) = match receiver.recv() {
+            Ok(Ok(sym)) => {
+                let mut response = Response::new(200, "OK");
+                response.content_type = Some("text/plain");
+                (response, sym)
+            }
+            Ok(Err(JobError::NotFound(e))) => (Response::new(404, "Not Found"), e.into_bytes()),
+            Ok(Err(JobError::Failed(e))) => {
+                (Response::new(422, "Unprocessable Entity"), e.into_bytes())
+            }
+            Err(_) => (Response::new(500, "Internal Server Error"), Vec::new()),
+        };
+        response.body = body;
+        response
+    }
+}
+
+/// Serve the dumps until the process is killed
+pub(crate) fn serve(options: ServeOptions, server: ServerOptions) -> common::Result<()> {
+    // With --symbol-server, the config files aren't used
+    if options.symbol_server.is_none() {
+        reload::start_config_watcher()?;
+    }
+
+    let num_jobs = options.num_jobs;
+    let addr = DumpServer::new(options)?.start(&server)?;
+    println!(
+        "Dump server ({} jobs) listening on http://{}{}",
+        num_jobs, addr, DUMP
+    );
+
+    loop {
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::read;
+
+    use super::*;
+
+    fn post(addr: SocketAddr, name: &str, data: Vec<u8>) -> (u16, Vec<u8>) {
+        let url = format!("http://{}{}{}", addr, DUMP, name);
+        let response = reqwest::blocking::Client::new()
+            .post(&url)
+            .body(data)
+            .send()
+            .unwrap();
+        let status = response.status().as_u16();
+        (status, response.bytes().unwrap().to_vec())
+    }
+
+    #[test]
+    fn test_serve() {
+        let server = DumpServer::new(ServeOptions {
+            num_jobs: 2,
+            queue_size: 4,
+            ..Default::default()
+        })
+        .unwrap();
+        let addr = server.start(&ServerOptions::local(0)).unwrap();
+
+        let (status, sym) = post(
+            addr,
+            "basic.full",
+            read("./test_data/linux/basic.full").unwrap(),
+        );
+        assert_eq!(status, 200);
+        let sym = String::from_utf8(sym).unwrap();
+        assert!(
+            sym.starts_with("MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n")
+        );
+        assert!(sym.contains("\nFUNC "));
+
+        let (status, _) = post(addr, "foo.txt", b"Not a debug file".to_vec());
+        assert_eq!(status, 422);
+
+        let (status, _) = post(addr, "foo/bar", b"Not a debug file".to_vec());
+        assert_eq!(status, 400);
+    }
+}
@@ -0,0 +1,215 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use hashbrown::HashSet;
+use log::warn;
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::common;
+
+#[derive(Default)]
+struct State {
+    // The temporary files which are currently written
+    in_flight: HashSet<PathBuf>,
+    // The number of files successfully written
+    written: usize,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn lock() -> MutexGuard<'static, State> {
+    // A panicking thread mustn't prevent the cleanup
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn on_interrupt() {
+    // The lock is kept until the process exits so no new file can be created
+    // and no temporary file can be renamed.
+    let mut state = lock();
+    INTERRUPTED.store(true, Ordering::SeqCst);
+
+    let removed = state.in_flight.len();
+    for tmp in state.in_flight.drain() {
+        let _ = fs::remove_file(tmp);
+    }
+
+    eprintln!(
+        "Interrupted: {} files written, {} incomplete files removed",
+        state.written, removed
+    );
+    std::process::exit(130);
+}
+
+/// Install a handler for SIGINT and SIGTERM (Ctrl-C and Ctrl-Break on Windows)
+/// to remove the files which are partially written before exiting.
+pub(crate) fn init() {
+    if let Err(e) = ctrlc::set_handler(on_interrupt) {
+        warn!("Unable to set the signal handler: {}", e);
+    }
+}
+
+fn get_tmp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().to_string());
+    let n = TMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+    path.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), n))
+}
+
+/// Remove the temporary file if it hasn't been renamed (e.g. on error or panic)
+struct InFlight {
+    tmp: PathBuf,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut state = lock();
+        if state.in_flight.remove(&self.tmp) {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+/// A file written in a temporary file which is renamed once it's complete,
+/// so a truncated file is never left at the given path.
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    output: BufWriter<File>,
+    // None when the file isn't a regular one
+    in_flight: Option<InFlight>,
+}
+
+impl AtomicFile {
+    pub(crate) fn create<P: AsRef<Path>>(path: P) -> common::Result<Self> {
+        // Write in the target of a symlink instead of replacing it
+        let path = fs::canonicalize(&path).unwrap_or_else(|_| path.as_ref().to_path_buf());
+
+        // Something like /dev/null or a named pipe mustn't be replaced
+        if fs::metadata(&path).map_or(false, |m| !m.is_file()) {
+            let output = BufWriter::new(File::create(&path)?);
+            return Ok(Self {
+                path,
+                output,
+                in_flight: None,
+            });
+        }
+
+        let in_flight = {
+            let mut state = lock();
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return Err("Interrupted".into());
+            }
+            let tmp = get_tmp_path(&path);
+            state.in_flight.insert(tmp.clone());
+            InFlight { tmp }
+        };
+        let output = BufWriter::new(File::create(&in_flight.tmp)?);
+
+        Ok(Self {
+            path,
+            output,
+            in_flight: Some(in_flight),
+        })
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut BufWriter<File> {
+        &mut self.output
+    }
+
+    /// Rename the temporary file: if the file isn't committed, it's removed when dropped.
+    pub(crate) fn commit(self) -> common::Result<()> {
+        let Self {
+            path,
+            mut output,
+            in_flight,
+        } = self;
+        output.flush()?;
+        drop(output);
+
+        let in_flight = match in_flight {
+            Some(in_flight) => in_flight,
+            _ => return Ok(()),
+        };
+
+        let mut state = lock();
+        if !state.in_flight.contains(&in_flight.tmp) {
+            return Err("Interrupted".into());
+        }
+
+        fs::rename(&in_flight.tmp, &path)?;
+        state.in_flight.remove(&in_flight.tmp);
+        state.written += 1;
+
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.output.seek(pos)
+    }
+}
+
+/// Write a file in a temporary file which is renamed once it's complete,
+/// so a truncated file is never left at the given path.
+pub(crate) fn write_file<P, F>(path: P, f: F) -> common::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufWriter<File>) -> common::Result<()>,
+{
+    let mut file = AtomicFile::create(path)?;
+    f(file.get_mut())?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_write_file() {
+        let tmp_dir = Builder::new().prefix("write_file").tempdir().unwrap();
+        let path = tmp_dir.path().join("foo.sym");
+
+        write_file(&path, |w| {
+            w.write_all(b"MODULE")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"MODULE");
+
+        let res = write_file(&path, |w| {
+            w.write_all(b"INFO")?;
+            Err("Oops".into())
+        });
+        assert!(res.is_err());
+
+        // The previous file is untouched and there are no temporary files
+        assert_eq!(fs::read(&path).unwrap(), b"MODULE");
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --embed-sources, the source files of the FILE records are read from the disk and written
+// in a source bundle (the zip file with a manifest read by symbolic) next to the symbol file
+// (xul.sym => xul.src.zip), so the source context can be shown once the build tree is gone.
+// The files in the bundle have the names of the FILE records (i.e. after the path mapping).
+
+use hashbrown::HashMap;
+use log::warn;
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::path::PathBuf;
+use symbolic::common::DebugId;
+use symbolic::debuginfo::sourcebundle::{SourceBundleWriter, SourceFileInfo, SourceFileType};
+
+use crate::common;
+
+/// The paths on the disk of the source files by name (when the path mapping has changed them)
+pub(crate) type SourcePaths = HashMap<String, PathBuf>;
+
+/// Get the paths on the disk of some source files
+pub(crate) fn get_source_files(names: &[String], paths: &SourcePaths) -> Vec<(String, PathBuf)> {
+    names
+        .iter()
+        .map(|name| {
+            let path = paths
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from(name));
+            (name.clone(), path)
+        })
+        .collect()
+}
+
+/// Get the path of the source bundle next to a symbol file (xul.sym.gz => xul.src.zip)
+pub(crate) fn get_bundle_path(path: &str) -> String {
+    let path = path
+        .strip_suffix(".gz")
+        .or_else(|| path.strip_suffix(".zst"))
+        .unwrap_or(path);
+    format!("{}.src.zip", path.strip_suffix(".sym").unwrap_or(path))
+}
+
+/// Get the path of a source file in the bundle (c:\foo\bar.cpp => c/foo/bar.cpp)
+fn get_entry_name(name: &str) -> String {
+    name.split(&['/', '\\'][..])
+        .map(|c| c.trim_end_matches(':'))
+        .filter(|c| !c.is_empty() && *c != "." && *c != "..")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Write the source files which can be read in a source bundle and get their number
+pub(crate) fn write_bundle<W: Write + Seek>(
+    output: W,
+    name: &str,
+    debug_id: &str,
+    files: &[(String, PathBuf)],
+) -> common::Result<usize> {
+    let mut writer = SourceBundleWriter::start(output)?;
+    writer.set_attribute("object_name", name);
+    if let Ok(debug_id) = DebugId::from_breakpad(debug_id) {
+        writer.set_attribute("debug_id", debug_id.to_string());
+    }
+
+    let mut count = 0;
+    for (name, path) in files {
+        let entry = get_entry_name(name);
+        if entry.is_empty() || writer.has_file(&entry) {
+            continue;
+        }
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Cannot read the source file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let mut info = SourceFileInfo::new();
+        info.set_ty(SourceFileType::Source);
+        info.set_path(name.clone());
+        writer.add_file(entry, file, info)?;
+        count += 1;
+    }
+    writer.finish()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Cursor;
+    use symbolic::debuginfo::sourcebundle::SourceBundle;
+
+    #[test]
+    fn test_bundle() {
+        assert_eq!(get_bundle_path("xul.sym"), "xul.src.zip");
+        assert_eq!(get_bundle_path("xul.sym.zst"), "xul.src.zip");
+        assert_eq!(get_entry_name("c:\\foo\\..\\bar.cpp"), "c/foo/bar.cpp");
+        assert_eq!(get_entry_name("/tmp/./basic.cpp"), "tmp/basic.cpp");
+
+        let mut paths = SourcePaths::default();
+        paths.insert(
+            "hg:hg.mozilla.org/mozilla-central:basic.cpp:abcd".to_string(),
+            PathBuf::from("./test_data/linux/basic.cpp"),
+        );
+        let names = vec![
+            "hg:hg.mozilla.org/mozilla-central:basic.cpp:abcd".to_string(),
+            "/not/a/file.h".to_string(),
+        ];
+        let files = get_source_files(&names, &paths);
+
+        let mut output = Cursor::new(Vec::new());
+        let count = write_bundle(
+            &mut output,
+            "basic.full",
+            "20AD60B0B4C68177552708AA192E77390",
+            &files,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+
+        let data = output.into_inner();
+        let bundle = SourceBundle::parse(&data).unwrap();
+        assert_eq!(bundle.name(), Some("basic.full"));
+        let session = bundle.debug_session().unwrap();
+        let source = session.source_by_path(&names[0]).unwrap().unwrap();
+        assert_eq!(
+            source,
+            std::fs::read_to_string("./test_data/linux/basic.cpp").unwrap()
+        );
+    }
+}
@@ -0,0 +1,387 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --stats, the records written for each module are counted (functions, functions without
+// line info, depths of the inlines, ...) and a report (text or JSON) is written at the end of
+// the run with the time spent in each phase, so the quality of the symbols can be tracked
+// across releases.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Write};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::common;
+use crate::shutdown;
+
+lazy_static! {
+    static ref STATS: Mutex<Option<Report>> = Mutex::new(None);
+}
+
+fn lock() -> MutexGuard<'static, Option<Report>> {
+    STATS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum StatsFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct ModuleStats {
+    pub name: String,
+    pub os: String,
+    pub debug_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_id: Option<String>,
+    pub functions: u64,
+    pub functions_without_lines: u64,
+    pub publics: u64,
+    pub files: u64,
+    pub inline_origins: u64,
+    pub lines: u64,
+    pub inlines: u64,
+    /// The number of INLINE records by nest level
+    pub inline_depths: BTreeMap<u32, u64>,
+    pub cfi: u64,
+    pub cfi_rules: u64,
+    pub stack_win: u64,
+}
+
+impl Display for ModuleStats {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "{} {}", self.name, self.debug_id)?;
+        writeln!(
+            f,
+            "  functions: {} ({} without line info)",
+            self.functions, self.functions_without_lines
+        )?;
+        writeln!(f, "  publics: {}", self.publics)?;
+        writeln!(f, "  files: {}", self.files)?;
+        writeln!(f, "  line records: {}", self.lines)?;
+        writeln!(
+            f,
+            "  inlines: {} ({} origins)",
+            self.inlines, self.inline_origins
+        )?;
+        if !self.inline_depths.is_empty() {
+            let depths: Vec<_> = self
+                .inline_depths
+                .iter()
+                .map(|(depth, count)| format!("{}: {}", depth, count))
+                .collect();
+            writeln!(f, "  inline depths: {}", depths.join(", "))?;
+        }
+        writeln!(f, "  CFI entries: {} ({} rules)", self.cfi, self.cfi_rules)?;
+        writeln!(f, "  STACK WIN: {}", self.stack_win)
+    }
+}
+
+/// A writer counting the Breakpad records of a module
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounter {
+    stats: ModuleStats,
+    // The beginning of a line split between two writes
+    line: Vec<u8>,
+    // Some(has_lines) when the last record is a FUNC or one of its lines
+    func_has_lines: Option<bool>,
+}
+
+impl StatsCounter {
+    fn end_function(&mut self) {
+        if self.func_has_lines.take() == Some(false) {
+            self.stats.functions_without_lines += 1;
+        }
+    }
+
+    fn count(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let mut fields = line.split_whitespace();
+        let first = fields.next().unwrap_or_default();
+        if first == "INLINE" {
+            // The INLINE records are between a FUNC and its lines
+            self.stats.inlines += 1;
+            if let Some(depth) = fields.next().and_then(|d| d.parse().ok()) {
+                *self.stats.inline_depths.entry(depth).or_default() += 1;
+            }
+            return;
+        }
+        if !first.is_empty() && first.bytes().all(|c| c.is_ascii_hexdigit()) {
+            self.stats.lines += 1;
+            if let Some(has_lines) = self.func_has_lines.as_mut() {
+                *has_lines = true;
+            }
+            return;
+        }
+
+        self.end_function();
+        match first {
+            "MODULE" => {
+                // MODULE os arch debug_id name
+                self.stats.os = fields.next().unwrap_or_default().to_string();
+                let mut fields = fields.skip(1);
+                self.stats.debug_id = fields.next().unwrap_or_default().to_string();
+                self.stats.name = fields.collect::<Vec<_>>().join(" ");
+            }
+            "INFO" if fields.next() == Some("CODE_ID") => {
+                self.stats.code_id = fields.next().map(String::from);
+            }
+            "FILE" => self.stats.files += 1,
+            "INLINE_ORIGIN" => self.stats.inline_origins += 1,
+            "FUNC" => {
+                self.stats.functions += 1;
+                self.func_has_lines = Some(false);
+            }
+            "PUBLIC" => self.stats.publics += 1,
+            "STACK" => match (fields.next(), fields.next()) {
+                (Some("CFI"), Some("INIT")) => self.stats.cfi += 1,
+                (Some("CFI"), _) => self.stats.cfi_rules += 1,
+                (Some("WIN"), _) => self.stats.stack_win += 1,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Count the last line (if it doesn't end with a newline) and get the stats
+    pub(crate) fn finish(mut self) -> ModuleStats {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.count(&line);
+        }
+        self.end_function();
+        self.stats
+    }
+}
+
+impl Write for StatsCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(pos) = rest.iter().position(|c| *c == b'\n') {
+            let (line, tail) = rest.split_at(pos + 1);
+            if self.line.is_empty() {
+                self.count(line);
+            } else {
+                let mut full = std::mem::take(&mut self.line);
+                full.extend_from_slice(line);
+                self.count(&full);
+            }
+            rest = tail;
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Phases {
+    /// Reading the files and collecting the debug info
+    read_ms: u128,
+    /// Formatting and writing the symbols
+    write_ms: u128,
+    total_ms: u128,
+}
+
+impl Phases {
+    fn new(write: Duration, total: Duration) -> Self {
+        Self {
+            read_ms: total.saturating_sub(write).as_millis(),
+            write_ms: write.as_millis(),
+            total_ms: total.as_millis(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    modules: &'a [ModuleStats],
+    phases: Phases,
+}
+
+impl Display for Summary<'_> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for module in self.modules {
+            write!(f, "{}", module)?;
+        }
+        writeln!(
+            f,
+            "time: read {}ms, write {}ms, total {}ms",
+            self.phases.read_ms, self.phases.write_ms, self.phases.total_ms
+        )
+    }
+}
+
+struct Report {
+    path: String,
+    format: StatsFormat,
+    start: Instant,
+    write: Duration,
+    modules: Vec<ModuleStats>,
+}
+
+/// Write a report about the dumped modules at the end of the run (in stderr with -)
+pub(crate) fn init(path: &str, format: &str) -> common::Result<()> {
+    let format = match format {
+        "text" => StatsFormat::Text,
+        "json" => StatsFormat::Json,
+        _ => return Err(format!("Invalid stats format: {}", format).into()),
+    };
+    *lock() = Some(Report {
+        path: path.to_string(),
+        format,
+        start: Instant::now(),
+        write: Duration::default(),
+        modules: Vec::new(),
+    });
+    Ok(())
+}
+
+pub(crate) fn is_enabled() -> bool {
+    lock().is_some()
+}
+
+/// Add the stats of a module and the time spent to write its symbols
+pub(crate) fn add(stats: ModuleStats, write: Duration) {
+    if let Some(report) = lock().as_mut() {
+        report.modules.push(stats);
+        report.write += write;
+    }
+}
+
+fn write_summary<W: Write>(
+    output: &mut W,
+    format: StatsFormat,
+    summary: &Summary,
+) -> common::Result<()> {
+    match format {
+        StatsFormat::Text => write!(output, "{}", summary)?,
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(&mut *output, summary)?;
+            writeln!(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the report (if any)
+pub(crate) fn finish() -> common::Result<()> {
+    let Report {
+        path,
+        format,
+        start,
+        write,
+        mut modules,
+    } = match lock().take() {
+        Some(report) => report,
+        None => return Ok(()),
+    };
+    // The modules can be dumped in parallel
+    modules.sort_by(|a, b| (&a.name, &a.debug_id).cmp(&(&b.name, &b.debug_id)));
+    let summary = Summary {
+        modules: &modules,
+        phases: Phases::new(write, start.elapsed()),
+    };
+
+    if path == "-" {
+        write_summary(&mut io::stderr(), format, &summary)
+    } else {
+        shutdown::write_file(&path, |w| write_summary(w, format, &summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let data = "MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full
+INFO CODE_ID B060AD20C6B47781552708AA192E7739
+FILE 0 /tmp/basic.cpp
+INLINE_ORIGIN 0 foo()
+FUNC 1000 10 0 main
+INLINE 0 3 0 0 1004 4
+INLINE 1 5 0 0 1006 2
+1000 4 2 0
+1004 c 3 0
+FUNC 1010 8 0 bar()
+FUNC m 1020 8 0 baz()
+INLINE 0 7 0 0 1020 4
+1020 8 7 0
+PUBLIC 1030 0 _init
+STACK CFI INIT 1000 10 .cfa: $rsp 8 + .ra: .cfa -8 + ^
+STACK CFI 1001 .cfa: $rsp 16 +
+STACK WIN 4 1030 8 0 0 0 0 0 0 1 $eip 4 + ^ = $esp $ebp 8 + = $ebp $ebp ^ =
+FUNC face 4 0 last";
+
+        let mut counter = StatsCounter::default();
+        // Split the records in some random chunks
+        for chunk in data.as_bytes().chunks(7) {
+            counter.write_all(chunk).unwrap();
+        }
+        let stats = counter.finish();
+
+        let mut inline_depths = BTreeMap::new();
+        inline_depths.insert(0, 2);
+        inline_depths.insert(1, 1);
+        assert_eq!(
+            stats,
+            ModuleStats {
+                name: "basic.full".to_string(),
+                os: "Linux".to_string(),
+                debug_id: "20AD60B0B4C68177552708AA192E77390".to_string(),
+                code_id: Some("B060AD20C6B47781552708AA192E7739".to_string()),
+                functions: 4,
+                functions_without_lines: 2,
+                publics: 1,
+                files: 1,
+                inline_origins: 1,
+                lines: 3,
+                inlines: 3,
+                inline_depths,
+                cfi: 1,
+                cfi_rules: 1,
+                stack_win: 1,
+            }
+        );
+
+        let modules = vec![stats];
+        let summary = Summary {
+            modules: &modules,
+            phases: Phases::new(Duration::from_millis(2), Duration::from_millis(5)),
+        };
+        let mut output = Vec::new();
+        write_summary(&mut output, StatsFormat::Text, &summary).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "basic.full 20AD60B0B4C68177552708AA192E77390
+  functions: 4 (2 without line info)
+  publics: 1
+  files: 1
+  line records: 3
+  inlines: 3 (1 origins)
+  inline depths: 0: 2, 1: 1
+  CFI entries: 1 (1 rules)
+  STACK WIN: 1
+time: read 3ms, write 2ms, total 5ms
+"
+        );
+
+        let mut output = Vec::new();
+        write_summary(&mut output, StatsFormat::Json, &summary).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json["modules"][0]["functions_without_lines"], 2);
+        assert_eq!(json["modules"][0]["inline_depths"]["1"], 1);
+        assert_eq!(json["phases"]["read_ms"], 3);
+    }
+}
@@ -0,0 +1,164 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A SymbolSupplier gets a debug file from its name and its id when it isn't next to the dumped
+// file (e.g. the PDB of a PE or a file dumped with --debug-id/--code-id).
+// By default, the symbol servers (and their caches) of the config files or of --symbol-server
+// are queried but the library can use another storage (a local store, debuginfod or a closure).
+// A supplier can open a PDB as a RemoteFile: the PDB is then parsed while it's downloaded.
+
+#[cfg(feature = "network")]
+use log::warn;
+#[cfg(feature = "network")]
+use reqwest::{blocking, Method};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cache::{self, HttpOptions, SymbolServer};
+use crate::common;
+use crate::pipeline::RemoteFile;
+use crate::utils;
+
+/// Get the data of a debug file from its name (e.g. xul.pdb) and its id (a debug id or a code id)
+pub trait SymbolSupplier: Send + Sync {
+    /// None when the file isn't found
+    fn fetch(&self, file_name: &str, id: &str) -> Option<Vec<u8>>;
+
+    /// Open a PDB to read it while it's downloaded: None when it must be fetched
+    fn open(&self, _file_name: &str, _id: &str) -> Option<RemoteFile> {
+        None
+    }
+}
+
+impl<F> SymbolSupplier for F
+where
+    F: Fn(&str, &str) -> Option<Vec<u8>> + Send + Sync,
+{
+    fn fetch(&self, file_name: &str, id: &str) -> Option<Vec<u8>> {
+        self(file_name, id)
+    }
+}
+
+/// The suppliers are tried in order (the files are fetched: they're never opened)
+impl SymbolSupplier for Vec<Box<dyn SymbolSupplier>> {
+    fn fetch(&self, file_name: &str, id: &str) -> Option<Vec<u8>> {
+        self.iter().find_map(|s| s.fetch(file_name, id))
+    }
+}
+
+/// Some symbol servers (SRV*cache*url) and their caches
+pub struct SymbolServers(Option<Vec<SymbolServer>>);
+
+impl SymbolServers {
+    /// The servers in symbol_server (as --symbol-server) or in the config files when it's None
+    pub fn new(symbol_server: Option<&str>, http: &HttpOptions) -> common::Result<Self> {
+        Ok(Self(cache::get_sym_servers(symbol_server, http)?))
+    }
+}
+
+impl SymbolSupplier for SymbolServers {
+    fn fetch(&self, file_name: &str, id: &str) -> Option<Vec<u8>> {
+        cache::search_file(file_name.to_string(), id, self.0.as_ref()).0
+    }
+
+    fn open(&self, file_name: &str, id: &str) -> Option<RemoteFile> {
+        cache::open_file(file_name, id, self.0.as_ref())
+    }
+}
+
+/// A local directory with the layout of a symbol store: xul.pdb/DEBUG_ID/xul.pdb
+/// (or xul.pd_ for a compressed file)
+pub struct LocalStore(PathBuf);
+
+impl LocalStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self(root.as_ref().to_path_buf())
+    }
+}
+
+impl SymbolSupplier for LocalStore {
+    fn fetch(&self, file_name: &str, id: &str) -> Option<Vec<u8>> {
+        let dir = self.0.join(cache::get_base(file_name)).join(id);
+        let mut compressed = file_name.to_string();
+        compressed.pop();
+        compressed.push('_');
+        [file_name, &compressed].iter().find_map(|name| {
+            let path = dir.join(name);
+            let buf = fs::read(&path).ok()?;
+            utils::read_cabinet(buf, path)
+        })
+    }
+}
+
+/// A debuginfod server: the id is the build id of an ELF file (its code id)
+#[cfg(feature = "network")]
+pub struct Debuginfod {
+    url: String,
+    http: HttpOptions,
+}
+
+#[cfg(feature = "network")]
+impl Debuginfod {
+    pub fn new(url: &str, http: HttpOptions) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    fn download(&self, id: &str) -> common::Result<Vec<u8>> {
+        let url = format!("{}/buildid/{}/debuginfo", self.url, id.to_lowercase());
+        let request = self.http.get_request(Method::GET, &url);
+        let resp = blocking::Client::new()
+            .request(request.method, &request.url)
+            .headers(request.headers)
+            .send()?;
+        if !resp.status().is_success() {
+            return Err(format!("Status {} for {}", resp.status(), request.url).into());
+        }
+        Ok(resp.bytes()?.to_vec())
+    }
+}
+
+#[cfg(feature = "network")]
+impl SymbolSupplier for Debuginfod {
+    fn fetch(&self, file_name: &str, id: &str) -> Option<Vec<u8>> {
+        self.download(id)
+            .map_err(|e| warn!("Unable to get {} from debuginfod: {}", file_name, e))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs::{copy, create_dir_all};
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_suppliers() {
+        let tmp_dir = Builder::new().prefix("supplier").tempdir().unwrap();
+        let dir = tmp_dir.path().join("basic.pdb").join("1234");
+        create_dir_all(&dir).unwrap();
+        copy("./test_data/windows/basic64.pdb", dir.join("basic.pdb")).unwrap();
+        let pdb = fs::read("./test_data/windows/basic64.pdb").unwrap();
+
+        let store = LocalStore::new(tmp_dir.path());
+        assert_eq!(store.fetch("basic.pdb", "1234"), Some(pdb.clone()));
+        assert!(store.fetch("basic.pdb", "5678").is_none());
+
+        let suppliers: Vec<Box<dyn SymbolSupplier>> = vec![
+            Box::new(store),
+            Box::new(|name: &str, _id: &str| Some(name.as_bytes().to_vec())),
+        ];
+        assert_eq!(suppliers.fetch("basic.pdb", "1234"), Some(pdb));
+        assert_eq!(
+            suppliers.fetch("foo.pdb", "1234"),
+            Some(b"foo.pdb".to_vec())
+        );
+    }
+}
@@ -0,0 +1,136 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A zip file gathering the symbol files written in a run, with their paths in a symbol store
+// (e.g. xul.pdb/DEBUG_ID/xul.sym) as expected by Tecken (symbols.mozilla.org) for an upload.
+
+use hashbrown::HashSet;
+use log::warn;
+use std::io::{Seek, Write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::common;
+use crate::shutdown::AtomicFile;
+
+lazy_static! {
+    static ref SYMBOLS_ZIP: Mutex<Option<SymbolsZip<AtomicFile>>> = Mutex::new(None);
+}
+
+fn lock() -> MutexGuard<'static, Option<SymbolsZip<AtomicFile>>> {
+    SYMBOLS_ZIP.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) struct SymbolsZip<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    names: HashSet<String>,
+}
+
+impl<W: Write + Seek> SymbolsZip<W> {
+    pub(crate) fn new(output: W) -> Self {
+        Self {
+            zip: ZipWriter::new(output),
+            names: HashSet::new(),
+        }
+    }
+
+    /// Add a file: the ones already compressed (.gz or .zst) are stored as is
+    pub(crate) fn add(&mut self, name: &str, data: &[u8]) -> common::Result<()> {
+        if !self.names.insert(name.to_string()) {
+            warn!("{} is already in the zip file", name);
+            return Ok(());
+        }
+
+        let method = if name.ends_with(".gz") || name.ends_with(".zst") {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        let options = FileOptions::default()
+            .compression_method(method)
+            .large_file(data.len() >= u32::MAX as usize);
+        self.zip.start_file(name, options)?;
+        self.zip.write_all(data)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> common::Result<W> {
+        Ok(self.zip.finish()?)
+    }
+}
+
+/// Write the symbol files of the run in a zip file
+pub(crate) fn init(path: &str) -> common::Result<()> {
+    *lock() = Some(SymbolsZip::new(AtomicFile::create(path)?));
+    Ok(())
+}
+
+pub(crate) fn is_enabled() -> bool {
+    lock().is_some()
+}
+
+/// Get the name of the entry for a path in a symbol store
+pub(crate) fn get_entry_name(path: &Path) -> String {
+    path.iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub(crate) fn add(name: &str, data: &[u8]) -> common::Result<()> {
+    match lock().as_mut() {
+        Some(zip) => zip.add(name, data),
+        _ => Ok(()),
+    }
+}
+
+/// Write the central directory and rename the zip file
+pub(crate) fn finish() -> common::Result<()> {
+    match lock().take() {
+        Some(zip) => zip.finish()?.commit(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+    use zip::ZipArchive;
+
+    #[test]
+    fn test_symbols_zip() {
+        let name = get_entry_name(&PathBuf::from("xul.pdb").join("ABCD1").join("xul.sym"));
+        assert_eq!(name, "xul.pdb/ABCD1/xul.sym");
+
+        let data = b"MODULE windows x86_64 ABCD1 xul.pdb\n";
+        let mut zip = SymbolsZip::new(Cursor::new(Vec::new()));
+        zip.add(&name, data).unwrap();
+        zip.add(&name, b"MODULE").unwrap();
+        zip.add("libfoo.so.1/ABCD0/libfoo.so.1.sym.zst", b"zstd")
+            .unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        let mut zip = ZipArchive::new(Cursor::new(zip)).unwrap();
+        assert_eq!(zip.len(), 2);
+
+        let mut file = zip.by_name("xul.pdb/ABCD1/xul.sym").unwrap();
+        assert_eq!(file.compression(), CompressionMethod::Deflated);
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content, data);
+        drop(file);
+
+        let file = zip
+            .by_name("libfoo.so.1/ABCD0/libfoo.so.1.sym.zst")
+            .unwrap();
+        assert_eq!(file.compression(), CompressionMethod::Stored);
+    }
+}
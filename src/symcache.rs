@@ -0,0 +1,70 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A SymCache (the format of the symbolic-based symbolication, e.g. in Sentry) is built
+// with the writer of symbolic from the Breakpad records of the dumpers, so the functions,
+// the lines, the path mappings and the module name are the same as in the .sym file.
+
+use failure::Fail;
+use std::io::{self, Cursor, Write};
+use symbolic::debuginfo::breakpad::BreakpadObject;
+use symbolic::symcache::SymCacheWriter;
+
+use crate::common;
+use crate::output::Converter;
+
+#[derive(Debug, Default)]
+pub(crate) struct SymCacheConverter {
+    records: Vec<u8>,
+}
+
+impl Write for SymCacheConverter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.records.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Converter for SymCacheConverter {
+    fn write_document(&mut self, output: &mut dyn Write) -> common::Result<()> {
+        let object = BreakpadObject::parse(&self.records).map_err(|e| e.compat())?;
+        let mut symcache = Vec::new();
+        SymCacheWriter::write_object(&object, Cursor::new(&mut symcache))
+            .map_err(|e| e.compat())?;
+        output.write_all(&symcache)?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_symcache() {
+        let mut converter = SymCacheConverter::default();
+        converter
+            .write_all(
+                b"MODULE Linux x86_64 20AD60B0B4C68177552708AA192E77390 basic.full\n\
+                  FILE 0 /tmp/basic.cpp\n\
+                  FUNC 1000 20 0 main\n\
+                  1000 20 3 0\n",
+            )
+            .unwrap();
+        let mut output = Vec::new();
+        converter.write_document(&mut output).unwrap();
+        assert!(output.starts_with(b"SYMC"));
+
+        let mut converter = SymCacheConverter::default();
+        converter.write_all(b"FUNC 1000 20 0 main\n").unwrap();
+        assert!(converter.write_document(&mut Vec::new()).is_err());
+    }
+}
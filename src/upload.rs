@@ -0,0 +1,268 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The symbol files are uploaded to a Tecken server (like symbols.mozilla.org) in some zip files:
+// each zip file is posted (as multipart/form-data) to the upload url with an Auth-Token header.
+// The servers limit the size of an upload so the files are split in several zip files.
+// Without the network feature, an upload fails.
+
+#![cfg_attr(not(feature = "network"), allow(dead_code))]
+
+#[cfg(feature = "network")]
+use log::{info, warn};
+#[cfg(feature = "network")]
+use reqwest::{
+    blocking::Client,
+    header::{HeaderValue, CONTENT_TYPE},
+};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "network")]
+use std::thread;
+use std::time::Duration;
+use zip::ZipArchive;
+
+use crate::cache::HttpOptions;
+use crate::common;
+use crate::symbols_zip::{self, SymbolsZip};
+
+pub(crate) const DEFAULT_URL: &str = "https://symbols.mozilla.org/upload/";
+
+const BOUNDARY: &str = "dump-syms-upload-boundary";
+
+pub(crate) struct UploadOptions {
+    pub url: String,
+    pub token: String,
+    /// The max size of the data in a zip file
+    pub max_size: u64,
+    pub retries: usize,
+    /// The delay before the first retry (it's doubled after each retry)
+    pub retry_delay: Duration,
+    pub http: HttpOptions,
+}
+
+/// A symbol file with its path in a symbol store
+struct SymbolFile {
+    name: String,
+    data: Vec<u8>,
+}
+
+fn is_symbol_file(name: &str) -> bool {
+    [".sym", ".sym.gz", ".sym.zst"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+fn read_store(root: &Path, dir: &Path, files: &mut Vec<SymbolFile>) -> common::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            read_store(root, &path, files)?;
+        } else if is_symbol_file(&path.to_string_lossy()) {
+            files.push(SymbolFile {
+                name: symbols_zip::get_entry_name(path.strip_prefix(root).unwrap()),
+                data: fs::read(&path)?,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn read_zip(path: &Path, files: &mut Vec<SymbolFile>) -> common::Result<()> {
+    let mut zip = ZipArchive::new(fs::File::open(path)?)?;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        if !file.is_file() {
+            continue;
+        }
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        files.push(SymbolFile {
+            name: file.name().to_string(),
+            data,
+        });
+    }
+    Ok(())
+}
+
+/// Get the symbol files in some zip files (e.g. written with --zip) or in some symbol stores
+fn get_files(paths: &[&str]) -> common::Result<Vec<SymbolFile>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = PathBuf::from(path);
+        if path.is_dir() {
+            read_store(&path, &path, &mut files)?;
+        } else {
+            read_zip(&path, &mut files)
+                .map_err(|e| format!("Invalid zip file {}: {}", path.display(), e))?;
+        }
+    }
+
+    if files.is_empty() {
+        return Err("No symbol file to upload".into());
+    }
+    Ok(files)
+}
+
+/// Split the files in some chunks of at most max_size bytes (a bigger file is alone in its chunk)
+fn get_chunks(files: Vec<SymbolFile>, max_size: u64) -> Vec<Vec<SymbolFile>> {
+    let mut chunks: Vec<Vec<SymbolFile>> = Vec::new();
+    let mut size = 0;
+    for file in files {
+        let len = file.data.len() as u64;
+        match chunks.last_mut() {
+            Some(chunk) if size + len <= max_size => {
+                size += len;
+                chunk.push(file);
+            }
+            _ => {
+                size = len;
+                chunks.push(vec![file]);
+            }
+        }
+    }
+    chunks
+}
+
+fn make_zip(files: &[SymbolFile]) -> common::Result<Vec<u8>> {
+    let mut zip = SymbolsZip::new(Cursor::new(Vec::new()));
+    for file in files {
+        zip.add(&file.name, &file.data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+fn get_multipart_body(zip: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"symbols.zip\"; filename=\"symbols.zip\"\r\n\
+         Content-Type: application/zip\r\n\r\n",
+        BOUNDARY
+    )
+    .into_bytes();
+    body.extend_from_slice(zip);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+    body
+}
+
+/// Post a zip file: the request is retried on a network error or on a server error
+#[cfg(feature = "network")]
+fn post(client: &Client, zip: Vec<u8>, options: &UploadOptions) -> common::Result<()> {
+    let token = HeaderValue::from_str(&options.token).map_err(|_| "Invalid token")?;
+    let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+    let body = get_multipart_body(&zip);
+    let mut delay = options.retry_delay;
+
+    for attempt in 0..=options.retries {
+        if attempt != 0 {
+            thread::sleep(delay);
+            delay *= 2;
+        }
+
+        let resp = client
+            .post(&options.url)
+            .headers(options.http.get_headers())
+            .header("Auth-Token", token.clone())
+            .header(CONTENT_TYPE, content_type.as_str())
+            .body(body.clone())
+            .send();
+        let error = match resp {
+            Ok(resp) if resp.status().is_success() => return Ok(()),
+            Ok(resp) => {
+                let status = resp.status();
+                let error = format!("Status {} for {}", status, options.url);
+                if !status.is_server_error() && status.as_u16() != 429 {
+                    return Err(error.into());
+                }
+                error
+            }
+            Err(e) => e.to_string(),
+        };
+        warn!("Upload failed (attempt {}): {}", attempt + 1, error);
+        if attempt == options.retries {
+            return Err(error.into());
+        }
+    }
+
+    unreachable!()
+}
+
+/// Upload the symbol files in some zip files or some symbol stores
+#[cfg(feature = "network")]
+pub(crate) fn upload(paths: &[&str], options: &UploadOptions) -> common::Result<()> {
+    let files = get_files(paths)?;
+    let total = files.len();
+    let client = Client::new();
+    let mut failed = 0;
+
+    for chunk in get_chunks(files, options.max_size) {
+        match make_zip(&chunk).and_then(|zip| post(&client, zip, options)) {
+            Ok(_) => {
+                for file in chunk.iter() {
+                    info!("{} uploaded", file.name);
+                }
+            }
+            Err(e) => {
+                for file in chunk.iter() {
+                    warn!("{} not uploaded: {}", file.name, e);
+                }
+                failed += chunk.len();
+            }
+        }
+    }
+
+    println!("{} files uploaded, {} failed", total - failed, failed);
+    if failed != 0 {
+        return Err(format!("{} symbol files haven't been uploaded", failed).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "network"))]
+pub(crate) fn upload(_paths: &[&str], _options: &UploadOptions) -> common::Result<()> {
+    Err("dump_syms has been built without the network feature: nothing can be uploaded".into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn file(name: &str, len: usize) -> SymbolFile {
+        SymbolFile {
+            name: name.to_string(),
+            data: vec![b'A'; len],
+        }
+    }
+
+    #[test]
+    fn test_chunks() {
+        let files = vec![
+            file("a.pdb/1/a.sym", 40),
+            file("b.pdb/1/b.sym", 50),
+            file("c.pdb/1/c.sym", 200),
+            file("d.pdb/1/d.sym", 10),
+        ];
+        let chunks = get_chunks(files, 100);
+        let names: Vec<Vec<_>> = chunks
+            .iter()
+            .map(|c| c.iter().map(|f| f.name.as_str()).collect())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                vec!["a.pdb/1/a.sym", "b.pdb/1/b.sym"],
+                vec!["c.pdb/1/c.sym"],
+                vec!["d.pdb/1/d.sym"],
+            ]
+        );
+
+        assert!(is_symbol_file("xul.sym.zst"));
+        assert!(!is_symbol_file("xul.sym.vcs.json"));
+    }
+}
@@ -4,33 +4,162 @@
 // copied, modified, or distributed except according to those terms.
 
 use cab::Cabinet;
+use log::{debug, trace, LevelFilter};
+use memmap2::Mmap;
+use std::ffi::OsString;
 use std::fs::{self, File, Metadata};
 use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::ops::Deref;
 use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
 
 use crate::common;
 
 pub fn read_file<P: AsRef<Path>>(path: P) -> Vec<u8> {
-    let metadata = fs::metadata(&path).unwrap_or_else(|_| {
-        panic!(
+    try_read_file(path).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Read a file (or the DWARF file of a dSYM bundle or the file in a cabinet)
+pub fn try_read_file<P: AsRef<Path>>(path: P) -> common::Result<Vec<u8>> {
+    let metadata = fs::metadata(&path).map_err(|_| {
+        format!(
             "Unable to open the file {}",
             path.as_ref().to_str().unwrap()
         )
-    });
+    })?;
 
     let (metadata, path) = get_mac_bundle(&metadata, &path)
         .unwrap_or_else(|| (metadata, PathBuf::from(path.as_ref())));
 
     let file_size = metadata.len() as usize;
     let mut file = File::open(&path)
-        .unwrap_or_else(|_| panic!("Unable to open the file {}", path.to_str().unwrap()));
+        .map_err(|_| format!("Unable to open the file {}", path.to_str().unwrap()))?;
 
     let mut buf = Vec::with_capacity(file_size + 1);
     file.read_to_end(&mut buf)
-        .unwrap_or_else(|_| panic!("Unable to read the file {}", path.to_str().unwrap()));
+        .map_err(|_| format!("Unable to read the file {}", path.to_str().unwrap()))?;
 
     read_cabinet(buf, path.clone())
-        .unwrap_or_else(|| panic!("Unable to read the cabinet file {}", path.to_str().unwrap()))
+        .ok_or_else(|| format!("Unable to read the cabinet file {}", path.to_str().unwrap()).into())
+}
+
+// The signature of a cabinet file (e.g. xul.pd_)
+const CABINET_MAGIC: &[u8] = b"MSCF";
+
+/// The data of a file: mapped in memory when it's read as is, so only the pages which are accessed
+/// are loaded (e.g. the streams of a huge PDB), or in a buffer (decompressed, downloaded, ...)
+pub(crate) enum FileBuffer {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for FileBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(mmap) => mmap,
+            Self::Owned(buf) => buf,
+        }
+    }
+}
+
+impl From<Vec<u8>> for FileBuffer {
+    fn from(buf: Vec<u8>) -> Self {
+        Self::Owned(buf)
+    }
+}
+
+pub(crate) fn map_file<P: AsRef<Path>>(path: P) -> FileBuffer {
+    try_map_file(path).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Map a file in memory (or the DWARF file of a dSYM bundle): the file in a cabinet is decompressed
+pub(crate) fn try_map_file<P: AsRef<Path>>(path: P) -> common::Result<FileBuffer> {
+    let metadata = fs::metadata(&path).map_err(|_| {
+        format!(
+            "Unable to open the file {}",
+            path.as_ref().to_str().unwrap()
+        )
+    })?;
+
+    let (metadata, path) = get_mac_bundle(&metadata, &path)
+        .unwrap_or_else(|| (metadata, PathBuf::from(path.as_ref())));
+
+    // An empty file can't be mapped
+    if metadata.len() == 0 {
+        return try_read_file(&path).map(FileBuffer::Owned);
+    }
+
+    let file = File::open(&path)
+        .map_err(|_| format!("Unable to open the file {}", path.to_str().unwrap()))?;
+    // The file mustn't be modified while it's dumped
+    let mmap = unsafe { Mmap::map(&file) }
+        .map_err(|_| format!("Unable to map the file {}", path.to_str().unwrap()))?;
+
+    if !mmap.starts_with(CABINET_MAGIC) {
+        return Ok(FileBuffer::Mapped(mmap));
+    }
+    read_cabinet(mmap.to_vec(), path.clone())
+        .map(FileBuffer::Owned)
+        .ok_or_else(|| format!("Unable to read the cabinet file {}", path.to_str().unwrap()).into())
+}
+
+/// The file name reading the data from stdin
+pub(crate) const STDIN: &str = "-";
+
+/// Read all the data piped in stdin
+pub(crate) fn read_stdin() -> common::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    io::stdin()
+        .lock()
+        .read_to_end(&mut buf)
+        .map_err(|e| format!("Unable to read stdin: {}", e))?;
+    if buf.is_empty() {
+        return Err("Nothing to read on stdin".into());
+    }
+    Ok(buf)
+}
+
+/// Log the time spent in a phase (fetch, parse, emit, ...) at the debug level when it's dropped
+pub(crate) struct Span {
+    name: String,
+    start: Instant,
+}
+
+impl Span {
+    pub(crate) fn new<S: Into<String>>(name: S) -> Self {
+        let name = name.into();
+        trace!("{}: start", name);
+        Self {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        debug!("{}: {:.3}s", self.name, self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Get the level of the logs for dump_syms from a RUST_LOG value
+/// (e.g. "info" or "dump_syms=debug,reqwest=warn")
+pub(crate) fn parse_rust_log(value: &str) -> Option<LevelFilter> {
+    let mut level = None;
+    for directive in value.split(',').map(|d| d.trim()) {
+        let directive_level = match directive.split_once('=') {
+            Some((target, l)) if target.split("::").next() == Some("dump_syms") => l,
+            Some(_) => continue,
+            None => directive,
+        };
+        if let Ok(l) = LevelFilter::from_str(directive_level) {
+            level = Some(l);
+        }
+    }
+    level
 }
 
 pub fn get_mac_bundle<P: AsRef<Path>>(metadata: &Metadata, path: P) -> Option<(Metadata, PathBuf)> {
@@ -174,6 +303,34 @@ pub fn get_filename(path: &PathBuf) -> String {
     path.file_name().unwrap().to_str().unwrap().to_string()
 }
 
+/// Replace the response files (@inputs.txt with an argument per line) by their arguments
+/// so the command line length limit doesn't bite with thousands of files
+pub fn expand_response_files<I: IntoIterator<Item = OsString>>(
+    args: I,
+) -> common::Result<Vec<OsString>> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        let path = arg
+            .to_str()
+            .and_then(|a| a.strip_prefix('@'))
+            .filter(|p| !p.is_empty());
+        match path {
+            Some(path) => {
+                let data = fs::read_to_string(path)
+                    .map_err(|e| format!("Cannot read the response file {}: {}", path, e))?;
+                expanded.extend(
+                    data.lines()
+                        .map(|l| l.trim_end_matches('\r'))
+                        .filter(|l| !l.trim().is_empty())
+                        .map(OsString::from),
+                );
+            }
+            _ => expanded.push(arg),
+        }
+    }
+    Ok(expanded)
+}
+
 pub fn read<P: AsRef<Path>>(path: P) -> common::Result<Vec<u8>> {
     let file_size = fs::metadata(&path)?.len() as usize;
     let mut file = File::open(&path).unwrap_or_else(|_| {
@@ -193,3 +350,65 @@ pub fn read<P: AsRef<Path>>(path: P) -> common::Result<Vec<u8>> {
 
     Ok(buf)
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn test_parse_rust_log() {
+        assert_eq!(parse_rust_log("info"), Some(LevelFilter::Info));
+        assert_eq!(parse_rust_log("dump_syms=debug"), Some(LevelFilter::Debug));
+        assert_eq!(
+            parse_rust_log("warn,dump_syms::cache=trace,reqwest=off"),
+            Some(LevelFilter::Trace)
+        );
+        assert_eq!(parse_rust_log("reqwest=debug"), None);
+        assert_eq!(parse_rust_log("foo"), None);
+    }
+
+    #[test]
+    fn test_response_files() {
+        let tmp_dir = Builder::new().prefix("response").tempdir().unwrap();
+        let inputs = tmp_dir.path().join("inputs.txt");
+        fs::write(&inputs, "libfoo.so\r\n\n/tmp/my dir/libbar.so\n").unwrap();
+
+        let args = vec![
+            "dump_syms".to_string(),
+            "--store".to_string(),
+            "symbols".to_string(),
+            format!("@{}", inputs.display()),
+            "xul.pdb".to_string(),
+        ];
+        let args = expand_response_files(args.into_iter().map(OsString::from)).unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "dump_syms",
+                "--store",
+                "symbols",
+                "libfoo.so",
+                "/tmp/my dir/libbar.so",
+                "xul.pdb"
+            ]
+        );
+
+        assert!(expand_response_files(vec![OsString::from("@/not/a/file")]).is_err());
+    }
+
+    #[test]
+    fn test_map_file() {
+        let path = "./test_data/windows/basic64.pdb";
+        let buf = try_map_file(path).unwrap();
+        assert!(matches!(buf, FileBuffer::Mapped(_)));
+        assert_eq!(&buf[..], &read_file(path)[..]);
+
+        let tmp_dir = Builder::new().prefix("map").tempdir().unwrap();
+        let empty = tmp_dir.path().join("empty");
+        fs::write(&empty, "").unwrap();
+        assert!(try_map_file(&empty).unwrap().is_empty());
+        assert!(try_map_file(tmp_dir.path().join("missing")).is_err());
+    }
+}
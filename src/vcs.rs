@@ -0,0 +1,306 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use hashbrown::{HashMap, HashSet};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use crate::common;
+use crate::shutdown;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum VcsKind {
+    Git,
+}
+
+impl VcsKind {
+    pub(crate) fn from_str(s: &str) -> common::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "git" => Ok(Self::Git),
+            _ => Err(format!("Invalid vcs {}: must be git", s).into()),
+        }
+    }
+}
+
+/// The revision of the repository containing a source file and the hash of the file content
+/// in this revision: when the file has been modified in the working tree, it's marked as dirty.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct FileRevision {
+    pub revision: String,
+    pub blob: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dirty: bool,
+}
+
+/// A repository with the blobs of the files in its HEAD revision
+/// and the files modified in the working tree: they're read once for all the files
+#[derive(Debug)]
+struct Repo {
+    revision: String,
+    blobs: HashMap<PathBuf, String>,
+    modified: HashSet<PathBuf>,
+}
+
+impl Repo {
+    fn new(kind: VcsKind, root: &Path) -> Option<Self> {
+        match kind {
+            VcsKind::Git => {
+                let revision = git(root, &["rev-parse", "HEAD"])?.trim().to_string();
+                let blobs =
+                    parse_ls_tree(&git(root, &["ls-tree", "-r", "-z", "--full-tree", "HEAD"])?);
+                let modified = git(root, &["diff", "--name-only", "-z", "HEAD"])?
+                    .split('\0')
+                    .filter(|name| !name.is_empty())
+                    .map(PathBuf::from)
+                    .collect();
+                Some(Self {
+                    revision,
+                    blobs,
+                    modified,
+                })
+            }
+        }
+    }
+
+    fn get_revision(&self, path: &Path) -> Option<FileRevision> {
+        Some(FileRevision {
+            revision: self.revision.clone(),
+            blob: self.blobs.get(path)?.clone(),
+            dirty: self.modified.contains(path),
+        })
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
+
+/// Parse the output of git ls-tree -z: "<mode> <type> <object>\t<path>\0"
+fn parse_ls_tree(output: &str) -> HashMap<PathBuf, String> {
+    output
+        .split('\0')
+        .filter_map(|entry| {
+            let (info, path) = entry.split_once('\t')?;
+            let mut info = info.split(' ');
+            match (info.next(), info.next(), info.next()) {
+                (Some(_), Some("blob"), Some(blob)) => {
+                    Some((PathBuf::from(path), blob.to_string()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // For a given directory, the root of its repository (if any)
+    roots: HashMap<PathBuf, Option<PathBuf>>,
+    repos: HashMap<PathBuf, Option<Arc<Repo>>>,
+    // The files without revision info
+    missing: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub(crate) struct VcsInfo {
+    kind: VcsKind,
+    state: Mutex<State>,
+    // The FILE record name and the corresponding revision info
+    files: Mutex<BTreeMap<String, FileRevision>>,
+}
+
+impl VcsInfo {
+    pub(crate) fn new(kind: VcsKind) -> Self {
+        Self {
+            kind,
+            state: Mutex::new(State::default()),
+            files: Mutex::new(BTreeMap::default()),
+        }
+    }
+
+    fn find_root(&self, dir: &Path) -> Option<PathBuf> {
+        let marker = match self.kind {
+            VcsKind::Git => ".git",
+        };
+        dir.ancestors()
+            .find(|d| d.join(marker).exists())
+            .map(Path::to_path_buf)
+    }
+
+    fn get_repo(&self, state: &mut State, dir: &Path) -> Option<(PathBuf, Arc<Repo>)> {
+        let root = match state.roots.get(dir) {
+            Some(root) => root.clone(),
+            None => {
+                let root = self.find_root(dir);
+                state.roots.insert(dir.to_path_buf(), root.clone());
+                root
+            }
+        }?;
+
+        let kind = self.kind;
+        let repo = state
+            .repos
+            .entry(root.clone())
+            .or_insert_with(|| Repo::new(kind, &root).map(Arc::new))
+            .clone()?;
+
+        Some((root, repo))
+    }
+
+    fn get_revision(&self, file: &Path) -> Option<FileRevision> {
+        let file = file.canonicalize().ok().filter(|f| f.is_file())?;
+        let (root, repo) = self.get_repo(&mut self.state.lock().unwrap(), file.parent()?)?;
+        repo.get_revision(file.strip_prefix(&root).ok()?)
+    }
+
+    /// Collect the revision info for the file which will appear as name in the FILE records
+    pub(crate) fn collect<P: AsRef<Path>>(&self, file: P, name: &str) {
+        {
+            let files = self.files.lock().unwrap();
+            if files.contains_key(name) {
+                return;
+            }
+        }
+
+        let file = file.as_ref();
+        if let Some(rev) = self.get_revision(file) {
+            let mut files = self.files.lock().unwrap();
+            files.insert(name.to_string(), rev);
+        } else {
+            self.state.lock().unwrap().missing.push(file.to_path_buf());
+        }
+    }
+
+    /// Write a json file containing the revisions of all the collected files
+    pub(crate) fn write<P: AsRef<Path>>(&self, path: P) -> common::Result<()> {
+        let path = path.as_ref();
+        let files = self.files.lock().unwrap();
+
+        let state = self.state.lock().unwrap();
+        if let Some(file) = state.missing.first() {
+            warn!(
+                "Cannot find vcs info for {} files (e.g. {})",
+                state.missing.len(),
+                file.to_string_lossy()
+            );
+        }
+        let dirty = files.values().filter(|rev| rev.dirty).count();
+        if dirty != 0 {
+            warn!(
+                "{} files are modified in the working tree: their blob is the one in HEAD",
+                dirty
+            );
+        }
+
+        shutdown::write_file(path, |output| {
+            serde_json::to_writer_pretty(output, &*files)?;
+            Ok(())
+        })?;
+        info!("Write vcs info at {}", path.to_string_lossy());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_vcs_kind() {
+        assert_eq!(VcsKind::from_str("git").unwrap(), VcsKind::Git);
+        assert_eq!(VcsKind::from_str("Git").unwrap(), VcsKind::Git);
+        assert!(VcsKind::from_str("svn").is_err());
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let vcs = VcsInfo::new(VcsKind::Git);
+        vcs.collect("/this/file/does/not/exist.cpp", "exist.cpp");
+        assert!(vcs.files.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_git_repo() {
+        let tmp_dir = Builder::new().prefix("vcs").tempdir().unwrap();
+        let root = tmp_dir.path();
+        let src = root.join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("a.cpp"), "int a;").unwrap();
+        fs::write(src.join("b.cpp"), "int b;").unwrap();
+
+        let commit = &[
+            "-c",
+            "user.name=dump_syms",
+            "-c",
+            "user.email=dump_syms@example.com",
+            "-c",
+            "commit.gpgsign=false",
+            "commit",
+            "-q",
+            "-m",
+            "init",
+        ];
+        git(root, &["init", "-q"]).unwrap();
+        git(root, &["add", "."]).unwrap();
+        git(root, commit).unwrap();
+
+        // b.cpp is modified and c.cpp isn't tracked
+        fs::write(src.join("b.cpp"), "int b = 1;").unwrap();
+        fs::write(src.join("c.cpp"), "int c;").unwrap();
+
+        let vcs = VcsInfo::new(VcsKind::Git);
+        for name in &["a.cpp", "b.cpp", "c.cpp"] {
+            vcs.collect(src.join(name), name);
+        }
+
+        let revision = git(root, &["rev-parse", "HEAD"])
+            .unwrap()
+            .trim()
+            .to_string();
+        let blob = |name: &str| git(root, &["rev-parse", name]).unwrap().trim().to_string();
+        let files = vcs.files.lock().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files["a.cpp"],
+            FileRevision {
+                revision: revision.clone(),
+                blob: blob("HEAD:src/a.cpp"),
+                dirty: false,
+            }
+        );
+        assert_eq!(
+            files["b.cpp"],
+            FileRevision {
+                revision,
+                blob: blob("HEAD:src/b.cpp"),
+                dirty: true,
+            }
+        );
+
+        // The repository is read once
+        let state = vcs.state.lock().unwrap();
+        assert_eq!(state.repos.len(), 1);
+        assert_eq!(state.missing, vec![src.join("c.cpp")]);
+    }
+}
@@ -0,0 +1,316 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A Visitor gets the records of a dump one by one (in the order of the .sym file) so a tool
+// can build its own index of the symbols without parsing the text output.
+// The records are the ones of the json module and they're made from the symbols, the files and
+// the inlines collected for the module: only the CFI (collected as some STACK records) and the
+// records read in a .sym file are parsed.
+
+use crate::checksums::Checksums;
+use crate::common;
+use crate::inlines::{Inline, InlineOrigins};
+use crate::json::{self, Range, Record};
+use crate::line::Lines;
+use crate::publics::Public;
+
+pub use crate::json::{
+    CfiDelta as CfiRecord, CfiInit as CfiInitRecord, File as FileRecord, Func as FuncRecord,
+    Info as InfoRecord, Inline as InlineRecord, Line as LineRecord, Module as ModuleRecord,
+    Public as PublicRecord, Range as RangeRecord, StackWin as StackWinRecord,
+};
+
+/// The callbacks called on the records of a dump: all of them do nothing by default
+///
+/// The LINE and INLINE records belong to the last visited function and the STACK CFI ones to the
+/// last visited STACK CFI INIT.
+#[allow(unused_variables)]
+pub trait Visitor {
+    fn module(&mut self, module: &ModuleRecord) {}
+
+    fn info(&mut self, info: &InfoRecord) {}
+
+    fn file(&mut self, file: &FileRecord) {}
+
+    fn inline_origin(&mut self, origin: &FileRecord) {}
+
+    fn func(&mut self, func: &FuncRecord) {}
+
+    fn line(&mut self, line: &LineRecord) {}
+
+    fn inline(&mut self, inline: &InlineRecord) {}
+
+    fn public(&mut self, public: &PublicRecord) {}
+
+    fn cfi_init(&mut self, init: &CfiInitRecord) {}
+
+    fn cfi(&mut self, cfi: &CfiRecord) {}
+
+    fn stack_win(&mut self, win: &StackWinRecord) {}
+}
+
+/// A visitor skipping the STACK records (for a dump without the CFI)
+pub(crate) struct NoCfi<'a>(pub &'a mut dyn Visitor);
+
+impl Visitor for NoCfi<'_> {
+    fn module(&mut self, module: &ModuleRecord) {
+        self.0.module(module);
+    }
+
+    fn info(&mut self, info: &InfoRecord) {
+        self.0.info(info);
+    }
+
+    fn file(&mut self, file: &FileRecord) {
+        self.0.file(file);
+    }
+
+    fn inline_origin(&mut self, origin: &FileRecord) {
+        self.0.inline_origin(origin);
+    }
+
+    fn func(&mut self, func: &FuncRecord) {
+        self.0.func(func);
+    }
+
+    fn line(&mut self, line: &LineRecord) {
+        self.0.line(line);
+    }
+
+    fn inline(&mut self, inline: &InlineRecord) {
+        self.0.inline(inline);
+    }
+
+    fn public(&mut self, public: &PublicRecord) {
+        self.0.public(public);
+    }
+}
+
+/// Call the visitor on a parsed record
+pub(crate) fn visit_record(visitor: &mut dyn Visitor, record: &Record) {
+    match record {
+        Record::Module(module) => visitor.module(module),
+        Record::Info(info) => visitor.info(info),
+        Record::File(file) => visitor.file(file),
+        Record::InlineOrigin(origin) => visitor.inline_origin(origin),
+        Record::Func(func) => visitor.func(func),
+        Record::Line(line) => visitor.line(line),
+        Record::Inline(inline) => visitor.inline(inline),
+        Record::Public(public) => visitor.public(public),
+        Record::StackCfiInit(init) => visitor.cfi_init(init),
+        Record::StackCfi(cfi) => visitor.cfi(cfi),
+        Record::StackWin(win) => visitor.stack_win(win),
+    }
+}
+
+/// Visit the Breakpad records of some text (e.g. the STACK records of a module)
+pub(crate) fn visit_text(visitor: &mut dyn Visitor, text: &str) -> common::Result<()> {
+    for line in text.lines() {
+        if let Some(record) = json::parse_line(line.as_bytes(), false)? {
+            visit_record(visitor, &record);
+        }
+    }
+    Ok(())
+}
+
+/// Visit the MODULE record
+pub(crate) fn visit_module(visitor: &mut dyn Visitor, os: &str, cpu: &str, id: &str, name: &str) {
+    visitor.module(&ModuleRecord {
+        os: os.to_string(),
+        cpu: cpu.to_string(),
+        debug_id: id.to_string(),
+        name: name.to_string(),
+    });
+}
+
+/// Visit the INFO CODE_ID record (the code file is the PE one)
+pub(crate) fn visit_code_id(visitor: &mut dyn Visitor, code_id: &str, code_file: Option<&str>) {
+    visitor.info(&InfoRecord {
+        key: "code_id".to_string(),
+        value: code_id.to_string(),
+        code_file: code_file.map(|file| file.to_string()),
+    });
+}
+
+/// Visit the FILE records with the checksums of the files (if any)
+pub(crate) fn visit_files(
+    visitor: &mut dyn Visitor,
+    files: &[String],
+    checksums: Option<&Checksums>,
+) {
+    for (n, name) in files.iter().enumerate() {
+        visitor.file(&FileRecord {
+            id: n as u32,
+            name: name.clone(),
+            checksum: checksums
+                .and_then(|checksums| checksums.get(name))
+                .map(|c| c.to_string()),
+        });
+    }
+}
+
+/// Visit the INLINE_ORIGIN records
+pub(crate) fn visit_inline_origins(visitor: &mut dyn Visitor, origins: &InlineOrigins) {
+    for (n, name) in origins.get_names().iter().enumerate() {
+        visitor.inline_origin(&FileRecord {
+            id: n as u32,
+            name: name.clone(),
+            checksum: None,
+        });
+    }
+}
+
+/// Visit a FUNC record with its INLINE and line records
+pub(crate) fn visit_func(
+    visitor: &mut dyn Visitor,
+    func: FuncRecord,
+    inlines: &[Inline],
+    lines: &Lines,
+) {
+    visitor.func(&func);
+    for inline in inlines {
+        visitor.inline(&InlineRecord {
+            depth: inline.depth,
+            call_line: inline.call_line,
+            call_file: inline.call_file,
+            origin: inline.origin,
+            ranges: inline
+                .ranges
+                .iter()
+                .map(|(address, size)| Range {
+                    address: u64::from(*address),
+                    size: u64::from(*size),
+                })
+                .collect(),
+        });
+    }
+    for line in lines.lines.iter() {
+        visitor.line(&LineRecord {
+            address: u64::from(line.rva),
+            size: u64::from(line.len),
+            line: line.num,
+            file: line.file_id,
+        });
+    }
+}
+
+/// Visit a PUBLIC record at the address of a FUNC (kept with --keep-publics)
+pub(crate) fn visit_public(visitor: &mut dyn Visitor, public: &Public) {
+    visitor.public(&PublicRecord {
+        address: u64::from(public.rva),
+        parameter_size: u64::from(public.parameter_size),
+        multiple: false,
+        name: public.name.clone(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Index {
+        module: String,
+        files: Vec<String>,
+        funcs: Vec<(u64, String, usize)>,
+        publics: Vec<String>,
+        cfi: Vec<(u64, usize)>,
+    }
+
+    impl Visitor for Index {
+        fn module(&mut self, module: &ModuleRecord) {
+            self.module = format!("{}-{}", module.name, module.debug_id);
+        }
+
+        fn file(&mut self, file: &FileRecord) {
+            self.files.push(file.name.clone());
+        }
+
+        fn func(&mut self, func: &FuncRecord) {
+            self.funcs.push((func.address, func.name.clone(), 0));
+        }
+
+        fn line(&mut self, _line: &LineRecord) {
+            self.funcs.last_mut().unwrap().2 += 1;
+        }
+
+        fn public(&mut self, public: &PublicRecord) {
+            self.publics.push(public.name.clone());
+        }
+
+        fn cfi_init(&mut self, init: &CfiInitRecord) {
+            self.cfi.push((init.address, 0));
+        }
+
+        fn cfi(&mut self, _cfi: &CfiRecord) {
+            self.cfi.last_mut().unwrap().1 += 1;
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let sym = "MODULE Linux x86_64 0F1E2D3C4B5A69788796A5B4C3D2E1F00 libfoo.so\n\
+                   INFO CODE_ID 3C2D1E0F5A4B7869\n\
+                   FILE 0 foo.c\n\
+                   FILE 1 bar.h\n\
+                   FUNC 1000 20 0 foo()\n\
+                   1000 10 3 0\n\
+                   1010 10 7 1\n\
+                   FUNC m 1020 8 0 bar()\n\
+                   1020 8 2 1\n\
+                   PUBLIC 2000 0 baz\n\
+                   STACK CFI INIT 1000 20 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+                   STACK CFI 1001 .cfa: $rsp 16 +\n\
+                   STACK CFI 1004 $rbp: .cfa -16 + ^";
+        let mut index = Index::default();
+        visit_text(&mut index, sym).unwrap();
+
+        assert_eq!(index.module, "libfoo.so-0F1E2D3C4B5A69788796A5B4C3D2E1F00");
+        assert_eq!(index.files, vec!["foo.c", "bar.h"]);
+        assert_eq!(
+            index.funcs,
+            vec![
+                (0x1000, "foo()".to_string(), 2),
+                (0x1020, "bar()".to_string(), 1)
+            ]
+        );
+        assert_eq!(index.publics, vec!["baz"]);
+        assert_eq!(index.cfi, vec![(0x1000, 2)]);
+
+        let mut index = Index::default();
+        visit_text(&mut NoCfi(&mut index), sym).unwrap();
+        assert_eq!(index.funcs.len(), 2);
+        assert!(index.cfi.is_empty());
+
+        assert!(visit_text(&mut index, "FUNC foo\n").is_err());
+    }
+
+    #[test]
+    fn test_visit_func() {
+        let mut lines = Lines::new();
+        lines.add_line(0x1000, 3, 0);
+        lines.add_line(0x1010, 4, 1);
+        lines.compute_len(0x1000, 0x20);
+        let inlines = vec![Inline {
+            depth: 0,
+            call_line: 4,
+            call_file: 1,
+            origin: 0,
+            ranges: vec![(0x1010, 8)],
+        }];
+        let func = FuncRecord {
+            address: 0x1000,
+            size: 0x20,
+            parameter_size: 0,
+            multiple: false,
+            name: "foo()".to_string(),
+        };
+
+        let mut index = Index::default();
+        visit_func(&mut index, func, &inlines, &lines);
+        assert_eq!(index.funcs, vec![(0x1000, "foo()".to_string(), 2)]);
+    }
+}
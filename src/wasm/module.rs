@@ -0,0 +1,102 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use failure::Fail;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use symbolic::debuginfo::Object;
+
+use crate::common::{self, Dumpable, Mergeable};
+use crate::linux::elf::{ElfInfo, Platform};
+use crate::mapping::PathMappings;
+use crate::options::Options;
+use crate::visitor::Visitor;
+
+/// A WebAssembly module: the DWARF data are in some custom sections
+/// and the addresses are offsets in the code section.
+#[derive(Debug)]
+pub struct WasmInfo {
+    elf: ElfInfo,
+}
+
+impl Display for WasmInfo {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.elf)
+    }
+}
+
+impl WasmInfo {
+    pub fn new(
+        buf: &[u8],
+        file_name: &str,
+        mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
+    ) -> common::Result<Self> {
+        let o = Object::parse(&buf).map_err(|e| e.compat())?;
+        if let Object::Wasm(_) = o {
+            Ok(Self {
+                elf: ElfInfo::from_object(&o, file_name, Platform::Wasm, mapping, options)?,
+            })
+        } else {
+            Err(format!("{} is not a wasm module", file_name).into())
+        }
+    }
+}
+
+impl Mergeable for WasmInfo {
+    fn merge(left: WasmInfo, right: WasmInfo) -> common::Result<WasmInfo> {
+        Ok(WasmInfo {
+            elf: ElfInfo::merge(left.elf, right.elf)?,
+        })
+    }
+}
+
+impl Dumpable for WasmInfo {
+    fn dump<W: Write>(&self, mut writer: W) -> common::Result<()> {
+        write!(writer, "{}", self.elf)?;
+        Ok(())
+    }
+
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        self.elf.visit(visitor)
+    }
+
+    fn get_debug_id(&self) -> &str {
+        &self.elf.get_debug_id()
+    }
+
+    fn get_name(&self) -> &str {
+        &self.elf.get_name()
+    }
+
+    fn get_source_files(&self) -> Vec<(String, PathBuf)> {
+        self.elf.get_source_files()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::common::FileType;
+
+    #[test]
+    fn test_empty_module() {
+        // A module with just the magic, the version and an empty custom section "foo"
+        let buf = b"\x00asm\x01\x00\x00\x00\x00\x0c\x03foo\x00\x00\x00\x00\x00\x00\x00\x00";
+        assert!(matches!(FileType::from_buf(buf), FileType::Wasm));
+
+        let wasm = WasmInfo::new(buf, "empty.wasm", None, Arc::default()).unwrap();
+        let mut output = Vec::new();
+        wasm.dump(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(
+            output.starts_with("MODULE wasm wasm32 000000000000000000000000000000000 empty.wasm\n")
+        );
+    }
+}
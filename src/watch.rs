@@ -0,0 +1,125 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --watch, the files are dumped in the store and then polled: a file is dumped again once
+// it has changed (e.g. relinked) and its modification time and size have been stable for one
+// interval, so a file which is still being written isn't dumped.
+// The config files are reloaded too (as in serve mode) when they change or on SIGHUP.
+
+use log::error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::common;
+use crate::dumper::{self, Config};
+use crate::reload;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// The modification time and the size of a file (None when it doesn't exist)
+type FileState = Option<(SystemTime, u64)>;
+
+fn get_state(path: &Path) -> FileState {
+    fs::metadata(path)
+        .and_then(|m| Ok((m.modified()?, m.len())))
+        .ok()
+}
+
+pub(crate) struct Watcher {
+    paths: Vec<PathBuf>,
+    states: Vec<FileState>,
+    // The files which changed but which may still be written
+    pending: Vec<bool>,
+}
+
+impl Watcher {
+    pub(crate) fn new<P: AsRef<Path>>(paths: &[P]) -> Self {
+        let paths: Vec<_> = paths.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        Self {
+            states: paths.iter().map(|p| get_state(p)).collect(),
+            pending: vec![false; paths.len()],
+            paths,
+        }
+    }
+
+    /// Get the indices of the files which changed and which are stable since the last poll
+    pub(crate) fn poll(&mut self) -> Vec<usize> {
+        let mut changed = Vec::new();
+        for (i, path) in self.paths.iter().enumerate() {
+            let state = get_state(path);
+            if state != self.states[i] {
+                self.states[i] = state;
+                self.pending[i] = true;
+            } else if self.pending[i] && state.is_some() {
+                self.pending[i] = false;
+                changed.push(i);
+            }
+        }
+        changed
+    }
+}
+
+fn dump(config: &Config, filename: &str) {
+    match dumper::single_file(config, filename) {
+        Ok(_) => println!("dumped {}", filename),
+        Err(e) => error!("{}: {}", filename, e),
+    }
+}
+
+/// Dump the files and dump them again when they change until the process is killed
+pub(crate) fn watch(config: &Config, filenames: &[&str]) -> common::Result<()> {
+    // With --symbol-server, the config files aren't used
+    if config.symbol_server.is_none() {
+        reload::start_config_watcher()?;
+    }
+
+    let mut watcher = Watcher::new(filenames);
+    for filename in filenames {
+        dump(config, filename);
+    }
+    println!("Watching {} files", filenames.len());
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        for i in watcher.poll() {
+            dump(config, filenames[i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_watcher() {
+        let tmp_dir = Builder::new().prefix("watch").tempdir().unwrap();
+        let foo = tmp_dir.path().join("libfoo.so");
+        let bar = tmp_dir.path().join("libbar.so");
+        fs::write(&foo, "foo").unwrap();
+
+        let mut watcher = Watcher::new(&[&foo, &bar]);
+        assert!(watcher.poll().is_empty());
+
+        // The files are reported once they're stable
+        fs::write(&foo, "foo foo").unwrap();
+        fs::write(&bar, "bar").unwrap();
+        assert!(watcher.poll().is_empty());
+        fs::write(&bar, "bar bar").unwrap();
+        assert_eq!(watcher.poll(), vec![0]);
+        assert_eq!(watcher.poll(), vec![1]);
+        assert!(watcher.poll().is_empty());
+
+        // A removed file isn't dumped
+        fs::remove_file(&foo).unwrap();
+        assert!(watcher.poll().is_empty());
+        assert!(watcher.poll().is_empty());
+    }
+}
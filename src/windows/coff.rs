@@ -0,0 +1,214 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use goblin::pe::{header, section_table::SectionTable, symbol::Symbol, Coff, PE};
+use log::info;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+use super::pdb::PDBSections;
+use crate::common::{self, Dumpable};
+use crate::visitor::{self, PublicRecord, Visitor};
+
+// The signature of the members of an import library
+const IMPORT_OBJECT_SIGNATURE: &[u8] = &[0, 0, 0xff, 0xff];
+
+fn get_rva(sym: &Symbol, sections: &[SectionTable]) -> Option<u32> {
+    if !sym.is_function_definition() {
+        return None;
+    }
+
+    // section_number is 1-based
+    let section = sections.get(sym.section_number as usize - 1)?;
+    if PDBSections::has_code(section.characteristics) {
+        Some(section.virtual_address + sym.value)
+    } else {
+        None
+    }
+}
+
+/// Get the functions from the COFF symbol table embedded in the PE file (if any).
+/// Such a table is usually only present in the binaries built with mingw.
+pub(crate) fn get_coff_symbols(pe_buf: &[u8]) -> Vec<(u32, String)> {
+    let pe = match PE::parse(pe_buf) {
+        Ok(pe) => pe,
+        _ => return Vec::new(),
+    };
+
+    let coff = pe.header.coff_header;
+    if coff.pointer_to_symbol_table == 0 || coff.number_of_symbol_table == 0 {
+        return Vec::new();
+    }
+
+    let (symbols, strings) = match (coff.symbols(pe_buf), coff.strings(pe_buf)) {
+        (Ok(symbols), Ok(strings)) => (symbols, strings),
+        _ => return Vec::new(),
+    };
+
+    let syms: Vec<_> = symbols
+        .iter()
+        .filter_map(|(_, name, sym)| {
+            let rva = get_rva(&sym, &pe.sections)?;
+            let name = match name {
+                Some(name) => name.to_string(),
+                _ => sym.name(&strings).ok()?.to_string(),
+            };
+            if name.is_empty() {
+                None
+            } else {
+                Some((rva, name))
+            }
+        })
+        .collect();
+
+    info!("Found {} functions in the COFF symbol table", syms.len());
+
+    syms
+}
+
+fn get_cpu(machine: u16) -> Option<&'static str> {
+    match machine {
+        header::COFF_MACHINE_X86 => Some("x86"),
+        header::COFF_MACHINE_X86_64 => Some("x86_64"),
+        header::COFF_MACHINE_ARM64 => Some("arm64"),
+        _ => None,
+    }
+}
+
+/// A COFF object (e.g. a member of a static library): there are no virtual addresses
+/// so the address of a function is its offset in its section.
+pub(crate) struct CoffObjectInfo {
+    name: String,
+    cpu: &'static str,
+    debug_id: String,
+    symbols: Vec<(u32, String)>,
+}
+
+impl Display for CoffObjectInfo {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "MODULE windows {} {} {}",
+            self.cpu, self.debug_id, self.name
+        )?;
+
+        for (rva, name) in self.symbols.iter() {
+            writeln!(f, "PUBLIC {:x} 0 {}", rva, name)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CoffObjectInfo {
+    /// Check if the file is a COFF object (the members of an import library aren't)
+    pub(crate) fn is_coff_object(buf: &[u8]) -> bool {
+        !buf.starts_with(IMPORT_OBJECT_SIGNATURE)
+            && Coff::parse(buf).map_or(false, |coff| get_cpu(coff.header.machine).is_some())
+    }
+
+    pub(crate) fn new(buf: &[u8], name: &str) -> common::Result<Self> {
+        let coff = Coff::parse(buf)?;
+        let cpu = get_cpu(coff.header.machine).ok_or("Unsupported COFF machine")?;
+
+        let mut symbols: Vec<_> = coff
+            .symbols
+            .iter()
+            .filter_map(|(_, name, sym)| {
+                let rva = get_rva(&sym, &coff.sections)?;
+                let name = match name {
+                    Some(name) => name,
+                    _ => sym.name(&coff.strings).ok()?,
+                };
+                if name.is_empty() {
+                    None
+                } else {
+                    Some((rva, name.to_string()))
+                }
+            })
+            .collect();
+        symbols.sort();
+
+        Ok(Self {
+            name: name.to_string(),
+            cpu,
+            debug_id: "0".repeat(33),
+            symbols,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+impl Dumpable for CoffObjectInfo {
+    fn dump<W: Write>(&self, mut writer: W) -> common::Result<()> {
+        write!(writer, "{}", self)?;
+        Ok(())
+    }
+
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        visitor::visit_module(visitor, "windows", self.cpu, &self.debug_id, &self.name);
+        for (rva, name) in self.symbols.iter() {
+            visitor.public(&PublicRecord {
+                address: u64::from(*rva),
+                parameter_size: 0,
+                multiple: false,
+                name: name.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn get_debug_id(&self) -> &str {
+        &self.debug_id
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use goblin::pe::symbol::{IMAGE_SYM_CLASS_EXTERNAL, IMAGE_SYM_CLASS_STATIC};
+
+    use super::*;
+
+    #[test]
+    fn test_get_rva() {
+        let text = SectionTable {
+            virtual_address: 0x1000,
+            characteristics: 0x6000_0020,
+            ..Default::default()
+        };
+        let data = SectionTable {
+            virtual_address: 0x3000,
+            characteristics: 0xc000_0040,
+            ..Default::default()
+        };
+        let sections = vec![text, data];
+
+        let mut sym = Symbol {
+            value: 0x123,
+            section_number: 1,
+            typ: 0x20,
+            storage_class: IMAGE_SYM_CLASS_EXTERNAL,
+            ..Default::default()
+        };
+        assert_eq!(get_rva(&sym, &sections), Some(0x1123));
+
+        // Not in a code section
+        sym.section_number = 2;
+        assert_eq!(get_rva(&sym, &sections), None);
+
+        // Not a function
+        sym.section_number = 1;
+        sym.storage_class = IMAGE_SYM_CLASS_STATIC;
+        assert_eq!(get_rva(&sym, &sections), None);
+    }
+}
@@ -0,0 +1,661 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A hybrid PE contains some ARM64 code next to some emulated code:
+//  - an ARM64EC (resp. ARM64X) file has some x64 code and its machine is AMD64 (resp. ARM64),
+//  - a CHPE (compiled hybrid PE) file has some x86 code and its machine is i386.
+// The hybrid metadata (pointed by the load configuration) contains a code map giving the
+// architecture of each code range. The functions of each architecture are in their own
+// exception table: the one of the data directory (x64 for ARM64EC so the file looks like
+// an x64 one, ARM64 for ARM64X) and the "extra" one in the metadata.
+
+use goblin::pe::{
+    data_directories::DataDirectory,
+    exception::{ExceptionData, StackFrameOffset, UnwindOperation},
+    header::{COFF_MACHINE_ARM64, COFF_MACHINE_X86, COFF_MACHINE_X86_64},
+    options::ParseOptions,
+    utils::find_offset,
+    PE,
+};
+use log::warn;
+use std::convert::TryInto;
+use std::fmt::Write;
+
+// Offset of CHPEMetadataPointer in IMAGE_LOAD_CONFIG_DIRECTORY32/64
+const CHPE_METADATA_32: usize = 124;
+const CHPE_METADATA_64: usize = 200;
+
+const ARM64_X29: u8 = 29;
+const ARM64_LR: u8 = 30;
+// The d8-d15 registers aren't used to unwind but they're needed to follow a save_next
+const ARM64_D0: u8 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum HybridKind {
+    Arm64EC,
+    Arm64X,
+    X86,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CodeArch {
+    Arm64,
+    X64,
+    X86,
+}
+
+#[derive(Debug)]
+struct CodeRange {
+    start: u32,
+    end: u32,
+    arch: CodeArch,
+}
+
+/// The state of a function after its prolog: the size of the frame and the saved registers
+/// with their offsets from the cfa
+#[derive(Debug, Default, PartialEq)]
+struct Frame {
+    size: u32,
+    saved: Vec<(&'static str, u32)>,
+}
+
+struct Function {
+    begin: u32,
+    end: u32,
+    arch: CodeArch,
+    frame: Option<Frame>,
+}
+
+pub(super) struct HybridPE<'a> {
+    pub kind: HybridKind,
+    pe: PE<'a>,
+    buf: &'a [u8],
+    file_alignment: u32,
+    ranges: Vec<CodeRange>,
+    tables: Vec<DataDirectory>,
+}
+
+fn get_arm64_name(reg: u8) -> Option<&'static str> {
+    const NAMES: [&str; 11] = [
+        "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28", "x29",
+    ];
+    match reg {
+        19..=ARM64_X29 => Some(NAMES[(reg - 19) as usize]),
+        ARM64_LR => Some(".ra"),
+        _ => None,
+    }
+}
+
+/// Get the frame described by the packed unwind data of an ARM64 function
+/// (the prolog is the canonical one so only the sizes and the counts are stored)
+fn unpack_arm64(data: u32) -> (u32, Frame) {
+    let len = ((data >> 2) & 0x7ff) * 4;
+    let reg_f = (data >> 13) & 0x7;
+    let reg_i = (data >> 16) & 0xf;
+    let h = (data >> 20) & 0x1;
+    let cr = (data >> 21) & 0x3;
+    let size = ((data >> 23) & 0x1ff) * 16;
+
+    let int_size = 8 * reg_i + if cr == 1 { 8 } else { 0 };
+    let fp_size = if reg_f == 0 { 0 } else { 8 * (reg_f + 1) };
+    let save_size = (int_size + fp_size + 64 * h + 15) & !15;
+
+    // x19, x20, ... and lr (when unchained) are saved at the top of the frame
+    let mut saved: Vec<_> = (0..reg_i)
+        .filter_map(|i| Some((get_arm64_name(19 + i as u8)?, save_size - 8 * i)))
+        .collect();
+    match cr {
+        1 => saved.push((".ra", save_size - 8 * reg_i)),
+        // Chained: x29 and lr are saved at the bottom of the frame
+        2 | 3 => {
+            saved.push(("x29", size));
+            saved.push((".ra", size - 8));
+        }
+        _ => {}
+    }
+
+    (len, Frame { size, saved })
+}
+
+#[derive(Debug)]
+enum Arm64Code {
+    Alloc(u32),
+    // A store of one or two registers at sp + offset (sp is decremented before when pre-indexed)
+    Save {
+        first: u8,
+        second: Option<u8>,
+        offset: u32,
+        pre: bool,
+    },
+    SaveNext,
+    Nop,
+    Unsupported(u8),
+}
+
+/// Decode the prolog unwind codes of an ARM64 function (they're in the reverse order of the prolog)
+fn decode_arm64_codes(codes: &[u8]) -> Vec<Arm64Code> {
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while let Some(&b) = codes.get(i) {
+        let b1 = u32::from(codes.get(i + 1).copied().unwrap_or(0));
+        let (code, size) = match b {
+            0x00..=0x1f => (Arm64Code::Alloc(u32::from(b & 0x1f) * 16), 1),
+            0x20..=0x3f => (save(19, Some(20), u32::from(b & 0x1f) * 8, true), 1),
+            0x40..=0x7f => (save(29, Some(30), u32::from(b & 0x3f) * 8, false), 1),
+            0x80..=0xbf => (save(29, Some(30), (u32::from(b & 0x3f) + 1) * 8, true), 1),
+            0xc0..=0xc7 => (Arm64Code::Alloc(((u32::from(b & 0x7) << 8) | b1) * 16), 2),
+            0xc8..=0xd3 => {
+                let x = (((u32::from(b) & 0x3) << 2) | (b1 >> 6)) as u8;
+                let z = b1 & 0x3f;
+                let code = match b & 0xfc {
+                    0xc8 => save(19 + x, Some(20 + x), z * 8, false),
+                    0xcc => save(19 + x, Some(20 + x), (z + 1) * 8, true),
+                    _ => save(19 + x, None, z * 8, false),
+                };
+                (code, 2)
+            }
+            0xd4..=0xd5 => {
+                let x = (((u32::from(b) & 0x1) << 3) | (b1 >> 5)) as u8;
+                (save(19 + x, None, ((b1 & 0x1f) + 1) * 8, true), 2)
+            }
+            0xd6..=0xdd => {
+                let x = (((u32::from(b) & 0x1) << 2) | (b1 >> 6)) as u8;
+                let z = b1 & 0x3f;
+                let code = match b & 0xfe {
+                    0xd6 => save(19 + 2 * x, Some(ARM64_LR), z * 8, false),
+                    0xd8 => save(ARM64_D0 + 8 + x, Some(ARM64_D0 + 9 + x), z * 8, false),
+                    0xda => save(ARM64_D0 + 8 + x, Some(ARM64_D0 + 9 + x), (z + 1) * 8, true),
+                    _ => save(ARM64_D0 + 8 + x, None, z * 8, false),
+                };
+                (code, 2)
+            }
+            0xde => {
+                let x = (b1 >> 5) as u8;
+                (save(ARM64_D0 + 8 + x, None, ((b1 & 0x1f) + 1) * 8, true), 2)
+            }
+            0xe0 => {
+                let b2 = u32::from(codes.get(i + 2).copied().unwrap_or(0));
+                let b3 = u32::from(codes.get(i + 3).copied().unwrap_or(0));
+                (Arm64Code::Alloc(((b1 << 16) | (b2 << 8) | b3) * 16), 4)
+            }
+            // end and end_c
+            0xe4 | 0xe5 => break,
+            // set_fp, nop and pac_sign_lr
+            0xe1 | 0xe3 | 0xfc => (Arm64Code::Nop, 1),
+            // add_fp
+            0xe2 => (Arm64Code::Nop, 2),
+            0xe6 => (Arm64Code::SaveNext, 1),
+            _ => (Arm64Code::Unsupported(b), 1),
+        };
+        decoded.push(code);
+        i += size;
+    }
+
+    decoded
+}
+
+fn save(first: u8, second: Option<u8>, offset: u32, pre: bool) -> Arm64Code {
+    Arm64Code::Save {
+        first,
+        second,
+        offset,
+        pre,
+    }
+}
+
+/// Record a register stored at sp + offset
+fn store(frame: &mut Frame, reg: u8, offset: u32) -> Option<()> {
+    if let Some(name) = get_arm64_name(reg) {
+        frame.saved.push((name, frame.size.checked_sub(offset)?));
+    }
+    Some(())
+}
+
+/// Run the prolog described by the unwind codes to get the frame after it
+fn run_arm64_codes(codes: &[Arm64Code]) -> Option<Frame> {
+    let mut frame = Frame::default();
+    let mut last = None;
+
+    for code in codes.iter().rev() {
+        match *code {
+            Arm64Code::Alloc(size) => frame.size += size,
+            Arm64Code::Save {
+                first,
+                second,
+                offset,
+                pre,
+            } => {
+                let offset = if pre {
+                    frame.size += offset;
+                    0
+                } else {
+                    offset
+                };
+                store(&mut frame, first, offset)?;
+                if let Some(second) = second {
+                    store(&mut frame, second, offset + 8)?;
+                    last = Some((first, second, offset));
+                }
+            }
+            Arm64Code::SaveNext => {
+                let (_, second, offset) = last?;
+                let next = (second + 1, second + 2, offset + 16);
+                store(&mut frame, next.0, next.2)?;
+                store(&mut frame, next.1, next.2 + 8)?;
+                last = Some(next);
+            }
+            Arm64Code::Nop => {}
+            Arm64Code::Unsupported(code) => {
+                warn!("Unsupported ARM64 unwind code {:#x}", code);
+                return None;
+            }
+        }
+    }
+
+    Some(frame)
+}
+
+impl<'a> HybridPE<'a> {
+    /// Get the hybrid PE when the file has some hybrid metadata
+    pub fn new(buf: &'a [u8]) -> Option<Self> {
+        let pe = PE::parse(buf).ok()?;
+        let kind = match pe.header.coff_header.machine {
+            COFF_MACHINE_X86_64 => HybridKind::Arm64EC,
+            COFF_MACHINE_ARM64 => HybridKind::Arm64X,
+            COFF_MACHINE_X86 => HybridKind::X86,
+            _ => return None,
+        };
+        let header = pe.header.optional_header?;
+        let load_config = (*header.data_directories.get_load_config_table())?;
+        let exception = *header.data_directories.get_exception_table();
+
+        let mut hybrid = HybridPE {
+            kind,
+            buf,
+            file_alignment: header.windows_fields.file_alignment,
+            pe,
+            ranges: Vec::new(),
+            tables: exception.into_iter().collect(),
+        };
+
+        // The metadata pointer is a virtual address
+        let config_size = hybrid.read_u32(load_config.virtual_address, 0)? as usize;
+        let metadata = if hybrid.pe.is_64 {
+            if config_size < CHPE_METADATA_64 + 8 {
+                return None;
+            }
+            hybrid.read_u64(load_config.virtual_address, CHPE_METADATA_64)?
+        } else {
+            if config_size < CHPE_METADATA_32 + 4 {
+                return None;
+            }
+            u64::from(hybrid.read_u32(load_config.virtual_address, CHPE_METADATA_32)?)
+        };
+        let metadata = metadata.checked_sub(hybrid.pe.image_base as u64)? as u32;
+        if metadata == 0 {
+            return None;
+        }
+
+        let code_map = hybrid.read_u32(metadata, 4)?;
+        let count = hybrid.read_u32(metadata, 8)?;
+        for i in 0..count {
+            let start = hybrid.read_u32(code_map, 8 * i as usize)?;
+            let len = hybrid.read_u32(code_map, 8 * i as usize + 4)?;
+            let (start, arch) = if kind == HybridKind::X86 {
+                // The low bit is set for the native code
+                let arch = if start & 1 == 1 {
+                    CodeArch::Arm64
+                } else {
+                    CodeArch::X86
+                };
+                (start & !1, arch)
+            } else {
+                // 0: ARM64, 1: ARM64EC and 2: x64
+                let arch = if start & 3 == 2 {
+                    CodeArch::X64
+                } else {
+                    CodeArch::Arm64
+                };
+                (start & !3, arch)
+            };
+            hybrid.ranges.push(CodeRange {
+                start,
+                end: start.checked_add(len)?,
+                arch,
+            });
+        }
+
+        if kind != HybridKind::X86 {
+            // ExtraRFETable and ExtraRFETableSize
+            let virtual_address = hybrid.read_u32(metadata, 64).unwrap_or(0);
+            let size = hybrid.read_u32(metadata, 68).unwrap_or(0);
+            if virtual_address != 0 && size != 0 {
+                hybrid.tables.push(DataDirectory {
+                    virtual_address,
+                    size,
+                });
+            }
+        }
+
+        Some(hybrid)
+    }
+
+    fn get_data(&self, rva: u32, offset: usize, len: usize) -> Option<&'a [u8]> {
+        let start = find_offset(
+            rva as usize,
+            &self.pe.sections,
+            self.file_alignment,
+            &ParseOptions::default(),
+        )?
+        .checked_add(offset)?;
+        self.buf.get(start..start.checked_add(len)?)
+    }
+
+    fn read_u32(&self, rva: u32, offset: usize) -> Option<u32> {
+        let data = self.get_data(rva, offset, 4)?;
+        Some(u32::from_le_bytes(data.try_into().ok()?))
+    }
+
+    fn read_u64(&self, rva: u32, offset: usize) -> Option<u64> {
+        let data = self.get_data(rva, offset, 8)?;
+        Some(u64::from_le_bytes(data.try_into().ok()?))
+    }
+
+    fn get_arch(&self, rva: u32) -> Option<CodeArch> {
+        self.ranges
+            .iter()
+            .find(|r| r.start <= rva && rva < r.end)
+            .map(|r| r.arch)
+    }
+
+    /// Get the frame of an ARM64 function from its .xdata record
+    fn get_arm64_xdata(&self, rva: u32) -> Option<(u32, Option<Frame>)> {
+        let header = self.read_u32(rva, 0)?;
+        let len = (header & 0x3ffff) * 4;
+        let single_epilog = (header >> 21) & 1 == 1;
+        let mut epilog_count = (header >> 22) & 0x1f;
+        let mut code_words = header >> 27;
+        let mut offset = 4;
+        if epilog_count == 0 && code_words == 0 {
+            let extended = self.read_u32(rva, offset)?;
+            epilog_count = extended & 0xffff;
+            code_words = (extended >> 16) & 0xff;
+            offset += 4;
+        }
+        if !single_epilog {
+            offset += 4 * epilog_count as usize;
+        }
+
+        let codes = self.get_data(rva, offset, 4 * code_words as usize)?;
+        Some((len, run_arm64_codes(&decode_arm64_codes(codes))))
+    }
+
+    fn get_arm64_functions(&self, table: &DataDirectory, functions: &mut Vec<Function>) {
+        for i in 0..(table.size / 8) as usize {
+            let (begin, data) = match (
+                self.read_u32(table.virtual_address, 8 * i),
+                self.read_u32(table.virtual_address, 8 * i + 4),
+            ) {
+                (Some(begin), Some(data)) => (begin, data),
+                _ => return,
+            };
+            let (len, frame) = match data & 3 {
+                0 => match self.get_arm64_xdata(data) {
+                    Some(function) => function,
+                    _ => continue,
+                },
+                // Packed unwind data (with or without a prolog)
+                1 | 2 => {
+                    let (len, frame) = unpack_arm64(data);
+                    (len, Some(frame))
+                }
+                _ => continue,
+            };
+            functions.push(Function {
+                begin,
+                end: begin + len,
+                arch: CodeArch::Arm64,
+                frame,
+            });
+        }
+    }
+
+    fn get_x64_functions(&self, table: &DataDirectory, functions: &mut Vec<Function>) {
+        let data =
+            match ExceptionData::parse(self.buf, *table, &self.pe.sections, self.file_alignment) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("Invalid x64 exception table: {}", e);
+                    return;
+                }
+            };
+
+        for function in data.functions().filter_map(|f| f.ok()) {
+            // The prolog of a chained unwind info is run before
+            let mut infos = Vec::new();
+            let mut next = Some(function);
+            while let Some(info) =
+                next.and_then(|f| data.get_unwind_info(f, &self.pe.sections).ok())
+            {
+                next = info.chained_info;
+                infos.push(info);
+            }
+
+            let mut codes = Vec::new();
+            for info in infos.iter().rev() {
+                let mut info_codes: Vec<_> = info.unwind_codes().filter_map(|c| c.ok()).collect();
+                info_codes.reverse();
+                codes.extend(info_codes);
+            }
+
+            // The return address is on the stack
+            let mut frame = Frame {
+                size: 8,
+                saved: Vec::new(),
+            };
+            let mut moved = Vec::new();
+            for code in codes {
+                match code.operation {
+                    UnwindOperation::PushNonVolatile(reg) => {
+                        frame.size += 8;
+                        frame.saved.push((reg.name(), frame.size));
+                    }
+                    UnwindOperation::Alloc(size) => frame.size += size,
+                    UnwindOperation::SaveNonVolatile(reg, StackFrameOffset::RSP(offset)) => {
+                        moved.push((reg, offset))
+                    }
+                    UnwindOperation::PushMachineFrame(is_error) => {
+                        frame.size += if is_error { 48 } else { 40 };
+                        let top = if is_error { 8 } else { 0 };
+                        frame.saved =
+                            vec![("$rsp", frame.size - top - 24), (".ra", frame.size - top)];
+                    }
+                    _ => {}
+                }
+            }
+            // The registers saved with a mov are relative to rsp after the prolog
+            for (reg, offset) in moved {
+                if let Some(offset) = frame.size.checked_sub(offset) {
+                    frame.saved.push((reg.name(), offset));
+                }
+            }
+
+            functions.push(Function {
+                begin: function.begin_address,
+                end: function.end_address,
+                arch: CodeArch::X64,
+                frame: Some(frame),
+            });
+        }
+    }
+
+    fn get_all_functions(&self) -> Vec<Function> {
+        let mut functions = Vec::new();
+        for table in self.tables.iter() {
+            // The format of the entries depends on the architecture of the code they describe
+            let first = self.read_u32(table.virtual_address, 0);
+            match first.and_then(|rva| self.get_arch(rva)) {
+                Some(CodeArch::Arm64) => self.get_arm64_functions(table, &mut functions),
+                Some(CodeArch::X64) => self.get_x64_functions(table, &mut functions),
+                _ => {}
+            }
+        }
+
+        // Skip the functions which aren't in a range of their architecture
+        functions.retain(|f| f.end > f.begin && self.get_arch(f.begin) == Some(f.arch));
+        functions.sort_by_key(|f| f.begin);
+        functions
+    }
+
+    /// Get the start and the end of the functions in the exception tables
+    pub fn get_functions(&self) -> Vec<(u32, u32)> {
+        self.get_all_functions()
+            .iter()
+            .map(|f| (f.begin, f.end))
+            .collect()
+    }
+
+    /// Get the STACK CFI records of the ARM64 and x64 functions
+    pub fn get_stack_info(&self) -> String {
+        let mut stack = String::new();
+        for function in self.get_all_functions() {
+            let frame = match function.frame {
+                Some(frame) => frame,
+                _ => continue,
+            };
+            let (sp, init) = match function.arch {
+                CodeArch::X64 => ("$rsp", ".cfa: $rsp 8 + .ra: .cfa 8 - ^"),
+                _ => ("sp", ".cfa: sp 0 + .ra: x30"),
+            };
+            let _ = writeln!(
+                stack,
+                "STACK CFI INIT {:x} {:x} {}",
+                function.begin,
+                function.end - function.begin,
+                init
+            );
+            let _ = write!(
+                stack,
+                "STACK CFI {:x} .cfa: {} {} +",
+                function.begin, sp, frame.size
+            );
+            for (name, offset) in frame.saved {
+                let _ = write!(stack, " {}: .cfa {} - ^", name, offset);
+            }
+            stack.push('\n');
+        }
+
+        stack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use symbolic::debuginfo::pe::PeObject;
+
+    use super::*;
+    use crate::common::Dumpable;
+    use crate::windows::pdb::PEInfo;
+
+    const IMAGE_BASE: u64 = 0x1_8000_0000;
+
+    fn put(buf: &mut [u8], offset: usize, values: &[u32]) {
+        for (i, value) in values.iter().enumerate() {
+            let offset = offset + 4 * i;
+            buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    /// An ARM64EC dll with two ARM64 functions (with packed and unpacked unwind data)
+    /// and a x64 one: all the data are in the .text section at 0x1000
+    fn get_arm64ec_dll() -> Vec<u8> {
+        let mut buf = vec![0; 0x1200];
+        let text = |rva: usize| rva - 0x1000 + 0x200;
+
+        buf[..2].copy_from_slice(b"MZ");
+        put(&mut buf, 0x3c, &[0x40]);
+        buf[0x40..0x44].copy_from_slice(b"PE\0\0");
+        // Machine (AMD64), one section, the size of the optional header and the characteristics
+        put(&mut buf, 0x44, &[0x0001_8664, 0, 0, 0, 0x2022_00f0]);
+
+        let opt = 0x58;
+        put(&mut buf, opt, &[0x020b]);
+        buf[opt + 24..opt + 32].copy_from_slice(&IMAGE_BASE.to_le_bytes());
+        // Section and file alignments
+        put(&mut buf, opt + 32, &[0x1000, 0x200]);
+        put(&mut buf, opt + 40, &[0, 0, 0x6, 0, 0x2000, 0x200, 0, 0x2]);
+        put(&mut buf, opt + 108, &[16]);
+        // The exception table and the load configuration
+        put(&mut buf, opt + 112 + 3 * 8, &[0x1400, 12]);
+        put(&mut buf, opt + 112 + 10 * 8, &[0x1700, 0x140]);
+
+        buf[0x148..0x14d].copy_from_slice(b".text");
+        put(&mut buf, 0x150, &[0x1000, 0x1000, 0x1000, 0x200]);
+        put(&mut buf, 0x164, &[0x6000_0020]);
+
+        // The x64 function and its unwind info: push rbx; sub rsp, 32
+        put(&mut buf, text(0x1400), &[0x1100, 0x1130, 0x1500]);
+        buf[text(0x1500)..text(0x1508)]
+            .copy_from_slice(&[0x01, 0x08, 0x02, 0x00, 0x08, 0x32, 0x01, 0x30]);
+
+        // The ARM64 functions: the first one saves x19, x20, fp and lr in a frame of 32 bytes
+        let packed = 1 | (16 << 2) | (2 << 16) | (3 << 21) | (2 << 23);
+        put(&mut buf, text(0x1420), &[0x1000, packed, 0x1040, 0x1600]);
+        // stp x29, lr, [sp, #-32]!; stp x19, x20, [sp, #16]; mov x29, sp
+        put(&mut buf, text(0x1600), &[8 | (1 << 21) | (2 << 27)]);
+        buf[text(0x1604)..text(0x160c)]
+            .copy_from_slice(&[0xe1, 0xc8, 0x02, 0x83, 0xe4, 0xe3, 0xe3, 0xe3]);
+
+        // The load configuration and the hybrid metadata with the code map and the extra table
+        put(&mut buf, text(0x1700), &[0x140]);
+        buf[text(0x1700) + CHPE_METADATA_64..text(0x1700) + CHPE_METADATA_64 + 8]
+            .copy_from_slice(&(IMAGE_BASE + 0x1800).to_le_bytes());
+        put(&mut buf, text(0x1800), &[1, 0x1900, 2]);
+        put(&mut buf, text(0x1800) + 64, &[0x1420, 16]);
+        put(&mut buf, text(0x1900), &[0x1000, 0x100, 0x1100 | 2, 0x100]);
+
+        buf
+    }
+
+    #[test]
+    fn test_arm64ec() {
+        let buf = get_arm64ec_dll();
+        let hybrid = HybridPE::new(&buf).unwrap();
+        assert_eq!(hybrid.kind, HybridKind::Arm64EC);
+        assert_eq!(
+            hybrid.get_functions(),
+            vec![(0x1000, 0x1040), (0x1040, 0x1060), (0x1100, 0x1130)]
+        );
+
+        let pe = PeObject::parse(&buf).unwrap();
+        let info = PEInfo::new("hybrid.dll", pe, Vec::new(), Default::default()).unwrap();
+        let mut output = Vec::new();
+        info.dump(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.starts_with("MODULE windows arm64 "));
+        let stack: Vec<_> = output.lines().filter(|l| l.starts_with("STACK")).collect();
+        assert_eq!(
+            stack,
+            vec![
+                "STACK CFI INIT 1000 40 .cfa: sp 0 + .ra: x30",
+                "STACK CFI 1000 .cfa: sp 32 + x19: .cfa 16 - ^ x20: .cfa 8 - ^ x29: .cfa 32 - ^ .ra: .cfa 24 - ^",
+                "STACK CFI INIT 1040 20 .cfa: sp 0 + .ra: x30",
+                "STACK CFI 1040 .cfa: sp 32 + x29: .cfa 32 - ^ .ra: .cfa 24 - ^ x19: .cfa 16 - ^ x20: .cfa 8 - ^",
+                "STACK CFI INIT 1100 30 .cfa: $rsp 8 + .ra: .cfa 8 - ^",
+                "STACK CFI 1100 .cfa: $rsp 48 + $rbx: .cfa 16 - ^",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_not_hybrid() {
+        let mut buf = get_arm64ec_dll();
+        // No metadata pointer in the load configuration
+        put(&mut buf, 0x900 + CHPE_METADATA_64, &[0, 0]);
+        assert!(HybridPE::new(&buf).is_none());
+    }
+}
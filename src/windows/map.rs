@@ -0,0 +1,157 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use goblin::pe::PE;
+use log::{info, warn};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+use super::pdb::PDBSections;
+
+/// Section rva and whether the section contains some code
+type Sections = Vec<(u32, bool)>;
+
+/// Parse the "Publics by Value" part of a linker map file.
+/// Such a file is generated by Delphi (with -GD) and by the MSVC linker (with /MAP),
+/// and its lines look like:
+///   0001:0004D2C4       Unit1.TForm1.Button1Click
+/// where 0001 is the section number (1-based) and 0004D2C4 is the offset in the section.
+fn parse(content: &str, sections: &[(u32, bool)]) -> Vec<(u32, String)> {
+    lazy_static! {
+        static ref PUBLIC: Regex =
+            Regex::new(r"^\s*([0-9A-Fa-f]{4}):([0-9A-Fa-f]{8})\s+(\S+)").unwrap();
+    }
+
+    let mut syms = Vec::new();
+    let mut lines = content.lines();
+
+    if lines.find(|l| l.contains("Publics by Value")).is_none() {
+        return syms;
+    }
+
+    for line in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let caps = match PUBLIC.captures(line) {
+            Some(caps) => caps,
+            // We reached the end of the section
+            _ => break,
+        };
+
+        let section = usize::from_str_radix(&caps[1], 16).unwrap();
+        let offset = u32::from_str_radix(&caps[2], 16).unwrap();
+        if let Some((rva, true)) = section.checked_sub(1).and_then(|s| sections.get(s)) {
+            syms.push((rva + offset, caps[3].to_string()));
+        }
+    }
+
+    syms
+}
+
+fn get_sections(pe_buf: &[u8]) -> Option<Sections> {
+    let pe = PE::parse(pe_buf).ok()?;
+    Some(
+        pe.sections
+            .iter()
+            .map(|s| (s.virtual_address, PDBSections::has_code(s.characteristics)))
+            .collect(),
+    )
+}
+
+/// Get the public symbols from the map file next to the PE one (if any)
+pub(crate) fn get_map_symbols(pe_path: &Path, pe_buf: &[u8]) -> Vec<(u32, String)> {
+    let map_path = pe_path.with_extension("map");
+    if !map_path.is_file() {
+        return Vec::new();
+    }
+
+    let sections = match get_sections(pe_buf) {
+        Some(sections) => sections,
+        _ => return Vec::new(),
+    };
+
+    match fs::read(&map_path) {
+        Ok(buf) => {
+            // Old map files aren't necessarly utf-8
+            let content = String::from_utf8_lossy(&buf);
+            let syms = parse(&content, &sections);
+            info!(
+                "Found {} public symbols in {}",
+                syms.len(),
+                map_path.to_str().unwrap()
+            );
+            syms
+        }
+        Err(e) => {
+            warn!(
+                "Unable to read the map file {}: {}",
+                map_path.to_str().unwrap(),
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_delphi_map() {
+        let content = r#"
+ Start         Length     Name                   Class
+ 0001:00401000 000F1234H .text                   CODE
+ 0002:004F3000 00001234H .data                   DATA
+
+  Address             Publics by Name
+
+ 0001:00000010       System.@HandleFinally
+
+  Address             Publics by Value
+
+ 0001:00000010       System.@HandleFinally
+ 0001:0004D2C4       Unit1.TForm1.Button1Click
+ 0002:00000020       Unit1.Form1
+
+Line numbers for Unit1(Unit1.pas) segment .text
+
+    29 0001:0004D2C4    30 0001:0004D2D4
+"#;
+        let sections = vec![(0x1000, true), (0xf3000, false)];
+        let syms = parse(content, &sections);
+
+        assert_eq!(
+            syms,
+            vec![
+                (0x1010, "System.@HandleFinally".to_string()),
+                (0x4e2c4, "Unit1.TForm1.Button1Click".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_msvc_map() {
+        let content = r#"
+  Address         Publics by Value              Rva+Base       Lib:Object
+
+ 0001:00000000       _main                      00401000 f   main.obj
+ 0001:00000020       _foo                       00401020 f   main.obj
+
+ entry point at        0001:00000000
+"#;
+        let sections = vec![(0x1000, true)];
+        let syms = parse(content, &sections);
+
+        assert_eq!(
+            syms,
+            vec![(0x1000, "_main".to_string()), (0x1020, "_foo".to_string())]
+        );
+    }
+}
@@ -3,9 +3,17 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+pub mod coff;
+mod hybrid;
 mod lines;
+pub mod map;
+mod modules;
 pub mod pdb;
 mod source;
+pub(crate) mod srcsrv;
+pub(crate) mod stack_win;
 mod symbol;
+pub(crate) mod type_server;
 mod types;
 pub mod utils;
+mod version;
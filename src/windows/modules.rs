@@ -0,0 +1,99 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The modules (the compilands) of a PDB are read in parallel but the readers of the pdb crate
+// can't be shared between threads: each thread opens the PDB on its own and reads the streams
+// of the modules it gets.
+// The PDB is in a buffer or it's a RemoteFile which is read while it's downloaded.
+
+use pdb::{AddressMap, Module, Result, StringTable, PDB};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+use crate::parallel;
+use crate::pipeline::{RemoteFile, RemoteReader};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum PdbSource<'s> {
+    Buf(&'s [u8]),
+    Remote(&'s RemoteFile),
+}
+
+impl<'s> PdbSource<'s> {
+    /// Open the PDB with a reader for this thread
+    pub(super) fn open(self) -> Result<PDB<'s, PdbReader<'s>>> {
+        PDB::open(match self {
+            Self::Buf(buf) => PdbReader::Buf(Cursor::new(buf)),
+            Self::Remote(file) => PdbReader::Remote(file.reader()),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum PdbReader<'s> {
+    Buf(Cursor<&'s [u8]>),
+    Remote(RemoteReader<'s>),
+}
+
+impl Read for PdbReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Buf(cursor) => cursor.read(buf),
+            Self::Remote(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for PdbReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Buf(cursor) => cursor.seek(pos),
+            Self::Remote(reader) => reader.seek(pos),
+        }
+    }
+}
+
+pub(super) struct ModuleReader<'s> {
+    pub pdb: PDB<'s, PdbReader<'s>>,
+    pub address_map: AddressMap<'s>,
+    pub string_table: Option<StringTable<'s>>,
+}
+
+impl<'s> ModuleReader<'s> {
+    fn open(source: PdbSource<'s>) -> Result<Self> {
+        let mut pdb = source.open()?;
+        let address_map = pdb.address_map()?;
+        // The string table may be empty: not a problem
+        let string_table = pdb.string_table().ok();
+
+        Ok(Self {
+            pdb,
+            address_map,
+            string_table,
+        })
+    }
+}
+
+/// Apply f to the modules in parallel and get the results in the order of the modules
+pub(super) fn map_modules<'s, R, F>(
+    num_threads: usize,
+    source: PdbSource<'s>,
+    modules: &[Module],
+    f: F,
+) -> Vec<Result<R>>
+where
+    R: Send,
+    F: Fn(&mut ModuleReader<'s>, &Module) -> Result<R> + Sync,
+{
+    parallel::map_with(
+        num_threads,
+        modules,
+        |reader: &mut Option<ModuleReader<'s>>, module| {
+            if reader.is_none() {
+                *reader = Some(ModuleReader::open(source)?);
+            }
+            f(reader.as_mut().unwrap(), module)
+        },
+    )
+}
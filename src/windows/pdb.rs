@@ -4,31 +4,56 @@
 // copied, modified, or distributed except according to those terms.
 
 use failure::Fail;
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
+use log::warn;
 use pdb::{
-    AddressMap, BlockSymbol, DebugInformation, FallibleIterator, MachineType, ModuleInfo,
-    PDBInformation, ProcedureSymbol, PublicSymbol, Register, RegisterRelativeSymbol, Result,
-    SeparatedCodeSymbol, Source, SymbolData, SymbolTable, PDB,
+    AddressMap, BlockSymbol, DebugInformation, FallibleIterator, IdFinder, IdIndex, IdInformation,
+    InlineSiteSymbol, Inlinee, MachineType, ModuleInfo, PDBInformation, PdbInternalRva,
+    PdbInternalSectionOffset, ProcedureSymbol, PublicSymbol, Register, Result, SeparatedCodeSymbol,
+    Source, StreamIndex, SymbolData, SymbolTable, TypeIndex, PDB,
 };
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use std::io::{Cursor, Write};
+use std::ops::Range;
+use std::path::PathBuf;
 use std::sync::Arc;
 use symbolic::common::Arch;
 use symbolic::debuginfo::{pdb::PdbObject, pe::PeObject, Object};
 use symbolic::minidump::cfi::AsciiCfiWriter;
 use uuid::Uuid;
 
-use super::source::{SourceFiles, SourceLineCollector};
-use super::symbol::{BlockInfo, PDBSymbols, RvaSymbols, SelectedSymbol};
+use super::hybrid::{HybridKind, HybridPE};
+use super::modules::{self, ModuleReader, PdbSource};
+use super::source::{InlineLine, RefToIds, SourceFiles, SourceLineCollector};
+use super::stack_win;
+use super::symbol::{BlockInfo, PDBSymbols, Procedure, RvaSymbols, SelectedSymbol};
+use super::type_server;
 use super::types::{DumperFlags, TypeDumper};
 use super::utils::get_pe_debug_id;
+use super::version::get_file_version;
+use crate::cancel;
+use crate::checksums::{self, Checksums};
 use crate::common::{self, Dumpable, Mergeable};
+use crate::debug_records;
+use crate::folding::Folded;
+use crate::inlines::{Inline, InlineOrigins};
+use crate::line::Lines;
 use crate::mapping::PathMappings;
+use crate::options::Options;
+use crate::parallel;
+use crate::publics::{Publics, PublicsPolicy};
+use crate::sources::{self, SourcePaths};
+use crate::visitor::{self, Visitor};
+
+// The number of symbols formatted in parallel before being written
+const RECORDS_BATCH_SIZE: usize = 16384;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum CPU {
     X86,
     X86_64,
+    Arm64,
     Unknown,
 }
 
@@ -49,6 +74,7 @@ impl Display for CPU {
             match self {
                 CPU::X86 => "x86",
                 CPU::X86_64 => "x86_64",
+                CPU::Arm64 => "arm64",
                 CPU::Unknown => "unknown",
             }
         )
@@ -58,9 +84,13 @@ impl Display for CPU {
 const IMAGE_SCN_CNT_CODE: u32 = 0x0000_0020;
 const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
 
+// The sections in the PDB are the ones of the image: when the binary has been optimized after the
+// link (e.g. with BBT), the PDB has some OMAP tables and the section offsets of the symbols are in
+// the layout before the optimization, so the symbols are checked with their translated rva.
 #[derive(Debug)]
 pub(super) struct PDBSections {
-    sections: Option<Vec<bool>>,
+    // The rva ranges of the executable sections
+    sections: Option<Vec<Range<u32>>>,
 }
 
 impl PDBSections {
@@ -69,52 +99,61 @@ impl PDBSections {
             sections: pdb.sections().ok().and_then(|s| s).map(|sections| {
                 sections
                     .iter()
-                    .map(|section| Self::has_code(section.characteristics))
+                    .filter(|section| Self::has_code(section.characteristics))
+                    // physical_address is the virtual size in an image (union Misc) but it's 0 in
+                    // some images: then the size of the raw data is used
+                    .map(|section| {
+                        let start = section.virtual_address;
+                        let size = if section.physical_address == 0 {
+                            section.size_of_raw_data
+                        } else {
+                            section.physical_address
+                        };
+                        start..start + size
+                    })
                     .collect()
             }),
         }
     }
 
-    pub(super) fn is_code(&self, section: u16) -> bool {
-        let section = (section - 1) as usize;
+    pub(super) fn is_code(&self, rva: u32) -> bool {
         self.sections
             .as_ref()
-            .map_or(false, |v| *v.get(section).unwrap_or(&false))
+            .map_or(false, |v| v.iter().any(|range| range.contains(&rva)))
     }
 
     pub(super) fn has_code(characteristics: u32) -> bool {
         characteristics & (IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE) != 0
     }
-
-    pub(super) fn len(&self) -> usize {
-        self.sections.as_ref().map_or(0, |s| s.len())
-    }
 }
 
 #[derive(Debug)]
 pub(super) struct PDBContributions {
-    contributions: Option<Vec<HashSet<u32>>>,
+    // The rvas of the contributions
+    contributions: Option<HashSet<u32>>,
 }
 
 // Some executable sections may contain symbols which are not executable (e.g. string constants)
 // So here we collect all the symbols which are in an exec section and which aren't exec
 impl PDBContributions {
-    fn new(dbi: &DebugInformation, pdb_sections: &PDBSections) -> Self {
+    fn new(dbi: &DebugInformation, pdb_sections: &PDBSections, address_map: &AddressMap) -> Self {
         PDBContributions {
             contributions: dbi
                 .section_contributions()
                 .ok()
                 .and_then(|mut contributions| {
-                    let mut contribs: Vec<HashSet<u32>> =
-                        vec![HashSet::default(); pdb_sections.len()];
+                    let mut contribs = HashSet::default();
                     loop {
                         if let Ok(c) = contributions.next() {
                             if let Some(contribution) = c {
-                                if pdb_sections.is_code(contribution.offset.section)
+                                let rva = match contribution.offset.to_rva(address_map) {
+                                    Some(rva) => rva.0,
+                                    _ => continue,
+                                };
+                                if pdb_sections.is_code(rva)
                                     && !PDBSections::has_code(contribution.characteristics)
                                 {
-                                    let section = (contribution.offset.section - 1) as usize;
-                                    contribs[section].insert(contribution.offset.offset);
+                                    contribs.insert(rva);
                                 }
                             } else {
                                 break;
@@ -123,25 +162,63 @@ impl PDBContributions {
                             return None;
                         }
                     }
-                    if contribs.is_empty() {
-                        None
-                    } else {
-                        Some(contribs)
-                    }
+                    Some(contribs)
                 }),
         }
     }
 
-    pub(super) fn is_code(&self, section: u16, offset: u32) -> bool {
-        let section = (section - 1) as usize;
-        self.contributions.as_ref().map_or(true, |v| {
-            v.get(section).map_or(true, |o| !o.contains(&offset))
-        })
+    pub(super) fn is_code(&self, rva: u32) -> bool {
+        self.contributions
+            .as_ref()
+            .map_or(true, |c| !c.contains(&rva))
     }
 }
 
 struct PDBData<'s> {
     address_map: AddressMap<'s>,
+    // The modules which can't be read are skipped in a /DEBUG:FASTLINK PDB
+    fastlink: bool,
+    // A /PDBSTRIPPED PDB has no module stream: only the publics are dumped
+    stripped: bool,
+}
+
+/// What's required to get the names of the inlined functions
+struct InlineContext<'a, 't> {
+    id_finder: IdFinder<'t>,
+    type_dumper: &'a TypeDumper<'t>,
+}
+
+/// An inline site read in a module: its origin is set when it's added to its procedure
+struct InlineSite {
+    // The offset of the procedure containing the site
+    parent: PdbInternalSectionOffset,
+    inlinee: IdIndex,
+    inline: Inline,
+    lines: Vec<InlineLine>,
+}
+
+/// What's read in the symbols of a module: the lines and the inline sites (the costly part) are
+/// got by the threads reading the modules and then the events are applied in the order of the
+/// modules, so the symbols are selected as if the modules were read one after the other.
+enum ModuleEvent {
+    Procedure {
+        procedure: Procedure,
+        info: BlockInfo,
+        // The line fragments of the procedure (only when its records are debugged)
+        raw_lines: Option<String>,
+    },
+    SeparatedCode {
+        parent_rva: u32,
+        info: BlockInfo,
+        source: Lines,
+    },
+    RegisterRelative {
+        register: Register,
+        offset: i32,
+        type_index: TypeIndex,
+    },
+    InlineSite(InlineSite),
+    ScopeEnd,
 }
 
 struct Collector {
@@ -149,6 +226,10 @@ struct Collector {
     symbols: RvaSymbols,
     pdb_sections: PDBSections,
     pdb_contributions: PDBContributions,
+    origins: InlineOrigins,
+    // The origins of the inlinees already seen
+    inlinee_origins: HashMap<IdIndex, u32>,
+    options: Arc<Options>,
 }
 
 impl Collector {
@@ -158,48 +239,133 @@ impl Collector {
             &self.pdb_sections,
             &self.pdb_contributions,
             address_map,
+            &self.options,
         )
     }
 
-    fn add_procedure_symbol(
-        &mut self,
-        symbol: ProcedureSymbol,
-        info: BlockInfo,
-        lines: &SourceLineCollector,
-    ) {
-        self.symbols.add_procedure_symbol(lines, symbol, info);
-    }
-
-    fn add_symbol(&mut self, symbol: SelectedSymbol, info: BlockInfo) {
-        self.symbols.add_symbol(symbol, info);
+    fn add_sepcode(&mut self, parent_rva: u32, info: BlockInfo, source: Lines) {
+        if let Some(parent) = self.symbols.get_symbol_at(parent_rva) {
+            if info.rva < parent_rva || info.rva > parent_rva + parent.len {
+                // So the block is outside of its parent procedure
+                let sym = SelectedSymbol {
+                    name: parent.name.clone(),
+                    type_index: parent.type_index,
+                    is_public: parent.is_public,
+                    is_multiple: false,
+                    offset: info.offset,
+                    sym_offset: parent.sym_offset,
+                    len: info.len,
+                    parameter_size: parent.parameter_size,
+                    source,
+                    inlines: Vec::new(),
+                    ebp: parent.ebp.clone(),
+                    id: parent.id,
+                };
+                self.symbols.add_symbol(sym, info);
+            }
+        }
     }
 
-    fn add_reg_rel(&mut self, symbol: RegisterRelativeSymbol) {
+    fn add_reg_rel(&mut self, register: Register, offset: i32, type_index: TypeIndex) {
         // TODO: check that's the correct way to know if we've a parameter here
         // 22 comes from https://github.com/microsoft/microsoft-pdb/blob/master/include/cvconst.h#L436
-        if self.cpu == CPU::X86 && symbol.register == Register(22 /* EBP */) && symbol.offset > 0 {
-            self.symbols.add_ebp(symbol);
+        if self.cpu == CPU::X86 && register == Register(22 /* EBP */) && offset > 0 {
+            self.symbols.add_ebp(type_index, offset);
         }
     }
 
-    fn close_procedure(&mut self) {
-        self.symbols.close_procedure();
+    fn add_inline_site(&mut self, site: InlineSite, context: &InlineContext) {
+        // The procedure of the site isn't the current one when it has been merged with a
+        // procedure at the same address
+        let procedure = match self.symbols.get_procedure() {
+            Some(procedure) if procedure.offset == site.parent => procedure,
+            _ => return,
+        };
+        let origins = &mut self.origins;
+        let origin = *self.inlinee_origins.entry(site.inlinee).or_insert_with(|| {
+            let name = context
+                .type_dumper
+                .dump_inlinee(&context.id_finder, site.inlinee);
+            origins.get_id(&name)
+        });
+        let inline = Inline {
+            origin,
+            ..site.inline
+        };
+        procedure.add_inline(inline, &site.lines);
     }
 
-    fn get_symbol_at(&self, rva: u32) -> Option<&SelectedSymbol> {
-        self.symbols.get_symbol_at(rva)
+    fn apply(&mut self, event: ModuleEvent, context: Option<&InlineContext>) {
+        match event {
+            ModuleEvent::Procedure {
+                procedure,
+                info,
+                raw_lines,
+            } => {
+                if let Some(raw_lines) = raw_lines {
+                    debug_records::print(
+                        "Raw PDB line fragments",
+                        &procedure.name,
+                        info.rva as u64,
+                        &raw_lines,
+                    );
+                }
+                self.symbols.add_procedure_symbol(procedure, info);
+            }
+            ModuleEvent::SeparatedCode {
+                parent_rva,
+                info,
+                source,
+            } => {
+                self.add_sepcode(parent_rva, info, source);
+            }
+            ModuleEvent::RegisterRelative {
+                register,
+                offset,
+                type_index,
+            } => {
+                self.add_reg_rel(register, offset, type_index);
+            }
+            ModuleEvent::InlineSite(site) => {
+                if let Some(context) = context {
+                    self.add_inline_site(site, context);
+                }
+            }
+            ModuleEvent::ScopeEnd => {
+                self.symbols.close_procedure();
+            }
+        }
     }
 }
 
+/// The reading of the symbols of a module in one of the threads reading the modules
+struct ModuleCollector<'a, 's> {
+    address_map: &'a AddressMap<'s>,
+    module_info: &'a ModuleInfo<'s>,
+    lines: &'a SourceLineCollector<'a, 's>,
+    inlinees: Option<&'a BTreeMap<IdIndex, Inlinee<'a>>>,
+    // The offset of the procedure containing the current symbols
+    procedure: Option<PdbInternalSectionOffset>,
+    // The lines of the inline sites in the current procedure (the innermost is the last)
+    inline_sites: Vec<Vec<InlineLine>>,
+    events: &'a mut Vec<ModuleEvent>,
+}
+
 pub(crate) struct PDBInfo {
     symbols: PDBSymbols,
+    publics: Publics,
     files: Vec<String>,
+    checksums: Checksums,
+    source_paths: SourcePaths,
+    inline_origins: InlineOrigins,
     cpu: CPU,
     debug_id: String,
     pdb_name: String,
     pe_name: String,
     code_id: Option<String>,
+    release: Option<String>,
     stack: String,
+    options: Arc<Options>,
 }
 
 impl Display for PDBInfo {
@@ -214,12 +380,30 @@ impl Display for PDBInfo {
             writeln!(f, "INFO CODE_ID {} {}", code_id, self.pe_name)?;
         }
 
+        if let Some(release) = self.release.as_ref() {
+            writeln!(f, "INFO RELEASE {}", release)?;
+        }
+
         for (n, file_name) in self.files.iter().enumerate() {
-            writeln!(f, "FILE {} {}", n, file_name)?;
+            checksums::write_file_record(f, n, file_name, &self.checksums)?;
         }
 
-        for (_, sym) in self.symbols.iter() {
-            write!(f, "{}", sym)?;
+        write!(f, "{}", self.inline_origins)?;
+
+        // The records are formatted in parallel by batches
+        let symbols: Vec<_> = self.symbols.iter().collect();
+        for batch in symbols.chunks(RECORDS_BATCH_SIZE) {
+            let policy = self.options.folding_policy;
+            let records = parallel::map(self.options.num_threads, batch, |(rva, sym)| {
+                let mut records = Folded(*sym, policy).to_string();
+                if let Some(public) = self.publics.get(rva) {
+                    records.push_str(&public.to_string());
+                }
+                records
+            });
+            for records in records {
+                f.write_str(&records)?;
+            }
         }
 
         write!(f, "{}", self.stack)?;
@@ -228,6 +412,14 @@ impl Display for PDBInfo {
     }
 }
 
+fn visit_release(visitor: &mut dyn Visitor, release: &str) {
+    visitor.info(&visitor::InfoRecord {
+        key: "release".to_string(),
+        value: release.to_string(),
+        code_file: None,
+    });
+}
+
 fn get_cpu(dbi: &DebugInformation) -> CPU {
     if let Ok(mt) = dbi.machine_type() {
         match mt {
@@ -236,6 +428,7 @@ fn get_cpu(dbi: &DebugInformation) -> CPU {
             // https://docs.rs/pdb/0.5.0/pdb/enum.MachineType.html
             MachineType::X86 => CPU::X86,
             MachineType::Amd64 | MachineType::Ia64 => CPU::X86_64,
+            MachineType::Arm64 => CPU::Arm64,
             _ => CPU::Unknown,
         }
     } else {
@@ -243,6 +436,14 @@ fn get_cpu(dbi: &DebugInformation) -> CPU {
     }
 }
 
+/// The code of a hybrid PE runs in an ARM64 process (or in an x86 one for a CHPE file)
+fn get_hybrid_cpu(hybrid: &HybridPE) -> CPU {
+    match hybrid.kind {
+        HybridKind::Arm64EC | HybridKind::Arm64X => CPU::Arm64,
+        HybridKind::X86 => CPU::X86,
+    }
+}
+
 fn get_debug_id(dbi: &DebugInformation, pi: PDBInformation) -> String {
     // Here the guid is treated like a 128-bit uuid (PDB >=7.0)
     let mut buf = Uuid::encode_buffer();
@@ -251,7 +452,68 @@ fn get_debug_id(dbi: &DebugInformation, pi: PDBInformation) -> String {
     format!("{}{:x}", guid, age)
 }
 
-fn get_stack_info(pdb_buf: Option<&[u8]>, pe: Option<PeObject>) -> String {
+// The PDB feature of a PDB made with /DEBUG:FASTLINK
+const PDB_FEATURE_MINI: u32 = 0x494E_494D;
+
+/// Check if the features at the end of the PDB info stream contain MINI
+///
+/// The features follow the map of the named streams: its size, its capacity, the present and the
+/// deleted bit arrays (each one being a number of words and the words) and the entries.
+fn has_mini_feature(info: &[u8], names_end: usize) -> bool {
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let bytes = info.get(pos..pos + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let get_features_pos = || -> Option<usize> {
+        let count = read_u32(names_end)? as usize;
+        let mut pos = names_end + 8;
+        for _ in 0..2 {
+            pos += 4 + 4 * read_u32(pos)? as usize;
+        }
+        Some(pos + 8 * count)
+    };
+    let mut pos = match get_features_pos() {
+        Some(pos) => pos,
+        _ => return false,
+    };
+    while let Some(feature) = read_u32(pos) {
+        if feature == PDB_FEATURE_MINI {
+            return true;
+        }
+        pos += 4;
+    }
+    false
+}
+
+/// Check if the PDB has been made with /DEBUG:FASTLINK: the types and the symbols are in the objects
+fn is_fastlink<'a, S: 'a + Source<'a>>(pdb: &mut PDB<'a, S>, pi: &PDBInformation) -> bool {
+    match pdb.raw_stream(StreamIndex(1)) {
+        Ok(Some(stream)) => has_mini_feature(stream.as_slice(), pi.names_offset + pi.names_size),
+        _ => false,
+    }
+}
+
+/// Check if the PDB has been made with /PDBSTRIPPED: no module has a stream with its private
+/// symbols and its lines
+fn is_stripped<'a, S: 'a + Source<'a>>(pdb: &mut PDB<'a, S>, dbi: &DebugInformation) -> bool {
+    let mut modules = match dbi.modules() {
+        Ok(modules) => modules,
+        _ => return false,
+    };
+    let mut has_modules = false;
+    while let Ok(Some(module)) = modules.next() {
+        if !matches!(pdb.module_info(&module), Ok(None)) {
+            return false;
+        }
+        has_modules = true;
+    }
+    has_modules
+}
+
+fn get_stack_info(pdb_buf: Option<&[u8]>, pe: Option<PeObject>, stack_win_cfi: bool) -> String {
+    // The exception directory of a hybrid PE doesn't only contain some x64 functions
+    let hybrid = pe.as_ref().and_then(|pe| HybridPE::new(pe.data()));
+    let pe = pe.filter(|_| hybrid.is_none());
     let mut found_unwind_info = false;
     let mut buf = Vec::new();
     let writer = Cursor::new(&mut buf);
@@ -280,37 +542,99 @@ fn get_stack_info(pdb_buf: Option<&[u8]>, pe: Option<PeObject>) -> String {
         }
     }
 
-    String::from_utf8(buf).unwrap()
+    let mut stack = String::from_utf8(buf).unwrap();
+    if let Some(hybrid) = hybrid {
+        stack.push_str(&hybrid.get_stack_info());
+    }
+    if stack_win_cfi {
+        let cfi = stack_win::get_stack_cfi(&stack);
+        stack.push_str(&cfi);
+    }
+
+    common::sort_stack(&stack)
 }
 
-impl<'s> PDBData<'s> {
-    fn collect_public_symbols(
-        &self,
-        globals: SymbolTable,
-        collector: &mut Collector,
+fn get_id_finder<'t>(id_info: &'t IdInformation) -> Result<IdFinder<'t>> {
+    let mut ids = id_info.iter();
+    let mut id_finder = id_info.finder();
+    while ids.next()?.is_some() {
+        id_finder.update(&ids);
+    }
+    Ok(id_finder)
+}
+
+impl<'a, 's> ModuleCollector<'a, 's> {
+    /// Collect the symbols of a module: the events read before an error are kept
+    fn collect(
+        reader: &ModuleReader<'s>,
+        module_info: &ModuleInfo<'s>,
+        file_ids: &RefToIds,
+        inlines: bool,
+        events: &mut Vec<ModuleEvent>,
     ) -> Result<()> {
-        let mut symbols = globals.iter();
+        let lines = SourceLineCollector::new(
+            &reader.address_map,
+            file_ids,
+            reader.string_table.as_ref(),
+            module_info.line_program()?,
+        )?;
+
+        let inlinees: Option<BTreeMap<_, _>> = if inlines {
+            Some(
+                module_info
+                    .inlinees()?
+                    .map(|inlinee| Ok((inlinee.index(), inlinee)))
+                    .collect()?,
+            )
+        } else {
+            None
+        };
+
+        let mut collector = ModuleCollector {
+            address_map: &reader.address_map,
+            module_info,
+            lines: &lines,
+            inlinees: inlinees.as_ref(),
+            procedure: None,
+            inline_sites: Vec::new(),
+            events,
+        };
+
+        let mut symbols = module_info.symbols()?;
         while let Some(symbol) = symbols.next()? {
             let symbol = match symbol.parse() {
                 Ok(s) => s,
-                _ => return Ok(()),
+                _ => continue,
             };
 
-            if let SymbolData::Public(symbol) = symbol {
-                collector.add_public_symbol(symbol, &self.address_map);
-            }
+            collector.handle_symbol(symbol)?;
         }
 
         Ok(())
     }
 
-    fn add_block(
-        &self,
-        module_info: &ModuleInfo,
-        block: BlockSymbol,
-        collector: &mut Collector,
-        lines: &SourceLineCollector,
-    ) -> Result<()> {
+    fn add_procedure(&mut self, procedure: ProcedureSymbol, info: BlockInfo) {
+        let name = procedure.name.to_string().into_owned();
+        let raw_lines = if debug_records::matches(&name, info.rva as u64, info.len as u64) {
+            Some(self.lines.debug_source_lines(info.offset, info.len))
+        } else {
+            None
+        };
+
+        self.procedure = Some(info.offset);
+        self.events.push(ModuleEvent::Procedure {
+            procedure: Procedure {
+                name,
+                type_index: procedure.type_index,
+                offset: procedure.offset,
+                source: self.lines.collect_source_lines(info.offset, info.len),
+            },
+            info,
+            raw_lines,
+        });
+    }
+
+    fn add_block(&mut self, block: BlockSymbol) -> Result<()> {
         // When building with PGO, the compiler can split functions into
         // "hot" and "cold" blocks, and move the "cold" blocks out to separate
         // pages, so the function can be noncontiguous. To find these blocks,
@@ -318,7 +642,7 @@ impl<'s> PDBData<'s> {
         // that are children of them. We can then find the lexical parents
         // of those blocks and print out an extra FUNC line for blocks
         // that are not contained in their parent functions.
-        let parent = match module_info.symbols_at(block.parent)?.next()? {
+        let parent = match self.module_info.symbols_at(block.parent)?.next()? {
             Some(p) => p,
             _ => return Ok(()),
         };
@@ -333,154 +657,243 @@ impl<'s> PDBData<'s> {
             _ => return Ok(()),
         };
 
-        let block_rva = match block.offset.to_rva(&self.address_map) {
+        let block_rva = match block.offset.to_rva(self.address_map) {
             Some(rva) => rva,
             _ => return Ok(()),
         };
 
-        let parent_rva = match parent.offset.to_rva(&self.address_map) {
+        let parent_rva = match parent.offset.to_rva(self.address_map) {
             Some(rva) => rva,
             _ => return Ok(()),
         };
 
         if block_rva < parent_rva || block_rva > parent_rva + parent.len {
             // So the block is outside of its parent procedure
-            collector.add_procedure_symbol(
+            self.add_procedure(
                 parent,
                 BlockInfo {
                     rva: block_rva.0,
                     offset: block.offset,
                     len: block.len,
                 },
-                lines,
             );
         }
 
         Ok(())
     }
 
-    fn add_sepcode(
-        &self,
-        block: SeparatedCodeSymbol,
-        collector: &mut Collector,
-        lines: &SourceLineCollector,
-    ) {
+    fn add_sepcode(&mut self, block: SeparatedCodeSymbol) {
         // We can see some sepcode syms in ntdll.dll
         // As far as I understand, they're pieces of code moved at compilation time.
         // According to some functions signatures these piece of code can be just
         // exception filter and exception handling
-        let block_rva = match block.offset.to_rva(&self.address_map) {
+        let block_rva = match block.offset.to_rva(self.address_map) {
             Some(rva) => rva,
             _ => return,
         };
 
-        let parent_rva = match block.parent_offset.to_rva(&self.address_map) {
+        let parent_rva = match block.parent_offset.to_rva(self.address_map) {
             Some(rva) => rva,
             _ => return,
         };
 
-        if let Some(parent) = collector.get_symbol_at(parent_rva.0) {
-            if block_rva < parent_rva || block_rva > parent_rva + parent.len {
-                // So the block is outside of its parent procedure
-                let source = lines.collect_source_lines(block.offset, block.len);
-                let sym = SelectedSymbol {
-                    name: parent.name.clone(),
-                    type_index: parent.type_index,
-                    is_public: parent.is_public,
-                    is_multiple: false,
-                    offset: block.offset,
-                    sym_offset: parent.sym_offset,
-                    len: block.len,
-                    parameter_size: parent.parameter_size,
-                    source,
-                    ebp: parent.ebp.clone(),
-                    id: parent.id,
-                };
-                collector.add_symbol(
-                    sym,
-                    BlockInfo {
-                        rva: block_rva.0,
-                        offset: block.offset,
-                        len: block.len,
-                    },
+        // The parent is found when the events are applied
+        self.events.push(ModuleEvent::SeparatedCode {
+            parent_rva: parent_rva.0,
+            info: BlockInfo {
+                rva: block_rva.0,
+                offset: block.offset,
+                len: block.len,
+            },
+            source: self.lines.collect_source_lines(block.offset, block.len),
+        });
+    }
+
+    fn add_inline_site(
+        &mut self,
+        site: InlineSiteSymbol,
+        inlinees: &BTreeMap<IdIndex, Inlinee>,
+    ) -> Result<()> {
+        let parent = match self.procedure {
+            Some(parent) => parent,
+            _ => return Ok(()),
+        };
+        let site_lines = match inlinees.get(&site.inlinee) {
+            Some(inlinee) => self
+                .lines
+                .collect_inline_lines(inlinee.lines(parent, &site))?,
+            _ => Vec::new(),
+        };
+
+        if let Some(first) = site_lines.first() {
+            // The call location is the line of the parent at the start of the inline site
+            let call = match self.inline_sites.last() {
+                Some(parent) => parent
+                    .iter()
+                    .take_while(|line| line.offset <= first.offset)
+                    .last()
+                    .map(|line| (line.num, line.file_id)),
+                _ => self.lines.get_line_at(first.offset),
+            };
+            let (call_line, call_file) = call.unwrap_or((0, 0));
+
+            let ranges = Inline::get_ranges(site_lines.iter().flat_map(|line| {
+                let (start, end) = (
+                    PdbInternalRva(line.rva),
+                    PdbInternalRva(line.rva + line.len),
                 );
-            }
+                self.address_map
+                    .rva_ranges(start..end)
+                    .map(|r| (r.start.0, r.end.0 - r.start.0))
+                    .collect::<Vec<_>>()
+            }));
+
+            // The origin is set when the site is added to its procedure
+            let inline = Inline {
+                depth: self.inline_sites.len() as u32,
+                call_line,
+                call_file,
+                origin: 0,
+                ranges,
+            };
+            self.events.push(ModuleEvent::InlineSite(InlineSite {
+                parent,
+                inlinee: site.inlinee,
+                inline,
+                lines: site_lines.clone(),
+            }));
         }
+
+        // The sites are pushed even without lines to be popped at their end
+        self.inline_sites.push(site_lines);
+
+        Ok(())
     }
 
-    fn handle_symbol(
-        &self,
-        symbol: SymbolData,
-        collector: &mut Collector,
-        lines: &SourceLineCollector,
-        module_info: &ModuleInfo,
-    ) -> Result<()> {
+    fn handle_symbol(&mut self, symbol: SymbolData) -> Result<()> {
         match symbol {
             SymbolData::Procedure(procedure) => {
-                let rva = match procedure.offset.to_rva(&self.address_map) {
+                let rva = match procedure.offset.to_rva(self.address_map) {
                     Some(rva) => rva,
                     _ => return Ok(()),
                 };
 
-                collector.add_procedure_symbol(
-                    procedure,
-                    BlockInfo {
-                        rva: rva.0,
-                        offset: procedure.offset,
-                        len: procedure.len,
-                    },
-                    lines,
-                );
+                let info = BlockInfo {
+                    rva: rva.0,
+                    offset: procedure.offset,
+                    len: procedure.len,
+                };
+                self.add_procedure(procedure, info);
             }
             SymbolData::Block(block) => {
-                self.add_block(&module_info, block, collector, lines)?;
+                self.add_block(block)?;
             }
             SymbolData::SeparatedCode(block) => {
-                self.add_sepcode(block, collector, lines);
+                self.add_sepcode(block);
             }
             SymbolData::RegisterRelative(regrel) => {
-                collector.add_reg_rel(regrel);
+                self.events.push(ModuleEvent::RegisterRelative {
+                    register: regrel.register,
+                    offset: regrel.offset,
+                    type_index: regrel.type_index,
+                });
+            }
+            SymbolData::InlineSite(site) => {
+                if let Some(inlinees) = self.inlinees {
+                    self.add_inline_site(site, inlinees)?;
+                }
+            }
+            SymbolData::InlineSiteEnd => {
+                self.inline_sites.pop();
             }
             SymbolData::ScopeEnd => {
-                collector.close_procedure();
+                self.procedure = None;
+                self.inline_sites.clear();
+                self.events.push(ModuleEvent::ScopeEnd);
             }
             _ => {}
         }
 
         Ok(())
     }
+}
+
+impl<'s> PDBData<'s> {
+    fn collect_public_symbols(
+        &self,
+        globals: SymbolTable,
+        collector: &mut Collector,
+    ) -> Result<()> {
+        let mut symbols = globals.iter();
+        while let Some(symbol) = symbols.next()? {
+            let symbol = match symbol.parse() {
+                Ok(s) => s,
+                _ => return Ok(()),
+            };
+
+            if let SymbolData::Public(symbol) = symbol {
+                collector.add_public_symbol(symbol, &self.address_map);
+            }
+        }
+
+        Ok(())
+    }
 
-    fn collect_functions<'a, S: 'a + Source<'a>>(
+    /// The modules are read in parallel (each thread has its own PDB opened with source)
+    fn collect_functions(
         &self,
-        pdb: &mut PDB<'a, S>,
+        source: PdbSource,
         dbi: &DebugInformation,
         collector: &mut Collector,
         source_files: &SourceFiles<'s>,
+        context: Option<&InlineContext>,
     ) -> Result<()> {
-        let mut modules = dbi.modules()?;
+        let modules: Vec<_> = dbi.modules()?.collect()?;
+        let file_ids = source_files.get_ids();
+        let inlines = context.is_some();
 
         // We get all the procedures and the labels
         // Labels correspond to some labelled code we can map with some public symbols (assembly)
-        while let Some(module) = modules.next()? {
-            let module_info = match pdb.module_info(&module)? {
-                Some(info) => info,
-                _ => continue,
-            };
+        let num_threads = collector.options.num_threads;
+        let module_events =
+            modules::map_modules(num_threads, source, &modules, |reader, module| {
+                // The dump fails before writing anything
+                if cancel::is_cancelled() {
+                    return Ok(None);
+                }
+                let module_info = match reader.pdb.module_info(module)? {
+                    Some(info) => info,
+                    _ => return Ok(None),
+                };
 
-            let lines = SourceLineCollector::new(
-                &self.address_map,
-                &source_files,
-                module_info.line_program()?,
-            )?;
+                let mut events = Vec::new();
+                let res =
+                    ModuleCollector::collect(reader, &module_info, file_ids, inlines, &mut events);
+                Ok(Some((events, res)))
+            });
 
-            let mut symbols = module_info.symbols()?;
-            while let Some(symbol) = symbols.next()? {
-                let symbol = match symbol.parse() {
-                    Ok(s) => s,
-                    _ => continue,
-                };
+        for (module, events) in modules.iter().zip(module_events) {
+            if cancel::is_cancelled() {
+                break;
+            }
+            let (events, res) = match events {
+                Ok(Some(events)) => events,
+                Ok(None) => continue,
+                Err(e) if self.fastlink => {
+                    warn!("Skip the module {}: {}", module.module_name(), e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
 
-                self.handle_symbol(symbol, collector, &lines, &module_info)?;
+            for event in events {
+                collector.apply(event, context);
+            }
+            match res {
+                Err(e) if self.fastlink => {
+                    warn!("Skip the module {}: {}", module.module_name(), e);
+                }
+                res => res?,
             }
         }
 
@@ -489,28 +902,68 @@ impl<'s> PDBData<'s> {
 }
 
 impl PDBInfo {
+    /// The inline sites (S_INLINESITE) are collected with their lines with --inlines and the names
+    /// of the functions have their return type with --with-signatures
     pub fn new(
         buf: &[u8],
         pdb_name: &str,
         pe_name: &str,
         pe: Option<PeObject>,
         mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
+    ) -> Result<Self> {
+        Self::from_source(PdbSource::Buf(buf), pdb_name, pe_name, pe, mapping, options)
+    }
+
+    /// A remote PDB is parsed while it's downloaded (the whole file is only needed at the end
+    /// for the frame data)
+    pub(crate) fn from_source(
+        source: PdbSource,
+        pdb_name: &str,
+        pe_name: &str,
+        pe: Option<PeObject>,
+        mapping: Option<Arc<PathMappings>>,
+        options: Arc<Options>,
     ) -> Result<Self> {
-        let cursor = Cursor::new(buf);
-        let mut pdb = PDB::open(cursor)?;
+        let mut pdb = source.open()?;
         let dbi = pdb.debug_information()?;
         let pi = pdb.pdb_information()?;
+        let fastlink = is_fastlink(&mut pdb, &pi);
+        if fastlink {
+            warn!(
+                "{} has been made with /DEBUG:FASTLINK: the types and the private symbols are in the object files, so only the modules and the public symbols are dumped (link with /DEBUG:FULL to get all the symbols)",
+                pdb_name
+            );
+        }
+        let stripped = is_stripped(&mut pdb, &dbi);
+        if stripped {
+            warn!(
+                "{} has been made with /PDBSTRIPPED: there are no private symbols and no lines, so only the public symbols are dumped",
+                pdb_name
+            );
+        }
         let frame_table = pdb.frame_table()?;
         let globals = pdb.global_symbols()?;
+        // With some OMAP tables, the addresses are translated to the ones of the image
+        let pdb_data = PDBData {
+            address_map: pdb.address_map()?,
+            fastlink,
+            stripped,
+        };
         let pdb_sections = PDBSections::new(&mut pdb);
-        let pdb_contributions = PDBContributions::new(&dbi, &pdb_sections);
+        let pdb_contributions = PDBContributions::new(&dbi, &pdb_sections, &pdb_data.address_map);
 
-        let cpu = get_cpu(&dbi);
+        let cpu = pe
+            .as_ref()
+            .and_then(|pe| HybridPE::new(pe.data()))
+            .map_or_else(|| get_cpu(&dbi), |hybrid| get_hybrid_cpu(&hybrid));
         let debug_id = get_debug_id(&dbi, pi);
-        let source_files = SourceFiles::new(&mut pdb, mapping)?;
-
-        let pdb_data = PDBData {
-            address_map: pdb.address_map()?,
+        let source_files = match SourceFiles::new(&mut pdb, source, mapping, &options) {
+            Err(e) if fastlink || stripped => {
+                warn!("No source files in {}: {}", pdb_name, e);
+                SourceFiles::empty()
+            }
+            res => res?,
         };
 
         let mut collector = Collector {
@@ -518,52 +971,146 @@ impl PDBInfo {
             symbols: RvaSymbols::default(),
             pdb_sections,
             pdb_contributions,
+            origins: InlineOrigins::default(),
+            inlinee_origins: HashMap::default(),
+            options: options.clone(),
         };
 
-        pdb_data.collect_functions(&mut pdb, &dbi, &mut collector, &source_files)?;
-        pdb_data.collect_public_symbols(globals, &mut collector)?;
-
         let type_info = pdb.type_information()?;
+        let type_server_bufs =
+            type_server::get_type_servers(&mut pdb, &type_info, &options.type_server_paths);
+        let type_servers: Vec<_> = type_server_bufs
+            .iter()
+            .filter_map(|buf| {
+                PDB::open(Cursor::new(&buf[..]))
+                    .and_then(|mut server| server.type_information())
+                    .map_err(|e| warn!("Invalid type server: {}", e))
+                    .ok()
+            })
+            .collect();
         // Demangler or dumper (for type info we've for private symbols)
-        let type_dumper = TypeDumper::new(&type_info, cpu.get_ptr_size(), DumperFlags::default())?;
+        let mut flags = DumperFlags::default();
+        flags.set(DumperFlags::NO_FUNCTION_RETURN, !options.signatures);
+        let type_dumper = TypeDumper::new(
+            &type_info,
+            &type_servers,
+            cpu.get_ptr_size(),
+            flags,
+            options.demangling,
+        )?;
+
+        // The names of the inlined functions are in the IPI stream
+        let id_info = if options.inlines && !stripped {
+            match pdb.id_information() {
+                Ok(id_info) => Some(id_info),
+                Err(e) => {
+                    warn!("No inline info: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let context = match id_info.as_ref() {
+            Some(id_info) => Some(InlineContext {
+                id_finder: get_id_finder(id_info)?,
+                type_dumper: &type_dumper,
+            }),
+            _ => None,
+        };
+
+        if !pdb_data.stripped {
+            pdb_data.collect_functions(
+                source,
+                &dbi,
+                &mut collector,
+                &source_files,
+                context.as_ref(),
+            )?;
+        }
+        pdb_data.collect_public_symbols(globals, &mut collector)?;
 
         let code_id = if let Some(pe) = pe.as_ref() {
             Some(pe.code_id().unwrap().as_str().to_uppercase())
         } else {
             None
         };
-
-        let stack = get_stack_info(Some(&buf), pe);
-        let symbols =
+        let release = pe.as_ref().and_then(|pe| get_file_version(pe.data()));
+
+        let complete;
+        let buf = match source {
+            PdbSource::Buf(buf) => buf,
+            PdbSource::Remote(file) => {
+                complete = file.wait()?;
+                &complete
+            }
+        };
+        let stack = get_stack_info(Some(buf), pe, options.stack_win_cfi);
+        let publics = collector.symbols.take_publics();
+        let mut symbols =
             collector
                 .symbols
                 .mv_to_pdb_symbols(type_dumper, &pdb_data.address_map, frame_table);
+        if options.publics_policy == PublicsPolicy::Dedup {
+            crate::windows::symbol::remove_covered_publics(&mut symbols);
+        }
+        let mut files = source_files.get_mapping();
+        if let Some(remapping) = common::sort_files(&mut files) {
+            crate::windows::symbol::remap_files(&mut symbols, &remapping);
+        }
         let symbols = crate::windows::symbol::append_dummy_symbol(symbols, pe_name);
+        crate::windows::symbol::debug_emitted_records(&symbols, options.folding_policy);
 
         Ok(PDBInfo {
             symbols,
-            files: source_files.get_mapping(),
+            publics,
+            files,
+            checksums: source_files.get_checksums(),
+            source_paths: source_files.get_paths(),
+            inline_origins: collector.origins,
             cpu,
             debug_id,
             pdb_name: String::from(pdb_name),
             pe_name: String::from(pe_name),
             code_id,
+            release,
             stack,
+            options,
         })
     }
 
     pub fn set_pe(&mut self, pe_name: String, pe: PeObject, pdb_buf: &[u8]) -> bool {
         if get_pe_debug_id(Some(&pe)).unwrap() == self.debug_id {
             self.code_id = Some(pe.code_id().unwrap().as_str().to_uppercase());
+            self.release = get_file_version(pe.data());
             self.pe_name = pe_name;
-            if self.stack.is_empty() {
-                self.stack = get_stack_info(Some(pdb_buf), Some(pe));
+            if let Some(hybrid) = HybridPE::new(pe.data()) {
+                self.cpu = get_hybrid_cpu(&hybrid);
+            }
+            // The unwind info of the exception directory (x64) is better than the frame data of
+            // the PDB (if any)
+            if self.stack.is_empty() || pe.has_unwind_info() {
+                self.stack = get_stack_info(Some(pdb_buf), Some(pe), self.options.stack_win_cfi);
             }
             true
         } else {
             false
         }
     }
+
+    /// Set the PE even if the PDB isn't its one (e.g. the PDB of a local rebuild): the module gets
+    /// the debug id of the PE so the symbols match its minidumps
+    pub fn force_pe(&mut self, pe_name: String, pe: PeObject, pdb_buf: &[u8]) {
+        let pe_debug_id = get_pe_debug_id(Some(&pe)).unwrap();
+        if pe_debug_id != self.debug_id {
+            warn!(
+                "The debug id of {} is {} but the one of {} is {}: the PDB is used with the debug id of the PE (--force-id-match), so the symbols may be wrong",
+                pe_name, pe_debug_id, self.pdb_name, self.debug_id
+            );
+            self.debug_id = pe_debug_id;
+        }
+        self.set_pe(pe_name, pe, pdb_buf);
+    }
 }
 
 impl Dumpable for PDBInfo {
@@ -572,6 +1119,28 @@ impl Dumpable for PDBInfo {
         Ok(())
     }
 
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        let cpu = self.cpu.to_string();
+        visitor::visit_module(visitor, "windows", &cpu, &self.debug_id, &self.pdb_name);
+        if let Some(code_id) = self.code_id.as_ref() {
+            visitor::visit_code_id(visitor, code_id, Some(&self.pe_name));
+        }
+        if let Some(release) = self.release.as_ref() {
+            visit_release(visitor, release);
+        }
+        visitor::visit_files(visitor, &self.files, Some(&self.checksums));
+        visitor::visit_inline_origins(visitor, &self.inline_origins);
+
+        for (rva, sym) in self.symbols.iter() {
+            Folded(sym, self.options.folding_policy).visit(visitor);
+            if let Some(public) = self.publics.get(rva) {
+                visitor::visit_public(visitor, public);
+            }
+        }
+
+        visitor::visit_text(visitor, &self.stack)
+    }
+
     fn get_debug_id(&self) -> &str {
         &self.debug_id
     }
@@ -579,6 +1148,10 @@ impl Dumpable for PDBInfo {
     fn get_name(&self) -> &str {
         &self.pdb_name
     }
+
+    fn get_source_files(&self) -> Vec<(String, PathBuf)> {
+        sources::get_source_files(&self.files, &self.source_paths)
+    }
 }
 
 impl Mergeable for PDBInfo {
@@ -594,7 +1167,9 @@ pub(crate) struct PEInfo {
     pdb_name: String,
     pe_name: String,
     code_id: Option<String>,
+    release: Option<String>,
     stack: String,
+    options: Arc<Options>,
 }
 
 impl Display for PEInfo {
@@ -609,8 +1184,12 @@ impl Display for PEInfo {
             writeln!(f, "INFO CODE_ID {} {}", code_id, self.pe_name)?;
         }
 
+        if let Some(release) = self.release.as_ref() {
+            writeln!(f, "INFO RELEASE {}", release)?;
+        }
+
         for (_, sym) in self.symbols.iter() {
-            write!(f, "{}", sym)?;
+            write!(f, "{}", Folded(sym, self.options.folding_policy))?;
         }
 
         write!(f, "{}", self.stack)?;
@@ -620,13 +1199,21 @@ impl Display for PEInfo {
 }
 
 impl PEInfo {
-    pub fn new(pe_name: &str, pe: PeObject) -> Result<Self> {
-        let cpu = match pe.arch() {
-            Arch::X86 => CPU::X86,
-            Arch::X86Unknown => CPU::X86,
-            Arch::Amd64 => CPU::X86_64,
-            Arch::Amd64h => CPU::X86_64,
-            Arch::Amd64Unknown => CPU::X86_64,
+    pub fn new(
+        pe_name: &str,
+        pe: PeObject,
+        map_symbols: Vec<(u32, String)>,
+        options: Arc<Options>,
+    ) -> Result<Self> {
+        let hybrid = HybridPE::new(pe.data());
+        let cpu = match (hybrid.as_ref(), pe.arch()) {
+            (Some(hybrid), _) => get_hybrid_cpu(hybrid),
+            (_, Arch::X86) => CPU::X86,
+            (_, Arch::X86Unknown) => CPU::X86,
+            (_, Arch::Amd64) => CPU::X86_64,
+            (_, Arch::Amd64h) => CPU::X86_64,
+            (_, Arch::Amd64Unknown) => CPU::X86_64,
+            (_, Arch::Arm64) => CPU::Arm64,
             _ => CPU::Unknown,
         };
         let pdb_name = pe.debug_file_name().unwrap_or_default().to_string();
@@ -634,13 +1221,26 @@ impl PEInfo {
 
         let debug_id = get_pe_debug_id(Some(&pe)).unwrap();
         let code_id = Some(pe.code_id().unwrap().as_str().to_uppercase());
+        let release = get_file_version(pe.data());
+        let functions = match hybrid {
+            Some(hybrid) => hybrid.get_functions(),
+            _ => pe.exception_data().map_or_else(Vec::new, |data| {
+                data.into_iter()
+                    .filter_map(|function| function.ok())
+                    .map(|function| (function.begin_address, function.end_address))
+                    .collect()
+            }),
+        };
         let symbols = crate::windows::symbol::symbolic_to_pdb_symbols(
             pe.symbols(),
-            pe.exception_data(),
+            functions,
+            map_symbols,
             pe_name,
+            options.demangling,
         );
         let symbols = crate::windows::symbol::append_dummy_symbol(symbols, pe_name);
-        let stack = get_stack_info(None, Some(pe));
+        crate::windows::symbol::debug_emitted_records(&symbols, options.folding_policy);
+        let stack = get_stack_info(None, Some(pe), options.stack_win_cfi);
 
         Ok(PEInfo {
             symbols,
@@ -649,7 +1249,9 @@ impl PEInfo {
             pdb_name,
             pe_name: String::from(pe_name),
             code_id,
+            release,
             stack,
+            options,
         })
     }
 
@@ -665,6 +1267,23 @@ impl Dumpable for PEInfo {
         Ok(())
     }
 
+    fn visit(&self, visitor: &mut dyn Visitor) -> common::Result<()> {
+        let cpu = self.cpu.to_string();
+        visitor::visit_module(visitor, "windows", &cpu, &self.debug_id, &self.pdb_name);
+        if let Some(code_id) = self.code_id.as_ref() {
+            visitor::visit_code_id(visitor, code_id, Some(&self.pe_name));
+        }
+        if let Some(release) = self.release.as_ref() {
+            visit_release(visitor, release);
+        }
+
+        for (_, sym) in self.symbols.iter() {
+            Folded(sym, self.options.folding_policy).visit(visitor);
+        }
+
+        visitor::visit_text(visitor, &self.stack)
+    }
+
     fn get_debug_id(&self) -> &str {
         &self.debug_id
     }
@@ -735,16 +1354,28 @@ mod tests {
         let name = toks[2];
         let pe_buf = dl_from_server(url);
         let pe_buf = crate::utils::read_cabinet(pe_buf, PathBuf::from(name)).unwrap();
-        let (pe, pdb_buf, pdb_name) = crate::windows::utils::get_pe_pdb_buf(
+        let (pe, pdb_file, pdb_name) = crate::windows::utils::get_pe_pdb_buf(
             &PathBuf::from("."),
             &pe_buf,
-            crate::cache::get_sym_servers(Some(&format!("SRV*~/symcache*{}", MS))).as_ref(),
+            &crate::supplier::SymbolServers::new(
+                Some(&format!("SRV*~/symcache*{}", MS)),
+                &crate::cache::HttpOptions::default(),
+            )
+            .unwrap(),
         )
         .unwrap();
 
         let mut output = Vec::new();
         let cursor = Cursor::new(&mut output);
-        let pdb = PDBInfo::new(&pdb_buf, &pdb_name, name, Some(pe), None).unwrap();
+        let pdb = PDBInfo::from_source(
+            pdb_file.source(),
+            &pdb_name,
+            name,
+            Some(pe),
+            None,
+            Arc::default(),
+        )
+        .unwrap();
         pdb.dump(cursor).unwrap();
 
         let toks: Vec<_> = name.rsplitn(2, '.').collect();
@@ -761,22 +1392,34 @@ mod tests {
         }
 
         let pe_buf = crate::utils::read_file(&path);
-        let (pe, pdb_buf, pdb_name) = crate::windows::utils::get_pe_pdb_buf(
+        let res = crate::windows::utils::get_pe_pdb_buf(
             &path,
             &pe_buf,
-            crate::cache::get_sym_servers(Some(&format!("SRV*~/symcache*{}", MS))).as_ref(),
-        )
-        .unwrap_or_else(|| (PeObject::parse(&pe_buf).unwrap(), vec![], "".to_string()));
+            &crate::supplier::SymbolServers::new(
+                Some(&format!("SRV*~/symcache*{}", MS)),
+                &crate::cache::HttpOptions::default(),
+            )
+            .unwrap(),
+        );
 
         let mut output = Vec::new();
         let cursor = Cursor::new(&mut output);
 
-        if pdb_buf.is_empty() {
-            let pe = PEInfo::new(file_name, pe).unwrap();
-            pe.dump(cursor).unwrap();
-        } else {
-            let pdb = PDBInfo::new(&pdb_buf, &pdb_name, file_name, Some(pe), mapping).unwrap();
+        if let Some((pe, pdb_file, pdb_name)) = res {
+            let pdb = PDBInfo::from_source(
+                pdb_file.source(),
+                &pdb_name,
+                file_name,
+                Some(pe),
+                mapping,
+                Arc::default(),
+            )
+            .unwrap();
             pdb.dump(cursor).unwrap();
+        } else {
+            let pe = PeObject::parse(&pe_buf).unwrap();
+            let pe = PEInfo::new(file_name, pe, Vec::new(), Arc::default()).unwrap();
+            pe.dump(cursor).unwrap();
         }
 
         output
@@ -997,7 +1640,9 @@ mod tests {
 
         let file_map_old = old.file_map();
         let file_map_new = new.file_map();
-        let files_old: Vec<_> = file_map_old.values().collect();
+        // The files are sorted in the new output
+        let mut files_old: Vec<_> = file_map_old.values().collect();
+        files_old.sort();
         let files_new: Vec<_> = file_map_new.values().collect();
 
         assert_eq!(files_new, files_old, "Not the same files");
@@ -1079,6 +1724,220 @@ mod tests {
         test_file("basic64", TestFlags::ALL);
     }
 
+    #[test]
+    fn test_fastlink() {
+        // The header, the names (an empty one) and a map with one entry
+        let mut info = vec![0; 28];
+        info.extend_from_slice(&[1, 0, 0, 0, 0]);
+        for x in &[1u32, 1, 1, 1, 0, 0, 5, 20140508] {
+            info.extend_from_slice(&x.to_le_bytes());
+        }
+        assert!(!has_mini_feature(&info, 33));
+        info.extend_from_slice(&PDB_FEATURE_MINI.to_le_bytes());
+        assert!(has_mini_feature(&info, 33));
+        assert!(!has_mini_feature(&info[..40], 33));
+
+        let buf = crate::utils::read_file(PathBuf::from("./test_data/windows/basic64.pdb"));
+        let mut pdb = PDB::open(Cursor::new(&buf)).unwrap();
+        let pi = pdb.pdb_information().unwrap();
+        assert!(!is_fastlink(&mut pdb, &pi));
+    }
+
+    #[test]
+    fn test_stripped() {
+        for name in &["basic32-min", "basic64", "dump_syms_regtest64"] {
+            let path = PathBuf::from(format!("./test_data/windows/{}.pdb", name));
+            let buf = crate::utils::read_file(path);
+            let mut pdb = PDB::open(Cursor::new(&buf)).unwrap();
+            let dbi = pdb.debug_information().unwrap();
+            assert!(!is_stripped(&mut pdb, &dbi));
+        }
+    }
+
+    #[test]
+    fn test_inlines() {
+        let buf = crate::utils::read_file("./test_data/windows/dump_syms_regtest64.pdb");
+        let options = Options {
+            inlines: true,
+            ..Default::default()
+        };
+        let pdb = PDBInfo::new(
+            &buf,
+            "dump_syms_regtest64.pdb",
+            "",
+            None,
+            None,
+            Arc::new(options),
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        pdb.dump(Cursor::new(&mut output)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        // isleadbyte inlines _isleadbyte_l which inlines the destructor of _LocaleUpdate
+        let func = output
+            .split("FUNC ")
+            .find(|f| f.starts_with("8240 45 0 isleadbyte(int)\n"))
+            .unwrap();
+        let inlines: Vec<_> = func.lines().filter(|l| l.starts_with("INLINE ")).collect();
+        assert_eq!(
+            inlines,
+            vec!["INLINE 0 64 95 10 8248 37", "INLINE 1 57 95 9 826c 13"]
+        );
+        // The lines are the ones of the innermost inlined function
+        assert!(func.contains("\n826c 7 267 165\n"));
+        assert!(output.contains("\nINLINE_ORIGIN 17 fast_error_exit(int)\n"));
+    }
+
+    #[test]
+    fn test_signatures() {
+        let buf = crate::utils::read_file("./test_data/windows/basic64.pdb");
+        let options = Options {
+            signatures: true,
+            ..Default::default()
+        };
+        let pdb = PDBInfo::new(&buf, "basic64.pdb", "", None, None, Arc::new(options)).unwrap();
+        let mut output = Vec::new();
+        pdb.dump(Cursor::new(&mut output)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("\nFUNC 6ba0 17 0 void test3(int, unsigned int, short)\n"));
+    }
+
+    #[test]
+    fn test_force_pe() {
+        let pdb_buf = crate::utils::read_file("./test_data/windows/basic64.pdb");
+        let pe_buf = crate::utils::read_file("./test_data/windows/basic32.dll");
+        let mut pdb =
+            PDBInfo::new(&pdb_buf, "basic64.pdb", "", None, None, Arc::default()).unwrap();
+        let pdb_debug_id = pdb.debug_id.clone();
+
+        let pe = PeObject::parse(&pe_buf).unwrap();
+        let pe_debug_id = get_pe_debug_id(Some(&pe)).unwrap();
+        assert_ne!(pe_debug_id, pdb_debug_id);
+        assert!(!pdb.set_pe("basic32.dll".to_string(), pe, &pdb_buf));
+        assert_eq!(pdb.debug_id, pdb_debug_id);
+
+        let pe = PeObject::parse(&pe_buf).unwrap();
+        pdb.force_pe("basic32.dll".to_string(), pe, &pdb_buf);
+        assert_eq!(pdb.debug_id, pe_debug_id);
+        assert_eq!(pdb.pe_name, "basic32.dll");
+        assert!(pdb.code_id.is_some());
+    }
+
+    #[test]
+    fn test_pe_unwind_info() {
+        // The frame data of a x86 PDB is kept: its PE hasn't any exception directory
+        let pdb_buf = crate::utils::read_file("./test_data/windows/basic-opt32.pdb");
+        let pe_buf = crate::utils::read_file("./test_data/windows/basic-opt32.dll");
+        let mut pdb =
+            PDBInfo::new(&pdb_buf, "basic-opt32.pdb", "", None, None, Arc::default()).unwrap();
+        assert!(pdb.stack.starts_with("STACK WIN "));
+        let stack = pdb.stack.clone();
+        let pe = PeObject::parse(&pe_buf).unwrap();
+        assert!(!pe.has_unwind_info());
+        assert!(pdb.set_pe("basic-opt32.dll".to_string(), pe, &pdb_buf));
+        assert_eq!(pdb.stack, stack);
+
+        // A x64 PDB hasn't any frame data: the STACK records come from the exception directory
+        let pdb_buf = crate::utils::read_file("./test_data/windows/basic-opt64.pdb");
+        let pe_buf = crate::utils::read_file("./test_data/windows/basic-opt64.dll");
+        let mut pdb =
+            PDBInfo::new(&pdb_buf, "basic-opt64.pdb", "", None, None, Arc::default()).unwrap();
+        assert!(pdb.stack.is_empty());
+        let pe = PeObject::parse(&pe_buf).unwrap();
+        assert!(pe.has_unwind_info());
+        let functions: HashSet<_> = pe
+            .exception_data()
+            .unwrap()
+            .into_iter()
+            .map(|function| {
+                let function = function.unwrap();
+                (
+                    function.begin_address,
+                    function.end_address - function.begin_address,
+                )
+            })
+            .collect();
+        assert!(pdb.set_pe("basic-opt64.dll".to_string(), pe, &pdb_buf));
+        assert!(!pdb.stack.contains("STACK WIN "));
+
+        let inits: Vec<_> = pdb
+            .stack
+            .lines()
+            .filter_map(|line| line.strip_prefix("STACK CFI INIT "))
+            .map(|init| {
+                let mut toks = init.split(' ');
+                let mut next = || u32::from_str_radix(toks.next().unwrap(), 16).unwrap();
+                (next(), next())
+            })
+            .collect();
+        assert!(!inits.is_empty());
+        assert!(inits.iter().all(|init| functions.contains(init)));
+    }
+
+    // basic-opt32-omap.pdb is basic-opt32.pdb where the two halves of .text have been swapped as
+    // a post-link optimizer would do: 0x1000..0x2d059 is moved to 0x2c1a7..0x58200 and
+    // 0x2d059..0x58200 to 0x1000..0x2c1a7. It has the OMAP tables and the original sections and
+    // the sections of the image have no virtual size.
+    fn to_omap_rva(rva: u32) -> u32 {
+        match rva {
+            0x1000..=0x2d058 => rva + 0x2b1a7,
+            0x2d059..=0x581ff => rva - 0x2c059,
+            _ => rva,
+        }
+    }
+
+    fn get_omap_records(name: &str, translate: fn(u32) -> u32) -> Vec<Vec<String>> {
+        let buf = crate::utils::read_file(format!("./test_data/windows/{}.pdb", name));
+        let pdb = PDBInfo::new(&buf, name, "", None, None, Arc::default()).unwrap();
+        let mut output = Vec::new();
+        pdb.dump(Cursor::new(&mut output)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let mut records: Vec<Vec<String>> = Vec::new();
+        for line in output.lines() {
+            let mut toks: Vec<_> = line.split(' ').map(String::from).collect();
+            // The position of the address in the record
+            let pos = match toks[0].as_str() {
+                "MODULE" | "INFO" | "FILE" => continue,
+                "PUBLIC" | "FUNC" if toks[1] == "m" => 2,
+                "PUBLIC" | "FUNC" => 1,
+                "STACK" => 3,
+                _ => 0,
+            };
+            let rva = u32::from_str_radix(&toks[pos], 16).unwrap();
+            toks[pos] = format!("{:x}", translate(rva));
+            let record = toks.join(" ");
+            if pos == 0 {
+                records.last_mut().unwrap().push(record);
+            } else {
+                records.push(vec![record]);
+            }
+        }
+
+        // The dummy symbol is just after the last one so it's not at the same place
+        records.retain(|r| !r[0].ends_with(" <unknown>"));
+        records.sort();
+        records
+    }
+
+    #[test]
+    fn test_omap() {
+        let records = get_omap_records("basic-opt32-omap", |rva| rva);
+        let expected = get_omap_records("basic-opt32", to_omap_rva);
+
+        // Some functions with their lines and some publics
+        assert!(records
+            .iter()
+            .any(|r| r[0].starts_with("FUNC ") && r.len() > 1));
+        assert!(records.iter().any(|r| r[0].starts_with("PUBLIC ")));
+        assert_eq!(records.len(), expected.len());
+        for (record, expected) in records.iter().zip(expected.iter()) {
+            assert_eq!(record, expected);
+        }
+    }
+
     #[test]
     fn test_basic_opt32() {
         test_file("basic-opt32", TestFlags::ALL);
@@ -1122,6 +1981,7 @@ mod tests {
             &Some(vec![r"d:\\agent\\_work\\3\\s\\src\\(.*)"]),
             &Some(vec!["https://source/{rev}/{1}"]),
             &None,
+            None,
         )
         .unwrap();
         let dll = "basic32.dll";
@@ -1129,19 +1989,18 @@ mod tests {
         let bp = BreakpadObject::parse(&output).unwrap();
 
         let map = bp.file_map();
+        // The files are sorted by name
         let files: Vec<_> = map.values().collect();
+        assert!(files.windows(2).all(|w| w[0] <= w[1]));
 
-        assert_eq!(
-            files[6].replace('\\', "/"),
-            "https://source/abcdef/externalapis/windows/10/sdk/inc/winbase.h"
-        );
-        assert_eq!(
-            files[7].replace('\\', "/"),
-            "https://source/abcdef/externalapis/windows/10/sdk/inc/winerror.h"
-        );
-        assert_eq!(
-            files[files.len() - 1].replace('\\', "/"),
-            "https://source/abcdef/vctools/crt/vcruntime/src/string/i386/memcmp.c"
-        );
+        let files: Vec<_> = files.iter().map(|f| f.replace('\\', "/")).collect();
+
+        for file in &[
+            "https://source/abcdef/externalapis/windows/10/sdk/inc/winbase.h",
+            "https://source/abcdef/externalapis/windows/10/sdk/inc/winerror.h",
+            "https://source/abcdef/vctools/crt/vcruntime/src/string/i386/memcmp.c",
+        ] {
+            assert!(files.iter().any(|f| f == file), "{} not found", file);
+        }
     }
 }
@@ -6,8 +6,8 @@
 use hashbrown::{hash_map, HashMap};
 use log::error;
 use pdb::{
-    AddressMap, FallibleIterator, FileIndex, LineInfo, LineProgram, PdbInternalSectionOffset,
-    Result, Source, StringRef, StringTable, PDB,
+    AddressMap, FallibleIterator, FileChecksum, FileIndex, InlineeLineIterator, LineInfo,
+    LineProgram, PdbInternalSectionOffset, Result, Source, StringRef, StringTable, PDB,
 };
 use std::collections::BTreeMap;
 use std::fs;
@@ -15,15 +15,34 @@ use std::ops::Bound::{Excluded, Included};
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::checksums::{Checksum, ChecksumKind, Checksums};
 use crate::line::Lines;
 use crate::mapping::PathMappings;
+use crate::options::Options;
+use crate::sources::SourcePaths;
 use crate::utils;
 
-type RefToIds = HashMap<StringRef, u32>;
+use super::modules::{self, PdbSource};
+use super::srcsrv::SrcSrv;
 
+pub(super) type RefToIds = HashMap<StringRef, u32>;
+
+/// A line of an inline site (rva is the internal one)
+#[derive(Clone)]
+pub(super) struct InlineLine {
+    pub offset: PdbInternalSectionOffset,
+    pub rva: u32,
+    pub len: u32,
+    pub num: u32,
+    pub file_id: u32,
+}
+
+/// The lines of a module (the file ids are the ones of the SourceFiles and the string table
+/// is only used to get the file names when debugging)
 pub(super) struct SourceLineCollector<'a, 's> {
     address_map: &'a AddressMap<'s>,
-    source_files: &'a SourceFiles<'s>,
+    file_ids: &'a RefToIds,
+    string_table: Option<&'a StringTable<'s>>,
     lines: BTreeMap<(u16, u32), LineInfo>,
     line_program: LineProgram<'a>,
 }
@@ -31,7 +50,8 @@ pub(super) struct SourceLineCollector<'a, 's> {
 impl<'a, 's> SourceLineCollector<'a, 's> {
     pub(super) fn new(
         address_map: &'a AddressMap<'s>,
-        source_files: &'a SourceFiles<'s>,
+        file_ids: &'a RefToIds,
+        string_table: Option<&'a StringTable<'s>>,
         line_program: LineProgram<'a>,
     ) -> Result<Self> {
         let mut source_lines = BTreeMap::default();
@@ -49,7 +69,8 @@ impl<'a, 's> SourceLineCollector<'a, 's> {
 
         Ok(Self {
             address_map,
-            source_files,
+            file_ids,
+            string_table,
             lines: source_lines,
             line_program,
         })
@@ -71,13 +92,88 @@ impl<'a, 's> SourceLineCollector<'a, 's> {
             if last_file_index != line.file_index {
                 let file = self.line_program.get_file_info(line.file_index).unwrap();
                 last_file_index = line.file_index;
-                last_file_id = self.source_files.get_id(file.name);
+                last_file_id = self.file_ids[&file.name];
             }
             source_lines.add_line(rva.0, line.line_start, last_file_id);
         }
 
         source_lines
     }
+
+    fn get_file_id(&self, file_index: FileIndex) -> u32 {
+        let file = self.line_program.get_file_info(file_index).unwrap();
+        self.file_ids[&file.name]
+    }
+
+    /// Get the line and the file id of the last line starting before the given offset
+    pub(super) fn get_line_at(&self, offset: PdbInternalSectionOffset) -> Option<(u32, u32)> {
+        let (_, line) = self
+            .lines
+            .range(..=(offset.section, offset.offset))
+            .next_back()?;
+        if line.offset.section != offset.section {
+            return None;
+        }
+        Some((line.line_start, self.get_file_id(line.file_index)))
+    }
+
+    /// Get the lines of an inline site
+    pub(super) fn collect_inline_lines(
+        &self,
+        mut lines: InlineeLineIterator,
+    ) -> Result<Vec<InlineLine>> {
+        let mut inline_lines = Vec::new();
+        while let Some(line) = lines.next()? {
+            if let Some(rva) = line.offset.to_internal_rva(&self.address_map) {
+                inline_lines.push(InlineLine {
+                    offset: line.offset,
+                    rva: rva.0,
+                    len: line.length.unwrap_or(0),
+                    num: line.line_start,
+                    file_id: self.get_file_id(line.file_index),
+                });
+            }
+        }
+        inline_lines.sort_by_key(|line| (line.offset.section, line.offset.offset));
+
+        Ok(inline_lines)
+    }
+
+    /// The raw line fragments in the given range (used for debugging)
+    pub(super) fn debug_source_lines(&self, offset: PdbInternalSectionOffset, len: u32) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let start = (offset.section, offset.offset);
+        let end = (offset.section, offset.offset + len);
+
+        for (_, line) in self.lines.range((Included(&start), Excluded(&end))) {
+            let rva = line
+                .offset
+                .to_internal_rva(&self.address_map)
+                .map_or(0, |rva| rva.0);
+            let file = self
+                .line_program
+                .get_file_info(line.file_index)
+                .ok()
+                .and_then(|file| self.string_table?.get(file.name).ok())
+                .map(|name| name.to_string().into_owned())
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{:04x}:{:08x} internal_rva=0x{:x} len={:?} lines={}-{} {}",
+                line.offset.section,
+                line.offset.offset,
+                rva,
+                line.length,
+                line.line_start,
+                line.line_end,
+                file
+            );
+        }
+
+        out
+    }
 }
 
 #[derive(Debug)]
@@ -85,49 +181,88 @@ pub(super) struct SourceFiles<'a> {
     string_table: Option<StringTable<'a>>,
     ref_to_id: RefToIds,
     id_to_ref: Vec<StringRef>,
+    // The checksums by id (empty when they aren't emitted)
+    checksums: Vec<Option<Checksum>>,
     mapping: Option<Arc<PathMappings>>,
+    // The urls of the files in the srcsrv stream (when --srcsrv is used)
+    srcsrv: Option<SrcSrv>,
+}
+
+fn get_checksum(checksum: &FileChecksum) -> Option<Checksum> {
+    match checksum {
+        FileChecksum::None => None,
+        FileChecksum::Md5(value) => Checksum::new(ChecksumKind::Md5, value),
+        FileChecksum::Sha1(value) => Checksum::new(ChecksumKind::Sha1, value),
+        FileChecksum::Sha256(value) => Checksum::new(ChecksumKind::Sha256, value),
+    }
 }
 
 impl<'a> SourceFiles<'a> {
+    pub(super) fn empty() -> Self {
+        Self {
+            string_table: None,
+            ref_to_id: RefToIds::default(),
+            id_to_ref: Vec::new(),
+            checksums: Vec::new(),
+            mapping: None,
+            srcsrv: None,
+        }
+    }
+
+    /// The files of the modules are read in parallel with the PDB in source
     pub(super) fn new<S: 'a + Source<'a>>(
         pdb: &mut PDB<'a, S>,
+        source: PdbSource<'a>,
         mapping: Option<Arc<PathMappings>>,
+        options: &Options,
     ) -> Result<Self> {
         // The string table may be empty: not a problem
         let string_table = match pdb.string_table() {
             Ok(st) => st,
-            _ => {
-                return Ok(Self {
-                    string_table: None,
-                    ref_to_id: RefToIds::default(),
-                    id_to_ref: Vec::new(),
-                    mapping: None,
-                })
-            }
+            _ => return Ok(Self::empty()),
         };
 
         let dbi = pdb.debug_information()?;
-        let mut modules = dbi.modules()?;
+        let modules: Vec<_> = dbi.modules()?.collect()?;
+        let has_checksums = options.checksums;
+        let module_files =
+            modules::map_modules(options.num_threads, source, &modules, |reader, module| {
+                let module_info = match reader.pdb.module_info(module)? {
+                    Some(info) => info,
+                    _ => return Ok(Vec::new()),
+                };
+
+                let mut files = module_info.line_program()?.files();
+                let mut module_files = Vec::new();
+                while let Some(file) = files.next()? {
+                    let checksum = if has_checksums {
+                        get_checksum(&file.checksum)
+                    } else {
+                        None
+                    };
+                    module_files.push((file.name, checksum));
+                }
+                Ok(module_files)
+            });
+
         let mut ref_to_id = RefToIds::default();
         let mut id_to_ref = Vec::new();
+        let mut file_checksums = Vec::new();
         let mut id = 0;
 
         // Get all source files and generate an unique id for each one.
         // According to the docs: https://docs.rs/pdb/0.5.0/pdb/struct.PDB.html#method.string_table
         // the table contains deduplicated strings so each source file must have an unique StringRef.
-        while let Some(module) = modules.next()? {
-            let module_info = match pdb.module_info(&module)? {
-                Some(info) => info,
-                _ => continue,
-            };
-
-            let mut files = module_info.line_program()?.files();
-            while let Some(file) = files.next()? {
-                match ref_to_id.entry(file.name) {
+        for files in module_files {
+            for (name, checksum) in files? {
+                match ref_to_id.entry(name) {
                     hash_map::Entry::Occupied(_) => {}
                     hash_map::Entry::Vacant(e) => {
                         e.insert(id);
-                        id_to_ref.push(file.name);
+                        id_to_ref.push(name);
+                        if has_checksums {
+                            file_checksums.push(checksum);
+                        }
                         id += 1;
                     }
                 }
@@ -138,12 +273,19 @@ impl<'a> SourceFiles<'a> {
             string_table: Some(string_table),
             ref_to_id,
             id_to_ref,
+            checksums: file_checksums,
             mapping,
+            srcsrv: if options.srcsrv {
+                SrcSrv::from_pdb(pdb)
+            } else {
+                None
+            },
         })
     }
 
-    pub(super) fn get_id(&self, file_ref: StringRef) -> u32 {
-        *self.ref_to_id.get(&file_ref).unwrap()
+    /// The ids of the files (shared by the threads reading the modules)
+    pub(super) fn get_ids(&self) -> &RefToIds {
+        &self.ref_to_id
     }
 
     fn map(&self, file: String) -> String {
@@ -171,6 +313,13 @@ impl<'a> SourceFiles<'a> {
         } else {
             None
         };
+        // An explicit mapping wins over the srcsrv stream
+        let new_path = new_path.or_else(|| {
+            self.srcsrv
+                .as_ref()
+                .and_then(|srcsrv| srcsrv.get_target(&file))
+                .map(|url| url.to_string())
+        });
         new_path.unwrap_or(file)
     }
 
@@ -186,4 +335,40 @@ impl<'a> SourceFiles<'a> {
             Vec::new()
         }
     }
+
+    /// Get the paths of the files by name when the mapping has changed them
+    pub(super) fn get_paths(&self) -> SourcePaths {
+        let string_table = match self.string_table.as_ref() {
+            Some(string_table) => string_table,
+            _ => return SourcePaths::default(),
+        };
+        self.id_to_ref
+            .iter()
+            .filter_map(|file_ref| {
+                let path = string_table.get(*file_ref).ok()?.to_string().into_owned();
+                let name = self.map(path.clone());
+                if name == path {
+                    None
+                } else {
+                    Some((name, PathBuf::from(path)))
+                }
+            })
+            .collect()
+    }
+
+    /// Get the checksums of the files by name (empty when they aren't emitted)
+    pub(super) fn get_checksums(&self) -> Checksums {
+        let string_table = match self.string_table.as_ref() {
+            Some(string_table) => string_table,
+            _ => return Checksums::default(),
+        };
+        self.id_to_ref
+            .iter()
+            .zip(self.checksums.iter())
+            .filter_map(|(file_ref, checksum)| {
+                let name = string_table.get(*file_ref).ok()?.to_string().into_owned();
+                Some((self.map(name), checksum.clone()?))
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,185 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A source-indexed PDB has a srcsrv stream giving the command or the url to get each source file:
+//   SRCSRV: ini ------------------------------------------------
+//   VERSION=2
+//   SRCSRV: variables ------------------------------------------
+//   HGSERVER=https://hg.mozilla.org/mozilla-central
+//   SRCSRVTRG=%hgserver%/raw-file/%var3%/%var2%
+//   SRCSRV: source files ---------------------------------------
+//   c:\build\src\dom\foo.cpp*dom/foo.cpp*abcdef
+//   SRCSRV: end ------------------------------------------------
+// With --srcsrv, the FILE records are the targets (%var1% is the path in the PDB, %var2% the
+// second field, ...) when the path isn't mapped with --mapping-*.
+
+use hashbrown::HashMap;
+use log::warn;
+use pdb::{Source, PDB};
+
+// The variables can reference each other but not forever
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Default)]
+pub(super) struct SrcSrv {
+    // The targets by path (in lower case)
+    targets: HashMap<String, String>,
+}
+
+/// The names of the variables are case-insensitive
+struct Expander<'a> {
+    variables: &'a HashMap<String, String>,
+    fields: Vec<&'a str>,
+}
+
+impl Expander<'_> {
+    fn get_var(&self, name: &str, depth: usize) -> Option<String> {
+        let name = name.to_lowercase();
+        if let Some(n) = name.strip_prefix("var") {
+            if let Ok(n) = n.parse::<usize>() {
+                return Some(self.fields.get(n.checked_sub(1)?)?.to_string());
+            }
+        }
+        self.expand(self.variables.get(&name)?, depth + 1)
+    }
+
+    fn call(&self, function: &str, arg: &str, depth: usize) -> Option<String> {
+        let arg = self.expand(arg, depth + 1)?;
+        match function {
+            "fnvar" => self.get_var(&arg, depth),
+            "fnbksl" => Some(arg.replace('/', "\\")),
+            "fnfile" => Some(
+                arg.rsplit(['\\', '/'])
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Replace the %variables% and the %functions%(arguments): None when one is unknown
+    fn expand(&self, s: &str, depth: usize) -> Option<String> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let mut res = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('%') {
+            res.push_str(&rest[..start]);
+            let end = rest[start + 1..].find('%')? + start + 1;
+            let name = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+
+            let function = name.to_lowercase();
+            if function.starts_with("fn") && rest.starts_with('(') {
+                let close = rest.find(')')?;
+                res.push_str(&self.call(&function, &rest[1..close], depth)?);
+                rest = &rest[close + 1..];
+            } else {
+                res.push_str(&self.get_var(name, depth)?);
+            }
+        }
+        res.push_str(rest);
+        Some(res)
+    }
+}
+
+impl SrcSrv {
+    pub(super) fn parse(data: &str) -> Self {
+        let mut variables = HashMap::new();
+        let mut targets = HashMap::new();
+        let mut section = "";
+        for line in data.lines().map(|l| l.trim_end_matches('\r')) {
+            if let Some(header) = line.strip_prefix("SRCSRV: ") {
+                section = header.split(' ').next().unwrap_or_default();
+                continue;
+            }
+            match section {
+                "ini" | "variables" => {
+                    if let Some((name, value)) = line.split_once('=') {
+                        variables.insert(name.trim().to_lowercase(), value.to_string());
+                    }
+                }
+                "source" if !line.is_empty() => {
+                    let expander = Expander {
+                        variables: &variables,
+                        fields: line.split('*').collect(),
+                    };
+                    match expander.get_var("srcsrvtrg", 0) {
+                        Some(target) => {
+                            targets.insert(expander.fields[0].to_lowercase(), target);
+                        }
+                        None => warn!("Invalid srcsrv entry: {}", line),
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self { targets }
+    }
+
+    /// Get the srcsrv stream of the PDB (if any)
+    pub(super) fn from_pdb<'s, S: 's + Source<'s>>(pdb: &mut PDB<'s, S>) -> Option<Self> {
+        let stream = pdb.named_stream(b"srcsrv").ok()?;
+        let srcsrv = Self::parse(&String::from_utf8_lossy(stream.as_slice()));
+        Some(srcsrv).filter(|s| !s.targets.is_empty())
+    }
+
+    pub(super) fn get_target(&self, path: &str) -> Option<&str> {
+        self.targets.get(&path.to_lowercase()).map(|t| t.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_srcsrv() {
+        let srcsrv = SrcSrv::parse(
+            "SRCSRV: ini ------------------------------------------------\r\n\
+             VERSION=2\r\n\
+             VERCTRL=http\r\n\
+             SRCSRV: variables ------------------------------------------\r\n\
+             HGSERVER=https://hg.mozilla.org/mozilla-central\r\n\
+             HTTP_EXTRACT_TARGET=%HGSERVER%/raw-file/%var3%/%var2%\r\n\
+             SRCSRVTRG=%http_extract_target%\r\n\
+             SRCSRV: source files ---------------------------------------\r\n\
+             c:\\build\\src\\dom\\Foo.cpp*dom/Foo.cpp*abcdef\r\n\
+             c:\\build\\src\\bar.cpp*bar.cpp\r\n\
+             SRCSRV: end ------------------------------------------------\r\n",
+        );
+        assert_eq!(
+            srcsrv.get_target("C:\\build\\src\\dom\\foo.cpp"),
+            Some("https://hg.mozilla.org/mozilla-central/raw-file/abcdef/dom/Foo.cpp")
+        );
+        // No %var3%
+        assert_eq!(srcsrv.get_target("c:\\build\\src\\bar.cpp"), None);
+
+        let variables = [
+            (
+                "srcsrvtrg".to_string(),
+                "%targ%\\%fnbksl%(%var2%)\\%fnfile%(%var1%)".to_string(),
+            ),
+            ("targ".to_string(), "%fnvar%(%var3%)".to_string()),
+            ("cache".to_string(), "c:\\src".to_string()),
+            ("loop".to_string(), "%loop%".to_string()),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let expander = Expander {
+            variables: &variables,
+            fields: vec!["d:\\a\\b.cpp", "x/y", "CACHE"],
+        };
+        assert_eq!(
+            expander.get_var("SRCSRVTRG", 0).unwrap(),
+            "c:\\src\\x\\y\\b.cpp"
+        );
+        assert!(expander.get_var("loop", 0).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// With --stack-win-cfi, the STACK WIN records with a program string (the x86 FrameData) get a STACK
+// CFI INIT record too, for the stackwalkers which only understand the CFI:
+//   STACK WIN 4 1000 20 4 0 8 0 4 0 1 $T0 $ebp = $eip $T0 4 + ^ = $ebp $T0 ^ = $esp $T0 8 + =
+//   STACK CFI INIT 1000 20 .cfa: $ebp 8 + .ra: $ebp 4 + ^ $ebp: $ebp ^
+// The program is evaluated symbolically: the temporaries are replaced by their expressions, the new
+// $esp is the CFA and the new $eip the return address. There is no search in the CFI, so .raSearch
+// is the address where the search starts ($esp + locals + saved registers).
+
+use hashbrown::HashMap;
+
+// The callee-saved registers which can be restored
+const REGISTERS: [&str; 4] = ["$ebp", "$ebx", "$esi", "$edi"];
+
+/// An operand of the program: the variable (if any) and its expression
+struct Operand<'a> {
+    name: Option<&'a str>,
+    expr: Vec<String>,
+}
+
+fn get_rules(program: &str, params: u32, saved_regs: u32, locals: u32) -> Option<String> {
+    let mut vars: HashMap<&str, Vec<String>> = HashMap::new();
+    let mut stack: Vec<Operand> = Vec::new();
+    let value = |expr: Vec<String>| Operand { name: None, expr };
+
+    for token in program.split_whitespace() {
+        match token {
+            "=" => {
+                let expr = stack.pop()?.expr;
+                let name = stack.pop()?.name?;
+                vars.insert(name, expr);
+            }
+            "+" | "-" | "*" | "/" | "%" | "@" => {
+                let b = stack.pop()?.expr;
+                let mut a = stack.pop()?.expr;
+                a.extend(b);
+                a.push(token.to_string());
+                stack.push(value(a));
+            }
+            "^" => {
+                let mut a = stack.pop()?.expr;
+                a.push(token.to_string());
+                stack.push(value(a));
+            }
+            ".cbParams" => stack.push(value(vec![params.to_string()])),
+            ".cbSavedRegs" => stack.push(value(vec![saved_regs.to_string()])),
+            ".cbLocals" => stack.push(value(vec![locals.to_string()])),
+            ".raSearch" | ".raSearchStart" => {
+                let mut expr = vec!["$esp".to_string()];
+                if locals + saved_regs != 0 {
+                    expr.extend([(locals + saved_regs).to_string(), "+".to_string()]);
+                }
+                stack.push(value(expr));
+            }
+            _ if token.starts_with('.') => return None,
+            _ if token.starts_with('$') => {
+                let expr = vars
+                    .get(token)
+                    .cloned()
+                    .unwrap_or_else(|| vec![token.to_string()]);
+                stack.push(Operand {
+                    name: Some(token),
+                    expr,
+                });
+            }
+            _ => stack.push(value(vec![token.to_string()])),
+        }
+    }
+    if !stack.is_empty() {
+        return None;
+    }
+
+    let mut rules = format!(
+        ".cfa: {} .ra: {}",
+        vars.get("$esp")?.join(" "),
+        vars.get("$eip")?.join(" ")
+    );
+    for register in REGISTERS.iter() {
+        // A register which keeps its value doesn't need a rule
+        if let Some(expr) = vars.get(register).filter(|expr| expr[..] != [*register]) {
+            rules.push_str(&format!(" {}: {}", register, expr.join(" ")));
+        }
+    }
+    Some(rules)
+}
+
+/// Get the STACK CFI INIT record of a STACK WIN one with a program string
+fn get_cfi(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("STACK WIN 4 ")?;
+    let mut fields = rest.splitn(10, ' ');
+    let rva = fields.next()?;
+    let code_size = fields.next()?;
+    let mut sizes = [0; 6];
+    for size in sizes.iter_mut() {
+        *size = u32::from_str_radix(fields.next()?, 16).ok()?;
+    }
+    let [_, _, params, saved_regs, locals, _] = sizes;
+    let program = match (fields.next()?, fields.next()) {
+        ("1", Some(program)) => program,
+        _ => return None,
+    };
+    let rules = get_rules(program, params, saved_regs, locals)?;
+    Some(format!("STACK CFI INIT {} {} {}\n", rva, code_size, rules))
+}
+
+/// Get the STACK CFI INIT records of the STACK WIN records which can be translated
+pub(super) fn get_stack_cfi(stack: &str) -> String {
+    stack.lines().filter_map(get_cfi).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_stack_win_cfi() {
+        let stack = "STACK WIN 4 1000 20 4 0 8 0 4 0 1 $T0 $ebp = $eip $T0 4 + ^ = $ebp $T0 ^ = $esp $T0 8 + =\n\
+                     STACK WIN 4 1100 10 0 0 4 c 8 0 1 $T0 .raSearch = $eip $T0 ^ = $esp $T0 4 + = $ebx $T0 .cbLocals - 4 - ^ =\n\
+                     STACK WIN 0 1200 10 0 0 0 0 0 0 0\n\
+                     STACK WIN 4 1300 10 0 0 0 0 0 0 1 $T0 .unknown = $eip $T0 ^ = $esp $T0 4 + =\n\
+                     STACK WIN 4 1400 10 0 0 0 0 0 0 1 $T0 $ebp =\n\
+                     STACK WIN 4 1500 10 0 0 0 0 0 0 1 $T0 $ebp = $T1 .raSearchStart = $eip $T1 ^ = $ebp $T0 = $esp $T1 4 + =\n";
+        assert_eq!(
+            get_stack_cfi(stack),
+            "STACK CFI INIT 1000 20 .cfa: $ebp 8 + .ra: $ebp 4 + ^ $ebp: $ebp ^\n\
+             STACK CFI INIT 1100 10 .cfa: $esp 20 + 4 + .ra: $esp 20 + ^ $ebx: $esp 20 + 8 - 4 - ^\n\
+             STACK CFI INIT 1500 10 .cfa: $esp 4 + .ra: $esp ^\n"
+        );
+    }
+}
@@ -5,19 +5,25 @@
 
 use hashbrown::{hash_map, HashMap};
 use pdb::{
-    AddressMap, FrameTable, PdbInternalRva, PdbInternalSectionOffset, ProcedureSymbol,
-    PublicSymbol, RegisterRelativeSymbol, TypeIndex,
+    AddressMap, FrameTable, PdbInternalRva, PdbInternalSectionOffset, PublicSymbol, TypeIndex,
 };
 use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::rc::Rc;
-use symbolic::debuginfo::pe::{ExceptionData, PeSymbolIterator};
+use std::sync::Arc;
+use symbolic::debuginfo::pe::PeSymbolIterator;
 
 use super::pdb::{PDBContributions, PDBSections};
-use super::source::SourceLineCollector;
+use super::source::InlineLine;
 use super::types::{FuncName, TypeDumper};
 use crate::common::LineFinalizer;
+use crate::debug_records;
+use crate::demangling::Demangling;
+use crate::folding::{self, Folded, FoldingPolicy};
+use crate::inlines::Inline;
 use crate::line::Lines;
+use crate::options::Options;
+use crate::publics::{self, Public, Publics, PublicsPolicy};
+use crate::visitor::{self, FuncRecord, PublicRecord, Visitor};
 
 pub(super) struct BlockInfo {
     pub rva: u32,
@@ -27,6 +33,14 @@ pub(super) struct BlockInfo {
 
 pub(super) type PDBSymbols = BTreeMap<u32, PDBSymbol>;
 
+/// A procedure read in a module with the lines of its block
+pub(super) struct Procedure {
+    pub name: String,
+    pub type_index: TypeIndex,
+    pub offset: PdbInternalSectionOffset,
+    pub source: Lines,
+}
+
 #[derive(Clone, Debug)]
 pub(super) struct EBPInfo {
     type_index: TypeIndex,
@@ -44,6 +58,7 @@ pub(super) struct SelectedSymbol {
     pub len: u32,
     pub parameter_size: u32,
     pub source: Lines,
+    pub inlines: Vec<Inline>,
     pub ebp: Vec<EBPInfo>,
     pub id: usize,
 }
@@ -56,13 +71,11 @@ pub(super) struct PDBSymbol {
     pub rva: u32,
     pub len: u32,
     pub parameter_size: u32,
-    pub source: Rc<Lines>,
+    pub source: Arc<Lines>,
+    pub inlines: Vec<Inline>,
     pub id: usize,
 }
 
-// it's safe because source (with Rc) isn't shared: it's just an internal thing
-unsafe impl Send for PDBSymbol {}
-
 impl PDBSymbol {
     fn get_from(&self, rva: u32, len: u32) -> PDBSymbol {
         PDBSymbol {
@@ -73,44 +86,74 @@ impl PDBSymbol {
             len,
             parameter_size: self.parameter_size,
             source: if let Some(source) = self.source.retain(rva, len) {
-                Rc::new(source)
+                Arc::new(source)
             } else {
-                Rc::clone(&self.source)
+                Arc::clone(&self.source)
             },
+            inlines: Inline::retain(&self.inlines, rva, len),
             id: self.id,
         }
     }
 }
 
-impl Display for PDBSymbol {
+impl Display for Folded<'_, PDBSymbol> {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        if self.is_public {
+        let Folded(sym, policy) = *self;
+        if sym.is_public {
             writeln!(
                 f,
                 "PUBLIC {}{:x} {:x} {}",
-                if self.is_multiple { "m " } else { "" },
-                self.rva,
-                self.parameter_size,
-                self.name,
+                folding::get_marker(sym.is_multiple, policy),
+                sym.rva,
+                sym.parameter_size,
+                sym.name,
             )?;
         } else {
             writeln!(
                 f,
                 "FUNC {}{:x} {:x} {:x} {}",
-                if self.is_multiple { "m " } else { "" },
-                self.rva,
-                self.len,
-                self.parameter_size,
-                self.name,
+                folding::get_marker(sym.is_multiple, policy),
+                sym.rva,
+                sym.len,
+                sym.parameter_size,
+                sym.name,
             )?;
 
-            write!(f, "{}", self.source)?;
+            for inline in sym.inlines.iter() {
+                write!(f, "{}", inline)?;
+            }
+            write!(f, "{}", sym.source)?;
         }
 
         Ok(())
     }
 }
 
+impl Folded<'_, PDBSymbol> {
+    /// Visit the records written for the symbol
+    pub(super) fn visit(&self, visitor: &mut dyn Visitor) {
+        let Folded(sym, policy) = *self;
+        let multiple = folding::is_multiple(sym.is_multiple, policy);
+        if sym.is_public {
+            visitor.public(&PublicRecord {
+                address: u64::from(sym.rva),
+                parameter_size: u64::from(sym.parameter_size),
+                multiple,
+                name: sym.name.clone(),
+            });
+        } else {
+            let func = FuncRecord {
+                address: u64::from(sym.rva),
+                size: u64::from(sym.len),
+                parameter_size: u64::from(sym.parameter_size),
+                multiple,
+                name: sym.name.clone(),
+            };
+            visitor::visit_func(visitor, func, &sym.inlines, &sym.source);
+        }
+    }
+}
+
 impl SelectedSymbol {
     fn get_und(&self, dumper: &TypeDumper) -> FuncName {
         dumper
@@ -163,25 +206,33 @@ impl SelectedSymbol {
         sps
     }
 
-    pub(super) fn update_private(
-        &mut self,
-        function: ProcedureSymbol,
-        block_info: BlockInfo,
-        line_collector: &SourceLineCollector,
-    ) {
+    pub(super) fn update_private(&mut self, function: Procedure, block_info: BlockInfo) {
         self.is_multiple = true;
 
         // TODO: this is legacy code
         // this is probably useless.
-        let fun_name = function.name.to_string().into_owned();
-
-        if fun_name < self.name {
-            self.name = fun_name;
+        if function.name < self.name {
+            self.name = function.name;
             self.type_index = function.type_index;
             self.offset = block_info.offset;
             self.len = block_info.len;
-            self.source = line_collector.collect_source_lines(block_info.offset, block_info.len);
+            self.source = function.source;
+            self.inlines.clear();
+        }
+    }
+
+    /// Add an inline site: its lines replace the ones of the call location
+    pub(super) fn add_inline(&mut self, inline: Inline, lines: &[InlineLine]) {
+        for line in lines {
+            match self.source.lines.iter_mut().find(|l| l.rva == line.rva) {
+                Some(l) => {
+                    l.num = line.num;
+                    l.file_id = line.file_id;
+                }
+                _ => self.source.add_line(line.rva, line.num, line.file_id),
+            }
         }
+        self.inlines.push(inline);
     }
 
     pub(super) fn update_public(&mut self, symbol: PublicSymbol) {
@@ -228,7 +279,12 @@ impl SelectedSymbol {
             FuncName::Unknown((name, sps)) => (name, sps),
         };
 
-        self.source.finalize(rva, self.len, address_map);
+        // The rvas of the lines are the internal ones (before the OMAP translation)
+        let internal_rva = self
+            .offset
+            .to_internal_rva(address_map)
+            .map_or(rva, |rva| rva.0);
+        self.source.finalize(internal_rva, self.len, address_map);
 
         (
             PDBSymbol {
@@ -238,7 +294,8 @@ impl SelectedSymbol {
                 rva: 0,
                 len: self.len,
                 parameter_size: stack_param_size,
-                source: Rc::new(self.source),
+                source: Arc::new(self.source),
+                inlines: self.inlines,
                 id: self.id,
             },
             self.offset,
@@ -252,6 +309,8 @@ pub(super) struct RvaSymbols {
     rva: u32,
     symbol: Option<SelectedSymbol>,
     last_id: usize,
+    // The publics at the address of a procedure (with --keep-publics)
+    publics: Publics,
 }
 
 impl RvaSymbols {
@@ -259,24 +318,17 @@ impl RvaSymbols {
         self.map.get(&rva)
     }
 
-    pub(super) fn add_procedure_symbol(
-        &mut self,
-        line_collector: &SourceLineCollector,
-        function: ProcedureSymbol,
-        block_info: BlockInfo,
-    ) {
+    pub(super) fn add_procedure_symbol(&mut self, function: Procedure, block_info: BlockInfo) {
         // Since several symbols may have the same rva (because they've the same disassembly code)
         // we need to "select" the a symbol for a rva.
         // Anyway it could lead to strange backtraces.
 
-        let fun_name = function.name.to_string().into_owned();
         if let Some(selected) = self.map.get_mut(&block_info.rva) {
-            selected.update_private(function, block_info, line_collector);
+            selected.update_private(function, block_info);
         } else {
-            let source = line_collector.collect_source_lines(block_info.offset, block_info.len);
             self.rva = block_info.rva;
             self.symbol = Some(SelectedSymbol {
-                name: fun_name,
+                name: function.name,
                 type_index: function.type_index,
                 is_public: false,
                 is_multiple: false,
@@ -284,7 +336,8 @@ impl RvaSymbols {
                 sym_offset: Some(function.offset),
                 len: block_info.len,
                 parameter_size: 0,
-                source,
+                source: function.source,
+                inlines: Vec::new(),
                 ebp: Vec::new(),
                 id: self.last_id,
             });
@@ -314,6 +367,7 @@ impl RvaSymbols {
         pdb_sections: &PDBSections,
         pdb_contributions: &PDBContributions,
         address_map: &AddressMap,
+        options: &Options,
     ) {
         let rva = match symbol.offset.to_rva(address_map) {
             Some(rva) => rva,
@@ -322,8 +376,7 @@ impl RvaSymbols {
 
         if symbol.code
             || symbol.function
-            || (pdb_sections.is_code(symbol.offset.section)
-                && pdb_contributions.is_code(symbol.offset.section, symbol.offset.offset))
+            || (pdb_sections.is_code(rva.0) && pdb_contributions.is_code(rva.0))
         {
             let sym_name = symbol.name.to_string().into_owned();
 
@@ -336,6 +389,18 @@ impl RvaSymbols {
             match self.map.entry(rva.0) {
                 hash_map::Entry::Occupied(selected) => {
                     let selected = selected.into_mut();
+                    if !selected.is_public && options.publics_policy == PublicsPolicy::Keep {
+                        let demangled_name = TypeDumper::demangle(&sym_name, options.demangling);
+                        let (name, parameter_size) = match demangled_name {
+                            FuncName::Undecorated(name) => (name, 0),
+                            FuncName::Unknown((name, parameter_size)) => (name, parameter_size),
+                        };
+                        self.publics.entry(rva.0).or_insert(Public {
+                            rva: rva.0,
+                            parameter_size,
+                            name,
+                        });
+                    }
                     selected.update_public(symbol);
                 }
                 hash_map::Entry::Vacant(e) => {
@@ -350,6 +415,7 @@ impl RvaSymbols {
                         len: 0,
                         parameter_size: 0,
                         source: Lines::new(),
+                        inlines: Vec::new(),
                         ebp: Vec::new(),
                         id: self.last_id,
                     });
@@ -371,15 +437,20 @@ impl RvaSymbols {
         }
     }
 
-    pub(super) fn add_ebp(&mut self, ebp: RegisterRelativeSymbol) {
+    pub(super) fn add_ebp(&mut self, type_index: TypeIndex, offset: i32) {
         if let Some(symbol) = self.symbol.as_mut() {
             symbol.ebp.push(EBPInfo {
-                type_index: ebp.type_index,
-                offset: ebp.offset as u32,
+                type_index,
+                offset: offset as u32,
             });
         }
     }
 
+    /// The procedure whose symbols are being read
+    pub(super) fn get_procedure(&mut self) -> Option<&mut SelectedSymbol> {
+        self.symbol.as_mut()
+    }
+
     pub(super) fn close_procedure(&mut self) {
         if let Some(symbol) = self.symbol.take() {
             self.map.insert(self.rva, symbol);
@@ -449,6 +520,11 @@ impl RvaSymbols {
         syms
     }
 
+    /// Get the publics at the address of a procedure
+    pub(super) fn take_publics(&mut self) -> Publics {
+        std::mem::take(&mut self.publics)
+    }
+
     pub(super) fn mv_to_pdb_symbols(
         self,
         dumper: TypeDumper,
@@ -464,6 +540,36 @@ impl RvaSymbols {
     }
 }
 
+/// Print the emitted records for the symbols matching the --debug-records filter
+pub(super) fn debug_emitted_records(syms: &PDBSymbols, policy: FoldingPolicy) {
+    for sym in syms.values() {
+        if debug_records::matches(&sym.name, sym.rva as u64, sym.len as u64) {
+            debug_records::print(
+                "Emitted records",
+                &sym.name,
+                sym.rva as u64,
+                &Folded(sym, policy).to_string(),
+            );
+        }
+    }
+}
+
+/// Set the new file ids (the lines shared by the pieces of a split function are copied)
+/// Remove the PUBLIC records inside the range of a FUNC
+pub(super) fn remove_covered_publics(syms: &mut PDBSymbols) {
+    let covered = publics::get_covered_publics(syms.values().map(|s| (s.rva, s.len, s.is_public)));
+    for rva in covered {
+        syms.remove(&rva);
+    }
+}
+
+pub(super) fn remap_files(syms: &mut PDBSymbols, remapping: &[u32]) {
+    for sym in syms.values_mut() {
+        Arc::make_mut(&mut sym.source).remap_files(remapping);
+        Inline::remap_files(&mut sym.inlines, remapping);
+    }
+}
+
 pub(super) fn append_dummy_symbol(mut syms: PDBSymbols, name: &str) -> PDBSymbols {
     let (rva, len, id) = if let Some((_, last_sym)) = syms.iter().next_back() {
         (last_sym.rva, last_sym.len, last_sym.id)
@@ -488,7 +594,8 @@ pub(super) fn append_dummy_symbol(mut syms: PDBSymbols, name: &str) -> PDBSymbol
             rva,
             len: 0,
             parameter_size: 0,
-            source: Rc::new(Lines::new()),
+            source: Arc::new(Lines::new()),
+            inlines: Vec::new(),
             id: id + 1,
         },
     );
@@ -498,8 +605,10 @@ pub(super) fn append_dummy_symbol(mut syms: PDBSymbols, name: &str) -> PDBSymbol
 
 pub(super) fn symbolic_to_pdb_symbols(
     syms: PeSymbolIterator,
-    exception_data: Option<&ExceptionData<'_>>,
+    functions: Vec<(u32, u32)>,
+    map_symbols: Vec<(u32, String)>,
     module_name: &str,
+    demangling: Demangling,
 ) -> PDBSymbols {
     let mut pdb_syms = PDBSymbols::default();
 
@@ -509,30 +618,29 @@ pub(super) fn symbolic_to_pdb_symbols(
         format!("<unknown in {}>", module_name)
     };
 
-    if let Some(data) = exception_data {
-        data.into_iter()
-            .filter_map(|result| result.ok())
-            .filter(|function| function.end_address > function.begin_address)
-            .for_each(|function| {
-                pdb_syms.insert(
-                    function.begin_address,
-                    PDBSymbol {
-                        name: module_name.clone(),
-                        is_public: false,
-                        is_multiple: false,
-                        rva: function.begin_address,
-                        len: function.end_address - function.begin_address,
-                        parameter_size: 0,
-                        source: Rc::new(Lines::new()),
-                        id: 0,
-                    },
-                );
-            });
-    };
+    functions
+        .into_iter()
+        .filter(|(begin, end)| end > begin)
+        .for_each(|(begin, end)| {
+            pdb_syms.insert(
+                begin,
+                PDBSymbol {
+                    name: module_name.clone(),
+                    is_public: false,
+                    is_multiple: false,
+                    rva: begin,
+                    len: end - begin,
+                    parameter_size: 0,
+                    source: Arc::new(Lines::new()),
+                    inlines: Vec::new(),
+                    id: 0,
+                },
+            );
+        });
 
     for sym in syms {
         if let Some(name) = sym.name() {
-            let demangled_name = TypeDumper::demangle(&name);
+            let demangled_name = TypeDumper::demangle(&name, demangling);
             let (name, parameter_size) = match demangled_name {
                 FuncName::Undecorated(name) => (name, 0),
                 FuncName::Unknown((name, parameter_size)) => (name, parameter_size),
@@ -551,11 +659,35 @@ pub(super) fn symbolic_to_pdb_symbols(
                     rva: sym.address as u32,
                     len: 0,
                     parameter_size,
-                    source: Rc::new(Lines::new()),
+                    source: Arc::new(Lines::new()),
+                    inlines: Vec::new(),
                     id: 0,
                 });
         }
     }
 
+    // The symbols from a map file are only used to name the functions
+    // we know nothing about (e.g. for Delphi binaries).
+    for (rva, name) in map_symbols {
+        pdb_syms
+            .entry(rva)
+            .and_modify(|e| {
+                if e.name == module_name {
+                    e.name = name.clone();
+                }
+            })
+            .or_insert(PDBSymbol {
+                name,
+                is_public: true,
+                is_multiple: false,
+                rva,
+                len: 0,
+                parameter_size: 0,
+                source: Arc::new(Lines::new()),
+                inlines: Vec::new(),
+                id: 0,
+            });
+    }
+
     pdb_syms
 }
@@ -0,0 +1,188 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The types of some modules aren't in the TPI stream but in a type server PDB (e.g. vc140.pdb):
+// an LF_TYPESERVER2 record gives its GUID, its age and its path on the build machine.
+// The type server is searched at this path and then in the directories of --type-server-path,
+// and it's used only when its GUID and its age are the ones of the record.
+// The pdb crate doesn't parse these records, so they're read in the raw TPI stream:
+//   header (its size is the second u32), then some records: length (u16), kind (u16), data.
+
+use log::{info, warn};
+use pdb::{FallibleIterator, Source, StreamIndex, TypeInformation, PDB};
+use std::convert::TryInto;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::utils::{self, FileBuffer};
+
+const TPI_STREAM: StreamIndex = StreamIndex(2);
+const LF_TYPESERVER2: u16 = 0x1515;
+
+/// An LF_TYPESERVER2 record
+#[derive(Debug, PartialEq)]
+struct TypeServerRef {
+    guid: Uuid,
+    age: u32,
+    path: String,
+}
+
+impl TypeServerRef {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let guid = Uuid::from_fields(
+            u32::from_le_bytes(data.get(..4)?.try_into().ok()?),
+            u16::from_le_bytes(data.get(4..6)?.try_into().ok()?),
+            u16::from_le_bytes(data.get(6..8)?.try_into().ok()?),
+            data.get(8..16)?,
+        )
+        .ok()?;
+        let age = u32::from_le_bytes(data.get(16..20)?.try_into().ok()?);
+        let name = data.get(20..)?;
+        let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        Some(Self {
+            guid,
+            age,
+            path: String::from_utf8_lossy(&name[..end]).into_owned(),
+        })
+    }
+
+    /// The recorded path and then the file in each search directory
+    fn get_candidates(&self, dirs: &[PathBuf]) -> Vec<PathBuf> {
+        let filename = self.path.rsplit(['\\', '/']).next().unwrap_or_default();
+        let mut candidates = vec![PathBuf::from(&self.path)];
+        candidates.extend(dirs.iter().map(|dir| dir.join(filename)));
+        candidates
+    }
+
+    fn matches(&self, path: &Path) -> Option<FileBuffer> {
+        let buf = utils::try_map_file(path).ok()?;
+        let (guid, age) = {
+            let mut pdb = PDB::open(Cursor::new(&buf[..])).ok()?;
+            let pi = pdb.pdb_information().ok()?;
+            (pi.guid, pi.age)
+        };
+        if guid == self.guid && age == self.age {
+            Some(buf)
+        } else {
+            warn!(
+                "The type server {} isn't the expected one (guid or age mismatch)",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+fn parse_type_server_refs(tpi: &[u8]) -> Vec<TypeServerRef> {
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let bytes = tpi.get(pos..pos + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let mut refs = Vec::new();
+    let mut pos = match tpi.get(4..8) {
+        Some(size) => u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize,
+        _ => return refs,
+    };
+    while let Some(len) = read_u16(pos) {
+        let len = len as usize;
+        if read_u16(pos + 2) == Some(LF_TYPESERVER2) {
+            if let Some(r) = tpi
+                .get(pos + 4..pos + 2 + len)
+                .and_then(TypeServerRef::parse)
+            {
+                refs.push(r);
+            }
+        }
+        pos += 2 + len;
+    }
+    refs
+}
+
+/// Get the data of the type servers referenced by the types of the PDB (dirs are the ones
+/// given with --type-server-path)
+pub(super) fn get_type_servers<'s, S: 's + Source<'s>>(
+    pdb: &mut PDB<'s, S>,
+    type_info: &TypeInformation,
+    dirs: &[PathBuf],
+) -> Vec<FileBuffer> {
+    let mut types = type_info.iter();
+    let mut has_refs = false;
+    while let Ok(Some(typ)) = types.next() {
+        if typ.raw_kind() == LF_TYPESERVER2 {
+            has_refs = true;
+            break;
+        }
+    }
+    if !has_refs {
+        return Vec::new();
+    }
+
+    let tpi = match pdb.raw_stream(TPI_STREAM) {
+        Ok(Some(stream)) => stream,
+        _ => return Vec::new(),
+    };
+    parse_type_server_refs(tpi.as_slice())
+        .iter()
+        .filter_map(|r| {
+            let buf = r.get_candidates(dirs).iter().find_map(|path| {
+                let buf = r.matches(path)?;
+                info!("Types from the type server {}", path.display());
+                Some(buf)
+            });
+            if buf.is_none() {
+                warn!(
+                    "Type server {} not found: add its directory with --type-server-path",
+                    r.path
+                );
+            }
+            buf
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_type_server_refs() {
+        let guid = Uuid::parse_str("01234567-89ab-cdef-0123-456789abcdef").unwrap();
+        let mut record = LF_TYPESERVER2.to_le_bytes().to_vec();
+        let (d1, d2, d3, d4) = guid.as_fields();
+        record.extend_from_slice(&d1.to_le_bytes());
+        record.extend_from_slice(&d2.to_le_bytes());
+        record.extend_from_slice(&d3.to_le_bytes());
+        record.extend_from_slice(d4);
+        record.extend_from_slice(&3u32.to_le_bytes());
+        record.extend_from_slice(b"c:\\build\\vc140.pdb\0\xf1");
+
+        // A header with its size only, a pointer and the type server
+        let mut tpi = vec![0; 8];
+        tpi[4] = 8;
+        tpi.extend_from_slice(&[6, 0, 0x02, 0x10, 0, 0, 0, 0]);
+        tpi.extend_from_slice(&(record.len() as u16).to_le_bytes());
+        tpi.extend_from_slice(&record);
+
+        let refs = parse_type_server_refs(&tpi);
+        assert_eq!(
+            refs,
+            vec![TypeServerRef {
+                guid,
+                age: 3,
+                path: "c:\\build\\vc140.pdb".to_string(),
+            }]
+        );
+
+        assert_eq!(
+            refs[0].get_candidates(&[PathBuf::from("/tmp/types")]),
+            vec![
+                PathBuf::from("c:\\build\\vc140.pdb"),
+                PathBuf::from("/tmp/types/vc140.pdb")
+            ]
+        );
+    }
+}
@@ -7,15 +7,16 @@ use bitflags::bitflags;
 use hashbrown::HashMap;
 use log::{error, warn};
 use pdb::{
-    ArgumentList, ArrayType, ClassKind, ClassType, FallibleIterator, FunctionAttributes,
-    MemberFunctionType, ModifierType, PointerMode, PointerType, PrimitiveKind, PrimitiveType,
-    ProcedureType, RawString, Result, TypeData, TypeFinder, TypeIndex, TypeInformation, UnionType,
-    Variant,
+    ArgumentList, ArrayType, ClassKind, ClassType, FallibleIterator, FunctionAttributes, IdData,
+    IdFinder, IdIndex, MemberFunctionType, ModifierType, PointerMode, PointerType, PrimitiveKind,
+    PrimitiveType, ProcedureType, RawString, Result, TypeData, TypeFinder, TypeIndex,
+    TypeInformation, UnionType, Variant,
 };
 use symbolic::common::{Language, Name, NameMangling};
 use symbolic::demangle::{Demangle, DemangleOptions};
 
 use crate::common;
+use crate::demangling::Demangling;
 
 type FwdRefSize<'a> = HashMap<RawString<'a>, u32>;
 
@@ -64,9 +65,12 @@ impl Default for DumperFlags {
 
 pub(super) struct TypeDumper<'a> {
     finder: TypeFinder<'a>,
+    // The finders of the type servers (if any) for the types which aren't in the PDB
+    servers: Vec<TypeFinder<'a>>,
     fwd: FwdRefSize<'a>,
     ptr_size: u32,
     flags: DumperFlags,
+    demangling: Demangling,
 }
 
 pub enum FuncName {
@@ -122,19 +126,42 @@ impl FuncName {
 
 impl<'a> TypeDumper<'a> {
     /// Collect all the Type and their TypeIndex to be able to search for a TypeIndex
-    pub fn new<'b>(
+    /// (the ones which aren't in type_info are searched in the type servers)
+    pub fn new<'b, 'c>(
         type_info: &'a TypeInformation<'b>,
+        servers: &'a [TypeInformation<'c>],
         ptr_size: u32,
         flags: DumperFlags,
+        demangling: Demangling,
     ) -> Result<Self> {
-        let mut types = type_info.iter();
-        let mut finder = type_info.finder();
-
         // Some struct are incomplete so they've no size but they're forward references
         // So create a map containing names defining the struct (when they aren't fwd ref) and their size.
         // Once we'll need to compute a size for a fwd ref, we just use this map.
         let mut fwd = FwdRefSize::default();
 
+        let finder = Self::collect_types(type_info, &mut fwd)?;
+        let servers = servers
+            .iter()
+            .map(|server| Self::collect_types(server, &mut fwd))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            finder,
+            servers,
+            fwd,
+            ptr_size,
+            flags,
+            demangling,
+        })
+    }
+
+    fn collect_types<'b>(
+        type_info: &'a TypeInformation<'b>,
+        fwd: &mut FwdRefSize<'a>,
+    ) -> Result<TypeFinder<'a>> {
+        let mut types = type_info.iter();
+        let mut finder = type_info.finder();
+
         while let Some(typ) = types.next()? {
             finder.update(&types);
             if let Ok(typ) = typ.parse() {
@@ -156,16 +183,18 @@ impl<'a> TypeDumper<'a> {
             }
         }
 
-        Ok(Self {
-            finder,
-            fwd,
-            ptr_size,
-            flags,
-        })
+        Ok(finder)
     }
 
     fn find(&self, index: TypeIndex) -> Result<TypeData> {
-        let typ = self.finder.find(index).unwrap();
+        let typ = match self.finder.find(index) {
+            Ok(typ) => typ,
+            Err(e) => self
+                .servers
+                .iter()
+                .find_map(|server| server.find(index).ok())
+                .ok_or(e)?,
+        };
         typ.parse()
     }
 
@@ -269,7 +298,9 @@ impl<'a> TypeDumper<'a> {
         if name.is_empty() {
             Ok(FuncName::Undecorated("<name omitted>".to_string()))
         } else if index == TypeIndex(0) {
-            Ok(Self::demangle(name))
+            Ok(Self::demangle(name, self.demangling))
+        } else if self.demangling != Demangling::Full {
+            Ok(FuncName::Undecorated(name.to_string()))
         } else {
             let typ = self.find(index)?;
             match typ {
@@ -309,6 +340,39 @@ impl<'a> TypeDumper<'a> {
         }
     }
 
+    /// Dump the name of an inlined function from its id in the IPI stream
+    pub fn dump_inlinee(&self, id_finder: &IdFinder, index: IdIndex) -> String {
+        let (name, type_index) = match id_finder.find(index).and_then(|id| id.parse()) {
+            Ok(IdData::Function(f)) => {
+                let scope = f.scope.and_then(|scope| {
+                    match id_finder.find(scope).and_then(|id| id.parse()) {
+                        Ok(IdData::String(s)) => Some(s.name),
+                        _ => None,
+                    }
+                });
+                let name = match scope {
+                    Some(scope) => format!("{}::{}", scope, f.name),
+                    _ => f.name.to_string().into_owned(),
+                };
+                (name, f.function_type)
+            }
+            Ok(IdData::MemberFunction(f)) => {
+                let name = match self.find(f.parent) {
+                    Ok(TypeData::Class(c)) => format!("{}::{}", c.name, f.name),
+                    Ok(TypeData::Union(u)) => format!("{}::{}", u.name, f.name),
+                    _ => f.name.to_string().into_owned(),
+                };
+                (name, f.function_type)
+            }
+            _ => return "<name omitted>".to_string(),
+        };
+
+        match self.dump_function(&name, type_index) {
+            Ok(FuncName::Undecorated(name)) | Ok(FuncName::Unknown((name, _))) => name,
+            _ => name,
+        }
+    }
+
     #[inline(always)]
     fn fix_return(mut name: String) -> String {
         if !name.is_empty() {
@@ -327,20 +391,31 @@ impl<'a> TypeDumper<'a> {
             .replace("  ", " ")
     }
 
-    pub fn demangle(ident: &str) -> FuncName {
+    pub fn demangle(ident: &str, demangling: Demangling) -> FuncName {
         // If the name is not mangled maybe we can guess stacksize in using it.
         // So the boolean flag in the returned value is here for that (true == known language)
         // For information:
         //  - msvc-demangler has no problem with symbols containing ".llvm."
         let lang = Name::new(ident, NameMangling::Mangled, Language::Unknown).detect_language();
         if lang == Language::Unknown {
-            return FuncName::get_unknown(ident.to_string());
+            return match (FuncName::get_unknown(ident.to_string()), demangling) {
+                // Keep the stack parameter size guessed from the decoration
+                (FuncName::Unknown((_, sps)), Demangling::None) => {
+                    FuncName::Unknown((ident.to_string(), sps))
+                }
+                (name, _) => name,
+            };
         }
 
+        let options = match demangling {
+            Demangling::Full => DemangleOptions::complete(),
+            Demangling::Name => DemangleOptions::name_only(),
+            Demangling::None => return FuncName::Undecorated(ident.to_string()),
+        };
         let name = Name::new(ident, NameMangling::Mangled, lang);
         let name = common::fix_symbol_name(&name);
 
-        match name.demangle(DemangleOptions::complete()) {
+        match name.demangle(options) {
             Some(demangled) => {
                 if demangled == ident {
                     // Maybe the langage detection was finally wrong
@@ -784,6 +859,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_demangling() {
+        let names: Vec<_> = [Demangling::Full, Demangling::Name, Demangling::None]
+            .iter()
+            .map(|d| match TypeDumper::demangle("_ZN3foo3barEi", *d) {
+                FuncName::Undecorated(name) => name,
+                FuncName::Unknown((name, _)) => panic!("{} not demangled", name),
+            })
+            .collect();
+        assert_eq!(names, ["foo::bar(int)", "foo::bar", "_ZN3foo3barEi"]);
+
+        if let FuncName::Unknown((name, sps)) = TypeDumper::demangle("_foo@8", Demangling::Name) {
+            assert_eq!(name, "foo");
+            assert_eq!(sps, 8);
+        }
+
+        if let FuncName::Unknown((name, sps)) = TypeDumper::demangle("_foo@8", Demangling::None) {
+            assert_eq!(name, "_foo@8");
+            assert_eq!(sps, 8);
+        }
+    }
+
     #[test]
     fn test_funcname() {
         if let FuncName::Unknown((name, sps)) = FuncName::get_unknown("_foo@123()".to_string()) {
@@ -7,11 +7,28 @@ use std::path::PathBuf;
 use symbolic::debuginfo::pe::PeObject;
 use uuid::Uuid;
 
-use crate::cache::{self, SymbolServer};
-use crate::utils;
+use crate::pipeline::RemoteFile;
+use crate::supplier::SymbolSupplier;
+use crate::utils::{self, FileBuffer};
+use crate::windows::modules::PdbSource;
 use crate::windows::pdb::PDBInfo;
 
-fn try_to_find_pdb(path: &PathBuf, pdb_filename: &str) -> Option<Vec<u8>> {
+/// A PDB next to its PE (or fetched) or a PDB which is being downloaded
+pub(crate) enum PdbFile {
+    Buf(FileBuffer),
+    Remote(RemoteFile),
+}
+
+impl PdbFile {
+    pub(crate) fn source(&self) -> PdbSource<'_> {
+        match self {
+            Self::Buf(buf) => PdbSource::Buf(buf),
+            Self::Remote(file) => PdbSource::Remote(file),
+        }
+    }
+}
+
+fn try_to_find_pdb(path: &PathBuf, pdb_filename: &str) -> Option<FileBuffer> {
     // Just check that the file is in the same directory as the PE one
     let pdb = path.with_file_name(pdb_filename);
     let mut pdb_cab = pdb.clone();
@@ -19,21 +36,24 @@ fn try_to_find_pdb(path: &PathBuf, pdb_filename: &str) -> Option<Vec<u8>> {
 
     for pdb in vec![pdb, pdb_cab].drain(..) {
         if pdb.is_file() {
-            return Some(utils::read_file(pdb));
+            return Some(utils::map_file(pdb));
         } else {
             // We try in CWD
             let mut pdb =
                 std::env::current_dir().expect("Unable to get the current working directory");
             pdb.set_file_name(pdb_filename);
             if pdb.is_file() {
-                return Some(utils::read_file(pdb));
+                return Some(utils::map_file(pdb));
             }
         }
     }
     None
 }
 
-fn os_specific_try_to_find_pdb(path: &PathBuf, pdb_filename: String) -> (Option<Vec<u8>>, String) {
+fn os_specific_try_to_find_pdb(
+    path: &PathBuf,
+    pdb_filename: String,
+) -> (Option<FileBuffer>, String) {
     // We may have gotten either an OS native path, or a Windows path.
     // On Windows, they're both the same. On Unix, they are different, and in that case,
     // we change backslashes to forward slashes for `file_name()` to do its job.
@@ -44,7 +64,7 @@ fn os_specific_try_to_find_pdb(path: &PathBuf, pdb_filename: String) -> (Option<
     let pdb_name = pdb_path.file_name().unwrap().to_str().unwrap().to_string();
 
     if pdb_path.is_file() {
-        (Some(utils::read_file(pdb_path)), pdb_name)
+        (Some(utils::map_file(pdb_path)), pdb_name)
     } else {
         (try_to_find_pdb(path, &pdb_name), pdb_name)
     }
@@ -53,8 +73,8 @@ fn os_specific_try_to_find_pdb(path: &PathBuf, pdb_filename: String) -> (Option<
 pub fn get_pe_pdb_buf<'a>(
     path: &PathBuf,
     buf: &'a [u8],
-    symbol_server: Option<&Vec<SymbolServer>>,
-) -> Option<(PeObject<'a>, Vec<u8>, String)> {
+    supplier: &dyn SymbolSupplier,
+) -> Option<(PeObject<'a>, PdbFile, String)> {
     let pe = PeObject::parse(&buf)
         .unwrap_or_else(|_| panic!("Unable to parse the PE file {}", path.to_str().unwrap()));
     if let Some(pdb_filename) = pe.debug_file_name() {
@@ -62,13 +82,15 @@ pub fn get_pe_pdb_buf<'a>(
         let (pdb, pdb_name) = os_specific_try_to_find_pdb(path, pdb_filename);
 
         if let Some(pdb_buf) = pdb {
-            Some((pe, pdb_buf, pdb_name))
+            Some((pe, PdbFile::Buf(pdb_buf), pdb_name))
         } else {
-            // Not here so try symbol server (or cache)
+            // Not here so try symbol server (or cache): the PDB is parsed while it's downloaded
+            // when the supplier can open it
             let debug_id = get_pe_debug_id(Some(&pe)).unwrap();
-            let (pdb, pdb_name) = cache::search_file(pdb_name, &debug_id, symbol_server);
-            if let Some(pdb_buf) = pdb {
-                Some((pe, pdb_buf, pdb_name))
+            if let Some(file) = supplier.open(&pdb_name, &debug_id) {
+                Some((pe, PdbFile::Remote(file), pdb_name))
+            } else if let Some(pdb_buf) = supplier.fetch(&pdb_name, &debug_id) {
+                Some((pe, PdbFile::Buf(pdb_buf.into()), pdb_name))
             } else {
                 None
             }
@@ -104,7 +126,7 @@ pub(crate) fn try_to_set_pe(path: &PathBuf, pdb_info: &mut PDBInfo, pdb_buf: &[u
     for ext in vec!["dll", "dl_", "exe", "ex_"].drain(..) {
         path.set_extension(ext);
         if path.is_file() {
-            let buf = utils::read_file(&path);
+            let buf = utils::map_file(&path);
             if let Ok(pe) = PeObject::parse(&buf) {
                 if ext.ends_with('_') {
                     path.set_extension(fix_extension(ext));
@@ -0,0 +1,154 @@
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The version of a PE is in its VERSIONINFO resource: the resource tree is
+// type (RT_VERSION) / name / language and the data start with a VS_VERSIONINFO
+// structure containing a VS_FIXEDFILEINFO one.
+
+use goblin::pe::{options::ParseOptions, utils::find_offset, PE};
+use std::convert::TryInto;
+
+const RT_VERSION: u32 = 16;
+const FIXED_FILE_INFO_SIGNATURE: u32 = 0xFEEF_04BD;
+
+struct Resources<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Resources<'a> {
+    fn read_u16(&self, offset: usize) -> Option<u16> {
+        let data = self.data.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes(data.try_into().ok()?))
+    }
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        let data = self.data.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(data.try_into().ok()?))
+    }
+
+    /// Get the entries (id or name, offset) of the directory at the given offset
+    fn get_entries(&self, offset: usize) -> Option<Vec<(u32, u32)>> {
+        let count =
+            usize::from(self.read_u16(offset + 12)?) + usize::from(self.read_u16(offset + 14)?);
+        (0..count)
+            .map(|i| {
+                let entry = offset + 16 + 8 * i;
+                Some((self.read_u32(entry)?, self.read_u32(entry + 4)?))
+            })
+            .collect()
+    }
+
+    /// Get the first subdirectory or the first data entry (with the given id if any)
+    fn get_child(&self, offset: usize, id: Option<u32>) -> Option<(bool, usize)> {
+        let (_, child) = self
+            .get_entries(offset)?
+            .into_iter()
+            .find(|(name, _)| id.is_none() || id == Some(*name))?;
+        Some((child & 0x8000_0000 != 0, (child & 0x7fff_ffff) as usize))
+    }
+
+    /// Get the rva and the size of the first VERSIONINFO resource
+    fn get_version_info(&self) -> Option<(u32, u32)> {
+        let mut offset = 0;
+        for id in &[Some(RT_VERSION), None] {
+            let (is_dir, child) = self.get_child(offset, *id)?;
+            if !is_dir {
+                return None;
+            }
+            offset = child;
+        }
+        // The language directory contains the data entry
+        let (is_dir, entry) = self.get_child(offset, None)?;
+        if is_dir {
+            return None;
+        }
+        Some((self.read_u32(entry)?, self.read_u32(entry + 4)?))
+    }
+}
+
+fn get_data<'a>(pe: &PE, buf: &'a [u8], rva: u32, size: u32) -> Option<&'a [u8]> {
+    let file_alignment = pe.header.optional_header?.windows_fields.file_alignment;
+    let start = find_offset(
+        rva as usize,
+        &pe.sections,
+        file_alignment,
+        &ParseOptions::default(),
+    )?;
+    buf.get(start..start.checked_add(size as usize)?)
+}
+
+fn get_fixed_file_version(info: &[u8]) -> Option<String> {
+    // VS_FIXEDFILEINFO is after the header and the key (VS_VERSION_INFO) and it's 32-bit aligned
+    let signature = FIXED_FILE_INFO_SIGNATURE.to_le_bytes();
+    let start = (0..info.len().min(128))
+        .step_by(4)
+        .find(|i| info.get(*i..*i + 4) == Some(&signature[..]))?;
+    let read = |i: usize| -> Option<u32> {
+        let offset = start + 4 * i;
+        Some(u32::from_le_bytes(
+            info.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    // dwSignature, dwStrucVersion, dwFileVersionMS, dwFileVersionLS
+    let (ms, ls) = (read(2)?, read(3)?);
+
+    Some(format!(
+        "{}.{}.{}.{}",
+        ms >> 16,
+        ms & 0xffff,
+        ls >> 16,
+        ls & 0xffff
+    ))
+}
+
+/// Get the file version (e.g. 1.2.3.4) from the VERSIONINFO resource of a PE
+pub(crate) fn get_file_version(buf: &[u8]) -> Option<String> {
+    let pe = PE::parse(buf).ok()?;
+    let table = (*pe
+        .header
+        .optional_header?
+        .data_directories
+        .get_resource_table())?;
+    let resources = Resources {
+        data: get_data(&pe, buf, table.virtual_address, table.size)?,
+    };
+    let (rva, size) = resources.get_version_info()?;
+
+    get_fixed_file_version(get_data(&pe, buf, rva, size)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_fixed_file_version() {
+        let mut info = vec![0; 40];
+        // The header, VS_VERSION_INFO and the padding
+        info[..2].copy_from_slice(&92u16.to_le_bytes());
+        for (ms, ls) in &[
+            (FIXED_FILE_INFO_SIGNATURE, 0x10000u32),
+            (0x5a_0001, 0x3_0004),
+        ] {
+            info.extend_from_slice(&ms.to_le_bytes());
+            info.extend_from_slice(&ls.to_le_bytes());
+        }
+        assert_eq!(get_fixed_file_version(&info).unwrap(), "90.1.3.4");
+        assert!(get_fixed_file_version(&info[..40]).is_none());
+    }
+
+    #[test]
+    fn test_file_version() {
+        let path = PathBuf::from("./test_data/windows");
+        let buf = fs::read(path.join("mozwer.dll")).unwrap();
+        assert_eq!(get_file_version(&buf).unwrap(), "86.0.0.7656");
+
+        let buf = fs::read(path.join("basic64.dll")).unwrap();
+        assert!(get_file_version(&buf).is_none());
+    }
+}
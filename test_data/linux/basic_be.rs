@@ -0,0 +1,30 @@
+// A program without the core library to get some binaries for the big-endian targets with a
+// nightly rustc and no sysroot for them (see the Makefile)
+
+#![feature(no_core, lang_items)]
+#![no_core]
+#![allow(internal_features)]
+#![no_main]
+
+#[lang = "pointee_sized"]
+pub trait PointeeSized {}
+
+#[lang = "meta_sized"]
+pub trait MetaSized: PointeeSized {}
+
+#[lang = "sized"]
+pub trait Sized: MetaSized {}
+
+#[lang = "copy"]
+pub trait Copy {}
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn foo(x: i32) -> i32 {
+    x
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> i32 {
+    foo(1)
+}
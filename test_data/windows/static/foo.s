@@ -0,0 +1,18 @@
+	.text
+	.def	"?foo@@YAHH@Z"
+	.scl	2
+	.type	32
+	.endef
+	.globl	"?foo@@YAHH@Z"
+"?foo@@YAHH@Z":
+	leal	1(%rcx), %eax
+	retq
+
+	.def	"?bar@@YAHH@Z"
+	.scl	2
+	.type	32
+	.endef
+	.globl	"?bar@@YAHH@Z"
+"?bar@@YAHH@Z":
+	leal	2(%rcx), %eax
+	retq
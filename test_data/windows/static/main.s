@@ -0,0 +1,9 @@
+	.text
+	.def	main
+	.scl	2
+	.type	32
+	.endef
+	.globl	main
+main:
+	xorl	%eax, %eax
+	retq